@@ -0,0 +1,133 @@
+//! Benchmarks behind the performance budget in the Readme: `now()` latency alone and under
+//! contention from reader threads and a continuously syncing clock, reply parsing
+//! throughput, and one full sync cycle against a local server.
+//!
+//! Run with `cargo bench`; pass `--features quanta` to also measure `TscSource`.
+
+use clock::sntp::{self, PACKET_LEN};
+use clock::{Clock, ClockConfig};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Reader thread counts for the contention benchmark
+const READERS: [usize; 3] = [0, 1, 4];
+
+/// A server reply carrying the current time, as a stratum 2 server would send it
+fn server_reply(request: &[u8; PACKET_LEN]) -> [u8; PACKET_LEN] {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let seconds = (now.as_secs() + sntp::NTP_UNIX_OFFSET as u64) as u32;
+    let fraction = ((u64::from(now.subsec_nanos()) << 32) / 1_000_000_000) as u32;
+    let mut reply = *request;
+    reply[0] = 0x24; // NTP version 4, server mode
+    reply[1] = 2; // stratum
+    reply[12..16].copy_from_slice(&[192, 0, 2, 1]);
+    // Originate timestamp echoes the request's transmit timestamp
+    reply[24..32].copy_from_slice(&request[40..48]);
+    for offset in [32, 40] {
+        reply[offset..offset + 4].copy_from_slice(&seconds.to_be_bytes());
+        reply[offset + 4..offset + 8].copy_from_slice(&fraction.to_be_bytes());
+    }
+    reply
+}
+
+/// Starts a local NTP server answering every request, returning its address
+fn spawn_server() -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        let mut request = [0u8; PACKET_LEN];
+        while let Ok((_, peer)) = socket.recv_from(&mut request) {
+            let _ = socket.send_to(&server_reply(&request), peer);
+        }
+    });
+    addr
+}
+
+fn synced_clock() -> Clock {
+    let clock = Clock::with_config(ClockConfig::new().with_servers(vec![spawn_server()]));
+    assert!(clock.is_synchronized(), "local server did not answer");
+    clock
+}
+
+fn bench_now(c: &mut Criterion) {
+    let clock = synced_clock();
+    let mut group = c.benchmark_group("now");
+    group.bench_function("monotonic", |b| b.iter(|| black_box(clock.now_timestamp())));
+    #[cfg(feature = "quanta")]
+    {
+        clock.set_elapsed_source(Arc::new(clock::TscSource::new()));
+        group.bench_function("tsc", |b| b.iter(|| black_box(clock.now_timestamp())));
+        clock.set_elapsed_source(Arc::new(clock::MonotonicSource::new()));
+    }
+    group.finish();
+}
+
+/// `now()` while `readers` threads read the clock in a loop and another steps it from the
+/// server as fast as it answers
+fn bench_now_contended(c: &mut Criterion) {
+    let clock = Arc::new(synced_clock());
+    let mut group = c.benchmark_group("now_contended");
+    for readers in READERS {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut threads = Vec::new();
+        for _ in 0..readers {
+            let handle = clock.handle();
+            let stop = Arc::clone(&stop);
+            threads.push(thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    black_box(handle.now_timestamp());
+                }
+            }));
+        }
+        let syncing = Arc::clone(&clock);
+        let sync_stop = Arc::clone(&stop);
+        threads.push(thread::spawn(move || {
+            while !sync_stop.load(Ordering::Relaxed) {
+                syncing.resync_now();
+            }
+        }));
+
+        group.bench_with_input(
+            BenchmarkId::new("readers_and_sync", readers),
+            &readers,
+            |b, _| b.iter(|| black_box(clock.now_timestamp())),
+        );
+
+        stop.store(true, Ordering::Relaxed);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+    group.finish();
+}
+
+fn bench_parse_reply(c: &mut Criterion) {
+    let request = sntp::client_request();
+    let reply = server_reply(&request);
+    let delay = Duration::from_micros(250);
+    let mut group = c.benchmark_group("sntp");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("parse_reply", |b| {
+        b.iter(|| sntp::parse_reply(&request, black_box(&reply), black_box(delay)))
+    });
+    group.finish();
+}
+
+/// One poll of a local server and the resulting step, as the background worker does it
+fn bench_sync_cycle(c: &mut Criterion) {
+    let clock = synced_clock();
+    c.bench_function("sync_cycle", |b| b.iter(|| assert!(clock.resync_now())));
+}
+
+criterion_group!(
+    benches,
+    bench_now,
+    bench_now_contended,
+    bench_parse_reply,
+    bench_sync_cycle
+);
+criterion_main!(benches);
@@ -0,0 +1,20 @@
+//! Packets of any length and content decode to a header that encodes back to the same bytes,
+//! and whatever follows the header is split into extension fields or refused, never a panic
+#![no_main]
+
+use clock::extension;
+use clock::sntp::{NtpPacket, PACKET_LEN};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|packet: &[u8]| {
+    let Some(header) = NtpPacket::parse(packet) else {
+        assert!(packet.len() < PACKET_LEN);
+        return;
+    };
+    assert_eq!(&header.encode()[..], &packet[..PACKET_LEN]);
+    let _ = (header.reference_time(), header.receive_time(), header.transmit_time());
+    let _ = header.is_client_request();
+    if let Ok(parsed) = extension::parse_packet(packet) {
+        assert_eq!(NtpPacket::from(&parsed.header), header);
+    }
+});
@@ -0,0 +1,23 @@
+//! Packets of any content are split into fields without panicking, and parsed fields
+//! encode back to the same packet
+#![no_main]
+
+use clock::extension;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|packet: &[u8]| {
+    if let Ok(parsed) = extension::parse_packet(packet) {
+        let well_formed = parsed.fields.iter().enumerate().all(|(i, field)| {
+            let min_len = if i + 1 == parsed.fields.len() && parsed.mac.is_none() {
+                extension::MIN_LAST_FIELD_LEN
+            } else {
+                extension::MIN_FIELD_LEN
+            };
+            extension::FIELD_HEADER_LEN + field.value.len() >= min_len
+        });
+        if well_formed {
+            let encoded = extension::encode_packet(&parsed.header, &parsed.fields, parsed.mac);
+            assert_eq!(encoded, packet);
+        }
+    }
+});
@@ -0,0 +1,24 @@
+//! Replies of any content to requests of any content, with any round-trip delay, are parsed
+//! without panicking, and the transmit time survives an encode/parse round trip in both NTP
+//! eras
+#![no_main]
+
+use clock::sntp::{self, PACKET_LEN};
+use core::time::Duration;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: ([u8; PACKET_LEN], [u8; PACKET_LEN], u64)| {
+    let (request, reply, delay_nanos) = input;
+    let delay = Duration::from_nanos(delay_nanos);
+    if let Some(sample) = sntp::parse_reply(&request, &reply, delay) {
+        assert_eq!(sample.stratum, reply[1]);
+        assert_eq!(reply[0] & 0x07, 4);
+        let _ = sample.kiss_code();
+        let _ = sample.is_synchronized_to([192, 0, 2, 1]);
+
+        let mut copy = [0u8; PACKET_LEN];
+        let transmit = sntp::parse_transmit_time(&reply).unwrap();
+        copy[40..48].copy_from_slice(&sntp::encode_timestamp(transmit));
+        assert_eq!(sntp::parse_transmit_time(&copy), Some(transmit));
+    }
+});
@@ -0,0 +1,11 @@
+//! RFC 3339 parsing rejects malformed input with an error rather than a panic
+#![no_main]
+
+use clock::Timestamp;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|s: &str| {
+    if let Ok(time) = Timestamp::parse_rfc3339(s) {
+        let _ = time.unix_secs();
+    }
+});
@@ -0,0 +1,27 @@
+//! Requests of any content are either refused or answered with a reply that parses back
+#![no_main]
+
+use clock::sntp::{self, ServerState, PACKET_LEN};
+use clock::Timestamp;
+use core::time::Duration;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: ([u8; PACKET_LEN], u8, u32, i64)| {
+    let (request, stratum, dispersion, secs) = input;
+    let state = ServerState {
+        stratum,
+        root_delay: 0.0,
+        root_dispersion: dispersion as f64 / 65536.0,
+        reference_id: [127, 0, 0, 1],
+        reference_time: None,
+    };
+    // Within the eras an NTP timestamp can express
+    let time = Timestamp::from_unix_secs(secs.rem_euclid(1 << 32) - 61_505_152);
+    if let Some(reply) = sntp::server_reply(&request, &state, time, time) {
+        assert_eq!(&reply[24..32], &request[40..48]);
+        if let Some(sample) = sntp::parse_reply(&request, &reply, Duration::ZERO) {
+            assert_eq!(sample.time, time);
+            assert_eq!(sample.stratum, stratum);
+        }
+    }
+});
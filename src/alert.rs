@@ -0,0 +1,504 @@
+//! # Alerting
+//!
+//! Pages someone when the clock gets into trouble, without glue code around
+//! [`Clock::events`](crate::Clock::events). Each [`AlertHook`] runs a command or POSTs JSON
+//! to a webhook when one of these happens:
+//!
+//! * [`AlertKind::LostSync`] — the clock had synced, but its last sync is now older than
+//!   the staleness threshold; raised once until a sync succeeds again
+//! * [`AlertKind::LargeStep`] — the clock was stepped by more than
+//!   [`ClockConfig::large_step_threshold`](crate::ClockConfig::large_step_threshold)
+//! * [`AlertKind::AllServersFailed`] — a poll got no time from any server or peer
+//! * [`AlertKind::Anomaly`] — an [`anomaly`](crate::anomaly) was detected
+//!
+//! Failed deliveries are retried [`DELIVERY_ATTEMPTS`] times in all, backing off from
+//! [`RETRY_BACKOFF`]. Alerts of one kind are rate limited to one per
+//! [`ClockConfig::alert_rate_limit`](crate::ClockConfig::alert_rate_limit); the next one
+//! sent says how many were suppressed.
+//!
+//! ```text
+//! alert_hook = exec:/usr/local/bin/page-oncall         # every kind
+//! alert_hook = lost_sync,large_step=http://alerts.internal:9000/clock
+//! ```
+
+use crate::json::{json_number, json_string};
+use crate::lock::{MutexExt, RwLockExt};
+use crate::logging::clock_log;
+use crate::{ClockConfig, Timestamp};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Shortest time between two alerts of one kind unless configured otherwise
+pub const DEFAULT_ALERT_RATE_LIMIT: Duration = Duration::from_secs(300);
+
+/// Step of the clock that raises [`AlertKind::LargeStep`] unless configured otherwise
+pub const DEFAULT_LARGE_STEP_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Tries at delivering an alert to one hook, the first included
+pub const DELIVERY_ATTEMPTS: u32 = 4;
+
+/// Wait before the first retry, doubled before each further one
+pub const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long a webhook may take to accept its request
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What an alert is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertKind {
+    LostSync,
+    LargeStep,
+    AllServersFailed,
+    Anomaly,
+}
+
+impl AlertKind {
+    /// Every kind
+    pub const ALL: [AlertKind; 4] = [
+        AlertKind::LostSync,
+        AlertKind::LargeStep,
+        AlertKind::AllServersFailed,
+        AlertKind::Anomaly,
+    ];
+
+    /// Short machine-readable name, used by hooks and accepted by [`FromStr`]
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertKind::LostSync => "lost_sync",
+            AlertKind::LargeStep => "large_step",
+            AlertKind::AllServersFailed => "all_servers_failed",
+            AlertKind::Anomaly => "anomaly",
+        }
+    }
+}
+
+impl fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for AlertKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AlertKind::ALL
+            .into_iter()
+            .find(|kind| kind.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| format!("unknown alert '{}'", s.trim()))
+    }
+}
+
+/// Something worth paging about
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    /// Clock time when the alert was raised
+    pub time: Timestamp,
+    /// Server involved, if any
+    pub server: Option<String>,
+    /// Size of the problem in seconds: the step, the anomaly, or the age of the last sync
+    pub magnitude: Option<f64>,
+    pub message: String,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl Alert {
+    /// The JSON body sent to webhooks
+    fn json(&self, suppressed: u64) -> String {
+        format!(
+            "{{\"alert\":{},\"time\":{},\"server\":{},\"magnitude\":{},\"message\":{},\"suppressed\":{}}}",
+            json_string(self.kind.name()),
+            json_string(&self.time.to_rfc3339()),
+            self.server.as_deref().map_or("null".to_string(), json_string),
+            json_number(self.magnitude),
+            json_string(&self.message),
+            suppressed
+        )
+    }
+}
+
+/// A command or webhook that notifications are delivered to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookTarget {
+    /// Runs a program, given as whitespace-separated words
+    Command(String),
+    /// POSTs JSON to a plain `http://` URL
+    Webhook(String),
+}
+
+impl FromStr for HookTarget {
+    type Err = String;
+
+    /// Parses `exec:COMMAND` or `http://HOST[:PORT]/PATH`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(command) = s.strip_prefix("exec:") {
+            if command.trim().is_empty() {
+                return Err("empty command in hook".to_string());
+            }
+            return Ok(HookTarget::Command(command.trim().to_string()));
+        }
+        if s.starts_with("http://") {
+            return Ok(HookTarget::Webhook(s.to_string()));
+        }
+        Err(format!(
+            "unknown hook '{}', expected exec:COMMAND or http://URL",
+            s
+        ))
+    }
+}
+
+impl fmt::Display for HookTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookTarget::Command(command) => write!(f, "exec:{}", command),
+            HookTarget::Webhook(url) => f.write_str(url),
+        }
+    }
+}
+
+/// Where alerts of some kinds are delivered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlertHook {
+    /// Kinds delivered to the hook, all of them if empty
+    pub kinds: Vec<AlertKind>,
+    pub target: HookTarget,
+}
+
+impl AlertHook {
+    /// Whether alerts of `kind` are delivered to this hook
+    pub fn wants(&self, kind: AlertKind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(&kind)
+    }
+}
+
+impl FromStr for AlertHook {
+    type Err = String;
+
+    /// Parses `[KIND,...=]TARGET`, e.g. `lost_sync,large_step=exec:page-oncall`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // URLs may contain '=' too, so a prefix only counts if it names alert kinds
+        if let Some((kinds, target)) = s.split_once('=') {
+            if let Ok(kinds) = kinds.split(',').map(str::parse).collect() {
+                return Ok(AlertHook {
+                    kinds,
+                    target: target.trim().parse()?,
+                });
+            }
+        }
+        Ok(AlertHook {
+            kinds: Vec::new(),
+            target: s.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for AlertHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.kinds.is_empty() {
+            let kinds: Vec<&str> = self.kinds.iter().map(AlertKind::name).collect();
+            write!(f, "{}=", kinds.join(","))?;
+        }
+        write!(f, "{}", self.target)
+    }
+}
+
+/// Rate limiting state of one kind
+#[derive(Debug)]
+struct Sent {
+    last: Instant,
+    suppressed: u64,
+}
+
+/// Raises alerts to the configured hooks
+pub(crate) struct Alerter {
+    hooks: RwLock<Vec<AlertHook>>,
+    rate_limit: RwLock<Duration>,
+    pub(crate) large_step_threshold: RwLock<Duration>,
+    sent: Mutex<HashMap<AlertKind, Sent>>,
+    /// Set once [`AlertKind::LostSync`] was raised, until a sync succeeds
+    pub(crate) sync_lost: AtomicBool,
+}
+
+impl Alerter {
+    pub(crate) fn new(config: &ClockConfig) -> Self {
+        Alerter {
+            hooks: RwLock::new(config.alert_hooks.clone()),
+            rate_limit: RwLock::new(config.alert_rate_limit),
+            large_step_threshold: RwLock::new(config.large_step_threshold),
+            sent: Mutex::new(HashMap::new()),
+            sync_lost: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn configure(&self, config: &ClockConfig) {
+        *self.hooks.write_or_recover() = config.alert_hooks.clone();
+        *self.rate_limit.write_or_recover() = config.alert_rate_limit;
+        *self.large_step_threshold.write_or_recover() = config.large_step_threshold;
+    }
+
+    /// Delivers `alert` to the hooks that want it from background threads, unless an
+    /// alert of its kind was sent within the rate limit
+    pub(crate) fn raise(&self, alert: Alert) {
+        let hooks: Vec<AlertHook> = self
+            .hooks
+            .read_or_recover()
+            .iter()
+            .filter(|hook| hook.wants(alert.kind))
+            .cloned()
+            .collect();
+        if hooks.is_empty() {
+            return;
+        }
+        let Some(suppressed) = self.admit(alert.kind, Instant::now()) else {
+            clock_log!(Debug, Anomaly, "Rate limited alert {}", alert);
+            return;
+        };
+        clock_log!(Info, Anomaly, "Raising alert {}", alert);
+        for hook in hooks {
+            let alert = alert.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = deliver(&hook.target, &alert, suppressed, RETRY_BACKOFF) {
+                    clock_log!(Warn, Anomaly, "Alert hook {} failed: {}", hook, e);
+                }
+            });
+        }
+    }
+
+    /// Whether an alert of `kind` may be sent at `now`, and if so, how many were
+    /// suppressed before it
+    fn admit(&self, kind: AlertKind, now: Instant) -> Option<u64> {
+        let rate_limit = *self.rate_limit.read_or_recover();
+        let mut sent = self.sent.lock_or_recover();
+        match sent.get_mut(&kind) {
+            Some(sent) if now.duration_since(sent.last) < rate_limit => {
+                sent.suppressed += 1;
+                None
+            }
+            _ => {
+                let previous = sent.insert(
+                    kind,
+                    Sent {
+                        last: now,
+                        suppressed: 0,
+                    },
+                );
+                Some(previous.map_or(0, |previous| previous.suppressed))
+            }
+        }
+    }
+}
+
+/// Delivers `alert` to `target`, retrying with exponential backoff
+fn deliver(
+    target: &HookTarget,
+    alert: &Alert,
+    suppressed: u64,
+    backoff: Duration,
+) -> io::Result<()> {
+    let mut wait = backoff;
+    let mut attempt = 1;
+    loop {
+        let result = match target {
+            HookTarget::Command(command) => run_command(
+                command,
+                &[
+                    ("CLOCK_NTP_ALERT", alert.kind.name()),
+                    ("CLOCK_NTP_SERVER", alert.server.as_deref().unwrap_or("")),
+                    ("CLOCK_NTP_MESSAGE", &alert.message),
+                    ("CLOCK_NTP_SUPPRESSED", &suppressed.to_string()),
+                ],
+            ),
+            HookTarget::Webhook(url) => post_json(url, &alert.json(suppressed)),
+        };
+        match result {
+            Err(e) if attempt < DELIVERY_ATTEMPTS => {
+                clock_log!(
+                    Debug,
+                    Anomaly,
+                    "Alert hook {} failed (attempt {}), retrying: {}",
+                    target,
+                    attempt,
+                    e
+                );
+                std::thread::sleep(wait);
+                wait *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Runs `command`, a program and its arguments separated by whitespace, with `env` set
+pub(crate) fn run_command(command: &str, env: &[(&str, &str)]) -> io::Result<()> {
+    let mut words = command.split_whitespace();
+    let program = words.next().unwrap_or_default();
+    let status = std::process::Command::new(program)
+        .args(words)
+        .envs(env.iter().copied())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("exited with {}", status)));
+    }
+    Ok(())
+}
+
+/// POSTs `body` as JSON to a plain `http://` URL, succeeding on a 2xx answer
+pub(crate) fn post_json(url: &str, body: &str) -> io::Result<()> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid("only http:// webhooks are supported"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let addr = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| invalid("webhook host did not resolve"))?;
+
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        authority,
+        body.len(),
+        body
+    );
+    let mut stream = TcpStream::connect_timeout(&addr, WEBHOOK_TIMEOUT)?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+    stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+    stream.write_all(request.as_bytes())?;
+
+    let mut status = [0u8; 12];
+    stream.read_exact(&mut status)?;
+    match &status[9..10] {
+        b"2" => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "webhook answered {}",
+            String::from_utf8_lossy(&status[9..12])
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::spawn_fake_server;
+    use crate::Clock;
+    use std::net::TcpListener;
+
+    /// Accepts `answers.len()` requests, answering each with the next status line, and
+    /// returns them
+    fn spawn_webhook(
+        answers: &'static [&'static str],
+    ) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for answer in answers {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with('}') {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.push_str(&String::from_utf8_lossy(&buf[..n]));
+                }
+                write!(stream, "HTTP/1.1 {}\r\n\r\n", answer).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn test_alert_hook_from_str() {
+        let hook: AlertHook = "lost_sync,large_step=http://alerts:9000/hook?a=b"
+            .parse()
+            .unwrap();
+        assert_eq!(hook.kinds, [AlertKind::LostSync, AlertKind::LargeStep]);
+        assert!(hook.wants(AlertKind::LargeStep) && !hook.wants(AlertKind::Anomaly));
+        assert_eq!(
+            hook.to_string(),
+            "lost_sync,large_step=http://alerts:9000/hook?a=b"
+        );
+
+        let hook: AlertHook = "http://alerts/hook?a=b".parse().unwrap();
+        assert!(hook.kinds.is_empty() && hook.wants(AlertKind::Anomaly));
+        assert_eq!(
+            hook.target,
+            HookTarget::Webhook("http://alerts/hook?a=b".to_string())
+        );
+        assert!("lost_sync=ftp://alerts".parse::<AlertHook>().is_err());
+        assert!("exec: ".parse::<AlertHook>().is_err());
+    }
+
+    #[test]
+    fn test_alerts_are_rate_limited_per_kind() {
+        let config = ClockConfig::new().with_alert_rate_limit(Duration::from_secs(60));
+        let alerter = Alerter::new(&config);
+        let start = Instant::now();
+        let later = start + Duration::from_secs(61);
+        assert_eq!(alerter.admit(AlertKind::LargeStep, start), Some(0));
+        assert_eq!(alerter.admit(AlertKind::LargeStep, start), None);
+        assert_eq!(alerter.admit(AlertKind::LargeStep, start), None);
+        assert_eq!(alerter.admit(AlertKind::Anomaly, start), Some(0));
+        assert_eq!(alerter.admit(AlertKind::LargeStep, later), Some(2));
+    }
+
+    #[test]
+    fn test_webhook_delivery_is_retried() {
+        let (url, server) = spawn_webhook(&["503 Service Unavailable", "204 No Content"]);
+
+        let alert = Alert {
+            kind: AlertKind::LargeStep,
+            time: "2026-02-03T06:50:57Z".parse().unwrap(),
+            server: Some("pool.ntp.org:123".to_string()),
+            magnitude: Some(-2.5),
+            message: "stepped by -2.5s".to_string(),
+        };
+        let target = HookTarget::Webhook(url);
+        deliver(&target, &alert, 3, Duration::from_millis(10)).unwrap();
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("POST /alerts HTTP/1.1\r\n"));
+        assert!(requests[1].contains("\"alert\":\"large_step\""));
+        assert!(requests[1].contains("\"magnitude\":-2.5,"));
+        assert!(requests[1].ends_with("\"suppressed\":3}"));
+    }
+
+    #[test]
+    fn test_failed_poll_raises_alert() {
+        let (url, server) = spawn_webhook(&["204 No Content"]);
+        let hook = format!("all_servers_failed={}", url).parse().unwrap();
+        let refused = spawn_fake_server(Timestamp::now(), 0);
+        let clock = Clock::with_config(
+            ClockConfig::new()
+                .with_servers(vec![refused])
+                .with_alert_hooks(vec![hook]),
+        );
+        assert!(!clock.resync_now());
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("\"alert\":\"all_servers_failed\""));
+    }
+}
@@ -0,0 +1,336 @@
+//! # Anomaly Detection
+//!
+//! Watches the offset history for signs that the time servers, or the network path to them,
+//! are being tampered with. Suspicious samples are still used as before, but are reported as
+//! a [`ClockEvent::Anomaly`](crate::ClockEvent::Anomaly) and passed to any configured
+//! [`AnomalyHook`]s:
+//!
+//! * [`AnomalyKind::TimeWentBackwards`] — the server's time fell behind where the previous
+//!   sample put it by more than the threshold
+//! * [`AnomalyKind::Oscillation`] — the offset swung back and forth by more than the
+//!   threshold several polls in a row
+//! * [`AnomalyKind::HistoryDisagreement`] — a sample suddenly disagrees with the recent
+//!   history, e.g. because every server now reports a different time
+//! * [`AnomalyKind::RtcDisagreement`] — the hardware RTC disagrees with the server's time;
+//!   whether the sample is used is up to the [`RtcPolicy`](crate::rtc::RtcPolicy)
+
+use crate::alert;
+use crate::json::{json_number, json_string};
+use crate::logging::clock_log;
+use crate::stability::OffsetSample;
+use crate::Timestamp;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Offset change, in either direction, that counts as suspicious unless configured otherwise
+pub const DEFAULT_ANOMALY_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Consecutive alternating offset changes that make an oscillation
+const OSCILLATION_STEPS: usize = 4;
+
+/// Number of previous offsets whose median a new sample is compared with
+const HISTORY_WINDOW: usize = 8;
+
+/// Fewest previous offsets needed before a disagreement with them is reported
+const MIN_HISTORY: usize = 4;
+
+/// What looked suspicious
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnomalyKind {
+    /// The server's time went backwards by `by` seconds relative to the previous sample
+    TimeWentBackwards { by: f64 },
+    /// The offset alternated direction with changes of up to `swing` seconds
+    Oscillation { swing: f64 },
+    /// The offset of `offset` seconds departed from the recent median of `median` seconds
+    HistoryDisagreement { offset: f64, median: f64 },
+    /// The hardware RTC read `offset` seconds away from the server's time, see
+    /// [`rtc`](crate::rtc)
+    RtcDisagreement { offset: f64 },
+}
+
+impl AnomalyKind {
+    /// Short machine-readable name, used by hooks
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnomalyKind::TimeWentBackwards { .. } => "time_went_backwards",
+            AnomalyKind::Oscillation { .. } => "oscillation",
+            AnomalyKind::HistoryDisagreement { .. } => "history_disagreement",
+            AnomalyKind::RtcDisagreement { .. } => "rtc_disagreement",
+        }
+    }
+
+    /// How far off the suspicious sample was, in seconds
+    pub fn magnitude(&self) -> f64 {
+        match *self {
+            AnomalyKind::TimeWentBackwards { by } => by,
+            AnomalyKind::Oscillation { swing } => swing,
+            AnomalyKind::HistoryDisagreement { offset, median } => (offset - median).abs(),
+            AnomalyKind::RtcDisagreement { offset } => offset.abs(),
+        }
+    }
+}
+
+/// A suspicious sample and the server it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    /// Server name as configured
+    pub server: String,
+    /// Server time of the sample
+    pub time: Timestamp,
+    pub kind: AnomalyKind,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            AnomalyKind::TimeWentBackwards { by } => {
+                write!(f, "{} went backwards by {:.6}s", self.server, by)
+            }
+            AnomalyKind::Oscillation { swing } => write!(
+                f,
+                "offsets from {} are oscillating by up to {:.6}s",
+                self.server, swing
+            ),
+            AnomalyKind::HistoryDisagreement { offset, median } => write!(
+                f,
+                "offset {:.6}s from {} disagrees with the recent median of {:.6}s",
+                offset, self.server, median
+            ),
+            AnomalyKind::RtcDisagreement { offset } => write!(
+                f,
+                "the RTC is {:.3}s away from the time of {}",
+                offset, self.server
+            ),
+        }
+    }
+}
+
+/// Checks the newest sample in `history` (oldest first) against the ones before it.
+///
+/// `threshold` is in seconds.
+pub fn detect(history: &[OffsetSample], threshold: f64) -> Option<AnomalyKind> {
+    let [.., previous, latest] = history else {
+        return None;
+    };
+
+    let recent = &history[history.len().saturating_sub(OSCILLATION_STEPS + 1)..];
+    let steps: Vec<f64> = recent
+        .windows(2)
+        .map(|w| w[1].offset - w[0].offset)
+        .collect();
+    if steps.len() == OSCILLATION_STEPS
+        && steps.iter().all(|step| step.abs() > threshold)
+        && steps.windows(2).all(|w| w[0].signum() != w[1].signum())
+    {
+        let swing = steps.iter().fold(0.0_f64, |max, step| max.max(step.abs()));
+        return Some(AnomalyKind::Oscillation { swing });
+    }
+
+    let step = latest.offset - previous.offset;
+    if step < -threshold {
+        return Some(AnomalyKind::TimeWentBackwards { by: -step });
+    }
+
+    let earlier = &history[..history.len() - 1];
+    let window = &earlier[earlier.len().saturating_sub(HISTORY_WINDOW)..];
+    if window.len() >= MIN_HISTORY {
+        let median = median(window.iter().map(|sample| sample.offset).collect());
+        // Only the sample where the disagreement starts is reported, not every one after it
+        let was_agreeing = (previous.offset - median).abs() <= threshold;
+        if was_agreeing && (latest.offset - median).abs() > threshold {
+            return Some(AnomalyKind::HistoryDisagreement {
+                offset: latest.offset,
+                median,
+            });
+        }
+    }
+    None
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Where anomalies are reported besides the event stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnomalyHook {
+    /// Runs a program, given as whitespace-separated words, with the anomaly in the
+    /// `CLOCK_NTP_ANOMALY`, `CLOCK_NTP_SERVER`, and `CLOCK_NTP_MESSAGE` environment variables
+    Command(String),
+    /// POSTs the anomaly as JSON to a plain `http://` URL
+    Webhook(String),
+}
+
+impl FromStr for AnomalyHook {
+    type Err = String;
+
+    /// Parses `exec:COMMAND` or `http://HOST[:PORT]/PATH`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(command) = s.strip_prefix("exec:") {
+            if command.trim().is_empty() {
+                return Err("empty command in anomaly hook".to_string());
+            }
+            return Ok(AnomalyHook::Command(command.trim().to_string()));
+        }
+        if s.starts_with("http://") {
+            return Ok(AnomalyHook::Webhook(s.to_string()));
+        }
+        Err(format!(
+            "unknown anomaly hook '{}', expected exec:COMMAND or http://URL",
+            s
+        ))
+    }
+}
+
+impl fmt::Display for AnomalyHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnomalyHook::Command(command) => write!(f, "exec:{}", command),
+            AnomalyHook::Webhook(url) => f.write_str(url),
+        }
+    }
+}
+
+impl AnomalyHook {
+    /// Reports `anomaly` from a background thread, logging any failure
+    pub fn fire(&self, anomaly: &Anomaly) {
+        let hook = self.clone();
+        let anomaly = anomaly.clone();
+        std::thread::spawn(move || {
+            let result = match &hook {
+                AnomalyHook::Command(command) => alert::run_command(
+                    command,
+                    &[
+                        ("CLOCK_NTP_ANOMALY", anomaly.kind.name()),
+                        ("CLOCK_NTP_SERVER", &anomaly.server),
+                        ("CLOCK_NTP_MESSAGE", &anomaly.to_string()),
+                    ],
+                ),
+                AnomalyHook::Webhook(url) => alert::post_json(url, &anomaly_json(&anomaly)),
+            };
+            if let Err(e) = result {
+                clock_log!(Warn, Anomaly, "Anomaly hook {} failed: {}", hook, e);
+            }
+        });
+    }
+}
+
+/// The JSON body sent to webhooks
+fn anomaly_json(anomaly: &Anomaly) -> String {
+    let (offset, median) = match anomaly.kind {
+        AnomalyKind::TimeWentBackwards { .. } | AnomalyKind::Oscillation { .. } => (None, None),
+        AnomalyKind::HistoryDisagreement { offset, median } => (Some(offset), Some(median)),
+        AnomalyKind::RtcDisagreement { offset } => (Some(offset), None),
+    };
+    format!(
+        "{{\"anomaly\":{},\"server\":{},\"time\":{},\"magnitude\":{},\"offset\":{},\"median\":{},\"message\":{}}}",
+        json_string(anomaly.kind.name()),
+        json_string(&anomaly.server),
+        json_string(&anomaly.time.to_rfc3339()),
+        json_number(Some(anomaly.kind.magnitude())),
+        json_number(offset),
+        json_number(median),
+        json_string(&anomaly.to_string())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn history(offsets: &[f64]) -> Vec<OffsetSample> {
+        let start: Timestamp = "2026-02-03T00:00:00Z".parse().unwrap();
+        offsets
+            .iter()
+            .enumerate()
+            .map(|(i, &offset)| OffsetSample {
+                timestamp: start + Duration::from_secs(64 * i as u64),
+                offset,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detects_backward_jump() {
+        assert_eq!(
+            detect(&history(&[0.0, 0.5, -4.5]), 1.0),
+            Some(AnomalyKind::TimeWentBackwards { by: 5.0 })
+        );
+        assert_eq!(detect(&history(&[0.01, 0.012, 0.011]), 1.0), None);
+        assert_eq!(detect(&history(&[0.01]), 1.0), None);
+    }
+
+    #[test]
+    fn test_detects_oscillation() {
+        let kind = detect(&history(&[0.0, 2.0, -1.0, 2.0, -1.5]), 1.0);
+        assert_eq!(kind, Some(AnomalyKind::Oscillation { swing: 3.5 }));
+        assert_eq!(kind.unwrap().name(), "oscillation");
+    }
+
+    #[test]
+    fn test_detects_sudden_disagreement_once() {
+        let mut offsets = vec![0.0, 0.001, 0.002, 0.001, 3.0];
+        assert_eq!(
+            detect(&history(&offsets), 1.0),
+            Some(AnomalyKind::HistoryDisagreement {
+                offset: 3.0,
+                median: 0.001
+            })
+        );
+        offsets.push(3.001);
+        assert_eq!(detect(&history(&offsets), 1.0), None);
+    }
+
+    #[test]
+    fn test_anomaly_hook_from_str() {
+        assert_eq!(
+            "exec:/usr/bin/logger -t clock".parse(),
+            Ok(AnomalyHook::Command("/usr/bin/logger -t clock".to_string()))
+        );
+        assert_eq!(
+            "http://alerts:9000/hook".parse(),
+            Ok(AnomalyHook::Webhook("http://alerts:9000/hook".to_string()))
+        );
+        assert!("https://alerts/hook".parse::<AnomalyHook>().is_err());
+        assert!("exec: ".parse::<AnomalyHook>().is_err());
+    }
+
+    #[test]
+    fn test_webhook_posts_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with('}') {
+                let n = stream.read(&mut buf).unwrap();
+                request.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            request
+        });
+
+        let anomaly = Anomaly {
+            server: "pool.ntp.org:123".to_string(),
+            time: "2026-02-03T06:50:57Z".parse().unwrap(),
+            kind: AnomalyKind::TimeWentBackwards { by: 5.0 },
+        };
+        alert::post_json(&url, &anomaly_json(&anomaly)).unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /alerts HTTP/1.1\r\n"));
+        assert!(request.contains("\"anomaly\":\"time_went_backwards\""));
+        assert!(request.contains("\"magnitude\":5,"));
+    }
+}
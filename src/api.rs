@@ -0,0 +1,768 @@
+//! # HTTP Time API
+//!
+//! A small HTTP/1.1 server so that other processes — for example sidecar containers in the
+//! same pod — can read the disciplined time without speaking NTP:
+//!
+//! * `GET /time` — the current time as JSON (`unix_nanos`, `rfc3339`, `synchronized`,
+//!   `source`)
+//! * `GET /status` — health, time source, drift estimate, and sync statistics as JSON
+//! * `GET /livez` — `200 OK` while the server runs, for a liveness probe. Losing NTP time
+//!   is no reason for the kubelet to restart the pod, and a stalled sync loop is restarted
+//!   by the clock's own watchdog.
+//! * `GET /readyz` — the clock's [`Health`] as text, with `200 OK` when healthy,
+//!   `503 Service Unavailable` before the first sync, and `500 Internal Server Error` once
+//!   the last sync is older than the staleness threshold, for a readiness probe that keeps
+//!   the pod out of service while its time is not correct. [`spawn_gated_server`] answers
+//!   `/status` with the same codes, for probes that only know one endpoint.
+//! * `GET /metrics` — the same figures in the Prometheus text exposition format
+//! * `GET /history` — the in-memory [sync history](crate::history) as a JSON array of
+//!   `time` (Unix milliseconds), `server`, `offset`, `delay`, `jitter` (seconds), and
+//!   `adjustment`, in the shape Grafana's JSON and Infinity data sources read
+//! * `GET /events` — the last sync attempts, failed ones included, as a JSON array of
+//!   `time`, `server`, `result`, `offset`, and `error`; `?n=N` limits it to the last `N`
+//! * `GET /clients` — the clients of the [peer responder](crate::peer), the most recently
+//!   seen first, as a JSON array of `address`, `requests`, `rate_limited`, and
+//!   `first_seen` and `last_seen` (seconds ago)
+//! * `GET /dashboard` — a self-contained HTML page plotting offset, delay, and jitter from
+//!   `/history`, for a look at clock health without setting up Prometheus
+//! * `GET /ws` — with the `websocket` feature, a WebSocket pushing the time every second;
+//!   see [`websocket`](crate::websocket). Upgrades beyond
+//!   [`ApiConfig::max_subscribers`] are answered with `503 Service Unavailable`.
+//!
+//! Each connection is answered and closed by one of a few worker threads, and must send its
+//! request within two seconds, so a slow or idle client holds up one worker rather than
+//! every probe. The server is meant for a loopback or pod-local address; it has no
+//! authentication or TLS.
+
+use crate::json::{json_number, json_string};
+use crate::lock::MutexExt;
+use crate::logging::clock_log;
+use crate::sntp;
+use crate::stats::{LAST_DAY, LAST_HOUR};
+use crate::statsfile::offset_jitter;
+use crate::timeline::MAX_RECENT_ATTEMPTS;
+use crate::{ClockHandle, Health, OffsetSample, WindowStats};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Address `clock serve-api` listens on unless told otherwise
+pub const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:8123";
+
+/// WebSocket clients served at once unless configured otherwise
+pub const DEFAULT_MAX_SUBSCRIBERS: usize = 64;
+
+/// How often the accept loop checks the shutdown flag
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// Longest a client may take to send its request, and to take the response
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Threads answering connections
+const WORKERS: usize = 4;
+
+/// Accepted connections waiting for a worker; more are answered with 503 at once
+const BACKLOG: usize = 32;
+
+/// Largest request head that is read
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// The page served at `/dashboard`
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+/// Options for [`spawn_server_with_config`]
+#[derive(Debug, Clone)]
+pub struct ApiConfig {
+    /// Answer `/status` with the status code of `/readyz`
+    pub gated_status: bool,
+    /// WebSocket clients served at once; further upgrades are answered with 503
+    pub max_subscribers: usize,
+}
+
+impl ApiConfig {
+    pub fn new() -> Self {
+        ApiConfig {
+            gated_status: false,
+            max_subscribers: DEFAULT_MAX_SUBSCRIBERS,
+        }
+    }
+
+    pub fn with_gated_status(mut self, gated_status: bool) -> Self {
+        self.gated_status = gated_status;
+        self
+    }
+
+    pub fn with_max_subscribers(mut self, max_subscribers: usize) -> Self {
+        self.max_subscribers = max_subscribers;
+        self
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig::new()
+    }
+}
+
+/// Serves the API on `listener` from a background thread until `shutdown` is set
+pub fn spawn_server(
+    listener: TcpListener,
+    handle: ClockHandle,
+    shutdown: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    spawn_server_with_config(listener, handle, ApiConfig::new(), shutdown)
+}
+
+/// Like [`spawn_server`], but `/status` answers with the status code of `/readyz`: 503
+/// until the first sync and 500 while the clock is stale
+pub fn spawn_gated_server(
+    listener: TcpListener,
+    handle: ClockHandle,
+    shutdown: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let config = ApiConfig::new().with_gated_status(true);
+    spawn_server_with_config(listener, handle, config, shutdown)
+}
+
+/// Like [`spawn_server`], with the options in `config`
+pub fn spawn_server_with_config(
+    listener: TcpListener,
+    handle: ClockHandle,
+    config: ApiConfig,
+    shutdown: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    listener.set_nonblocking(true)?;
+    if let Ok(addr) = listener.local_addr() {
+        clock_log!(Info, Serving, "Serving the time API on http://{}", addr);
+    }
+    Ok(std::thread::spawn(move || {
+        let (queue, connections) = mpsc::sync_channel::<TcpStream>(BACKLOG);
+        let connections = Arc::new(Mutex::new(connections));
+        let subscribers = Arc::new(AtomicUsize::new(0));
+        let workers: Vec<JoinHandle<()>> = (0..WORKERS)
+            .map(|_| {
+                let connections = Arc::clone(&connections);
+                let handle = handle.clone();
+                let shutdown = Arc::clone(&shutdown);
+                let config = config.clone();
+                let subscribers = Arc::clone(&subscribers);
+                std::thread::spawn(move || loop {
+                    // Ends once the accept loop drops the queue
+                    let Ok(stream) = connections.lock_or_recover().recv() else {
+                        return;
+                    };
+                    let served =
+                        serve_connection(stream, &handle, &shutdown, &config, &subscribers);
+                    if let Err(e) = served {
+                        clock_log!(Warn, Serving, "Time API request failed: {}", e);
+                    }
+                })
+            })
+            .collect();
+
+        while !shutdown.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(TrySendError::Full(stream)) = queue.try_send(stream) {
+                        refuse(stream);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_POLL),
+                Err(e) => {
+                    clock_log!(Warn, Serving, "Failed to accept time API connection: {}", e);
+                    std::thread::sleep(ACCEPT_POLL);
+                }
+            }
+        }
+        drop(queue);
+        for worker in workers {
+            let _ = worker.join();
+        }
+    }))
+}
+
+/// Answers a connection no worker is free for with 503, without waiting on the client
+fn refuse(mut stream: TcpStream) {
+    clock_log!(Warn, Serving, "Time API busy, refusing a connection");
+    let body = "503 Service Unavailable\n";
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_write_timeout(Some(ACCEPT_POLL));
+    let _ = write!(
+        stream,
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+}
+
+/// A response ready to be written
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(body: String) -> Self {
+        Response {
+            status: "200 OK",
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn text(status: &'static str, body: String) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body,
+        }
+    }
+
+    fn error(status: &'static str) -> Self {
+        Response::text(status, format!("{}\n", status))
+    }
+}
+
+#[cfg_attr(not(feature = "websocket"), allow(unused_variables))]
+fn serve_connection(
+    mut stream: TcpStream,
+    handle: &ClockHandle,
+    shutdown: &Arc<AtomicBool>,
+    config: &ApiConfig,
+    subscribers: &Arc<AtomicUsize>,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    // One deadline for the whole request, so that a client trickling bytes in cannot keep
+    // the worker past it
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_LEN {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "request not sent in time"))?;
+        stream.set_read_timeout(Some(remaining))?;
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut lines = request.lines();
+    let mut parts = lines.next().unwrap_or("").split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+    #[cfg(feature = "websocket")]
+    if method == "GET" && target.split('?').next() == Some("/ws") {
+        // Header names are case-insensitive; they are compared in lowercase
+        let headers: Vec<(String, String)> = lines
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        return crate::websocket::upgrade(
+            stream,
+            target,
+            &headers,
+            handle.clone(),
+            Arc::clone(subscribers),
+            config.max_subscribers,
+            Arc::clone(shutdown),
+        );
+    }
+
+    let response = route(method, target, handle, config.gated_status);
+
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    if response.status.starts_with("405") {
+        head.push_str("Allow: GET, HEAD\r\n");
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    if method != "HEAD" {
+        stream.write_all(response.body.as_bytes())?;
+    }
+    stream.flush()
+}
+
+fn route(method: &str, target: &str, handle: &ClockHandle, gated: bool) -> Response {
+    let path = target.split('?').next().unwrap_or("");
+    if !matches!(
+        path,
+        "/time"
+            | "/status"
+            | "/livez"
+            | "/readyz"
+            | "/metrics"
+            | "/history"
+            | "/events"
+            | "/clients"
+            | "/dashboard"
+    ) {
+        return Response::error("404 Not Found");
+    }
+    if !matches!(method, "GET" | "HEAD") {
+        return Response::error("405 Method Not Allowed");
+    }
+    match path {
+        "/time" => Response::json(time_json(handle)),
+        "/status" if gated => Response {
+            status: readiness(handle.health()),
+            ..Response::json(status_json(handle))
+        },
+        "/status" => Response::json(status_json(handle)),
+        "/livez" => Response::text("200 OK", "ok\n".to_string()),
+        "/readyz" => {
+            let health = handle.health();
+            Response::text(readiness(health), format!("{}\n", health))
+        }
+        "/history" => Response::json(history_json(handle)),
+        "/events" => Response::json(events_json(target, handle)),
+        "/clients" => Response::json(clients_json(handle)),
+        "/dashboard" => Response {
+            status: "200 OK",
+            content_type: "text/html; charset=utf-8",
+            body: DASHBOARD_HTML.to_string(),
+        },
+        _ => Response {
+            status: "200 OK",
+            content_type: "text/plain; version=0.0.4",
+            body: metrics_text(handle),
+        },
+    }
+}
+
+/// Status line of a readiness probe of a clock in `health`
+fn readiness(health: Health) -> &'static str {
+    match health {
+        Health::Healthy => "200 OK",
+        Health::Stale { .. } => "500 Internal Server Error",
+        Health::Unsynchronized => "503 Service Unavailable",
+    }
+}
+
+fn time_json(handle: &ClockHandle) -> String {
+    let now = handle.now_timestamp();
+    format!(
+        "{{\"unix_nanos\":{},\"rfc3339\":{},\"synchronized\":{},\"source\":{}}}",
+        now.unix_nanos(),
+        json_string(&now.to_rfc3339()),
+        handle.is_synchronized(),
+        json_string(&handle.time_source().to_string())
+    )
+}
+
+fn status_json(handle: &ClockHandle) -> String {
+    let health = handle.health();
+    let own = handle.server_state();
+    let stats = handle.stats();
+    let heartbeat_age = handle
+        .last_heartbeat()
+        .map(|beat| beat.elapsed().as_secs_f64());
+    let sources: Vec<String> = handle
+        .source_weights()
+        .iter()
+        .map(|source| {
+            format!(
+                "{{\"server\":{},\"stratum\":{},\"weight\":{},\"offset\":{},\
+                 \"root_distance\":{},\"jitter\":{}}}",
+                json_string(&source.server),
+                source.stratum,
+                json_number(Some(source.weight)),
+                json_number(Some(source.offset)),
+                json_number(Some(source.root_distance)),
+                json_number(Some(source.jitter))
+            )
+        })
+        .collect();
+    format!(
+        "{{\"health\":{},\"healthy\":{},\"synchronized\":{},\"source\":{},\"drift_ppm\":{},\
+         \"stratum\":{},\"reference_id\":{},\"root_delay\":{},\"root_dispersion\":{},\
+         \"sources\":[{}],\"last_heartbeat_age_seconds\":{},\
+         \"stats\":{{\"total_attempts\":{},\"successful_syncs\":{},\"failed_syncs\":{},\
+         \"worker_restarts\":{},\"last_offset\":{},\"mean_abs_offset\":{},\
+         \"max_abs_offset\":{},\"last_delay\":{},\"mean_delay\":{},\"jitter\":{},\"steps\":{},\
+         \"slews\":{},\"consecutive_failures\":{},\"last_success\":{},\"last_hour\":{},\
+         \"last_day\":{}}}}}",
+        json_string(&health.to_string()),
+        health.is_healthy(),
+        handle.is_synchronized(),
+        json_string(&handle.time_source().to_string()),
+        json_number(handle.drift_ppm()),
+        own.stratum,
+        json_string(&sntp::display_reference_id(own.stratum, own.reference_id).to_string()),
+        json_number(Some(own.root_delay)),
+        json_number(Some(own.root_dispersion)),
+        sources.join(","),
+        json_number(heartbeat_age),
+        stats.total_attempts,
+        stats.successful_syncs,
+        stats.failed_syncs,
+        stats.worker_restarts,
+        json_number(stats.last_offset),
+        json_number(Some(stats.mean_abs_offset)),
+        json_number(Some(stats.max_abs_offset)),
+        json_number(stats.last_delay),
+        json_number(Some(stats.mean_delay)),
+        json_number(Some(stats.jitter)),
+        stats.steps,
+        stats.slews,
+        stats.consecutive_failures,
+        stats
+            .last_success
+            .map_or("null".to_string(), |time| json_string(&time.to_rfc3339())),
+        window_json(handle.window_stats(LAST_HOUR)),
+        window_json(handle.window_stats(LAST_DAY))
+    )
+}
+
+fn window_json(stats: WindowStats) -> String {
+    format!(
+        "{{\"total_attempts\":{},\"successful_syncs\":{},\"failed_syncs\":{}}}",
+        stats.total_attempts, stats.successful_syncs, stats.failed_syncs
+    )
+}
+
+fn history_json(handle: &ClockHandle) -> String {
+    let records = handle.sync_history();
+    let offsets: Vec<OffsetSample> = records
+        .iter()
+        .map(|record| OffsetSample {
+            timestamp: record.time,
+            offset: record.offset,
+        })
+        .collect();
+    let entries: Vec<String> = records
+        .iter()
+        .enumerate()
+        .map(|(i, record)| {
+            format!(
+                "{{\"time\":{},\"server\":{},\"offset\":{},\"delay\":{},\"jitter\":{},\
+                 \"adjustment\":{}}}",
+                record.time.unix_millis(),
+                json_string(&record.server),
+                json_number(Some(record.offset)),
+                json_number(Some(record.delay)),
+                json_number(Some(offset_jitter(&offsets[..=i]))),
+                record
+                    .adjustment
+                    .map_or("null".to_string(), |kind| json_string(&kind.to_string()))
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+fn events_json(target: &str, handle: &ClockHandle) -> String {
+    let n = target
+        .split_once('?')
+        .into_iter()
+        .flat_map(|(_, query)| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("n=")?.parse().ok())
+        .unwrap_or(MAX_RECENT_ATTEMPTS);
+    let entries: Vec<String> = handle
+        .recent_events(n)
+        .iter()
+        .map(|attempt| {
+            format!(
+                "{{\"time\":{},\"server\":{},\"result\":{},\"offset\":{},\"error\":{}}}",
+                json_string(&attempt.at.to_rfc3339()),
+                attempt
+                    .server
+                    .as_deref()
+                    .map_or("null".to_string(), json_string),
+                json_string(&attempt.result.to_string()),
+                json_number(attempt.offset),
+                attempt
+                    .error
+                    .as_deref()
+                    .map_or("null".to_string(), json_string)
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+fn clients_json(handle: &ClockHandle) -> String {
+    let entries: Vec<String> = handle
+        .clients()
+        .iter()
+        .map(|client| {
+            format!(
+                "{{\"address\":{},\"requests\":{},\"rate_limited\":{},\"first_seen\":{},\"last_seen\":{}}}",
+                json_string(&client.addr.to_string()),
+                client.requests,
+                client.rate_limited,
+                json_number(Some(client.first_seen.elapsed().as_secs_f64())),
+                json_number(Some(client.last_seen.elapsed().as_secs_f64()))
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+fn metrics_text(handle: &ClockHandle) -> String {
+    let stats = handle.stats();
+    let health = handle.health();
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        let _ = writeln!(out, "# HELP clock_ntp_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE clock_ntp_{} {}", name, kind);
+        let _ = writeln!(out, "clock_ntp_{} {}", name, value);
+    };
+    metric(
+        "synchronized",
+        "gauge",
+        "Whether the clock has obtained NTP time",
+        (handle.is_synchronized() as u8).to_string(),
+    );
+    metric(
+        "health",
+        "gauge",
+        "0 when healthy, 1 when stale, 2 when unsynchronized",
+        health.exit_code().to_string(),
+    );
+    if let Health::Stale { age } = health {
+        metric(
+            "last_sync_age_seconds",
+            "gauge",
+            "Time since the last successful sync, reported while stale",
+            age.as_secs_f64().to_string(),
+        );
+    }
+    metric(
+        "sync_attempts_total",
+        "counter",
+        "NTP sync attempts",
+        stats.total_attempts.to_string(),
+    );
+    metric(
+        "sync_successes_total",
+        "counter",
+        "Successful NTP syncs",
+        stats.successful_syncs.to_string(),
+    );
+    metric(
+        "sync_failures_total",
+        "counter",
+        "Failed NTP syncs",
+        stats.failed_syncs.to_string(),
+    );
+    metric(
+        "worker_restarts_total",
+        "counter",
+        "Restarts of the background sync loop after a panic, exit, or stall",
+        stats.worker_restarts.to_string(),
+    );
+    metric(
+        "consecutive_failures",
+        "gauge",
+        "Failed NTP syncs since the last successful one",
+        stats.consecutive_failures.to_string(),
+    );
+    metric(
+        "steps_total",
+        "counter",
+        "Times the clock was stepped",
+        stats.steps.to_string(),
+    );
+    metric(
+        "slews_total",
+        "counter",
+        "Times the clock was slewed",
+        stats.slews.to_string(),
+    );
+    if let (Some(offset), Some(delay)) = (stats.last_offset, stats.last_delay) {
+        metric(
+            "offset_seconds",
+            "gauge",
+            "Offset of the last synced time from the clock's reading",
+            offset.to_string(),
+        );
+        metric(
+            "max_abs_offset_seconds",
+            "gauge",
+            "Largest absolute offset of a successful sync",
+            stats.max_abs_offset.to_string(),
+        );
+        metric(
+            "delay_seconds",
+            "gauge",
+            "Round-trip delay of the last successful sync",
+            delay.to_string(),
+        );
+        metric(
+            "jitter_seconds",
+            "gauge",
+            "RMS of the differences between recent offsets",
+            stats.jitter.to_string(),
+        );
+    }
+    if let Some(beat) = handle.last_heartbeat() {
+        metric(
+            "last_heartbeat_age_seconds",
+            "gauge",
+            "Time since the background sync loop last completed a cycle",
+            beat.elapsed().as_secs_f64().to_string(),
+        );
+    }
+    if let Some(drift) = handle.drift_ppm() {
+        metric(
+            "drift_ppm",
+            "gauge",
+            "Estimated local oscillator frequency error in PPM",
+            drift.to_string(),
+        );
+    }
+    metric(
+        "time_seconds",
+        "gauge",
+        "Disciplined time in seconds since the Unix epoch",
+        (handle.now_timestamp().unix_nanos() as f64 / 1e9).to_string(),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Clock;
+
+    fn get(addr: std::net::SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serves_time_status_and_metrics() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server = spawn_server(listener, clock.handle(), Arc::clone(&shutdown)).unwrap();
+
+        let time = get(addr, "GET /time HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(time.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(time.contains("\"synchronized\":false"));
+        assert!(time.contains("\"source\":\"system-derived, unverified\""));
+
+        let status = get(addr, "GET /status?verbose=1 HTTP/1.1\r\n\r\n");
+        assert!(status.contains("\"health\":\"unsynchronized\""));
+        assert!(status.contains("\"drift_ppm\":null"));
+        assert!(status.contains("\"stratum\":16,\"reference_id\":\"INIT\",\"root_delay\":0,"));
+        assert!(status.contains("\"last_heartbeat_age_seconds\":null"));
+        assert!(status.contains("\"worker_restarts\":0"));
+        assert!(status.contains("\"last_hour\":{\"total_attempts\":0,"));
+        assert!(status.contains("\"last_offset\":null,"));
+        assert!(status.contains("\"consecutive_failures\":0,\"last_success\":null"));
+
+        let metrics = get(addr, "GET /metrics HTTP/1.1\r\n\r\n");
+        assert!(metrics.contains("\nclock_ntp_synchronized 0\n"));
+        assert!(metrics.contains("# TYPE clock_ntp_sync_attempts_total counter\n"));
+        assert!(metrics.contains("\nclock_ntp_worker_restarts_total 0\n"));
+        assert!(metrics.contains("\nclock_ntp_steps_total 0\n"));
+        assert!(!metrics.contains("clock_ntp_offset_seconds"));
+
+        assert!(get(addr, "GET /history HTTP/1.1\r\n\r\n").ends_with("\r\n\r\n[]"));
+        assert!(get(addr, "GET /events?n=5 HTTP/1.1\r\n\r\n").ends_with("\r\n\r\n[]"));
+        assert!(get(addr, "GET /clients HTTP/1.1\r\n\r\n").ends_with("\r\n\r\n[]"));
+        let dashboard = get(addr, "GET /dashboard HTTP/1.1\r\n\r\n");
+        assert!(dashboard.contains("Content-Type: text/html"));
+        assert!(dashboard.contains("fetch(\"history\")"));
+
+        assert!(get(addr, "GET /nope HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        let post = get(addr, "POST /time HTTP/1.1\r\n\r\n");
+        assert!(post.starts_with("HTTP/1.1 405") && post.contains("Allow: GET, HEAD"));
+        assert!(get(addr, "HEAD /time HTTP/1.1\r\n\r\n").ends_with("\r\n\r\n"));
+
+        let livez = get(addr, "GET /livez HTTP/1.1\r\n\r\n");
+        assert!(livez.starts_with("HTTP/1.1 200 OK\r\n") && livez.ends_with("\r\n\r\nok\n"));
+        let readyz = get(addr, "GET /readyz HTTP/1.1\r\n\r\n");
+        assert!(readyz.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(readyz.ends_with("\r\n\r\nunsynchronized\n"));
+        assert!(status.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        shutdown.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_idle_clients_do_not_hold_up_probes() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server = spawn_server(listener, clock.handle(), Arc::clone(&shutdown)).unwrap();
+
+        // Clients that connect and send nothing, fewer than there are workers
+        let idle: Vec<TcpStream> = (0..WORKERS - 1)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+        std::thread::sleep(ACCEPT_POLL * 2);
+        let started = Instant::now();
+        let livez = get(addr, "GET /livez HTTP/1.1\r\n\r\n");
+        assert!(livez.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(
+            started.elapsed() < REQUEST_TIMEOUT / 2,
+            "{:?}",
+            started.elapsed()
+        );
+
+        // They are cut off at the request deadline
+        for mut stream in idle {
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            assert!(response.is_empty());
+        }
+        shutdown.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_gated_status_follows_readiness() {
+        let server = crate::tests::spawn_fake_server(crate::Timestamp::now(), 1);
+        let config = crate::ClockConfig::new()
+            .with_servers(vec![server])
+            .with_staleness_threshold(Some(Duration::from_millis(500)));
+        let clock = Clock::with_config(config);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server = spawn_gated_server(listener, clock.handle(), Arc::clone(&shutdown)).unwrap();
+
+        let status = get(addr, "GET /status HTTP/1.1\r\n\r\n");
+        assert!(status.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(status.contains("Content-Type: application/json"));
+        assert!(get(addr, "GET /readyz HTTP/1.1\r\n\r\n").ends_with("\r\n\r\nhealthy\n"));
+
+        std::thread::sleep(Duration::from_millis(600));
+        let status = get(addr, "GET /status HTTP/1.1\r\n\r\n");
+        assert!(status.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(status.contains("\"healthy\":false"));
+        let readyz = get(addr, "GET /readyz HTTP/1.1\r\n\r\n");
+        assert!(readyz.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(get(addr, "GET /livez HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 200 OK"));
+
+        shutdown.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+    }
+}
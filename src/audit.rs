@@ -0,0 +1,397 @@
+//! # Adjustment Audit Log
+//!
+//! An append-only record of every correction made to the clock, for environments (such as
+//! MiFID II or FINRA regulated trading) that must show when and why their clock was changed.
+//! Each line holds a sequence number, the time before and after the adjustment, whether it
+//! was a step or a slew, the measured offset and round-trip delay, and the server the
+//! correction came from:
+//!
+//! ```text
+//! # seq before after kind offset delay server address hash
+//! 1 2026-02-03T06:50:55.120+00:00 2026-02-03T06:50:57.250+00:00 step 2.130000000 0.021000000 pool.ntp.org:123 192.0.2.1:123 -
+//! ```
+//!
+//! A disagreement between NTP and the hardware RTC (see [`rtc`](crate::rtc)) is recorded as
+//! an `rtc-conflict` record whose `before` is the NTP time and `after` the RTC's, whether or
+//! not the clock was changed for it.
+//!
+//! With [`AuditLog::with_hash_chain`], the last column is the SHA-256 of the previous
+//! record's hash followed by the rest of the line, so editing or deleting a record breaks
+//! every hash after it. [`verify`] checks the chain and [`query`] reads the records back.
+
+use crate::{NtpSample, Timestamp};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// First line of a new log, naming the columns
+const HEADER: &str = "# seq before after kind offset delay server address hash";
+
+/// Hash column of records written without a hash chain
+const NO_HASH: &str = "-";
+
+/// Previous hash of the first record in a chain
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// How an adjustment was applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdjustmentKind {
+    /// The clock jumped to the new time at once
+    Step,
+    /// The clock's rate was changed to reach the new time gradually
+    Slew,
+    /// NTP and the hardware RTC disagreed: `before` is the NTP time and `after` the RTC's
+    RtcConflict,
+}
+
+impl fmt::Display for AdjustmentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AdjustmentKind::Step => "step",
+            AdjustmentKind::Slew => "slew",
+            AdjustmentKind::RtcConflict => "rtc-conflict",
+        })
+    }
+}
+
+impl FromStr for AdjustmentKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "step" => Ok(AdjustmentKind::Step),
+            "slew" => Ok(AdjustmentKind::Slew),
+            "rtc-conflict" => Ok(AdjustmentKind::RtcConflict),
+            other => Err(format!("unknown adjustment kind '{}'", other)),
+        }
+    }
+}
+
+/// One audited adjustment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adjustment {
+    /// Position in the log, starting at 1
+    pub seq: u64,
+    /// What the clock read just before the adjustment
+    pub before: Timestamp,
+    /// What the clock read just after the adjustment
+    pub after: Timestamp,
+    pub kind: AdjustmentKind,
+    /// Measured offset of the server from the local clock, in seconds
+    pub offset: f64,
+    /// Round-trip delay of the sample, in seconds
+    pub delay: f64,
+    /// Server name as configured
+    pub server: String,
+    /// Address the sample came from
+    pub addr: SocketAddr,
+    /// Hex SHA-256 chaining this record to the previous one, if the log is hash-chained
+    pub hash: Option<String>,
+}
+
+impl Adjustment {
+    /// The line without its hash column, which is what the hash covers
+    fn body(&self) -> String {
+        format!(
+            "{} {} {} {} {:.9} {:.9} {} {}",
+            self.seq,
+            self.before.to_rfc3339(),
+            self.after.to_rfc3339(),
+            self.kind,
+            self.offset,
+            self.delay,
+            self.server,
+            self.addr
+        )
+    }
+
+    fn parse(line: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [seq, before, after, kind, offset, delay, server, addr, hash] = fields[..] else {
+            return Err(format!("expected 9 fields, found {}", fields.len()));
+        };
+        let number = |value: &str, name: &str| {
+            value
+                .parse::<f64>()
+                .map_err(|e| format!("invalid {} '{}': {}", name, value, e))
+        };
+        Ok(Adjustment {
+            seq: seq
+                .parse()
+                .map_err(|e| format!("invalid sequence number '{}': {}", seq, e))?,
+            before: Timestamp::parse_rfc3339(before)?,
+            after: Timestamp::parse_rfc3339(after)?,
+            kind: kind.parse()?,
+            offset: number(offset, "offset")?,
+            delay: number(delay, "delay")?,
+            server: server.to_string(),
+            addr: addr
+                .parse()
+                .map_err(|e| format!("invalid address '{}': {}", addr, e))?,
+            hash: (hash != NO_HASH).then(|| hash.to_string()),
+        })
+    }
+}
+
+/// Appends [`Adjustment`]s to a log file
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+    hash_chain: bool,
+    next_seq: u64,
+    /// Hash of the last record, or `None` if it was not chained
+    last_hash: Option<String>,
+}
+
+impl AuditLog {
+    /// Opens (creating if needed) the log at `path`, continuing the numbering and hash chain
+    /// of any records already in it
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let existing = match read(&path) {
+            Ok(records) => records,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let last = existing.last();
+        if last.is_none() && file.metadata()?.len() == 0 {
+            writeln!(file, "{}", HEADER)?;
+        }
+        Ok(AuditLog {
+            path,
+            file,
+            hash_chain: false,
+            next_seq: last.map_or(1, |record| record.seq + 1),
+            last_hash: last.and_then(|record| record.hash.clone()),
+        })
+    }
+
+    /// Chains each new record to the previous one with SHA-256
+    pub fn with_hash_chain(mut self, enabled: bool) -> Self {
+        self.hash_chain = enabled;
+        self
+    }
+
+    /// Returns the path of the log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records an adjustment from `before` to `after` made for `sample`, syncing it to disk
+    /// before returning
+    pub fn append(
+        &mut self,
+        kind: AdjustmentKind,
+        before: Timestamp,
+        after: Timestamp,
+        sample: &NtpSample,
+    ) -> io::Result<Adjustment> {
+        let mut record = Adjustment {
+            seq: self.next_seq,
+            before,
+            after,
+            kind,
+            offset: after.seconds_since(before),
+            delay: sample.delay.as_secs_f64(),
+            server: sample.server.clone(),
+            addr: sample.addr,
+            hash: None,
+        };
+        let body = record.body();
+        if self.hash_chain {
+            let previous = self.last_hash.as_deref().unwrap_or(GENESIS_HASH);
+            record.hash = Some(chain_hash(previous, &body));
+        }
+        writeln!(
+            self.file,
+            "{} {}",
+            body,
+            record.hash.as_deref().unwrap_or(NO_HASH)
+        )?;
+        self.file.sync_data()?;
+
+        self.next_seq += 1;
+        self.last_hash = record.hash.clone();
+        Ok(record)
+    }
+
+    /// Returns the records whose `after` time falls in `range`, oldest first
+    pub fn query(&self, range: impl RangeBounds<Timestamp>) -> io::Result<Vec<Adjustment>> {
+        query(&self.path, range)
+    }
+}
+
+/// Reads every record in the log at `path`, oldest first
+pub fn read(path: &Path) -> io::Result<Vec<Adjustment>> {
+    let contents = fs::read_to_string(path)?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            Adjustment::parse(line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {}", path.display(), number + 1, e),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Reads the records in the log at `path` whose `after` time falls in `range`
+pub fn query(path: &Path, range: impl RangeBounds<Timestamp>) -> io::Result<Vec<Adjustment>> {
+    let mut records = read(path)?;
+    records.retain(|record| range.contains(&record.after));
+    Ok(records)
+}
+
+/// Checks the hash chain of the log at `path`, returning the number of records.
+///
+/// Fails with [`io::ErrorKind::InvalidData`] at the first record that is out of sequence,
+/// unhashed, or whose hash does not match.
+pub fn verify(path: &Path) -> io::Result<usize> {
+    let records = read(path)?;
+    let mut previous = GENESIS_HASH.to_string();
+    for (index, record) in records.iter().enumerate() {
+        let broken = |reason: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record {}: {}", record.seq, reason),
+            )
+        };
+        if record.seq != index as u64 + 1 {
+            return Err(broken(&format!("expected sequence number {}", index + 1)));
+        }
+        let Some(hash) = &record.hash else {
+            return Err(broken("not hash-chained"));
+        };
+        if *hash != chain_hash(&previous, &record.body()) {
+            return Err(broken("hash mismatch"));
+        }
+        previous = hash.clone();
+    }
+    Ok(records.len())
+}
+
+/// Hex SHA-256 of the previous hash, a space, and the record body
+fn chain_hash(previous: &str, body: &str) -> String {
+    sha256(format!("{} {}", previous, body).as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("clock-ntp-audit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn sample() -> NtpSample {
+        NtpSample {
+            server: "pool.ntp.org:123".to_string(),
+            addr: "192.0.2.1:123".parse().unwrap(),
+            time: "2026-02-03T06:50:57.25Z".parse().unwrap(),
+            delay: Duration::from_millis(21),
+            root_delay: 0.0,
+            root_dispersion: 0.0014,
+            stratum: 2,
+            reference_id: [192, 0, 2, 1],
+            precision: -20,
+        }
+    }
+
+    #[test]
+    fn test_sha256_known_answers() {
+        let hex = |digest: [u8; 32]| -> String {
+            digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+        };
+        assert_eq!(
+            hex(sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn test_append_query_and_reopen() {
+        let path = temp_path("plain");
+        let sample = sample();
+        let before: Timestamp = "2026-02-03T06:50:55.12Z".parse().unwrap();
+
+        let mut log = AuditLog::open(&path).unwrap();
+        let first = log
+            .append(AdjustmentKind::Step, before, sample.time, &sample)
+            .unwrap();
+        assert_eq!(first.seq, 1);
+        assert!((first.offset - 2.13).abs() < 1e-9);
+        assert_eq!(first.hash, None);
+        drop(log);
+
+        let mut log = AuditLog::open(&path).unwrap();
+        let later = sample.time + Duration::from_secs(3600);
+        let second = log
+            .append(AdjustmentKind::Slew, later, later, &sample)
+            .unwrap();
+        assert_eq!(second.seq, 2);
+
+        assert_eq!(read(&path).unwrap(), vec![first.clone(), second.clone()]);
+        assert_eq!(log.query(..later).unwrap(), vec![first]);
+        assert_eq!(log.query(later..).unwrap(), vec![second]);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap().lines().next(),
+            Some(HEADER)
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_hash_chain_detects_tampering() {
+        let path = temp_path("chained");
+        let sample = sample();
+        let mut log = AuditLog::open(&path).unwrap().with_hash_chain(true);
+        for i in 0..3 {
+            let after = sample.time + Duration::from_secs(i);
+            log.append(AdjustmentKind::Step, after, after, &sample)
+                .unwrap();
+        }
+        assert_eq!(verify(&path).unwrap(), 3);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, contents.replacen("step", "slew", 1)).unwrap();
+        let err = verify(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("record 1: hash mismatch"));
+
+        let lines: Vec<&str> = contents.lines().collect();
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(verify(&path)
+            .unwrap_err()
+            .to_string()
+            .contains("expected sequence number 1"));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,544 @@
+//! # Symmetric Key Authentication
+//!
+//! NTP's legacy authentication (RFC 5905) appends a MAC to a packet: a 32-bit key ID and a
+//! digest of the shared secret followed by the packet, MD5 (16 bytes) or SHA-1 (20 bytes)
+//! as ntpd and chrony compute it. A [`KeyStore`] holds the keys by ID and both signs
+//! packets and verifies them, comparing digests in constant time so that a forger learns
+//! nothing from how quickly a guess is refused. Packets whose MAC names an unknown key, is
+//! cut short, or does not match are rejected.
+//!
+//! ```
+//! use clock::auth::{DigestType, KeyStore, MacError, SymmetricKey};
+//! use clock::sntp;
+//!
+//! let mut keys = KeyStore::new();
+//! keys.add_key(SymmetricKey::new(7, DigestType::Sha1, b"shared secret".to_vec()));
+//! let request = keys.sign(7, &sntp::client_request()).unwrap();
+//! assert_eq!(keys.verify(&request), Ok(7));
+//!
+//! let mut forged = request.clone();
+//! forged[40] ^= 1;
+//! assert_eq!(keys.verify(&forged), Err(MacError::Mismatch(7)));
+//! ```
+//!
+//! Keys are usually shared through an ntpd-style keys file of `ID TYPE SECRET` lines, read
+//! with `KeyStore::from_ntp_keys_file` or parsed from a string:
+//!
+//! ```
+//! use clock::auth::KeyStore;
+//!
+//! let keys: KeyStore = "1 MD5 swordfish\n\
+//!                       2 SHA1 6f1ae0c3fdd6d68c4f1b6d7bc9c6dc0e7d6b8f49 # rotated in 2026\n"
+//!     .parse()
+//!     .unwrap();
+//! assert_eq!(keys.len(), 2);
+//! ```
+//!
+//! Keys are rotated at runtime with [`KeyStore::add_key`] and [`KeyStore::revoke_key`] on
+//! a `SharedKeyStore`; the daemon rereads its `--keys` file on SIGHUP.
+//!
+//! The [`Clock`](crate::Clock) engine signs its requests to servers with a `key` with the
+//! store in [`ClockConfig::keys`](crate::ClockConfig::keys) and drops replies that fail to
+//! verify; the [`peer`](crate::peer) responder verifies requests and signs its replies.
+
+use crate::extension::{self, ExtensionError};
+#[cfg(feature = "std")]
+use crate::lock::RwLockExt;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+
+/// Size of the key ID at the start of a MAC
+pub const KEY_ID_LEN: usize = 4;
+
+/// Longest secret a keys file gives as ASCII; longer ones are hex, as in ntpd
+const MAX_ASCII_SECRET_LEN: usize = 20;
+
+/// A digest a key is used with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestType {
+    Md5,
+    Sha1,
+}
+
+impl DigestType {
+    /// Size of the digest in a MAC
+    pub fn digest_len(&self) -> usize {
+        match self {
+            DigestType::Md5 => 16,
+            DigestType::Sha1 => 20,
+        }
+    }
+
+    /// Digest of `secret` followed by `data`
+    fn digest(&self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut message = Vec::with_capacity(secret.len() + data.len());
+        message.extend_from_slice(secret);
+        message.extend_from_slice(data);
+        match self {
+            DigestType::Md5 => md5(&message).to_vec(),
+            DigestType::Sha1 => sha1(&message).to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for DigestType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DigestType::Md5 => "MD5",
+            DigestType::Sha1 => "SHA1",
+        })
+    }
+}
+
+/// A shared secret and the ID and digest it is used with
+#[derive(Clone, PartialEq, Eq)]
+pub struct SymmetricKey {
+    pub id: u32,
+    pub digest: DigestType,
+    secret: Vec<u8>,
+}
+
+impl SymmetricKey {
+    /// Key `id` (1 or above) with `secret`, used with `digest`
+    pub fn new(id: u32, digest: DigestType, secret: Vec<u8>) -> Self {
+        SymmetricKey { id, digest, secret }
+    }
+
+    /// The MAC of `data`: the key ID, then the digest
+    pub fn mac(&self, data: &[u8]) -> Vec<u8> {
+        let mut mac = self.id.to_be_bytes().to_vec();
+        mac.extend_from_slice(&self.digest.digest(&self.secret, data));
+        mac
+    }
+}
+
+/// Leaves the secret out
+impl fmt::Debug for SymmetricKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SymmetricKey")
+            .field("id", &self.id)
+            .field("digest", &self.digest)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Why a packet's MAC was rejected, or a packet could not be signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacError {
+    /// The packet carries no MAC
+    Missing,
+    /// The MAC is a key ID of zero alone: the peer could not authenticate our packet
+    CryptoNak,
+    /// The MAC is too short or too long for its key's digest
+    Truncated { key_id: u32, len: usize },
+    /// No key with this ID is known
+    UnknownKey(u32),
+    /// The digest does not match the packet
+    Mismatch(u32),
+    /// The packet's extension fields, which precede the MAC, are malformed
+    Malformed(ExtensionError),
+}
+
+impl fmt::Display for MacError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MacError::Missing => f.write_str("packet has no MAC"),
+            MacError::CryptoNak => f.write_str("peer could not authenticate the packet"),
+            MacError::Truncated { key_id, len } => {
+                write!(f, "{}-byte MAC does not fit key {}", len, key_id)
+            }
+            MacError::UnknownKey(id) => write!(f, "unknown key {}", id),
+            MacError::Mismatch(id) => write!(f, "MAC does not match key {}", id),
+            MacError::Malformed(e) => write!(f, "malformed packet: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MacError {}
+
+/// Why a keys file could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFileError {
+    /// Line of the file, from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeyFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for KeyFileError {}
+
+/// The symmetric keys known to a client or server, by ID
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyStore {
+    keys: BTreeMap<u32, SymmetricKey>,
+}
+
+impl KeyStore {
+    /// A store without keys
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads an ntpd-style keys file, in the format its `FromStr` implementation parses
+    #[cfg(feature = "std")]
+    pub fn from_ntp_keys_file(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        std::fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Adds `key`, returning the key it replaces with the same ID
+    pub fn add_key(&mut self, key: SymmetricKey) -> Option<SymmetricKey> {
+        self.keys.insert(key.id, key)
+    }
+
+    /// Removes the key with `id`, so that packets signed with it are rejected from now on
+    pub fn revoke_key(&mut self, id: u32) -> Option<SymmetricKey> {
+        self.keys.remove(&id)
+    }
+
+    /// IDs of the keys, in ascending order
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.keys.keys().copied()
+    }
+
+    /// The key with `id`
+    pub fn get(&self, id: u32) -> Option<&SymmetricKey> {
+        self.keys.get(&id)
+    }
+
+    /// Number of keys
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether the store has no keys
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// `packet` followed by its MAC with key `id`
+    pub fn sign(&self, id: u32, packet: &[u8]) -> Result<Vec<u8>, MacError> {
+        let key = self.get(id).ok_or(MacError::UnknownKey(id))?;
+        let mut signed = packet.to_vec();
+        signed.extend_from_slice(&key.mac(packet));
+        Ok(signed)
+    }
+
+    /// Checks the MAC at the end of `packet`, after any extension fields, and returns the
+    /// ID of the key it was made with
+    pub fn verify(&self, packet: &[u8]) -> Result<u32, MacError> {
+        let parsed = extension::parse_packet(packet).map_err(|e| match e {
+            ExtensionError::TrailingBytes { len } => MacError::Truncated {
+                key_id: packet
+                    .get(packet.len() - len..)
+                    .and_then(|mac| mac.get(..KEY_ID_LEN))
+                    .map_or(0, |id| u32::from_be_bytes([id[0], id[1], id[2], id[3]])),
+                len,
+            },
+            e => MacError::Malformed(e),
+        })?;
+        let mac = parsed.mac.ok_or(MacError::Missing)?;
+        let key_id = u32::from_be_bytes([mac[0], mac[1], mac[2], mac[3]]);
+        if mac.len() == KEY_ID_LEN {
+            return Err(MacError::CryptoNak);
+        }
+        let key = self.get(key_id).ok_or(MacError::UnknownKey(key_id))?;
+        if mac.len() != KEY_ID_LEN + key.digest.digest_len() {
+            return Err(MacError::Truncated {
+                key_id,
+                len: mac.len(),
+            });
+        }
+        let signed = &packet[..packet.len() - mac.len()];
+        if !constant_time_eq(&key.mac(signed), mac) {
+            return Err(MacError::Mismatch(key_id));
+        }
+        Ok(key_id)
+    }
+}
+
+/// A [`KeyStore`] shared by the client, the responder, and whoever rotates its keys. Keys
+/// added or revoked through any clone take effect with the next packet; clones are equal
+/// to each other and to nothing else.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct SharedKeyStore(std::sync::Arc<std::sync::RwLock<KeyStore>>);
+
+#[cfg(feature = "std")]
+impl SharedKeyStore {
+    pub fn new(keys: KeyStore) -> Self {
+        SharedKeyStore(std::sync::Arc::new(std::sync::RwLock::new(keys)))
+    }
+
+    /// The keys, for signing or verifying
+    pub fn read(&self) -> std::sync::RwLockReadGuard<'_, KeyStore> {
+        self.0.read_or_recover()
+    }
+
+    /// The keys, for adding, revoking, or replacing them
+    pub fn write(&self) -> std::sync::RwLockWriteGuard<'_, KeyStore> {
+        self.0.write_or_recover()
+    }
+}
+
+#[cfg(feature = "std")]
+impl PartialEq for SharedKeyStore {
+    fn eq(&self, other: &Self) -> bool {
+        std::sync::Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Parses an ntpd-style keys file: one `ID TYPE SECRET` line per key, with `#` starting a
+/// comment. IDs run from 1 to 65535, types are `MD5` (or `M`) and `SHA1` (or `SHA`), and
+/// secrets are ASCII of up to 20 characters or, longer, hex; chrony's `ASCII:` and `HEX:`
+/// prefixes are understood too. Address restrictions after the secret are refused rather
+/// than ignored, as ignoring them would accept the key from anywhere.
+impl FromStr for KeyStore {
+    type Err = KeyFileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keys = KeyStore::new();
+        for (index, line) in s.lines().enumerate() {
+            let error = |message: String| KeyFileError {
+                line: index + 1,
+                message,
+            };
+            let mut fields = line
+                .split('#')
+                .next()
+                .unwrap_or_default()
+                .split_whitespace();
+            let Some(id) = fields.next() else {
+                continue;
+            };
+            let (Some(digest), Some(secret)) = (fields.next(), fields.next()) else {
+                return Err(error("expected a key ID, a type, and a secret".to_string()));
+            };
+            if fields.next().is_some() {
+                return Err(error("address restrictions are not supported".to_string()));
+            }
+            let id = match id.parse::<u32>() {
+                Ok(id @ 1..=65535) => id,
+                _ => return Err(error(format!("invalid key ID {:?}", id))),
+            };
+            let digest = match digest.to_ascii_uppercase().as_str() {
+                "MD5" | "M" => DigestType::Md5,
+                "SHA1" | "SHA" => DigestType::Sha1,
+                _ => return Err(error(format!("unsupported key type {:?}", digest))),
+            };
+            let secret = parse_secret(secret).map_err(error)?;
+            if keys
+                .add_key(SymmetricKey::new(id, digest, secret))
+                .is_some()
+            {
+                return Err(error(format!("key {} is defined twice", id)));
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// A secret from a keys file, as its bytes
+fn parse_secret(secret: &str) -> Result<Vec<u8>, String> {
+    let hex = match secret.split_once(':') {
+        Some(("ASCII", ascii)) => return Ok(ascii.as_bytes().to_vec()),
+        Some(("HEX", hex)) => hex,
+        _ if secret.len() <= MAX_ASCII_SECRET_LEN => return Ok(secret.as_bytes().to_vec()),
+        _ => secret,
+    };
+    if hex.is_empty() || hex.len() % 2 != 0 {
+        return Err("hex secret must have an even number of digits".to_string());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| "invalid hex secret".to_string())
+        })
+        .collect()
+}
+
+/// Whether `a` and `b` are equal, taking as long for every pair of the same length
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let difference = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    // Keeps the compiler from turning the fold into an early exit
+    core::hint::black_box(difference) == 0
+}
+
+fn md5(data: &[u8]) -> [u8; 16] {
+    Md5::digest(data).into()
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    Sha1::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::{ExtensionField, UNIQUE_IDENTIFIER};
+    use crate::sntp;
+    use alloc::string::String;
+    use alloc::vec;
+
+    fn hex(digest: &[u8]) -> String {
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn test_digest_known_answers() {
+        assert_eq!(hex(&md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(
+            hex(&md5(b"The quick brown fox jumps over the lazy dog")),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    fn keys() -> KeyStore {
+        let mut keys = KeyStore::new();
+        keys.add_key(SymmetricKey::new(1, DigestType::Md5, b"one".to_vec()));
+        keys.add_key(SymmetricKey::new(2, DigestType::Sha1, b"two".to_vec()));
+        keys
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let keys = keys();
+        let request = sntp::client_request();
+        for (id, len) in [(1, 68), (2, 72)] {
+            let signed = keys.sign(id, &request).unwrap();
+            assert_eq!(signed.len(), len);
+            assert_eq!(&signed[48..52], &id.to_be_bytes());
+            assert_eq!(keys.verify(&signed), Ok(id));
+        }
+        // The MAC covers extension fields before it
+        let uid = ExtensionField::new(UNIQUE_IDENTIFIER, vec![3; 32]);
+        let packet = extension::encode_packet(&request, &[uid], None);
+        let signed = keys.sign(2, &packet).unwrap();
+        assert_eq!(keys.verify(&signed), Ok(2));
+        assert_eq!(keys.sign(3, &request), Err(MacError::UnknownKey(3)));
+        assert!(format!("{:?}", keys.get(1).unwrap()).contains("Md5"));
+        assert!(!format!("{:?}", keys).contains("one"));
+    }
+
+    #[test]
+    fn test_verify_rejects_bad_macs() {
+        let keys = keys();
+        let request = sntp::client_request();
+        let signed = keys.sign(1, &request).unwrap();
+
+        let mut tampered = signed.clone();
+        tampered[47] ^= 1;
+        assert_eq!(keys.verify(&tampered), Err(MacError::Mismatch(1)));
+        assert_eq!(keys.verify(&request), Err(MacError::Missing));
+        assert_eq!(
+            keys.verify(&[request.as_slice(), &[0; 4]].concat()),
+            Err(MacError::CryptoNak)
+        );
+
+        let other = KeyStore::new().sign(1, &request);
+        assert_eq!(other, Err(MacError::UnknownKey(1)));
+        let mut unknown = signed.clone();
+        unknown[48..52].copy_from_slice(&9u32.to_be_bytes());
+        assert_eq!(keys.verify(&unknown), Err(MacError::UnknownKey(9)));
+
+        // An MD5 digest cut short, and one padded out to SHA-1's length
+        assert_eq!(
+            keys.verify(&signed[..60]),
+            Err(MacError::Truncated { key_id: 1, len: 12 })
+        );
+        let padded = [signed.as_slice(), &[0; 4]].concat();
+        assert_eq!(
+            keys.verify(&padded),
+            Err(MacError::Truncated { key_id: 1, len: 24 })
+        );
+    }
+
+    #[test]
+    fn test_parse_keys_file() {
+        let text = "# ntp.keys\n\
+                    1 M one\n\
+                    \n\
+                    2 SHA1 74776f   # short, so ASCII though it looks like hex\n\
+                    3 sha1 HEX:74776f\n\
+                    4 MD5 0123456789abcdef0123456789abcdef01234567\n\
+                    5 SHA ASCII:0123456789abcdef0123456789\n";
+        let parsed: KeyStore = text.parse().unwrap();
+        assert_eq!(parsed.ids().collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+        // Key 1 is the same as in `keys()`, so it verifies what that signed
+        let signed = keys().sign(1, &sntp::client_request()).unwrap();
+        assert_eq!(parsed.verify(&signed), Ok(1));
+        assert_eq!(parsed.get(2).unwrap().secret, b"74776f");
+        assert_eq!(parsed.get(3).unwrap().secret, b"two");
+        assert_eq!(parsed.get(4).unwrap().secret.len(), 20);
+        assert_eq!(parsed.get(5).unwrap().secret.len(), 26);
+
+        for (text, line, message) in [
+            ("1 MD5", 1, "expected"),
+            ("\n0 MD5 zero", 2, "invalid key ID"),
+            ("70000 MD5 big", 1, "invalid key ID"),
+            ("1 AES128CMAC secret", 1, "unsupported key type"),
+            (
+                "1 MD5 0123456789abcdef0123456789abcdef0123456",
+                1,
+                "even number",
+            ),
+            ("1 MD5 HEX:zz", 1, "invalid hex"),
+            ("1 MD5 one 192.0.2.1", 1, "address restrictions"),
+            ("1 MD5 one\n1 SHA1 two", 2, "defined twice"),
+        ] {
+            let error = text.parse::<KeyStore>().unwrap_err();
+            assert_eq!(error.line, line, "{}", text);
+            assert!(error.message.contains(message), "{}: {}", text, error);
+        }
+    }
+
+    #[test]
+    fn test_revoked_keys_no_longer_verify() {
+        let mut keys = keys();
+        let signed = keys.sign(2, &sntp::client_request()).unwrap();
+        let rotated = SymmetricKey::new(2, DigestType::Sha1, b"new".to_vec());
+        assert_eq!(
+            keys.add_key(rotated.clone()).map(|key| key.secret),
+            Some(b"two".to_vec())
+        );
+        assert_eq!(keys.verify(&signed), Err(MacError::Mismatch(2)));
+        assert_eq!(keys.revoke_key(2), Some(rotated));
+        assert_eq!(keys.verify(&signed), Err(MacError::UnknownKey(2)));
+        assert_eq!(keys.revoke_key(2), None);
+        assert_eq!(keys.len(), 1);
+    }
+}
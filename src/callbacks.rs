@@ -0,0 +1,203 @@
+//! # Synchronous Callbacks
+//!
+//! Code that must react to the clock at once, rather than drain
+//! [`Clock::events`](crate::Clock::events) or poll statistics, can register a callback that
+//! the sync loop calls directly:
+//!
+//! * [`Clock::on_offset_exceeds`](crate::Clock::on_offset_exceeds) — a measured offset is
+//!   beyond a threshold, e.g. for a database that relies on bounded clock skew
+//! * [`Clock::add_step_listener`](crate::Clock::add_step_listener) — the clock has been
+//!   stepped, e.g. to invalidate token caches or reschedule timers. Listeners are called
+//!   right after the step is applied, with the step exactly as it was applied, so the clock
+//!   already reads the new time.
+//!
+//! Callbacks run on the thread that syncs the clock, so they should return quickly. A
+//! callback that panics is logged and otherwise ignored. Remove a callback with
+//! [`Clock::remove_callback`](crate::Clock::remove_callback).
+
+use crate::lock::RwLockExt;
+use crate::logging::clock_log;
+use crate::Timestamp;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Identifies a registered callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CallbackId(u64);
+
+/// Source of [`CallbackId`]s, shared by all registries so that one id names one callback
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A measured offset beyond a registered threshold
+#[derive(Debug, Clone, PartialEq)]
+pub struct OffsetBreach {
+    /// Offset of the server's time from the clock's reading, in seconds (positive when
+    /// the clock is behind)
+    pub offset: f64,
+    /// The threshold given at registration
+    pub threshold: Duration,
+    /// Server name as configured
+    pub server: String,
+    /// Server time of the sample
+    pub time: Timestamp,
+}
+
+/// Which way the clock is stepped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepDirection {
+    Forward,
+    Backward,
+}
+
+/// A step of the clock from one time to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    /// The time before the step
+    pub from: Timestamp,
+    /// The time after the step
+    pub to: Timestamp,
+    /// How far the time jumps
+    pub magnitude: Duration,
+    pub direction: StepDirection,
+}
+
+impl Step {
+    pub(crate) fn new(from: Timestamp, to: Timestamp) -> Self {
+        let nanos = to.nanos_since(from);
+        Step {
+            from,
+            to,
+            magnitude: Duration::from_nanos(nanos.unsigned_abs().min(u64::MAX as u128) as u64),
+            direction: if nanos < 0 {
+                StepDirection::Backward
+            } else {
+                StepDirection::Forward
+            },
+        }
+    }
+
+    /// The step in seconds, negative when the clock goes back
+    pub fn seconds(&self) -> f64 {
+        self.to.seconds_since(self.from)
+    }
+}
+
+/// Told about steps of the clock, see [`Clock::add_step_listener`](crate::Clock::add_step_listener)
+pub trait StepListener: Send + Sync {
+    fn on_step(&self, step: &Step);
+}
+
+impl<F: Fn(&Step) + Send + Sync> StepListener for F {
+    fn on_step(&self, step: &Step) {
+        self(step)
+    }
+}
+
+/// Called with each breach of its threshold
+pub(crate) struct OffsetWatch {
+    pub(crate) threshold: Duration,
+    pub(crate) callback: Box<dyn Fn(&OffsetBreach) + Send + Sync>,
+}
+
+/// Callbacks of one kind
+pub(crate) struct Registry<T: ?Sized> {
+    entries: RwLock<Vec<(CallbackId, Arc<T>)>>,
+}
+
+impl<T: ?Sized> Default for Registry<T> {
+    fn default() -> Self {
+        Registry {
+            entries: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl<T: ?Sized> Registry<T> {
+    pub(crate) fn add(&self, entry: Arc<T>) -> CallbackId {
+        let id = CallbackId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        self.entries.write_or_recover().push((id, entry));
+        id
+    }
+
+    /// Removes the callback, returning whether it was registered here
+    pub(crate) fn remove(&self, id: CallbackId) -> bool {
+        let mut entries = self.entries.write_or_recover();
+        let before = entries.len();
+        entries.retain(|(entry_id, _)| *entry_id != id);
+        entries.len() != before
+    }
+
+    /// The registered callbacks, so they can be called without holding the lock
+    pub(crate) fn snapshot(&self) -> Vec<Arc<T>> {
+        let entries = self.entries.read_or_recover();
+        entries.iter().map(|(_, entry)| Arc::clone(entry)).collect()
+    }
+}
+
+/// Runs a callback, logging rather than propagating a panic
+pub(crate) fn call_guarded(what: &str, callback: impl FnOnce()) {
+    if panic::catch_unwind(AssertUnwindSafe(callback)).is_err() {
+        clock_log!(Error, Worker, "A {} callback panicked", what);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::spawn_fake_server;
+    use crate::{Clock, ClockConfig};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_offset_callbacks_fire_beyond_their_threshold() {
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        let clock = Clock::with_config(ClockConfig::new().with_servers(vec![server]));
+
+        let breaches = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&breaches);
+        let id = clock.on_offset_exceeds(Duration::from_secs(2), move |breach| {
+            seen.lock().unwrap().push(breach.clone());
+        });
+        clock.on_offset_exceeds(Duration::from_secs(60), |_| panic!("within threshold"));
+
+        // A server ten seconds ahead of the clock
+        let ahead = spawn_fake_server(Timestamp::now().add_nanos(10_000_000_000), 2);
+        clock.reconfigure(&ClockConfig::new().with_servers(vec![ahead.clone()]));
+        assert!(clock.resync_now());
+
+        let breach = breaches.lock().unwrap().pop().unwrap();
+        assert!((breach.offset - 10.0).abs() < 2.0, "{:?}", breach);
+        assert_eq!(breach.threshold, Duration::from_secs(2));
+        assert_eq!(breach.server, ahead);
+
+        assert!(clock.remove_callback(id));
+        assert!(!clock.remove_callback(id));
+    }
+
+    #[test]
+    fn test_step_listeners_are_told_the_applied_step() {
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        let clock = Clock::with_config(ClockConfig::new().with_servers(vec![server]));
+
+        let steps = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&steps);
+        let handle = clock.handle();
+        let id = clock.add_step_listener(move |step: &Step| {
+            seen.lock().unwrap().push((*step, handle.now_timestamp()));
+        });
+
+        let behind = spawn_fake_server(Timestamp::now().add_nanos(-10_000_000_000), 2);
+        clock.reconfigure(&ClockConfig::new().with_servers(vec![behind]));
+        assert!(clock.resync_now());
+
+        let (step, read_during) = steps.lock().unwrap().pop().unwrap();
+        assert_eq!(step.direction, StepDirection::Backward);
+        assert!((step.seconds() + 10.0).abs() < 2.0, "{:?}", step);
+        // The clock already reads the time the step went to
+        assert!(read_during.seconds_since(step.to).abs() < 1.0);
+        assert!(clock.now_timestamp().seconds_since(step.to) < 2.0);
+        assert!(clock.remove_callback(id));
+    }
+}
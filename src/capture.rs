@@ -0,0 +1,613 @@
+//! # Capture and Replay
+//!
+//! A bug in filtering or discipline often shows only with the packets of one network at one
+//! time. A [`Recorder`] wraps the transport a clock queries its servers through and writes
+//! every exchange to a capture file: when the request went out, relative to the start of
+//! the recording, the server's address, the raw request and reply packets, and the measured
+//! round trip, or the error the exchange failed with. [`Replay::run`] feeds a capture back
+//! through a clock and its discipline, so a maintainer can reproduce a reported run from
+//! the file alone:
+//!
+//! ```no_run
+//! use clock::capture::{Recorder, Replay};
+//! use clock::sntp::UdpTransport;
+//! use clock::transport::TransportFactory;
+//! use clock::{Clock, ClockConfig};
+//! use std::path::Path;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let recorder = Recorder::create(Path::new("capture.txt"))?;
+//! let udp = TransportFactory::new("udp", UdpTransport::default);
+//! let clock = Clock::with_config(ClockConfig::new().with_transport(Some(recorder.factory(udp))));
+//! # drop(clock);
+//!
+//! let replayed = Replay::open(Path::new("capture.txt"))?.run(ClockConfig::new())?;
+//! for sync in replayed.sync_history() {
+//!     println!("{} {} {:+.6}s", sync.time, sync.server, sync.offset);
+//! }
+//! # Ok(())
+//! }
+//! ```
+//!
+//! The capture is text, one exchange per line after a `#` header, with packets in hex:
+//!
+//! ```text
+//! # clock-ntp capture, started 2026-10-16T09:30:00.123Z
+//! 1520331 192.0.2.1:123 2300...00 ok 18243007 0 10.0.0.2:53211 2402...00
+//! 64021877 192.0.2.2:123 2300...00 err timed out
+//! ```
+//!
+//! A replay hands the clock the recorded replies in their recorded order, and measures the
+//! clock's elapsed time by the recorded send times, so it steps and slews the same way on
+//! every run. It needs the configuration of the recording, with servers resolving to the
+//! same addresses, e.g. given as IP addresses or through a `static:` [`DnsStrategy`]. The
+//! hardware RTC, server discovery, and network monitoring are left out of a replay, and
+//! recordings should be made without `race_initial_sync`, whose parallel queries have no
+//! fixed order. Timing within a poll, such as the spacing of `samples_per_poll` samples, is
+//! still taken from real time. Only packet headers are recorded, so the MACs of servers
+//! with a `key` are not, and a replay of them drops every reply as unverified.
+//!
+//! [`DnsStrategy`]: crate::DnsStrategy
+
+use crate::elapsed::ElapsedSource;
+use crate::lock::MutexExt;
+use crate::logging::clock_log;
+use crate::sntp::{Transport, PACKET_LEN};
+use crate::transport::TransportFactory;
+use crate::{Clock, ClockConfig, Timestamp};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A reply as the transport received it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordedReply {
+    pub packet: [u8; PACKET_LEN],
+    /// Round trip measured by the transport
+    pub delay: Duration,
+    /// How long before the exchange returned the reply arrived
+    pub receive_latency: Duration,
+    /// Local address the request was sent from, if the transport knew it
+    pub local_addr: Option<SocketAddr>,
+}
+
+/// One exchange with a server, as written to a capture file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    /// When the request was sent, since the recording started
+    pub at: Duration,
+    pub server: SocketAddr,
+    pub request: [u8; PACKET_LEN],
+    /// The reply, or the message of the error the exchange failed with
+    pub outcome: Result<RecordedReply, String>,
+}
+
+impl fmt::Display for Exchange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.at.as_nanos(),
+            self.server,
+            hex(&self.request)
+        )?;
+        match &self.outcome {
+            Ok(reply) => write!(
+                f,
+                " ok {} {} {} {}",
+                reply.delay.as_nanos(),
+                reply.receive_latency.as_nanos(),
+                reply
+                    .local_addr
+                    .map_or_else(|| "-".to_string(), |addr| addr.to_string()),
+                hex(&reply.packet)
+            ),
+            // Keep the record on one line
+            Err(message) => write!(f, " err {}", message.replace(['\r', '\n'], " ")),
+        }
+    }
+}
+
+impl FromStr for Exchange {
+    type Err = String;
+
+    /// Parses a line written by the [`Display`](fmt::Display) implementation
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.trim().splitn(5, ' ');
+        let mut next = |what: &str| {
+            fields
+                .next()
+                .filter(|field| !field.is_empty())
+                .ok_or_else(|| format!("missing {}", what))
+        };
+        let at = nanos(next("send time")?)?;
+        let server = next("server address")?;
+        let server = server
+            .parse()
+            .map_err(|_| format!("invalid server address '{}'", server))?;
+        let request = packet(next("request")?)?;
+        let outcome = match next("outcome")? {
+            "ok" => {
+                let mut fields = next("reply")?.split(' ');
+                let mut next =
+                    |what: &str| fields.next().ok_or_else(|| format!("missing {}", what));
+                let delay = nanos(next("round trip")?)?;
+                let receive_latency = nanos(next("receive latency")?)?;
+                let local_addr = match next("local address")? {
+                    "-" => None,
+                    addr => Some(
+                        addr.parse()
+                            .map_err(|_| format!("invalid local address '{}'", addr))?,
+                    ),
+                };
+                Ok(RecordedReply {
+                    packet: packet(next("reply")?)?,
+                    delay,
+                    receive_latency,
+                    local_addr,
+                })
+            }
+            "err" => Err(next("error").unwrap_or_default().to_string()),
+            other => return Err(format!("unknown outcome '{}'", other)),
+        };
+        Ok(Exchange {
+            at,
+            server,
+            request,
+            outcome,
+        })
+    }
+}
+
+fn nanos(s: &str) -> Result<Duration, String> {
+    s.parse::<u64>()
+        .map(Duration::from_nanos)
+        .map_err(|_| format!("invalid nanoseconds '{}'", s))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn packet(s: &str) -> Result<[u8; PACKET_LEN], String> {
+    let invalid = || format!("invalid packet '{}'", s);
+    if s.len() != PACKET_LEN * 2 {
+        return Err(invalid());
+    }
+    let mut packet = [0; PACKET_LEN];
+    for (i, byte) in packet.iter_mut().enumerate() {
+        *byte = s
+            .get(i * 2..i * 2 + 2)
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            .ok_or_else(invalid)?;
+    }
+    Ok(packet)
+}
+
+#[derive(Debug)]
+struct RecorderState {
+    file: File,
+    path: PathBuf,
+    origin: Instant,
+}
+
+/// Writes the exchanges of the transports it wraps to a capture file. Clones share the
+/// file.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    state: Arc<Mutex<RecorderState>>,
+}
+
+impl Recorder {
+    /// Starts a recording in a new file at `path`, replacing any file there
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        writeln!(
+            file,
+            "# clock-ntp capture, started {}",
+            Timestamp::now().to_rfc3339()
+        )?;
+        Ok(Recorder {
+            state: Arc::new(Mutex::new(RecorderState {
+                file,
+                path: path.to_path_buf(),
+                origin: Instant::now(),
+            })),
+        })
+    }
+
+    /// Records the exchanges of `inner`
+    pub fn wrap<T>(&self, inner: T) -> RecordingTransport<T>
+    where
+        T: Transport<Address = SocketAddr, Error = io::Error>,
+    {
+        RecordingTransport {
+            inner,
+            recorder: self.clone(),
+        }
+    }
+
+    /// A factory creating the transports of `inner`, recorded by this recorder
+    pub fn factory(&self, inner: TransportFactory) -> TransportFactory {
+        let recorder = self.clone();
+        let name = format!("recorded {}", inner.name());
+        TransportFactory::new(name, move || recorder.wrap(inner.create()))
+    }
+
+    /// Time since the recording started
+    fn elapsed(&self) -> Duration {
+        self.state.lock_or_recover().origin.elapsed()
+    }
+
+    fn append(&self, exchange: &Exchange) {
+        let mut state = self.state.lock_or_recover();
+        if let Err(e) = writeln!(state.file, "{}", exchange) {
+            clock_log!(
+                Warn,
+                Storage,
+                "Failed to record an exchange to {}: {}",
+                state.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// A transport recording the exchanges of another, see [`Recorder`]
+#[derive(Debug)]
+pub struct RecordingTransport<T> {
+    inner: T,
+    recorder: Recorder,
+}
+
+impl<T> RecordingTransport<T> {
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T> RecordingTransport<T>
+where
+    T: Transport<Address = SocketAddr, Error = io::Error>,
+{
+    /// Records the headers of an exchange sent at `at`
+    fn record(
+        &self,
+        at: Duration,
+        server: &SocketAddr,
+        request: &[u8],
+        reply: &[u8],
+        result: &io::Result<Duration>,
+    ) {
+        let outcome = match result {
+            Ok(delay) => Ok(RecordedReply {
+                packet: header(reply),
+                delay: *delay,
+                receive_latency: self.inner.receive_latency(),
+                local_addr: self.inner.local_addr(),
+            }),
+            Err(e) => Err(e.to_string()),
+        };
+        self.recorder.append(&Exchange {
+            at,
+            server: *server,
+            request: header(request),
+            outcome,
+        });
+    }
+}
+
+/// The NTP header at the start of `packet`, zero-padded if it is short
+fn header(packet: &[u8]) -> [u8; PACKET_LEN] {
+    let mut header = [0u8; PACKET_LEN];
+    let len = packet.len().min(PACKET_LEN);
+    header[..len].copy_from_slice(&packet[..len]);
+    header
+}
+
+impl<T> Transport for RecordingTransport<T>
+where
+    T: Transport<Address = SocketAddr, Error = io::Error>,
+{
+    type Address = SocketAddr;
+    type Error = io::Error;
+
+    fn exchange(
+        &mut self,
+        server: &SocketAddr,
+        request: &[u8; PACKET_LEN],
+        reply: &mut [u8; PACKET_LEN],
+    ) -> io::Result<Duration> {
+        let at = self.recorder.elapsed();
+        let result = self.inner.exchange(server, request, reply);
+        self.record(at, server, request, reply, &result);
+        result
+    }
+
+    fn exchange_packet(
+        &mut self,
+        server: &SocketAddr,
+        request: &[u8],
+        reply: &mut Vec<u8>,
+    ) -> io::Result<Duration> {
+        let at = self.recorder.elapsed();
+        let result = self.inner.exchange_packet(server, request, reply);
+        self.record(at, server, request, reply, &result);
+        result
+    }
+
+    fn receive_latency(&self) -> Duration {
+        self.inner.receive_latency()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+#[derive(Debug)]
+struct ReplayState {
+    exchanges: VecDeque<Exchange>,
+    /// The recording's time as of the last replayed exchange
+    elapsed: Duration,
+}
+
+/// Plays a capture back to a clock, see the [module documentation](self). Clones share
+/// the remaining exchanges.
+///
+/// As an [`ElapsedSource`] it reads the time of the recording: when the last replayed
+/// request was sent, plus its round trip.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl Replay {
+    /// Reads the capture at `path`
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let exchanges = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| {
+                line.parse().map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}:{}: {}", path.display(), i + 1, e),
+                    )
+                })
+            })
+            .collect::<io::Result<Vec<Exchange>>>()?;
+        Ok(Self::new(exchanges))
+    }
+
+    /// Plays back `exchanges` in order
+    pub fn new(exchanges: impl IntoIterator<Item = Exchange>) -> Self {
+        Replay {
+            state: Arc::new(Mutex::new(ReplayState {
+                exchanges: exchanges.into_iter().collect(),
+                elapsed: Duration::ZERO,
+            })),
+        }
+    }
+
+    /// Number of exchanges not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.state.lock_or_recover().exchanges.len()
+    }
+
+    /// A factory creating transports that answer with the recorded replies
+    pub fn factory(&self) -> TransportFactory {
+        let replay = self.clone();
+        TransportFactory::new("replay", move || ReplayTransport {
+            replay: replay.clone(),
+            receive_latency: Duration::ZERO,
+            local_addr: None,
+        })
+    }
+
+    /// Replays every exchange through a clock configured like `config`, as the recording
+    /// clock was, and returns the clock for inspection, e.g. of its
+    /// [`sync_history`](Clock::sync_history). The clock is not started. Fails if the
+    /// clock queries a server other than the capture has next.
+    pub fn run(&self, config: ClockConfig) -> io::Result<Clock> {
+        let total = self.remaining();
+        let config = ClockConfig {
+            transport: Some(self.factory()),
+            rtc_policy: None,
+            rtc_write_interval: None,
+            mdns_discovery: false,
+            manycast: Vec::new(),
+            resync_on_network_change: false,
+            ..config
+        };
+        let clock = Clock::with_config(config);
+        clock.shared.restart_elapsed_source(Arc::new(self.clone()));
+        while self.remaining() > 0 {
+            let before = self.remaining();
+            clock.shared.update_latest_time();
+            if self.remaining() == before {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "the replay diverged from the capture after {} of {} exchanges",
+                        total - before,
+                        total
+                    ),
+                ));
+            }
+        }
+        Ok(clock)
+    }
+}
+
+impl ElapsedSource for Replay {
+    fn now(&self) -> Duration {
+        self.state.lock_or_recover().elapsed
+    }
+}
+
+/// A transport answering from a capture, see [`Replay`]
+#[derive(Debug)]
+pub struct ReplayTransport {
+    replay: Replay,
+    receive_latency: Duration,
+    local_addr: Option<SocketAddr>,
+}
+
+impl Transport for ReplayTransport {
+    type Address = SocketAddr;
+    type Error = io::Error;
+
+    fn exchange(
+        &mut self,
+        server: &SocketAddr,
+        request: &[u8; PACKET_LEN],
+        reply: &mut [u8; PACKET_LEN],
+    ) -> io::Result<Duration> {
+        let mut state = self.replay.state.lock_or_recover();
+        let next = state.exchanges.front().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the capture has no more exchanges",
+            )
+        })?;
+        if next.server != *server {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the capture has an exchange with {} next, not {}",
+                    next.server, server
+                ),
+            ));
+        }
+        let Some(exchange) = state.exchanges.pop_front() else {
+            unreachable!("the front exchange was just read");
+        };
+        let recorded = match exchange.outcome {
+            Ok(recorded) => recorded,
+            Err(message) => {
+                state.elapsed = exchange.at;
+                return Err(io::Error::other(message));
+            }
+        };
+        state.elapsed = exchange.at + recorded.delay;
+        *reply = recorded.packet;
+        // The reply echoed the recorded request's transmit time, or its receive time in
+        // interleaved mode; the live request carries different ones
+        for field in [40..48, 32..40] {
+            if exchange.request[field.clone()] != [0; 8]
+                && reply[24..32] == exchange.request[field.clone()]
+            {
+                reply[24..32].copy_from_slice(&request[field]);
+                break;
+            }
+        }
+        self.receive_latency = recorded.receive_latency;
+        self.local_addr = recorded.local_addr;
+        Ok(recorded.delay)
+    }
+
+    fn receive_latency(&self) -> Duration {
+        self.receive_latency
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sntp::UdpTransport;
+    use crate::tests::spawn_fake_server;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("clock-ntp-capture-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_exchange_round_trips_through_text() {
+        let mut reply = [0x24; PACKET_LEN];
+        reply[47] = 0xff;
+        let exchanges = [
+            Exchange {
+                at: Duration::from_nanos(1_520_331),
+                server: "192.0.2.1:123".parse().unwrap(),
+                request: [0x23; PACKET_LEN],
+                outcome: Ok(RecordedReply {
+                    packet: reply,
+                    delay: Duration::from_micros(18_243),
+                    receive_latency: Duration::from_nanos(700),
+                    local_addr: Some("10.0.0.2:53211".parse().unwrap()),
+                }),
+            },
+            Exchange {
+                at: Duration::from_secs(64),
+                server: "[2001:db8::1]:123".parse().unwrap(),
+                request: [0; PACKET_LEN],
+                outcome: Err("timed out\nafter 1s".to_string()),
+            },
+        ];
+        let parsed: Exchange = exchanges[0].to_string().parse().unwrap();
+        assert_eq!(parsed, exchanges[0]);
+        let parsed: Exchange = exchanges[1].to_string().parse().unwrap();
+        assert_eq!(parsed.outcome, Err("timed out after 1s".to_string()));
+
+        assert!("12 192.0.2.1:123 00".parse::<Exchange>().is_err());
+        let line = exchanges[0].to_string().replace(" ok ", " maybe ");
+        assert!(line.parse::<Exchange>().is_err());
+    }
+
+    #[test]
+    fn test_replay_reproduces_the_recorded_syncs() {
+        let path = temp_path("session");
+        let recorder = Recorder::create(&path).unwrap();
+        let udp = TransportFactory::new("udp", UdpTransport::default);
+        let server = spawn_fake_server(Timestamp::now() + Duration::from_secs(30), 3);
+        let config = ClockConfig::new()
+            .with_servers(vec![server])
+            .with_smoothing(Some(crate::SmoothingFilter::Ema { alpha: 0.5 }));
+        let recorded =
+            Clock::with_config(config.clone().with_transport(Some(recorder.factory(udp))));
+        assert!(recorded.is_synchronized());
+        recorded.shared.update_latest_time();
+        recorded.shared.update_latest_time();
+
+        let replay = Replay::open(&path).unwrap();
+        assert_eq!(replay.remaining(), 3);
+        let first = replay.run(config.clone()).unwrap();
+        assert_eq!(replay.remaining(), 0);
+        let second = Replay::open(&path).unwrap().run(config).unwrap();
+        let history = first.sync_history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history, second.sync_history());
+        assert_eq!(first.now_timestamp(), second.now_timestamp());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_replay_detects_divergence() {
+        let exchange = Exchange {
+            at: Duration::ZERO,
+            server: "192.0.2.1:123".parse().unwrap(),
+            request: [0; PACKET_LEN],
+            outcome: Err("timed out".to_string()),
+        };
+        let replay = Replay::new([exchange.clone(), exchange]);
+        let config = ClockConfig::new().with_servers(vec!["192.0.2.2:123".to_string()]);
+        let Err(error) = replay.run(config) else {
+            panic!("the replay should have diverged");
+        };
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(replay.remaining(), 2);
+    }
+}
@@ -0,0 +1,155 @@
+//! # Pluggable Clocks
+//!
+//! Libraries that take a pluggable clock, such as rate limiters, caches with expiry, and
+//! token validators, can be written against [`ClockSource`] and handed NTP time from a
+//! [`Clock`](crate::Clock) or [`ClockHandle`](crate::ClockHandle) in production, the
+//! [`SystemClock`] where NTP is not wanted, or a [`ManualClock`] in tests. References,
+//! `Box`es, and `Arc`s of clock sources are clock sources too, so a library can store an
+//! `Arc<dyn ClockSource>`.
+//!
+//! ```no_run
+//! use clock::{Clock, ClockSource, Timestamp};
+//! use std::time::Duration;
+//!
+//! struct Token { expires: Timestamp }
+//!
+//! fn is_expired(token: &Token, clock: &impl ClockSource) -> bool {
+//!     clock.now() >= token.expires
+//! }
+//!
+//! let clock = Clock::new(None);
+//! let token = Token { expires: clock.now_timestamp() + Duration::from_secs(60) };
+//! assert!(!is_expired(&token, &clock));
+//! ```
+
+use crate::lock::MutexExt;
+use crate::{Clock, ClockHandle, ClockSnapshot, Timestamp};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Something that tells the time
+pub trait ClockSource: Send + Sync {
+    /// The current time
+    fn now(&self) -> Timestamp;
+
+    /// The current time as a `std::time::SystemTime`
+    fn now_system_time(&self) -> SystemTime {
+        self.now().into()
+    }
+
+    /// Time since `earlier`, zero if it is in the future
+    fn elapsed_since(&self, earlier: Timestamp) -> Duration {
+        let nanos = self.now().nanos_since(earlier).max(0);
+        Duration::from_nanos(nanos.min(u64::MAX as i128) as u64)
+    }
+}
+
+impl ClockSource for Clock {
+    fn now(&self) -> Timestamp {
+        self.now_timestamp()
+    }
+}
+
+impl ClockSource for ClockHandle {
+    fn now(&self) -> Timestamp {
+        self.now_timestamp()
+    }
+}
+
+impl ClockSource for ClockSnapshot {
+    fn now(&self) -> Timestamp {
+        ClockSnapshot::now(self)
+    }
+}
+
+impl<T: ClockSource + ?Sized> ClockSource for &T {
+    fn now(&self) -> Timestamp {
+        (**self).now()
+    }
+}
+
+impl<T: ClockSource + ?Sized> ClockSource for Box<T> {
+    fn now(&self) -> Timestamp {
+        (**self).now()
+    }
+}
+
+impl<T: ClockSource + ?Sized> ClockSource for Arc<T> {
+    fn now(&self) -> Timestamp {
+        (**self).now()
+    }
+}
+
+/// The operating system's clock, unsynchronized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl ClockSource for SystemClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::now()
+    }
+}
+
+/// A clock that only moves when told to, for tests
+#[derive(Debug)]
+pub struct ManualClock {
+    time: Mutex<Timestamp>,
+}
+
+impl ManualClock {
+    /// Creates a clock standing at `time`
+    pub fn new(time: Timestamp) -> Self {
+        ManualClock {
+            time: Mutex::new(time),
+        }
+    }
+
+    /// Sets the time, which may go backwards
+    pub fn set(&self, time: Timestamp) {
+        *self.time.lock_or_recover() = time;
+    }
+
+    /// Moves the time forward by `by`
+    pub fn advance(&self, by: Duration) {
+        let mut time = self.time.lock_or_recover();
+        *time = *time + by;
+    }
+}
+
+impl ClockSource for ManualClock {
+    fn now(&self) -> Timestamp {
+        *self.time.lock_or_recover()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::spawn_fake_server;
+    use crate::ClockConfig;
+
+    fn age(since: Timestamp, clock: &dyn ClockSource) -> Duration {
+        clock.elapsed_since(since)
+    }
+
+    #[test]
+    fn test_clock_sources() {
+        let start: Timestamp = "2026-02-03T06:50:57Z".parse().unwrap();
+        let manual = Arc::new(ManualClock::new(start));
+        let shared: Arc<dyn ClockSource> = manual.clone();
+        manual.advance(Duration::from_secs(90));
+        assert_eq!(age(start, &shared), Duration::from_secs(90));
+        manual.set(start);
+        assert_eq!(
+            shared.elapsed_since(start + Duration::from_secs(1)),
+            Duration::ZERO
+        );
+        assert_eq!(shared.now_system_time(), SystemTime::from(start));
+
+        let server = spawn_fake_server(start, 1);
+        let clock = Clock::with_config(ClockConfig::new().with_servers(vec![server]));
+        assert!(age(start, &clock) < Duration::from_secs(2));
+        assert!(age(start, &clock.handle()) < Duration::from_secs(2));
+        assert!(age(start, &SystemClock) > Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,1599 @@
+//! # Clock Configuration
+//!
+//! Settings used to construct a [`Clock`](crate::Clock), either programmatically or from a
+//! configuration file of `key = value` lines:
+//!
+//! ```text
+//! # Servers are tried in order; listing any replaces the defaults. `prefer` wins ties in
+//! # selection, `noselect` servers are monitored but never used to set the time.
+//! server = time.google.com:123 prefer
+//! server = time.cloudflare.com:123
+//! server = ntp.staging.internal:123 noselect
+//! server = ntp://10.0.0.5:1123 iburst version 4   # see ServerSpec for the options
+//! sync_interval = 64        # seconds
+//! fallback = file:/var/lib/clock/state
+//! min_time = 2026-01-01T00:00:00Z
+//! max_time = +20y           # or an RFC 3339 time, or off
+//! rtc_policy = hold         # when NTP and the RTC disagree: trust-ntp, trust-rtc, hold, or off
+//! rtc_device = /dev/rtc0
+//! rtc_tolerance = 3600      # seconds of disagreement tolerated
+//! rtc_write_interval = 660  # program the RTC with the disciplined time this often, or off
+//! persisted_floor = true
+//! stale_after = 300         # seconds
+//! samples_per_poll = 5      # median of 5 requests per sync
+//! combine_sources = true    # query every server and combine the truechimers
+//! race_initial_sync = true  # start from whichever server answers first
+//! best_practices = true     # RFC 8633 profile, see ClockConfig::best_practices
+//! max_delay_ms = 250        # discard samples with a longer round trip
+//! max_delay_ratio = 3       # ... or 3x the smallest recent round trip
+//! max_queries_per_minute = 6
+//! source_ports = 50000-50999   # random source port per query from this range
+//! dscp = EF                 # or a number from 0 to 63
+//! ttl = 64
+//! nts_cookies = /var/lib/clock/nts.cookies   # NTS sessions survive restarts
+//! dns = static:ntp1.plant.example=10.0.0.5   # or system, doh:https://IP/PATH pin sha256//...
+//! deny_addresses = private, loopback   # never query these, whatever DNS answers
+//! allow_addresses = 203.0.113.0/24      # ... nor anything outside these
+//! smoothing = pi:0.5,0.05    # or raw, ema:0.25; unset keeps the first step
+//! max_slew_ppm = 100        # slew pi corrections at no more than 100 ppm
+//! offline_retry_max = 60    # retry at 1, 2, 4, ... up to 60 s while no server answers
+//! orphan_after = 3600       # seconds without a reachable server before free-running
+//! orphan_stratum = 10
+//! peer = 10.0.0.7:11123     # another instance, polled when no server is reachable
+//! peer_listen = 0.0.0.0:11123   # answer peers' queries on this address
+//! rate_limit = 2:8          # KoD RATE after a burst of 8, then one query per 2 s; or off
+//! mru_size = 1024           # clients remembered, least recently seen forgotten first
+//! mdns_discovery = true     # add _ntp._udp.local servers found on the LAN
+//! manycast = 192.168.1.255  # add servers answering a probe of this broadcast address
+//! mdns_advertise = lab-clock   # advertise the peer_listen responder under this name
+//! resync_on_network_change = true   # burst resync when an interface comes up
+//! anomaly_threshold_ms = 500
+//! anomaly_hook = exec:/usr/local/bin/page-oncall
+//! anomaly_hook = http://alerts.internal:9000/clock
+//! alert_hook = lost_sync,all_servers_failed=exec:/usr/local/bin/page-oncall
+//! alert_rate_limit = 600    # seconds between alerts of one kind
+//! large_step_threshold_ms = 500
+//! audit_log = /var/log/clock/audit.log   # record every adjustment, from the first sync on
+//! audit_hash_chain = true
+//! ```
+
+use crate::alert::{AlertHook, DEFAULT_ALERT_RATE_LIMIT, DEFAULT_LARGE_STEP_THRESHOLD};
+use crate::anomaly::{AnomalyHook, DEFAULT_ANOMALY_THRESHOLD};
+use crate::auth::SharedKeyStore;
+use crate::dns::{AddressPolicy, AddressSet, DnsStrategy};
+use crate::peer::{RateLimit, DEFAULT_MRU_SIZE};
+use crate::rtc::{RtcCheck, RtcPolicy, RtcWrite, DEFAULT_RTC_DEVICE, DEFAULT_RTC_TOLERANCE};
+use crate::server::{self, ServerSpec};
+use crate::smoothing::{SmoothingFilter, DEFAULT_MAX_SLEW_PPM};
+use crate::sntp::{DelayLimits, DEFAULT_SOURCE_PORTS, MAX_STRATUM};
+use crate::transport::TransportFactory;
+use crate::Timestamp;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// NTP servers used when none are configured
+pub const DEFAULT_SERVERS: [&str; 3] = [
+    "time.google.com:123",
+    "time.cloudflare.com:123",
+    "pool.ntp.org:123",
+];
+
+/// Default interval between background syncs
+pub const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Fewest servers queried under [`ClockConfig::best_practices`], as RFC 8633 (BCP 223)
+/// recommends so that one falseticker can be outvoted
+pub const BCP_MIN_SOURCES: usize = 4;
+
+/// Shortest sync interval under [`ClockConfig::best_practices`]: the 64 s minimum poll
+/// interval of RFC 5905
+pub const BCP_MIN_SYNC_INTERVAL: Duration = Duration::from_secs(64);
+
+/// Stratum a clock reports in orphan mode unless configured otherwise, as in ntpd's
+/// `tos orphan`
+pub const DEFAULT_ORPHAN_STRATUM: u8 = 10;
+
+/// Earliest time baked in at build time from the `CLOCK_NTP_MIN_TIME` environment variable
+/// (Unix seconds), typically set to the build timestamp by the packaging scripts
+pub fn build_time_floor() -> Option<Timestamp> {
+    let secs = option_env!("CLOCK_NTP_MIN_TIME")?.trim().parse().ok()?;
+    Some(Timestamp::from_unix_secs(secs))
+}
+
+/// Years after the build-time floor up to which NTP time is plausible by default
+pub const DEFAULT_PLAUSIBLE_YEARS: u32 = 20;
+
+/// Latest plausible time, `years` after the [build-time floor](build_time_floor), or
+/// `None` if no floor was baked in
+pub fn build_time_ceiling(years: u32) -> Option<Timestamp> {
+    Some(build_time_floor()? + years_duration(years))
+}
+
+/// `years` Gregorian years, close enough for a plausibility bound
+fn years_duration(years: u32) -> Duration {
+    Duration::from_secs(u64::from(years) * 31_556_952)
+}
+
+/// Parses a [`ClockConfig::max_time`]: an RFC 3339 timestamp, or `+Ny` for `N` years after
+/// the build-time floor
+pub fn parse_max_time(s: &str) -> Result<Timestamp, String> {
+    let s = s.trim();
+    let Some(years) = s.strip_prefix('+') else {
+        return Timestamp::parse_rfc3339(s);
+    };
+    let years = years
+        .strip_suffix('y')
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| format!("invalid maximum time '{}': expected +Ny", s))?;
+    build_time_ceiling(years).ok_or_else(|| {
+        format!(
+            "invalid maximum time '{}': no build time was baked in with CLOCK_NTP_MIN_TIME",
+            s
+        )
+    })
+}
+
+/// What a clock reports before it has obtained NTP time
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum FallbackPolicy {
+    /// Report no time: [`Clock::try_current_time`](crate::Clock::try_current_time) fails
+    /// until the first successful sync
+    Error,
+    /// Start from the operating system's clock
+    #[default]
+    SystemClock,
+    /// Start from [`DEFAULT_TIMESTAMP`](crate::DEFAULT_TIMESTAMP) (January 1, 2000)
+    FixedDefault,
+    /// Start from the state last persisted to this file, advanced by the time since boot
+    /// and corrected for the measured drift. The system clock is used instead if it is
+    /// later or the file cannot be read. The file is updated after every successful sync.
+    LastPersistedTime(PathBuf),
+}
+
+impl FromStr for FallbackPolicy {
+    type Err = String;
+
+    /// Parses `error`, `system`, `default`, or `file:PATH`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(FallbackPolicy::Error),
+            "system" => Ok(FallbackPolicy::SystemClock),
+            "default" => Ok(FallbackPolicy::FixedDefault),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => {
+                    Ok(FallbackPolicy::LastPersistedTime(PathBuf::from(path)))
+                }
+                _ => Err(format!(
+                    "unknown fallback policy '{}', expected error, system, default, or file:PATH",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Configuration for a clock instance
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockConfig {
+    /// NTP servers, tried in order: `host`, `host:port`, or `ntp://host:port`, optionally
+    /// followed by options such as `iburst`, see [`ServerSpec`]
+    pub servers: Vec<String>,
+    /// Servers tried before the others, and chosen as the source of a combined sample when
+    /// they survive selection
+    pub preferred_servers: Vec<String>,
+    /// Servers that are queried and reported in
+    /// [`Clock::source_weights`](crate::Clock::source_weights) but never used to set the
+    /// time, for staging new servers
+    pub noselect_servers: Vec<String>,
+    /// Interval between background syncs
+    pub sync_interval: Duration,
+    /// What to report before the first successful sync
+    pub fallback_policy: FallbackPolicy,
+    /// NTP samples earlier than this are rejected, protecting against rollback attacks
+    pub min_time: Option<Timestamp>,
+    /// Also use the time persisted under [`FallbackPolicy::LastPersistedTime`] as a floor
+    pub persisted_floor: bool,
+    /// NTP samples later than this are rejected, so that a broken or compromised server
+    /// cannot send the clock decades into the future. Defaults to
+    /// [`DEFAULT_PLAUSIBLE_YEARS`] after the build-time floor, if one was baked in.
+    pub max_time: Option<Timestamp>,
+    /// Cross-check NTP time against the hardware RTC at startup and on every sync, resolving
+    /// disagreements with this policy, see [`rtc`](crate::rtc); `None` skips the check
+    pub rtc_policy: Option<RtcPolicy>,
+    /// The RTC read by the [`rtc_policy`](Self::rtc_policy) check and written every
+    /// [`rtc_write_interval`](Self::rtc_write_interval)
+    pub rtc_device: PathBuf,
+    /// Disagreement between NTP and the RTC beyond which the
+    /// [`rtc_policy`](Self::rtc_policy) applies
+    pub rtc_tolerance: Duration,
+    /// While the clock is healthy, program the RTC with its disciplined time this often, so
+    /// the next boot starts close to the right time; `None` never writes it
+    pub rtc_write_interval: Option<Duration>,
+    /// Time since the last successful sync after which [`Clock::health`](crate::Clock::health)
+    /// reports the clock as stale; `None` uses
+    /// [`DEFAULT_STALENESS_FACTOR`](crate::health::DEFAULT_STALENESS_FACTOR) times the sync
+    /// interval
+    pub staleness_threshold: Option<Duration>,
+    /// Requests sent to the selected server per sync; with more than one, outliers are
+    /// discarded and the median offset is used
+    pub samples_per_poll: u32,
+    /// Query every server on each sync, discard falsetickers, and combine the rest weighted
+    /// by root distance and jitter, instead of using the first server that answers
+    pub combine_sources: bool,
+    /// Query every selectable server at once for the initial sync and start from the first
+    /// acceptable answer, leaving the other queries to finish in the background
+    pub race_initial_sync: bool,
+    /// Follow the RFC 8633 (BCP 223) best practices for NTP clients: query at least
+    /// [`BCP_MIN_SOURCES`] servers (topped up from the NTP pool) and combine them, poll no
+    /// more often than [`BCP_MIN_SYNC_INTERVAL`] with random jitter, honor kiss-o'-death
+    /// replies, space queries to each server at least 2 s apart, and rotate through the
+    /// addresses of pool names. See [`effective`](Self::effective).
+    pub best_practices: bool,
+    /// Samples with a longer round-trip delay are rejected, bounding the error an attacker
+    /// can introduce by delaying packets to half this value
+    pub max_delay: Option<Duration>,
+    /// Samples whose round-trip delay exceeds this multiple of the smallest delay recently
+    /// seen from the same server are rejected
+    pub max_delay_ratio: Option<f64>,
+    /// Most queries sent to any one server in a minute, counting forced syncs, bursts,
+    /// retries, and multi-sample polls; queries over the budget are skipped
+    pub max_queries_per_minute: Option<u32>,
+    /// Each query is sent from a fresh socket bound to a port picked at random from this
+    /// range, so that spoofed replies have to guess it
+    pub source_ports: RangeInclusive<u16>,
+    /// DSCP code point queries are marked with, for QoS classification of time traffic
+    pub dscp: Option<u8>,
+    /// TTL (IPv4) or hop limit (IPv6) of queries, instead of the system default
+    pub ttl: Option<u32>,
+    /// Carries queries instead of plain UDP, see [`transport`](crate::transport); only
+    /// settable programmatically
+    pub transport: Option<TransportFactory>,
+    /// Keys requests to servers with a `key` are signed with, and their replies verified
+    /// against; without them such servers are not queried. Only settable programmatically.
+    pub keys: Option<SharedKeyStore>,
+    /// Where the cookies and keys of NTS sessions are kept across restarts, encrypted under
+    /// a key in the same path with `.key` appended (`nts` feature); without it every start
+    /// renews them by key exchange
+    pub nts_cookies: Option<PathBuf>,
+    /// Who resolves server names, see [`dns`](crate::dns)
+    pub dns: DnsStrategy,
+    /// Which resolved server addresses may be queried, see [`AddressPolicy`]
+    pub address_policy: AddressPolicy,
+    /// How the offsets measured after the first sync are applied to the reported time;
+    /// with `None` they are only measured, see [`smoothing`](crate::smoothing)
+    pub smoothing: Option<SmoothingFilter>,
+    /// Fastest rate, in parts per million, at which the `pi` filter slews the reported time
+    /// away from real elapsed time while correcting it
+    pub max_slew_ppm: f64,
+    /// Once every server and peer failed, retry after one second, then after twice as
+    /// long with every further failure, up to this (and never more than the sync
+    /// interval), until a sync succeeds. `None` retries at the sync interval.
+    pub offline_retry_max: Option<Duration>,
+    /// Time without a reachable server after which a synchronized clock enters orphan
+    /// mode: it free-runs from its last NTP time with drift compensation, reports
+    /// [`orphan_stratum`](Self::orphan_stratum), and emits
+    /// [`ClockEvent::OrphanModeEntered`](crate::ClockEvent::OrphanModeEntered). `None`
+    /// disables orphan mode.
+    pub orphan_after: Option<Duration>,
+    /// Stratum reported in orphan mode
+    pub orphan_stratum: u8,
+    /// Other clock instances in `host:port` form, polled over NTP when no server is
+    /// reachable, see [`peer`](crate::peer)
+    pub peers: Vec<String>,
+    /// Address on which the CLI answers peers' queries with the disciplined time
+    pub peer_listen: Option<SocketAddr>,
+    /// How fast one client may query the [`peer_listen`](Self::peer_listen) responder
+    /// before it is sent kiss-o'-death `RATE` replies; `None` answers every query
+    pub rate_limit: Option<RateLimit>,
+    /// Clients the responder remembers for [`rate_limit`](Self::rate_limit) and
+    /// [`ClockHandle::clients`](crate::ClockHandle::clients)
+    pub mru_size: usize,
+    /// Discover NTP servers advertised as `_ntp._udp.local` and add them to
+    /// [`servers`](Self::servers), see [`mdns`](crate::mdns)
+    pub mdns_discovery: bool,
+    /// Broadcast or LAN addresses probed for NTP servers, which are added to
+    /// [`servers`](Self::servers) if they answer, see [`manycast`](crate::manycast)
+    pub manycast: Vec<SocketAddr>,
+    /// Instance name under which the CLI advertises its
+    /// [`peer_listen`](Self::peer_listen) responder over mDNS
+    pub mdns_advertise: Option<String>,
+    /// Resync in a burst as soon as the operating system reports that an interface came
+    /// up or gained an address, see [`netwatch`](crate::netwatch)
+    pub resync_on_network_change: bool,
+    /// Offset change that is reported as an [`anomaly`](crate::anomaly)
+    pub anomaly_threshold: Duration,
+    /// Where anomalies are reported besides [`Clock::events`](crate::Clock::events)
+    pub anomaly_hooks: Vec<AnomalyHook>,
+    /// Where [`alert`](crate::alert)s are delivered
+    pub alert_hooks: Vec<AlertHook>,
+    /// Shortest time between two alerts of one kind
+    pub alert_rate_limit: Duration,
+    /// Step of the clock that raises an [`AlertKind::LargeStep`](crate::AlertKind::LargeStep)
+    pub large_step_threshold: Duration,
+    /// Append every adjustment of the clock, including the initial step, to this
+    /// [audit log](crate::audit)
+    pub audit_log: Option<PathBuf>,
+    /// Chain the [`audit_log`](Self::audit_log) records with SHA-256
+    pub audit_hash_chain: bool,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            servers: DEFAULT_SERVERS.iter().map(|s| s.to_string()).collect(),
+            preferred_servers: Vec::new(),
+            noselect_servers: Vec::new(),
+            sync_interval: DEFAULT_SYNC_INTERVAL,
+            fallback_policy: FallbackPolicy::default(),
+            min_time: build_time_floor(),
+            persisted_floor: false,
+            max_time: build_time_ceiling(DEFAULT_PLAUSIBLE_YEARS),
+            rtc_policy: None,
+            rtc_device: PathBuf::from(DEFAULT_RTC_DEVICE),
+            rtc_tolerance: DEFAULT_RTC_TOLERANCE,
+            rtc_write_interval: None,
+            staleness_threshold: None,
+            samples_per_poll: 1,
+            combine_sources: false,
+            race_initial_sync: false,
+            best_practices: false,
+            max_delay: None,
+            max_delay_ratio: None,
+            max_queries_per_minute: None,
+            source_ports: DEFAULT_SOURCE_PORTS,
+            dscp: None,
+            ttl: None,
+            transport: None,
+            keys: None,
+            nts_cookies: None,
+            dns: DnsStrategy::default(),
+            address_policy: AddressPolicy::default(),
+            smoothing: None,
+            max_slew_ppm: DEFAULT_MAX_SLEW_PPM,
+            offline_retry_max: None,
+            orphan_after: None,
+            orphan_stratum: DEFAULT_ORPHAN_STRATUM,
+            peers: Vec::new(),
+            peer_listen: None,
+            rate_limit: Some(RateLimit::default()),
+            mru_size: DEFAULT_MRU_SIZE,
+            mdns_discovery: false,
+            manycast: Vec::new(),
+            mdns_advertise: None,
+            resync_on_network_change: false,
+            anomaly_threshold: DEFAULT_ANOMALY_THRESHOLD,
+            anomaly_hooks: Vec::new(),
+            alert_hooks: Vec::new(),
+            alert_rate_limit: DEFAULT_ALERT_RATE_LIMIT,
+            large_step_threshold: DEFAULT_LARGE_STEP_THRESHOLD,
+            audit_log: None,
+            audit_hash_chain: false,
+        }
+    }
+}
+
+impl ClockConfig {
+    /// Creates a configuration with the default servers and interval
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the NTP servers
+    pub fn with_servers(mut self, servers: Vec<String>) -> Self {
+        self.servers = servers;
+        self
+    }
+
+    /// Sets the servers from typed specs, see [`ServerSpec::builder`]
+    pub fn with_server_specs(mut self, specs: Vec<ServerSpec>) -> Self {
+        self.servers = specs.iter().map(ServerSpec::to_string).collect();
+        self
+    }
+
+    /// The servers that parse as a [`ServerSpec`], with the preferred and noselect lists
+    /// folded into their options
+    pub fn server_specs(&self) -> Vec<ServerSpec> {
+        let names = |servers: &[String]| -> Vec<String> {
+            servers.iter().map(|s| server::canonical_name(s)).collect()
+        };
+        let preferred = names(&self.preferred_servers);
+        let noselect = names(&self.noselect_servers);
+        self.servers
+            .iter()
+            .filter_map(|s| s.parse::<ServerSpec>().ok())
+            .map(|mut spec| {
+                spec.prefer |= preferred.contains(&spec.name());
+                spec.noselect |= noselect.contains(&spec.name());
+                spec
+            })
+            .collect()
+    }
+
+    /// Marks servers as preferred; they should also be listed in [`servers`](Self::servers)
+    pub fn with_preferred_servers(mut self, servers: Vec<String>) -> Self {
+        self.preferred_servers = servers;
+        self
+    }
+
+    /// Marks servers as monitored only; they should also be listed in
+    /// [`servers`](Self::servers)
+    pub fn with_noselect_servers(mut self, servers: Vec<String>) -> Self {
+        self.noselect_servers = servers;
+        self
+    }
+
+    /// Sets the background sync interval
+    pub fn with_sync_interval(mut self, interval: Duration) -> Self {
+        self.sync_interval = interval;
+        self
+    }
+
+    /// Sets what the clock reports before its first successful sync
+    pub fn with_fallback_policy(mut self, policy: FallbackPolicy) -> Self {
+        self.fallback_policy = policy;
+        self
+    }
+
+    /// Sets the earliest acceptable NTP time, replacing the build-time floor
+    pub fn with_min_time(mut self, min_time: Option<Timestamp>) -> Self {
+        self.min_time = min_time;
+        self
+    }
+
+    /// Sets the latest plausible NTP time, replacing the one derived from the build time
+    pub fn with_max_time(mut self, max_time: Option<Timestamp>) -> Self {
+        self.max_time = max_time;
+        self
+    }
+
+    /// Sets what to do when NTP and the hardware RTC disagree; `None` skips the check
+    pub fn with_rtc_policy(mut self, policy: Option<RtcPolicy>) -> Self {
+        self.rtc_policy = policy;
+        self
+    }
+
+    /// Sets the RTC device cross-checked against NTP
+    pub fn with_rtc_device(mut self, device: impl Into<PathBuf>) -> Self {
+        self.rtc_device = device.into();
+        self
+    }
+
+    /// Sets the disagreement between NTP and the RTC that is tolerated
+    pub fn with_rtc_tolerance(mut self, tolerance: Duration) -> Self {
+        self.rtc_tolerance = tolerance;
+        self
+    }
+
+    /// Sets how often the RTC is programmed with the disciplined time; `None` never writes it
+    pub fn with_rtc_write_interval(mut self, interval: Option<Duration>) -> Self {
+        self.rtc_write_interval = interval;
+        self
+    }
+
+    /// Enables using the persisted last-known-good time as a floor
+    pub fn with_persisted_floor(mut self, enabled: bool) -> Self {
+        self.persisted_floor = enabled;
+        self
+    }
+
+    /// Sets how long after the last successful sync the clock is reported as stale
+    pub fn with_staleness_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.staleness_threshold = threshold;
+        self
+    }
+
+    /// Sets how many requests are sent to the selected server per sync (at least one)
+    pub fn with_samples_per_poll(mut self, samples: u32) -> Self {
+        self.samples_per_poll = samples.max(1);
+        self
+    }
+
+    /// Enables combining all servers that agree instead of using the first that answers
+    pub fn with_combine_sources(mut self, enabled: bool) -> Self {
+        self.combine_sources = enabled;
+        self
+    }
+
+    /// Enables racing all servers for the initial sync
+    pub fn with_race_initial_sync(mut self, enabled: bool) -> Self {
+        self.race_initial_sync = enabled;
+        self
+    }
+
+    /// Enables the RFC 8633 best-practices profile, see
+    /// [`best_practices`](Self::best_practices)
+    pub fn with_best_practices(mut self, enabled: bool) -> Self {
+        self.best_practices = enabled;
+        self
+    }
+
+    /// The configuration a clock actually applies. The sync interval is kept within the
+    /// `minpoll` and `maxpoll` options of the selectable servers, the smallest `maxpoll`
+    /// winning over the largest `minpoll`. Under [`best_practices`](Self::best_practices),
+    /// source combining is also enabled, the servers are topped up to [`BCP_MIN_SOURCES`]
+    /// with `N.pool.ntp.org`, and the sync interval is raised to [`BCP_MIN_SYNC_INTERVAL`].
+    pub fn effective(&self) -> ClockConfig {
+        let mut config = self.clone();
+        let selectable: Vec<ServerSpec> = config
+            .server_specs()
+            .into_iter()
+            .filter(|spec| !spec.noselect)
+            .collect();
+        if let Some(min_poll) = selectable.iter().filter_map(|spec| spec.min_poll).max() {
+            config.sync_interval = config.sync_interval.max(server::poll_interval(min_poll));
+        }
+        if let Some(max_poll) = selectable.iter().filter_map(|spec| spec.max_poll).min() {
+            config.sync_interval = config.sync_interval.min(server::poll_interval(max_poll));
+        }
+        if !config.best_practices {
+            return config;
+        }
+        config.combine_sources = true;
+        config.sync_interval = config.sync_interval.max(BCP_MIN_SYNC_INTERVAL);
+        let mut selectable = selectable.len();
+        for n in 0.. {
+            if selectable >= BCP_MIN_SOURCES {
+                break;
+            }
+            let server = format!("{}.pool.ntp.org:123", n);
+            if !config.servers.contains(&server) {
+                config.servers.push(server);
+                selectable += 1;
+            }
+        }
+        config
+    }
+
+    /// Sets the absolute cap on the round-trip delay of accepted samples
+    pub fn with_max_delay(mut self, max_delay: Option<Duration>) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Sets the cap on the round-trip delay as a multiple of the recent minimum
+    pub fn with_max_delay_ratio(mut self, ratio: Option<f64>) -> Self {
+        self.max_delay_ratio = ratio;
+        self
+    }
+
+    /// Sets the most queries sent to any one server in a minute (at least one)
+    pub fn with_max_queries_per_minute(mut self, budget: Option<u32>) -> Self {
+        self.max_queries_per_minute = budget.map(|budget| budget.max(1));
+        self
+    }
+
+    /// Sets the range source ports are picked from
+    pub fn with_source_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.source_ports = ports;
+        self
+    }
+
+    /// Sets the DSCP code point (0-63) queries are marked with
+    pub fn with_dscp(mut self, dscp: Option<u8>) -> Self {
+        self.dscp = dscp;
+        self
+    }
+
+    /// Sets the TTL or hop limit of queries (at least one)
+    pub fn with_ttl(mut self, ttl: Option<u32>) -> Self {
+        self.ttl = ttl.map(|ttl| ttl.max(1));
+        self
+    }
+
+    /// Sets the transport queries are carried over; `None` uses plain UDP
+    pub fn with_transport(mut self, transport: Option<TransportFactory>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Sets the keys servers with a `key` are authenticated with
+    pub fn with_keys(mut self, keys: Option<SharedKeyStore>) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Sets where NTS sessions are kept across restarts
+    pub fn with_nts_cookies(mut self, path: Option<PathBuf>) -> Self {
+        self.nts_cookies = path;
+        self
+    }
+
+    /// Sets who resolves server names
+    pub fn with_dns(mut self, dns: DnsStrategy) -> Self {
+        self.dns = dns;
+        self
+    }
+
+    /// Sets which resolved server addresses may be queried
+    pub fn with_address_policy(mut self, policy: AddressPolicy) -> Self {
+        self.address_policy = policy;
+        self
+    }
+
+    /// Sets how measured offsets are applied to the reported time
+    pub fn with_smoothing(mut self, filter: Option<SmoothingFilter>) -> Self {
+        self.smoothing = filter;
+        self
+    }
+
+    /// Sets how long no server may be reachable before the clock enters orphan mode
+    pub fn with_orphan_after(mut self, after: Option<Duration>) -> Self {
+        self.orphan_after = after;
+        self
+    }
+
+    /// Sets the fastest rate the `pi` filter slews at, in parts per million
+    pub fn with_max_slew_ppm(mut self, ppm: f64) -> Self {
+        self.max_slew_ppm = ppm;
+        self
+    }
+
+    /// Sets the longest wait between the fast retries made while no source is reachable
+    pub fn with_offline_retry_max(mut self, max: Option<Duration>) -> Self {
+        self.offline_retry_max = max;
+        self
+    }
+
+    /// Sets the stratum reported in orphan mode (1-15)
+    pub fn with_orphan_stratum(mut self, stratum: u8) -> Self {
+        self.orphan_stratum = stratum.clamp(1, MAX_STRATUM - 1);
+        self
+    }
+
+    /// Sets the peer instances polled when no server is reachable
+    pub fn with_peers(mut self, peers: Vec<String>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    /// Sets the address on which peers' queries are answered
+    pub fn with_peer_listen(mut self, addr: Option<SocketAddr>) -> Self {
+        self.peer_listen = addr;
+        self
+    }
+
+    /// Sets how fast one client may query the responder; `None` turns rate limiting off
+    pub fn with_rate_limit(mut self, rate_limit: Option<RateLimit>) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Sets how many clients the responder remembers, at least one
+    pub fn with_mru_size(mut self, size: usize) -> Self {
+        self.mru_size = size.max(1);
+        self
+    }
+
+    /// Enables discovering servers over mDNS
+    pub fn with_mdns_discovery(mut self, enabled: bool) -> Self {
+        self.mdns_discovery = enabled;
+        self
+    }
+
+    /// Sets the addresses probed for NTP servers
+    pub fn with_manycast(mut self, targets: Vec<SocketAddr>) -> Self {
+        self.manycast = targets;
+        self
+    }
+
+    /// Enables burst resyncs on network changes
+    pub fn with_resync_on_network_change(mut self, enabled: bool) -> Self {
+        self.resync_on_network_change = enabled;
+        self
+    }
+
+    /// Sets the instance name the peer responder is advertised under over mDNS
+    pub fn with_mdns_advertise(mut self, instance: Option<String>) -> Self {
+        self.mdns_advertise = instance;
+        self
+    }
+
+    /// Sets the offset change that is reported as an anomaly
+    pub fn with_anomaly_threshold(mut self, threshold: Duration) -> Self {
+        self.anomaly_threshold = threshold;
+        self
+    }
+
+    /// Sets where anomalies are reported
+    pub fn with_anomaly_hooks(mut self, hooks: Vec<AnomalyHook>) -> Self {
+        self.anomaly_hooks = hooks;
+        self
+    }
+
+    /// Sets where alerts are delivered
+    pub fn with_alert_hooks(mut self, hooks: Vec<AlertHook>) -> Self {
+        self.alert_hooks = hooks;
+        self
+    }
+
+    /// Sets the shortest time between two alerts of one kind
+    pub fn with_alert_rate_limit(mut self, rate_limit: Duration) -> Self {
+        self.alert_rate_limit = rate_limit;
+        self
+    }
+
+    /// Sets the step of the clock that raises a large step alert
+    pub fn with_large_step_threshold(mut self, threshold: Duration) -> Self {
+        self.large_step_threshold = threshold;
+        self
+    }
+
+    /// Sets the audit log every adjustment is appended to, opened before the first sync
+    pub fn with_audit_log(mut self, path: Option<PathBuf>) -> Self {
+        self.audit_log = path;
+        self
+    }
+
+    /// Sets whether the audit log records are hash-chained
+    pub fn with_audit_hash_chain(mut self, enabled: bool) -> Self {
+        self.audit_hash_chain = enabled;
+        self
+    }
+
+    /// Reads a configuration file, starting from the defaults for keys it does not set
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The round-trip delay limits implied by this configuration
+    pub fn delay_limits(&self) -> DelayLimits {
+        DelayLimits {
+            max_delay: self.max_delay,
+            max_ratio: self.max_delay_ratio,
+        }
+    }
+
+    /// The RTC cross-check implied by this configuration, if [`rtc_policy`](Self::rtc_policy)
+    /// is set
+    pub fn rtc_check(&self) -> Option<RtcCheck> {
+        Some(RtcCheck {
+            device: self.rtc_device.clone(),
+            tolerance: self.rtc_tolerance,
+            policy: self.rtc_policy?,
+        })
+    }
+
+    /// Where and how often the RTC is written, if
+    /// [`rtc_write_interval`](Self::rtc_write_interval) is set
+    pub fn rtc_write(&self) -> Option<RtcWrite> {
+        Some(RtcWrite {
+            device: self.rtc_device.clone(),
+            interval: self.rtc_write_interval?,
+        })
+    }
+
+    /// The floor implied by this configuration, combining `min_time` with the persisted
+    /// time when `persisted_floor` is enabled
+    pub fn time_floor(&self) -> Option<Timestamp> {
+        let persisted = match &self.fallback_policy {
+            FallbackPolicy::LastPersistedTime(path) if self.persisted_floor => {
+                crate::persist::load_state(path)
+                    .ok()
+                    .map(|state| state.time)
+            }
+            _ => None,
+        };
+        self.min_time.max(persisted)
+    }
+}
+
+/// One setting that differs between two configurations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChange {
+    /// Name of the setting, as used in configuration files
+    pub setting: &'static str,
+    /// Previous value, formatted for display
+    pub old: String,
+    /// New value, formatted for display
+    pub new: String,
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.setting, self.old, self.new)
+    }
+}
+
+impl ClockConfig {
+    /// The settings that differ between this configuration and `new`
+    pub fn diff(&self, new: &ClockConfig) -> Vec<ConfigChange> {
+        fn change<T: PartialEq>(
+            setting: &'static str,
+            old: &T,
+            new: &T,
+            show: impl Fn(&T) -> String,
+        ) -> Option<ConfigChange> {
+            (old != new).then(|| ConfigChange {
+                setting,
+                old: show(old),
+                new: show(new),
+            })
+        }
+        let secs = |d: &Duration| format!("{}s", d.as_secs());
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "none".to_string());
+
+        [
+            change("server", &self.servers, &new.servers, |s| s.join(", ")),
+            change(
+                "prefer",
+                &self.preferred_servers,
+                &new.preferred_servers,
+                |s| s.join(", "),
+            ),
+            change(
+                "noselect",
+                &self.noselect_servers,
+                &new.noselect_servers,
+                |s| s.join(", "),
+            ),
+            change(
+                "sync_interval",
+                &self.sync_interval,
+                &new.sync_interval,
+                secs,
+            ),
+            change(
+                "fallback",
+                &self.fallback_policy,
+                &new.fallback_policy,
+                |p| format!("{:?}", p),
+            ),
+            change("min_time", &self.min_time, &new.min_time, |t| {
+                optional(t.map(|t| t.to_rfc3339()))
+            }),
+            change("max_time", &self.max_time, &new.max_time, |t| {
+                t.map_or_else(|| "off".to_string(), |t| t.to_rfc3339())
+            }),
+            change("rtc_policy", &self.rtc_policy, &new.rtc_policy, |p| {
+                p.map_or_else(|| "off".to_string(), |p| p.to_string())
+            }),
+            change("rtc_device", &self.rtc_device, &new.rtc_device, |d| {
+                d.display().to_string()
+            }),
+            change(
+                "rtc_tolerance",
+                &self.rtc_tolerance,
+                &new.rtc_tolerance,
+                secs,
+            ),
+            change(
+                "rtc_write_interval",
+                &self.rtc_write_interval,
+                &new.rtc_write_interval,
+                |d| d.as_ref().map_or_else(|| "off".to_string(), secs),
+            ),
+            change(
+                "persisted_floor",
+                &self.persisted_floor,
+                &new.persisted_floor,
+                bool::to_string,
+            ),
+            change(
+                "stale_after",
+                &self.staleness_threshold,
+                &new.staleness_threshold,
+                |d| optional(d.as_ref().map(secs)),
+            ),
+            change(
+                "samples_per_poll",
+                &self.samples_per_poll,
+                &new.samples_per_poll,
+                u32::to_string,
+            ),
+            change(
+                "combine_sources",
+                &self.combine_sources,
+                &new.combine_sources,
+                bool::to_string,
+            ),
+            change(
+                "race_initial_sync",
+                &self.race_initial_sync,
+                &new.race_initial_sync,
+                bool::to_string,
+            ),
+            change(
+                "best_practices",
+                &self.best_practices,
+                &new.best_practices,
+                bool::to_string,
+            ),
+            change("max_delay_ms", &self.max_delay, &new.max_delay, |d| {
+                optional(d.map(|d| d.as_millis().to_string()))
+            }),
+            change(
+                "max_delay_ratio",
+                &self.max_delay_ratio,
+                &new.max_delay_ratio,
+                |r| optional(r.map(|r| r.to_string())),
+            ),
+            change(
+                "max_queries_per_minute",
+                &self.max_queries_per_minute,
+                &new.max_queries_per_minute,
+                |b| optional(b.map(|b| b.to_string())),
+            ),
+            change("source_ports", &self.source_ports, &new.source_ports, |p| {
+                format!("{}-{}", p.start(), p.end())
+            }),
+            change("dscp", &self.dscp, &new.dscp, |d| {
+                optional(d.map(|d| d.to_string()))
+            }),
+            change("ttl", &self.ttl, &new.ttl, |t| {
+                optional(t.map(|t| t.to_string()))
+            }),
+            change("transport", &self.transport, &new.transport, |t| {
+                t.as_ref()
+                    .map_or_else(|| "udp".to_string(), |t| t.to_string())
+            }),
+            change("keys", &self.keys, &new.keys, |k| {
+                optional(k.as_ref().map(|k| format!("{} keys", k.read().len())))
+            }),
+            change("nts_cookies", &self.nts_cookies, &new.nts_cookies, |p| {
+                optional(p.as_ref().map(|p| p.display().to_string()))
+            }),
+            change("dns", &self.dns, &new.dns, DnsStrategy::to_string),
+            change(
+                "allow_addresses",
+                &self.address_policy.allow,
+                &new.address_policy.allow,
+                |a| optional((!a.is_empty()).then(|| a.to_string())),
+            ),
+            change(
+                "deny_addresses",
+                &self.address_policy.deny,
+                &new.address_policy.deny,
+                |d| optional((!d.is_empty()).then(|| d.to_string())),
+            ),
+            change("smoothing", &self.smoothing, &new.smoothing, |f| {
+                optional(f.map(|f| f.to_string()))
+            }),
+            change(
+                "max_slew_ppm",
+                &self.max_slew_ppm,
+                &new.max_slew_ppm,
+                f64::to_string,
+            ),
+            change(
+                "offline_retry_max",
+                &self.offline_retry_max,
+                &new.offline_retry_max,
+                |d| optional(d.as_ref().map(secs)),
+            ),
+            change("orphan_after", &self.orphan_after, &new.orphan_after, |d| {
+                optional(d.as_ref().map(secs))
+            }),
+            change(
+                "orphan_stratum",
+                &self.orphan_stratum,
+                &new.orphan_stratum,
+                u8::to_string,
+            ),
+            change("peer", &self.peers, &new.peers, |p| {
+                optional((!p.is_empty()).then(|| p.join(", ")))
+            }),
+            change("peer_listen", &self.peer_listen, &new.peer_listen, |a| {
+                optional(a.map(|a| a.to_string()))
+            }),
+            change("rate_limit", &self.rate_limit, &new.rate_limit, |r| {
+                r.map_or_else(|| "off".to_string(), |r| r.to_string())
+            }),
+            change("mru_size", &self.mru_size, &new.mru_size, usize::to_string),
+            change(
+                "mdns_discovery",
+                &self.mdns_discovery,
+                &new.mdns_discovery,
+                bool::to_string,
+            ),
+            change("manycast", &self.manycast, &new.manycast, |t| {
+                let targets: Vec<String> = t.iter().map(SocketAddr::to_string).collect();
+                optional((!targets.is_empty()).then(|| targets.join(", ")))
+            }),
+            change(
+                "mdns_advertise",
+                &self.mdns_advertise,
+                &new.mdns_advertise,
+                |i| optional(i.clone()),
+            ),
+            change(
+                "resync_on_network_change",
+                &self.resync_on_network_change,
+                &new.resync_on_network_change,
+                bool::to_string,
+            ),
+            change(
+                "anomaly_threshold_ms",
+                &self.anomaly_threshold,
+                &new.anomaly_threshold,
+                |d| d.as_millis().to_string(),
+            ),
+            change(
+                "anomaly_hook",
+                &self.anomaly_hooks,
+                &new.anomaly_hooks,
+                |hooks| {
+                    let hooks: Vec<String> = hooks.iter().map(|h| h.to_string()).collect();
+                    optional((!hooks.is_empty()).then(|| hooks.join(", ")))
+                },
+            ),
+            change("alert_hook", &self.alert_hooks, &new.alert_hooks, |hooks| {
+                let hooks: Vec<String> = hooks.iter().map(|h| h.to_string()).collect();
+                optional((!hooks.is_empty()).then(|| hooks.join(", ")))
+            }),
+            change(
+                "alert_rate_limit",
+                &self.alert_rate_limit,
+                &new.alert_rate_limit,
+                secs,
+            ),
+            change(
+                "large_step_threshold_ms",
+                &self.large_step_threshold,
+                &new.large_step_threshold,
+                |d| d.as_millis().to_string(),
+            ),
+            change("audit_log", &self.audit_log, &new.audit_log, |p| {
+                optional(p.as_ref().map(|p| p.display().to_string()))
+            }),
+            change(
+                "audit_hash_chain",
+                &self.audit_hash_chain,
+                &new.audit_hash_chain,
+                bool::to_string,
+            ),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// Parses a port range such as `50000-50999`, or a single port
+pub fn parse_port_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    let port = |p: &str| {
+        p.trim()
+            .parse::<u16>()
+            .map_err(|e| format!("invalid port '{}': {}", p.trim(), e))
+    };
+    let (start, end) = (port(start)?, port(end)?);
+    if start == 0 || start > end {
+        return Err(format!("invalid port range '{}'", s));
+    }
+    Ok(start..=end)
+}
+
+/// Parses a DSCP code point given as a number from 0 to 63 or by its name: `EF`, `VA`,
+/// `CS0`-`CS7`, or `AF11`-`AF43`
+pub fn parse_dscp(s: &str) -> Result<u8, String> {
+    let name = s.trim().to_ascii_uppercase();
+    let dscp = match name.as_str() {
+        "EF" => Some(46),
+        "VA" => Some(44),
+        _ => {
+            if let Some(class) = name.strip_prefix("CS") {
+                class.parse::<u8>().ok().filter(|c| *c <= 7).map(|c| c << 3)
+            } else if let Some(af) = name.strip_prefix("AF") {
+                // AFxy: class x in 1-4, drop precedence y in 1-3
+                match af.as_bytes() {
+                    [x @ b'1'..=b'4', y @ b'1'..=b'3'] => Some((x - b'0') << 3 | (y - b'0') << 1),
+                    _ => None,
+                }
+            } else {
+                name.parse::<u8>().ok().filter(|d| *d <= 63)
+            }
+        }
+    };
+    dscp.ok_or_else(|| format!("invalid DSCP '{}': expected 0-63, EF, VA, CSn, or AFxy", s))
+}
+
+impl FromStr for ClockConfig {
+    type Err = String;
+
+    /// Parses the contents of a configuration file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = ClockConfig::default();
+        let mut servers = Vec::new();
+        for (index, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |msg: String| format!("line {}: {}", index + 1, msg);
+            let (key, value) = line
+                .split_once('=')
+                .map(|(k, v)| (k.trim(), v.trim()))
+                .ok_or_else(|| error(format!("expected 'key = value', found '{}'", line)))?;
+            let seconds = || {
+                value
+                    .parse()
+                    .map(Duration::from_secs)
+                    .map_err(|e| error(format!("invalid {}: {}", key, e)))
+            };
+            match key {
+                "server" => {
+                    let mut spec: ServerSpec = value.parse().map_err(error)?;
+                    if std::mem::take(&mut spec.prefer) {
+                        config.preferred_servers.push(spec.name());
+                    }
+                    if std::mem::take(&mut spec.noselect) {
+                        config.noselect_servers.push(spec.name());
+                    }
+                    servers.push(spec.to_string());
+                }
+                "sync_interval" => config.sync_interval = seconds()?,
+                "fallback" => config.fallback_policy = value.parse().map_err(error)?,
+                "min_time" => {
+                    config.min_time = Some(Timestamp::parse_rfc3339(value).map_err(error)?)
+                }
+                "rtc_policy" => {
+                    config.rtc_policy = match value {
+                        "off" => None,
+                        _ => Some(value.parse().map_err(error)?),
+                    }
+                }
+                "rtc_device" => config.rtc_device = PathBuf::from(value),
+                "rtc_tolerance" => config.rtc_tolerance = seconds()?,
+                "rtc_write_interval" => {
+                    config.rtc_write_interval = match value {
+                        "off" => None,
+                        _ => Some(seconds()?),
+                    }
+                }
+                "max_time" => {
+                    config.max_time = match value {
+                        "off" => None,
+                        _ => Some(parse_max_time(value).map_err(error)?),
+                    }
+                }
+                "persisted_floor" => {
+                    config.persisted_floor = value
+                        .parse()
+                        .map_err(|e| error(format!("invalid persisted_floor: {}", e)))?
+                }
+                "stale_after" => config.staleness_threshold = Some(seconds()?),
+                "samples_per_poll" => match value.parse::<u32>() {
+                    Ok(samples) if samples >= 1 => config.samples_per_poll = samples,
+                    _ => return Err(error(format!("invalid {}: expected a number >= 1", key))),
+                },
+                "combine_sources" => {
+                    config.combine_sources = value
+                        .parse()
+                        .map_err(|e| error(format!("invalid combine_sources: {}", e)))?
+                }
+                "race_initial_sync" => {
+                    config.race_initial_sync = value
+                        .parse()
+                        .map_err(|e| error(format!("invalid {}: {}", key, e)))?
+                }
+                "best_practices" => {
+                    config.best_practices = value
+                        .parse()
+                        .map_err(|e| error(format!("invalid best_practices: {}", e)))?
+                }
+                "max_delay_ms" => {
+                    config.max_delay = Some(
+                        value
+                            .parse()
+                            .map(Duration::from_millis)
+                            .map_err(|e| error(format!("invalid {}: {}", key, e)))?,
+                    )
+                }
+                "max_delay_ratio" => match value.parse::<f64>() {
+                    Ok(ratio) if ratio >= 1.0 => config.max_delay_ratio = Some(ratio),
+                    _ => return Err(error(format!("invalid {}: expected a number >= 1", key))),
+                },
+                "max_queries_per_minute" => match value.parse::<u32>() {
+                    Ok(budget) if budget >= 1 => config.max_queries_per_minute = Some(budget),
+                    _ => return Err(error(format!("invalid {}: expected a number >= 1", key))),
+                },
+                "source_ports" => config.source_ports = parse_port_range(value).map_err(error)?,
+                "dscp" => config.dscp = Some(parse_dscp(value).map_err(error)?),
+                "ttl" => match value.parse::<u32>() {
+                    Ok(ttl) if (1..=255).contains(&ttl) => config.ttl = Some(ttl),
+                    _ => {
+                        return Err(error(format!(
+                            "invalid {}: expected a number from 1 to 255",
+                            key
+                        )))
+                    }
+                },
+                "nts_cookies" => config.nts_cookies = Some(PathBuf::from(value)),
+                "dns" => config.dns = value.parse().map_err(error)?,
+                "allow_addresses" => {
+                    let ranges = value.parse::<AddressSet>().map_err(error)?;
+                    config.address_policy.allow.0.extend(ranges.0);
+                }
+                "deny_addresses" => {
+                    let ranges = value.parse::<AddressSet>().map_err(error)?;
+                    config.address_policy.deny.0.extend(ranges.0);
+                }
+                "smoothing" => config.smoothing = Some(value.parse().map_err(error)?),
+                "max_slew_ppm" => match value.parse::<f64>() {
+                    Ok(ppm) if ppm > 0.0 && ppm.is_finite() => config.max_slew_ppm = ppm,
+                    _ => return Err(error(format!("invalid {}: expected a number > 0", key))),
+                },
+                "offline_retry_max" => config.offline_retry_max = Some(seconds()?),
+                "orphan_after" => config.orphan_after = Some(seconds()?),
+                "orphan_stratum" => match value.parse::<u8>() {
+                    Ok(stratum) if (1..MAX_STRATUM).contains(&stratum) => {
+                        config.orphan_stratum = stratum
+                    }
+                    _ => return Err(error(format!("invalid {}: expected 1 to 15", key))),
+                },
+                "peer" => config.peers.push(value.to_string()),
+                "mdns_discovery" => {
+                    config.mdns_discovery = value
+                        .parse()
+                        .map_err(|e| error(format!("invalid mdns_discovery: {}", e)))?
+                }
+                "mdns_advertise" => config.mdns_advertise = Some(value.to_string()),
+                "manycast" => config
+                    .manycast
+                    .push(crate::manycast::parse_target(value).map_err(error)?),
+                "resync_on_network_change" => {
+                    config.resync_on_network_change = value
+                        .parse()
+                        .map_err(|e| error(format!("invalid {}: {}", key, e)))?
+                }
+                "peer_listen" => {
+                    config.peer_listen = Some(
+                        value
+                            .parse()
+                            .map_err(|e| error(format!("invalid {}: {}", key, e)))?,
+                    )
+                }
+                "rate_limit" => {
+                    config.rate_limit = match value {
+                        "off" => None,
+                        _ => Some(value.parse().map_err(error)?),
+                    }
+                }
+                "mru_size" => match value.parse::<usize>() {
+                    Ok(size) if size >= 1 => config.mru_size = size,
+                    _ => return Err(error(format!("invalid {}: expected a number >= 1", key))),
+                },
+                "anomaly_threshold_ms" => {
+                    config.anomaly_threshold = value
+                        .parse()
+                        .map(Duration::from_millis)
+                        .map_err(|e| error(format!("invalid {}: {}", key, e)))?
+                }
+                "anomaly_hook" => config.anomaly_hooks.push(value.parse().map_err(error)?),
+                "alert_hook" => config.alert_hooks.push(value.parse().map_err(error)?),
+                "alert_rate_limit" => config.alert_rate_limit = seconds()?,
+                "large_step_threshold_ms" => {
+                    config.large_step_threshold = value
+                        .parse()
+                        .map(Duration::from_millis)
+                        .map_err(|e| error(format!("invalid {}: {}", key, e)))?
+                }
+                "audit_log" => config.audit_log = Some(PathBuf::from(value)),
+                "audit_hash_chain" => {
+                    config.audit_hash_chain = value
+                        .parse()
+                        .map_err(|e| error(format!("invalid {}: {}", key, e)))?
+                }
+                _ => return Err(error(format!("unknown key '{}'", key))),
+            }
+        }
+        if !servers.is_empty() {
+            config.servers = servers;
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fallback_policy_from_str() {
+        assert_eq!("error".parse(), Ok(FallbackPolicy::Error));
+        assert_eq!("system".parse(), Ok(FallbackPolicy::SystemClock));
+        assert_eq!(
+            "file:/var/lib/clock/time".parse(),
+            Ok(FallbackPolicy::LastPersistedTime(PathBuf::from(
+                "/var/lib/clock/time"
+            )))
+        );
+        assert!("file:".parse::<FallbackPolicy>().is_err());
+        assert!("rtc".parse::<FallbackPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_best_practices_effective_config() {
+        let config = ClockConfig::new()
+            .with_servers(vec![
+                "1.pool.ntp.org:123".to_string(),
+                "ntp.staging:123".to_string(),
+            ])
+            .with_noselect_servers(vec!["ntp.staging:123".to_string()])
+            .with_best_practices(true);
+        let effective = config.effective();
+        assert!(effective.combine_sources);
+        assert_eq!(effective.sync_interval, BCP_MIN_SYNC_INTERVAL);
+        assert_eq!(
+            effective.servers,
+            [
+                "1.pool.ntp.org:123",
+                "ntp.staging:123",
+                "0.pool.ntp.org:123",
+                "2.pool.ntp.org:123",
+                "3.pool.ntp.org:123"
+            ]
+        );
+
+        let plain = config.with_best_practices(false);
+        assert_eq!(plain.effective(), plain);
+    }
+
+    #[test]
+    fn test_server_poll_bounds_clamp_sync_interval() {
+        let specs = vec![
+            ServerSpec::builder("a").min_poll(6).build().unwrap(),
+            ServerSpec::builder("b").max_poll(8).build().unwrap(),
+            ServerSpec::builder("c")
+                .max_poll(4)
+                .noselect()
+                .build()
+                .unwrap(),
+        ];
+        let config = ClockConfig::new()
+            .with_server_specs(specs.clone())
+            .with_sync_interval(Duration::from_secs(10));
+        assert_eq!(config.server_specs(), specs);
+        assert_eq!(config.effective().sync_interval, Duration::from_secs(64));
+
+        let config = config.with_sync_interval(Duration::from_secs(3600));
+        assert_eq!(config.effective().sync_interval, Duration::from_secs(256));
+    }
+
+    #[test]
+    fn test_config_from_str() {
+        let config: ClockConfig = "
+            # local servers first
+            server = 127.0.0.1:123 prefer
+            server = time.google.com:123
+            server = 10.0.0.5:123 noselect
+            server = ntp://10.0.0.6:1123 iburst version 4
+            sync_interval = 64  # seconds
+            fallback = error
+            min_time = 2026-01-01T00:00:00Z
+            max_time = 2060-01-01T00:00:00Z
+            rtc_policy = hold
+            rtc_device = /dev/rtc1
+            rtc_tolerance = 600
+            rtc_write_interval = 3600
+            stale_after = 300
+            samples_per_poll = 5
+            combine_sources = true
+            race_initial_sync = true
+            best_practices = true
+            max_delay_ms = 250
+            max_delay_ratio = 2.5
+            max_queries_per_minute = 6
+            source_ports = 50000 - 50999
+            dscp = ef
+            ttl = 32
+            nts_cookies = /var/lib/clock/nts.cookies
+            dns = static:ntp.plant=10.0.0.5
+            deny_addresses = loopback
+            deny_addresses = 192.0.2.0/24
+            allow_addresses = 10.0.0.0/8
+            smoothing = ema:0.5
+            max_slew_ppm = 50
+            offline_retry_max = 30
+            orphan_after = 600
+            orphan_stratum = 12
+            peer = 10.0.0.7:11123
+            peer = 10.0.0.8:11123
+            peer_listen = 0.0.0.0:11123
+            rate_limit = 0.5:4
+            mru_size = 64
+            mdns_discovery = true
+            manycast = 192.168.1.255
+            manycast = 10.0.0.5:1123
+            mdns_advertise = lab-clock
+            resync_on_network_change = true
+            anomaly_threshold_ms = 250
+            anomaly_hook = exec:logger -t clock
+            alert_hook = large_step=http://alerts:9000/hook
+            alert_rate_limit = 60
+            large_step_threshold_ms = 100
+            audit_log = /var/log/clock/audit.log
+            audit_hash_chain = true
+        "
+        .parse()
+        .unwrap();
+        assert_eq!(
+            config.servers,
+            [
+                "127.0.0.1:123",
+                "time.google.com:123",
+                "10.0.0.5:123",
+                "10.0.0.6:1123 iburst version 4"
+            ]
+        );
+        assert_eq!(config.preferred_servers, ["127.0.0.1:123"]);
+        assert_eq!(config.noselect_servers, ["10.0.0.5:123"]);
+        assert_eq!(config.sync_interval, Duration::from_secs(64));
+        assert_eq!(config.fallback_policy, FallbackPolicy::Error);
+        assert_eq!(config.min_time, "2026-01-01T00:00:00Z".parse().ok());
+        assert_eq!(config.max_time, "2060-01-01T00:00:00Z".parse().ok());
+        assert_eq!(
+            config.rtc_check(),
+            Some(RtcCheck {
+                device: PathBuf::from("/dev/rtc1"),
+                tolerance: Duration::from_secs(600),
+                policy: RtcPolicy::Hold
+            })
+        );
+        assert_eq!(
+            config.rtc_write(),
+            Some(RtcWrite {
+                device: PathBuf::from("/dev/rtc1"),
+                interval: Duration::from_secs(3600)
+            })
+        );
+        assert_eq!(config.staleness_threshold, Some(Duration::from_secs(300)));
+        assert!(!config.persisted_floor);
+        assert_eq!(config.samples_per_poll, 5);
+        assert!(config.combine_sources);
+        assert!(config.race_initial_sync);
+        assert!(config.best_practices);
+        assert_eq!(
+            config.delay_limits(),
+            DelayLimits {
+                max_delay: Some(Duration::from_millis(250)),
+                max_ratio: Some(2.5),
+            }
+        );
+        assert_eq!(config.max_queries_per_minute, Some(6));
+        assert_eq!(config.source_ports, 50000..=50999);
+        assert_eq!(config.dscp, Some(46));
+        assert_eq!(config.ttl, Some(32));
+        assert_eq!(
+            config.nts_cookies.as_deref(),
+            Some(Path::new("/var/lib/clock/nts.cookies"))
+        );
+        assert_eq!(config.dns.to_string(), "static:ntp.plant=10.0.0.5");
+        assert_eq!(
+            config.address_policy.deny.to_string(),
+            "127.0.0.0/8,::1/128,192.0.2.0/24"
+        );
+        assert!(config.address_policy.permits("10.0.0.5".parse().unwrap()));
+        assert!(!config.address_policy.permits("172.16.0.1".parse().unwrap()));
+        assert_eq!(config.smoothing, Some(SmoothingFilter::Ema { alpha: 0.5 }));
+        assert_eq!(config.max_slew_ppm, 50.0);
+        assert_eq!(config.offline_retry_max, Some(Duration::from_secs(30)));
+        assert_eq!(config.orphan_after, Some(Duration::from_secs(600)));
+        assert_eq!(config.orphan_stratum, 12);
+        assert_eq!(config.peers, ["10.0.0.7:11123", "10.0.0.8:11123"]);
+        assert_eq!(config.peer_listen, "0.0.0.0:11123".parse().ok());
+        assert_eq!(
+            config.rate_limit,
+            Some(RateLimit {
+                interval: Duration::from_millis(500),
+                burst: 4
+            })
+        );
+        assert_eq!(config.mru_size, 64);
+        assert!(config.mdns_discovery);
+        assert_eq!(
+            config.manycast,
+            [
+                "192.168.1.255:123".parse::<SocketAddr>().unwrap(),
+                "10.0.0.5:1123".parse().unwrap()
+            ]
+        );
+        assert_eq!(config.mdns_advertise.as_deref(), Some("lab-clock"));
+        assert!(config.resync_on_network_change);
+        assert_eq!(config.anomaly_threshold, Duration::from_millis(250));
+        assert_eq!(
+            config.anomaly_hooks,
+            [AnomalyHook::Command("logger -t clock".to_string())]
+        );
+        assert_eq!(
+            config.alert_hooks,
+            ["large_step=http://alerts:9000/hook".parse().unwrap()]
+        );
+        assert_eq!(config.alert_rate_limit, Duration::from_secs(60));
+        assert_eq!(config.large_step_threshold, Duration::from_millis(100));
+        assert_eq!(
+            config.audit_log.as_deref(),
+            Some(Path::new("/var/log/clock/audit.log"))
+        );
+        assert!(config.audit_hash_chain);
+
+        let defaults: ClockConfig = "".parse().unwrap();
+        assert_eq!(defaults, ClockConfig::default());
+    }
+
+    #[test]
+    fn test_config_from_str_rejects_bad_lines() {
+        let err = "server = a:123\nsync_interval = soon\n"
+            .parse::<ClockConfig>()
+            .unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        assert!("servers = a:123".parse::<ClockConfig>().is_err());
+        assert!("server a:123".parse::<ClockConfig>().is_err());
+        assert!("server = a:123 burst".parse::<ClockConfig>().is_err());
+        assert!("server = http://a".parse::<ClockConfig>().is_err());
+        assert!("max_delay_ratio = 0.5".parse::<ClockConfig>().is_err());
+        assert!("max_queries_per_minute = 0".parse::<ClockConfig>().is_err());
+        assert!("source_ports = 2000-1000".parse::<ClockConfig>().is_err());
+        assert!("source_ports = 0".parse::<ClockConfig>().is_err());
+        assert!("dscp = 64".parse::<ClockConfig>().is_err());
+        assert!("dscp = AF44".parse::<ClockConfig>().is_err());
+        assert!("ttl = 0".parse::<ClockConfig>().is_err());
+        assert!("smoothing = kalman".parse::<ClockConfig>().is_err());
+        assert!("max_slew_ppm = 0".parse::<ClockConfig>().is_err());
+        assert!("orphan_stratum = 16".parse::<ClockConfig>().is_err());
+        assert!("peer_listen = 11123".parse::<ClockConfig>().is_err());
+        assert!("max_time = +20".parse::<ClockConfig>().is_err());
+        assert!("max_time = 2060".parse::<ClockConfig>().is_err());
+        let config: ClockConfig = "max_time = off".parse().unwrap();
+        assert_eq!(config.max_time, None);
+    }
+
+    #[test]
+    fn test_parse_dscp() {
+        assert_eq!(parse_dscp("46"), Ok(46));
+        assert_eq!(parse_dscp("EF"), Ok(46));
+        assert_eq!(parse_dscp("cs6"), Ok(48));
+        assert_eq!(parse_dscp("AF41"), Ok(34));
+        assert_eq!(parse_dscp("AF13"), Ok(14));
+        assert!(parse_dscp("CS8").is_err());
+        assert!(parse_dscp("64").is_err());
+        assert!(parse_dscp("best-effort").is_err());
+    }
+
+    #[test]
+    fn test_config_diff() {
+        let old = ClockConfig::new();
+        let new = ClockConfig::new()
+            .with_servers(vec!["a:123".to_string()])
+            .with_sync_interval(Duration::from_secs(64));
+        let changes = old.diff(&new);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].setting, "server");
+        assert_eq!(changes[0].new, "a:123");
+        assert_eq!(changes[1].to_string(), "sync_interval: 10s -> 64s");
+        assert!(new.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn test_time_floor_takes_latest_bound() {
+        let path = std::env::temp_dir().join(format!("clock-ntp-floor-{}", std::process::id()));
+        let persisted: Timestamp = "2026-01-01T00:00:00Z".parse().unwrap();
+        crate::persist::save_state(
+            &path,
+            &crate::persist::PersistedState {
+                time: persisted,
+                drift_ppm: 0.0,
+            },
+        )
+        .unwrap();
+
+        let min_time: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+        let config = ClockConfig::new()
+            .with_min_time(Some(min_time))
+            .with_fallback_policy(FallbackPolicy::LastPersistedTime(path.clone()));
+        assert_eq!(config.time_floor(), Some(min_time));
+        assert_eq!(
+            config.with_persisted_floor(true).time_floor(),
+            Some(persisted)
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
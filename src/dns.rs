@@ -0,0 +1,745 @@
+//! # DNS Resolution Strategy
+//!
+//! Server names are resolved before every poll, and plain DNS is itself an attack surface
+//! for time: whoever answers the lookups picks the servers a clock believes. The
+//! [`DnsStrategy`] in [`ClockConfig::dns`](crate::ClockConfig::dns) decides who answers:
+//!
+//! - `system`: the operating system's resolver (the default)
+//! - `static:HOST=IP[,IP...][;HOST=IP...]`: a fixed map, for air-gapped networks; hosts
+//!   missing from it do not resolve at all
+//! - `doh:URL [cafile PATH] [pin sha256//BASE64]...`: DNS-over-HTTPS queries (RFC 8484)
+//!   to one `https://` resolver, given by IP address so that reaching it needs no lookup
+//!   of its own. Its certificate must chain to the roots in `cafile`, or to the system's,
+//!   and carry one of the `pin`ned public keys; at least one of the two is required, so
+//!   the resolver is never trusted on the system roots alone. Queries need the `doh`
+//!   feature, without which every name fails to resolve.
+//!
+//! ```text
+//! dns = static:ntp1.plant.example=10.0.0.5;ntp2.plant.example=10.0.0.6,10.0.0.7
+//! dns = doh:https://1.1.1.1/dns-query pin sha256//SPKI-HASH-OF-THE-RESOLVER=
+//! dns = doh:https://10.0.0.53/dns-query cafile /etc/clock/corp-ca.pem
+//! ```
+//!
+//! IP addresses are used as they are, whatever the strategy. Pins and `cafile` are written
+//! as for NTS servers, see [`server`](crate::server).
+//!
+//! Whatever answers, an [`AddressPolicy`] in
+//! [`ClockConfig::address_policy`](crate::ClockConfig::address_policy) then decides which
+//! of the addresses may be queried, so that a poisoned answer cannot point the clock at a
+//! host of the attacker's choosing. Addresses in a `deny` set are dropped, and when an
+//! `allow` set is given, so is every address outside it:
+//!
+//! ```text
+//! deny_addresses = private, loopback, link-local   # public pools only
+//! allow_addresses = 10.20.0.0/16, fd00:20::/32     # or only the corporate ranges
+//! ```
+
+#[cfg(feature = "doh")]
+use crate::dnsmsg::{self, RecordData, CLASS_IN, TYPE_A, TYPE_AAAA};
+use crate::server;
+#[cfg(feature = "doh")]
+use crate::server::TlsVersion;
+#[cfg(feature = "doh")]
+use crate::tls::{self, Trust};
+#[cfg(feature = "doh")]
+use rustls::ClientConfig;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+#[cfg(feature = "doh")]
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+#[cfg(feature = "doh")]
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a DNS-over-HTTPS query may take, connecting included
+pub const DOH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest DNS-over-HTTPS response read
+#[cfg(feature = "doh")]
+const MAX_RESPONSE_LEN: u64 = 64 * 1024;
+
+/// Who resolves server names
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum DnsStrategy {
+    /// The operating system's resolver
+    #[default]
+    System,
+    /// Fixed addresses by lowercase host name; other names do not resolve
+    Static(BTreeMap<String, Vec<IpAddr>>),
+    /// RFC 8484 queries POSTed to this `https://` URL, whose host is an IP address
+    DnsOverHttps {
+        url: String,
+        /// PEM file of the root certificates to trust instead of the system store
+        ca_file: Option<String>,
+        /// SHA-256 hashes of subject public keys, one of which the resolver must present
+        spki_pins: Vec<[u8; 32]>,
+    },
+}
+
+impl DnsStrategy {
+    /// The addresses of `host` with `port`
+    pub fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        let ips = match self {
+            DnsStrategy::System => return Ok((host, port).to_socket_addrs()?.collect()),
+            DnsStrategy::Static(hosts) => hosts
+                .get(&host.to_ascii_lowercase())
+                .cloned()
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} is not in the static host map", host),
+                    )
+                })?,
+            #[cfg(feature = "doh")]
+            DnsStrategy::DnsOverHttps {
+                url,
+                ca_file,
+                spki_pins,
+            } => {
+                let trust = Trust {
+                    ca_file: ca_file.as_deref(),
+                    spki_pins,
+                    // RFC 8484 asks for TLS 1.2 or later
+                    min_tls: TlsVersion::Tls12,
+                };
+                let config = tls::client_config(trust, b"http/1.1")?;
+                let mut ips = Vec::new();
+                for qtype in [TYPE_A, TYPE_AAAA] {
+                    let response = post_dns_message(url, &config, &query(host, qtype))?;
+                    ips.extend(addresses(&response)?);
+                }
+                if ips.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} has no addresses", host),
+                    ));
+                }
+                ips
+            }
+            #[cfg(not(feature = "doh"))]
+            DnsStrategy::DnsOverHttps { .. } => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "DNS-over-HTTPS needs the `doh` feature",
+                ))
+            }
+        };
+        Ok(ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+}
+
+impl FromStr for DnsStrategy {
+    type Err = String;
+
+    /// Parses `system`, `static:HOST=IP[,IP...][;HOST=IP...]`, or
+    /// `doh:URL [cafile PATH] [pin sha256//BASE64]...`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "system" {
+            return Ok(DnsStrategy::System);
+        }
+        if let Some(entries) = s.strip_prefix("static:") {
+            let mut hosts = BTreeMap::new();
+            for entry in entries.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+                let (host, ips) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("expected HOST=IP, found '{}'", entry))?;
+                let ips = ips
+                    .split(',')
+                    .map(|ip| {
+                        ip.trim()
+                            .parse::<IpAddr>()
+                            .map_err(|_| format!("invalid address '{}' for {}", ip, host))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                hosts.insert(host.trim().to_ascii_lowercase(), ips);
+            }
+            return Ok(DnsStrategy::Static(hosts));
+        }
+        if let Some(rest) = s.strip_prefix("doh:") {
+            let words = server::split_words(rest)?;
+            let mut words = words.iter().map(String::as_str);
+            let url = words.next().ok_or("'doh:' needs a URL")?;
+            let (authority, _) = split_url(url)?;
+            if resolver_addr(authority).is_none() {
+                return Err(format!(
+                    "the DoH resolver must be an IP address, found '{}'",
+                    authority
+                ));
+            }
+            let (mut ca_file, mut spki_pins) = (None, Vec::new());
+            while let Some(option) = words.next() {
+                match option {
+                    "cafile" => {
+                        let path = words.next().ok_or("'cafile' needs a path")?;
+                        ca_file = Some(path.to_string());
+                    }
+                    "pin" => {
+                        let pin = words.next().ok_or("'pin' needs a sha256//BASE64 hash")?;
+                        spki_pins.push(server::parse_pin(pin)?);
+                    }
+                    _ => return Err(format!("unknown DoH option '{}'", option)),
+                }
+            }
+            if ca_file.is_none() && spki_pins.is_empty() {
+                return Err(format!(
+                    "the DoH resolver {} needs a cafile or a pin to be trusted",
+                    authority
+                ));
+            }
+            return Ok(DnsStrategy::DnsOverHttps {
+                url: url.to_string(),
+                ca_file,
+                spki_pins,
+            });
+        }
+        Err(format!(
+            "unknown DNS strategy '{}', expected system, static:HOST=IP, or doh:URL",
+            s
+        ))
+    }
+}
+
+/// Writes the form [`FromStr`] reads
+impl fmt::Display for DnsStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsStrategy::System => f.write_str("system"),
+            DnsStrategy::Static(hosts) => {
+                f.write_str("static:")?;
+                for (i, (host, ips)) in hosts.iter().enumerate() {
+                    let ips: Vec<String> = ips.iter().map(IpAddr::to_string).collect();
+                    let separator = if i == 0 { "" } else { ";" };
+                    write!(f, "{}{}={}", separator, host, ips.join(","))?;
+                }
+                Ok(())
+            }
+            DnsStrategy::DnsOverHttps {
+                url,
+                ca_file,
+                spki_pins,
+            } => {
+                write!(f, "doh:{}", url)?;
+                if let Some(ca_file) = ca_file {
+                    write!(f, " cafile {}", server::quote_word(ca_file))?;
+                }
+                for pin in spki_pins {
+                    write!(f, " pin {}", server::format_pin(pin))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A block of addresses written `IP/PREFIX`, or a single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl AddressRange {
+    /// The addresses sharing the first `prefix_len` bits of `network`, or `None` if the
+    /// prefix is longer than the address
+    pub fn new(network: IpAddr, prefix_len: u8) -> Option<Self> {
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        (prefix_len <= bits).then(|| AddressRange {
+            network: mask(network, prefix_len),
+            prefix_len,
+        })
+    }
+
+    /// Whether `ip` is in the range; IPv4-mapped IPv6 addresses count as IPv4
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.network.is_ipv4() && mask(ip, self.prefix_len) == self.network
+    }
+}
+
+/// `ip` with all but its first `prefix_len` bits cleared
+fn mask(ip: IpAddr, prefix_len: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let mask = !u32::MAX.checked_shr(u32::from(prefix_len)).unwrap_or(0);
+            IpAddr::V4((u32::from(ip) & mask).into())
+        }
+        IpAddr::V6(ip) => {
+            let mask = !u128::MAX.checked_shr(u32::from(prefix_len)).unwrap_or(0);
+            IpAddr::V6((u128::from(ip) & mask).into())
+        }
+    }
+}
+
+impl FromStr for AddressRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid address range '{}', expected IP/PREFIX", s);
+        let (ip, prefix_len) = match s.split_once('/') {
+            Some((ip, len)) => (ip, Some(len.parse::<u8>().map_err(|_| invalid())?)),
+            None => (s, None),
+        };
+        let ip: IpAddr = ip.parse().map_err(|_| invalid())?;
+        let prefix_len = prefix_len.unwrap_or(if ip.is_ipv4() { 32 } else { 128 });
+        AddressRange::new(ip, prefix_len).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for AddressRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// Ranges named in an [`AddressSet`]: `private` for the RFC 1918 and unique local blocks,
+/// `loopback`, and `link-local`
+const NAMED_RANGES: [(&str, &[&str]); 3] = [
+    (
+        "private",
+        &["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "fc00::/7"],
+    ),
+    ("loopback", &["127.0.0.0/8", "::1/128"]),
+    ("link-local", &["169.254.0.0/16", "fe80::/10"]),
+];
+
+/// Address ranges, parsed from a comma-separated list of ranges and the names `private`,
+/// `loopback`, and `link-local`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AddressSet(pub Vec<AddressRange>);
+
+impl AddressSet {
+    /// Whether the set has no ranges
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether `ip` is in any of the ranges
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(ip))
+    }
+}
+
+impl FromStr for AddressSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ranges = Vec::new();
+        for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            match NAMED_RANGES
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(item))
+            {
+                Some((_, named)) => ranges.extend(
+                    named
+                        .iter()
+                        .map(|range| range.parse::<AddressRange>().unwrap()),
+                ),
+                None => ranges.push(item.parse()?),
+            }
+        }
+        Ok(AddressSet(ranges))
+    }
+}
+
+/// Writes the ranges, named ones spelled out, in the form [`FromStr`] reads
+impl fmt::Display for AddressSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ranges: Vec<String> = self.0.iter().map(AddressRange::to_string).collect();
+        f.write_str(&ranges.join(","))
+    }
+}
+
+/// Which resolved server addresses may be queried
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AddressPolicy {
+    /// When not empty, only addresses in these ranges are queried
+    pub allow: AddressSet,
+    /// Addresses in these ranges are never queried, even if allowed
+    pub deny: AddressSet,
+}
+
+impl AddressPolicy {
+    /// Whether `ip` may be queried
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        (self.allow.is_empty() || self.allow.contains(ip)) && !self.deny.contains(ip)
+    }
+}
+
+/// The authority and path of an `https://` URL
+fn split_url(url: &str) -> Result<(&str, &str), String> {
+    let rest = url
+        .strip_prefix("https://")
+        .ok_or_else(|| format!("expected an https:// URL, found '{}'", url))?;
+    Ok(match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    })
+}
+
+/// The address of a resolver given as `IP`, `IP:port`, or `[IPv6]:port`
+fn resolver_addr(authority: &str) -> Option<SocketAddr> {
+    authority.parse().ok().or_else(|| {
+        let ip = authority.trim_start_matches('[').trim_end_matches(']');
+        Some(SocketAddr::new(ip.parse().ok()?, 443))
+    })
+}
+
+/// A recursive query for the `qtype` records of `host`, with the ID of 0 RFC 8484
+/// recommends for caching
+#[cfg(feature = "doh")]
+fn query(host: &str, qtype: u16) -> Vec<u8> {
+    // Recursion desired, one question
+    let mut msg = vec![0, 0, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    dnsmsg::encode_name(&mut msg, host);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    msg
+}
+
+/// The addresses in a response, following the resolver through any CNAME records
+#[cfg(feature = "doh")]
+fn addresses(response: &[u8]) -> io::Result<Vec<IpAddr>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let message = dnsmsg::parse(response).ok_or_else(|| invalid("malformed DNS response"))?;
+    if !message.is_response {
+        return Err(invalid("DoH resolver did not send a DNS response"));
+    }
+    match message.rcode {
+        // No error, or no such name
+        0 | 3 => {}
+        rcode => return Err(io::Error::other(format!("DNS error code {}", rcode))),
+    }
+    Ok(message
+        .records
+        .iter()
+        .filter_map(|record| match record.data {
+            RecordData::A(ip) => Some(ip.into()),
+            RecordData::Aaaa(ip) => Some(ip.into()),
+            _ => None,
+        })
+        .collect())
+}
+
+/// POSTs a DNS message to `url` over TLS with `config` and returns the DNS message
+/// answered. HTTP/1.0 keeps the response free of chunked encoding.
+#[cfg(feature = "doh")]
+fn post_dns_message(url: &str, config: &Arc<ClientConfig>, message: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let (authority, path) = split_url(url).map_err(invalid)?;
+    let addr = resolver_addr(authority)
+        .ok_or_else(|| invalid(format!("'{}' is not an IP address", authority)))?;
+
+    let mut stream = tls::connect(
+        addr,
+        &addr.ip().to_string(),
+        Arc::clone(config),
+        DOH_TIMEOUT,
+    )?;
+    let header = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/dns-message\r\nAccept: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+        path,
+        authority,
+        message.len()
+    );
+    stream.write_all(&[header.as_bytes(), message].concat())?;
+    stream.flush()?;
+
+    let mut response = Vec::new();
+    match stream.take(MAX_RESPONSE_LEN).read_to_end(&mut response) {
+        // Resolvers may close without a TLS close_notify; a truncated body is caught by
+        // its Content-Length below
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+        result => {
+            result?;
+        }
+    }
+    let truncated = || io::Error::new(io::ErrorKind::InvalidData, "truncated HTTP response");
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(truncated)?;
+    let status = response.get(9..12).unwrap_or_default();
+    if status != b"200" {
+        return Err(io::Error::other(format!(
+            "DoH resolver answered {}",
+            String::from_utf8_lossy(status)
+        )));
+    }
+    let header = String::from_utf8_lossy(&response[..end]).to_ascii_lowercase();
+    let length = header
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .and_then(|length| length.trim().parse::<usize>().ok());
+    let body = response.split_off(end + 4);
+    if length.is_some_and(|length| length != body.len()) {
+        return Err(truncated());
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::spawn_fake_server;
+    #[cfg(feature = "doh")]
+    use crate::tests::TestPki;
+    use crate::{Clock, ClockConfig, Timestamp};
+    #[cfg(feature = "doh")]
+    use std::net::{Ipv4Addr, TcpListener};
+
+    #[test]
+    fn test_parse_strategies() {
+        assert_eq!("system".parse(), Ok(DnsStrategy::System));
+        let strategy: DnsStrategy = "static:NTP1.example=10.0.0.5; ntp2.example=10.0.0.6,::1"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            strategy.to_string(),
+            "static:ntp1.example=10.0.0.5;ntp2.example=10.0.0.6,::1"
+        );
+        assert_eq!(strategy.to_string().parse(), Ok(strategy.clone()));
+        let addrs = strategy.resolve("ntp2.EXAMPLE", 123).unwrap();
+        assert_eq!(addrs[1], "[::1]:123".parse().unwrap());
+        let error = strategy.resolve("time.google.com", 123).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            strategy.resolve("192.0.2.1", 123).unwrap(),
+            ["192.0.2.1:123".parse().unwrap()]
+        );
+
+        let doh = "doh:https://[::1]:8053/dns-query cafile \"/etc/clock/DNS CA.pem\" \
+                   pin sha256//y8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLy8s=";
+        let strategy: DnsStrategy = doh.parse().unwrap();
+        assert_eq!(strategy.to_string(), doh);
+        let DnsStrategy::DnsOverHttps {
+            url,
+            ca_file,
+            spki_pins,
+        } = strategy
+        else {
+            panic!("not DoH");
+        };
+        assert_eq!(url, "https://[::1]:8053/dns-query");
+        assert_eq!(ca_file.as_deref(), Some("/etc/clock/DNS CA.pem"));
+        assert_eq!(spki_pins, [[0xcb; 32]]);
+        for bad in [
+            "hosts",
+            "static:ntp.example",
+            "static:ntp.example=ntp.other",
+            "doh:https://1.1.1.1/dns-query",
+            "doh:http://1.1.1.1/dns-query cafile ca.pem",
+            "doh:https://dns.example/dns-query cafile ca.pem",
+            "doh:https://1.1.1.1/dns-query pin sha256//AAAA",
+            "doh:https://1.1.1.1/dns-query cafile ca.pem mintls 1.3",
+        ] {
+            assert!(bad.parse::<DnsStrategy>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_address_policy() {
+        let set: AddressSet = "private, 198.51.100.7, 2001:db8::/33".parse().unwrap();
+        assert_eq!(
+            set.to_string(),
+            "10.0.0.0/8,172.16.0.0/12,192.168.0.0/16,fc00::/7,198.51.100.7/32,2001:db8::/33"
+        );
+        assert_eq!(set.to_string().parse(), Ok(set.clone()));
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(set.contains(ip("172.31.255.1")));
+        assert!(!set.contains(ip("172.32.0.1")));
+        assert!(set.contains(ip("::ffff:192.168.1.1")));
+        assert!(set.contains(ip("2001:db8:7fff::1")));
+        assert!(!set.contains(ip("2001:db8:8000::1")));
+        assert_eq!(
+            "10.1.2.3/8".parse::<AddressRange>().unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        for bad in ["10.0.0.0/33", "::/129", "intranet", "10.0.0.0/"] {
+            assert!(bad.parse::<AddressSet>().is_err(), "{}", bad);
+        }
+
+        // Public pools only
+        let policy = AddressPolicy {
+            allow: AddressSet::default(),
+            deny: "private, loopback".parse().unwrap(),
+        };
+        assert!(policy.permits(ip("162.159.200.1")));
+        assert!(!policy.permits(ip("10.0.0.1")));
+        // Corporate ranges only, less one host
+        let policy = AddressPolicy {
+            allow: "10.20.0.0/16".parse().unwrap(),
+            deny: "10.20.0.66".parse().unwrap(),
+        };
+        assert!(policy.permits(ip("10.20.3.4")));
+        assert!(!policy.permits(ip("10.20.0.66")));
+        assert!(!policy.permits(ip("162.159.200.1")));
+    }
+
+    #[test]
+    fn test_address_policy_refuses_poisoned_answers() {
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        let port = server.rsplit(':').next().unwrap();
+        // The name resolves to an address outside the allowed ranges
+        let dns: DnsStrategy = "static:ntp.corp=127.0.0.1".parse().unwrap();
+        let config = ClockConfig::new()
+            .with_servers(vec![format!("ntp.corp:{}", port)])
+            .with_dns(dns)
+            .with_address_policy(AddressPolicy {
+                allow: "10.0.0.0/8".parse().unwrap(),
+                deny: AddressSet::default(),
+            });
+        let clock = Clock::with_config(config.clone());
+        assert!(!clock.is_synchronized());
+
+        let policy = AddressPolicy {
+            allow: "10.0.0.0/8, loopback".parse().unwrap(),
+            deny: AddressSet::default(),
+        };
+        let clock = Clock::with_config(config.with_address_policy(policy));
+        assert!(clock.is_synchronized());
+    }
+
+    /// Answers DoH queries for `ntp.example` with 127.0.0.1 and no IPv6 addresses over
+    /// TLS with `pki`'s certificate, returning the resolver's URL
+    #[cfg(feature = "doh")]
+    fn spawn_doh_resolver(pki: &TestPki, queries: usize) -> String {
+        use rustls::pki_types::PrivateKeyDer;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                pki.chain.clone(),
+                PrivateKeyDer::Pkcs8(pki.key.clone().into()),
+            )
+            .unwrap();
+        let config = Arc::new(config);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("https://{}/dns-query", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for socket in listener.incoming().take(queries) {
+                let session = rustls::ServerConnection::new(Arc::clone(&config)).unwrap();
+                let mut stream = rustls::StreamOwned::new(session, socket.unwrap());
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                // The header, then a body as long as its Content-Length
+                let query = loop {
+                    let Ok(len) = stream.read(&mut buf) else {
+                        break None;
+                    };
+                    request.extend_from_slice(&buf[..len]);
+                    let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") else {
+                        continue;
+                    };
+                    let header = String::from_utf8_lossy(&request[..end]).to_lowercase();
+                    assert!(header.contains("content-type: application/dns-message"));
+                    let length: usize = header
+                        .split("content-length: ")
+                        .nth(1)
+                        .and_then(|rest| rest.lines().next()?.trim().parse().ok())
+                        .unwrap();
+                    if request.len() >= end + 4 + length {
+                        break Some(request[end + 4..].to_vec());
+                    }
+                };
+                // A client that rejected the certificate
+                let Some(query) = query else {
+                    continue;
+                };
+                let qtype = u16::from_be_bytes([query[query.len() - 4], query[query.len() - 3]]);
+                let mut answer = query.clone();
+                answer[2] |= 0x80;
+                if qtype == TYPE_A {
+                    answer[7] = 1;
+                    // The question's name, by pointer
+                    answer.extend_from_slice(&[0xc0, 12]);
+                    answer.extend_from_slice(&TYPE_A.to_be_bytes());
+                    answer.extend_from_slice(&CLASS_IN.to_be_bytes());
+                    answer.extend_from_slice(&60u32.to_be_bytes());
+                    answer.extend_from_slice(&4u16.to_be_bytes());
+                    answer.extend_from_slice(&Ipv4Addr::LOCALHOST.octets());
+                }
+                let header = format!(
+                    "HTTP/1.0 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+                    answer.len()
+                );
+                let _ = stream.write_all(&[header.as_bytes(), &answer].concat());
+                stream.conn.send_close_notify();
+                let _ = stream.flush();
+            }
+        });
+        url
+    }
+
+    #[cfg(feature = "doh")]
+    #[test]
+    fn test_doh_resolves_names_for_the_clock() {
+        let pki = TestPki::new("doh");
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        let port = server.rsplit(':').next().unwrap();
+        let url = spawn_doh_resolver(&pki, 2);
+        let dns = format!("doh:{} cafile {}", url, pki.ca_file.display());
+        let config = ClockConfig::new()
+            .with_servers(vec![format!("ntp.example:{}", port)])
+            .with_dns(dns.parse().unwrap());
+        let clock = Clock::with_config(config);
+        assert!(clock.is_synchronized());
+        std::fs::remove_dir_all(&pki.dir).unwrap();
+    }
+
+    #[cfg(feature = "doh")]
+    #[test]
+    fn test_doh_resolver_must_be_trusted() {
+        let pki = TestPki::new("doh-trust");
+        let url = spawn_doh_resolver(&pki, 8);
+        let resolve = |options: String| {
+            format!("doh:{} {}", url, options)
+                .parse::<DnsStrategy>()
+                .unwrap()
+                .resolve("ntp.example", 123)
+        };
+        let pin = |hash: &[u8; 32]| format!("pin {}", server::format_pin(hash));
+
+        // Trusted by its CA, and pinned by its own key or the CA's
+        let ca_file = format!("cafile {}", pki.ca_file.display());
+        let trusted = resolve(ca_file.clone()).unwrap();
+        assert_eq!(trusted, ["127.0.0.1:123".parse().unwrap()]);
+        assert!(resolve(format!("{} {}", ca_file, pin(&pki.leaf_spki_sha256))).is_ok());
+        assert!(resolve(format!("{} {}", ca_file, pin(&pki.ca_spki_sha256))).is_ok());
+        assert!(resolve(format!("{} {}", ca_file, pin(&[0; 32]))).is_err());
+        // A pin does not make up for a certificate that does not chain to a trusted root
+        assert!(resolve(pin(&pki.leaf_spki_sha256)).is_err());
+        std::fs::remove_dir_all(&pki.dir).unwrap();
+    }
+
+    #[test]
+    fn test_static_map_keeps_unknown_names_unresolved() {
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        let port = server.rsplit(':').next().unwrap();
+        let dns: DnsStrategy = "static:ntp.plant=127.0.0.1".parse().unwrap();
+        let config = ClockConfig::new()
+            .with_servers(vec![
+                format!("localhost:{}", port),
+                format!("ntp.plant:{}", port),
+            ])
+            .with_dns(dns);
+        let clock = Clock::with_config(config);
+        assert!(clock.is_synchronized());
+        assert_eq!(clock.get_stats().failed_syncs, 0);
+        assert_eq!(
+            clock.reference().map(|r| r.server),
+            Some(format!("ntp.plant:{}", port))
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! # DNS Messages
+//!
+//! The parts of the DNS wire format (RFC 1035) that [`mdns`](crate::mdns) discovery and
+//! DNS-over-HTTPS in [`dns`](crate::dns) share: names, with compression on reading,
+//! questions, and the records they look at.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub(crate) const TYPE_A: u16 = 1;
+pub(crate) const TYPE_PTR: u16 = 12;
+pub(crate) const TYPE_TXT: u16 = 16;
+pub(crate) const TYPE_AAAA: u16 = 28;
+pub(crate) const TYPE_SRV: u16 = 33;
+pub(crate) const TYPE_ANY: u16 = 255;
+pub(crate) const CLASS_IN: u16 = 1;
+/// Set on a question's class to ask for a unicast answer, and on a record's class to mark
+/// it as the only one of its name and type (mDNS)
+pub(crate) const CLASS_TOP_BIT: u16 = 0x8000;
+
+/// A question of a DNS message
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Question {
+    pub(crate) name: String,
+    pub(crate) qtype: u16,
+    /// The querier asked for a unicast answer (mDNS)
+    pub(crate) unicast: bool,
+}
+
+/// The data of the record types mDNS discovery and DNS-over-HTTPS use
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum RecordData {
+    Ptr(String),
+    Srv { port: u16, target: String },
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Other,
+}
+
+/// A resource record of a DNS message
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Record {
+    pub(crate) name: String,
+    pub(crate) data: RecordData,
+}
+
+/// A parsed DNS message
+#[derive(Debug)]
+pub(crate) struct Message {
+    pub(crate) id: u16,
+    pub(crate) is_response: bool,
+    /// Response code, 0 for no error
+    pub(crate) rcode: u8,
+    pub(crate) questions: Vec<Question>,
+    /// Answer, authority, and additional records alike
+    pub(crate) records: Vec<Record>,
+}
+
+/// Appends `name` as uncompressed labels
+pub(crate) fn encode_name(msg: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label);
+    }
+    msg.push(0);
+}
+
+/// Reads the possibly compressed name at `pos`, returning it and the position after it
+fn read_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bounds the pointers followed, so that a pointer loop cannot hang the parser
+    for _ in 0..128 {
+        let len = *msg.get(pos)? as usize;
+        match len {
+            0 => {
+                let name = labels.join(".");
+                return Some((name, end.unwrap_or(pos + 1)));
+            }
+            l if l & 0xC0 == 0xC0 => {
+                let target = (l & 0x3F) << 8 | *msg.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                pos = target;
+            }
+            l if l < 64 => {
+                let label = msg.get(pos + 1..pos + 1 + l)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + l;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(msg.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+/// Parses a DNS message, or `None` if it is malformed
+pub(crate) fn parse(msg: &[u8]) -> Option<Message> {
+    let id = read_u16(msg, 0)?;
+    let flags = read_u16(msg, 2)?;
+    let counts: Vec<u16> = (0..4)
+        .map(|i| read_u16(msg, 4 + 2 * i))
+        .collect::<Option<_>>()?;
+    let mut pos = 12;
+
+    let mut questions = Vec::new();
+    for _ in 0..counts[0] {
+        let (name, next) = read_name(msg, pos)?;
+        let qtype = read_u16(msg, next)?;
+        let class = read_u16(msg, next + 2)?;
+        questions.push(Question {
+            name,
+            qtype,
+            unicast: class & CLASS_TOP_BIT != 0,
+        });
+        pos = next + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..counts[1..].iter().map(|&c| usize::from(c)).sum::<usize>() {
+        let (name, next) = read_name(msg, pos)?;
+        let rtype = read_u16(msg, next)?;
+        let len = usize::from(read_u16(msg, next + 8)?);
+        let start = next + 10;
+        let rdata = msg.get(start..start + len)?;
+        let data = match rtype {
+            TYPE_PTR => RecordData::Ptr(read_name(msg, start)?.0),
+            TYPE_SRV => RecordData::Srv {
+                port: read_u16(msg, start + 4)?,
+                target: read_name(msg, start + 6)?.0,
+            },
+            TYPE_A => RecordData::A(<[u8; 4]>::try_from(rdata).ok()?.into()),
+            TYPE_AAAA => RecordData::Aaaa(<[u8; 16]>::try_from(rdata).ok()?.into()),
+            _ => RecordData::Other,
+        };
+        records.push(Record { name, data });
+        pos = start + len;
+    }
+
+    Some(Message {
+        id,
+        is_response: flags & 0x8000 != 0,
+        rcode: (flags & 0x000f) as u8,
+        questions,
+        records,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_follows_compressed_names() {
+        // PTR _ntp._udp.local -> a._ntp._udp.local, with the target compressed
+        let mut msg = vec![0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0];
+        encode_name(&mut msg, "_ntp._udp.local");
+        msg.extend_from_slice(&[0, 12, 0, 1, 0, 0, 0, 120, 0, 4, 1, b'a', 0xC0, 12]);
+        let message = parse(&msg).unwrap();
+        assert_eq!(
+            message.records[0].data,
+            RecordData::Ptr("a._ntp._udp.local".to_string())
+        );
+
+        // A pointer to itself is rejected rather than followed forever
+        let mut looped = vec![0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0];
+        looped.extend_from_slice(&[0xC0, 12]);
+        assert!(parse(&looped).is_none());
+        assert!(parse(&msg[..msg.len() - 3]).is_none());
+    }
+}
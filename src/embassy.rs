@@ -0,0 +1,262 @@
+//! # Embassy Transport
+//!
+//! An [`AsyncTransport`] for embassy firmware (ESP32, RP2040, ...) in place of hand-rolled
+//! SNTP. Requests go out over a UDP socket; replies are bounded by an `embassy-time` timeout
+//! and timed with its monotonic `Instant`. [`sync_loop`] repeats the query on an
+//! `embassy-time` timer.
+//!
+//! An embassy-net [`UdpSocket`] bound to a local port is a [`Datagram`] as it is:
+//!
+//! ```ignore
+//! use embassy_net::udp::{PacketMetadata, UdpSocket};
+//! use embassy_net::{IpEndpoint, Ipv4Address};
+//!
+//! let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+//! socket.bind(0).unwrap();
+//! let server = IpEndpoint::new(Ipv4Address::new(192, 0, 2, 1).into(), 123);
+//!
+//! let mut transport = EmbassyTransport::new(socket, Duration::from_secs(3));
+//! let nonce = || rng.next_u64().to_be_bytes();
+//! clock::embassy::sync_loop(&mut transport, &server, Duration::from_secs(64), nonce, |result| {
+//!     if let Ok(sample) = result {
+//!         rtc.set(sample.time);
+//!     }
+//! })
+//! .await
+//! ```
+//!
+//! Other network stacks plug in with their own [`Datagram`] impl.
+
+use crate::sntp::{self, AsyncTransport, Measurement, QueryError, PACKET_LEN};
+use core::fmt;
+use embassy_net::udp::{RecvError, SendError, UdpSocket};
+use embassy_net::IpEndpoint;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+/// An unconnected UDP socket, such as embassy-net's [`UdpSocket`]
+#[allow(async_fn_in_trait)]
+pub trait Datagram {
+    /// Remote address type
+    type Endpoint: PartialEq;
+    /// Error returned by the socket
+    type Error;
+
+    /// Sends one datagram to `to`
+    async fn send_to(&mut self, buf: &[u8], to: &Self::Endpoint) -> Result<(), Self::Error>;
+
+    /// Receives one datagram into `buf`, returning its length and sender
+    async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, Self::Endpoint), Self::Error>;
+}
+
+/// Why an embassy-net [`UdpSocket`] failed to send or receive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpError {
+    Send(SendError),
+    Recv(RecvError),
+}
+
+impl Datagram for UdpSocket<'_> {
+    type Endpoint = IpEndpoint;
+    type Error = UdpError;
+
+    async fn send_to(&mut self, buf: &[u8], to: &IpEndpoint) -> Result<(), UdpError> {
+        UdpSocket::send_to(self, buf, *to)
+            .await
+            .map_err(UdpError::Send)
+    }
+
+    async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), UdpError> {
+        let (len, meta) = UdpSocket::recv_from(self, buf)
+            .await
+            .map_err(UdpError::Recv)?;
+        Ok((len, meta.endpoint))
+    }
+}
+
+/// Why an [`EmbassyTransport`] exchange failed
+#[derive(Debug, PartialEq, Eq)]
+pub enum EmbassyError<E> {
+    /// The socket reported an error
+    Socket(E),
+    /// No reply arrived from the server within the timeout
+    Timeout,
+    /// The reply was shorter than an NTP packet
+    ShortReply(usize),
+}
+
+impl<E: fmt::Debug> fmt::Display for EmbassyError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbassyError::Socket(e) => write!(f, "socket error: {:?}", e),
+            EmbassyError::Timeout => write!(f, "timed out waiting for a reply"),
+            EmbassyError::ShortReply(len) => write!(f, "short NTP reply of {} bytes", len),
+        }
+    }
+}
+
+/// SNTP over a [`Datagram`] socket with `embassy-time` timeouts
+pub struct EmbassyTransport<S> {
+    socket: S,
+    timeout: Duration,
+}
+
+impl<S> EmbassyTransport<S> {
+    /// Creates a transport that gives up on a server after `timeout`
+    pub fn new(socket: S, timeout: Duration) -> Self {
+        EmbassyTransport { socket, timeout }
+    }
+
+    /// Returns the socket
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+}
+
+impl<S: Datagram> AsyncTransport for EmbassyTransport<S> {
+    type Address = S::Endpoint;
+    type Error = EmbassyError<S::Error>;
+
+    async fn exchange(
+        &mut self,
+        server: &S::Endpoint,
+        request: &[u8; PACKET_LEN],
+        reply: &mut [u8; PACKET_LEN],
+    ) -> Result<core::time::Duration, Self::Error> {
+        let socket = &mut self.socket;
+        let sent_at = Instant::now();
+        socket
+            .send_to(request, server)
+            .await
+            .map_err(EmbassyError::Socket)?;
+
+        let receive = async {
+            loop {
+                let (len, from) = socket.recv_from(reply).await?;
+                // The socket may be shared with other traffic; only the server's reply counts
+                if from == *server {
+                    return Ok(len);
+                }
+            }
+        };
+        let len = with_timeout(self.timeout, receive)
+            .await
+            .map_err(|_| EmbassyError::Timeout)?
+            .map_err(EmbassyError::Socket)?;
+        let delay = sent_at.elapsed();
+        if len < PACKET_LEN {
+            return Err(EmbassyError::ShortReply(len));
+        }
+        Ok(core::time::Duration::from_micros(delay.as_micros()))
+    }
+}
+
+/// Queries `server` every `interval`, forever, passing each result to `on_result`. Each
+/// request carries a fresh `nonce()`, which should come from a hardware RNG.
+pub async fn sync_loop<T: AsyncTransport>(
+    transport: &mut T,
+    server: &T::Address,
+    interval: Duration,
+    mut nonce: impl FnMut() -> [u8; 8],
+    mut on_result: impl FnMut(Result<Measurement, QueryError<T::Error>>),
+) -> ! {
+    loop {
+        on_result(sntp::query_async(transport, server, nonce()).await);
+        Timer::after(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_TIMESTAMP;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use std::collections::VecDeque;
+
+    /// Polls `future` to completion, spinning while it waits on timers
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Delivers queued datagrams, then never receives anything again
+    struct FakeSocket {
+        inbox: VecDeque<(Vec<u8>, u8)>,
+        sent: Vec<u8>,
+    }
+
+    impl Datagram for FakeSocket {
+        type Endpoint = u8;
+        type Error = ();
+
+        async fn send_to(&mut self, _buf: &[u8], to: &u8) -> Result<(), ()> {
+            self.sent.push(*to);
+            Ok(())
+        }
+
+        async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, u8), ()> {
+            match self.inbox.pop_front() {
+                Some((datagram, from)) => {
+                    let len = datagram.len().min(buf.len());
+                    buf[..len].copy_from_slice(&datagram[..len]);
+                    Ok((len, from))
+                }
+                None => core::future::pending().await,
+            }
+        }
+    }
+
+    const NONCE: [u8; 8] = [7; 8];
+
+    fn server_reply() -> Vec<u8> {
+        let mut reply = vec![0u8; PACKET_LEN];
+        reply[0] = 4 << 3 | 4;
+        reply[1] = 2;
+        reply[24..32].copy_from_slice(&NONCE);
+        reply[40..44].copy_from_slice(&3_155_673_600u32.to_be_bytes());
+        reply
+    }
+
+    #[test]
+    fn test_exchange_ignores_other_senders() {
+        let socket = FakeSocket {
+            inbox: VecDeque::from([(vec![0xff; PACKET_LEN], 9), (server_reply(), 1)]),
+            sent: Vec::new(),
+        };
+        let mut transport = EmbassyTransport::new(socket, Duration::from_secs(1));
+
+        let sample = block_on(sntp::query_async(&mut transport, &1, NONCE)).unwrap();
+        assert_eq!(transport.into_inner().sent, vec![1]);
+        assert!(sample.time >= DEFAULT_TIMESTAMP);
+        assert!(sample.delay < core::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_exchange_times_out_and_rejects_short_replies() {
+        let socket = FakeSocket {
+            inbox: VecDeque::new(),
+            sent: Vec::new(),
+        };
+        let mut transport = EmbassyTransport::new(socket, Duration::from_millis(20));
+        assert!(matches!(
+            block_on(sntp::query_async(&mut transport, &1, NONCE)),
+            Err(QueryError::Transport(EmbassyError::Timeout))
+        ));
+
+        let socket = FakeSocket {
+            inbox: VecDeque::from([(vec![0; 12], 1)]),
+            sent: Vec::new(),
+        };
+        let mut transport = EmbassyTransport::new(socket, Duration::from_secs(1));
+        assert!(matches!(
+            block_on(sntp::query_async(&mut transport, &1, NONCE)),
+            Err(QueryError::Transport(EmbassyError::ShortReply(12)))
+        ));
+    }
+}
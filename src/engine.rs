@@ -0,0 +1,3124 @@
+//! # Sync Engine
+//!
+//! The state shared by a [`Clock`](crate::Clock), its [`ClockHandle`](crate::ClockHandle)s,
+//! and the background worker, along with the NTP client and sync logic that updates it.
+
+use crate::alert::{Alert, AlertKind, Alerter};
+use crate::anomaly::{self, Anomaly, AnomalyHook, AnomalyKind};
+use crate::audit::{AdjustmentKind, AuditLog};
+use crate::auth::SharedKeyStore;
+use crate::callbacks::{call_guarded, OffsetBreach, OffsetWatch, Registry, Step, StepListener};
+use crate::config::{ConfigChange, FallbackPolicy};
+use crate::dns::{AddressPolicy, DnsStrategy};
+#[cfg(any(unix, windows))]
+use crate::elapsed::BootTimeSource;
+use crate::events::{ClockEvent, EventBus};
+use crate::health::{self, Health, DEFAULT_STALENESS_FACTOR};
+use crate::history::{SyncRecord, MAX_SYNC_HISTORY};
+use crate::lock::{self, MutexExt, RwLockExt};
+use crate::logging::clock_log;
+use crate::netwatch::NetworkWatcher;
+#[cfg(feature = "nts")]
+use crate::nts::{CookieJar, LocalKey, NtsRequest, ReplyError};
+#[cfg(feature = "nts")]
+use crate::ntske;
+use crate::persist::{self, PersistedState};
+use crate::rtc::{self, RtcCheck, RtcPolicy, RtcWrite};
+use crate::server::{self, Protocol, ServerSpec};
+#[cfg(feature = "simulation")]
+use crate::simulation::{self, VirtualTimeline};
+use crate::smoothing::{Correction, SmoothingFilter};
+use crate::sntp::{
+    self, DelayFilter, DelayLimits, InterleavedState, Measurement, ServerState, SourceEstimate,
+    Transport, UdpTransport, MAX_STRATUM, PACKET_LEN,
+};
+use crate::stability::{self, OffsetSample, StabilityPoint};
+use crate::stats::RollingCounts;
+use crate::statsfile::{self, LoopRecord, PeerRecord, StatsLogger};
+use crate::statusfile;
+use crate::timeline::{SyncAttempt, MAX_RECENT_ATTEMPTS};
+use crate::transport::{DynTransport, TransportFactory};
+use crate::validity::{self, ValidityStatus};
+use crate::{manycast, mdns, peer};
+use crate::{
+    ClockConfig, ClockError, ClockSnapshot, ClockState, ElapsedSource, MonotonicSource, NtpSample,
+    ReferenceInfo, SourceWeight, SuspendDetector, SyncStats, TimeSource, Timestamp, BURST_ATTEMPTS,
+    BURST_SPACING, DEFAULT_TIMESTAMP, MAX_OFFSET_HISTORY,
+};
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(feature = "nts")]
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Reference point that the current time is extrapolated from
+#[derive(Debug, Clone)]
+pub(crate) struct TimeBase {
+    pub(crate) latest_time_ntp: Option<Timestamp>,
+    pub(crate) latest_time: Timestamp,
+    pub(crate) latest_instant: Instant,
+    pub(crate) source: TimeSource,
+    base_reading: Duration,
+    elapsed_source: Arc<dyn ElapsedSource>,
+    /// Applied by a [`SmoothingFilter`] on top of the extrapolated time
+    correction: Correction,
+    /// Frequency error elapsed time is corrected for, in orphan mode
+    drift_ppm: f64,
+}
+
+impl TimeBase {
+    fn new(
+        latest_time_ntp: Option<Timestamp>,
+        fallback: impl FnOnce() -> (Timestamp, TimeSource),
+    ) -> Self {
+        let elapsed_source: Arc<dyn ElapsedSource> = Arc::new(MonotonicSource::new());
+        let (latest_time, source) = match latest_time_ntp {
+            Some(time) => (time, TimeSource::Ntp),
+            None => fallback(),
+        };
+        TimeBase {
+            latest_time_ntp,
+            latest_time,
+            latest_instant: Instant::now(),
+            source,
+            base_reading: elapsed_source.now(),
+            elapsed_source,
+            correction: Correction::default(),
+            drift_ppm: 0.0,
+        }
+    }
+
+    /// Returns the duration elapsed since the last sync
+    fn elapsed(&self) -> Duration {
+        let raw = self.elapsed_source.now().saturating_sub(self.base_reading);
+        if self.drift_ppm == 0.0 {
+            raw
+        } else {
+            sntp::correct_for_drift(raw, self.drift_ppm)
+        }
+    }
+
+    pub(crate) fn now(&self) -> Timestamp {
+        let elapsed = self.elapsed();
+        let correction = self.correction.at(elapsed);
+        (self.latest_time + elapsed).add_nanos((correction * 1e9) as i128)
+    }
+
+    /// The time extrapolated from the last step, without the smoothing correction
+    fn uncorrected_now(&self) -> Timestamp {
+        self.latest_time + self.elapsed()
+    }
+
+    /// Jumps to `time`, dropping any smoothing correction
+    fn step_to(&mut self, time: Timestamp) {
+        self.latest_time = time;
+        self.correction = Correction::default();
+        self.mark_sync_point();
+    }
+
+    /// Applies a measured offset (NTP time minus [`uncorrected_now`](Self::uncorrected_now))
+    /// through `filter`, slewing at no more than `max_slew_ppm`
+    fn smooth(&mut self, filter: &SmoothingFilter, offset: f64, max_slew_ppm: f64) {
+        let elapsed = self.elapsed();
+        self.correction
+            .update(filter, offset, elapsed, max_slew_ppm);
+    }
+
+    /// Slows a slew in progress down to `max_slew_ppm`, keeping the current time
+    fn cap_slew(&mut self, max_slew_ppm: f64) {
+        let elapsed = self.elapsed();
+        self.correction = self.correction.capped(elapsed, max_slew_ppm);
+    }
+
+    /// Records "now" as the reference point that elapsed time is measured from
+    fn mark_sync_point(&mut self) {
+        self.latest_instant = Instant::now();
+        self.base_reading = self.elapsed_source.now();
+    }
+
+    /// Makes "now" the reference point without changing the time, carrying the correction
+    /// over, so that how elapsed time is measured can change
+    fn rebase(&mut self) {
+        let elapsed = self.elapsed();
+        self.latest_time = self.latest_time + elapsed;
+        self.correction = self.correction.rebased(elapsed);
+        self.mark_sync_point();
+    }
+
+    /// Measures elapsed time with `source` from now on, carrying the current time and
+    /// correction over
+    fn set_elapsed_source(&mut self, source: Arc<dyn ElapsedSource>) {
+        self.rebase();
+        self.elapsed_source = source;
+        self.mark_sync_point();
+    }
+
+    /// Measures elapsed time with `source` from now on, as if the last sync had happened at
+    /// its current reading
+    fn restart_elapsed_source(&mut self, source: Arc<dyn ElapsedSource>) {
+        self.elapsed_source = source;
+        self.mark_sync_point();
+    }
+
+    /// Corrects elapsed time for a frequency error of `drift_ppm` from now on
+    fn set_drift(&mut self, drift_ppm: f64) {
+        self.rebase();
+        self.drift_ppm = drift_ppm;
+    }
+
+    pub(crate) fn state(&self) -> ClockState {
+        if self.latest_time_ntp.is_some() {
+            ClockState::Synchronized
+        } else {
+            ClockState::Unsynchronized
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> ClockSnapshot {
+        let correction = self.correction.at(self.elapsed());
+        ClockSnapshot {
+            base_time: self.latest_time.add_nanos((correction * 1e9) as i128),
+            base_instant: self.latest_instant,
+            state: self.state(),
+            source: self.source,
+        }
+    }
+}
+
+/// Rate at which the error bound of a sample grows as it ages, in seconds per second (the
+/// 15 PPM frequency tolerance RFC 5905 calls PHI)
+pub(crate) const DISPERSION_RATE: f64 = 15e-6;
+
+/// When the last NTP sample was obtained and how far off it could have been at the time
+#[derive(Debug, Clone, Copy)]
+struct LastSync {
+    at: Instant,
+    /// Half the root delay plus the server's root dispersion, in seconds: the root distance
+    /// of RFC 5905
+    error_bound: f64,
+    /// The server's root delay plus the round trip to it, in seconds
+    root_delay: f64,
+    /// Stratum of the server the sample came from
+    stratum: u8,
+    /// The server's IPv4 address, reported to peers as this clock's reference ID so that
+    /// they can detect timing loops
+    reference_id: [u8; 4],
+}
+
+impl LastSync {
+    fn new(sample: &NtpSample, at: Instant) -> Self {
+        let root_delay = sample.root_delay + sample.delay.as_secs_f64();
+        LastSync {
+            at,
+            error_bound: root_delay / 2.0 + sample.root_dispersion,
+            root_delay,
+            stratum: sample.stratum,
+            reference_id: match sample.addr.ip() {
+                IpAddr::V4(ip) => ip.octets(),
+                IpAddr::V6(_) => [0; 4],
+            },
+        }
+    }
+}
+
+/// Longest uninterrupted wait in the worker, bounding how long it takes to notice the
+/// caller's shutdown flag or a system suspend
+const TICK: Duration = Duration::from_secs(1);
+
+/// Orphan mode settings and state, see [`ClockConfig::orphan_after`]
+#[derive(Debug)]
+struct Orphan {
+    after: Option<Duration>,
+    stratum: u8,
+    /// When orphan mode was entered, `None` outside it
+    since: Option<Instant>,
+}
+
+/// Settings that decide which NTP samples are accepted and how they are combined
+#[derive(Debug, Clone)]
+struct PollSettings {
+    /// NTP samples earlier than this are rejected
+    floor: Option<Timestamp>,
+    /// NTP samples later than this are rejected as implausible
+    ceiling: Option<Timestamp>,
+    /// Samples with a longer round trip than these limits are rejected
+    delay_limits: DelayLimits,
+    samples_per_poll: u32,
+    combine_sources: bool,
+    preferred: Vec<String>,
+    noselect: Vec<String>,
+    /// Honor kiss-o'-death replies, space queries out, and rotate pool addresses
+    best_practices: bool,
+    max_queries_per_minute: Option<u32>,
+    source_ports: RangeInclusive<u16>,
+    dscp: Option<u8>,
+    ttl: Option<u32>,
+    /// Replaces the UDP transport, along with the three settings above
+    transport: Option<TransportFactory>,
+    dns: DnsStrategy,
+    address_policy: AddressPolicy,
+    /// Where this clock answers NTP queries, if it does; servers that synchronize to it
+    /// are then taken out of selection as timing loops
+    serving: Option<SocketAddr>,
+    /// Signs requests to servers with a `key` and verifies their replies
+    keys: Option<SharedKeyStore>,
+    /// Sessions with NTS servers
+    #[cfg(feature = "nts")]
+    nts: NtsState,
+}
+
+impl PollSettings {
+    fn new(config: &ClockConfig) -> Self {
+        PollSettings {
+            floor: config.time_floor(),
+            ceiling: config.max_time,
+            delay_limits: config.delay_limits(),
+            samples_per_poll: config.samples_per_poll,
+            combine_sources: config.combine_sources,
+            preferred: names(&config.preferred_servers),
+            noselect: names(&config.noselect_servers),
+            best_practices: config.best_practices,
+            max_queries_per_minute: config.max_queries_per_minute,
+            source_ports: config.source_ports.clone(),
+            dscp: config.dscp,
+            ttl: config.ttl,
+            transport: config.transport.clone(),
+            dns: config.dns.clone(),
+            address_policy: config.address_policy.clone(),
+            serving: config.peer_listen,
+            keys: config.keys.clone(),
+            #[cfg(feature = "nts")]
+            nts: NtsState::new(config.nts_cookies.as_deref()),
+        }
+    }
+}
+
+/// The NTS sessions of a clock, shared by the polls and kept across restarts if
+/// [`ClockConfig::nts_cookies`] is set
+#[cfg(feature = "nts")]
+#[derive(Debug, Clone)]
+struct NtsState {
+    jar: Arc<Mutex<CookieJar>>,
+    /// Where the jar is saved, and the key it is encrypted with
+    storage: Option<(PathBuf, LocalKey)>,
+}
+
+#[cfg(feature = "nts")]
+impl NtsState {
+    /// Loads the jar saved at `path`, starting empty if there is none or it cannot be read
+    fn new(path: Option<&Path>) -> Self {
+        let storage = path.and_then(|path| {
+            let mut key_path = path.as_os_str().to_owned();
+            key_path.push(".key");
+            match LocalKey::load_or_create(PathBuf::from(key_path)) {
+                Ok(key) => Some((path.to_path_buf(), key)),
+                Err(e) => {
+                    clock_log!(
+                        Warn,
+                        Config,
+                        "Not keeping NTS cookies in {}: {}",
+                        path.display(),
+                        e
+                    );
+                    None
+                }
+            }
+        });
+        let jar = match &storage {
+            Some((path, key)) => CookieJar::load(path, key).unwrap_or_else(|e| {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    clock_log!(
+                        Warn,
+                        Config,
+                        "Discarding the NTS cookies in {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+                CookieJar::new()
+            }),
+            None => CookieJar::new(),
+        };
+        NtsState {
+            jar: Arc::new(Mutex::new(jar)),
+            storage,
+        }
+    }
+
+    fn path(&self) -> Option<&Path> {
+        self.storage.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    fn save(&self, jar: &CookieJar) {
+        if let Some((path, key)) = &self.storage {
+            if let Err(e) = jar.save(path, key) {
+                clock_log!(
+                    Warn,
+                    Server,
+                    "Failed to save NTS cookies to {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// The NTP server of the session with `spec`, whose key establishment server is at
+    /// `ke_addr`, renewing the session first if needed. The jar stays locked during a key
+    /// exchange, so that concurrent polls do not run one each.
+    fn ntp_server(&self, spec: &ServerSpec, ke_addr: SocketAddr) -> std::io::Result<(String, u16)> {
+        let mut jar = self.jar.lock_or_recover();
+        let session = jar.renew(&spec.name(), |_| ntske::key_exchange(spec, ke_addr))?;
+        let host = session
+            .ntp_server
+            .clone()
+            .unwrap_or_else(|| spec.host.clone());
+        let port = session.ntp_port.unwrap_or(server::NTP_PORT);
+        self.save(&jar);
+        Ok((host, port))
+    }
+
+    /// Protects `request` to `server` with its session, spending a cookie
+    fn seal_request(
+        &self,
+        server: &str,
+        request: &[u8; PACKET_LEN],
+    ) -> std::io::Result<NtsRequest> {
+        let mut jar = self.jar.lock_or_recover();
+        let session = jar
+            .get_mut(server)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no NTS session"))?;
+        let sealed = session.seal_request(request)?;
+        self.save(&jar);
+        Ok(sealed)
+    }
+
+    /// Checks a reply from `server` to `request`, storing its cookies. A NAK ends the
+    /// session, so that the next poll renews it by key exchange.
+    fn open_reply(
+        &self,
+        server: &str,
+        request: &NtsRequest,
+        reply: &[u8],
+    ) -> Result<(), ReplyError> {
+        let mut jar = self.jar.lock_or_recover();
+        let opened = match jar.get_mut(server) {
+            Some(session) => session.open_reply(request, reply),
+            None => Err(ReplyError::NotOurs),
+        };
+        match opened {
+            Ok(()) => self.save(&jar),
+            Err(ReplyError::Nak) => {
+                jar.remove(server);
+                self.save(&jar);
+            }
+            Err(_) => {}
+        }
+        opened
+    }
+}
+
+/// The [`ServerSpec::name`]s of `servers`, which is how servers are compared
+fn names(servers: &[String]) -> Vec<String> {
+    servers.iter().map(|s| server::canonical_name(s)).collect()
+}
+
+/// What is remembered about a server between polls
+#[derive(Debug, Default)]
+struct SourceState {
+    /// Recent round-trip delays, for the limit relative to the minimum
+    delay_filter: DelayFilter,
+    /// Stratum of the server's last accepted sample, for ranking the servers
+    stratum: Option<u8>,
+    /// When the server was queried within the last minute, for the query budget and for
+    /// spacing queries out under the best-practices profile
+    recent_queries: VecDeque<Instant>,
+    /// The server sent a kiss-o'-death reply and must not be queried before this
+    held_off_until: Option<Instant>,
+    /// Which of the server's addresses to query next, for rotating through a pool
+    rotation: usize,
+    /// The server synchronizes to this host while it serves time, so it is only monitored
+    /// until it stops
+    in_loop: bool,
+    /// The last exchange with an `xleave` server and when its reply arrived, to ask for
+    /// interleaved mode in the next
+    interleaved: Option<(InterleavedState, Instant)>,
+    /// Interleaved requests the `xleave` server answered in basic mode in a row
+    basic_replies: u32,
+}
+
+/// Window over which [`ClockConfig::max_queries_per_minute`] is enforced
+const QUERY_BUDGET_WINDOW: Duration = Duration::from_secs(60);
+
+/// Under the best-practices profile, each sync is delayed by a random part of the sync
+/// interval divided by this
+const POLL_JITTER_DIVISOR: u32 = 8;
+
+/// First retry after every source failed, with
+/// [`ClockConfig::offline_retry_max`] set; each further failure doubles the wait
+const OFFLINE_RETRY_START: Duration = Duration::from_secs(1);
+
+/// A random duration below `max`
+fn random_fraction(max: Duration) -> Duration {
+    max.mul_f64((sntp::random_u64() >> 11) as f64 / (1u64 << 53) as f64)
+}
+
+impl SourceState {
+    /// Records a query about to be sent, unless `budget` queries were already sent in the
+    /// last minute
+    fn take_query(&mut self, budget: Option<u32>) -> bool {
+        let now = Instant::now();
+        while let Some(&at) = self.recent_queries.front() {
+            if now.duration_since(at) < QUERY_BUDGET_WINDOW {
+                break;
+            }
+            self.recent_queries.pop_front();
+        }
+        if budget.is_some_and(|budget| self.recent_queries.len() >= budget as usize) {
+            return false;
+        }
+        self.recent_queries.push_back(now);
+        true
+    }
+}
+
+/// Runs `update` on what is remembered about `server`
+fn update_source_state<R>(
+    states: &Mutex<HashMap<String, SourceState>>,
+    server: &str,
+    update: impl FnOnce(&mut SourceState) -> R,
+) -> R {
+    update(
+        states
+            .lock_or_recover()
+            .entry(server.to_string())
+            .or_default(),
+    )
+}
+
+/// A server's accepted measurement within one poll
+struct Candidate {
+    server: String,
+    addr: SocketAddr,
+    measurement: Measurement,
+    /// Spread of the server's samples in this poll, in seconds
+    jitter: f64,
+    /// When the measurement completed, relative to the start of the poll
+    taken: Duration,
+    /// False for `noselect` servers, which are only monitored
+    selectable: bool,
+}
+
+/// Pause between the requests of a poll that takes several samples
+const SAMPLE_SPACING: Duration = Duration::from_millis(200);
+
+/// Samples taken per poll from an `iburst` server until it first answers, so that its
+/// first offset is not a single noisy measurement
+const IBURST_SAMPLES: u32 = 4;
+
+/// Shortest time between two queries to one server under the best-practices profile, the
+/// minimum headway of RFC 5905
+const BCP_MIN_HEADWAY: Duration = Duration::from_secs(2);
+
+/// Oldest exchange an interleaved one builds on. Its measurement is carried forward by the
+/// local clock, whose frequency error over a longer gap would outweigh what interleaving
+/// gains.
+const XLEAVE_MAX_AGE: Duration = Duration::from_secs(4);
+
+/// Interleaved requests an `xleave` server may answer in basic mode in a row before it is
+/// only sent basic ones
+const XLEAVE_BASIC_REPLIES: u32 = 3;
+
+/// How long a server that answered with a `RATE` kiss-o'-death is left alone: the longest
+/// standard poll interval
+const KOD_RATE_BACKOFF: Duration = Duration::from_secs(1024);
+
+/// How long a server that denied access with a `DENY` or `RSTR` kiss-o'-death is left
+/// alone
+const KOD_DENY_BACKOFF: Duration = Duration::from_secs(86_400);
+
+/// Shortest sync interval the worker accepts
+pub(crate) const MIN_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Worker scheduling state, guarded by `ClockShared::control`
+#[derive(Debug)]
+pub(crate) struct Control {
+    interval: Duration,
+    interval_changed: bool,
+    /// Set by the network watcher thread, cleared by the sync loop once it has resynced
+    network_changed: bool,
+    stop: bool,
+    /// Incremented whenever the watchdog replaces the sync loop; a loop exits once it no
+    /// longer matches
+    generation: u64,
+    /// When the sync loop last completed a cycle, `None` while no worker is running
+    pub(crate) heartbeat: Option<Instant>,
+}
+
+/// A [`ClockShared::resync_now`] running on its own thread, which any number of callers
+/// wait on, see [`ClockShared::resync_with_deadline`]
+#[derive(Debug, Default)]
+struct InFlightSync {
+    /// Whether the sync obtained a sample, once it has finished
+    synced: Mutex<Option<bool>>,
+    finished: Condvar,
+}
+
+/// Why a worker wait returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wake {
+    Stop,
+    IntervalChanged,
+    NetworkChanged,
+    Timeout,
+}
+
+/// The sync loop is considered stalled after this many intervals without completing a cycle
+const STALL_FACTOR: u32 = 3;
+
+/// Shortest time without a completed cycle that counts as a stall, so that slow polls of
+/// unreachable servers at short intervals are not mistaken for one
+const MIN_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Longest wait of the network watcher for a notification, bounding how long stopping the
+/// worker waits for it
+const NETWORK_WATCH_SLICE: Duration = Duration::from_millis(200);
+
+/// Longest wait in [`ClockShared::block_until_plausible_time`] before the uncertainty is
+/// checked again
+const PLAUSIBLE_TIME_RECHECK: Duration = Duration::from_millis(500);
+
+/// A running background sync thread, supervised by a watchdog thread
+pub(crate) struct Worker {
+    shared: Arc<ClockShared>,
+    watchdog: JoinHandle<()>,
+    network_watcher: JoinHandle<()>,
+}
+
+impl Worker {
+    /// Spawns the sync loop for `shared`, syncing every `interval`
+    pub(crate) fn spawn(
+        shared: Arc<ClockShared>,
+        interval: Duration,
+        shutdown: Arc<AtomicBool>,
+    ) -> Self {
+        {
+            let mut control = shared.control.lock_or_recover();
+            control.interval = interval.max(MIN_SYNC_INTERVAL);
+            control.interval_changed = false;
+            control.network_changed = false;
+            control.stop = false;
+            control.heartbeat = Some(Instant::now());
+        }
+        let watcher_shared = Arc::clone(&shared);
+        let watcher_shutdown = Arc::clone(&shutdown);
+        let network_watcher =
+            std::thread::spawn(move || watcher_shared.watch_network(&watcher_shutdown));
+        let watchdog_shared = Arc::clone(&shared);
+        let watchdog = std::thread::spawn(move || watchdog_shared.watch(&shutdown));
+        Worker {
+            shared,
+            watchdog,
+            network_watcher,
+        }
+    }
+
+    /// Signals the threads to stop and waits for them to exit
+    pub(crate) fn stop(self) {
+        self.shared.control.lock_or_recover().stop = true;
+        self.shared.wake.notify_all();
+        if self.watchdog.join().is_err() {
+            clock_log!(Error, Worker, "Background sync watchdog panicked");
+        }
+        if self.network_watcher.join().is_err() {
+            clock_log!(Error, Worker, "Network change watcher panicked");
+        }
+        self.shared.control.lock_or_recover().heartbeat = None;
+    }
+}
+
+/// The message a panic was raised with, if it was a string
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Time since the system booted, including suspend where the platform can measure it
+fn time_since_boot() -> Duration {
+    #[cfg(any(unix, windows))]
+    if let Ok(source) = BootTimeSource::new() {
+        return source.now();
+    }
+    Duration::ZERO
+}
+
+/// Records an adjustment of the clock from `before` to `after` in `log`, if one is open
+fn append_audit(
+    log: &mut Option<AuditLog>,
+    kind: AdjustmentKind,
+    before: Timestamp,
+    after: Timestamp,
+    sample: &NtpSample,
+) {
+    let Some(log) = log.as_mut() else {
+        return;
+    };
+    if let Err(e) = log.append(kind, before, after, sample) {
+        clock_log!(Error, Storage, "Failed to write audit record: {}", e);
+    }
+}
+
+/// Sleeps for `interval_secs` in one-second ticks, returning early once `shutdown` is set
+pub(crate) fn sleep_interval(interval_secs: u64, shutdown: &AtomicBool) {
+    let mut slept = 0;
+    while slept < interval_secs && !shutdown.load(Ordering::Relaxed) {
+        std::thread::sleep(TICK);
+        slept += 1;
+    }
+}
+
+/// State shared between a [`Clock`](crate::Clock), its handles, and the worker thread
+pub(crate) struct ClockShared {
+    pub(crate) ntp_servers: RwLock<Vec<String>>,
+    /// Other clock instances polled when no server is reachable
+    peers: RwLock<Vec<String>>,
+    pub(crate) fallback_policy: FallbackPolicy,
+    poll_settings: RwLock<PollSettings>,
+    /// What is remembered about each server between polls, including by initial sync queries
+    source_states: Arc<Mutex<HashMap<String, SourceState>>>,
+    /// How much each server contributed to the last successful sync
+    source_weights: Mutex<Vec<SourceWeight>>,
+    staleness_threshold: RwLock<Option<Duration>>,
+    anomaly_threshold: RwLock<Duration>,
+    anomaly_hooks: RwLock<Vec<AnomalyHook>>,
+    pub(crate) offset_watches: Registry<OffsetWatch>,
+    pub(crate) step_listeners: Registry<dyn StepListener>,
+    alerts: Alerter,
+    /// How measured offsets are applied to the reported time, if at all
+    smoothing: RwLock<Option<SmoothingFilter>>,
+    max_slew_ppm: RwLock<f64>,
+    resync_on_network_change: AtomicBool,
+    offline_retry_max: RwLock<Option<Duration>>,
+    orphan: Mutex<Orphan>,
+    /// The configuration last applied, for reporting what a reconfiguration changed
+    applied_config: Mutex<ClockConfig>,
+    /// Clients of the peer responder, for its rate limit
+    clients: Mutex<peer::ClientTable>,
+    /// Cross-check of NTP samples against the hardware RTC, see [`rtc`](crate::rtc)
+    rtc_check: RwLock<Option<RtcCheck>>,
+    /// Programming of the RTC with the disciplined time, and when it last was
+    rtc_write: RwLock<Option<RtcWrite>>,
+    last_rtc_write: Mutex<Option<Instant>>,
+    pub(crate) events: EventBus,
+    /// Replaces the monotonic clock in the sync loop and in ages, see [`simulation`]
+    #[cfg(feature = "simulation")]
+    timeline: RwLock<Option<VirtualTimeline>>,
+    last_sync: Mutex<Option<LastSync>>,
+    /// The server of the last sync and what it reported about itself
+    reference: Mutex<Option<ReferenceInfo>>,
+    pub(crate) base: RwLock<TimeBase>,
+    /// Last timestamp handed out by a [`Timestamper`](crate::Timestamper)
+    last_stamp: Mutex<Timestamp>,
+    pub(crate) stats: Mutex<SyncStats>,
+    pub(crate) rolling_stats: Mutex<RollingCounts>,
+    offset_history: Mutex<VecDeque<OffsetSample>>,
+    sync_history: Mutex<VecDeque<SyncRecord>>,
+    recent_attempts: Mutex<VecDeque<SyncAttempt>>,
+    stats_logger: Mutex<Option<StatsLogger>>,
+    status_file: Mutex<Option<std::path::PathBuf>>,
+    audit_log: Mutex<Option<AuditLog>>,
+    /// The background sync of a caller that gave up waiting on it, joined by later callers
+    in_flight_sync: Mutex<Option<Arc<InFlightSync>>>,
+    pub(crate) control: Mutex<Control>,
+    wake: Condvar,
+    /// Set once NTP time has been obtained; never held while taking another lock
+    synchronized: Mutex<bool>,
+    synchronized_cond: Condvar,
+    #[cfg(feature = "tokio")]
+    snapshots: tokio::sync::watch::Sender<ClockSnapshot>,
+}
+
+impl ClockShared {
+    /// Creates the shared state, fetching the initial time from NTP and giving up on it
+    /// after `deadline`, if one is set
+    pub(crate) fn new(config: ClockConfig, deadline: Option<Duration>) -> Self {
+        let config = config.effective();
+        let applied_config = config.clone();
+        let poll_settings = PollSettings::new(&config);
+        let time_floor = poll_settings.floor;
+        if let Some(floor) = time_floor {
+            clock_log!(Info, Sync, "Rejecting NTP time earlier than {}", floor);
+        }
+        if let Some(ceiling) = poll_settings.ceiling {
+            clock_log!(Info, Sync, "Rejecting NTP time later than {}", ceiling);
+        }
+        let servers = Self::with_discovered_servers(&config);
+        let interval = config.sync_interval.max(MIN_SYNC_INTERVAL);
+
+        clock_log!(
+            Info,
+            Sync,
+            "Initializing clock with NTP servers: {:?}",
+            servers
+        );
+
+        let alerts = Alerter::new(&config);
+        let mut audit_log = Self::open_audit_log(&config);
+        let source_states = Arc::new(Mutex::new(HashMap::new()));
+        // A zero deadline leaves the first sync to the sync loop
+        let skip_initial = deadline == Some(Duration::ZERO);
+        let initial = if skip_initial {
+            clock_log!(
+                Info,
+                Sync,
+                "Starting on unverified time until the first sync"
+            );
+            Err("initial sync skipped".into())
+        } else {
+            Self::initial_ntp_time(
+                config.race_initial_sync,
+                &servers,
+                &poll_settings,
+                &source_states,
+                deadline,
+            )
+        };
+        // Nothing can have subscribed to events yet, so a conflict found now is only logged
+        // and audited
+        let rtc_check = config.rtc_check();
+        let rtc_write = config.rtc_write();
+        let initial = initial.and_then(|(sample, weights)| {
+            let conflict = rtc_check
+                .as_ref()
+                .and_then(|check| Some((check.policy, Self::rtc_conflict(check, &sample)?)));
+            match conflict {
+                Some((policy, rtc)) => {
+                    append_audit(
+                        &mut audit_log,
+                        AdjustmentKind::RtcConflict,
+                        sample.time,
+                        rtc,
+                        &sample,
+                    );
+                    Ok((Self::resolve_rtc_conflict(policy, sample, rtc)?, weights))
+                }
+                None => Ok((sample, weights)),
+            }
+        });
+        let (initial_sample, source_weights) = match initial {
+            Ok((sample, weights)) => {
+                clock_log!(
+                    Info,
+                    Sync,
+                    "Successfully fetched initial NTP time: {}",
+                    sample.time
+                );
+                // The first sync steps the clock from the host's time to the server's
+                append_audit(
+                    &mut audit_log,
+                    AdjustmentKind::Step,
+                    Timestamp::now(),
+                    sample.time,
+                    &sample,
+                );
+                (Some(sample), weights)
+            }
+            Err(_) if skip_initial => (None, Vec::new()),
+            Err(e) => {
+                clock_log!(
+                    Error,
+                    Sync,
+                    "NTP fetch failed, falling back to unverified time: {}",
+                    e
+                );
+                (None, Vec::new())
+            }
+        };
+        let latest_time_ntp = initial_sample.as_ref().map(|sample| sample.time);
+
+        let fallback_policy = config.fallback_policy;
+        let base = TimeBase::new(latest_time_ntp, || {
+            let (time, source) = Self::fallback_time(&fallback_policy);
+            match time_floor {
+                Some(floor) if time < floor && source != TimeSource::Unavailable => (floor, source),
+                _ => (time, source),
+            }
+        });
+        #[cfg(feature = "tokio")]
+        let snapshots = tokio::sync::watch::Sender::new(base.snapshot());
+
+        ClockShared {
+            ntp_servers: RwLock::new(servers),
+            peers: RwLock::new(config.peers),
+            fallback_policy,
+            poll_settings: RwLock::new(poll_settings),
+            source_states,
+            source_weights: Mutex::new(source_weights),
+            staleness_threshold: RwLock::new(config.staleness_threshold),
+            anomaly_threshold: RwLock::new(config.anomaly_threshold),
+            anomaly_hooks: RwLock::new(config.anomaly_hooks),
+            offset_watches: Registry::default(),
+            step_listeners: Registry::default(),
+            alerts,
+            smoothing: RwLock::new(config.smoothing),
+            max_slew_ppm: RwLock::new(config.max_slew_ppm.max(0.0)),
+            resync_on_network_change: AtomicBool::new(config.resync_on_network_change),
+            offline_retry_max: RwLock::new(config.offline_retry_max),
+            orphan: Mutex::new(Orphan {
+                after: config.orphan_after,
+                stratum: config.orphan_stratum,
+                since: None,
+            }),
+            applied_config: Mutex::new(applied_config),
+            clients: Mutex::new(peer::ClientTable::new(config.rate_limit, config.mru_size)),
+            rtc_check: RwLock::new(rtc_check),
+            rtc_write: RwLock::new(rtc_write),
+            last_rtc_write: Mutex::new(None),
+            events: EventBus::default(),
+            #[cfg(feature = "simulation")]
+            timeline: RwLock::new(None),
+            last_sync: Mutex::new(
+                initial_sample
+                    .as_ref()
+                    .map(|sample| LastSync::new(sample, Instant::now())),
+            ),
+            reference: Mutex::new(initial_sample.as_ref().map(ReferenceInfo::from)),
+            base: RwLock::new(base),
+            last_stamp: Mutex::new(Timestamp::UNIX_EPOCH),
+            stats: Mutex::new(SyncStats::default()),
+            rolling_stats: Mutex::new(RollingCounts::new()),
+            offset_history: Mutex::new(VecDeque::with_capacity(MAX_OFFSET_HISTORY)),
+            sync_history: Mutex::new(VecDeque::new()),
+            recent_attempts: Mutex::new(VecDeque::new()),
+            stats_logger: Mutex::new(None),
+            status_file: Mutex::new(None),
+            audit_log: Mutex::new(audit_log),
+            in_flight_sync: Mutex::new(None),
+            control: Mutex::new(Control {
+                interval,
+                interval_changed: false,
+                network_changed: false,
+                stop: false,
+                generation: 0,
+                heartbeat: None,
+            }),
+            wake: Condvar::new(),
+            synchronized: Mutex::new(latest_time_ntp.is_some()),
+            synchronized_cond: Condvar::new(),
+            #[cfg(feature = "tokio")]
+            snapshots,
+        }
+    }
+
+    /// Time to start from before NTP time is available, and where it came from
+    fn fallback_time(policy: &FallbackPolicy) -> (Timestamp, TimeSource) {
+        match policy {
+            FallbackPolicy::Error => (DEFAULT_TIMESTAMP, TimeSource::Unavailable),
+            FallbackPolicy::FixedDefault => (DEFAULT_TIMESTAMP, TimeSource::FixedDefault),
+            FallbackPolicy::SystemClock => {
+                clock_log!(
+                    Warn,
+                    Sync,
+                    "Using system-derived, unverified time until NTP succeeds"
+                );
+                (Timestamp::now(), TimeSource::SystemClock)
+            }
+            FallbackPolicy::LastPersistedTime(path) => match persist::load_state(path) {
+                Ok(state) => {
+                    // Time since boot is a lower bound on the time since the state was saved
+                    let estimate = state.advance(time_since_boot());
+                    let system = Timestamp::now();
+                    if system > estimate {
+                        clock_log!(
+                            Info,
+                            Storage,
+                            "System clock {} is ahead of persisted estimate {}, using it",
+                            system,
+                            estimate
+                        );
+                        (system, TimeSource::SystemClock)
+                    } else {
+                        clock_log!(
+                            Info,
+                            Storage,
+                            "Starting from persisted time {} advanced to {}",
+                            state.time,
+                            estimate
+                        );
+                        (estimate, TimeSource::Persisted)
+                    }
+                }
+                Err(e) => {
+                    clock_log!(
+                        Warn,
+                        Storage,
+                        "Failed to load persisted state from {}: {}. Using system clock.",
+                        path.display(),
+                        e
+                    );
+                    (Timestamp::now(), TimeSource::SystemClock)
+                }
+            },
+        }
+    }
+
+    /// Current time, or an error if the clock is unsynchronized under the `Error` policy
+    pub(crate) fn try_current_time(&self) -> Result<Timestamp, ClockError> {
+        let base = self.base.read_or_recover();
+        if base.latest_time_ntp.is_none() && self.fallback_policy == FallbackPolicy::Error {
+            return Err(ClockError::NotSynchronized);
+        }
+        Ok(base.now())
+    }
+
+    /// Publishes the new time base to snapshot subscribers and synchronization waiters
+    fn publish(&self, base: &TimeBase) {
+        if base.latest_time_ntp.is_some() {
+            *self.synchronized.lock_or_recover() = true;
+            self.synchronized_cond.notify_all();
+        }
+        #[cfg(feature = "tokio")]
+        self.snapshots.send_replace(base.snapshot());
+    }
+
+    /// Blocks until the clock has NTP time, returning the current time, or fails after
+    /// `timeout`
+    pub(crate) fn wait_until_synchronized(
+        &self,
+        timeout: Duration,
+    ) -> Result<Timestamp, ClockError> {
+        let synchronized = self.synchronized.lock_or_recover();
+        let (synchronized, _) = lock::recover(self.synchronized_cond.wait_timeout_while(
+            synchronized,
+            timeout,
+            |synced| !*synced,
+        ));
+        if !*synchronized {
+            return Err(ClockError::Timeout(timeout));
+        }
+        drop(synchronized);
+        Ok(self.get_current_time())
+    }
+
+    /// Blocks until the clock has NTP time with an uncertainty of at most `min_accuracy`,
+    /// returning the current time, or fails after `timeout`
+    pub(crate) fn block_until_plausible_time(
+        &self,
+        min_accuracy: Duration,
+        timeout: Duration,
+    ) -> Result<Timestamp, ClockError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.uncertainty().is_some_and(|u| u <= min_accuracy) {
+                return Ok(self.get_current_time());
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(ClockError::Timeout(timeout));
+            }
+            // A sync between the check and the wait is noticed within a slice
+            let synchronized = self.synchronized.lock_or_recover();
+            drop(lock::recover(self.synchronized_cond.wait_timeout(
+                synchronized,
+                left.min(PLAUSIBLE_TIME_RECHECK),
+            )));
+        }
+    }
+
+    /// Async version of [`wait_until_synchronized`](Self::wait_until_synchronized)
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn wait_until_synchronized_async(
+        &self,
+        timeout: Duration,
+    ) -> Result<Timestamp, ClockError> {
+        let mut snapshots = self.subscribe();
+        let synced = snapshots.wait_for(|s| s.state == ClockState::Synchronized);
+        let synced = matches!(tokio::time::timeout(timeout, synced).await, Ok(Ok(_)));
+        if !synced {
+            return Err(ClockError::Timeout(timeout));
+        }
+        Ok(self.get_current_time())
+    }
+
+    /// Subscribes to time base changes
+    #[cfg(feature = "tokio")]
+    pub(crate) fn subscribe(&self) -> tokio::sync::watch::Receiver<ClockSnapshot> {
+        self.snapshots.subscribe()
+    }
+
+    /// Replaces the source used to measure time since the last sync.
+    ///
+    /// The current time is carried over, so switching sources does not step the clock.
+    pub(crate) fn set_elapsed_source(&self, source: Arc<dyn ElapsedSource>) {
+        let mut base = self.base.write_or_recover();
+        base.set_elapsed_source(source);
+        self.publish(&base);
+    }
+
+    /// Replaces the source used to measure time since the last sync, dropping the time that
+    /// passed since then, so that elapsed time starts from the last sync on `source`
+    pub(crate) fn restart_elapsed_source(&self, source: Arc<dyn ElapsedSource>) {
+        let mut base = self.base.write_or_recover();
+        base.restart_elapsed_source(source);
+        self.publish(&base);
+    }
+
+    /// Runs the clock on `timeline` instead of the monotonic clock
+    #[cfg(feature = "simulation")]
+    pub(crate) fn set_virtual_timeline(&self, timeline: VirtualTimeline) {
+        self.set_elapsed_source(Arc::new(timeline.clone()));
+        *self.timeline.write_or_recover() = Some(timeline);
+        self.wake.notify_all();
+    }
+
+    /// The current instant, on the virtual timeline under simulation
+    fn instant(&self) -> Instant {
+        #[cfg(feature = "simulation")]
+        if let Some(timeline) = &*self.timeline.read_or_recover() {
+            return timeline.instant();
+        }
+        Instant::now()
+    }
+
+    /// Time since `earlier`, as measured by [`instant`](Self::instant)
+    fn since(&self, earlier: Instant) -> Duration {
+        self.instant().saturating_duration_since(earlier)
+    }
+
+    /// Longest uninterrupted wait of the sync loop
+    fn tick(&self) -> Duration {
+        #[cfg(feature = "simulation")]
+        if self.timeline.read_or_recover().is_some() {
+            return simulation::SIMULATED_TICK;
+        }
+        TICK
+    }
+
+    /// Fetches current time from NTP servers, returning the sample used and how much each
+    /// server contributed to it.
+    ///
+    /// Servers are tried preferred ones first, then by the stratum they last reported, and
+    /// the first acceptable one is used, unless `combine_sources` is set: then every server is queried, the truechimers
+    /// are selected, and their offsets are combined. `noselect` servers are always queried
+    /// and reported, but never used. Servers that do not parse as a [`ServerSpec`] are
+    /// skipped.
+    fn get_ntp_time(
+        servers: &[String],
+        settings: &PollSettings,
+        source_states: &Mutex<HashMap<String, SourceState>>,
+    ) -> Result<(NtpSample, Vec<SourceWeight>), Box<dyn std::error::Error>> {
+        let (selectable, monitored, preferred) = Self::plan_poll(servers, settings, source_states);
+        let mut transport = Self::poll_transport(settings);
+        let poll_start = Instant::now();
+        let mut candidates = Vec::new();
+        for server in &selectable {
+            let candidate =
+                Self::sample_server(&mut transport, server, settings, source_states, poll_start);
+            if let Some(candidate) = candidate {
+                candidates.push(candidate);
+                if !settings.combine_sources {
+                    break;
+                }
+            }
+        }
+        for server in &monitored {
+            let candidate =
+                Self::sample_server(&mut transport, server, settings, source_states, poll_start);
+            candidates.extend(candidate.map(|c| Candidate {
+                selectable: false,
+                ..c
+            }));
+        }
+        Self::combine_candidates(&candidates, &preferred)
+    }
+
+    /// Fetches the time for the initial sync, racing the servers if `race` is set, and
+    /// gives up after `deadline` if one is set.
+    ///
+    /// A fetch cut off by the deadline runs on in the background, however long DNS or its
+    /// sockets hang, and what it learns about the servers is kept for later polls.
+    fn initial_ntp_time(
+        race: bool,
+        servers: &[String],
+        settings: &PollSettings,
+        source_states: &Arc<Mutex<HashMap<String, SourceState>>>,
+        deadline: Option<Duration>,
+    ) -> Result<(NtpSample, Vec<SourceWeight>), Box<dyn std::error::Error>> {
+        let Some(deadline) = deadline else {
+            return if race {
+                Self::race_ntp_time(servers, settings, source_states)
+            } else {
+                Self::get_ntp_time(servers, settings, source_states)
+            };
+        };
+
+        let (sender, fetched) = mpsc::channel();
+        let servers = servers.to_vec();
+        let settings = settings.clone();
+        let states = Arc::clone(source_states);
+        std::thread::spawn(move || {
+            let result = Self::initial_ntp_time(race, &servers, &settings, &states, None)
+                .map_err(|e| e.to_string());
+            // Nobody is listening any more once the deadline passed
+            let _ = sender.send(result);
+        });
+        match fetched.recv_timeout(deadline) {
+            Ok(result) => result.map_err(Into::into),
+            Err(_) => Err(format!("no answer within {:?}", deadline).into()),
+        }
+    }
+
+    /// Fetches the initial time by querying every selectable server at once and using the
+    /// first acceptable answer.
+    ///
+    /// The other queries run on in the background, so what they learn about their servers
+    /// (stratum, round trips, kiss-o'-death hold-offs) is there for the first poll of the
+    /// sync loop. `noselect` servers are left to that poll.
+    fn race_ntp_time(
+        servers: &[String],
+        settings: &PollSettings,
+        source_states: &Arc<Mutex<HashMap<String, SourceState>>>,
+    ) -> Result<(NtpSample, Vec<SourceWeight>), Box<dyn std::error::Error>> {
+        let (selectable, _, preferred) = Self::plan_poll(servers, settings, source_states);
+        if selectable.len() < 2 {
+            return Self::get_ntp_time(servers, settings, source_states);
+        }
+        clock_log!(
+            Info,
+            Sync,
+            "Racing the initial sync across {} servers",
+            selectable.len()
+        );
+        let (sender, results) = mpsc::channel();
+        let poll_start = Instant::now();
+        for server in selectable {
+            let sender = sender.clone();
+            let settings = settings.clone();
+            let source_states = Arc::clone(source_states);
+            std::thread::spawn(move || {
+                let mut transport = Self::poll_transport(&settings);
+                let candidate = Self::sample_server(
+                    &mut transport,
+                    &server,
+                    &settings,
+                    &source_states,
+                    poll_start,
+                );
+                // Nobody is listening any more once another server won
+                let _ = sender.send(candidate);
+            });
+        }
+        drop(sender);
+
+        let winner: Vec<Candidate> = results.iter().flatten().take(1).collect();
+        if let Some(winner) = winner.first() {
+            clock_log!(Info, Sync, "{} answered first", winner.server);
+        }
+        Self::combine_candidates(&winner, &preferred)
+    }
+
+    /// The servers of a poll, skipping those that do not parse as a [`ServerSpec`]: the
+    /// selectable ones, preferred ones first and then by the stratum they last reported,
+    /// `noselect` ones and those found in a timing loop, which are only monitored, and the
+    /// names of the preferred ones
+    fn plan_poll(
+        servers: &[String],
+        settings: &PollSettings,
+        source_states: &Mutex<HashMap<String, SourceState>>,
+    ) -> (Vec<ServerSpec>, Vec<ServerSpec>, Vec<String>) {
+        let specs: Vec<ServerSpec> = servers
+            .iter()
+            .filter_map(|server| match server.parse() {
+                Ok(spec) => Some(spec),
+                Err(e) => {
+                    clock_log!(Warn, Server, "Skipping server '{}': {}", server, e);
+                    None
+                }
+            })
+            .collect();
+        let mut preferred = settings.preferred.clone();
+        preferred.extend(specs.iter().filter(|s| s.prefer).map(ServerSpec::name));
+        let states = source_states.lock_or_recover();
+        let (monitored, mut selectable): (Vec<ServerSpec>, Vec<ServerSpec>) =
+            specs.into_iter().partition(|s| {
+                let name = s.name();
+                s.noselect
+                    || settings.noselect.contains(&name)
+                    || states.get(&name).is_some_and(|state| state.in_loop)
+            });
+        selectable.sort_by_key(|s| {
+            let name = s.name();
+            let stratum = states.get(&name).and_then(|state| state.stratum);
+            (!preferred.contains(&name), stratum.unwrap_or(MAX_STRATUM))
+        });
+        (selectable, monitored, preferred)
+    }
+
+    /// The transport a poll queries servers through
+    fn poll_transport(settings: &PollSettings) -> Box<DynTransport> {
+        match &settings.transport {
+            Some(factory) => factory.create(),
+            None => Box::new(
+                UdpTransport::default()
+                    .with_source_ports(settings.source_ports.clone())
+                    .with_dscp(settings.dscp)
+                    .with_ttl(settings.ttl),
+            ),
+        }
+    }
+
+    /// Sends `request` to `spec` at `addr`, spacing queries out under the best-practices
+    /// profile and within the server's query budget, and signing it if the server has a
+    /// `key` or protecting it with NTS. Returns the measurement, the reply, and when the
+    /// reply arrived; replies from such a server that fail to verify are dropped.
+    fn exchange(
+        transport: &mut Box<DynTransport>,
+        addr: SocketAddr,
+        spec: &ServerSpec,
+        request: &[u8; PACKET_LEN],
+        settings: &PollSettings,
+        source_states: &Mutex<HashMap<String, SourceState>>,
+    ) -> Option<(Measurement, [u8; PACKET_LEN], Instant)> {
+        let name = spec.name();
+        let server = name.as_str();
+        if settings.best_practices {
+            let last_query =
+                update_source_state(source_states, server, |s| s.recent_queries.back().copied());
+            if let Some(wait) = last_query
+                .and_then(|at| (at + BCP_MIN_HEADWAY).checked_duration_since(Instant::now()))
+            {
+                std::thread::sleep(wait);
+            }
+        }
+        let budget = settings.max_queries_per_minute;
+        if !update_source_state(source_states, server, |s| s.take_query(budget)) {
+            clock_log!(
+                Warn,
+                Server,
+                "Not querying {}: its budget of {} queries per minute is used up",
+                server,
+                budget.unwrap_or_default()
+            );
+            return None;
+        }
+        let packet = match (spec.key, &settings.keys) {
+            (Some(id), Some(keys)) => match keys.read().sign(id, request) {
+                Ok(signed) => signed,
+                Err(e) => {
+                    clock_log!(Warn, Server, "Not querying {}: {}", server, e);
+                    return None;
+                }
+            },
+            (Some(_), None) => {
+                clock_log!(
+                    Warn,
+                    Server,
+                    "Not querying {}: no keys are configured",
+                    server
+                );
+                return None;
+            }
+            (None, _) => request.to_vec(),
+        };
+        #[cfg(feature = "nts")]
+        let nts_request = match spec.protocol {
+            Protocol::Nts => match settings.nts.seal_request(server, request) {
+                Ok(sealed) => Some(sealed),
+                Err(e) => {
+                    clock_log!(Warn, Server, "Not querying {}: {}", server, e);
+                    return None;
+                }
+            },
+            _ => None,
+        };
+        #[cfg(feature = "nts")]
+        let packet = nts_request
+            .as_ref()
+            .map_or(packet, |sealed| sealed.packet.clone());
+        let (measurement, reply) = match sntp::query_with_packet(transport, &addr, &packet) {
+            Ok(exchanged) => exchanged,
+            Err(e) => {
+                clock_log!(Warn, Server, "Query to {} failed: {}", server, e);
+                return None;
+            }
+        };
+        if let (Some(id), Some(keys)) = (spec.key, &settings.keys) {
+            let verified = keys.read().verify(&reply);
+            if verified != Ok(id) {
+                let reason = match verified {
+                    Ok(other) => format!("signed with key {} rather than {}", other, id),
+                    Err(e) => e.to_string(),
+                };
+                clock_log!(Warn, Server, "Dropping a reply from {}: {}", server, reason);
+                return None;
+            }
+        }
+        #[cfg(feature = "nts")]
+        if let Some(sealed) = &nts_request {
+            if let Err(e) = settings.nts.open_reply(server, sealed, &reply) {
+                clock_log!(Warn, Server, "Dropping a reply from {}: {}", server, e);
+                return None;
+            }
+        }
+        let now = Instant::now();
+        let arrived = now.checked_sub(transport.receive_latency()).unwrap_or(now);
+        let mut header = [0u8; PACKET_LEN];
+        header.copy_from_slice(&reply[..PACKET_LEN]);
+        Some((measurement, header, arrived))
+    }
+
+    /// Measures an `xleave` server in interleaved mode, priming it with a basic exchange
+    /// first unless the last one is recent, and measures it in basic mode once it has
+    /// answered [`XLEAVE_BASIC_REPLIES`] interleaved requests in a row in basic mode
+    fn exchange_interleaved(
+        transport: &mut Box<DynTransport>,
+        addr: SocketAddr,
+        spec: &ServerSpec,
+        settings: &PollSettings,
+        source_states: &Mutex<HashMap<String, SourceState>>,
+    ) -> Option<Measurement> {
+        let name = spec.name();
+        let server = name.as_str();
+        let basic_request = sntp::client_request_with_version(spec.version);
+        let (previous, basic_replies) = update_source_state(source_states, server, |s| {
+            (s.interleaved.take(), s.basic_replies)
+        });
+        if basic_replies >= XLEAVE_BASIC_REPLIES {
+            let exchanged = Self::exchange(
+                transport,
+                addr,
+                spec,
+                &basic_request,
+                settings,
+                source_states,
+            );
+            return exchanged.map(|(measurement, _, _)| measurement);
+        }
+        let (previous, previous_arrived) =
+            match previous.filter(|(_, arrived)| arrived.elapsed() <= XLEAVE_MAX_AGE) {
+                Some(previous) => previous,
+                None => {
+                    let (primed, reply, arrived) = Self::exchange(
+                        transport,
+                        addr,
+                        spec,
+                        &basic_request,
+                        settings,
+                        source_states,
+                    )?;
+                    let state = InterleavedState::new(&reply, Timestamp::now(), primed.delay);
+                    (state, arrived)
+                }
+            };
+
+        let request = sntp::interleaved_request(spec.version, &previous, Timestamp::now());
+        let (basic, reply, arrived) =
+            Self::exchange(transport, addr, spec, &request, settings, source_states)?;
+        let since = previous_arrived.elapsed();
+        let interleaved = sntp::is_interleaved_reply(&request, &reply)
+            .then(|| sntp::parse_interleaved_reply(&reply, &previous, since))
+            .flatten();
+        let basic_replies = update_source_state(source_states, server, |s| {
+            s.interleaved = Some((
+                InterleavedState::new(&reply, Timestamp::now(), basic.delay),
+                arrived,
+            ));
+            s.basic_replies = if interleaved.is_some() {
+                0
+            } else {
+                s.basic_replies + 1
+            };
+            s.basic_replies
+        });
+        match interleaved {
+            Some(measurement) => {
+                clock_log!(Debug, Server, "{} answered in interleaved mode", server);
+                Some(measurement)
+            }
+            None => {
+                if basic_replies == XLEAVE_BASIC_REPLIES {
+                    clock_log!(
+                        Info,
+                        Server,
+                        "{} does not answer in interleaved mode, falling back to basic mode",
+                        server
+                    );
+                }
+                Some(basic)
+            }
+        }
+    }
+
+    /// The IPv4 addresses a server may know this host by, and report as its reference ID
+    /// if it synchronizes to it: the address a query was sent from, and the address this
+    /// clock serves time on if it is a specific one
+    fn own_addresses(
+        sent_from: Option<SocketAddr>,
+        serving: Option<SocketAddr>,
+    ) -> impl Iterator<Item = Ipv4Addr> {
+        [sent_from, serving]
+            .into_iter()
+            .flatten()
+            .filter_map(|addr| match addr.ip() {
+                IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+                _ => None,
+            })
+    }
+
+    /// The addresses of `host` for `server` that the address policy permits, or `None` if
+    /// there are none
+    fn resolve(
+        server: &str,
+        host: &str,
+        port: u16,
+        settings: &PollSettings,
+    ) -> Option<Vec<SocketAddr>> {
+        let mut addrs = match settings.dns.resolve(host, port) {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                clock_log!(Warn, Server, "Failed to resolve {}: {}", server, e);
+                return None;
+            }
+        };
+        addrs.retain(|addr| {
+            let permitted = settings.address_policy.permits(addr.ip());
+            if !permitted {
+                clock_log!(
+                    Warn,
+                    Server,
+                    "Not querying {} for {}: refused by the address policy",
+                    addr.ip(),
+                    server
+                );
+            }
+            permitted
+        });
+        (!addrs.is_empty()).then_some(addrs)
+    }
+
+    /// Queries one server, taking `samples_per_poll` measurements (at least
+    /// [`IBURST_SAMPLES`] for an `iburst` server that has not answered yet), and checks the
+    /// result against the server's stratum, the delay limits, and the time floor and
+    /// ceiling
+    fn sample_server(
+        transport: &mut Box<DynTransport>,
+        spec: &ServerSpec,
+        settings: &PollSettings,
+        source_states: &Mutex<HashMap<String, SourceState>>,
+        poll_start: Instant,
+    ) -> Option<Candidate> {
+        let name = spec.name();
+        let server = name.as_str();
+        #[cfg(not(feature = "nts"))]
+        if spec.protocol == Protocol::Nts {
+            clock_log!(
+                Warn,
+                Server,
+                "Not querying {}: NTS needs the `nts` feature, and it will not be queried \
+                 without authentication",
+                server
+            );
+            return None;
+        }
+        let held_off_until = update_source_state(source_states, server, |s| s.held_off_until);
+        if held_off_until.is_some_and(|until| Instant::now() < until) {
+            clock_log!(
+                Info,
+                Server,
+                "Skipping {}: held off by a kiss-o'-death reply",
+                server
+            );
+            return None;
+        }
+
+        clock_log!(
+            Info,
+            Server,
+            "Attempting to connect to NTP server: {}",
+            server
+        );
+        let addrs = Self::resolve(server, &spec.host, spec.port, settings)?;
+        let mut rotation = 0;
+        if settings.best_practices {
+            // Spread the load over a pool's addresses
+            rotation = update_source_state(source_states, server, |s| {
+                s.rotation = s.rotation.wrapping_add(1);
+                s.rotation - 1
+            });
+        }
+        let addr = *addrs.get(rotation % addrs.len().max(1))?;
+        // The address of an NTS server is that of its key establishment, which names the
+        // NTP server the session is for
+        #[cfg(feature = "nts")]
+        let addr = match spec.protocol {
+            Protocol::Nts => {
+                let (host, port) = match settings.nts.ntp_server(spec, addr) {
+                    Ok(ntp_server) => ntp_server,
+                    Err(e) => {
+                        clock_log!(Warn, Server, "Key exchange with {} failed: {}", server, e);
+                        return None;
+                    }
+                };
+                *Self::resolve(server, &host, port, settings)?.first()?
+            }
+            _ => addr,
+        };
+        let mut measure = || {
+            let measurement = if spec.xleave {
+                Self::exchange_interleaved(transport, addr, spec, settings, source_states)?
+            } else {
+                let request = sntp::client_request_with_version(spec.version);
+                Self::exchange(transport, addr, spec, &request, settings, source_states)?.0
+            };
+            if let Some(code) = measurement.kiss_code() {
+                clock_log!(
+                    Warn,
+                    Server,
+                    "{} sent a kiss-o'-death reply: {}",
+                    server,
+                    code
+                );
+                let backoff = match code {
+                    "RATE" => Some(KOD_RATE_BACKOFF),
+                    "DENY" | "RSTR" => Some(KOD_DENY_BACKOFF),
+                    _ => None,
+                };
+                if let Some(backoff) = backoff.filter(|_| settings.best_practices) {
+                    clock_log!(
+                        Warn,
+                        Server,
+                        "Not querying {} again for {}s",
+                        server,
+                        backoff.as_secs()
+                    );
+                    update_source_state(source_states, server, |s| {
+                        s.held_off_until = Some(Instant::now() + backoff)
+                    });
+                }
+                return None;
+            }
+            if !measurement.is_synchronized() {
+                clock_log!(
+                    Warn,
+                    Server,
+                    "Rejecting time from {}: server is unsynchronized (stratum {})",
+                    server,
+                    measurement.stratum
+                );
+                return None;
+            }
+            let looped = Self::own_addresses(transport.local_addr(), settings.serving)
+                .find(|own| measurement.is_synchronized_to(own.octets()));
+            let in_loop = looped.is_some() && settings.serving.is_some();
+            let was_in_loop = update_source_state(source_states, server, |s| {
+                mem::replace(&mut s.in_loop, in_loop)
+            });
+            match looped {
+                Some(own) if in_loop && !was_in_loop => {
+                    clock_log!(
+                        Warn,
+                        Server,
+                        "Removing {} from selection: it synchronizes to this host ({}), which \
+                         serves time, so following it would form a timing loop",
+                        server,
+                        own
+                    );
+                    return None;
+                }
+                // Already reported when it was taken out of selection
+                Some(_) if in_loop => return None,
+                Some(own) => {
+                    clock_log!(
+                        Warn,
+                        Server,
+                        "Rejecting time from {}: it synchronizes to this host ({}), which \
+                         would form a timing loop",
+                        server,
+                        own
+                    );
+                    return None;
+                }
+                None if was_in_loop => clock_log!(
+                    Info,
+                    Server,
+                    "{} no longer synchronizes to this host, selecting it again",
+                    server
+                ),
+                None => {}
+            }
+            let delay_check = update_source_state(source_states, server, |s| {
+                s.delay_filter
+                    .check(measurement.delay, &settings.delay_limits)
+            });
+            if let Err(rejection) = delay_check {
+                clock_log!(
+                    Warn,
+                    Server,
+                    "Rejecting time from {}: {}",
+                    server,
+                    rejection
+                );
+                return None;
+            }
+            Some(measurement)
+        };
+
+        let mut samples = settings.samples_per_poll;
+        if spec.iburst && update_source_state(source_states, server, |s| s.stratum.is_none()) {
+            samples = samples.max(IBURST_SAMPLES);
+        }
+        let mut measurement = measure()?;
+        let mut jitter = 0.0;
+        if samples > 1 {
+            let first_taken = Instant::now();
+            let mut taken = vec![(measurement, Duration::ZERO)];
+            for _ in 1..samples {
+                std::thread::sleep(SAMPLE_SPACING);
+                if let Some(next) = measure() {
+                    taken.push((next, first_taken.elapsed()));
+                }
+            }
+            if let Some(combined) = sntp::combine_measurements(&taken) {
+                measurement = combined;
+            }
+            jitter = sntp::sample_jitter(&taken);
+            clock_log!(
+                Info,
+                Server,
+                "Combined {} of {} samples from {}",
+                taken.len(),
+                samples,
+                server
+            );
+        }
+        if settings.floor.is_some_and(|floor| measurement.time < floor) {
+            clock_log!(
+                Warn,
+                Server,
+                "Rejecting time {} from {}: earlier than the minimum time",
+                measurement.time,
+                server
+            );
+            return None;
+        }
+        if settings
+            .ceiling
+            .is_some_and(|ceiling| measurement.time > ceiling)
+        {
+            clock_log!(
+                Warn,
+                Server,
+                "Rejecting time {} from {}: later than the maximum plausible time",
+                measurement.time,
+                server
+            );
+            return None;
+        }
+        clock_log!(
+            Info,
+            Server,
+            "Successfully retrieved time from {} (stratum {}): {}",
+            server,
+            measurement.stratum,
+            measurement.time
+        );
+        update_source_state(source_states, server, |s| {
+            s.stratum = Some(measurement.stratum)
+        });
+        Some(Candidate {
+            server: server.to_string(),
+            addr,
+            measurement,
+            jitter,
+            taken: poll_start.elapsed(),
+            selectable: true,
+        })
+    }
+
+    /// Selects the truechimers among the selectable `candidates` and combines them into one
+    /// sample, dated when the last candidate was taken. A preferred truechimer, or else the
+    /// one with the lowest stratum and then the largest weight, is reported as the sample's
+    /// source.
+    fn combine_candidates(
+        candidates: &[Candidate],
+        preferred: &[String],
+    ) -> Result<(NtpSample, Vec<SourceWeight>), Box<dyn std::error::Error>> {
+        let pool: Vec<usize> = (0..candidates.len())
+            .filter(|&i| candidates[i].selectable)
+            .collect();
+        if pool.is_empty() {
+            return Err("All NTP servers failed".into());
+        }
+        // Selectable candidates come first, so both are set
+        let (first, last) = (&candidates[0], &candidates[candidates.len() - 1]);
+        // Offsets from the first candidate, once local elapsed time is taken out
+        let estimates: Vec<SourceEstimate> = candidates
+            .iter()
+            .map(|c| {
+                let elapsed = (c.taken - first.taken).as_nanos() as i128;
+                SourceEstimate {
+                    offset: (c.measurement.time.nanos_since(first.measurement.time) - elapsed)
+                        as f64
+                        / 1e9,
+                    root_distance: c.measurement.delay.as_secs_f64() / 2.0
+                        + c.measurement.root_dispersion,
+                    jitter: c.jitter,
+                }
+            })
+            .collect();
+
+        let selected: Vec<usize> = if pool.len() == 1 {
+            pool.clone()
+        } else {
+            let pool_estimates: Vec<SourceEstimate> = pool.iter().map(|&i| estimates[i]).collect();
+            sntp::select_truechimers(&pool_estimates)
+                .into_iter()
+                .map(|i| pool[i])
+                .collect()
+        };
+        let (offset, selected_weights) =
+            sntp::combine_offsets(&estimates, &selected).ok_or_else(|| {
+                format!(
+                    "No majority of the {} servers agree on the time",
+                    pool.len()
+                )
+            })?;
+        let mut weights = vec![0.0; candidates.len()];
+        for (&i, &weight) in selected.iter().zip(&selected_weights) {
+            weights[i] = weight;
+        }
+        for (candidate, weight) in candidates.iter().zip(&weights) {
+            if candidate.selectable && *weight == 0.0 {
+                clock_log!(
+                    Warn,
+                    Server,
+                    "Discarding {} as a falseticker",
+                    candidate.server
+                );
+            }
+        }
+
+        let peer_index = selected
+            .iter()
+            .copied()
+            .find(|&i| preferred.contains(&candidates[i].server))
+            .or_else(|| {
+                selected.iter().copied().min_by(|&a, &b| {
+                    let stratum = |i: usize| candidates[i].measurement.stratum;
+                    stratum(a)
+                        .cmp(&stratum(b))
+                        .then(weights[b].total_cmp(&weights[a]))
+                })
+            })
+            .unwrap_or(pool[0]);
+        let peer = &candidates[peer_index];
+        let elapsed = (last.taken - first.taken).as_nanos() as i128;
+        let sample = NtpSample {
+            server: peer.server.clone(),
+            addr: peer.addr,
+            time: first
+                .measurement
+                .time
+                .add_nanos(elapsed + (offset * 1e9) as i128),
+            delay: peer.measurement.delay,
+            root_delay: peer.measurement.root_delay,
+            root_dispersion: peer.measurement.root_dispersion,
+            stratum: peer.measurement.stratum,
+            reference_id: peer.measurement.reference_id,
+            precision: peer.measurement.precision,
+        };
+        let sources = candidates
+            .iter()
+            .zip(&estimates)
+            .zip(weights)
+            .map(|((candidate, estimate), weight)| SourceWeight {
+                server: candidate.server.clone(),
+                offset: estimate.offset - offset,
+                root_distance: estimate.root_distance,
+                jitter: estimate.jitter,
+                stratum: candidate.measurement.stratum,
+                weight,
+            })
+            .collect();
+        Ok((sample, sources))
+    }
+
+    /// Whether the clock has obtained time from an NTP server at least once
+    pub(crate) fn is_synchronized(&self) -> bool {
+        self.base.read_or_recover().latest_time_ntp.is_some()
+    }
+
+    /// Returns the current time with elapsed offset
+    pub(crate) fn get_current_time(&self) -> Timestamp {
+        self.base.read_or_recover().now()
+    }
+
+    /// Reserves `count` consecutive nanoseconds for stamping events, starting at the current
+    /// time or just after the last reserved stamp if the clock has not passed it yet.
+    /// Returns the first.
+    pub(crate) fn reserve_stamps(&self, count: u64) -> Timestamp {
+        let now = self.get_current_time();
+        let mut last = self.last_stamp.lock_or_recover();
+        let first = now.max(last.add_nanos(1));
+        if count > 0 {
+            *last = first.add_nanos(i128::from(count) - 1);
+        }
+        first
+    }
+
+    /// Queries the NTP servers once, recording the attempt in the statistics
+    fn poll(&self) -> Option<NtpSample> {
+        let servers = self.ntp_servers.read_or_recover().clone();
+        let settings = self.poll_settings.read_or_recover().clone();
+        let result = match Self::get_ntp_time(&servers, &settings, &self.source_states) {
+            Err(e) if !self.peers.read_or_recover().is_empty() => {
+                clock_log!(Warn, Sync, "NTP fetch failed, trying peers: {}", e);
+                self.poll_peers(&settings)
+            }
+            result => result,
+        };
+        let result = result.and_then(|(sample, weights)| Ok((self.check_rtc(sample)?, weights)));
+
+        self.rolling_stats.lock_or_recover().record(result.is_ok());
+        match result {
+            Ok((sample, weights)) => {
+                let offset = sample.time.seconds_since(self.base.read_or_recover().now());
+                self.record_attempt(SyncAttempt::synced(&sample, offset));
+                self.check_offset_watches(&sample, offset);
+                let jitter = self.attempt_jitter();
+                let delay = sample.delay.as_secs_f64();
+                self.stats
+                    .lock_or_recover()
+                    .record_success(offset, delay, jitter);
+                *self.source_weights.lock_or_recover() = weights;
+                *self.last_sync.lock_or_recover() = Some(LastSync::new(&sample, self.instant()));
+                *self.reference.lock_or_recover() = Some(ReferenceInfo::from(&sample));
+                // Wakes block_until_plausible_time, whose uncertainty just shrank
+                drop(self.synchronized.lock_or_recover());
+                self.synchronized_cond.notify_all();
+                clock_log!(
+                    Info,
+                    Sync,
+                    "NTP sync successful. Updated time: {}",
+                    sample.time
+                );
+                self.leave_orphan_mode();
+                self.alerts.sync_lost.store(false, Ordering::Relaxed);
+                Some(sample)
+            }
+            Err(e) => {
+                self.stats.lock_or_recover().record_failure();
+                self.record_attempt(SyncAttempt::failed(&e));
+                clock_log!(Error, Sync, "NTP fetch failed: {}", e);
+                self.alerts.raise(Alert {
+                    kind: AlertKind::AllServersFailed,
+                    time: self.get_current_time(),
+                    server: None,
+                    magnitude: None,
+                    message: e.to_string(),
+                });
+                self.check_lost_sync();
+                self.check_orphan_mode();
+                self.rediscover_servers();
+                None
+            }
+        }
+    }
+
+    /// Calls the [`Clock::on_offset_exceeds`](crate::Clock::on_offset_exceeds) callbacks
+    /// whose threshold `offset` is beyond
+    fn check_offset_watches(&self, sample: &NtpSample, offset: f64) {
+        for watch in self.offset_watches.snapshot() {
+            if offset.abs() <= watch.threshold.as_secs_f64() {
+                continue;
+            }
+            let breach = OffsetBreach {
+                offset,
+                threshold: watch.threshold,
+                server: sample.server.clone(),
+                time: sample.time,
+            };
+            call_guarded("offset", || (watch.callback)(&breach));
+        }
+    }
+
+    /// Calls the step listeners with a step that has been applied. No lock is held, so they
+    /// may read the clock, which serves the time from after the step.
+    fn announce_step(&self, step: &Step) {
+        for listener in self.step_listeners.snapshot() {
+            call_guarded("step", || listener.on_step(step));
+        }
+    }
+
+    /// The servers of `config`, followed by those advertised over mDNS if
+    /// [`ClockConfig::mdns_discovery`] is set and those answering a probe of
+    /// [`ClockConfig::manycast`]
+    fn with_discovered_servers(config: &ClockConfig) -> Vec<String> {
+        let mut servers = config.servers.clone();
+        let mut add = |found: Vec<SocketAddr>, how: &str| {
+            for addr in found.iter().map(SocketAddr::to_string) {
+                if !servers.contains(&addr) {
+                    clock_log!(
+                        Info,
+                        Discovery,
+                        "Discovered NTP server {} over {}",
+                        addr,
+                        how
+                    );
+                    servers.push(addr);
+                }
+            }
+        };
+        if config.mdns_discovery {
+            match mdns::discover(mdns::DISCOVERY_TIMEOUT) {
+                Ok(found) => add(found, "mDNS"),
+                Err(e) => clock_log!(Warn, Discovery, "mDNS discovery failed: {}", e),
+            }
+        }
+        if !config.manycast.is_empty() {
+            match manycast::probe(&config.manycast, manycast::PROBE_TIMEOUT) {
+                Ok(found) => add(found, "manycast"),
+                Err(e) => clock_log!(Warn, Discovery, "Manycast probe failed: {}", e),
+            }
+        }
+        servers
+    }
+
+    /// Looks for servers over mDNS and manycast again after a failed poll, in case some
+    /// appeared since
+    fn rediscover_servers(&self) {
+        let config = self.applied_config.lock_or_recover().clone();
+        if config.mdns_discovery || !config.manycast.is_empty() {
+            *self.ntp_servers.write_or_recover() = Self::with_discovered_servers(&config);
+        }
+    }
+
+    /// Queries the peers in order and uses the first that is a better source than this
+    /// clock would be in orphan mode: one with a lower stratum than the orphan stratum, or
+    /// an orphan itself with a lower address than ours, so that of several orphaned peers
+    /// exactly one keeps free-running and the others follow it
+    fn poll_peers(
+        &self,
+        settings: &PollSettings,
+    ) -> Result<(NtpSample, Vec<SourceWeight>), Box<dyn std::error::Error>> {
+        let peers = self.peers.read_or_recover().clone();
+        let orphan_stratum = self.orphan.lock_or_recover().stratum;
+        let settings = PollSettings {
+            combine_sources: false,
+            preferred: Vec::new(),
+            noselect: Vec::new(),
+            ..settings.clone()
+        };
+        for peer in &peers {
+            let Ok((sample, weights)) =
+                Self::get_ntp_time(std::slice::from_ref(peer), &settings, &self.source_states)
+            else {
+                continue;
+            };
+            let usable = match sample.stratum.cmp(&orphan_stratum) {
+                std::cmp::Ordering::Less => true,
+                std::cmp::Ordering::Equal => {
+                    peer::local_ip_towards(sample.addr).is_ok_and(|local| sample.addr.ip() < local)
+                }
+                std::cmp::Ordering::Greater => false,
+            };
+            if usable {
+                clock_log!(
+                    Info,
+                    Discovery,
+                    "Synchronized to peer {} (stratum {})",
+                    peer,
+                    sample.stratum
+                );
+                return Ok((sample, weights));
+            }
+            clock_log!(
+                Info,
+                Discovery,
+                "Not synchronizing to peer {}: stratum {} is no better than this clock's",
+                peer,
+                sample.stratum
+            );
+        }
+        Err("No peer is a usable source".into())
+    }
+
+    /// Enters orphan mode if no server has been reachable for the configured time since the
+    /// last successful sync
+    fn check_orphan_mode(&self) {
+        let Some(last) = *self.last_sync.lock_or_recover() else {
+            return;
+        };
+        let unreachable_for = self.since(last.at);
+        {
+            let mut orphan = self.orphan.lock_or_recover();
+            match orphan.after {
+                Some(after) if orphan.since.is_none() && unreachable_for >= after => {
+                    orphan.since = Some(self.instant());
+                }
+                _ => return,
+            }
+        }
+
+        let drift = self.drift_ppm().unwrap_or(0.0);
+        let mut base = self.base.write_or_recover();
+        base.set_drift(drift);
+        base.source = TimeSource::Orphan;
+        self.publish(&base);
+        drop(base);
+        clock_log!(
+            Warn,
+            Sync,
+            "No server reachable for {}s, entering orphan mode (drift {:+.3} PPM)",
+            unreachable_for.as_secs(),
+            drift
+        );
+        self.events
+            .emit(ClockEvent::OrphanModeEntered { unreachable_for });
+    }
+
+    /// Ends orphan mode after a successful sync. The sample is applied by the caller.
+    fn leave_orphan_mode(&self) {
+        let Some(since) = self.orphan.lock_or_recover().since.take() else {
+            return;
+        };
+        let mut base = self.base.write_or_recover();
+        base.set_drift(0.0);
+        base.source = TimeSource::Ntp;
+        self.publish(&base);
+        drop(base);
+        let orphaned_for = self.since(since);
+        clock_log!(
+            Info,
+            Sync,
+            "Left orphan mode after {}s",
+            orphaned_for.as_secs()
+        );
+        self.events
+            .emit(ClockEvent::OrphanModeLeft { orphaned_for });
+    }
+
+    /// Raises [`AlertKind::LostSync`] once the last sync has gone stale
+    fn check_lost_sync(&self) {
+        let Health::Stale { age } = self.health() else {
+            return;
+        };
+        if self.alerts.sync_lost.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        self.alerts.raise(Alert {
+            kind: AlertKind::LostSync,
+            time: self.get_current_time(),
+            server: None,
+            magnitude: Some(age.as_secs_f64()),
+            message: format!("no successful sync for {}s", age.as_secs()),
+        });
+    }
+
+    /// Whether the clock is in orphan mode
+    pub(crate) fn is_orphaned(&self) -> bool {
+        self.orphan.lock_or_recover().since.is_some()
+    }
+
+    /// Updates the latest time from NTP servers
+    pub(crate) fn update_latest_time(&self) {
+        if let Some(sample) = self.poll() {
+            self.apply_sample(&sample, false);
+        }
+    }
+
+    /// Queries NTP once and applies the result at once, like a periodic sync. Without a
+    /// smoothing filter, the clock is stepped to it rather than only measured.
+    ///
+    /// Used after a suspend, when the local clock is known to be behind and the offset is
+    /// not a meaningful stability measurement. Returns whether a sample was obtained.
+    pub(crate) fn resync_now(&self) -> bool {
+        let Some(sample) = self.poll() else {
+            return false;
+        };
+        self.apply_sample(&sample, true);
+        true
+    }
+
+    /// Applies `sample` to the time base. The first sample steps the clock to it; later ones
+    /// go through the smoothing filter, at no more than `max_slew_ppm`, and without a filter
+    /// are only measured unless this is a `resync`, which steps to them then. The offset of
+    /// a resync is not kept as a stability measurement.
+    ///
+    /// The step is worked out and applied under one lock of the time base, so that step
+    /// listeners are told of the step that was actually applied.
+    fn apply_sample(&self, sample: &NtpSample, resync: bool) {
+        let new_time = sample.time;
+        self.persist_time(new_time);
+        let smoothing = *self.smoothing.read_or_recover();
+        let max_slew_ppm = *self.max_slew_ppm.read_or_recover();
+
+        let mut base = self.base.write_or_recover();
+        let was_synchronized = base.latest_time_ntp.is_some();
+        base.latest_time_ntp = Some(new_time);
+        let before = base.now();
+        let offset = if was_synchronized && !resync {
+            self.record_offset(new_time, base.uncorrected_now())
+        } else {
+            new_time.seconds_since(base.uncorrected_now())
+        };
+        let kind = match smoothing.filter(|_| was_synchronized) {
+            Some(filter) => {
+                base.smooth(&filter, offset, max_slew_ppm);
+                Some(match filter {
+                    SmoothingFilter::Pi { .. } => AdjustmentKind::Slew,
+                    _ => AdjustmentKind::Step,
+                })
+            }
+            None if was_synchronized && !resync => None,
+            None => {
+                base.step_to(new_time);
+                base.source = TimeSource::Ntp;
+                Some(AdjustmentKind::Step)
+            }
+        };
+        let after = base.now();
+        if kind.is_some() {
+            self.publish(&base);
+        }
+        drop(base);
+
+        if kind == Some(AdjustmentKind::Step) {
+            self.announce_step(&Step::new(before, after));
+        }
+        if !was_synchronized {
+            clock_log!(Info, Sync, "Initialized time from fallback to NTP time");
+        } else if resync && kind == Some(AdjustmentKind::Step) {
+            clock_log!(Info, Sync, "Stepped clock to {}", after);
+        }
+        self.record_sync(SyncRecord::new(sample, offset, kind));
+        if let Some(kind) = kind {
+            self.audit(kind, before, after, sample);
+        }
+        if was_synchronized && !resync {
+            self.log_statistics(sample, offset);
+            self.check_for_anomaly(sample);
+        }
+    }
+
+    /// Runs [`resync_now`](Self::resync_now) on a background thread, waiting at most
+    /// `deadline` for it, and returns whether it obtained a sample or `None` if it is still
+    /// running.
+    ///
+    /// A sync still running from an earlier call is waited on instead of starting another,
+    /// so a hung resolver or server leaves at most one thread behind.
+    pub(crate) fn resync_with_deadline(self: &Arc<Self>, deadline: Duration) -> Option<bool> {
+        let sync = {
+            let mut in_flight = self.in_flight_sync.lock_or_recover();
+            match in_flight.as_ref() {
+                Some(sync) => Arc::clone(sync),
+                None => {
+                    let sync = Arc::new(InFlightSync::default());
+                    *in_flight = Some(Arc::clone(&sync));
+                    let shared = Arc::clone(self);
+                    let finished = Arc::clone(&sync);
+                    std::thread::spawn(move || {
+                        let synced = panic::catch_unwind(AssertUnwindSafe(|| shared.resync_now()))
+                            .unwrap_or(false);
+                        *shared.in_flight_sync.lock_or_recover() = None;
+                        *finished.synced.lock_or_recover() = Some(synced);
+                        finished.finished.notify_all();
+                    });
+                    sync
+                }
+            }
+        };
+        let synced = sync.synced.lock_or_recover();
+        let (synced, _) = lock::recover(sync.finished.wait_timeout_while(
+            synced,
+            deadline,
+            |synced| synced.is_none(),
+        ));
+        *synced
+    }
+
+    /// The RTC's time if it disagrees with `sample` by more than `check` tolerates. An RTC
+    /// that cannot be read agrees with everything.
+    fn rtc_conflict(check: &RtcCheck, sample: &NtpSample) -> Option<Timestamp> {
+        check.conflict(sample.time).unwrap_or_else(|e| {
+            clock_log!(
+                Debug,
+                Sync,
+                "Skipping the RTC cross-check, cannot read {}: {}",
+                check.device.display(),
+                e
+            );
+            None
+        })
+    }
+
+    /// Resolves a disagreement between `sample` and the RTC's time `rtc` by `policy`:
+    /// returns the sample to use, or an error to hold the clock where it is
+    fn resolve_rtc_conflict(
+        policy: RtcPolicy,
+        sample: NtpSample,
+        rtc: Timestamp,
+    ) -> Result<NtpSample, Box<dyn std::error::Error>> {
+        let offset = rtc.seconds_since(sample.time);
+        clock_log!(
+            Warn,
+            Sync,
+            "Time {} from {} disagrees with the RTC's {} by {:.0}s, resolving by {}",
+            sample.time,
+            sample.server,
+            rtc,
+            offset,
+            policy
+        );
+        match policy {
+            RtcPolicy::TrustNtp => Ok(sample),
+            RtcPolicy::TrustRtc => Ok(NtpSample {
+                time: rtc,
+                ..sample
+            }),
+            RtcPolicy::Hold => Err(format!(
+                "time from {} disagrees with the RTC by {:.0}s, holding",
+                sample.server, offset
+            )
+            .into()),
+        }
+    }
+
+    /// Cross-checks a polled sample against the RTC, reporting a conflict as an anomaly and
+    /// recording it in the audit log before resolving it
+    fn check_rtc(&self, sample: NtpSample) -> Result<NtpSample, Box<dyn std::error::Error>> {
+        let Some(check) = self.rtc_check.read_or_recover().clone() else {
+            return Ok(sample);
+        };
+        let Some(rtc) = Self::rtc_conflict(&check, &sample) else {
+            return Ok(sample);
+        };
+        self.audit(AdjustmentKind::RtcConflict, sample.time, rtc, &sample);
+        self.report_anomaly(Anomaly {
+            server: sample.server.clone(),
+            time: sample.time,
+            kind: AnomalyKind::RtcDisagreement {
+                offset: rtc.seconds_since(sample.time),
+            },
+        });
+        Self::resolve_rtc_conflict(check.policy, sample, rtc)
+    }
+
+    /// Programs the RTC with the current time if the clock is healthy and
+    /// [`ClockConfig::rtc_write_interval`] passed since it last tried, waiting for the
+    /// start of the next second since the RTC keeps whole seconds
+    fn write_rtc_if_due(&self) {
+        let Some(write) = self.rtc_write.read_or_recover().clone() else {
+            return;
+        };
+        if !self.health().is_healthy() {
+            return;
+        }
+        {
+            let now = self.instant();
+            let mut last = self.last_rtc_write.lock_or_recover();
+            if last.is_some_and(|at| now.saturating_duration_since(at) < write.interval) {
+                return;
+            }
+            // A failed write waits for the next interval too, rather than being retried
+            // every cycle
+            *last = Some(now);
+        }
+        let now = self.get_current_time();
+        let next_second = Timestamp::from_unix_secs(now.unix_secs() + 1);
+        std::thread::sleep(Duration::from_nanos(next_second.nanos_since(now) as u64));
+        match rtc::write(&write.device, next_second) {
+            Ok(()) => clock_log!(
+                Debug,
+                Sync,
+                "Set the RTC {} to {}",
+                write.device.display(),
+                next_second
+            ),
+            Err(e) => clock_log!(
+                Warn,
+                Sync,
+                "Failed to set the RTC {}: {}",
+                write.device.display(),
+                e
+            ),
+        }
+    }
+
+    /// Saves a verified time and the current drift estimate for the `LastPersistedTime`
+    /// fallback policy
+    fn persist_time(&self, time: Timestamp) {
+        let FallbackPolicy::LastPersistedTime(path) = &self.fallback_policy else {
+            return;
+        };
+        let state = PersistedState {
+            time,
+            drift_ppm: self.drift_ppm().unwrap_or(0.0),
+        };
+        if let Err(e) = persist::save_state(path, &state) {
+            clock_log!(
+                Warn,
+                Storage,
+                "Failed to persist state to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    /// Classifies the clock by the age of its last successful sync
+    pub(crate) fn health(&self) -> Health {
+        let threshold = self
+            .staleness_threshold
+            .read_or_recover()
+            .unwrap_or_else(|| self.sync_interval() * DEFAULT_STALENESS_FACTOR);
+        let since_sync = self
+            .last_sync
+            .lock_or_recover()
+            .map(|last| self.since(last.at));
+        health::assess(since_sync, threshold)
+    }
+
+    /// Bound on the error of the current time: the last sample's error bound, grown by
+    /// [`DISPERSION_RATE`] since it was taken. `None` before the first sync.
+    pub(crate) fn uncertainty(&self) -> Option<Duration> {
+        let last = (*self.last_sync.lock_or_recover())?;
+        let bound = last.error_bound + self.since(last.at).as_secs_f64() * DISPERSION_RATE;
+        Some(Duration::from_secs_f64(bound.max(0.0)))
+    }
+
+    /// Checks a validity period against the current time give or take its uncertainty
+    pub(crate) fn check_validity(
+        &self,
+        not_before: Timestamp,
+        not_after: Timestamp,
+    ) -> ValidityStatus {
+        let uncertainty = self.uncertainty();
+        validity::check(self.get_current_time(), uncertainty, not_before, not_after)
+    }
+
+    /// Stratum of this clock: the orphan stratum in orphan mode, otherwise one more than
+    /// the server it last synchronized to, or [`MAX_STRATUM`] before the first sync
+    pub(crate) fn stratum(&self) -> u8 {
+        {
+            let orphan = self.orphan.lock_or_recover();
+            if orphan.since.is_some() {
+                return orphan.stratum;
+            }
+        }
+        self.last_sync
+            .lock_or_recover()
+            .map_or(MAX_STRATUM, |last| (last.stratum + 1).min(MAX_STRATUM))
+    }
+
+    /// What this clock reports about itself when answering [`peer`] queries: before the
+    /// first sync it is unsynchronized, and in orphan mode it claims the loopback address
+    /// as its reference like ntpd, with no root delay. Half the root delay plus the root
+    /// dispersion is the clock's [`uncertainty`](Self::uncertainty).
+    pub(crate) fn server_state(&self) -> ServerState {
+        let last = *self.last_sync.lock_or_recover();
+        let (reference_id, root_delay) = match last {
+            None => (*b"INIT", 0.0),
+            Some(_) if self.is_orphaned() => ([127, 0, 0, 1], 0.0),
+            Some(last) => (last.reference_id, last.root_delay),
+        };
+        let uncertainty = self.uncertainty().map_or(0.0, |u| u.as_secs_f64());
+        ServerState {
+            stratum: self.stratum(),
+            root_delay,
+            root_dispersion: (uncertainty - root_delay / 2.0).max(0.0),
+            reference_id,
+            reference_time: last.map(|last| {
+                let age = self.since(last.at).as_nanos() as i128;
+                self.get_current_time().add_nanos(-age)
+            }),
+        }
+    }
+
+    /// Records a query from `addr` to the peer responder, returning whether it is within
+    /// the [`ClockConfig::rate_limit`]
+    pub(crate) fn admit_client(&self, addr: IpAddr) -> bool {
+        self.clients.lock_or_recover().admit(addr, Instant::now())
+    }
+
+    /// The peer responder's clients, the most recently seen first
+    pub(crate) fn clients(&self) -> Vec<peer::ClientRecord> {
+        self.clients.lock_or_recover().records()
+    }
+
+    /// How much each server contributed to the last successful sync
+    pub(crate) fn source_weights(&self) -> Vec<SourceWeight> {
+        self.source_weights.lock_or_recover().clone()
+    }
+
+    /// The servers taken out of selection because they synchronize to this host, sorted
+    pub(crate) fn timing_loops(&self) -> Vec<String> {
+        let states = self.source_states.lock_or_recover();
+        let mut looped: Vec<String> = states
+            .iter()
+            .filter(|(_, state)| state.in_loop)
+            .map(|(server, _)| server.clone())
+            .collect();
+        looped.sort();
+        looped
+    }
+
+    /// The server of the last sync, `None` before the first
+    pub(crate) fn reference(&self) -> Option<ReferenceInfo> {
+        self.reference.lock_or_recover().clone()
+    }
+
+    /// Returns the interval between background syncs
+    pub(crate) fn sync_interval(&self) -> Duration {
+        self.control.lock_or_recover().interval
+    }
+
+    /// Whether the last attempt to sync reached no server or peer
+    pub(crate) fn is_offline(&self) -> bool {
+        self.stats.lock_or_recover().consecutive_failures > 0
+    }
+
+    /// Time from one poll of the sync loop to the next: the sync interval, or while the
+    /// clock is offline and [`ClockConfig::offline_retry_max`] is set, a wait that starts
+    /// at [`OFFLINE_RETRY_START`] and doubles with every failure up to that limit
+    fn poll_interval(&self) -> Duration {
+        let interval = self.sync_interval();
+        let Some(max) = *self.offline_retry_max.read_or_recover() else {
+            return interval;
+        };
+        let failures = self.stats.lock_or_recover().consecutive_failures;
+        if failures == 0 {
+            return interval;
+        }
+        let doublings = (failures - 1).min(u32::BITS as u64 - 1) as u32;
+        OFFLINE_RETRY_START
+            .saturating_mul(1 << doublings)
+            .min(max.max(MIN_SYNC_INTERVAL))
+            .min(interval)
+    }
+
+    /// Applies the settings of `config` that can change while the clock runs: servers,
+    /// peers, sync interval, sample selection, staleness threshold, and anomaly reporting.
+    /// Emits [`ClockEvent::ConfigReloaded`] with the settings that changed.
+    pub(crate) fn reconfigure(&self, config: &ClockConfig) -> Vec<ConfigChange> {
+        let config = &config.effective();
+        let changes = {
+            let mut applied = self.applied_config.lock_or_recover();
+            applied.sync_interval = self.sync_interval();
+            let changes = applied.diff(config);
+            *applied = config.clone();
+            changes
+        };
+        *self.ntp_servers.write_or_recover() = Self::with_discovered_servers(config);
+        *self.peers.write_or_recover() = config.peers.clone();
+        {
+            let mut poll_settings = self.poll_settings.write_or_recover();
+            #[cfg(feature = "nts")]
+            let nts = poll_settings.nts.clone();
+            *poll_settings = PollSettings::new(config);
+            // Sessions are kept unless they now live elsewhere
+            #[cfg(feature = "nts")]
+            if poll_settings.nts.path() == nts.path() {
+                poll_settings.nts = nts;
+            }
+        }
+        *self.staleness_threshold.write_or_recover() = config.staleness_threshold;
+        *self.anomaly_threshold.write_or_recover() = config.anomaly_threshold;
+        *self.anomaly_hooks.write_or_recover() = config.anomaly_hooks.clone();
+        self.alerts.configure(config);
+        *self.smoothing.write_or_recover() = config.smoothing;
+        self.resync_on_network_change
+            .store(config.resync_on_network_change, Ordering::Relaxed);
+        *self.offline_retry_max.write_or_recover() = config.offline_retry_max;
+        self.clients
+            .lock_or_recover()
+            .configure(config.rate_limit, config.mru_size);
+        *self.rtc_check.write_or_recover() = config.rtc_check();
+        *self.rtc_write.write_or_recover() = config.rtc_write();
+        {
+            let cap = config.max_slew_ppm.max(0.0);
+            let mut max_slew_ppm = self.max_slew_ppm.write_or_recover();
+            if cap < *max_slew_ppm {
+                self.base.write_or_recover().cap_slew(cap);
+            }
+            *max_slew_ppm = cap;
+        }
+        {
+            let mut orphan = self.orphan.lock_or_recover();
+            orphan.after = config.orphan_after;
+            orphan.stratum = config.orphan_stratum;
+        }
+        if config.sync_interval.max(MIN_SYNC_INTERVAL) != self.sync_interval() {
+            self.set_sync_interval(config.sync_interval);
+        }
+        if changes
+            .iter()
+            .any(|change| matches!(change.setting, "audit_log" | "audit_hash_chain"))
+        {
+            self.set_audit_log(Self::open_audit_log(config));
+        }
+        for change in &changes {
+            clock_log!(Info, Config, "Configuration changed: {}", change);
+        }
+        self.events.emit(ClockEvent::ConfigReloaded {
+            changes: changes.clone(),
+        });
+        changes
+    }
+
+    /// Persists the current time if it is NTP-derived, e.g. before the process exits
+    pub(crate) fn flush_state(&self) {
+        if self.is_synchronized() {
+            self.persist_time(self.get_current_time());
+        }
+    }
+
+    /// Changes the interval between background syncs, waking the worker so it takes
+    /// effect immediately
+    pub(crate) fn set_sync_interval(&self, interval: Duration) {
+        let mut control = self.control.lock_or_recover();
+        control.interval = interval.max(MIN_SYNC_INTERVAL);
+        control.interval_changed = true;
+        self.wake.notify_all();
+    }
+
+    /// Tells the sync loop that the network configuration changed, so it resyncs at once
+    pub(crate) fn network_changed(&self) {
+        self.control.lock_or_recover().network_changed = true;
+        self.wake.notify_all();
+    }
+
+    /// Body of the network watcher thread: while
+    /// [`resync_on_network_change`](crate::ClockConfig::resync_on_network_change) is set,
+    /// waits for network change notifications and passes them on to the sync loop
+    fn watch_network(&self, shutdown: &AtomicBool) {
+        let mut watcher: Option<NetworkWatcher> = None;
+        let mut warned = false;
+        loop {
+            if self.control.lock_or_recover().stop || shutdown.load(Ordering::Relaxed) {
+                return;
+            }
+            if !self.resync_on_network_change.load(Ordering::Relaxed) {
+                watcher = None;
+                self.pause(TICK);
+                continue;
+            }
+            let result = match watcher.as_mut() {
+                Some(active) => active.wait(NETWORK_WATCH_SLICE),
+                None => NetworkWatcher::new().map(|created| {
+                    watcher = Some(created);
+                    warned = false;
+                    false
+                }),
+            };
+            match result {
+                Ok(true) => self.network_changed(),
+                Ok(false) => {}
+                Err(e) => {
+                    if !std::mem::replace(&mut warned, true) {
+                        clock_log!(Warn, Worker, "Cannot watch for network changes: {}", e);
+                    }
+                    watcher = None;
+                    self.pause(TICK);
+                }
+            }
+        }
+    }
+
+    /// Waits up to `timeout`, returning early if the worker is stopped
+    fn pause(&self, timeout: Duration) {
+        let control = self.control.lock_or_recover();
+        if !control.stop {
+            drop(lock::recover(self.wake.wait_timeout(control, timeout)));
+        }
+    }
+
+    /// Waits up to `timeout` (capped at one tick), returning early if the worker is stopped,
+    /// the loop of `generation` was replaced, the interval changes, or the network does
+    fn wait(&self, timeout: Duration, shutdown: &AtomicBool, generation: u64) -> Wake {
+        let mut control = self.control.lock_or_recover();
+        if !control.stop && !control.interval_changed && !control.network_changed {
+            control = lock::recover(self.wake.wait_timeout(control, timeout.min(self.tick()))).0;
+        }
+
+        if control.stop || control.generation != generation || shutdown.load(Ordering::Relaxed) {
+            Wake::Stop
+        } else if std::mem::take(&mut control.interval_changed) {
+            Wake::IntervalChanged
+        } else if std::mem::take(&mut control.network_changed) {
+            Wake::NetworkChanged
+        } else {
+            Wake::Timeout
+        }
+    }
+
+    /// Body of the watchdog thread: runs the sync loop on a thread of its own and replaces
+    /// it when it exits or stops completing cycles
+    fn watch(self: &Arc<Self>, shutdown: &Arc<AtomicBool>) {
+        let mut thread = self.spawn_sync_loop(shutdown);
+        loop {
+            let mut control = self.control.lock_or_recover();
+            if !control.stop {
+                control = lock::recover(self.wake.wait_timeout(control, TICK)).0;
+            }
+            // Checked before the flags: a loop that exited because of them is not restarted
+            let finished = thread.is_finished();
+            if control.stop || shutdown.load(Ordering::Relaxed) {
+                break;
+            }
+            drop(control);
+
+            let message = if finished {
+                "sync loop exited".to_string()
+            } else if let Some(age) = self.stalled_for() {
+                format!("sync loop stalled for {}s", age.as_secs())
+            } else {
+                continue;
+            };
+            // A stalled thread cannot be killed; it is left to notice it was replaced. The
+            // restart is reported once its replacement is running.
+            thread = self.spawn_sync_loop(shutdown);
+            self.record_worker_restart(message);
+        }
+        if thread.join().is_err() {
+            clock_log!(Error, Worker, "Background sync thread panicked");
+        }
+    }
+
+    /// Starts a new generation of the sync loop, retiring any previous one
+    fn spawn_sync_loop(self: &Arc<Self>, shutdown: &Arc<AtomicBool>) -> JoinHandle<()> {
+        let generation = {
+            let mut control = self.control.lock_or_recover();
+            control.generation += 1;
+            control.heartbeat = Some(Instant::now());
+            control.generation
+        };
+        self.wake.notify_all();
+        let shared = Arc::clone(self);
+        let shutdown = Arc::clone(shutdown);
+        std::thread::spawn(move || shared.supervise(&shutdown, generation))
+    }
+
+    /// How long the sync loop has gone without completing a cycle, if that counts as a stall
+    fn stalled_for(&self) -> Option<Duration> {
+        let control = self.control.lock_or_recover();
+        let age = control.heartbeat?.elapsed();
+        let limit = (control.interval * STALL_FACTOR).max(MIN_STALL_TIMEOUT);
+        (age > limit).then_some(age)
+    }
+
+    /// Records that the sync loop of `generation` is alive, unless it was replaced
+    fn beat(&self, generation: u64) {
+        let mut control = self.control.lock_or_recover();
+        if control.generation == generation {
+            control.heartbeat = Some(Instant::now());
+        }
+    }
+
+    /// Counts and reports a restart of the sync loop
+    fn record_worker_restart(&self, message: String) {
+        clock_log!(
+            Error,
+            Worker,
+            "Restarting the background sync loop: {}",
+            message
+        );
+        self.stats.lock_or_recover().worker_restarts += 1;
+        self.events.emit(ClockEvent::WorkerRestarted { message });
+    }
+
+    /// When the sync loop last completed a cycle, `None` if no worker is running
+    pub(crate) fn last_heartbeat(&self) -> Option<Instant> {
+        self.control.lock_or_recover().heartbeat
+    }
+
+    /// Body of the background sync thread
+    fn run(&self, shutdown: &AtomicBool, generation: u64) {
+        let mut detector = SuspendDetector::default();
+        'cycles: while !shutdown.load(Ordering::Relaxed) {
+            {
+                let control = self.control.lock_or_recover();
+                if control.stop || control.generation != generation {
+                    break;
+                }
+            }
+            let mut cycle_start = self.instant();
+            self.update_latest_time();
+            self.write_rtc_if_due();
+            self.write_status_file();
+            self.beat(generation);
+            let poll_interval = self.poll_interval();
+            if poll_interval < self.sync_interval() {
+                clock_log!(
+                    Warn,
+                    Cycle,
+                    "No source reachable, retrying in {}s",
+                    poll_interval.as_secs()
+                );
+            }
+            let mut poll_jitter = Duration::ZERO;
+            if self.poll_settings.read_or_recover().best_practices {
+                // Keep clients started together from polling in lockstep
+                poll_jitter = random_fraction(poll_interval / POLL_JITTER_DIVISOR);
+            }
+            clock_log!(
+                Info,
+                Cycle,
+                "Updated the time: {}",
+                self.base.read_or_recover().latest_time
+            );
+
+            loop {
+                let deadline = cycle_start + self.poll_interval() + poll_jitter;
+                let now = self.instant();
+                if now >= deadline {
+                    break;
+                }
+
+                let network_changed = match self.wait(deadline - now, shutdown, generation) {
+                    Wake::Stop => break 'cycles,
+                    Wake::IntervalChanged => {
+                        clock_log!(
+                            Info,
+                            Cycle,
+                            "Sync interval changed to {:?}",
+                            self.sync_interval()
+                        );
+                        continue;
+                    }
+                    Wake::NetworkChanged => {
+                        clock_log!(Info, Sync, "Network configuration changed, resyncing");
+                        true
+                    }
+                    Wake::Timeout => false,
+                };
+
+                let suspended = detector.check();
+                if let Some(gap) = suspended {
+                    clock_log!(
+                        Warn,
+                        Sync,
+                        "Detected system suspend of ~{}s, resyncing",
+                        gap.as_secs()
+                    );
+                }
+                if network_changed || suspended.is_some() {
+                    if !self.burst_resync(shutdown, generation) {
+                        break 'cycles;
+                    }
+                    // Changes reported during the burst were covered by its retries
+                    self.control.lock_or_recover().network_changed = false;
+                    self.beat(generation);
+                    detector = SuspendDetector::default();
+                    cycle_start = self.instant();
+                }
+            }
+        }
+        clock_log!(Info, Worker, "Background sync thread shutting down");
+    }
+
+    /// Runs the sync loop, restarting it one interval after a panic instead of letting the
+    /// thread die
+    fn supervise(&self, shutdown: &AtomicBool, generation: u64) {
+        loop {
+            let run = || self.run(shutdown, generation);
+            let Err(payload) = panic::catch_unwind(AssertUnwindSafe(run)) else {
+                return;
+            };
+            self.record_worker_restart(format!("panicked: {}", panic_message(payload.as_ref())));
+
+            // Back off so a panic on every cycle does not spin
+            let deadline = self.instant() + self.sync_interval();
+            loop {
+                let now = self.instant();
+                if now >= deadline {
+                    break;
+                }
+                if self.wait(deadline - now, shutdown, generation) == Wake::Stop {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Retries `resync_now` a few times, spacing the attempts out. Returns `false` if the
+    /// worker was stopped meanwhile.
+    fn burst_resync(&self, shutdown: &AtomicBool, generation: u64) -> bool {
+        for attempt in 1..=BURST_ATTEMPTS {
+            if self.resync_now() {
+                return true;
+            }
+            if attempt < BURST_ATTEMPTS {
+                let resume_at = self.instant() + BURST_SPACING;
+                while let Some(remaining) = resume_at.checked_duration_since(self.instant()) {
+                    if self.wait(remaining, shutdown, generation) == Wake::Stop {
+                        return false;
+                    }
+                }
+            }
+        }
+        clock_log!(
+            Warn,
+            Sync,
+            "Burst resync failed after {} attempts",
+            BURST_ATTEMPTS
+        );
+        true
+    }
+
+    /// Records the offset between a fresh NTP time and the local clock, returning it in seconds
+    fn record_offset(&self, ntp_time: Timestamp, local: Timestamp) -> f64 {
+        let offset = ntp_time.seconds_since(local);
+
+        let mut history = self.offset_history.lock_or_recover();
+        if history.len() == MAX_OFFSET_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(OffsetSample {
+            timestamp: ntp_time,
+            offset,
+        });
+        offset
+    }
+
+    /// Appends to the exportable sync history, dropping the oldest record when it is full
+    fn record_sync(&self, record: SyncRecord) {
+        match record.adjustment {
+            Some(AdjustmentKind::Step) => {
+                self.stats.lock_or_recover().steps += 1;
+                let threshold = *self.alerts.large_step_threshold.read_or_recover();
+                if record.offset.abs() > threshold.as_secs_f64() {
+                    self.alerts.raise(Alert {
+                        kind: AlertKind::LargeStep,
+                        time: self.get_current_time(),
+                        server: Some(record.server.clone()),
+                        magnitude: Some(record.offset),
+                        message: format!("clock stepped by {:+.6}s", record.offset),
+                    });
+                }
+            }
+            Some(AdjustmentKind::Slew) => self.stats.lock_or_recover().slews += 1,
+            Some(AdjustmentKind::RtcConflict) | None => {}
+        }
+        let mut history = self.sync_history.lock_or_recover();
+        if history.len() == MAX_SYNC_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(record);
+    }
+
+    /// Appends to the recent sync attempts, dropping the oldest when full
+    fn record_attempt(&self, attempt: SyncAttempt) {
+        let mut attempts = self.recent_attempts.lock_or_recover();
+        if attempts.len() == MAX_RECENT_ATTEMPTS {
+            attempts.pop_front();
+        }
+        attempts.push_back(attempt);
+    }
+
+    /// Jitter of the offsets of the recent successful attempts
+    fn attempt_jitter(&self) -> f64 {
+        let attempts = self.recent_attempts.lock_or_recover();
+        let offsets: Vec<OffsetSample> = attempts
+            .iter()
+            .filter_map(|attempt| {
+                Some(OffsetSample {
+                    timestamp: attempt.at,
+                    offset: attempt.offset?,
+                })
+            })
+            .collect();
+        statsfile::offset_jitter(&offsets)
+    }
+
+    /// Returns the last `n` sync attempts, oldest first
+    pub(crate) fn recent_attempts(&self, n: usize) -> Vec<SyncAttempt> {
+        let attempts = self.recent_attempts.lock_or_recover();
+        attempts
+            .iter()
+            .skip(attempts.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the sync history, oldest first
+    pub(crate) fn sync_history(&self) -> Vec<SyncRecord> {
+        self.sync_history
+            .lock_or_recover()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Reports the newest offset as a [`ClockEvent::Anomaly`] if it looks suspicious
+    fn check_for_anomaly(&self, sample: &NtpSample) {
+        let threshold = self.anomaly_threshold.read_or_recover().as_secs_f64();
+        let Some(kind) = anomaly::detect(&self.offset_history(), threshold) else {
+            return;
+        };
+        self.report_anomaly(Anomaly {
+            server: sample.server.clone(),
+            time: sample.time,
+            kind,
+        });
+    }
+
+    /// Reports `anomaly` to the hooks, the alerts, and the event stream
+    fn report_anomaly(&self, anomaly: Anomaly) {
+        clock_log!(Warn, Anomaly, "Anomaly: {}", anomaly);
+        for hook in self.anomaly_hooks.read_or_recover().iter() {
+            hook.fire(&anomaly);
+        }
+        self.alerts.raise(Alert {
+            kind: AlertKind::Anomaly,
+            time: self.get_current_time(),
+            server: Some(anomaly.server.clone()),
+            magnitude: Some(anomaly.kind.magnitude()),
+            message: anomaly.to_string(),
+        });
+        self.events.emit(ClockEvent::Anomaly(anomaly));
+    }
+
+    /// Enables (or disables with `None`) ntpd-style statistics files
+    pub(crate) fn set_stats_logger(&self, logger: Option<StatsLogger>) {
+        *self.stats_logger.lock_or_recover() = logger;
+    }
+
+    /// Enables (or disables with `None`) the status file written after each cycle
+    pub(crate) fn set_status_file(&self, path: Option<std::path::PathBuf>) {
+        *self.status_file.lock_or_recover() = path;
+    }
+
+    /// Rewrites the status file, if one is enabled
+    fn write_status_file(&self) {
+        let Some(path) = self.status_file.lock_or_recover().clone() else {
+            return;
+        };
+        let (last_offset, last_success, consecutive_failures) = {
+            let stats = self.stats.lock_or_recover();
+            (
+                stats.last_offset,
+                stats.last_success,
+                stats.consecutive_failures,
+            )
+        };
+        let status = statusfile::Status {
+            health: self.health(),
+            synchronized: self.is_synchronized(),
+            source: self.base.read_or_recover().source,
+            stratum: self.stratum(),
+            time: self.get_current_time(),
+            last_offset,
+            last_success,
+            consecutive_failures,
+            poll_interval: self.poll_interval(),
+        };
+        if let Err(e) = statusfile::write(&path, &status) {
+            clock_log!(
+                Warn,
+                Storage,
+                "Failed to write status file {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    /// Enables (or disables with `None`) the adjustment audit log
+    pub(crate) fn set_audit_log(&self, log: Option<AuditLog>) {
+        *self.audit_log.lock_or_recover() = log;
+    }
+
+    /// Path of the adjustment audit log, if one is enabled
+    pub(crate) fn audit_log_path(&self) -> Option<std::path::PathBuf> {
+        let log = self.audit_log.lock_or_recover();
+        log.as_ref().map(|log| log.path().to_path_buf())
+    }
+
+    /// Opens the audit log `config` names, if any, logging rather than failing if it cannot
+    fn open_audit_log(config: &ClockConfig) -> Option<AuditLog> {
+        let path = config.audit_log.as_ref()?;
+        match AuditLog::open(path) {
+            Ok(log) => Some(log.with_hash_chain(config.audit_hash_chain)),
+            Err(e) => {
+                clock_log!(
+                    Error,
+                    Storage,
+                    "Failed to open audit log {}: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Records an adjustment of the clock from `before` to `sample`'s time in the audit log
+    fn audit(&self, kind: AdjustmentKind, before: Timestamp, after: Timestamp, sample: &NtpSample) {
+        append_audit(
+            &mut self.audit_log.lock_or_recover(),
+            kind,
+            before,
+            after,
+            sample,
+        );
+    }
+
+    /// Writes peerstats and loopstats records for a successful sample
+    fn log_statistics(&self, sample: &NtpSample, offset: f64) {
+        let logger = self.stats_logger.lock_or_recover();
+        let Some(logger) = logger.as_ref() else {
+            return;
+        };
+
+        let history = self.offset_history();
+        let peer = PeerRecord {
+            time: sample.time,
+            addr: sample.addr,
+            offset,
+            delay: sample.delay.as_secs_f64(),
+            dispersion: sample.root_dispersion,
+            jitter: statsfile::offset_jitter(&history),
+        };
+        if let Err(e) = logger.log_peer(&peer) {
+            clock_log!(Warn, Storage, "Failed to write peerstats record: {}", e);
+        }
+
+        if let Some(record) = LoopRecord::from_history(&history) {
+            if let Err(e) = logger.log_loop(&record) {
+                clock_log!(Warn, Storage, "Failed to write loopstats record: {}", e);
+            }
+        }
+    }
+
+    /// Returns the stored offset history, oldest first
+    pub(crate) fn offset_history(&self) -> Vec<OffsetSample> {
+        self.offset_history
+            .lock_or_recover()
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// Estimates the local oscillator's frequency error from the offset history
+    pub(crate) fn drift_ppm(&self) -> Option<f64> {
+        stability::drift_ppm(self.offset_history.lock_or_recover().make_contiguous())
+    }
+
+    /// Computes Allan deviation of the offset history at octave-spaced averaging times
+    pub(crate) fn stability(&self) -> Vec<StabilityPoint> {
+        stability::analyze(&self.offset_history())
+    }
+}
@@ -0,0 +1,91 @@
+//! # Errors
+//!
+//! Error types returned by fallible clock operations.
+
+use crate::Timestamp;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// Errors returned by clock operations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClockError {
+    /// The clock did not synchronize within the allowed time
+    Timeout(Duration),
+    /// The clock has no NTP time and its fallback policy forbids reporting any other
+    NotSynchronized,
+    /// A format string contained an unsupported specifier
+    InvalidFormat(String),
+    /// No configured time source could be reached
+    SourceUnavailable(String),
+}
+
+impl fmt::Display for ClockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClockError::Timeout(timeout) => {
+                write!(f, "clock not synchronized within {:?}", timeout)
+            }
+            ClockError::NotSynchronized => write!(f, "clock has not been synchronized yet"),
+            ClockError::InvalidFormat(fmt) => write!(f, "invalid format string '{}'", fmt),
+            ClockError::SourceUnavailable(reason) => {
+                write!(f, "no time source available: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClockError {}
+
+/// Errors from loading or installing a leap-second table
+#[derive(Debug)]
+pub enum LeapSecondError {
+    /// The file could not be read
+    Io(io::Error),
+    /// A line of the file could not be parsed
+    Parse { line: usize, message: String },
+    /// The file contained no leap seconds
+    Empty,
+    /// The table expired at the given time
+    Expired(Timestamp),
+    /// The table expires earlier than the one already in use
+    Outdated {
+        current: Timestamp,
+        candidate: Timestamp,
+    },
+}
+
+impl fmt::Display for LeapSecondError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeapSecondError::Io(e) => write!(f, "failed to read leap-second table: {}", e),
+            LeapSecondError::Parse { line, message } => {
+                write!(f, "invalid leap-second table at line {}: {}", line, message)
+            }
+            LeapSecondError::Empty => write!(f, "leap-second table has no entries"),
+            LeapSecondError::Expired(expires) => {
+                write!(f, "leap-second table expired on {}", expires)
+            }
+            LeapSecondError::Outdated { current, candidate } => write!(
+                f,
+                "leap-second table expiring {} is older than the one in use (expiring {})",
+                candidate, current
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LeapSecondError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LeapSecondError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LeapSecondError {
+    fn from(e: io::Error) -> Self {
+        LeapSecondError::Io(e)
+    }
+}
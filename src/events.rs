@@ -0,0 +1,73 @@
+//! # Clock Events
+//!
+//! Notifications about changes to a running clock, delivered through standard channels.
+//! Each call to [`Clock::events`](crate::Clock::events) returns a new receiver that sees
+//! every event emitted afterwards; receivers that are dropped are forgotten.
+
+use crate::anomaly::Anomaly;
+use crate::config::ConfigChange;
+use crate::lock::MutexExt;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Something that happened to a clock
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ClockEvent {
+    /// A new configuration was applied, e.g. by a [`ConfigWatcher`](crate::ConfigWatcher)
+    /// or [`Clock::reconfigure`](crate::Clock::reconfigure). Lists the settings that
+    /// changed, which may be none.
+    ConfigReloaded { changes: Vec<ConfigChange> },
+    /// A sample looked like the time servers or the path to them are being tampered with.
+    /// The sample is still used; see [`anomaly`](crate::anomaly).
+    Anomaly(Anomaly),
+    /// The background sync loop panicked and was restarted after one sync interval. The
+    /// clock keeps reporting time meanwhile; `message` is the panic's message.
+    WorkerRestarted { message: String },
+    /// No server was reachable for
+    /// [`ClockConfig::orphan_after`](crate::ClockConfig::orphan_after): the clock now
+    /// free-runs from its last NTP time with drift compensation and reports the orphan
+    /// stratum
+    OrphanModeEntered { unreachable_for: Duration },
+    /// A server answered again, ending orphan mode
+    OrphanModeLeft { orphaned_for: Duration },
+}
+
+/// Fans events out to all subscribers
+#[derive(Default)]
+pub(crate) struct EventBus {
+    subscribers: Mutex<Vec<Sender<ClockEvent>>>,
+}
+
+impl EventBus {
+    pub(crate) fn subscribe(&self) -> Receiver<ClockEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock_or_recover().push(sender);
+        receiver
+    }
+
+    pub(crate) fn emit(&self, event: ClockEvent) {
+        self.subscribers
+            .lock_or_recover()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_reach_live_subscribers() {
+        let bus = EventBus::default();
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        drop(second);
+
+        let event = ClockEvent::ConfigReloaded { changes: vec![] };
+        bus.emit(event.clone());
+        assert_eq!(first.try_recv(), Ok(event));
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+    }
+}
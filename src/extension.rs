@@ -0,0 +1,439 @@
+//! # NTPv4 Extension Fields
+//!
+//! An NTPv4 packet may carry extension fields after its 48-byte header (RFC 7822), each a
+//! 16-bit type and length followed by a value padded to a multiple of four bytes, and then
+//! an optional legacy MAC. Network Time Security (RFC 8915) sends its unique identifier,
+//! cookies, and authenticator this way.
+//!
+//! [`parse_packet`] splits a packet into its header, fields, and MAC, validating every
+//! length, and [`encode_packet`] builds one. An [`ExtensionRegistry`] hands parsed fields to
+//! the [`ExtensionHandler`] registered for their type and skips the rest, as RFC 7822 asks
+//! of receivers:
+//!
+//! ```
+//! use clock::extension::{self, ExtensionField, ExtensionRegistry, UNIQUE_IDENTIFIER};
+//! use clock::sntp;
+//!
+//! let uid = ExtensionField::new(UNIQUE_IDENTIFIER, vec![7; 32]);
+//! let packet = extension::encode_packet(&sntp::client_request(), &[uid], None);
+//! let parsed = extension::parse_packet(&packet).unwrap();
+//!
+//! let mut seen = Vec::new();
+//! let mut registry = ExtensionRegistry::new();
+//! registry.register(UNIQUE_IDENTIFIER, |field: &ExtensionField| {
+//!     seen.push(field.value.clone());
+//!     Ok(())
+//! });
+//! assert_eq!(registry.dispatch(&parsed.fields), Ok(1));
+//! drop(registry);
+//! assert_eq!(seen, vec![vec![7; 32]]);
+//! ```
+
+use crate::sntp::PACKET_LEN;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// RFC 8915 Unique Identifier, echoed by the server to match a reply to its request
+pub const UNIQUE_IDENTIFIER: u16 = 0x0104;
+/// RFC 8915 NTS Cookie
+pub const NTS_COOKIE: u16 = 0x0204;
+/// RFC 8915 NTS Cookie Placeholder, asking the server for another cookie
+pub const NTS_COOKIE_PLACEHOLDER: u16 = 0x0304;
+/// RFC 8915 NTS Authenticator and Encrypted Extension Fields
+pub const NTS_AUTHENTICATOR: u16 = 0x0404;
+
+/// Size of a field's type and length
+pub const FIELD_HEADER_LEN: usize = 4;
+
+/// Shortest extension field RFC 7822 allows
+pub const MIN_FIELD_LEN: usize = 16;
+
+/// Shortest last field of a packet without a MAC, so that it cannot be taken for one
+pub const MIN_LAST_FIELD_LEN: usize = 28;
+
+/// Longest legacy MAC: a key ID and a SHA-1 digest
+pub const MAX_MAC_LEN: usize = 24;
+
+/// One extension field, its value including any padding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionField {
+    pub field_type: u16,
+    pub value: Vec<u8>,
+}
+
+impl ExtensionField {
+    /// A field of `field_type` carrying `value`
+    pub fn new(field_type: u16, value: Vec<u8>) -> Self {
+        ExtensionField { field_type, value }
+    }
+
+    /// The field's length on the wire, with its header and padding, when at least
+    /// `min_len` long
+    fn encoded_len(&self, min_len: usize) -> usize {
+        ((FIELD_HEADER_LEN + self.value.len() + 3) & !3).max(min_len)
+    }
+
+    /// Appends the field, zero-padded to a multiple of four bytes and to `min_len`. Values
+    /// too long for a UDP datagram are not supported.
+    fn encode_into(&self, out: &mut Vec<u8>, min_len: usize) {
+        let start = out.len();
+        let len = self.encoded_len(min_len);
+        out.extend_from_slice(&self.field_type.to_be_bytes());
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+        out.extend_from_slice(&self.value);
+        out.resize(start + len, 0);
+    }
+}
+
+/// A packet split into its header, extension fields, and legacy MAC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedPacket<'a> {
+    pub header: [u8; PACKET_LEN],
+    pub fields: Vec<ExtensionField>,
+    /// The key ID and digest after the fields, or a 4-byte crypto-NAK, if present
+    pub mac: Option<&'a [u8]>,
+}
+
+/// Why a packet's extension fields could not be parsed, or a handler rejected one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionError {
+    /// The packet is shorter than an NTP header
+    TooShort { len: usize },
+    /// A field's length is not a multiple of four, shorter than its header, or runs past
+    /// the end of the packet
+    InvalidLength { offset: usize, len: u16 },
+    /// What follows the fields is too short for a field and not a MAC
+    TrailingBytes { len: usize },
+    /// A handler refused the field
+    Rejected {
+        field_type: u16,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for ExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtensionError::TooShort { len } => {
+                write!(f, "{}-byte packet is shorter than an NTP header", len)
+            }
+            ExtensionError::InvalidLength { offset, len } => {
+                write!(
+                    f,
+                    "extension field at byte {} has invalid length {}",
+                    offset, len
+                )
+            }
+            ExtensionError::TrailingBytes { len } => {
+                write!(f, "{} bytes after the extension fields are not a MAC", len)
+            }
+            ExtensionError::Rejected { field_type, reason } => {
+                write!(
+                    f,
+                    "extension field {:#06x} rejected: {}",
+                    field_type, reason
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ExtensionError {}
+
+/// Splits `packet` into its header, extension fields, and MAC.
+///
+/// As in RFC 7822, anything after the header longer than [`MAX_MAC_LEN`] starts with an
+/// extension field; what remains after the fields must be empty or a MAC of 4, 20, or 24
+/// bytes.
+pub fn parse_packet(packet: &[u8]) -> Result<ParsedPacket<'_>, ExtensionError> {
+    let header: [u8; PACKET_LEN] = packet
+        .get(..PACKET_LEN)
+        .and_then(|header| header.try_into().ok())
+        .ok_or(ExtensionError::TooShort { len: packet.len() })?;
+    let mut fields = Vec::new();
+    let mut offset = PACKET_LEN;
+    while packet.len() - offset > MAX_MAC_LEN {
+        let rest = &packet[offset..];
+        let field_type = u16::from_be_bytes([rest[0], rest[1]]);
+        let len = u16::from_be_bytes([rest[2], rest[3]]);
+        let size = usize::from(len);
+        if size < FIELD_HEADER_LEN || size % 4 != 0 || size > rest.len() {
+            return Err(ExtensionError::InvalidLength { offset, len });
+        }
+        fields.push(ExtensionField::new(
+            field_type,
+            rest[FIELD_HEADER_LEN..size].to_vec(),
+        ));
+        offset += size;
+    }
+    let mac = match packet.len() - offset {
+        0 => None,
+        4 | 20 | 24 => Some(&packet[offset..]),
+        len => return Err(ExtensionError::TrailingBytes { len }),
+    };
+    Ok(ParsedPacket {
+        header,
+        fields,
+        mac,
+    })
+}
+
+/// Builds a packet from `header`, `fields`, and an optional `mac`. Fields are padded to at
+/// least [`MIN_FIELD_LEN`] bytes, and the last to [`MIN_LAST_FIELD_LEN`] when there is no
+/// MAC, so that [`parse_packet`] reads them back.
+pub fn encode_packet(
+    header: &[u8; PACKET_LEN],
+    fields: &[ExtensionField],
+    mac: Option<&[u8]>,
+) -> Vec<u8> {
+    let mut packet = header.to_vec();
+    for (i, field) in fields.iter().enumerate() {
+        let last_without_mac = i + 1 == fields.len() && mac.is_none();
+        let min_len = if last_without_mac {
+            MIN_LAST_FIELD_LEN
+        } else {
+            MIN_FIELD_LEN
+        };
+        field.encode_into(&mut packet, min_len);
+    }
+    if let Some(mac) = mac {
+        packet.extend_from_slice(mac);
+    }
+    packet
+}
+
+/// Handles the extension fields of one type
+pub trait ExtensionHandler {
+    /// Processes `field`, or refuses it with an [`ExtensionError::Rejected`]
+    fn handle(&mut self, field: &ExtensionField) -> Result<(), ExtensionError>;
+}
+
+impl<F: FnMut(&ExtensionField) -> Result<(), ExtensionError>> ExtensionHandler for F {
+    fn handle(&mut self, field: &ExtensionField) -> Result<(), ExtensionError> {
+        self(field)
+    }
+}
+
+/// Routes extension fields to the handlers registered for their types
+#[derive(Default)]
+pub struct ExtensionRegistry<'a> {
+    handlers: Vec<(u16, Box<dyn ExtensionHandler + 'a>)>,
+}
+
+impl<'a> ExtensionRegistry<'a> {
+    /// A registry without handlers
+    pub fn new() -> Self {
+        ExtensionRegistry {
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Hands fields of `field_type` to `handler`, replacing any handler registered for it
+    pub fn register(&mut self, field_type: u16, handler: impl ExtensionHandler + 'a) {
+        self.handlers
+            .retain(|(registered, _)| *registered != field_type);
+        self.handlers.push((field_type, Box::new(handler)));
+    }
+
+    /// Whether a handler is registered for `field_type`
+    pub fn handles(&self, field_type: u16) -> bool {
+        self.handlers
+            .iter()
+            .any(|(registered, _)| *registered == field_type)
+    }
+
+    /// Hands each of `fields` to its handler in order, stopping at the first refusal.
+    /// Fields without a handler are skipped. Returns how many were handled.
+    pub fn dispatch(&mut self, fields: &[ExtensionField]) -> Result<usize, ExtensionError> {
+        let mut handled = 0;
+        for field in fields {
+            let handler = self
+                .handlers
+                .iter_mut()
+                .find(|(registered, _)| *registered == field.field_type);
+            if let Some((_, handler)) = handler {
+                handler.handle(field)?;
+                handled += 1;
+            }
+        }
+        Ok(handled)
+    }
+}
+
+impl fmt::Debug for ExtensionRegistry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtensionRegistry")
+            .field(
+                "field_types",
+                &self.handlers.iter().map(|(t, _)| *t).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// An NTS client request laid out as RFC 8915 clients send it: a unique identifier,
+    /// a cookie, two cookie placeholders, and an authenticator with a 16-byte nonce and a
+    /// 16-byte AES-SIV tag
+    fn nts_request() -> Vec<u8> {
+        let mut packet = vec![0x23];
+        packet.resize(PACKET_LEN, 0);
+        let mut field = |field_type: u16, value: &[u8]| {
+            packet.extend_from_slice(&field_type.to_be_bytes());
+            packet.extend_from_slice(&((value.len() + 4) as u16).to_be_bytes());
+            packet.extend_from_slice(value);
+        };
+        field(UNIQUE_IDENTIFIER, &[0xab; 32]);
+        field(NTS_COOKIE, &[0xcd; 100]);
+        field(NTS_COOKIE_PLACEHOLDER, &[0; 100]);
+        field(NTS_COOKIE_PLACEHOLDER, &[0; 100]);
+        let mut authenticator = vec![0, 16, 0, 16];
+        authenticator.extend_from_slice(&[0x11; 16]);
+        authenticator.extend_from_slice(&[0x22; 16]);
+        field(NTS_AUTHENTICATOR, &authenticator);
+        packet
+    }
+
+    #[test]
+    fn test_parse_nts_request() {
+        let packet = nts_request();
+        assert_eq!(packet.len(), 48 + 36 + 3 * 104 + 40);
+        let parsed = parse_packet(&packet).unwrap();
+        assert_eq!(parsed.header[0], 0x23);
+        assert_eq!(parsed.mac, None);
+        let types: Vec<u16> = parsed.fields.iter().map(|f| f.field_type).collect();
+        assert_eq!(
+            types,
+            [
+                UNIQUE_IDENTIFIER,
+                NTS_COOKIE,
+                NTS_COOKIE_PLACEHOLDER,
+                NTS_COOKIE_PLACEHOLDER,
+                NTS_AUTHENTICATOR
+            ]
+        );
+        assert_eq!(parsed.fields[0].value, [0xab; 32]);
+        assert_eq!(encode_packet(&parsed.header, &parsed.fields, None), packet);
+    }
+
+    #[test]
+    fn test_parse_legacy_mac() {
+        // A symmetric-key packet as ntpd sends it: key ID 1 and an MD5 digest
+        let mut packet = vec![0x23; PACKET_LEN];
+        packet.extend_from_slice(&1u32.to_be_bytes());
+        packet.extend_from_slice(&[0x5a; 16]);
+        let parsed = parse_packet(&packet).unwrap();
+        assert!(parsed.fields.is_empty());
+        assert_eq!(parsed.mac.map(<[u8]>::len), Some(20));
+
+        // And a crypto-NAK, a key ID of zero alone
+        let nak = [[0x24; PACKET_LEN].as_slice(), &[0; 4]].concat();
+        let parsed = parse_packet(&nak).unwrap();
+        assert_eq!(parsed.mac, Some([0u8; 4].as_slice()));
+    }
+
+    #[test]
+    fn test_encode_pads_fields() {
+        let header = [0u8; PACKET_LEN];
+        let fields = [
+            ExtensionField::new(0x0002, vec![1, 2, 3]),
+            ExtensionField::new(UNIQUE_IDENTIFIER, vec![9; 5]),
+        ];
+        let packet = encode_packet(&header, &fields, None);
+        assert_eq!(
+            packet.len(),
+            PACKET_LEN + MIN_FIELD_LEN + MIN_LAST_FIELD_LEN
+        );
+        assert_eq!(&packet[48..56], &[0, 2, 0, 16, 1, 2, 3, 0]);
+        let parsed = parse_packet(&packet).unwrap();
+        assert_eq!(parsed.fields[1].value[..5], [9; 5]);
+        assert!(parsed.fields[1].value[5..].iter().all(|&b| b == 0));
+
+        let mac = [0x77; 24];
+        let packet = encode_packet(&header, &fields[..1], Some(&mac));
+        assert_eq!(packet.len(), PACKET_LEN + MIN_FIELD_LEN + 24);
+        assert_eq!(parse_packet(&packet).unwrap().mac, Some(mac.as_slice()));
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_lengths() {
+        let mut packet = nts_request();
+        assert_eq!(
+            parse_packet(&packet[..40]),
+            Err(ExtensionError::TooShort { len: 40 })
+        );
+        assert_eq!(
+            parse_packet(&packet[..packet.len() - 8]),
+            Err(ExtensionError::InvalidLength {
+                offset: 48 + 36 + 3 * 104,
+                len: 40
+            })
+        );
+        packet[50..52].copy_from_slice(&35u16.to_be_bytes());
+        assert!(matches!(
+            parse_packet(&packet),
+            Err(ExtensionError::InvalidLength {
+                offset: 48,
+                len: 35
+            })
+        ));
+        packet[50..52].copy_from_slice(&0u16.to_be_bytes());
+        assert!(parse_packet(&packet).is_err());
+
+        // Too short for a field, and not a MAC
+        assert_eq!(
+            parse_packet(&[0u8; PACKET_LEN + 12]),
+            Err(ExtensionError::TrailingBytes { len: 12 })
+        );
+    }
+
+    #[test]
+    fn test_registry_dispatches_by_type() {
+        let fields = parse_packet(&nts_request()).unwrap().fields;
+        let mut cookies = 0;
+        let mut registry = ExtensionRegistry::new();
+        registry.register(NTS_COOKIE_PLACEHOLDER, |_: &ExtensionField| {
+            cookies += 1;
+            Ok(())
+        });
+        assert!(registry.handles(NTS_COOKIE_PLACEHOLDER));
+        assert!(!registry.handles(NTS_COOKIE));
+        assert_eq!(registry.dispatch(&fields), Ok(2));
+
+        registry.register(UNIQUE_IDENTIFIER, |field: &ExtensionField| {
+            Err(ExtensionError::Rejected {
+                field_type: field.field_type,
+                reason: "unexpected",
+            })
+        });
+        assert!(matches!(
+            registry.dispatch(&fields),
+            Err(ExtensionError::Rejected {
+                field_type: UNIQUE_IDENTIFIER,
+                ..
+            })
+        ));
+        drop(registry);
+        assert_eq!(cookies, 2);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_parse_packet_accepts_any_bytes(
+            bytes in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..256),
+        ) {
+            if let Ok(parsed) = parse_packet(&bytes) {
+                let len = PACKET_LEN
+                    + parsed.fields.iter().map(|f| FIELD_HEADER_LEN + f.value.len()).sum::<usize>()
+                    + parsed.mac.map_or(0, <[u8]>::len);
+                proptest::prop_assert_eq!(len, bytes.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,426 @@
+//! # Fault Injection
+//!
+//! Robustness tests of the filter and selection algorithms need a network that misbehaves
+//! the same way on every run. A [`FaultInjector`] wraps any [`Transport`] in a
+//! [`FaultyTransport`] that, driven by a seeded generator, loses exchanges, delays requests
+//! by latencies drawn from a [`Latency`] distribution, flips bits in replies, and duplicates
+//! or reorders replies. A duplicated or held-back reply is handed out in place of the reply
+//! to the next exchange with the same server, as a client that reads the first packet to
+//! arrive would see it.
+//!
+//! The engine creates a transport per poll, so wrap its factory with
+//! [`FaultInjector::factory`]; the transports it creates share the injector's generator,
+//! and the same seed gives the same faults across polls:
+//!
+//! ```no_run
+//! use clock::faults::{FaultConfig, FaultInjector, Latency};
+//! use clock::sntp::UdpTransport;
+//! use clock::transport::TransportFactory;
+//! use clock::{Clock, ClockConfig};
+//! use std::time::Duration;
+//!
+//! let injector = FaultInjector::new(
+//!     FaultConfig::new(42)
+//!         .with_loss(0.2)
+//!         .with_latency(Latency::Exponential { mean: Duration::from_millis(30) }),
+//! );
+//! let udp = TransportFactory::new("udp", UdpTransport::default);
+//! let clock = Clock::with_config(
+//!     ClockConfig::new().with_transport(Some(injector.factory(udp))),
+//! );
+//! println!("{:?}", injector.counts());
+//! ```
+
+use crate::lock::MutexExt;
+use crate::rng::SplitMix64;
+use crate::sntp::{Transport, PACKET_LEN};
+use crate::transport::TransportFactory;
+use std::f64::consts::TAU;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long requests are delayed on their way to the server
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Latency {
+    /// No added latency
+    #[default]
+    None,
+    /// The same latency for every request
+    Constant(Duration),
+    /// Uniformly distributed between `min` and `max`
+    Uniform { min: Duration, max: Duration },
+    /// Normally distributed, cut off at zero
+    Normal { mean: Duration, std_dev: Duration },
+    /// Exponentially distributed, like queueing delay on a busy link
+    Exponential { mean: Duration },
+}
+
+impl Latency {
+    /// A latency drawn from this distribution
+    fn sample(self, rng: &mut SplitMix64) -> Duration {
+        let seconds = match self {
+            Latency::None => return Duration::ZERO,
+            Latency::Constant(latency) => return latency,
+            Latency::Uniform { min, max } => {
+                let (min, max) = (min.as_secs_f64(), max.as_secs_f64().max(min.as_secs_f64()));
+                min + (max - min) * rng.next_f64()
+            }
+            Latency::Normal { mean, std_dev } => {
+                // Box-Muller; 1 - u keeps the logarithm finite
+                let (u1, u2) = (1.0 - rng.next_f64(), rng.next_f64());
+                let z = (-2.0 * u1.ln()).sqrt() * (TAU * u2).cos();
+                mean.as_secs_f64() + std_dev.as_secs_f64() * z
+            }
+            Latency::Exponential { mean } => -mean.as_secs_f64() * (1.0 - rng.next_f64()).ln(),
+        };
+        Duration::try_from_secs_f64(seconds.max(0.0)).unwrap_or(Duration::MAX)
+    }
+}
+
+/// Which faults to inject, and how often. Probabilities are per exchange, between 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultConfig {
+    pub seed: u64,
+    /// Exchanges that fail with a timeout, as if the request or its reply was lost
+    pub loss: f64,
+    /// Replies that arrive twice
+    pub duplication: f64,
+    /// Replies that arrive after the reply to the next exchange
+    pub reordering: f64,
+    /// Replies with bits flipped in transit
+    pub corruption: f64,
+    pub latency: Latency,
+}
+
+impl FaultConfig {
+    /// No faults, with the generator seeded by `seed`
+    pub fn new(seed: u64) -> Self {
+        FaultConfig {
+            seed,
+            loss: 0.0,
+            duplication: 0.0,
+            reordering: 0.0,
+            corruption: 0.0,
+            latency: Latency::None,
+        }
+    }
+
+    pub fn with_loss(mut self, probability: f64) -> Self {
+        self.loss = probability;
+        self
+    }
+
+    pub fn with_duplication(mut self, probability: f64) -> Self {
+        self.duplication = probability;
+        self
+    }
+
+    pub fn with_reordering(mut self, probability: f64) -> Self {
+        self.reordering = probability;
+        self
+    }
+
+    pub fn with_corruption(mut self, probability: f64) -> Self {
+        self.corruption = probability;
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Latency) -> Self {
+        self.latency = latency;
+        self
+    }
+}
+
+/// How many faults have been injected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultCounts {
+    pub exchanges: u64,
+    pub lost: u64,
+    pub delayed: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub corrupted: u64,
+    /// Duplicated or held-back replies handed out in place of a later one
+    pub stale_delivered: u64,
+}
+
+#[derive(Debug)]
+struct State {
+    rng: SplitMix64,
+    /// Replies still in flight, oldest first
+    in_flight: Vec<(SocketAddr, [u8; PACKET_LEN])>,
+    counts: FaultCounts,
+}
+
+impl State {
+    fn take_in_flight(&mut self, server: &SocketAddr) -> Option<[u8; PACKET_LEN]> {
+        let index = self.in_flight.iter().position(|(to, _)| to == server)?;
+        Some(self.in_flight.remove(index).1)
+    }
+}
+
+/// Decides which faults to inject, shared by the transports it wraps. Clones share the
+/// generator and counts.
+#[derive(Debug, Clone)]
+pub struct FaultInjector {
+    config: FaultConfig,
+    state: Arc<Mutex<State>>,
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig) -> Self {
+        FaultInjector {
+            config,
+            state: Arc::new(Mutex::new(State {
+                rng: SplitMix64::new(config.seed),
+                in_flight: Vec::new(),
+                counts: FaultCounts::default(),
+            })),
+        }
+    }
+
+    pub fn config(&self) -> &FaultConfig {
+        &self.config
+    }
+
+    /// Faults injected so far
+    pub fn counts(&self) -> FaultCounts {
+        self.state.lock_or_recover().counts
+    }
+
+    /// Injects faults into the exchanges of `inner`
+    pub fn wrap<T>(&self, inner: T) -> FaultyTransport<T>
+    where
+        T: Transport<Address = SocketAddr, Error = io::Error>,
+    {
+        FaultyTransport {
+            inner,
+            injector: self.clone(),
+        }
+    }
+
+    /// A factory creating the transports of `inner`, wrapped by this injector
+    pub fn factory(&self, inner: TransportFactory) -> TransportFactory {
+        let injector = self.clone();
+        let name = format!("faulty {}", inner.name());
+        TransportFactory::new(name, move || injector.wrap(inner.create()))
+    }
+}
+
+/// A transport injecting faults into the exchanges of another, see [`FaultInjector`]
+#[derive(Debug)]
+pub struct FaultyTransport<T> {
+    inner: T,
+    injector: FaultInjector,
+}
+
+impl<T> FaultyTransport<T> {
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+fn lost() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "packet lost by fault injection")
+}
+
+impl<T> Transport for FaultyTransport<T>
+where
+    T: Transport<Address = SocketAddr, Error = io::Error>,
+{
+    type Address = SocketAddr;
+    type Error = io::Error;
+
+    fn exchange(
+        &mut self,
+        server: &SocketAddr,
+        request: &[u8; PACKET_LEN],
+        reply: &mut [u8; PACKET_LEN],
+    ) -> io::Result<Duration> {
+        let config = self.injector.config;
+        let latency = {
+            let mut state = self.injector.state.lock_or_recover();
+            state.counts.exchanges += 1;
+            if state.rng.chance(config.loss) {
+                state.counts.lost += 1;
+                return Err(lost());
+            }
+            let latency = config.latency.sample(&mut state.rng);
+            if !latency.is_zero() {
+                state.counts.delayed += 1;
+            }
+            latency
+        };
+        std::thread::sleep(latency);
+        let round_trip = self.inner.exchange(server, request, reply)? + latency;
+
+        let mut state = self.injector.state.lock_or_recover();
+        if state.rng.chance(config.corruption) {
+            state.counts.corrupted += 1;
+            for _ in 0..1 + state.rng.next_u64() % 4 {
+                let bit = state.rng.next_u64() as usize % (PACKET_LEN * 8);
+                reply[bit / 8] ^= 1 << (bit % 8);
+            }
+        }
+        let fresh = *reply;
+        let stale = state.take_in_flight(server);
+        if state.rng.chance(config.duplication) {
+            state.counts.duplicated += 1;
+            state.in_flight.push((*server, fresh));
+        }
+        let reordered = state.rng.chance(config.reordering);
+        if reordered {
+            state.counts.reordered += 1;
+            state.in_flight.push((*server, fresh));
+        }
+        match stale {
+            Some(stale) => {
+                state.counts.stale_delivered += 1;
+                *reply = stale;
+            }
+            None if reordered => return Err(lost()),
+            None => {}
+        }
+        Ok(round_trip)
+    }
+
+    fn receive_latency(&self) -> Duration {
+        self.inner.receive_latency()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sntp::UdpTransport;
+    use crate::tests::spawn_fake_server;
+    use crate::{Clock, ClockConfig, Timestamp};
+
+    /// Replies with the number of the exchange, in place of a server
+    struct Counter(u8);
+
+    impl Transport for Counter {
+        type Address = SocketAddr;
+        type Error = io::Error;
+
+        fn exchange(
+            &mut self,
+            _server: &SocketAddr,
+            _request: &[u8; PACKET_LEN],
+            reply: &mut [u8; PACKET_LEN],
+        ) -> io::Result<Duration> {
+            self.0 = self.0.wrapping_add(1);
+            *reply = [self.0; PACKET_LEN];
+            Ok(Duration::from_millis(1))
+        }
+    }
+
+    fn run(config: FaultConfig) -> (Vec<Option<[u8; PACKET_LEN]>>, FaultCounts) {
+        let injector = FaultInjector::new(config);
+        let mut transport = injector.wrap(Counter(0));
+        let server: SocketAddr = "192.0.2.1:123".parse().unwrap();
+        let replies = (0..400)
+            .map(|_| {
+                let mut reply = [0; PACKET_LEN];
+                let result = transport.exchange(&server, &[0; PACKET_LEN], &mut reply);
+                result.ok().map(|_| reply)
+            })
+            .collect();
+        (replies, injector.counts())
+    }
+
+    #[test]
+    fn test_faults_are_reproducible_from_the_seed() {
+        let config = FaultConfig::new(7)
+            .with_loss(0.1)
+            .with_duplication(0.05)
+            .with_reordering(0.05)
+            .with_corruption(0.1);
+        let (replies, counts) = run(config);
+        assert_eq!(run(config), (replies.clone(), counts));
+        assert_ne!(run(FaultConfig { seed: 8, ..config }).0, replies);
+
+        assert_eq!(counts.exchanges, 400);
+        for (count, expected) in [
+            (counts.lost, 40),
+            (counts.corrupted, 36),
+            (counts.duplicated, 18),
+            (counts.reordered, 18),
+        ] {
+            assert!(count > expected / 3 && count < expected * 3, "{:?}", counts);
+        }
+        let delivered = replies.iter().flatten().count() as u64;
+        assert!(delivered < 400 - counts.lost, "{:?}", counts);
+
+        let (clean, counts) = run(FaultConfig::new(7));
+        assert!(clean
+            .iter()
+            .enumerate()
+            .all(|(i, r)| *r == Some([(i as u8).wrapping_add(1); PACKET_LEN])));
+        assert_eq!(
+            counts,
+            FaultCounts {
+                exchanges: 400,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_latency_distributions() {
+        let mut rng = SplitMix64::new(1);
+        let ms = Duration::from_millis;
+        let mut mean = |latency: Latency| {
+            (0..2000)
+                .map(|_| latency.sample(&mut rng).as_secs_f64())
+                .sum::<f64>()
+                / 2000.0
+        };
+        assert_eq!(mean(Latency::None), 0.0);
+        assert!((mean(Latency::Constant(ms(10))) - 0.010).abs() < 1e-9);
+        let uniform = mean(Latency::Uniform {
+            min: ms(10),
+            max: ms(30),
+        });
+        assert!((uniform - 0.020).abs() < 0.001, "{}", uniform);
+        let normal = mean(Latency::Normal {
+            mean: ms(50),
+            std_dev: ms(5),
+        });
+        assert!((normal - 0.050).abs() < 0.001, "{}", normal);
+        let exponential = mean(Latency::Exponential { mean: ms(20) });
+        assert!((exponential - 0.020).abs() < 0.002, "{}", exponential);
+    }
+
+    #[test]
+    fn test_clock_syncs_through_a_lossy_network() {
+        let server = spawn_fake_server(Timestamp::now(), 20);
+        let injector = FaultInjector::new(FaultConfig::new(3).with_loss(0.3).with_latency(
+            Latency::Uniform {
+                min: Duration::from_millis(1),
+                max: Duration::from_millis(5),
+            },
+        ));
+        let udp = TransportFactory::new("udp", UdpTransport::default);
+        let factory = injector.factory(udp);
+        assert_eq!(factory.name(), "faulty udp");
+
+        let config = ClockConfig::new()
+            .with_servers(vec![server])
+            .with_transport(Some(factory));
+        let clock = Clock::with_config(config);
+        for _ in 0..5 {
+            clock.resync_now();
+        }
+        assert!(clock.is_synchronized());
+        let counts = injector.counts();
+        assert!(counts.lost > 0 && counts.delayed == counts.exchanges - counts.lost);
+        assert!(clock.get_stats().failed_syncs >= 1, "{:?}", counts);
+    }
+}
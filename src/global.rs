@@ -0,0 +1,88 @@
+//! # Global Clock
+//!
+//! An opt-in, process-wide clock for applications that just want synchronized time without
+//! passing a `Clock` around. Nothing is started until one of the free functions is first
+//! called; the configuration can be set beforehand with [`set_global_config`].
+//!
+//! Starting does not wait for the network: the clock reads its fallback time until the
+//! background worker's first sync, which [`wait_until_synchronized`] waits for.
+
+use crate::lock::MutexExt;
+use crate::{Clock, ClockConfig, ClockError, Timestamp};
+
+use crate::logging::clock_log;
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, Local, Utc};
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+lazy_static! {
+    static ref GLOBAL_CONFIG: Mutex<Option<ClockConfig>> = Mutex::new(None);
+    static ref GLOBAL_CLOCK: Arc<Clock> = start_global();
+    static ref GLOBAL_SHUTDOWN: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    static ref GLOBAL_STARTED: AtomicBool = AtomicBool::new(false);
+}
+
+fn start_global() -> Arc<Clock> {
+    let config = {
+        let mut slot = GLOBAL_CONFIG.lock_or_recover();
+        GLOBAL_STARTED.store(true, Ordering::SeqCst);
+        slot.take().unwrap_or_default()
+    };
+    let interval_secs = config.sync_interval.as_secs().max(1);
+    clock_log!(Info, Worker, "Starting global clock");
+
+    // No initial sync, which would block whoever first touches the clock and poison it
+    // if it panicked; the worker makes the first sync instead
+    let clock = Arc::new(Clock::new_with_deadline(config, Duration::ZERO));
+    clock.start(interval_secs, Arc::clone(&GLOBAL_SHUTDOWN));
+    clock
+}
+
+/// Sets the configuration used when the global clock is first started.
+///
+/// Returns the configuration back as an error if the global clock is already running.
+#[allow(clippy::result_large_err)] // handing the config back is the point of the error
+pub fn set_global_config(config: ClockConfig) -> Result<(), ClockConfig> {
+    let mut slot = GLOBAL_CONFIG.lock_or_recover();
+    if GLOBAL_STARTED.load(Ordering::SeqCst) {
+        return Err(config);
+    }
+    *slot = Some(config);
+    Ok(())
+}
+
+/// Returns the global clock, starting it on first use
+pub fn global_clock() -> Arc<Clock> {
+    Arc::clone(&GLOBAL_CLOCK)
+}
+
+/// Starts the global clock if needed and blocks until it has obtained NTP time, returning
+/// the current time, or fails with [`ClockError::Timeout`] after `timeout`
+pub fn wait_until_synchronized(timeout: Duration) -> Result<Timestamp, ClockError> {
+    GLOBAL_CLOCK.wait_until_synchronized(timeout)
+}
+
+/// Current synchronized time in the system's local timezone
+#[cfg(feature = "chrono")]
+pub fn now() -> DateTime<Local> {
+    now_utc().with_timezone(&Local)
+}
+
+/// Current synchronized time in UTC
+#[cfg(feature = "chrono")]
+pub fn now_utc() -> DateTime<Utc> {
+    GLOBAL_CLOCK.get_current_time()
+}
+
+/// Current synchronized time as a [`Timestamp`]
+pub fn now_timestamp() -> Timestamp {
+    GLOBAL_CLOCK.now_timestamp()
+}
+
+/// Whether the global clock has obtained time from an NTP server
+pub fn is_synchronized() -> bool {
+    GLOBAL_CLOCK.is_synchronized()
+}
@@ -0,0 +1,374 @@
+//! # Clock Handles
+//!
+//! A [`ClockHandle`] is a cheap, cloneable, read-only view of a [`Clock`](crate::Clock). Pass
+//! handles to the tasks that need the time; only the owner of the `Clock` can start, resync,
+//! or reconfigure it.
+
+use crate::engine::ClockShared;
+use crate::lock::{MutexExt, RwLockExt};
+use crate::{
+    ClockError, SyncAttempt, SyncRecord, SyncStats, Timestamp, ValidityStatus, WindowStats,
+};
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+/// Synchronization state of a clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockState {
+    /// No NTP time has been obtained yet; the clock is running from its fallback time
+    Unsynchronized,
+    /// The clock is running from NTP time
+    Synchronized,
+}
+
+/// Where the time a clock reports currently comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSource {
+    /// Obtained from an NTP server
+    Ntp,
+    /// Obtained from an HTTP(S) time source, as used by browser builds
+    Http,
+    /// Seeded from the operating system's clock before the first sync
+    SystemClock,
+    /// The fixed [`DEFAULT_TIMESTAMP`](crate::DEFAULT_TIMESTAMP) time
+    FixedDefault,
+    /// Seeded from a previously persisted NTP time
+    Persisted,
+    /// Free-running from the last NTP time with drift compensation, because no server has
+    /// been reachable for a while; see
+    /// [`ClockConfig::orphan_after`](crate::ClockConfig::orphan_after)
+    Orphan,
+    /// No time is reported under [`FallbackPolicy::Error`](crate::FallbackPolicy::Error)
+    Unavailable,
+}
+
+impl TimeSource {
+    /// Whether the time was verified against a network time source
+    pub fn is_verified(&self) -> bool {
+        matches!(self, TimeSource::Ntp | TimeSource::Http)
+    }
+}
+
+impl fmt::Display for TimeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            TimeSource::Ntp => "NTP-verified",
+            TimeSource::Http => "HTTP-verified",
+            TimeSource::SystemClock => "system-derived, unverified",
+            TimeSource::FixedDefault => "fixed default, unverified",
+            TimeSource::Persisted => "persisted, unverified",
+            TimeSource::Orphan => "orphan, free-running",
+            TimeSource::Unavailable => "unavailable",
+        };
+        f.write_str(description)
+    }
+}
+
+/// The reference point a clock extrapolates from, as published to subscribers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSnapshot {
+    /// Time at the last step
+    pub base_time: Timestamp,
+    /// Monotonic instant of the last step
+    pub base_instant: Instant,
+    /// Synchronization state at the last step
+    pub state: ClockState,
+    /// Where `base_time` came from
+    pub source: TimeSource,
+}
+
+impl ClockSnapshot {
+    /// Extrapolates the current time from this snapshot using the monotonic clock
+    pub fn now(&self) -> Timestamp {
+        self.base_time + self.base_instant.elapsed()
+    }
+}
+
+/// Read-only handle to a clock
+#[derive(Clone)]
+pub struct ClockHandle {
+    shared: Arc<ClockShared>,
+}
+
+impl ClockHandle {
+    pub(crate) fn new(shared: Arc<ClockShared>) -> Self {
+        ClockHandle { shared }
+    }
+
+    /// Current synchronized time
+    #[cfg(feature = "chrono")]
+    pub fn now(&self) -> DateTime<Utc> {
+        self.now_timestamp().into()
+    }
+
+    /// Current synchronized time as a [`Timestamp`]
+    pub fn now_timestamp(&self) -> Timestamp {
+        self.shared.get_current_time()
+    }
+
+    /// Current synchronized time as a `time::OffsetDateTime` in UTC
+    #[cfg(feature = "time")]
+    pub fn now_offset_datetime(&self) -> time::OffsetDateTime {
+        self.now_timestamp().into()
+    }
+
+    /// Current synchronized time on the TAI scale
+    pub fn now_tai(&self) -> Timestamp {
+        crate::timescale::utc_to_tai(self.now_timestamp())
+    }
+
+    /// Current synchronized time on the GPS scale
+    pub fn now_gps(&self) -> Timestamp {
+        crate::timescale::utc_to_gps(self.now_timestamp())
+    }
+
+    /// Current synchronized time as whole seconds since the Unix epoch
+    pub fn now_unix_secs(&self) -> i64 {
+        self.now_timestamp().unix_secs()
+    }
+
+    /// Current synchronized time as milliseconds since the Unix epoch
+    pub fn now_unix_millis(&self) -> i64 {
+        self.now_timestamp().unix_millis()
+    }
+
+    /// Current synchronized time as nanoseconds since the Unix epoch
+    pub fn now_unix_nanos(&self) -> i128 {
+        self.now_timestamp().unix_nanos()
+    }
+
+    /// Current synchronized time as a `std::time::SystemTime`
+    pub fn now_system_time(&self) -> SystemTime {
+        self.now_timestamp().into()
+    }
+
+    /// Current synchronized time as RFC 3339 in UTC, e.g. `2026-02-03T06:50:57.250+00:00`
+    pub fn format_rfc3339(&self) -> String {
+        self.now_timestamp().to_rfc3339()
+    }
+
+    /// Current synchronized time as RFC 2822 in UTC, e.g. `Tue, 3 Feb 2026 06:50:57 +0000`
+    pub fn format_rfc2822(&self) -> String {
+        self.now_timestamp().to_rfc2822()
+    }
+
+    /// Current synchronized time in UTC, formatted with a chrono `strftime`-style string such
+    /// as `%Y%m%d-%H%M%S` for filenames
+    #[cfg(feature = "chrono")]
+    pub fn format(&self, fmt: &str) -> Result<String, ClockError> {
+        crate::format_time(&self.now(), fmt)
+    }
+
+    /// Current synchronized time in the system's local timezone
+    #[cfg(feature = "chrono")]
+    pub fn now_local(&self) -> DateTime<Local> {
+        self.now().with_timezone(&Local)
+    }
+
+    /// Current synchronized time at a fixed UTC offset
+    #[cfg(feature = "chrono")]
+    pub fn now_fixed_offset(&self, offset: FixedOffset) -> DateTime<FixedOffset> {
+        self.now().with_timezone(&offset)
+    }
+
+    /// Current synchronized time in an IANA timezone
+    #[cfg(feature = "tz")]
+    pub fn now_in(&self, tz: chrono_tz::Tz) -> DateTime<chrono_tz::Tz> {
+        self.now().with_timezone(&tz)
+    }
+
+    /// Current time, or an error if the clock is unsynchronized under
+    /// [`FallbackPolicy::Error`](crate::FallbackPolicy::Error)
+    #[cfg(feature = "chrono")]
+    pub fn try_now(&self) -> Result<DateTime<Utc>, ClockError> {
+        self.try_now_timestamp().map(Into::into)
+    }
+
+    /// [`Timestamp`] version of [`try_now`](Self::try_now)
+    pub fn try_now_timestamp(&self) -> Result<Timestamp, ClockError> {
+        self.shared.try_current_time()
+    }
+
+    /// Blocks until the synchronized clock reads `target`
+    pub fn sleep_until(&self, target: impl Into<Timestamp>) {
+        crate::schedule::sleep_until(&self.shared, target.into())
+    }
+
+    /// Async version of [`sleep_until`](Self::sleep_until)
+    #[cfg(feature = "tokio")]
+    pub async fn sleep_until_async(&self, target: impl Into<Timestamp>) {
+        crate::schedule::sleep_until_async(&self.shared, target.into()).await
+    }
+
+    /// An [`Interval`](crate::Interval) ticking every `period` from `start` on this clock
+    pub fn interval_at(
+        &self,
+        start: impl Into<Timestamp>,
+        period: std::time::Duration,
+    ) -> crate::Interval {
+        crate::Interval::new(self.clone(), start.into(), period)
+    }
+
+    /// The local oscillator's frequency error in parts per million, estimated from the
+    /// offset history
+    pub fn drift_ppm(&self) -> Option<f64> {
+        self.shared.drift_ppm()
+    }
+
+    /// Starts a [`Stopwatch`](crate::Stopwatch) measuring drift-compensated elapsed time
+    pub fn start_stopwatch(&self) -> crate::Stopwatch {
+        crate::Stopwatch::new(self.clone())
+    }
+
+    /// Returns a [`Timestamper`](crate::Timestamper) issuing strictly increasing timestamps
+    pub fn timestamper(&self) -> crate::Timestamper {
+        crate::Timestamper::new(self.clone())
+    }
+
+    /// Stamps `count` events with one clock read, like
+    /// [`Clock::timestamp_batch`](crate::Clock::timestamp_batch)
+    #[cfg(feature = "chrono")]
+    pub fn timestamp_batch(&self, count: usize) -> impl Iterator<Item = DateTime<Utc>> {
+        self.timestamper().batch(count).map(DateTime::<Utc>::from)
+    }
+
+    pub(crate) fn reserve_stamps(&self, count: u64) -> Timestamp {
+        self.shared.reserve_stamps(count)
+    }
+
+    /// Current synchronization state
+    pub fn state(&self) -> ClockState {
+        self.shared.base.read_or_recover().state()
+    }
+
+    /// Where the reported time currently comes from
+    pub fn time_source(&self) -> TimeSource {
+        self.shared.base.read_or_recover().source
+    }
+
+    /// The reference point the clock currently extrapolates from
+    pub fn snapshot(&self) -> ClockSnapshot {
+        self.shared.base.read_or_recover().snapshot()
+    }
+
+    /// Subscribes to snapshot updates, published whenever the clock is stepped
+    #[cfg(feature = "tokio")]
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<ClockSnapshot> {
+        self.shared.subscribe()
+    }
+
+    /// Subscribes to [`ClockEvent`](crate::ClockEvent)s emitted from now on
+    pub fn events(&self) -> std::sync::mpsc::Receiver<crate::ClockEvent> {
+        self.shared.events.subscribe()
+    }
+
+    /// Whether the clock synced recently enough to be trusted, for health probes
+    pub fn health(&self) -> crate::Health {
+        self.shared.health()
+    }
+
+    /// Whether the clock has obtained time from an NTP server at least once
+    pub fn is_synchronized(&self) -> bool {
+        self.shared.is_synchronized()
+    }
+
+    /// Stratum of this clock as an NTP source, 16 before the first sync
+    pub fn stratum(&self) -> u8 {
+        self.shared.stratum()
+    }
+
+    /// Whether the clock is free-running in orphan mode
+    pub fn is_orphaned(&self) -> bool {
+        self.shared.is_orphaned()
+    }
+
+    /// What the clock reports about itself to clients and [`peer`](crate::peer)s
+    pub fn server_state(&self) -> crate::sntp::ServerState {
+        self.shared.server_state()
+    }
+
+    /// When the background sync loop last completed a cycle; `None` if no worker is running
+    pub fn last_heartbeat(&self) -> Option<Instant> {
+        self.shared.last_heartbeat()
+    }
+
+    /// The clients the peer responder heard from, the most recently seen first, up to
+    /// [`ClockConfig::mru_size`](crate::ClockConfig::mru_size)
+    pub fn clients(&self) -> Vec<crate::peer::ClientRecord> {
+        self.shared.clients()
+    }
+
+    /// Records a query to the peer responder, returning whether it is within the rate limit
+    pub(crate) fn admit_client(&self, addr: std::net::IpAddr) -> bool {
+        self.shared.admit_client(addr)
+    }
+
+    /// How much each server contributed to the last successful sync
+    pub fn source_weights(&self) -> Vec<crate::SourceWeight> {
+        self.shared.source_weights()
+    }
+
+    /// The server the clock last synchronized to, `None` before the first sync
+    pub fn reference(&self) -> Option<crate::ReferenceInfo> {
+        self.shared.reference()
+    }
+
+    /// Blocks until the clock has obtained NTP time, or fails after `timeout`
+    pub fn wait_until_synchronized(
+        &self,
+        timeout: std::time::Duration,
+    ) -> Result<Timestamp, ClockError> {
+        self.shared.wait_until_synchronized(timeout)
+    }
+
+    /// Async version of [`wait_until_synchronized`](Self::wait_until_synchronized)
+    #[cfg(feature = "tokio")]
+    pub async fn wait_until_synchronized_async(
+        &self,
+        timeout: std::time::Duration,
+    ) -> Result<Timestamp, ClockError> {
+        self.shared.wait_until_synchronized_async(timeout).await
+    }
+
+    /// Bound on the error of the current time, growing between syncs; `None` before the
+    /// first sync
+    pub fn uncertainty(&self) -> Option<std::time::Duration> {
+        self.shared.uncertainty()
+    }
+
+    /// Checks a certificate's validity period against the current time and its uncertainty,
+    /// see [`validity`](crate::validity)
+    pub fn check_validity(
+        &self,
+        not_before: impl Into<Timestamp>,
+        not_after: impl Into<Timestamp>,
+    ) -> ValidityStatus {
+        self.shared
+            .check_validity(not_before.into(), not_after.into())
+    }
+
+    /// The last `n` sync attempts, oldest first, see
+    /// [`Clock::recent_events`](crate::Clock::recent_events)
+    pub fn recent_events(&self, n: usize) -> Vec<SyncAttempt> {
+        self.shared.recent_attempts(n)
+    }
+
+    /// The recent syncs, oldest first, see [`Clock::sync_history`](crate::Clock::sync_history)
+    pub fn sync_history(&self) -> Vec<SyncRecord> {
+        self.shared.sync_history()
+    }
+
+    /// Snapshot of the synchronization statistics
+    pub fn stats(&self) -> SyncStats {
+        self.shared.stats.lock_or_recover().clone()
+    }
+
+    /// Sync attempts of the last `window`, see
+    /// [`Clock::window_stats`](crate::Clock::window_stats)
+    pub fn window_stats(&self, window: std::time::Duration) -> WindowStats {
+        self.shared.rolling_stats.lock_or_recover().window(window)
+    }
+}
@@ -0,0 +1,94 @@
+//! # Health Checks
+//!
+//! [`Health`] summarizes whether a clock's time can currently be trusted, for liveness and
+//! readiness probes. A clock is stale when its last successful sync is older than the
+//! staleness threshold, by default [`DEFAULT_STALENESS_FACTOR`] times the sync interval.
+
+use std::fmt;
+use std::time::Duration;
+
+/// Multiple of the sync interval after which a clock is considered stale, unless
+/// [`ClockConfig::staleness_threshold`](crate::ClockConfig::staleness_threshold) is set
+pub const DEFAULT_STALENESS_FACTOR: u32 = 3;
+
+/// Health of a clock, as reported by [`Clock::health`](crate::Clock::health)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// The clock synced recently
+    Healthy,
+    /// The clock has synced, but not within the staleness threshold
+    Stale {
+        /// Time since the last successful sync
+        age: Duration,
+    },
+    /// The clock has never synced and is running on its fallback time
+    Unsynchronized,
+}
+
+impl Health {
+    /// Whether the clock synced within the staleness threshold
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, Health::Healthy)
+    }
+
+    /// Process exit code for probes: 0 when healthy, 1 when stale, 2 when unsynchronized
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Health::Healthy => 0,
+            Health::Stale { .. } => 1,
+            Health::Unsynchronized => 2,
+        }
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Health::Healthy => write!(f, "healthy"),
+            Health::Stale { age } => write!(f, "stale (last sync {}s ago)", age.as_secs()),
+            Health::Unsynchronized => write!(f, "unsynchronized"),
+        }
+    }
+}
+
+/// Classifies a clock given the time since its last successful sync
+pub(crate) fn assess(since_sync: Option<Duration>, threshold: Duration) -> Health {
+    match since_sync {
+        None => Health::Unsynchronized,
+        Some(age) if age > threshold => Health::Stale { age },
+        Some(_) => Health::Healthy,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::spawn_fake_server;
+    use crate::{Clock, ClockConfig, Timestamp};
+
+    #[test]
+    fn test_assess_thresholds() {
+        let threshold = Duration::from_secs(30);
+        assert_eq!(assess(None, threshold), Health::Unsynchronized);
+        assert_eq!(assess(Some(threshold), threshold), Health::Healthy);
+        let age = Duration::from_secs(31);
+        assert_eq!(assess(Some(age), threshold), Health::Stale { age });
+        assert_eq!(Health::Stale { age }.exit_code(), 1);
+    }
+
+    #[test]
+    fn test_clock_health() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        assert_eq!(clock.health(), Health::Unsynchronized);
+        assert_eq!(clock.health().exit_code(), 2);
+
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        let config = ClockConfig::new()
+            .with_servers(vec![server])
+            .with_staleness_threshold(Some(Duration::from_millis(50)));
+        let clock = Clock::with_config(config);
+        assert!(clock.health().is_healthy());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(matches!(clock.health(), Health::Stale { .. }));
+    }
+}
@@ -0,0 +1,293 @@
+//! # Sync History Export
+//!
+//! The clock keeps its most recent [`MAX_SYNC_HISTORY`] syncs in memory: when each sample
+//! was taken, which server it came from, the measured offset and round-trip delay, and how
+//! the clock was corrected. [`Clock::export_history`](crate::Clock::export_history) writes
+//! them out for offline analysis, as CSV or, with the `parquet` feature, as Parquet; pandas
+//! and DuckDB read both directly:
+//!
+//! ```text
+//! time,server,address,offset,delay,adjustment
+//! 2026-02-03T06:50:57.250+00:00,pool.ntp.org:123,192.0.2.1:123,0.002130000,0.021000000,slew
+//! ```
+//!
+//! Offsets and delays are in seconds. The adjustment column is `step` or `slew`, or empty
+//! when the sample was only measured because no smoothing filter is configured.
+
+use crate::{AdjustmentKind, NtpSample, Timestamp};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Number of syncs kept for export: about twelve days at the 64 s interval of RFC 8633
+pub const MAX_SYNC_HISTORY: usize = 16_384;
+
+/// Header line of CSV exports
+const CSV_HEADER: &str = "time,server,address,offset,delay,adjustment";
+
+/// One sync of the clock
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncRecord {
+    /// Server time of the sample
+    pub time: Timestamp,
+    /// Server name as configured
+    pub server: String,
+    /// Resolved address the sample came from
+    pub addr: SocketAddr,
+    /// Measured offset of the server from the local clock, in seconds
+    pub offset: f64,
+    /// Round-trip delay of the sample, in seconds
+    pub delay: f64,
+    /// How the clock was corrected, if at all
+    pub adjustment: Option<AdjustmentKind>,
+}
+
+impl SyncRecord {
+    pub(crate) fn new(sample: &NtpSample, offset: f64, adjustment: Option<AdjustmentKind>) -> Self {
+        SyncRecord {
+            time: sample.time,
+            server: sample.server.clone(),
+            addr: sample.addr,
+            offset,
+            delay: sample.delay.as_secs_f64(),
+            adjustment,
+        }
+    }
+}
+
+/// File format of [`export`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HistoryFormat {
+    /// Comma-separated records with a header line
+    #[default]
+    Csv,
+    /// Apache Parquet, one row group
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl FromStr for HistoryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(HistoryFormat::Csv),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(HistoryFormat::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err("Parquet export needs the `parquet` feature".to_string()),
+            other => Err(format!("unknown history format '{}'", other)),
+        }
+    }
+}
+
+/// Writes `records` to `path` in `format`, replacing the file if it exists
+pub fn export(records: &[SyncRecord], format: HistoryFormat, path: &Path) -> io::Result<()> {
+    let file = File::create(path)?;
+    match format {
+        HistoryFormat::Csv => {
+            let mut out = BufWriter::new(file);
+            write_csv(records, &mut out)?;
+            out.flush()
+        }
+        #[cfg(feature = "parquet")]
+        HistoryFormat::Parquet => write_parquet(records, file).map_err(io::Error::other),
+    }
+}
+
+/// Writes `records` as CSV, header first
+pub fn write_csv(records: &[SyncRecord], out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{}", CSV_HEADER)?;
+    for record in records {
+        writeln!(
+            out,
+            "{},{},{},{:.9},{:.9},{}",
+            record.time.to_rfc3339(),
+            csv_field(&record.server),
+            record.addr,
+            record.offset,
+            record.delay,
+            record
+                .adjustment
+                .map(|kind| kind.to_string())
+                .unwrap_or_default()
+        )?;
+    }
+    Ok(())
+}
+
+/// Quotes a field containing a separator or a quote
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Schema of Parquet exports; times are nanoseconds since the Unix epoch
+#[cfg(feature = "parquet")]
+const PARQUET_SCHEMA: &str = "
+    message sync_history {
+        required int64 time (TIMESTAMP(NANOS,true));
+        required binary server (UTF8);
+        required binary address (UTF8);
+        required double offset;
+        required double delay;
+        optional binary adjustment (UTF8);
+    }
+";
+
+#[cfg(feature = "parquet")]
+fn write_parquet(records: &[SyncRecord], file: File) -> parquet::errors::Result<()> {
+    use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use std::sync::Arc;
+
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let properties = Arc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(file, schema, properties)?;
+    let mut row_group = writer.next_row_group()?;
+
+    let times: Vec<i64> = records
+        .iter()
+        .map(|r| r.time.unix_nanos().clamp(i64::MIN.into(), i64::MAX.into()) as i64)
+        .collect();
+    let servers: Vec<ByteArray> = records.iter().map(|r| r.server.as_str().into()).collect();
+    let addresses: Vec<ByteArray> = records
+        .iter()
+        .map(|r| r.addr.to_string().as_str().into())
+        .collect();
+    let offsets: Vec<f64> = records.iter().map(|r| r.offset).collect();
+    let delays: Vec<f64> = records.iter().map(|r| r.delay).collect();
+    let adjustments: Vec<ByteArray> = records
+        .iter()
+        .filter_map(|r| r.adjustment)
+        .map(|kind| kind.to_string().as_str().into())
+        .collect();
+    let adjusted: Vec<i16> = records
+        .iter()
+        .map(|r| r.adjustment.is_some() as i16)
+        .collect();
+
+    // Columns must be written in schema order
+    let mut column = row_group.next_column()?.expect("time column");
+    column
+        .typed::<Int64Type>()
+        .write_batch(&times, None, None)?;
+    column.close()?;
+    for values in [&servers, &addresses] {
+        let mut column = row_group.next_column()?.expect("string column");
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(values, None, None)?;
+        column.close()?;
+    }
+    for values in [&offsets, &delays] {
+        let mut column = row_group.next_column()?.expect("double column");
+        column
+            .typed::<DoubleType>()
+            .write_batch(values, None, None)?;
+        column.close()?;
+    }
+    let mut column = row_group.next_column()?.expect("adjustment column");
+    column
+        .typed::<ByteArrayType>()
+        .write_batch(&adjustments, Some(&adjusted), None)?;
+    column.close()?;
+
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::spawn_fake_server;
+    use crate::{Clock, ClockConfig};
+    use std::fs;
+
+    fn record(server: &str, adjustment: Option<AdjustmentKind>) -> SyncRecord {
+        SyncRecord {
+            time: Timestamp::from_unix_nanos(1_770_101_457_250_000_000),
+            server: server.to_string(),
+            addr: "192.0.2.1:123".parse().unwrap(),
+            offset: 0.00213,
+            delay: 0.021,
+            adjustment,
+        }
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let mut out = Vec::new();
+        let records = [
+            record("pool.ntp.org:123", Some(AdjustmentKind::Slew)),
+            record("a,b", None),
+        ];
+        write_csv(&records, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "time,server,address,offset,delay,adjustment\n\
+             2026-02-03T06:50:57.250+00:00,pool.ntp.org:123,192.0.2.1:123,0.002130000,0.021000000,slew\n\
+             2026-02-03T06:50:57.250+00:00,\"a,b\",192.0.2.1:123,0.002130000,0.021000000,\n"
+        );
+        assert_eq!("CSV".parse(), Ok(HistoryFormat::Csv));
+        assert!("xlsx".parse::<HistoryFormat>().is_err());
+    }
+
+    #[test]
+    fn test_clock_exports_its_syncs() {
+        let server = spawn_fake_server(Timestamp::now(), 2);
+        let clock = Clock::with_config(ClockConfig::new().with_servers(vec![server.clone()]));
+        assert!(clock.resync_now());
+
+        let history = clock.sync_history();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].server, server);
+        assert_eq!(history[0].adjustment, Some(AdjustmentKind::Step));
+        assert_eq!(clock.stats_snapshot().steps, 1);
+
+        let path = std::env::temp_dir().join(format!("clock-history-{}.csv", std::process::id()));
+        clock.export_history(HistoryFormat::Csv, &path).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.lines().nth(1).unwrap().ends_with(",step"));
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_export() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let records = [
+            record("pool.ntp.org:123", Some(AdjustmentKind::Step)),
+            record("ntp.local:123", None),
+        ];
+        let path =
+            std::env::temp_dir().join(format!("clock-history-{}.parquet", std::process::id()));
+        export(&records, HistoryFormat::Parquet, &path).unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        assert!(
+            rows[0].contains("server: \"pool.ntp.org:123\""),
+            "{}",
+            rows[0]
+        );
+        assert!(rows[0].contains("adjustment: \"step\""), "{}", rows[0]);
+        assert!(rows[1].contains("adjustment: null"), "{}", rows[1]);
+    }
+}
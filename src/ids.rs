@@ -0,0 +1,267 @@
+//! # Time-Ordered IDs
+//!
+//! Generators for UUIDv7 (RFC 9562) and Snowflake-style 64-bit IDs whose timestamp
+//! component comes from a [`ClockHandle`] rather than the system clock.
+//!
+//! IDs from one generator are strictly increasing even when the clock is stepped backwards
+//! by a sync: the generator keeps issuing IDs at the last millisecond it used, counting up
+//! the sequence bits, until the clock catches up. If the sequence is exhausted the
+//! timestamp component is advanced by one millisecond ahead of the clock.
+
+use crate::lock::MutexExt;
+use crate::rng::SplitMix64;
+use crate::{ClockHandle, Timestamp};
+
+use std::fmt;
+use std::sync::Mutex;
+
+/// Largest value of the 48-bit UUIDv7 millisecond field
+const UUID_MAX_MILLIS: u64 = (1 << 48) - 1;
+
+/// Width of the UUIDv7 `rand_a` field, used as a sequence counter
+const UUID_COUNTER_BITS: u32 = 12;
+
+/// Bits of a Snowflake ID holding the node ID
+pub const SNOWFLAKE_NODE_BITS: u32 = 10;
+
+/// Bits of a Snowflake ID holding the per-millisecond sequence
+pub const SNOWFLAKE_SEQUENCE_BITS: u32 = 12;
+
+/// Largest node ID a Snowflake ID can carry
+pub const SNOWFLAKE_MAX_NODE: u16 = (1 << SNOWFLAKE_NODE_BITS) - 1;
+
+/// Default Snowflake epoch, 2010-11-04T01:42:54.657Z as used by Twitter
+pub const SNOWFLAKE_EPOCH: Timestamp = Timestamp::from_unix_nanos(1_288_834_974_657_000_000);
+
+/// A UUID, as produced by [`UuidV7Generator`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Uuid(u128);
+
+impl Uuid {
+    /// Creates a UUID from its 128-bit big-endian value
+    pub const fn from_u128(value: u128) -> Self {
+        Uuid(value)
+    }
+
+    /// The UUID as a 128-bit integer
+    pub const fn as_u128(&self) -> u128 {
+        self.0
+    }
+
+    /// The UUID in network byte order
+    pub const fn to_bytes(&self) -> [u8; 16] {
+        self.0.to_be_bytes()
+    }
+
+    /// The version field
+    pub const fn version(&self) -> u8 {
+        ((self.0 >> 76) & 0xf) as u8
+    }
+
+    /// The Unix millisecond timestamp embedded in a UUIDv7
+    pub const fn unix_millis(&self) -> u64 {
+        (self.0 >> 80) as u64
+    }
+}
+
+impl fmt::Display for Uuid {
+    /// Formats in the hyphenated lowercase form, e.g. `01890a5d-ac96-774b-bcce-b302099a8057`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            (v >> 96) as u32,
+            (v >> 80) as u16,
+            (v >> 64) as u16,
+            (v >> 48) as u16,
+            v & 0xffff_ffff_ffff
+        )
+    }
+}
+
+/// Millisecond and sequence of the last ID issued
+struct Sequence {
+    millis: u64,
+    counter: u64,
+}
+
+impl Sequence {
+    /// Picks the millisecond and counter for the next ID, never going backwards.
+    /// `restart` gives the counter value to use at a fresh millisecond.
+    fn advance(&mut self, now_millis: u64, max_counter: u64, restart: impl FnOnce() -> u64) {
+        if now_millis > self.millis {
+            self.millis = now_millis;
+            self.counter = restart();
+        } else if self.counter < max_counter {
+            self.counter += 1;
+        } else {
+            self.millis += 1;
+            self.counter = restart();
+        }
+    }
+}
+
+/// Thread-safe generator of time-ordered UUIDv7s
+pub struct UuidV7Generator {
+    clock: ClockHandle,
+    state: Mutex<(Sequence, SplitMix64)>,
+}
+
+impl UuidV7Generator {
+    /// Creates a generator reading time from `clock`
+    pub fn new(clock: ClockHandle) -> Self {
+        UuidV7Generator {
+            clock,
+            state: Mutex::new((
+                Sequence {
+                    millis: 0,
+                    counter: 0,
+                },
+                SplitMix64::from_entropy(),
+            )),
+        }
+    }
+
+    /// Generates the next UUID
+    pub fn generate(&self) -> Uuid {
+        let now_millis = self
+            .clock
+            .now_unix_millis()
+            .clamp(0, UUID_MAX_MILLIS as i64) as u64;
+        let mut guard = self.state.lock_or_recover();
+        let (sequence, rng) = &mut *guard;
+        // Restart the counter at a random value in its lower half, leaving room to count up
+        let max_counter = (1 << UUID_COUNTER_BITS) - 1;
+        sequence.advance(now_millis, max_counter, || {
+            rng.next_u64() & (max_counter >> 1)
+        });
+
+        let rand_b = rng.next_u64() & ((1 << 62) - 1);
+        let value = ((sequence.millis & UUID_MAX_MILLIS) as u128) << 80
+            | 0x7 << 76
+            | (sequence.counter as u128) << 64
+            | 0b10 << 62
+            | rand_b as u128;
+        Uuid(value)
+    }
+}
+
+/// Thread-safe generator of Snowflake-style 64-bit IDs: 41 bits of milliseconds since the
+/// epoch, then the node ID, then a per-millisecond sequence
+pub struct SnowflakeGenerator {
+    clock: ClockHandle,
+    node: u16,
+    epoch: Timestamp,
+    state: Mutex<Sequence>,
+}
+
+impl SnowflakeGenerator {
+    /// Creates a generator for `node` with the default [`SNOWFLAKE_EPOCH`].
+    ///
+    /// Returns `None` if `node` exceeds [`SNOWFLAKE_MAX_NODE`].
+    pub fn new(clock: ClockHandle, node: u16) -> Option<Self> {
+        if node > SNOWFLAKE_MAX_NODE {
+            return None;
+        }
+        Some(SnowflakeGenerator {
+            clock,
+            node,
+            epoch: SNOWFLAKE_EPOCH,
+            state: Mutex::new(Sequence {
+                millis: 0,
+                counter: 0,
+            }),
+        })
+    }
+
+    /// Sets the epoch the timestamp bits count from
+    pub fn with_epoch(mut self, epoch: Timestamp) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// The node ID embedded in generated IDs
+    pub fn node(&self) -> u16 {
+        self.node
+    }
+
+    /// Generates the next ID
+    pub fn generate(&self) -> u64 {
+        let since_epoch = self.clock.now_timestamp().nanos_since(self.epoch) / 1_000_000;
+        let now_millis = since_epoch.clamp(0, (1 << 41) - 1) as u64;
+        let mut sequence = self.state.lock_or_recover();
+        sequence.advance(now_millis, (1 << SNOWFLAKE_SEQUENCE_BITS) - 1, || 0);
+
+        sequence.millis << (SNOWFLAKE_NODE_BITS + SNOWFLAKE_SEQUENCE_BITS)
+            | (self.node as u64) << SNOWFLAKE_SEQUENCE_BITS
+            | sequence.counter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Clock;
+
+    fn handle() -> ClockHandle {
+        Clock::new(Some(vec!["invalid.invalid:123".to_string()])).handle()
+    }
+
+    #[test]
+    fn test_uuid_layout() {
+        let generator = UuidV7Generator::new(handle());
+        let before = Timestamp::now().unix_millis() as u64;
+        let uuid = generator.generate();
+        assert_eq!(uuid.version(), 7);
+        assert_eq!(uuid.as_u128() >> 62 & 0b11, 0b10);
+        assert!(uuid.unix_millis() >= before);
+
+        let text = uuid.to_string();
+        assert_eq!(text.len(), 36);
+        assert_eq!(&text[14..15], "7");
+    }
+
+    #[test]
+    fn test_uuids_strictly_increase() {
+        let generator = UuidV7Generator::new(handle());
+        let mut previous = generator.generate();
+        for _ in 0..10_000 {
+            let next = generator.generate();
+            assert!(next > previous);
+            previous = next;
+        }
+    }
+
+    #[test]
+    fn test_sequence_survives_backward_step() {
+        let mut sequence = Sequence {
+            millis: 1_000,
+            counter: 5,
+        };
+        sequence.advance(400, 7, || 0);
+        assert_eq!((sequence.millis, sequence.counter), (1_000, 6));
+        sequence.advance(400, 7, || 0);
+        sequence.advance(400, 7, || 0);
+        assert_eq!((sequence.millis, sequence.counter), (1_001, 0));
+        sequence.advance(1_002, 7, || 3);
+        assert_eq!((sequence.millis, sequence.counter), (1_002, 3));
+    }
+
+    #[test]
+    fn test_snowflake_layout() {
+        assert!(SnowflakeGenerator::new(handle(), SNOWFLAKE_MAX_NODE + 1).is_none());
+        let generator = SnowflakeGenerator::new(handle(), 42)
+            .unwrap()
+            .with_epoch(Timestamp::UNIX_EPOCH);
+        let first = generator.generate();
+        let second = generator.generate();
+        assert!(second > first);
+        assert_eq!(
+            (first >> SNOWFLAKE_SEQUENCE_BITS) & SNOWFLAKE_MAX_NODE as u64,
+            42
+        );
+        let millis = first >> (SNOWFLAKE_NODE_BITS + SNOWFLAKE_SEQUENCE_BITS);
+        assert!(millis.abs_diff(Timestamp::now().unix_millis() as u64) < 60_000);
+    }
+}
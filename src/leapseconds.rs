@@ -0,0 +1,252 @@
+//! # Leap-Second Tables
+//!
+//! A [`LeapSecondTable`] holds the TAI−UTC offset history. Tables are read from the
+//! leap-seconds.list file published by IERS and distributed with the IANA tz database
+//! (usually installed as `/usr/share/zoneinfo/leap-seconds.list`); the copy current when
+//! this crate was released is compiled in as [`LeapSecondTable::builtin`].
+//!
+//! The file carries an expiry date: past it, IERS may have announced a leap second the
+//! table does not know about. An expired table still gives correct offsets for all past
+//! leap seconds.
+
+use crate::error::LeapSecondError;
+use crate::Timestamp;
+use std::fs;
+use std::path::Path;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970)
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+/// The leap-seconds.list file bundled with this crate
+const BUILTIN_LIST: &str = include_str!("leap-seconds.list");
+
+/// One entry of a leap-second table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeapSecond {
+    /// UTC time from which `tai_minus_utc` applies
+    pub start: Timestamp,
+    /// TAI−UTC in whole seconds
+    pub tai_minus_utc: i32,
+}
+
+/// A table of TAI−UTC offsets, sorted by start time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeapSecondTable {
+    entries: Vec<LeapSecond>,
+    updated: Option<Timestamp>,
+    expires: Option<Timestamp>,
+}
+
+impl LeapSecondTable {
+    /// Creates a table from entries in any order, with no expiry date. Returns `None` if
+    /// it is empty.
+    pub fn new(mut entries: Vec<LeapSecond>) -> Option<Self> {
+        if entries.is_empty() {
+            return None;
+        }
+        entries.sort_by_key(|e| e.start);
+        Some(LeapSecondTable {
+            entries,
+            updated: None,
+            expires: None,
+        })
+    }
+
+    /// The table compiled into this crate
+    pub fn builtin() -> Self {
+        Self::from_bytes(BUILTIN_LIST.as_bytes()).expect("bundled leap-seconds.list is valid")
+    }
+
+    /// Reads a leap-seconds.list file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, LeapSecondError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Parses the contents of a leap-seconds.list file
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LeapSecondError> {
+        let text = std::str::from_utf8(bytes).map_err(|_| LeapSecondError::Parse {
+            line: 0,
+            message: "file is not valid UTF-8".to_string(),
+        })?;
+
+        let mut entries = Vec::new();
+        let mut updated = None;
+        let mut expires = None;
+        for (index, line) in text.lines().enumerate() {
+            let line_no = index + 1;
+            let parse_ntp = |value: &str| {
+                value
+                    .trim()
+                    .parse::<i64>()
+                    .map(|secs| Timestamp::from_unix_secs(secs - NTP_UNIX_OFFSET))
+                    .map_err(|e| LeapSecondError::Parse {
+                        line: line_no,
+                        message: format!("invalid NTP timestamp: {}", e),
+                    })
+            };
+
+            if let Some(value) = line.strip_prefix("#$") {
+                updated = Some(parse_ntp(value)?);
+            } else if let Some(value) = line.strip_prefix("#@") {
+                expires = Some(parse_ntp(value)?);
+            } else if !line.starts_with('#') && !line.trim().is_empty() {
+                // Data line: NTP timestamp, TAI-UTC, then an optional comment
+                let data = line.split('#').next().unwrap_or_default();
+                let mut fields = data.split_whitespace();
+                let (Some(start), Some(offset), None) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    return Err(LeapSecondError::Parse {
+                        line: line_no,
+                        message: "expected a timestamp and an offset".to_string(),
+                    });
+                };
+                entries.push(LeapSecond {
+                    start: parse_ntp(start)?,
+                    tai_minus_utc: offset.parse().map_err(|e| LeapSecondError::Parse {
+                        line: line_no,
+                        message: format!("invalid offset: {}", e),
+                    })?,
+                });
+            }
+        }
+
+        let mut table = Self::new(entries).ok_or(LeapSecondError::Empty)?;
+        table.updated = updated;
+        table.expires = expires;
+        Ok(table)
+    }
+
+    /// The entries of the table, oldest first
+    pub fn entries(&self) -> &[LeapSecond] {
+        &self.entries
+    }
+
+    /// When the table was last updated, if the file said
+    pub fn updated(&self) -> Option<Timestamp> {
+        self.updated
+    }
+
+    /// When the table expires, if the file said
+    pub fn expires(&self) -> Option<Timestamp> {
+        self.expires
+    }
+
+    /// Whether the table has expired at `now`. Tables without an expiry date never expire.
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires.is_some_and(|expires| now >= expires)
+    }
+
+    /// Fails with [`LeapSecondError::Expired`] if the table has expired at `now`
+    pub fn validate(&self, now: Timestamp) -> Result<(), LeapSecondError> {
+        match self.expires {
+            Some(expires) if now >= expires => Err(LeapSecondError::Expired(expires)),
+            _ => Ok(()),
+        }
+    }
+
+    /// TAI−UTC in seconds at a UTC time.
+    ///
+    /// Times before the first entry use its offset; the pre-1972 rubber-second era is not
+    /// modelled.
+    pub fn tai_minus_utc(&self, utc: Timestamp) -> i32 {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| utc >= e.start)
+            .unwrap_or(&self.entries[0])
+            .tai_minus_utc
+    }
+
+    /// Converts a UTC timestamp to TAI
+    pub fn utc_to_tai(&self, utc: Timestamp) -> Timestamp {
+        utc.add_nanos(self.tai_minus_utc(utc) as i128 * NANOS_PER_SEC)
+    }
+
+    /// Converts a TAI timestamp to UTC. A TAI time inside an inserted leap second maps to
+    /// the UTC second that follows it.
+    pub fn tai_to_utc(&self, tai: Timestamp) -> Timestamp {
+        let entry = self
+            .entries
+            .iter()
+            .rev()
+            .find(|e| tai >= e.start.add_nanos(e.tai_minus_utc as i128 * NANOS_PER_SEC))
+            .unwrap_or(&self.entries[0]);
+        tai.add_nanos(-(entry.tai_minus_utc as i128) * NANOS_PER_SEC)
+    }
+}
+
+impl Default for LeapSecondTable {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_table() {
+        let table = LeapSecondTable::builtin();
+        assert_eq!(table.entries().len(), 28);
+        assert_eq!(
+            table.entries()[0].start,
+            "1972-01-01T00:00:00Z".parse().unwrap()
+        );
+        assert_eq!(table.entries().last().unwrap().tai_minus_utc, 37);
+        assert_eq!(
+            table.expires(),
+            Some("2026-06-28T00:00:00Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_tai_minus_utc_lookup() {
+        let table = LeapSecondTable::builtin();
+        let before: Timestamp = "2016-12-31T23:59:59Z".parse().unwrap();
+        let after: Timestamp = "2017-01-01T00:00:00Z".parse().unwrap();
+        assert_eq!(table.tai_minus_utc(before), 36);
+        assert_eq!(table.tai_minus_utc(after), 37);
+        assert_eq!(table.tai_minus_utc(Timestamp::UNIX_EPOCH), 10);
+    }
+
+    #[test]
+    fn test_tai_round_trip() {
+        let table = LeapSecondTable::builtin();
+        for utc in [
+            "2016-12-31T23:59:59.5Z",
+            "2017-01-01T00:00:00Z",
+            "2026-02-03T06:50:57Z",
+        ] {
+            let utc: Timestamp = utc.parse().unwrap();
+            assert_eq!(table.tai_to_utc(table.utc_to_tai(utc)), utc);
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_lines() {
+        let err = LeapSecondTable::from_bytes(b"#@\t3991593600\n2272060800\n").unwrap_err();
+        assert!(matches!(err, LeapSecondError::Parse { line: 2, .. }));
+        assert!(matches!(
+            LeapSecondTable::from_bytes(b"# only comments\n"),
+            Err(LeapSecondError::Empty)
+        ));
+    }
+
+    #[test]
+    fn test_validate_checks_expiry() {
+        let table = LeapSecondTable::builtin();
+        let expires = table.expires().unwrap();
+        assert!(table
+            .validate(expires - std::time::Duration::from_secs(1))
+            .is_ok());
+        assert!(table.is_expired(expires));
+        assert!(matches!(
+            table.validate(expires),
+            Err(LeapSecondError::Expired(t)) if t == expires
+        ));
+    }
+}
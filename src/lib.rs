@@ -3,34 +3,395 @@
 //! This library provides functionality to synchronize with NTP (Network Time Protocol) servers
 //! to maintain accurate time. It periodically fetches time from configured NTP servers and
 //! provides real-time clock updates.
+//!
+//! The `std` feature (on by default) provides the [`Clock`] engine and everything built on
+//! it. Without it the crate is `no_std` + `alloc` and exposes only [`Timestamp`], the
+//! sans-I/O [`sntp`] core, NTPv4 [`extension`] fields, and symmetric key [`auth`]entication,
+//! for embedded targets that bring their own network stack.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+extern crate alloc;
+
+#[cfg(feature = "chrono")]
 use chrono::NaiveDate;
+#[cfg(feature = "chrono")]
 use chrono::NaiveDateTime;
-use chrono::TimeZone;
-use chrono::{DateTime, Duration, Utc};
-use log::{error, info, warn};
-use std::net::ToSocketAddrs;
-use std::net::UdpSocket;
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, FixedOffset, Local, Utc};
+#[cfg(feature = "std")]
+use engine::{ClockShared, Worker};
+#[cfg(feature = "std")]
+use lock::{MutexExt, RwLockExt};
+#[cfg(feature = "std")]
+use logging::clock_log;
+#[cfg(feature = "std")]
+use std::net::SocketAddr;
+#[cfg(feature = "std")]
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, MutexGuard};
+#[cfg(feature = "std")]
+use std::time::{Instant, SystemTime};
+
+#[cfg(feature = "std")]
+pub mod alert;
+#[cfg(feature = "std")]
+pub mod anomaly;
+#[cfg(feature = "api")]
+pub mod api;
+#[cfg(feature = "std")]
+pub mod audit;
+pub mod auth;
+#[cfg(feature = "std")]
+pub mod callbacks;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "std")]
+pub mod clocksource;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod dns;
+#[cfg(feature = "std")]
+mod dnsmsg;
+#[cfg(feature = "std")]
+pub mod elapsed;
+#[cfg(feature = "embassy")]
+pub mod embassy;
+#[cfg(feature = "std")]
+mod engine;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod events;
+pub mod extension;
+#[cfg(feature = "simulation")]
+pub mod faults;
+#[cfg(feature = "std")]
+pub mod global;
+#[cfg(feature = "std")]
+pub mod handle;
+#[cfg(feature = "std")]
+pub mod health;
+#[cfg(feature = "std")]
+pub mod history;
+#[cfg(feature = "ids")]
+pub mod ids;
+#[cfg(feature = "std")]
+mod json;
+#[cfg(feature = "std")]
+pub mod leapseconds;
+#[cfg(feature = "std")]
+mod lock;
+#[cfg(feature = "std")]
+pub mod logging;
+#[cfg(feature = "std")]
+pub mod manager;
+#[cfg(feature = "std")]
+pub mod manycast;
+#[cfg(feature = "std")]
+pub mod mdns;
+#[cfg(feature = "std")]
+pub mod netwatch;
+#[cfg(feature = "nts")]
+pub mod nts;
+#[cfg(feature = "nts")]
+pub mod ntske;
+#[cfg(feature = "std")]
+pub mod peer;
+#[cfg(feature = "std")]
+pub mod persist;
+#[cfg(any(feature = "ids", feature = "simulation"))]
+mod rng;
+#[cfg(feature = "std")]
+pub mod rtc;
+#[cfg(feature = "std")]
+pub mod schedule;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(feature = "simulation")]
+pub mod simulation;
+#[cfg(feature = "std")]
+pub mod smoothing;
+pub mod sntp;
+#[cfg(feature = "std")]
+pub mod stability;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod statsfile;
+#[cfg(feature = "std")]
+pub mod statusfile;
+#[cfg(feature = "std")]
+pub mod stopwatch;
+#[cfg(feature = "std")]
+pub mod suspend;
+#[cfg(all(unix, feature = "systemd"))]
+pub mod systemd;
+#[cfg(feature = "std")]
+pub mod timeline;
+#[cfg(feature = "std")]
+pub mod timescale;
+pub mod timestamp;
+#[cfg(feature = "std")]
+pub mod timestamper;
+#[cfg(all(feature = "std", target_os = "linux", target_pointer_width = "64"))]
+mod timestamping;
+#[cfg(any(feature = "nts", feature = "doh"))]
+mod tls;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "std")]
+pub mod validity;
+#[cfg(feature = "std")]
+pub mod w32time;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "std")]
+pub mod watcher;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+#[cfg(all(windows, feature = "windows-service"))]
+pub mod winservice;
 
+#[cfg(feature = "std")]
+pub use alert::{Alert, AlertHook, AlertKind, HookTarget};
+#[cfg(feature = "std")]
+pub use anomaly::{Anomaly, AnomalyHook, AnomalyKind};
+#[cfg(feature = "std")]
+pub use audit::{Adjustment, AdjustmentKind, AuditLog};
+#[cfg(feature = "std")]
+pub use callbacks::{CallbackId, OffsetBreach, Step, StepDirection, StepListener};
+#[cfg(feature = "std")]
+pub use clocksource::{ClockSource, ManualClock, SystemClock};
+#[cfg(feature = "std")]
+pub use config::{ClockConfig, ConfigChange, FallbackPolicy};
+#[cfg(feature = "std")]
+pub use dns::{AddressPolicy, DnsStrategy};
+#[cfg(all(feature = "std", any(unix, windows)))]
+pub use elapsed::BootTimeSource;
+#[cfg(feature = "quanta")]
+pub use elapsed::TscSource;
+#[cfg(feature = "std")]
+pub use elapsed::{ElapsedSource, MonotonicSource};
+#[cfg(feature = "std")]
+pub use error::{ClockError, LeapSecondError};
+#[cfg(feature = "std")]
+pub use events::ClockEvent;
+#[cfg(feature = "std")]
+pub use global::{
+    global_clock, is_synchronized, now_timestamp, set_global_config, wait_until_synchronized,
+};
+#[cfg(feature = "chrono")]
+pub use global::{now, now_utc};
+#[cfg(feature = "std")]
+pub use handle::{ClockHandle, ClockSnapshot, ClockState, TimeSource};
+#[cfg(feature = "std")]
+pub use health::Health;
+#[cfg(feature = "std")]
+pub use history::{HistoryFormat, SyncRecord};
+#[cfg(feature = "std")]
+pub use leapseconds::{LeapSecond, LeapSecondTable};
+#[cfg(feature = "std")]
+pub use logging::{LogCategory, LogRecord, LogSink};
+#[cfg(feature = "std")]
+pub use manager::ClockManager;
+#[cfg(feature = "std")]
+pub use schedule::{Interval, JobId, Scheduler};
+#[cfg(feature = "std")]
+pub use server::{Protocol, ServerSpec, ServerSpecBuilder, TlsVersion};
+#[cfg(feature = "simulation")]
+pub use simulation::VirtualTimeline;
+#[cfg(feature = "std")]
+pub use smoothing::SmoothingFilter;
+#[cfg(feature = "std")]
+pub use stability::{OffsetSample, StabilityPoint};
+#[cfg(feature = "std")]
+pub use stats::WindowStats;
+#[cfg(feature = "std")]
+pub use statsfile::{LoopRecord, PeerRecord, Rotation, StatsFormat, StatsLogger};
+#[cfg(feature = "std")]
+pub use stopwatch::Stopwatch;
+#[cfg(feature = "std")]
+pub use suspend::SuspendDetector;
+#[cfg(feature = "std")]
+pub use timeline::{AttemptResult, SyncAttempt};
+#[cfg(feature = "std")]
+pub use timescale::{tai_to_utc, utc_to_tai};
+pub use timestamp::Timestamp;
+#[cfg(feature = "std")]
+pub use timestamper::{TimestampBatch, Timestamper};
+#[cfg(feature = "std")]
+pub use validity::ValidityStatus;
+#[cfg(feature = "std")]
+pub use watcher::ConfigWatcher;
+
+#[cfg(feature = "chrono")]
 const NATIVE: NaiveDateTime = NaiveDate::from_ymd_opt(2000, 1, 1)
     .unwrap()
     .and_hms_opt(0, 0, 0)
     .unwrap();
 
-/// Default fallback time (January 1, 2000)
+/// Fixed fallback time (January 1, 2000), used by [`FallbackPolicy::FixedDefault`]
+#[cfg(feature = "chrono")]
 pub const DEFAULT: DateTime<Utc> = DateTime::<Utc>::from_naive_utc_and_offset(NATIVE, Utc);
 
+/// [`DEFAULT`] as a [`Timestamp`]
+pub const DEFAULT_TIMESTAMP: Timestamp = Timestamp::from_unix_secs(946_684_800);
+
+/// Number of resync attempts made after a suspend is detected
+#[cfg(feature = "std")]
+pub(crate) const BURST_ATTEMPTS: u32 = 4;
+
+/// Spacing between burst resync attempts, giving the network time to come back up
+#[cfg(feature = "std")]
+pub(crate) const BURST_SPACING: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Maximum number of offset samples kept for stability analysis
+pub const MAX_OFFSET_HISTORY: usize = 1024;
+
+/// A time sample received from an NTP server
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct NtpSample {
+    /// Server name as configured
+    pub server: String,
+    /// Resolved address the sample came from
+    pub addr: SocketAddr,
+    /// Server time, corrected for half the round-trip delay
+    pub time: Timestamp,
+    /// Round-trip delay of the request
+    pub delay: std::time::Duration,
+    /// Root delay reported by the server, in seconds
+    pub root_delay: f64,
+    /// Root dispersion reported by the server, in seconds
+    pub root_dispersion: f64,
+    /// The server's stratum
+    pub stratum: u8,
+    /// What the server is synchronized to, see
+    /// [`Measurement::reference_id`](sntp::Measurement::reference_id)
+    pub reference_id: [u8; 4],
+    /// Precision of the server's clock, as a power of two in seconds
+    pub precision: i8,
+}
+
+/// What the clock last synchronized to, from the most recent accepted sample, see
+/// [`Clock::reference`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceInfo {
+    /// Server name as configured
+    pub server: String,
+    /// Resolved address of the server
+    pub addr: SocketAddr,
+    /// The server's stratum
+    pub stratum: u8,
+    /// What the server is synchronized to, see [`refid`](Self::refid)
+    pub reference_id: [u8; 4],
+    /// Precision of the server's clock, as a power of two in seconds
+    pub precision: i8,
+    /// Root delay reported by the server, in seconds
+    pub root_delay: f64,
+    /// Root dispersion reported by the server, in seconds
+    pub root_dispersion: f64,
+}
+
+#[cfg(feature = "std")]
+impl ReferenceInfo {
+    /// The server's reference ID as `ntpq` shows it: a reference clock code such as `GPS`
+    /// or `PPS` at stratum 1, otherwise the address of the server's own upstream
+    pub fn refid(&self) -> String {
+        sntp::display_reference_id(self.stratum, self.reference_id).to_string()
+    }
+
+    /// Precision of the server's clock in seconds
+    pub fn precision_secs(&self) -> f64 {
+        2f64.powi(self.precision as i32)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<&NtpSample> for ReferenceInfo {
+    fn from(sample: &NtpSample) -> Self {
+        ReferenceInfo {
+            server: sample.server.clone(),
+            addr: sample.addr,
+            stratum: sample.stratum,
+            reference_id: sample.reference_id,
+            precision: sample.precision,
+            root_delay: sample.root_delay,
+            root_dispersion: sample.root_dispersion,
+        }
+    }
+}
+
+/// How much one server contributed to the last sync, see
+/// [`ClockConfig::combine_sources`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceWeight {
+    /// Server name as configured
+    pub server: String,
+    /// Offset of the server's time from the combined time, in seconds
+    pub offset: f64,
+    /// Half the round-trip delay plus the server's root dispersion, in seconds
+    pub root_distance: f64,
+    /// RMS spread of the server's samples within the poll, in seconds
+    pub jitter: f64,
+    /// The server's stratum
+    pub stratum: u8,
+    /// The server's share of the combined offset; zero if it was discarded as a
+    /// falseticker
+    pub weight: f64,
+}
+
+/// Formats a time with a chrono format string, rejecting unsupported specifiers instead of
+/// panicking
+#[cfg(feature = "chrono")]
+pub(crate) fn format_time<Tz>(time: &DateTime<Tz>, fmt: &str) -> Result<String, ClockError>
+where
+    Tz: chrono::TimeZone,
+    Tz::Offset: std::fmt::Display,
+{
+    use std::fmt::Write;
+    let mut out = String::new();
+    write!(out, "{}", time.format(fmt)).map_err(|_| ClockError::InvalidFormat(fmt.to_string()))?;
+    Ok(out)
+}
+
 /// Statistics for NTP synchronization
+#[cfg(feature = "std")]
 #[derive(Debug, Default, Clone)]
 pub struct SyncStats {
     pub total_attempts: u64,
     pub successful_syncs: u64,
     pub failed_syncs: u64,
+    /// Times the background sync loop was restarted after a panic, exiting, or stalling
+    pub worker_restarts: u64,
+    /// Offset of the last synced time from the clock's reading just before, in seconds
+    pub last_offset: Option<f64>,
+    /// Mean of the absolute offsets of the successful syncs, in seconds
+    pub mean_abs_offset: f64,
+    /// Largest absolute offset of a successful sync, in seconds
+    pub max_abs_offset: f64,
+    /// Round-trip delay of the last successful sync, in seconds
+    pub last_delay: Option<f64>,
+    /// Mean round-trip delay of the successful syncs, in seconds
+    pub mean_delay: f64,
+    /// RMS of the differences between the offsets of recent successful syncs, in seconds
+    pub jitter: f64,
+    /// Times the clock was stepped to a new time
+    pub steps: u64,
+    /// Times the clock was slewed towards a new time
+    pub slews: u64,
+    /// Failed attempts since the last successful sync
+    pub consecutive_failures: u64,
+    /// System time of the last successful sync
+    pub last_success: Option<Timestamp>,
 }
 
+#[cfg(feature = "std")]
 impl SyncStats {
     /// Calculate success rate as a percentage
     pub fn success_rate(&self) -> f64 {
@@ -40,186 +401,973 @@ impl SyncStats {
             (self.successful_syncs as f64 / self.total_attempts as f64) * 100.0
         }
     }
+
+    /// Counts a successful sync and folds its offset and delay into the figures
+    pub(crate) fn record_success(&mut self, offset: f64, delay: f64, jitter: f64) {
+        self.total_attempts += 1;
+        self.successful_syncs += 1;
+        let n = self.successful_syncs as f64;
+        self.last_offset = Some(offset);
+        self.mean_abs_offset += (offset.abs() - self.mean_abs_offset) / n;
+        self.max_abs_offset = self.max_abs_offset.max(offset.abs());
+        self.last_delay = Some(delay);
+        self.mean_delay += (delay - self.mean_delay) / n;
+        self.jitter = jitter;
+        self.consecutive_failures = 0;
+        self.last_success = Some(Timestamp::now());
+    }
+
+    pub(crate) fn record_failure(&mut self) {
+        self.total_attempts += 1;
+        self.failed_syncs += 1;
+        self.consecutive_failures += 1;
+    }
 }
 
-/// Main Clock structure that maintains synchronized time
+/// Main Clock structure that maintains synchronized time.
+///
+/// A `Clock` is the sync engine: it owns the background worker and the discipline state.
+/// It is internally synchronized, so it can be shared as an `Arc<Clock>`; code that only
+/// needs to read the time should take a cheap, cloneable [`ClockHandle`] instead.
+#[cfg(feature = "std")]
 pub struct Clock {
-    latest_time_ntp: Option<DateTime<Utc>>,
-    latest_time: DateTime<Utc>,
-    pub latest_instant: Instant,
-    pub ntp_servers: Vec<String>,
-    stats: SyncStats,
+    shared: Arc<ClockShared>,
+    worker: Mutex<Option<Worker>>,
 }
 
+/// Name emphasising a [`Clock`]'s role as the owner of the sync machinery, as opposed to a
+/// read-only [`ClockHandle`]
+#[cfg(feature = "std")]
+pub type ClockEngine = Clock;
+
+#[cfg(feature = "std")]
 impl Clock {
     /// Creates a new Clock instance with specified NTP servers
     pub fn new(ntp_servers: Option<Vec<String>>) -> Self {
-        let servers = ntp_servers.unwrap_or_else(|| {
-            vec![
-                "time.google.com:123".to_string(),
-                "time.cloudflare.com:123".to_string(),
-                "pool.ntp.org:123".to_string(),
-            ]
-        });
-
-        info!("Initializing clock with NTP servers: {:?}", servers);
-
-        let latest_time_ntp = match Self::get_ntp_time(&servers) {
-            Ok(time) => {
-                info!("Successfully fetched initial NTP time: {}", time);
-                Some(time)
-            }
-            Err(e) => {
-                error!("NTP fetch failed, falling back to default time: {}", e);
-                None
-            }
-        };
+        let mut config = ClockConfig::default();
+        if let Some(servers) = ntp_servers {
+            config.servers = servers;
+        }
+        Self::with_config(config)
+    }
 
+    /// Creates a new Clock instance from a configuration
+    pub fn with_config(config: ClockConfig) -> Self {
         Clock {
-            latest_time_ntp,
-            latest_time: latest_time_ntp.unwrap_or(DEFAULT),
-            latest_instant: Instant::now(),
-            ntp_servers: servers,
-            stats: SyncStats::default(),
+            shared: Arc::new(ClockShared::new(config, None)),
+            worker: Mutex::new(None),
         }
     }
 
-    /// Returns the duration elapsed since the last sync
-    fn elapsed(&self) -> Duration {
-        chrono::Duration::from_std(self.latest_instant.elapsed()).unwrap_or_else(|e| {
-            warn!(
-                "Failed to convert elapsed time: {}. Using zero duration.",
-                e
-            );
-            Duration::zero()
-        })
-    }
-
-    /// Fetches current time from NTP servers
-    fn get_ntp_time(servers: &[String]) -> Result<DateTime<Utc>, Box<dyn std::error::Error>> {
-        for server in servers {
-            info!("Attempting to connect to NTP server: {}", server);
-            match server.to_socket_addrs() {
-                Ok(mut addrs) => {
-                    if let Some(addr) = addrs.next() {
-                        match UdpSocket::bind("0.0.0.0:0") {
-                            Ok(socket) => {
-                                // Set timeouts
-                                let _ = socket
-                                    .set_read_timeout(Some(std::time::Duration::from_secs(3)));
-                                let _ = socket
-                                    .set_write_timeout(Some(std::time::Duration::from_secs(3)));
-
-                                if socket.connect(addr).is_ok() {
-                                    let mut buf = [0u8; 48];
-                                    buf[0] = 0x1b; // NTP version 3, client mode
-
-                                    if socket.send(&buf).is_ok() && socket.recv(&mut buf).is_ok() {
-                                        let seconds = u32::from_be_bytes([
-                                            buf[40], buf[41], buf[42], buf[43],
-                                        ])
-                                            as i64
-                                            - 2_208_988_800;
-                                        if let Some(dt) = Utc.timestamp_opt(seconds, 0).single() {
-                                            info!(
-                                                "Successfully retrieved time from {}: {}",
-                                                server, dt
-                                            );
-                                            return Ok(dt);
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                warn!("Failed to bind socket: {}", e);
-                                continue;
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    warn!("Failed to resolve {}: {}", server, e);
-                    continue;
-                }
-            }
+    /// Like [`with_config`](Self::with_config), but returns within `deadline` even if DNS
+    /// or a server hangs: if the initial sync has not finished by then, the clock starts on
+    /// its fallback time, as if every server had failed, and the sync runs on in the
+    /// background without being applied. Meant for services that must start quickly.
+    ///
+    /// A zero deadline skips the initial sync: the clock starts on its fallback time and
+    /// the first sync is made by the worker once the clock is [`start`](Self::start)ed.
+    pub fn new_with_deadline(config: ClockConfig, deadline: std::time::Duration) -> Self {
+        Clock {
+            shared: Arc::new(ClockShared::new(config, Some(deadline))),
+            worker: Mutex::new(None),
         }
+    }
+
+    /// Returns a cloneable, read-only handle to this clock
+    pub fn handle(&self) -> ClockHandle {
+        ClockHandle::new(Arc::clone(&self.shared))
+    }
+
+    /// The reference point the clock currently extrapolates from
+    pub fn snapshot(&self) -> ClockSnapshot {
+        self.shared.base.read_or_recover().snapshot()
+    }
+
+    /// Subscribes to snapshot updates through a `tokio::sync::watch` channel.
+    ///
+    /// A new snapshot is published whenever the clock is stepped, e.g. on the first
+    /// successful sync, so async code can await synchronization without polling.
+    #[cfg(feature = "tokio")]
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<ClockSnapshot> {
+        self.shared.subscribe()
+    }
+
+    /// Returns the configured NTP servers
+    pub fn ntp_servers(&self) -> Vec<String> {
+        self.shared.ntp_servers.read_or_recover().clone()
+    }
+
+    /// Applies a new configuration to the running clock.
+    ///
+    /// The servers, sync interval, minimum and maximum time, and staleness threshold take effect
+    /// immediately. The fallback policy only matters before the first sync and is not
+    /// changed. Returns the settings that changed, which are also reported as a
+    /// [`ClockEvent::ConfigReloaded`].
+    pub fn reconfigure(&self, config: &ClockConfig) -> Vec<ConfigChange> {
+        self.shared.reconfigure(config)
+    }
+
+    /// Watches a configuration file, applying it with [`reconfigure`](Self::reconfigure)
+    /// whenever it changes, until the returned watcher is dropped. Invalid files are logged
+    /// and skipped.
+    pub fn watch_config(
+        &self,
+        path: impl Into<std::path::PathBuf>,
+        poll_interval: std::time::Duration,
+    ) -> ConfigWatcher {
+        self.watch_config_with(path, poll_interval, |config| config)
+    }
+
+    /// Like [`watch_config`](Self::watch_config), passing each configuration through
+    /// `adjust` first, e.g. to keep command-line overrides
+    pub fn watch_config_with(
+        &self,
+        path: impl Into<std::path::PathBuf>,
+        poll_interval: std::time::Duration,
+        adjust: impl Fn(ClockConfig) -> ClockConfig + Send + 'static,
+    ) -> ConfigWatcher {
+        ConfigWatcher::spawn(
+            Arc::clone(&self.shared),
+            path.into(),
+            poll_interval,
+            Box::new(adjust),
+        )
+    }
+
+    /// Subscribes to [`ClockEvent`]s emitted from now on
+    pub fn events(&self) -> std::sync::mpsc::Receiver<ClockEvent> {
+        self.shared.events.subscribe()
+    }
+
+    /// Calls `callback` on the sync thread as soon as a measured offset is beyond
+    /// `threshold` either way, for every such measurement; see [`callbacks`]
+    pub fn on_offset_exceeds(
+        &self,
+        threshold: std::time::Duration,
+        callback: impl Fn(&OffsetBreach) + Send + Sync + 'static,
+    ) -> CallbackId {
+        self.shared
+            .offset_watches
+            .add(Arc::new(callbacks::OffsetWatch {
+                threshold,
+                callback: Box::new(callback),
+            }))
+    }
+
+    /// Calls `listener` on the sync thread whenever the clock has been stepped, with the step
+    /// as it was applied; see [`callbacks`]
+    pub fn add_step_listener(&self, listener: impl StepListener + 'static) -> CallbackId {
+        self.shared.step_listeners.add(Arc::new(listener))
+    }
+
+    /// Unregisters a callback or step listener, returning whether it was registered
+    pub fn remove_callback(&self, id: CallbackId) -> bool {
+        self.shared.offset_watches.remove(id) || self.shared.step_listeners.remove(id)
+    }
+
+    /// Persists the current time and drift estimate under
+    /// [`FallbackPolicy::LastPersistedTime`], if the clock is synchronized. Call before
+    /// exiting so the next start resumes from the latest state.
+    pub fn flush_state(&self) {
+        self.shared.flush_state()
+    }
+
+    /// Returns the monotonic instant of the last time step
+    pub fn latest_instant(&self) -> Instant {
+        self.shared.base.read_or_recover().latest_instant
+    }
+
+    /// Replaces the source used to measure time since the last sync.
+    ///
+    /// The current time is carried over, so switching sources does not step the clock.
+    pub fn set_elapsed_source(&self, source: Arc<dyn ElapsedSource>) {
+        self.shared.set_elapsed_source(source);
+    }
+
+    /// Measures elapsed time on `timeline` and paces the sync loop by it, so tests can
+    /// fast-forward through poll cycles and holdover; see [`simulation`]
+    #[cfg(feature = "simulation")]
+    pub fn set_virtual_timeline(&self, timeline: VirtualTimeline) {
+        self.shared.set_virtual_timeline(timeline);
+    }
+
+    /// Whether the clock synced recently enough to be trusted, for health probes
+    pub fn health(&self) -> Health {
+        self.shared.health()
+    }
+
+    /// Bound on the error of the current time: half the round-trip delay plus the server's
+    /// root dispersion at the last sync, growing by 15 PPM of the time since. `None` before
+    /// the first sync
+    pub fn uncertainty(&self) -> Option<std::time::Duration> {
+        self.shared.uncertainty()
+    }
+
+    /// Checks a certificate's validity period against the current time and its
+    /// [`uncertainty`](Self::uncertainty), see [`validity`]
+    pub fn check_validity(
+        &self,
+        not_before: impl Into<Timestamp>,
+        not_after: impl Into<Timestamp>,
+    ) -> ValidityStatus {
+        self.shared
+            .check_validity(not_before.into(), not_after.into())
+    }
+
+    /// Whether the clock has obtained time from an NTP server at least once
+    pub fn is_synchronized(&self) -> bool {
+        self.shared.is_synchronized()
+    }
+
+    /// Stratum of this clock as an NTP source: one more than the server it last
+    /// synchronized to, or 16 (unsynchronized) before the first sync
+    pub fn stratum(&self) -> u8 {
+        self.shared.stratum()
+    }
+
+    /// Whether the last attempt to sync reached no server or peer; see
+    /// [`ClockConfig::offline_retry_max`] for retrying faster meanwhile
+    pub fn is_offline(&self) -> bool {
+        self.shared.is_offline()
+    }
+
+    /// Whether the clock is free-running in orphan mode because no server has been
+    /// reachable for [`ClockConfig::orphan_after`]
+    pub fn is_orphaned(&self) -> bool {
+        self.shared.is_orphaned()
+    }
+
+    /// When the background sync loop last completed a cycle; `None` if no worker is
+    /// running. A loop that goes three intervals (at least a minute) without one is
+    /// replaced, see [`SyncStats::worker_restarts`].
+    pub fn last_heartbeat(&self) -> Option<Instant> {
+        self.shared.last_heartbeat()
+    }
 
-        Err("All NTP servers failed".into())
+    /// How much each server contributed to the last successful sync. Only the server used
+    /// is listed unless [`ClockConfig::combine_sources`] is set.
+    pub fn source_weights(&self) -> Vec<SourceWeight> {
+        self.shared.source_weights()
     }
 
-    /// Returns the current time with elapsed offset
+    /// The servers and peers left out of selection because they synchronize to this clock,
+    /// which would form a timing loop while it serves time over
+    /// [`ClockConfig::peer_listen`]. They are still queried, and selected again once they
+    /// stop.
+    pub fn timing_loops(&self) -> Vec<String> {
+        self.shared.timing_loops()
+    }
+
+    /// The server the clock last synchronized to and what it reported about itself;
+    /// `None` before the first sync
+    pub fn reference(&self) -> Option<ReferenceInfo> {
+        self.shared.reference()
+    }
+
+    /// What this clock reports about itself to clients and [`peer`]s: its stratum (one
+    /// more than its server's), reference ID (the server's IPv4 address), root delay, and
+    /// root dispersion. Render the reference ID with [`sntp::display_reference_id`].
+    pub fn server_state(&self) -> sntp::ServerState {
+        self.shared.server_state()
+    }
+
+    /// Returns the current time with elapsed offset.
+    ///
+    /// Before the first successful sync this is extrapolated from the fallback time chosen
+    /// by the [`FallbackPolicy`]; under [`FallbackPolicy::Error`] that is [`DEFAULT`], so
+    /// use [`try_current_time`](Self::try_current_time) instead.
+    #[cfg(feature = "chrono")]
     pub fn get_current_time(&self) -> DateTime<Utc> {
-        self.latest_time + self.elapsed()
+        self.now_timestamp().into()
     }
 
-    /// Updates the latest time from NTP servers
-    fn update_latest_time(&mut self) {
-        self.stats.total_attempts += 1;
+    /// Returns the current time as a [`Timestamp`], the clock's internal representation
+    pub fn now_timestamp(&self) -> Timestamp {
+        self.shared.get_current_time()
+    }
 
-        let latest_time_ntp = match Self::get_ntp_time(&self.ntp_servers) {
-            Ok(time) => {
-                self.stats.successful_syncs += 1;
-                info!("NTP sync successful. Updated time: {}", time);
-                Some(time)
-            }
-            Err(e) => {
-                self.stats.failed_syncs += 1;
-                error!("NTP fetch failed: {}", e);
-                None
-            }
-        };
+    /// Returns the current time as a `time::OffsetDateTime` in UTC
+    #[cfg(feature = "time")]
+    pub fn now_offset_datetime(&self) -> time::OffsetDateTime {
+        self.now_timestamp().into()
+    }
+
+    /// Returns the current time on the TAI scale (see [`timescale`])
+    pub fn now_tai(&self) -> Timestamp {
+        timescale::utc_to_tai(self.now_timestamp())
+    }
+
+    /// Returns the current time on the GPS scale (see [`timescale`])
+    pub fn now_gps(&self) -> Timestamp {
+        timescale::utc_to_gps(self.now_timestamp())
+    }
+
+    /// Returns the current time as whole seconds since the Unix epoch
+    pub fn now_unix_secs(&self) -> i64 {
+        self.now_timestamp().unix_secs()
+    }
+
+    /// Returns the current time as milliseconds since the Unix epoch
+    pub fn now_unix_millis(&self) -> i64 {
+        self.now_timestamp().unix_millis()
+    }
+
+    /// Returns the current time as nanoseconds since the Unix epoch
+    pub fn now_unix_nanos(&self) -> i128 {
+        self.now_timestamp().unix_nanos()
+    }
+
+    /// Returns the current time as a `std::time::SystemTime`
+    pub fn now_system_time(&self) -> SystemTime {
+        self.now_timestamp().into()
+    }
+
+    /// Returns the current time as RFC 3339 in UTC, e.g. `2026-02-03T06:50:57.250+00:00`
+    pub fn format_rfc3339(&self) -> String {
+        self.now_timestamp().to_rfc3339()
+    }
+
+    /// Returns the current time as RFC 2822 in UTC, e.g. `Tue, 3 Feb 2026 06:50:57 +0000`
+    pub fn format_rfc2822(&self) -> String {
+        self.now_timestamp().to_rfc2822()
+    }
+
+    /// Returns the current time in UTC, formatted with a chrono `strftime`-style string such
+    /// as `%Y%m%d-%H%M%S` for filenames
+    #[cfg(feature = "chrono")]
+    pub fn format(&self, fmt: &str) -> Result<String, ClockError> {
+        crate::format_time(&self.get_current_time(), fmt)
+    }
+
+    /// Returns the current time in the system's local timezone
+    #[cfg(feature = "chrono")]
+    pub fn now_local(&self) -> DateTime<Local> {
+        self.get_current_time().with_timezone(&Local)
+    }
+
+    /// Returns the current time at a fixed UTC offset
+    #[cfg(feature = "chrono")]
+    pub fn now_fixed_offset(&self, offset: FixedOffset) -> DateTime<FixedOffset> {
+        self.get_current_time().with_timezone(&offset)
+    }
+
+    /// Returns the current time in an IANA timezone
+    #[cfg(feature = "tz")]
+    pub fn now_in(&self, tz: chrono_tz::Tz) -> DateTime<chrono_tz::Tz> {
+        self.get_current_time().with_timezone(&tz)
+    }
+
+    /// Returns the current time, or [`ClockError::NotSynchronized`] if the clock has no NTP
+    /// time yet and its fallback policy is [`FallbackPolicy::Error`]
+    #[cfg(feature = "chrono")]
+    pub fn try_current_time(&self) -> Result<DateTime<Utc>, ClockError> {
+        self.try_now_timestamp().map(Into::into)
+    }
+
+    /// [`Timestamp`] version of [`try_current_time`](Self::try_current_time)
+    pub fn try_now_timestamp(&self) -> Result<Timestamp, ClockError> {
+        self.shared.try_current_time()
+    }
+
+    /// Where the reported time currently comes from: NTP, or an unverified fallback
+    pub fn time_source(&self) -> TimeSource {
+        self.shared.base.read_or_recover().source
+    }
+
+    /// Returns the configured fallback policy
+    pub fn fallback_policy(&self) -> &FallbackPolicy {
+        &self.shared.fallback_policy
+    }
+
+    /// Blocks until the clock has obtained NTP time, returning the current time.
+    ///
+    /// Fails with [`ClockError::Timeout`] if that does not happen within `timeout`, so
+    /// applications that must not run on the fallback time can fail fast at startup.
+    pub fn wait_until_synchronized(
+        &self,
+        timeout: std::time::Duration,
+    ) -> Result<Timestamp, ClockError> {
+        self.shared.wait_until_synchronized(timeout)
+    }
+
+    /// Blocks until the clock has NTP time known to within `min_accuracy`, by its
+    /// [`uncertainty`](Self::uncertainty), returning the current time.
+    ///
+    /// Meant for early boot on devices without an RTC, so that services started after it
+    /// (TLS, loggers) see correct time; `clock wait` runs it from the command line. Unless
+    /// the clock is [`start`](Self::start)ed, only the sync at construction counts. Fails
+    /// with [`ClockError::Timeout`] after `timeout`.
+    pub fn block_until_plausible_time(
+        &self,
+        min_accuracy: std::time::Duration,
+        timeout: std::time::Duration,
+    ) -> Result<Timestamp, ClockError> {
+        self.shared
+            .block_until_plausible_time(min_accuracy, timeout)
+    }
+
+    /// Async version of [`wait_until_synchronized`](Self::wait_until_synchronized)
+    #[cfg(feature = "tokio")]
+    pub async fn wait_until_synchronized_async(
+        &self,
+        timeout: std::time::Duration,
+    ) -> Result<Timestamp, ClockError> {
+        self.shared.wait_until_synchronized_async(timeout).await
+    }
+
+    /// Blocks until the synchronized clock reads `target`, following any corrections made
+    /// while waiting
+    pub fn sleep_until(&self, target: impl Into<Timestamp>) {
+        schedule::sleep_until(&self.shared, target.into())
+    }
+
+    /// Async version of [`sleep_until`](Self::sleep_until), also woken when the clock is
+    /// stepped
+    #[cfg(feature = "tokio")]
+    pub async fn sleep_until_async(&self, target: impl Into<Timestamp>) {
+        schedule::sleep_until_async(&self.shared, target.into()).await
+    }
+
+    /// An [`Interval`] ticking every `period` from `start` on this clock
+    pub fn interval_at(
+        &self,
+        start: impl Into<Timestamp>,
+        period: std::time::Duration,
+    ) -> Interval {
+        Interval::new(self.handle(), start.into(), period)
+    }
+
+    /// Queries NTP once and applies the result at once. Like a periodic sync, it goes
+    /// through the [`smoothing`](ClockConfig::smoothing) filter, so the `pi` filter slews at
+    /// no more than [`max_slew_ppm`](ClockConfig::max_slew_ppm) rather than stepping; without
+    /// a filter the clock is stepped to the result.
+    ///
+    /// Used after a suspend, when the local clock is known to be behind and the offset is
+    /// not a meaningful stability measurement. Returns whether a sample was obtained.
+    pub fn resync_now(&self) -> bool {
+        self.shared.resync_now()
+    }
+
+    /// Like [`resync_now`](Self::resync_now), but returns within `deadline` even if DNS or
+    /// a server hangs, with the synchronized time, [`ClockError::Timeout`], or
+    /// [`ClockError::SourceUnavailable`] if no server answered. A sync cut off by the
+    /// deadline runs on in the background and still steps the clock if it succeeds; calls
+    /// made while it runs wait on it rather than starting another.
+    pub fn sync_once_with_deadline(
+        &self,
+        deadline: std::time::Duration,
+    ) -> Result<Timestamp, ClockError> {
+        match self.shared.resync_with_deadline(deadline) {
+            Some(true) => Ok(self.now_timestamp()),
+            Some(false) => Err(ClockError::SourceUnavailable(
+                "no NTP server answered".to_string(),
+            )),
+            None => Err(ClockError::Timeout(deadline)),
+        }
+    }
 
-        if latest_time_ntp.is_none() {
-            return;
+    /// Starts the background thread for periodic NTP updates.
+    ///
+    /// The thread runs until `shutdown` is set, [`stop`](Self::stop) is called, or the clock
+    /// is dropped. Starting an already running clock restarts its worker.
+    pub fn start(&self, interval_secs: u64, shutdown: Arc<AtomicBool>) {
+        let mut worker = self.worker.lock_or_recover();
+        if let Some(previous) = worker.take() {
+            previous.stop();
         }
+        *worker = Some(Worker::spawn(
+            Arc::clone(&self.shared),
+            std::time::Duration::from_secs(interval_secs),
+            shutdown,
+        ));
+    }
 
-        let new_time = latest_time_ntp.unwrap();
-        self.latest_time_ntp = Some(new_time);
+    /// Returns the interval between background syncs
+    pub fn sync_interval(&self) -> std::time::Duration {
+        self.shared.sync_interval()
+    }
 
-        // If we're using default time and got a valid NTP time, update
-        if self.latest_time == DEFAULT {
-            self.latest_time = new_time - self.elapsed();
-            self.latest_instant = Instant::now();
-            info!("Initialized time from default to NTP time");
-        } else {
-            // Calculate drift and update time
-            // let expected_time = self.get_current_time();
-            // let drift = new_time.signed_duration_since(expected_time);
-
-            // if drift.num_milliseconds().abs() > 100 {
-            //     info!("Correcting time drift: {} ms", drift.num_milliseconds());
-            //     self.latest_time = new_time;
-            //     self.latest_instant = Instant::now();
-            // }
+    /// Changes the interval between background syncs.
+    ///
+    /// A running worker picks up the new interval immediately: shortening it past the time
+    /// already waited triggers a sync right away. Intervals below one second are raised to
+    /// one second.
+    pub fn set_sync_interval(&self, interval: std::time::Duration) {
+        self.shared.set_sync_interval(interval);
+    }
+
+    /// Stops the background thread and waits for it to exit
+    pub fn stop(&self) {
+        if let Some(worker) = self.worker.lock_or_recover().take() {
+            worker.stop();
         }
     }
 
-    /// Starts the background thread for periodic NTP updates
-    pub fn start(clock: Arc<Mutex<Self>>, interval_secs: u64, shutdown: Arc<AtomicBool>) {
+    /// Starts the background thread for a clock shared through a `Mutex`.
+    ///
+    /// The lock is only taken for each sync cycle, never while sleeping.
+    #[deprecated(note = "Clock is internally synchronized; share an Arc<Clock> and call start()")]
+    pub fn start_locked(clock: Arc<Mutex<Self>>, interval_secs: u64, shutdown: Arc<AtomicBool>) {
         std::thread::spawn(move || {
             while !shutdown.load(Ordering::Relaxed) {
-                {
-                    let mut clock = clock.lock().unwrap();
-                    clock.update_latest_time();
-                    info!("=================================");
-                    info!("Updated the time: {}", clock.latest_time);
-                    info!("=================================");
-                }
-                std::thread::sleep(std::time::Duration::from_secs(interval_secs));
+                clock.lock_or_recover().shared.update_latest_time();
+                engine::sleep_interval(interval_secs, &shutdown);
             }
-            info!("Background sync thread shutting down");
+            clock_log!(Info, Worker, "Background sync thread shutting down");
         });
     }
 
-    /// Returns current synchronization statistics
-    pub fn get_stats(&self) -> &SyncStats {
-        &self.stats
+    /// Returns current synchronization statistics.
+    ///
+    /// The returned guard holds the statistics lock; drop it promptly, or use
+    /// [`stats_snapshot`](Self::stats_snapshot) instead.
+    pub fn get_stats(&self) -> MutexGuard<'_, SyncStats> {
+        self.shared.stats.lock_or_recover()
+    }
+
+    /// Returns a copy of the synchronization statistics, without holding the lock
+    pub fn stats_snapshot(&self) -> SyncStats {
+        self.shared.stats.lock_or_recover().clone()
+    }
+
+    /// Counts the sync attempts of the last `window`, such as [`stats::LAST_HOUR`], to the
+    /// minute; windows longer than [`stats::LAST_DAY`] are shortened to it
+    pub fn window_stats(&self, window: std::time::Duration) -> WindowStats {
+        self.shared.rolling_stats.lock_or_recover().window(window)
+    }
+
+    /// Zeroes the lifetime statistics and the rolling windows
+    pub fn reset_stats(&self) {
+        *self.shared.stats.lock_or_recover() = SyncStats::default();
+        *self.shared.rolling_stats.lock_or_recover() = stats::RollingCounts::new();
+    }
+
+    /// Enables (or disables with `None`) ntpd-style statistics files
+    pub fn set_stats_logger(&self, logger: Option<StatsLogger>) {
+        self.shared.set_stats_logger(logger);
+    }
+
+    /// Enables (or disables with `None`) the [`statusfile`] rewritten after every sync cycle
+    pub fn set_status_file(&self, path: Option<std::path::PathBuf>) {
+        self.shared.set_status_file(path);
+    }
+
+    /// Returns the stored offset history, oldest first
+    pub fn offset_history(&self) -> Vec<OffsetSample> {
+        self.shared.offset_history()
+    }
+
+    /// Returns the recent syncs with their offset, delay, and adjustment, oldest first
+    pub fn sync_history(&self) -> Vec<SyncRecord> {
+        self.shared.sync_history()
+    }
+
+    /// Returns the last `n` sync attempts, failed ones included, oldest first; see
+    /// [`timeline`]
+    pub fn recent_events(&self, n: usize) -> Vec<SyncAttempt> {
+        self.shared.recent_attempts(n)
+    }
+
+    /// Writes [`sync_history`](Self::sync_history) to `path` for offline analysis, see
+    /// [`history`]
+    pub fn export_history(
+        &self,
+        format: HistoryFormat,
+        path: impl AsRef<std::path::Path>,
+    ) -> std::io::Result<()> {
+        history::export(&self.sync_history(), format, path.as_ref())
+    }
+
+    /// Enables (or disables with `None`) the append-only log of clock adjustments.
+    ///
+    /// A log attached here misses the initial sync; set
+    /// [`ClockConfig::audit_log`] to audit it too.
+    pub fn set_audit_log(&self, log: Option<AuditLog>) {
+        self.shared.set_audit_log(log);
+    }
+
+    /// Returns the audited adjustments whose resulting time falls in `range`, oldest first.
+    ///
+    /// Empty if no audit log is enabled; see [`set_audit_log`](Self::set_audit_log).
+    pub fn adjustments(
+        &self,
+        range: impl std::ops::RangeBounds<Timestamp>,
+    ) -> std::io::Result<Vec<Adjustment>> {
+        match self.shared.audit_log_path() {
+            Some(path) => audit::query(&path, range),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Computes Allan deviation of the offset history at octave-spaced averaging times
+    pub fn stability(&self) -> Vec<StabilityPoint> {
+        self.shared.stability()
+    }
+
+    /// The local oscillator's frequency error in parts per million, estimated from the
+    /// offset history (positive when the local clock runs slow)
+    pub fn drift_ppm(&self) -> Option<f64> {
+        self.shared.drift_ppm()
+    }
+
+    /// Starts a [`Stopwatch`] measuring drift-compensated elapsed time
+    pub fn start_stopwatch(&self) -> Stopwatch {
+        Stopwatch::new(self.handle())
+    }
+
+    /// Returns a [`Timestamper`] issuing strictly increasing timestamps for event streams
+    pub fn timestamper(&self) -> Timestamper {
+        Timestamper::new(self.handle())
+    }
+
+    /// Stamps `count` events with one clock read: consecutive nanoseconds starting at the
+    /// current time, all later than any stamp issued by this clock before
+    #[cfg(feature = "chrono")]
+    pub fn timestamp_batch(&self, count: usize) -> impl Iterator<Item = DateTime<Utc>> {
+        self.timestamper().batch(count).map(DateTime::<Utc>::from)
     }
 }
 
-#[cfg(test)]
+#[cfg(feature = "std")]
+impl Drop for Clock {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
+    use std::net::UdpSocket;
+
+    /// Starts a local server answering `replies` NTP requests with `time`, returning its
+    /// address
+    pub(crate) fn spawn_fake_server(time: Timestamp, replies: usize) -> String {
+        spawn_fake_server_with(time, replies, |_| ())
+    }
+
+    /// Like [`spawn_fake_server`], with `edit` applied to each reply before it is sent
+    pub(crate) fn spawn_fake_server_with(
+        time: Timestamp,
+        replies: usize,
+        edit: fn(&mut [u8; 48]),
+    ) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        if replies == 0 {
+            // Close the port now, so queries are refused rather than queued until the
+            // thread gets to drop the socket
+            return addr;
+        }
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 48];
+            for _ in 0..replies {
+                let Ok((_, peer)) = socket.recv_from(&mut buf) else {
+                    return;
+                };
+                let seconds = (time.unix_secs() + 2_208_988_800) as u32;
+                buf[0] = 0x1c; // NTP version 3, server mode
+                buf[1] = 2; // stratum
+                buf[12..16].copy_from_slice(&[192, 0, 2, 1]);
+                buf.copy_within(40..48, 24); // the request's nonce as the origin
+                buf[40..44].copy_from_slice(&seconds.to_be_bytes());
+                buf[44..48].copy_from_slice(&[0; 4]);
+                edit(&mut buf);
+                let _ = socket.send_to(&buf, peer);
+            }
+        });
+        addr
+    }
+
+    /// Like [`spawn_fake_server_with`], signing replies with the key of the request and
+    /// then passing them to `edit`, and answering requests that fail to verify against
+    /// `keys` with a crypto-NAK
+    pub(crate) fn spawn_keyed_fake_server(
+        time: Timestamp,
+        replies: usize,
+        keys: auth::SharedKeyStore,
+        edit: fn(&mut Vec<u8>),
+    ) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; sntp::MAX_PACKET_LEN];
+            for _ in 0..replies {
+                let Ok((len, peer)) = socket.recv_from(&mut buf) else {
+                    return;
+                };
+                let seconds = (time.unix_secs() + 2_208_988_800) as u32;
+                let mut reply = [0u8; 48];
+                reply[0] = 0x1c; // NTP version 3, server mode
+                reply[1] = 2; // stratum
+                reply[12..16].copy_from_slice(&[192, 0, 2, 1]);
+                reply[24..32].copy_from_slice(&buf[40..48]);
+                reply[40..44].copy_from_slice(&seconds.to_be_bytes());
+                let keys = keys.read();
+                let mut reply = match keys.verify(&buf[..len]) {
+                    Ok(id) => keys.sign(id, &reply).unwrap(),
+                    Err(_) => [&reply[..], &[0; 4]].concat(),
+                };
+                edit(&mut reply);
+                let _ = socket.send_to(&reply, peer);
+            }
+        });
+        addr
+    }
+
+    /// A CA and a certificate it issued for `localhost` and 127.0.0.1, with the CA's
+    /// certificate in a PEM file under `dir`
+    #[cfg(any(feature = "nts", feature = "doh"))]
+    pub(crate) struct TestPki {
+        pub dir: std::path::PathBuf,
+        pub ca_file: std::path::PathBuf,
+        /// The server's certificate followed by the CA's
+        pub chain: Vec<rustls::pki_types::CertificateDer<'static>>,
+        /// PKCS #8 key of the server's certificate
+        pub key: Vec<u8>,
+        pub leaf_spki_sha256: [u8; 32],
+        pub ca_spki_sha256: [u8; 32],
+    }
+
+    #[cfg(any(feature = "nts", feature = "doh"))]
+    impl TestPki {
+        pub(crate) fn new(name: &str) -> Self {
+            use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+            use sha2::{Digest, Sha256};
+
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+            let leaf_key = KeyPair::generate().unwrap();
+            let leaf_names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+            let leaf = CertificateParams::new(leaf_names)
+                .unwrap()
+                .signed_by(&leaf_key, &ca, &ca_key)
+                .unwrap();
+
+            let dir = std::env::temp_dir().join(format!("clock-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let ca_file = dir.join("ca.pem");
+            std::fs::write(&ca_file, ca.pem()).unwrap();
+            TestPki {
+                dir,
+                ca_file,
+                chain: vec![leaf.der().clone(), ca.der().clone()],
+                key: leaf_key.serialize_der(),
+                leaf_spki_sha256: Sha256::digest(leaf_key.public_key_der()).into(),
+                ca_spki_sha256: Sha256::digest(ca_key.public_key_der()).into(),
+            }
+        }
+    }
+
+    /// Client-to-server and server-to-client keys
+    #[cfg(feature = "nts")]
+    pub(crate) type SessionKeys = (Vec<u8>, Vec<u8>);
+
+    /// Starts a local NTS-KE server with `pki`'s certificate, speaking only `versions`,
+    /// that serves `sessions` connections. Each hands out 8 cookies for an NTP server at
+    /// 127.0.0.1:`ntp_port`, and the client-to-server and server-to-client keys are sent
+    /// over the returned channel. A cookie holds the two keys, so fake NTS servers can read
+    /// them back without state of their own.
+    #[cfg(feature = "nts")]
+    pub(crate) fn spawn_fake_nts_ke_server(
+        pki: &TestPki,
+        versions: &[&'static rustls::SupportedProtocolVersion],
+        ntp_port: u16,
+        sessions: usize,
+    ) -> (SocketAddr, std::sync::mpsc::Receiver<SessionKeys>) {
+        use rustls::pki_types::PrivateKeyDer;
+        use std::io::{Read, Write};
+
+        let provider = std::sync::Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(versions)
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                pki.chain.clone(),
+                PrivateKeyDer::Pkcs8(pki.key.clone().into()),
+            )
+            .unwrap();
+        config.alpn_protocols = vec![b"ntske/1".to_vec()];
+        let config = std::sync::Arc::new(config);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (keys, received) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for socket in listener.incoming().take(sessions) {
+                let Ok(socket) = socket else {
+                    return;
+                };
+                socket
+                    .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+                    .unwrap();
+                let session = rustls::ServerConnection::new(config.clone()).unwrap();
+                let mut stream = rustls::StreamOwned::new(session, socket);
+                // Skip the request, up to its end-of-message record
+                let request_read = (|| loop {
+                    let mut header = [0u8; 4];
+                    stream.read_exact(&mut header)?;
+                    let mut body = vec![0; u16::from_be_bytes([header[2], header[3]]).into()];
+                    stream.read_exact(&mut body)?;
+                    if header[..2] == [0x80, 0] {
+                        return std::io::Result::Ok(());
+                    }
+                })();
+                if request_read.is_err() {
+                    continue;
+                }
+                let export = |direction: u8| {
+                    stream
+                        .conn
+                        .export_keying_material(
+                            vec![0; 32],
+                            b"EXPORTER-network-time-security",
+                            Some(&[0, 0, 0, 15, direction]),
+                        )
+                        .unwrap()
+                };
+                let (c2s, s2c) = (export(0), export(1));
+                let mut response = Vec::new();
+                let mut record = |record_type: u16, body: &[u8]| {
+                    response.extend_from_slice(&record_type.to_be_bytes());
+                    response.extend_from_slice(&(body.len() as u16).to_be_bytes());
+                    response.extend_from_slice(body);
+                };
+                record(0x8001, &[0, 0]);
+                record(0x8004, &[0, 15]);
+                for i in 0..8 {
+                    record(5, &[&c2s[..], &s2c[..], &[i; 36]].concat());
+                }
+                record(6, b"127.0.0.1");
+                record(7, &ntp_port.to_be_bytes());
+                record(0x8000, &[]);
+                let _ = stream.write_all(&response);
+                stream.conn.send_close_notify();
+                let _ = stream.flush();
+                let _ = keys.send((c2s, s2c));
+            }
+        });
+        (addr, received)
+    }
+
+    /// Like [`spawn_keyed_fake_server`] for NTS: reads the keys of each request back from
+    /// its cookie (see [`spawn_fake_nts_ke_server`]), answers requests that verify with a
+    /// reply that echoes their unique identifier and brings a new cookie for each cookie and
+    /// placeholder they carry, and passes it to `edit`. Other requests get an NTS NAK.
+    #[cfg(feature = "nts")]
+    pub(crate) fn spawn_fake_nts_server(
+        time: Timestamp,
+        replies: usize,
+        edit: fn(&mut Vec<u8>),
+    ) -> SocketAddr {
+        use aes_siv::aead::KeyInit;
+        use aes_siv::siv::Aes128Siv;
+        use extension::{
+            ExtensionField, NTS_AUTHENTICATOR, NTS_COOKIE, NTS_COOKIE_PLACEHOLDER,
+            UNIQUE_IDENTIFIER,
+        };
+
+        // The reply to a verified `request`, with `header` and the fields after it
+        let answer = |request: &[u8], header: &[u8; 48]| -> Option<Vec<u8>> {
+            let parsed = extension::parse_packet(request).ok()?;
+            let (mut unique_id, mut keys, mut cookies) = (None, None, 0);
+            let mut offset = sntp::PACKET_LEN;
+            for field in &parsed.fields {
+                match field.field_type {
+                    UNIQUE_IDENTIFIER => unique_id = Some(field.value.clone()),
+                    NTS_COOKIE => {
+                        keys = Some((field.value.get(..32)?, field.value.get(32..64)?));
+                        cookies += 1;
+                    }
+                    NTS_COOKIE_PLACEHOLDER => cookies += 1,
+                    NTS_AUTHENTICATOR => {
+                        let (c2s, s2c) = keys?;
+                        let unique_id = unique_id.clone()?;
+                        let (nonce, tag) = (field.value.get(4..20)?, field.value.get(20..36)?);
+                        Aes128Siv::new_from_slice(c2s)
+                            .ok()?
+                            .decrypt([&request[..offset], nonce], tag)
+                            .ok()?;
+
+                        let cookie = [c2s, s2c, &[0xcc; 36]].concat();
+                        let mut plaintext = Vec::new();
+                        for _ in 0..cookies {
+                            plaintext.extend_from_slice(&NTS_COOKIE.to_be_bytes());
+                            plaintext.extend_from_slice(&(4 + cookie.len() as u16).to_be_bytes());
+                            plaintext.extend_from_slice(&cookie);
+                        }
+                        let nonce = [0x5a; 16];
+                        let fields = |ciphertext: &[u8]| {
+                            let lengths = [16, ciphertext.len() as u16];
+                            let authenticator = [
+                                &lengths[0].to_be_bytes()[..],
+                                &lengths[1].to_be_bytes(),
+                                &nonce,
+                                ciphertext,
+                            ]
+                            .concat();
+                            [
+                                ExtensionField::new(UNIQUE_IDENTIFIER, unique_id.clone()),
+                                ExtensionField::new(NTS_AUTHENTICATOR, authenticator),
+                            ]
+                        };
+                        let ciphertext_len = plaintext.len() + 16;
+                        let blank = extension::encode_packet(
+                            header,
+                            &fields(&vec![0; ciphertext_len]),
+                            None,
+                        );
+                        // Up to the authenticator's header, lengths, and nonce
+                        let associated = &blank[..blank.len() - 24 - ciphertext_len];
+                        let ciphertext = Aes128Siv::new_from_slice(s2c)
+                            .ok()?
+                            .encrypt([associated, &nonce[..]], &plaintext)
+                            .ok()?;
+                        return Some(extension::encode_packet(header, &fields(&ciphertext), None));
+                    }
+                    _ => {}
+                }
+                offset += extension::FIELD_HEADER_LEN + field.value.len();
+            }
+            None
+        };
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; sntp::MAX_PACKET_LEN];
+            for _ in 0..replies {
+                let Ok((len, peer)) = socket.recv_from(&mut buf) else {
+                    return;
+                };
+                let request = &buf[..len];
+                let seconds = (time.unix_secs() + 2_208_988_800) as u32;
+                let mut header = [0u8; 48];
+                header[0] = 0x24; // NTP version 4, server mode
+                header[1] = 2; // stratum
+                header[12..16].copy_from_slice(&[192, 0, 2, 1]);
+                header[24..32].copy_from_slice(&request[40..48]);
+                header[40..44].copy_from_slice(&seconds.to_be_bytes());
+                let mut reply = answer(request, &header).unwrap_or_else(|| {
+                    // Kiss code NTSN, with the unique identifier if there is one
+                    header[1] = 0;
+                    header[12..16].copy_from_slice(b"NTSN");
+                    let unique_id = extension::parse_packet(request)
+                        .map(|parsed| parsed.fields)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(|field| field.field_type == UNIQUE_IDENTIFIER)
+                        .collect::<Vec<_>>();
+                    extension::encode_packet(&header, &unique_id, None)
+                });
+                edit(&mut reply);
+                let _ = socket.send_to(&reply, peer);
+            }
+        });
+        addr
+    }
 
     #[test]
     fn test_sync_stats_default() {
@@ -236,29 +1384,1147 @@ mod tests {
             total_attempts: 10,
             successful_syncs: 8,
             failed_syncs: 2,
+            ..SyncStats::default()
         };
         assert_eq!(stats.success_rate(), 80.0);
     }
 
+    #[test]
+    fn test_sync_stats_offsets_and_streaks() {
+        let mut stats = SyncStats::default();
+        stats.record_failure();
+        stats.record_failure();
+        assert_eq!(stats.consecutive_failures, 2);
+        stats.record_success(-0.3, 0.02, 0.0);
+        stats.record_success(0.1, 0.04, 0.4);
+        assert_eq!((stats.total_attempts, stats.consecutive_failures), (4, 0));
+        assert_eq!(stats.last_offset, Some(0.1));
+        assert!((stats.mean_abs_offset - 0.2).abs() < 1e-12);
+        assert_eq!(stats.max_abs_offset, 0.3);
+        assert_eq!(stats.last_delay, Some(0.04));
+        assert!((stats.mean_delay - 0.03).abs() < 1e-12);
+        assert_eq!(stats.jitter, 0.4);
+        assert!(stats.last_success.is_some());
+    }
+
+    #[test]
+    fn test_stats_snapshot_windows_and_reset() {
+        let refused = spawn_fake_server(Timestamp::now(), 0);
+        let clock = Clock::with_config(ClockConfig::new().with_servers(vec![refused]));
+        assert!(!clock.resync_now());
+
+        let snapshot = clock.stats_snapshot();
+        assert_eq!((snapshot.total_attempts, snapshot.failed_syncs), (1, 1));
+        let hour = clock.window_stats(stats::LAST_HOUR);
+        assert_eq!((hour.total_attempts, hour.failed_syncs), (1, 1));
+
+        clock.reset_stats();
+        assert_eq!(clock.stats_snapshot().total_attempts, 0);
+        assert_eq!(clock.stats_snapshot().consecutive_failures, 0);
+        assert_eq!(clock.window_stats(stats::LAST_DAY).total_attempts, 0);
+    }
+
+    #[test]
+    fn test_initial_sync_race_does_not_wait_for_silent_servers() {
+        // Bound but never answering, so a query waits out the transport's timeout
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let servers = vec![
+            silent.local_addr().unwrap().to_string(),
+            spawn_fake_server(Timestamp::now(), 1),
+        ];
+        let started = Instant::now();
+        let clock = Clock::with_config(
+            ClockConfig::new()
+                .with_servers(servers)
+                .with_race_initial_sync(true),
+        );
+        assert!(clock.is_synchronized());
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_deadlines_bound_construction_and_syncs() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config =
+            ClockConfig::new().with_servers(vec![silent.local_addr().unwrap().to_string()]);
+        let deadline = std::time::Duration::from_millis(200);
+
+        let started = Instant::now();
+        let clock = Clock::new_with_deadline(config, deadline);
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        assert!(!clock.is_synchronized());
+        assert_eq!(
+            clock.sync_once_with_deadline(deadline),
+            Err(ClockError::Timeout(deadline))
+        );
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+
+        let refused = spawn_fake_server(Timestamp::now(), 0);
+        let clock = Clock::new(Some(vec![refused]));
+        assert!(matches!(
+            clock.sync_once_with_deadline(deadline),
+            Err(ClockError::SourceUnavailable(_))
+        ));
+
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        let config = ClockConfig::new().with_servers(vec![server]);
+        let clock = Clock::new_with_deadline(config, deadline);
+        assert!(clock.is_synchronized());
+    }
+
+    #[test]
+    fn test_zero_deadline_leaves_the_first_sync_to_the_worker() {
+        // One reply, so an initial sync would leave nothing for the worker
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        let config = ClockConfig::new().with_servers(vec![server]);
+        let clock = Clock::new_with_deadline(config, std::time::Duration::ZERO);
+        assert!(!clock.is_synchronized());
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        clock.start(60, Arc::clone(&shutdown));
+        assert!(clock
+            .wait_until_synchronized(std::time::Duration::from_secs(5))
+            .is_ok());
+        shutdown.store(true, Ordering::Relaxed);
+        clock.stop();
+    }
+
+    #[test]
+    fn test_timed_out_syncs_share_one_background_sync() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        clock.reconfigure(
+            &ClockConfig::new().with_servers(vec![silent.local_addr().unwrap().to_string()]),
+        );
+        let deadline = std::time::Duration::from_millis(100);
+        for _ in 0..3 {
+            assert_eq!(
+                clock.sync_once_with_deadline(deadline),
+                Err(ClockError::Timeout(deadline))
+            );
+        }
+
+        // Only the first call queried the server, the others waited on its sync
+        silent
+            .set_read_timeout(Some(std::time::Duration::from_millis(300)))
+            .unwrap();
+        let mut buf = [0u8; 512];
+        assert!(silent.recv_from(&mut buf).is_ok());
+        assert!(silent.recv_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_offline_retries_fast_until_a_sync_succeeds() {
+        let refused = spawn_fake_server(Timestamp::now(), 0);
+        let config = ClockConfig::new()
+            .with_servers(vec![refused])
+            .with_offline_retry_max(Some(std::time::Duration::from_secs(2)));
+        let clock = Clock::with_config(config.clone());
+        clock.start(3600, Arc::new(AtomicBool::new(false)));
+
+        let stats_reach = |done: &dyn Fn(&SyncStats) -> bool| {
+            (0..500).any(|_| {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                done(&clock.get_stats())
+            })
+        };
+        // Retries after 1 s and 2 s rather than an hour
+        assert!(stats_reach(&|stats| stats.total_attempts >= 3));
+        assert!(clock.is_offline());
+
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        clock.reconfigure(&config.with_servers(vec![server]));
+        assert!(stats_reach(&|stats| stats.successful_syncs == 1));
+        assert!(!clock.is_offline());
+        // Back on the hourly interval
+        let attempts = clock.get_stats().total_attempts;
+        std::thread::sleep(std::time::Duration::from_millis(2500));
+        assert_eq!(clock.get_stats().total_attempts, attempts);
+        clock.stop();
+    }
+
     #[test]
     fn test_clock_initialization() {
         let clock = Clock::new(None);
         // Clock should be initialized (even if NTP fails, it uses default time)
-        assert!(clock.latest_time >= DEFAULT);
+        assert!(clock.shared.base.read().unwrap().latest_time >= DEFAULT_TIMESTAMP);
     }
 
     #[test]
     fn test_clock_with_custom_servers() {
         let servers = vec!["time.google.com:123".to_string()];
         let clock = Clock::new(Some(servers.clone()));
-        assert_eq!(clock.ntp_servers, servers);
+        assert_eq!(clock.ntp_servers(), servers);
     }
 
+    #[cfg(feature = "chrono")]
     #[test]
     fn test_clock_get_current_time() {
         let clock = Clock::new(None);
         let current_time = clock.get_current_time();
         // Current time should be greater than or equal to the initial time
-        assert!(current_time >= clock.latest_time);
+        assert!(Timestamp::from(current_time) >= clock.shared.base.read().unwrap().latest_time);
+    }
+
+    #[test]
+    fn test_handle_reads_same_time_as_clock() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let handle = clock.handle().clone();
+        assert_eq!(handle.state(), ClockState::Unsynchronized);
+        assert!(!handle.is_synchronized());
+
+        let before = clock.now_timestamp();
+        let now = handle.now_timestamp();
+        assert!(now >= before && now <= clock.now_timestamp());
+        assert_eq!(handle.stats().total_attempts, 0);
+    }
+
+    #[test]
+    fn test_clock_is_shareable_without_mutex() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Clock>();
+
+        let clock = Arc::new(Clock::new(Some(vec!["invalid.invalid:123".to_string()])));
+        let reader = Arc::clone(&clock);
+        let handle = std::thread::spawn(move || reader.now_timestamp());
+        assert!(handle.join().unwrap() >= DEFAULT_TIMESTAMP);
+    }
+
+    #[test]
+    fn test_drop_joins_worker_thread() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let shared = Arc::downgrade(&clock.shared);
+        clock.start(60, Arc::new(AtomicBool::new(false)));
+
+        drop(clock);
+        // The worker held the only other reference to the shared state
+        assert!(shared.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_worker_restarts_after_panic() {
+        /// Panics on the first read after being armed
+        #[derive(Debug)]
+        struct PanickingSource(AtomicBool);
+        impl ElapsedSource for PanickingSource {
+            fn now(&self) -> std::time::Duration {
+                if self.0.swap(false, Ordering::SeqCst) {
+                    panic!("elapsed source failed");
+                }
+                std::time::Duration::ZERO
+            }
+        }
+
+        let clock = Clock::new(Some(vec![spawn_fake_server(Timestamp::now(), 2)]));
+        let source = Arc::new(PanickingSource(AtomicBool::new(false)));
+        clock.set_elapsed_source(source.clone());
+        source.0.store(true, Ordering::SeqCst);
+        let events = clock.events();
+        clock.start(1, Arc::new(AtomicBool::new(false)));
+
+        let event = events
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            event,
+            ClockEvent::WorkerRestarted {
+                message: "panicked: elapsed source failed".to_string()
+            }
+        );
+        // The lock poisoned by the panic is recovered
+        assert!(clock.is_synchronized());
+        assert!(clock.now_timestamp() > DEFAULT_TIMESTAMP);
+        assert_eq!(clock.handle().stats().worker_restarts, 1);
+        clock.stop();
+    }
+
+    #[test]
+    fn test_watchdog_replaces_stalled_worker() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        assert_eq!(clock.last_heartbeat(), None);
+        let events = clock.events();
+        clock.start(60, Arc::new(AtomicBool::new(false)));
+        assert!(clock.last_heartbeat().is_some());
+
+        // Pretend the loop has not completed a cycle for ten minutes, until the watchdog
+        // notices; the first cycle may still be running and overwrite the heartbeat
+        let stale = Instant::now() - std::time::Duration::from_secs(600);
+        let event = (0..50)
+            .find_map(|_| {
+                clock.shared.control.lock().unwrap().heartbeat = Some(stale);
+                events
+                    .recv_timeout(std::time::Duration::from_millis(200))
+                    .ok()
+            })
+            .unwrap();
+        let ClockEvent::WorkerRestarted { message } = event else {
+            panic!("unexpected event {:?}", event);
+        };
+        assert!(message.starts_with("sync loop stalled for"), "{}", message);
+        assert_eq!(clock.handle().stats().worker_restarts, 1);
+        assert!(clock.last_heartbeat().unwrap() > stale);
+
+        clock.stop();
+        assert_eq!(clock.last_heartbeat(), None);
+    }
+
+    #[test]
+    fn test_stop_leaves_handles_usable() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let handle = clock.handle();
+        clock.start(60, Arc::new(AtomicBool::new(false)));
+        clock.stop();
+
+        assert_eq!(Arc::strong_count(&clock.shared), 2);
+        assert!(handle.now_timestamp() >= DEFAULT_TIMESTAMP);
+    }
+
+    #[test]
+    fn test_stop_interrupts_long_interval() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        clock.start(3600, Arc::new(AtomicBool::new(false)));
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let started = Instant::now();
+        clock.stop();
+        assert!(started.elapsed() < std::time::Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_set_sync_interval_triggers_early_sync() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        clock.start(3600, Arc::new(AtomicBool::new(false)));
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(clock.get_stats().total_attempts, 1);
+
+        clock.set_sync_interval(std::time::Duration::from_secs(1));
+        assert_eq!(clock.sync_interval(), std::time::Duration::from_secs(1));
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+        assert!(clock.get_stats().total_attempts >= 2);
+    }
+
+    #[test]
+    fn test_reconfigure_switches_servers() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        assert!(!clock.is_synchronized());
+
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        let config = ClockConfig::new()
+            .with_servers(vec![server.clone()])
+            .with_sync_interval(std::time::Duration::from_secs(30));
+        clock.reconfigure(&config);
+        assert_eq!(clock.ntp_servers(), [server]);
+        assert_eq!(clock.sync_interval(), std::time::Duration::from_secs(30));
+
+        assert!(clock.resync_now());
+        assert!(clock.is_synchronized());
+    }
+
+    #[test]
+    fn test_uncertainty_starts_at_first_sync() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        assert_eq!(clock.uncertainty(), None);
+
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        clock.reconfigure(&ClockConfig::new().with_servers(vec![server]));
+        assert!(clock.resync_now());
+        let uncertainty = clock.uncertainty().unwrap();
+        assert!(uncertainty < std::time::Duration::from_secs(1));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert!(clock.handle().uncertainty().unwrap() > uncertainty);
+    }
+
+    #[test]
+    fn test_initial_step_is_audited() {
+        let path =
+            std::env::temp_dir().join(format!("clock-ntp-audit-init-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = spawn_fake_server(Timestamp::now() + std::time::Duration::from_secs(60), 1);
+        let clock = Clock::with_config(
+            ClockConfig::new()
+                .with_servers(vec![server.clone()])
+                .with_audit_log(Some(path.clone()))
+                .with_audit_hash_chain(true),
+        );
+
+        let adjustments = clock.adjustments(..).unwrap();
+        assert_eq!(adjustments.len(), 1);
+        assert_eq!(adjustments[0].kind, AdjustmentKind::Step);
+        assert_eq!(adjustments[0].server, server);
+        assert!(adjustments[0].offset > 0.0);
+        assert_eq!(audit::verify(&path).unwrap(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_resync_steps_are_audited() {
+        let path = std::env::temp_dir().join(format!("clock-ntp-audit-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        assert!(clock.adjustments(..).unwrap().is_empty());
+
+        clock.set_audit_log(Some(AuditLog::open(&path).unwrap().with_hash_chain(true)));
+        let server = spawn_fake_server(Timestamp::now() + std::time::Duration::from_secs(60), 1);
+        clock.reconfigure(&ClockConfig::new().with_servers(vec![server.clone()]));
+        assert!(clock.resync_now());
+
+        let adjustments = clock.adjustments(..).unwrap();
+        assert_eq!(adjustments.len(), 1);
+        assert_eq!(adjustments[0].kind, AdjustmentKind::Step);
+        assert_eq!(adjustments[0].server, server);
+        assert!(adjustments[0].offset > 0.0);
+        assert_eq!(audit::verify(&path).unwrap(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_status_file_written_each_cycle() {
+        let path =
+            std::env::temp_dir().join(format!("clock-ntp-status-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let clock = Clock::new(Some(vec![spawn_fake_server(Timestamp::now(), 2)]));
+        clock.set_status_file(Some(path.clone()));
+        clock.start(1, Arc::new(AtomicBool::new(false)));
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !path.exists() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        clock.stop();
+        assert_eq!(statusfile::check(&path, None), Ok(()));
+        let status = std::fs::read_to_string(&path).unwrap();
+        assert!(status.contains("\"synchronized\":true,\"source\":\"NTP-verified\""));
+        assert!(status.contains("\"poll_interval\":1}"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_smoothing_filter_applies_offsets() {
+        let now = Timestamp::now();
+        let clock = Clock::new(Some(vec![spawn_fake_server(now, 1)]));
+        assert!(clock.is_synchronized());
+        // Syncs once, returning how far the reported time jumped and the measured offset
+        let sync = |clock: &Clock| {
+            let (before, started) = (clock.now_timestamp(), Instant::now());
+            clock.shared.update_latest_time();
+            let jump =
+                clock.now_timestamp().seconds_since(before) - started.elapsed().as_secs_f64();
+            (jump, clock.offset_history().last().unwrap().offset)
+        };
+
+        // The server jumps ahead 10 s; without a filter this is only measured
+        let server = spawn_fake_server(now + std::time::Duration::from_secs(10), 3);
+        let config = ClockConfig::new().with_servers(vec![server]);
+        clock.reconfigure(&config);
+        let (jump, offset) = sync(&clock);
+        assert!((9.0..=10.5).contains(&offset), "{}", offset);
+        assert!(jump.abs() < 0.1);
+
+        let ema = SmoothingFilter::Ema { alpha: 0.5 };
+        clock.reconfigure(&config.clone().with_smoothing(Some(ema)));
+        let (jump, offset) = sync(&clock);
+        assert!((jump - offset / 2.0).abs() < 0.1, "{} {}", jump, offset);
+
+        // Raw closes the remaining half at once
+        clock.reconfigure(&config.with_smoothing(Some(SmoothingFilter::Raw)));
+        let (jump, offset) = sync(&clock);
+        assert!((jump - offset / 2.0).abs() < 0.1, "{} {}", jump, offset);
+    }
+
+    #[test]
+    fn test_resync_goes_through_the_smoothing_filter() {
+        let now = Timestamp::now();
+        let pi = SmoothingFilter::Pi { kp: 0.5, ki: 0.05 };
+        let config = ClockConfig::new()
+            .with_servers(vec![spawn_fake_server(now, 1)])
+            .with_smoothing(Some(pi))
+            .with_max_slew_ppm(100.0);
+        let clock = Clock::with_config(config.clone());
+        assert!(clock.is_synchronized());
+        let steps = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&steps);
+        clock.add_step_listener(move |step: &Step| seen.lock().unwrap().push(*step));
+        // Resyncs once, returning how far the reported time jumped
+        let resync = |clock: &Clock| {
+            let (before, started) = (clock.now_timestamp(), Instant::now());
+            assert!(clock.resync_now());
+            clock.now_timestamp().seconds_since(before) - started.elapsed().as_secs_f64()
+        };
+
+        // A forced resync to a server 10 s ahead slews like any sync under `pi`
+        let ahead = spawn_fake_server(now + std::time::Duration::from_secs(10), 2);
+        clock.reconfigure(&config.clone().with_servers(vec![ahead]));
+        assert!(resync(&clock).abs() < 0.1);
+        assert!(steps.lock().unwrap().is_empty());
+
+        // Without a filter, a resync steps
+        let ahead = spawn_fake_server(now + std::time::Duration::from_secs(10), 2);
+        clock.reconfigure(&config.with_servers(vec![ahead]).with_smoothing(None));
+        assert!((resync(&clock) - 10.0).abs() < 0.5);
+        assert_eq!(steps.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_orphan_mode_when_servers_unreachable() {
+        let config = ClockConfig::new()
+            .with_servers(vec![spawn_fake_server(Timestamp::now(), 1)])
+            .with_orphan_after(Some(std::time::Duration::ZERO))
+            .with_orphan_stratum(12);
+        let clock = Clock::with_config(config.clone());
+        assert_eq!(clock.stratum(), 3);
+        let events = clock.events();
+
+        // The server has stopped answering
+        clock.shared.update_latest_time();
+        assert!(clock.is_orphaned());
+        assert_eq!(clock.stratum(), 12);
+        assert_eq!(clock.time_source(), TimeSource::Orphan);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(ClockEvent::OrphanModeEntered { .. })
+        ));
+
+        clock.reconfigure(&config.with_servers(vec![spawn_fake_server(Timestamp::now(), 1)]));
+        clock.shared.update_latest_time();
+        assert!(!clock.is_orphaned());
+        assert_eq!(clock.stratum(), 3);
+        assert_eq!(clock.time_source(), TimeSource::Ntp);
+        let left = events
+            .try_iter()
+            .any(|event| matches!(event, ClockEvent::OrphanModeLeft { .. }));
+        assert!(left);
+    }
+
+    #[test]
+    fn test_falls_back_to_peers_when_servers_unreachable() {
+        let upstream = Clock::with_config(
+            ClockConfig::new().with_servers(vec![spawn_fake_server(Timestamp::now(), 1)]),
+        );
+        assert_eq!(upstream.stratum(), 3);
+        // Served over IPv6 so that the peer does not mistake the upstream's IPv4 loopback
+        // reference for itself
+        let socket = UdpSocket::bind("[::1]:0").unwrap();
+        let peer_addr = socket.local_addr().unwrap().to_string();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let responder = peer::spawn_responder(socket, upstream.handle(), shutdown.clone()).unwrap();
+
+        let clock = Clock::with_config(
+            ClockConfig::new()
+                .with_servers(vec![spawn_fake_server(Timestamp::now(), 0)])
+                .with_peers(vec![peer_addr.clone()]),
+        );
+        assert!(!clock.is_synchronized());
+        clock.shared.update_latest_time();
+        assert!(clock.is_synchronized());
+        assert_eq!(clock.stratum(), 4);
+
+        // A peer no better than this clock's own orphan stratum is not followed
+        let clock = Clock::with_config(
+            ClockConfig::new()
+                .with_servers(vec![spawn_fake_server(Timestamp::now(), 0)])
+                .with_peers(vec![peer_addr])
+                .with_orphan_stratum(3),
+        );
+        clock.shared.update_latest_time();
+        assert!(!clock.is_synchronized());
+
+        shutdown.store(true, Ordering::Relaxed);
+        responder.join().unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_subscribe_publishes_steps() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let mut snapshots = clock.subscribe();
+        assert_eq!(snapshots.borrow().state, ClockState::Unsynchronized);
+
+        clock.set_elapsed_source(Arc::new(MonotonicSource::new()));
+        assert!(snapshots.has_changed().unwrap());
+        assert_eq!(*snapshots.borrow_and_update(), clock.snapshot());
+    }
+
+    #[test]
+    fn test_wait_until_synchronized_times_out() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let timeout = std::time::Duration::from_millis(50);
+        assert_eq!(
+            clock.wait_until_synchronized(timeout),
+            Err(ClockError::Timeout(timeout))
+        );
+    }
+
+    #[test]
+    fn test_block_until_plausible_time() {
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        let clock = Clock::with_config(ClockConfig::new().with_servers(vec![server]));
+        let timeout = std::time::Duration::from_millis(200);
+        let time = clock
+            .block_until_plausible_time(std::time::Duration::from_secs(1), timeout)
+            .unwrap();
+        assert!(time.seconds_since(Timestamp::now()).abs() < 2.0);
+        assert_eq!(
+            clock.block_until_plausible_time(std::time::Duration::ZERO, timeout),
+            Err(ClockError::Timeout(timeout))
+        );
+    }
+
+    #[test]
+    fn test_error_policy_reports_no_time_until_synced() {
+        let config = ClockConfig::new()
+            .with_servers(vec!["invalid.invalid:123".to_string()])
+            .with_fallback_policy(FallbackPolicy::Error);
+        let clock = Clock::with_config(config);
+        assert_eq!(clock.try_now_timestamp(), Err(ClockError::NotSynchronized));
+    }
+
+    #[test]
+    fn test_system_clock_policy_starts_near_system_time() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let skew = clock
+            .try_now_timestamp()
+            .unwrap()
+            .seconds_since(Timestamp::now());
+        assert!(skew.abs() < 5.0);
+        assert_eq!(clock.time_source(), TimeSource::SystemClock);
+        assert!(!clock.time_source().is_verified());
+        assert_eq!(
+            clock.time_source().to_string(),
+            "system-derived, unverified"
+        );
+    }
+
+    #[test]
+    fn test_persisted_policy_starts_from_saved_state() {
+        let path = std::env::temp_dir().join(format!("clock-ntp-state-{}", std::process::id()));
+        let saved = Timestamp::now() + std::time::Duration::from_secs(365 * 86_400);
+        persist::save_state(
+            &path,
+            &persist::PersistedState {
+                time: saved,
+                drift_ppm: 0.0,
+            },
+        )
+        .unwrap();
+
+        let config = ClockConfig::new()
+            .with_servers(vec!["invalid.invalid:123".to_string()])
+            .with_fallback_policy(FallbackPolicy::LastPersistedTime(path.clone()));
+        let clock = Clock::with_config(config);
+        assert_eq!(clock.time_source(), TimeSource::Persisted);
+        assert!(clock.now_timestamp() >= saved);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_min_time_rejects_samples_before_floor() {
+        let old: Timestamp = "2001-01-01T00:00:00Z".parse().unwrap();
+
+        let config = ClockConfig::new().with_servers(vec![spawn_fake_server(old, 1)]);
+        let clock = Clock::with_config(config.with_min_time(None));
+        assert!(clock.is_synchronized());
+
+        let floor: Timestamp = "2020-01-01T00:00:00Z".parse().unwrap();
+        let config = ClockConfig::new()
+            .with_servers(vec![spawn_fake_server(old, 1)])
+            .with_min_time(Some(floor));
+        let clock = Clock::with_config(config);
+        assert!(!clock.is_synchronized());
+        assert!(clock.now_timestamp() >= floor);
+    }
+
+    #[test]
+    fn test_max_time_rejects_implausible_future_samples() {
+        let future: Timestamp = "2035-01-01T00:00:00Z".parse().unwrap();
+        let config = ClockConfig::new()
+            .with_servers(vec![spawn_fake_server(future, 1)])
+            .with_max_time(Some("2030-01-01T00:00:00Z".parse().unwrap()));
+        let clock = Clock::with_config(config);
+        assert!(!clock.is_synchronized());
+        assert!(clock.now_timestamp() < future);
+    }
+
+    #[test]
+    fn test_samples_per_poll_queries_repeatedly() {
+        let server = spawn_fake_server(Timestamp::now(), 3);
+        let config = ClockConfig::new()
+            .with_servers(vec![server.clone()])
+            .with_samples_per_poll(3);
+        let clock = Clock::with_config(config);
+        assert!(clock.is_synchronized());
+
+        // The fake server stops answering after three requests
+        clock.reconfigure(
+            &ClockConfig::new()
+                .with_servers(vec![server])
+                .with_samples_per_poll(1),
+        );
+        assert!(!clock.resync_now());
+    }
+
+    #[test]
+    fn test_combine_sources_discards_falseticker() {
+        let now = Timestamp::now();
+        let servers = vec![
+            spawn_fake_server(now.add_nanos(3_600_000_000_000), 1),
+            spawn_fake_server(now, 1),
+            spawn_fake_server(now, 1),
+        ];
+        let config = ClockConfig::new()
+            .with_servers(servers.clone())
+            .with_combine_sources(true);
+        let clock = Clock::with_config(config);
+        assert!(clock.is_synchronized());
+
+        let weights = clock.source_weights();
+        assert_eq!(weights.len(), 3);
+        assert_eq!(weights[0].server, servers[0]);
+        assert_eq!(weights[0].weight, 0.0);
+        let total: f64 = weights.iter().map(|source| source.weight).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert!(clock.now_timestamp().nanos_since(now).abs() < 60_000_000_000);
+    }
+
+    #[test]
+    fn test_prefer_and_noselect_servers() {
+        let now = Timestamp::now();
+        let hour = 3_600_000_000_000;
+        let (late, preferred, staged) = (
+            spawn_fake_server(now.add_nanos(hour), 1),
+            spawn_fake_server(now, 1),
+            spawn_fake_server(now.add_nanos(-hour), 1),
+        );
+        let config = ClockConfig::new()
+            .with_servers(vec![late, preferred.clone(), staged.clone()])
+            .with_preferred_servers(vec![preferred.clone()])
+            .with_noselect_servers(vec![staged.clone()]);
+        let clock = Clock::with_config(config);
+        assert!(clock.now_timestamp().nanos_since(now).abs() < 60_000_000_000);
+
+        // The staged server is monitored but carries no weight
+        let weights = clock.source_weights();
+        assert_eq!(weights.len(), 2);
+        assert_eq!(
+            (weights[0].server.as_str(), weights[0].weight),
+            (preferred.as_str(), 1.0)
+        );
+        assert_eq!(
+            (weights[1].server.as_str(), weights[1].weight),
+            (staged.as_str(), 0.0)
+        );
+        assert!((weights[1].offset + 3600.0).abs() < 60.0);
+    }
+
+    #[test]
+    fn test_server_spec_options() {
+        let authenticated = format!("ntp://{} key 1", spawn_fake_server(Timestamp::now(), 1));
+        let clock = Clock::with_config(ClockConfig::new().with_servers(vec![authenticated]));
+        assert!(!clock.is_synchronized());
+
+        // An iburst server is sampled several times until it has answered, using up all
+        // four replies
+        let server = spawn_fake_server(Timestamp::now(), 4);
+        let spec = format!("ntp://{}/ iburst version 4 prefer", server);
+        let clock = Clock::with_config(ClockConfig::new().with_servers(vec![spec]));
+        assert!(clock.is_synchronized());
+        assert_eq!(clock.source_weights()[0].server, server);
+        clock.shared.update_latest_time();
+        assert_eq!(clock.get_stats().failed_syncs, 1);
+    }
+
+    #[test]
+    fn test_stratum_follows_server() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        assert_eq!(clock.stratum(), sntp::MAX_STRATUM);
+
+        let clock = Clock::new(Some(vec![spawn_fake_server(Timestamp::now(), 1)]));
+        assert_eq!(clock.stratum(), 3);
+        assert_eq!(clock.source_weights()[0].stratum, 2);
+    }
+
+    #[test]
+    fn test_reference_describes_last_sample() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        assert_eq!(clock.reference(), None);
+
+        let server = spawn_fake_server_with(Timestamp::now(), 1, |reply| {
+            reply[1] = 1;
+            reply[3] = -18i8 as u8;
+            reply[4..8].copy_from_slice(&sntp::encode_short_format(0.5));
+            reply[8..12].copy_from_slice(&sntp::encode_short_format(0.25));
+            reply[12..16].copy_from_slice(b"GPS\0");
+        });
+        let clock = Clock::new(Some(vec![server.clone()]));
+        let reference = clock.handle().reference().unwrap();
+        assert_eq!(reference.server, server);
+        assert_eq!(reference.addr.to_string(), server);
+        assert_eq!(reference.stratum, 1);
+        assert_eq!(reference.refid(), "GPS");
+        assert_eq!(reference.precision_secs(), 2f64.powi(-18));
+        assert_eq!(reference.root_dispersion, 0.25);
+
+        // One stratum further down, referencing the server's address, with the round trip
+        // to the server added to its root delay, and a root distance of the uncertainty
+        let state = clock.server_state();
+        assert_eq!(state.stratum, 2);
+        assert_eq!(state.reference_id, [127, 0, 0, 1]);
+        assert!(
+            state.root_delay > 0.5 && state.root_delay < 0.6,
+            "{}",
+            state.root_delay
+        );
+        let root_distance = state.root_delay / 2.0 + state.root_dispersion;
+        let uncertainty = clock.uncertainty().unwrap().as_secs_f64();
+        assert!(
+            (root_distance - uncertainty).abs() < 1e-6,
+            "{}",
+            root_distance
+        );
+    }
+
+    #[test]
+    fn test_unsynchronized_server_is_rejected() {
+        let server = spawn_fake_server_with(Timestamp::now(), 1, |reply| reply[1] = 0);
+        let clock = Clock::new(Some(vec![server]));
+        assert!(!clock.is_synchronized());
+    }
+
+    #[test]
+    fn test_server_synchronized_to_us_is_rejected() {
+        // Queries to the fake server are sent from 127.0.0.1
+        let server = spawn_fake_server_with(Timestamp::now(), 1, |reply| {
+            reply[12..16].copy_from_slice(&[127, 0, 0, 1])
+        });
+        let clock = Clock::new(Some(vec![server]));
+        assert!(!clock.is_synchronized());
+    }
+
+    #[test]
+    fn test_keyed_server_replies_must_verify() {
+        use auth::{DigestType, KeyStore, SharedKeyStore, SymmetricKey};
+
+        let mut keys = KeyStore::new();
+        keys.add_key(SymmetricKey::new(7, DigestType::Sha1, b"secret".to_vec()));
+        let keys = SharedKeyStore::new(keys);
+        let synchronized = |server: String, key_id: u32| {
+            let config = ClockConfig::new()
+                .with_servers(vec![format!("{} key {}", server, key_id)])
+                .with_keys(Some(keys.clone()));
+            Clock::with_config(config).is_synchronized()
+        };
+        let spawn = |edit| spawn_keyed_fake_server(Timestamp::now(), 1, keys.clone(), edit);
+
+        assert!(synchronized(spawn(|_| ()), 7));
+
+        // Forged and stripped replies never reach selection, and unknown keys are not used
+        assert!(!synchronized(spawn(|reply| reply[40] ^= 1), 7));
+        assert!(!synchronized(
+            spawn(|reply| reply.truncate(sntp::PACKET_LEN)),
+            7
+        ));
+        assert!(!synchronized(spawn(|_| ()), 8));
+    }
+
+    #[test]
+    fn test_rotated_keys_apply_to_the_next_query() {
+        use auth::{DigestType, KeyStore, SharedKeyStore, SymmetricKey};
+
+        let store = |secret: &[u8]| {
+            let mut keys = KeyStore::new();
+            keys.add_key(SymmetricKey::new(7, DigestType::Md5, secret.to_vec()));
+            keys
+        };
+        let (client_keys, server_keys) = (
+            SharedKeyStore::new(store(b"old")),
+            SharedKeyStore::new(store(b"old")),
+        );
+        let server = spawn_keyed_fake_server(Timestamp::now(), 3, server_keys.clone(), |_| ());
+        let clock = Clock::with_config(
+            ClockConfig::new()
+                .with_servers(vec![format!("{} key 7", server)])
+                .with_keys(Some(client_keys.clone())),
+        );
+        assert!(clock.is_synchronized());
+
+        // The server rotates first: the old secret gets a crypto-NAK
+        *server_keys.write() = store(b"new");
+        assert!(!clock.resync_now());
+        *client_keys.write() = store(b"new");
+        assert!(clock.resync_now());
+
+        // A revoked key is no longer sent, so the server is not queried at all
+        client_keys.write().revoke_key(7);
+        assert!(!clock.resync_now());
+        assert_eq!(clock.reference().unwrap().server, server);
+    }
+
+    /// Config of one NTS server, with its key establishment at `ke` trusting `pki`
+    #[cfg(feature = "nts")]
+    fn nts_config(pki: &TestPki, ke: SocketAddr) -> ClockConfig {
+        let server = format!("nts://{} cafile {}", ke, pki.ca_file.display());
+        ClockConfig::new().with_servers(vec![server])
+    }
+
+    #[cfg(feature = "nts")]
+    #[test]
+    fn test_nts_server_replies_must_verify() {
+        let pki = TestPki::new("nts-verify");
+        let synchronized = |edit| {
+            let ntp = spawn_fake_nts_server(Timestamp::now(), 1, edit);
+            let (ke, _) = spawn_fake_nts_ke_server(&pki, &[&rustls::version::TLS13], ntp.port(), 1);
+            Clock::with_config(nts_config(&pki, ke)).is_synchronized()
+        };
+
+        assert!(synchronized(|_| ()));
+
+        // Forged and stripped replies never reach selection
+        assert!(!synchronized(|reply| reply[40] ^= 1));
+        assert!(!synchronized(|reply| reply.truncate(sntp::PACKET_LEN)));
+        std::fs::remove_dir_all(&pki.dir).unwrap();
+    }
+
+    #[cfg(feature = "nts")]
+    #[test]
+    fn test_nts_sessions_survive_restarts_and_end_with_a_nak() {
+        let pki = TestPki::new("nts-sessions");
+        let ntp = spawn_fake_nts_server(Timestamp::now(), 2, |_| ());
+        let (ke, sessions) =
+            spawn_fake_nts_ke_server(&pki, &[&rustls::version::TLS13], ntp.port(), 1);
+        let config = nts_config(&pki, ke).with_nts_cookies(Some(pki.dir.join("nts.cookies")));
+        assert!(Clock::with_config(config.clone()).is_synchronized());
+        assert!(sessions.recv().is_ok());
+
+        // A restart spends the cookies it saved rather than running a key exchange
+        assert!(Clock::with_config(config).is_synchronized());
+        assert!(sessions.try_recv().is_err());
+
+        // A server that no longer reads its cookies ends the session, so the next poll
+        // renews it
+        let ntp = spawn_fake_nts_server(Timestamp::now(), 2, |reply| {
+            reply[1] = 0;
+            reply[12..16].copy_from_slice(b"NTSN");
+            reply.truncate(sntp::PACKET_LEN + 36);
+        });
+        let (ke, sessions) =
+            spawn_fake_nts_ke_server(&pki, &[&rustls::version::TLS13], ntp.port(), 2);
+        let clock = Clock::with_config(nts_config(&pki, ke));
+        assert!(!clock.is_synchronized());
+        assert!(!clock.resync_now());
+        assert_eq!(sessions.iter().take(2).count(), 2);
+        std::fs::remove_dir_all(&pki.dir).unwrap();
+    }
+
+    #[test]
+    fn test_server_in_timing_loop_leaves_selection_while_serving() {
+        let looped = spawn_fake_server_with(Timestamp::now(), 2, |reply| {
+            reply[12..16].copy_from_slice(&[127, 0, 0, 1])
+        });
+        let good = spawn_fake_server(Timestamp::now(), 2);
+        let config = ClockConfig::new()
+            .with_servers(vec![looped.clone(), good.clone()])
+            .with_peer_listen("127.0.0.1:0".parse().ok());
+        let clock = Clock::with_config(config);
+        assert!(clock.is_synchronized());
+        assert_eq!(clock.timing_loops(), vec![looped.clone()]);
+
+        // Still monitored, so the loop is noticed again, but no longer tried first
+        assert!(clock.resync_now());
+        assert_eq!(clock.reference().unwrap().server, good);
+        assert_eq!(clock.timing_loops(), vec![looped]);
+    }
+
+    #[test]
+    fn test_xleave_server_is_measured_in_interleaved_mode() {
+        use std::sync::atomic::AtomicUsize;
+        // Stamps its replies 50 ms before they leave, which only interleaved mode corrects
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let interleaved = Arc::new(AtomicUsize::new(0));
+        let answered = Arc::clone(&interleaved);
+        std::thread::spawn(move || {
+            let ahead = std::time::Duration::from_secs(3600);
+            let state = sntp::ServerState {
+                stratum: 2,
+                root_delay: 0.0,
+                root_dispersion: 0.0,
+                reference_id: [192, 0, 2, 1],
+                reference_time: None,
+            };
+            // The receive timestamp and the precise transmit time of the last reply
+            let mut last: Option<([u8; 8], Timestamp)> = None;
+            let mut request = [0u8; 48];
+            for _ in 0..4 {
+                let Ok((_, peer)) = socket.recv_from(&mut request) else {
+                    return;
+                };
+                let receive = Timestamp::now() + ahead;
+                let mut reply = sntp::server_reply(&request, &state, receive, receive).unwrap();
+                if let Some((last_receive, last_transmit)) = last {
+                    if request[24..32] == last_receive {
+                        reply[24..32].copy_from_slice(&request[32..40]);
+                        reply[32..40].copy_from_slice(&last_receive);
+                        reply[40..48].copy_from_slice(&sntp::encode_timestamp(last_transmit));
+                        answered.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                let _ = socket.send_to(&reply, peer);
+                last = Some((sntp::encode_timestamp(receive), Timestamp::now() + ahead));
+            }
+        });
+
+        let clock = Clock::new(Some(vec![format!("{} xleave", addr)]));
+        assert!(clock.is_synchronized());
+        assert!(interleaved.load(Ordering::SeqCst) >= 1);
+        let error = clock.now_timestamp().seconds_since(Timestamp::now()) - 3600.0;
+        assert!(error.abs() < 0.01, "{}", error);
+
+        // A server that answers in basic mode is still measured, in basic mode
+        let basic = spawn_fake_server(Timestamp::now(), 4);
+        let clock = Clock::new(Some(vec![format!("{} xleave", basic)]));
+        assert!(clock.is_synchronized());
+        assert!(clock.resync_now() && clock.resync_now());
+    }
+
+    #[test]
+    fn test_best_practices_honors_kiss_of_death() {
+        let server = spawn_fake_server_with(Timestamp::now(), 2, |reply| {
+            reply[1] = 0;
+            reply[12..16].copy_from_slice(b"RATE");
+        });
+        // Servers that stopped listening fill the list up to four, so no pool servers are added
+        let mut servers: Vec<String> = (0..3)
+            .map(|_| spawn_fake_server(Timestamp::now(), 0))
+            .collect();
+        servers.insert(0, server.clone());
+        let clock = Clock::with_config(
+            ClockConfig::new()
+                .with_servers(servers)
+                .with_best_practices(true),
+        );
+        assert!(!clock.is_synchronized());
+        // The other servers were just queried, so the resync waits before querying them again
+        let started = std::time::Instant::now();
+        assert!(!clock.resync_now());
+        assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+
+        // The server was not queried again, so its second reply is still available
+        let mut transport = sntp::UdpTransport::new(std::time::Duration::from_millis(500));
+        let addr = server.parse().unwrap();
+        let reply = sntp::query(&mut transport, &addr).unwrap();
+        assert_eq!(reply.kiss_code(), Some("RATE"));
+    }
+
+    #[test]
+    fn test_query_budget_limits_forced_syncs() {
+        let server = spawn_fake_server(Timestamp::now(), 2);
+        let config = ClockConfig::new()
+            .with_servers(vec![server.clone()])
+            .with_max_queries_per_minute(Some(1));
+        let clock = Clock::with_config(config);
+        assert!(clock.is_synchronized());
+        assert!(!clock.resync_now());
+
+        // Lifting the budget lets the next sync through
+        clock.reconfigure(&ClockConfig::new().with_servers(vec![server]));
+        assert!(clock.resync_now());
+    }
+
+    #[test]
+    fn test_queries_use_random_source_ports() {
+        let server = spawn_fake_server(Timestamp::now(), 8).parse().unwrap();
+        let mut transport = sntp::UdpTransport::new(std::time::Duration::from_secs(1))
+            .with_source_ports(50000..=59999);
+        let mut ports = std::collections::HashSet::new();
+        for _ in 0..8 {
+            sntp::query(&mut transport, &server).unwrap();
+            let port = transport.local_addr().unwrap().port();
+            assert!((50000..=59999).contains(&port));
+            ports.insert(port);
+        }
+        assert!(ports.len() > 1);
+    }
+
+    #[test]
+    fn test_max_delay_rejects_slow_samples() {
+        let config = ClockConfig::new()
+            .with_servers(vec![spawn_fake_server(Timestamp::now(), 1)])
+            .with_max_delay(Some(std::time::Duration::ZERO));
+        let clock = Clock::with_config(config);
+        assert!(!clock.is_synchronized());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_now_fixed_offset_matches_utc() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let offset = FixedOffset::east_opt(5 * 3600 + 1800).unwrap();
+        let local = clock.now_fixed_offset(offset);
+        assert_eq!(local.offset(), &offset);
+        let skew = local.signed_duration_since(clock.get_current_time());
+        assert!(skew.num_seconds().abs() < 1);
+    }
+
+    #[cfg(feature = "tz")]
+    #[test]
+    fn test_now_in_uses_named_timezone() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let tokyo = clock.now_in(chrono_tz::Asia::Tokyo);
+        assert_eq!(
+            chrono::Offset::fix(tokyo.offset()).local_minus_utc(),
+            9 * 3600
+        );
+    }
+
+    #[test]
+    fn test_unix_accessors_agree() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let secs = clock.now_unix_secs();
+        let millis = clock.now_unix_millis();
+        let nanos = clock.now_unix_nanos();
+        assert!((millis / 1000 - secs).abs() <= 1);
+        assert!((nanos / 1_000_000 - millis as i128).abs() < 1000);
+
+        let system = clock.now_system_time();
+        let since_epoch = system.duration_since(std::time::UNIX_EPOCH).unwrap();
+        assert!((since_epoch.as_secs() as i64 - secs).abs() <= 1);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_now_offset_datetime_matches_timestamp() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let odt = clock.now_offset_datetime();
+        let skew = Timestamp::from(odt).seconds_since(clock.now_timestamp());
+        assert!(skew.abs() < 1.0);
+    }
+
+    #[test]
+    fn test_format_helpers() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let rfc3339: Timestamp = clock.format_rfc3339().parse().unwrap();
+        assert!(rfc3339.seconds_since(clock.now_timestamp()).abs() < 1.0);
+        assert!(clock.format_rfc2822().ends_with(" +0000"));
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_format_rejects_invalid_specifier() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        assert_eq!(clock.format("%Y").unwrap().len(), 4);
+        assert_eq!(
+            clock.format("%Q"),
+            Err(ClockError::InvalidFormat("%Q".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_now_tai_is_ahead_of_utc() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let utc = clock.now_timestamp();
+        let tai = clock.now_tai();
+        let gps = clock.now_gps();
+        assert!((tai.seconds_since(utc) - 37.0).abs() < 1.0);
+        assert!((tai.seconds_since(gps) - 19.0).abs() < 1.0);
     }
 }
@@ -0,0 +1,71 @@
+//! # Poisoned Lock Recovery
+//!
+//! A thread that panics while holding a lock poisons it, and `lock().unwrap()` would pass
+//! that panic on to every later user: one bug in the sync loop would take every reader of
+//! the clock down with it. The state behind this crate's locks is consistent between
+//! statements, so the helpers here log the poisoning and carry on with the guard instead.
+
+use crate::logging::clock_log;
+use std::sync::{LockResult, Mutex, MutexGuard, PoisonError, RwLock};
+use std::sync::{RwLockReadGuard, RwLockWriteGuard};
+
+/// Takes the guard out of a possibly poisoned lock result, e.g. of a `Condvar` wait
+pub(crate) fn recover<G>(result: LockResult<G>) -> G {
+    result.unwrap_or_else(|poisoned: PoisonError<G>| {
+        clock_log!(
+            Warn,
+            Worker,
+            "Recovering a lock poisoned by a panicked thread"
+        );
+        poisoned.into_inner()
+    })
+}
+
+/// [`Mutex::lock`] that recovers from poisoning
+pub(crate) trait MutexExt<T: ?Sized> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T: ?Sized> MutexExt<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        recover(self.lock())
+    }
+}
+
+/// [`RwLock::read`] and [`RwLock::write`] that recover from poisoning
+pub(crate) trait RwLockExt<T: ?Sized> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T>;
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T: ?Sized> RwLockExt<T> for RwLock<T> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T> {
+        recover(self.read())
+    }
+
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T> {
+        recover(self.write())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poisoned_locks_are_recovered() {
+        let mutex = Mutex::new(1);
+        let rwlock = RwLock::new(1);
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = mutex.lock().unwrap();
+            let _write = rwlock.write().unwrap();
+            panic!("poison");
+        });
+        assert!(mutex.is_poisoned() && rwlock.is_poisoned());
+
+        *mutex.lock_or_recover() += 1;
+        *rwlock.write_or_recover() += 1;
+        assert_eq!(*mutex.lock_or_recover(), 2);
+        assert_eq!(*rwlock.read_or_recover(), 2);
+    }
+}
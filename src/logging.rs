@@ -0,0 +1,276 @@
+//! # Log Routing
+//!
+//! Everything the library logs goes through this module, tagged with a [`LogCategory`]. By
+//! default messages go to the [`log`] crate with the category as the target
+//! (`clock::sync`, `clock::server`, ...), so `RUST_LOG=info,clock::server=warn` quiets the
+//! per-server chatter alone. Embedders can also:
+//!
+//! * route messages into their own telemetry instead of `log` with [`set_sink`]
+//! * drop everything below warnings with [`set_quiet`]
+//! * throttle a category with [`set_throttle`]: after a message of that category gets
+//!   through, the rest within the window are dropped, and the next one to get through says
+//!   how many were
+//!
+//! [`LogCategory::Cycle`], the line the sync loop logs once per cycle, is throttled to one
+//! message per [`DEFAULT_CYCLE_THROTTLE`] unless configured otherwise. Like the `log`
+//! crate's logger, these settings are process-wide.
+
+use lazy_static::lazy_static;
+use log::Level;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+/// Throttle window of [`LogCategory::Cycle`] unless set with [`set_throttle`]
+pub const DEFAULT_CYCLE_THROTTLE: Duration = Duration::from_secs(60);
+
+/// What a log message is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogCategory {
+    /// Sync results, steps of the clock, fallback and orphan mode
+    Sync,
+    /// Queries to individual servers and why their samples were rejected
+    Server,
+    /// The sync loop's once-per-cycle progress line and interval changes
+    Cycle,
+    /// mDNS discovery and advertising, and falling back to peers
+    Discovery,
+    /// The HTTP API, WebSocket clients, and answering peers' queries
+    Serving,
+    /// Configuration changes and reloads
+    Config,
+    /// Background threads: the sync loop, its watchdog, the scheduler, and service managers
+    Worker,
+    /// Suspicious offsets and the hooks they fire
+    Anomaly,
+    /// Persisted state, audit logs, and statistics files
+    Storage,
+}
+
+impl LogCategory {
+    /// Every category
+    pub const ALL: [LogCategory; 9] = [
+        LogCategory::Sync,
+        LogCategory::Server,
+        LogCategory::Cycle,
+        LogCategory::Discovery,
+        LogCategory::Serving,
+        LogCategory::Config,
+        LogCategory::Worker,
+        LogCategory::Anomaly,
+        LogCategory::Storage,
+    ];
+
+    /// The category's name, as accepted by [`FromStr`]
+    pub fn name(&self) -> &'static str {
+        self.target().trim_start_matches("clock::")
+    }
+
+    /// The `log` target messages of this category are logged with
+    pub fn target(&self) -> &'static str {
+        match self {
+            LogCategory::Sync => "clock::sync",
+            LogCategory::Server => "clock::server",
+            LogCategory::Cycle => "clock::cycle",
+            LogCategory::Discovery => "clock::discovery",
+            LogCategory::Serving => "clock::serving",
+            LogCategory::Config => "clock::config",
+            LogCategory::Worker => "clock::worker",
+            LogCategory::Anomaly => "clock::anomaly",
+            LogCategory::Storage => "clock::storage",
+        }
+    }
+}
+
+impl fmt::Display for LogCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for LogCategory {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LogCategory::ALL
+            .into_iter()
+            .find(|category| category.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown log category '{}'", s))
+    }
+}
+
+/// One message, as handed to a [`LogSink`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub level: Level,
+    pub category: LogCategory,
+    pub message: String,
+}
+
+/// Receives the library's log messages in place of the `log` crate, see [`set_sink`]
+pub trait LogSink: Send + Sync {
+    fn log(&self, record: &LogRecord);
+}
+
+impl<F: Fn(&LogRecord) + Send + Sync> LogSink for F {
+    fn log(&self, record: &LogRecord) {
+        self(record)
+    }
+}
+
+/// Throttling state of one category
+#[derive(Debug)]
+struct Throttle {
+    window: Duration,
+    last: Option<Instant>,
+    suppressed: u64,
+}
+
+impl Throttle {
+    fn new(window: Duration) -> Self {
+        Throttle {
+            window,
+            last: None,
+            suppressed: 0,
+        }
+    }
+}
+
+// Poisoning is ignored here rather than recovered through `lock`, which logs about it and
+// would come straight back into this module
+lazy_static! {
+    static ref SINK: RwLock<Option<Arc<dyn LogSink>>> = RwLock::new(None);
+    static ref THROTTLES: Mutex<HashMap<LogCategory, Throttle>> = Mutex::new(HashMap::from([(
+        LogCategory::Cycle,
+        Throttle::new(DEFAULT_CYCLE_THROTTLE)
+    )]));
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Sends messages to `sink` instead of the `log` crate, or back to `log` with `None`
+pub fn set_sink(sink: Option<Arc<dyn LogSink>>) {
+    *SINK.write().unwrap_or_else(PoisonError::into_inner) = sink;
+}
+
+/// Drops every message below [`Level::Warn`] while `quiet` is set
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Lets through at most one message of `category` per `window`, or all of them with `None`
+pub fn set_throttle(category: LogCategory, window: Option<Duration>) {
+    let mut throttles = THROTTLES.lock().unwrap_or_else(PoisonError::into_inner);
+    match window {
+        Some(window) => {
+            throttles.insert(category, Throttle::new(window));
+        }
+        None => {
+            throttles.remove(&category);
+        }
+    }
+}
+
+/// Whether a message of `category` gets through its throttle now, and if so, how many
+/// were dropped before it
+fn admit(category: LogCategory) -> Option<u64> {
+    let mut throttles = THROTTLES.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(throttle) = throttles.get_mut(&category) else {
+        return Some(0);
+    };
+    let now = Instant::now();
+    if throttle
+        .last
+        .is_some_and(|last| now.duration_since(last) < throttle.window)
+    {
+        throttle.suppressed += 1;
+        return None;
+    }
+    throttle.last = Some(now);
+    Some(std::mem::take(&mut throttle.suppressed))
+}
+
+/// Filters a message and passes it to the sink or the `log` crate; use [`clock_log`]
+pub(crate) fn dispatch(level: Level, category: LogCategory, args: fmt::Arguments<'_>) {
+    if level > Level::Warn && QUIET.load(Ordering::Relaxed) {
+        return;
+    }
+    let sink = SINK.read().unwrap_or_else(PoisonError::into_inner).clone();
+    if sink.is_none() && !log::log_enabled!(target: category.target(), level) {
+        return;
+    }
+    let Some(suppressed) = admit(category) else {
+        return;
+    };
+    let mut message = args.to_string();
+    if suppressed > 0 {
+        let _ = write!(message, " ({} similar messages suppressed)", suppressed);
+    }
+    match sink {
+        Some(sink) => sink.log(&LogRecord {
+            level,
+            category,
+            message,
+        }),
+        None => log::log!(target: category.target(), level, "{}", message),
+    }
+}
+
+/// Logs a message through [`dispatch`]: `clock_log!(Info, Sync, "Stepped to {}", time)`
+macro_rules! clock_log {
+    ($level:ident, $category:ident, $($arg:tt)+) => {
+        $crate::logging::dispatch(
+            ::log::Level::$level,
+            $crate::logging::LogCategory::$category,
+            format_args!($($arg)+),
+        )
+    };
+}
+pub(crate) use clock_log;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sink_quiet_mode_and_throttling() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&records);
+        set_sink(Some(Arc::new(move |record: &LogRecord| {
+            // Other tests log concurrently; only this category is used here
+            if record.category == LogCategory::Anomaly {
+                sink.lock().unwrap().push(record.clone());
+            }
+        })));
+
+        set_quiet(true);
+        clock_log!(Info, Anomaly, "dropped");
+        clock_log!(Warn, Anomaly, "kept {}", 1);
+        set_quiet(false);
+
+        set_throttle(LogCategory::Anomaly, Some(Duration::from_millis(200)));
+        clock_log!(Info, Anomaly, "first");
+        clock_log!(Info, Anomaly, "second");
+        clock_log!(Info, Anomaly, "third");
+        std::thread::sleep(Duration::from_millis(250));
+        clock_log!(Info, Anomaly, "fourth");
+        set_throttle(LogCategory::Anomaly, None);
+        set_sink(None);
+
+        let messages: Vec<String> = records
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|record| record.message.clone())
+            .collect();
+        assert_eq!(
+            messages,
+            ["kept 1", "first", "fourth (2 similar messages suppressed)"]
+        );
+        assert_eq!("Cycle".parse(), Ok(LogCategory::Cycle));
+        assert_eq!(LogCategory::Server.target(), "clock::server");
+    }
+}
@@ -2,17 +2,53 @@
 //!
 //! Command-line application for displaying NTP-synchronized time.
 
-use chrono::Duration;
-use clap::Parser;
-use clock::Clock;
-use log::info;
+use chrono::{DateTime, FixedOffset};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clock::auth::{KeyStore, SharedKeyStore};
+use clock::sntp::UdpTransport;
+use clock::transport::TransportFactory;
+use clock::{
+    AuditLog, BootTimeSource, Clock, ClockConfig, FallbackPolicy, StatsFormat, StatsLogger,
+};
+use log::{error, info};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+
+/// Name the binary registers under when run with `--service`
+#[cfg(all(windows, feature = "windows-service"))]
+const SERVICE_NAME: &str = "clock-ntp";
+
+/// Set by the SIGHUP handler; the display loop reloads the configuration and keys files when
+/// it sees it
+#[cfg(unix)]
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Records the exchanges of every configuration the clock runs with, when given `--record`
+static RECORDER: std::sync::OnceLock<clock::capture::Recorder> = std::sync::OnceLock::new();
+
+/// The keys of the `--keys` file, shared by the client, the peer responder, and the SIGHUP
+/// reload
+static KEYS: std::sync::OnceLock<SharedKeyStore> = std::sync::OnceLock::new();
+
+#[cfg(unix)]
+extern "C" fn request_reload(_signal: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+}
 
 /// Command-line arguments for the NTP clock application
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Configuration file of `key = value` lines; options given on the command line take
+    /// precedence. Reloaded on SIGHUP
+    #[arg(short, long)]
+    config: Option<std::path::PathBuf>,
+
+    /// Apply changes to the --config file as soon as it is modified, without waiting for SIGHUP
+    #[arg(long, requires = "config")]
+    watch_config: bool,
+
     /// NTP update interval in seconds
     #[arg(short, long, default_value_t = 10)]
     interval: u64,
@@ -21,8 +57,9 @@ struct Args {
     #[arg(short, long, default_value_t = 1)]
     display_interval: u64,
 
-    /// Custom NTP server (can be specified multiple times)
-    #[arg(short, long)]
+    /// Custom NTP server: host, host:port, or ntp://host:port, optionally followed by
+    /// options such as "iburst" or "version 4" (can be specified multiple times)
+    #[arg(short, long, value_parser = parse_server)]
     server: Vec<String>,
 
     /// Timezone offset in hours (e.g., -5 for EST, 0 for UTC)
@@ -33,17 +70,550 @@ struct Args {
     #[arg(short, long)]
     verbose: bool,
 
+    /// Only log the library's warnings and errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log at most one message of a category per interval, e.g. "server=60"; categories are
+    /// sync, server, cycle, discovery, serving, config, worker, anomaly, and storage, and 0
+    /// turns throttling off (can be specified multiple times)
+    #[arg(long, value_name = "CATEGORY=SECS", value_parser = parse_log_throttle)]
+    log_throttle: Vec<(clock::LogCategory, u64)>,
+
     /// Show statistics
     #[arg(long)]
     show_stats: bool,
+
+    /// Directory for ntpd-style loopstats/peerstats files
+    #[arg(long)]
+    statsdir: Option<std::path::PathBuf>,
+
+    /// Format of the statistics files (ntpd or csv)
+    #[arg(long, default_value = "ntpd")]
+    stats_format: StatsFormat,
+
+    /// Append every adjustment of the clock to this audit log file
+    #[arg(long)]
+    audit_log: Option<std::path::PathBuf>,
+
+    /// Chain the --audit-log records with SHA-256 so tampering can be detected
+    #[arg(long, requires = "audit_log")]
+    audit_hash_chain: bool,
+
+    /// Rewrite this JSON status file atomically after every sync cycle, for the
+    /// healthcheck subcommand and sidecars
+    #[arg(long)]
+    status_file: Option<std::path::PathBuf>,
+
+    /// Record every exchange with a server to this capture file, for the replay subcommand
+    #[arg(long, value_name = "PATH")]
+    record: Option<std::path::PathBuf>,
+
+    /// Measure elapsed time with a clock that keeps counting during system suspend
+    #[arg(long)]
+    boottime: bool,
+
+    /// Interpolate between syncs from the CPU's timestamp counter for low-latency reads
+    #[cfg(feature = "quanta")]
+    #[arg(long, conflicts_with = "boottime")]
+    tsc: bool,
+
+    /// Time to report before the first sync: error, system, default, or file:PATH
+    #[arg(long, default_value = "system")]
+    fallback: FallbackPolicy,
+
+    /// Reject NTP time earlier than this RFC 3339 timestamp (defaults to the build-time floor)
+    #[arg(long)]
+    min_time: Option<clock::Timestamp>,
+
+    /// Reject NTP time later than this RFC 3339 timestamp, or than N years after the
+    /// build-time floor with +Ny (default: +20y if a floor was baked in)
+    #[arg(
+        long,
+        value_name = "TIME",
+        value_parser = clock::config::parse_max_time,
+        conflicts_with = "no_max_time"
+    )]
+    max_time: Option<clock::Timestamp>,
+
+    /// Accept NTP time however far in the future it is
+    #[arg(long)]
+    no_max_time: bool,
+
+    /// Cross-check NTP time against the hardware RTC at startup and on every sync; when
+    /// they disagree beyond --rtc-tolerance, trust-ntp, trust-rtc, or hold
+    #[arg(long, value_name = "POLICY")]
+    rtc_policy: Option<clock::rtc::RtcPolicy>,
+
+    /// RTC device cross-checked by --rtc-policy and set by --rtc-write (default: /dev/rtc0)
+    #[arg(long, value_name = "PATH")]
+    rtc_device: Option<std::path::PathBuf>,
+
+    /// Seconds NTP and the RTC may disagree before --rtc-policy applies (default: 3600)
+    #[arg(long, value_name = "SECONDS", requires = "rtc_policy")]
+    rtc_tolerance: Option<u64>,
+
+    /// While synchronized, set the hardware RTC to the disciplined time every SECONDS
+    /// (default: 660) so the next boot starts close to it; needs CAP_SYS_TIME
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        default_missing_value = "660",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    rtc_write: Option<u64>,
+
+    /// Also reject NTP time earlier than the time persisted with --fallback file:PATH
+    #[arg(long)]
+    persisted_floor: bool,
+
+    /// Output format: rfc3339, rfc2822, or a strftime-style string
+    #[arg(long, default_value = "%Y-%m-%d %H:%M:%S")]
+    format: String,
+
+    /// Seconds since the last successful sync after which the clock is reported as stale
+    /// (defaults to 3x the update interval)
+    #[arg(long)]
+    stale_after: Option<u64>,
+
+    /// Requests per sync; with more than one, outliers are dropped and the median offset used
+    #[arg(long)]
+    samples_per_poll: Option<u32>,
+
+    /// Try this server before the others and report it as the source when it agrees with the
+    /// majority (can be specified multiple times)
+    #[arg(long)]
+    prefer: Vec<String>,
+
+    /// Query and report this server without using it to set the time, for staging new
+    /// servers (can be specified multiple times)
+    #[arg(long)]
+    noselect: Vec<String>,
+
+    /// Query every server and combine those that agree, weighted by root distance and jitter
+    #[arg(long)]
+    combine_sources: bool,
+
+    /// Query all servers at once at startup and start from the first that answers
+    #[arg(long)]
+    race_initial_sync: bool,
+
+    /// Follow the RFC 8633 best practices: at least four servers, combined; polls at least
+    /// 64 s apart with random jitter; kiss-o'-death honored; pool addresses rotated
+    #[arg(long)]
+    best_practices: bool,
+
+    /// Reject samples with a round-trip delay above this many milliseconds
+    #[arg(long)]
+    max_delay_ms: Option<u64>,
+
+    /// Reject samples with a round-trip delay above this multiple of the server's recent
+    /// minimum
+    #[arg(long)]
+    max_delay_ratio: Option<f64>,
+
+    /// Never send more than this many queries to one server per minute, whatever forces a
+    /// sync
+    #[arg(long)]
+    max_queries_per_minute: Option<u32>,
+
+    /// Send each query from a random port in this range, e.g. 50000-50999 (default:
+    /// 49152-65535)
+    #[arg(long, value_parser = clock::config::parse_port_range)]
+    source_ports: Option<std::ops::RangeInclusive<u16>>,
+
+    /// Mark queries with this DSCP code point for QoS, as 0-63 or a name such as EF, CS6, or
+    /// AF41
+    #[arg(long, value_parser = clock::config::parse_dscp)]
+    dscp: Option<u8>,
+
+    /// TTL (IPv4) or hop limit (IPv6) of queries
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=255))]
+    ttl: Option<u32>,
+
+    /// Resolve server names with: system, static:HOST=IP[,IP...][;HOST=IP...] (air-gapped
+    /// networks), or "doh:https://IP[:PORT]/PATH [cafile PATH] [pin sha256//BASE64]"
+    /// (DNS-over-HTTPS, with the `doh` feature)
+    #[arg(long, value_name = "STRATEGY")]
+    dns: Option<clock::DnsStrategy>,
+
+    /// Query only server addresses in these ranges: IP/PREFIX, or private, loopback, or
+    /// link-local, separated by commas
+    #[arg(long, value_name = "RANGES")]
+    allow_addresses: Option<clock::dns::AddressSet>,
+
+    /// Never query server addresses in these ranges, e.g. private,loopback for public pools
+    #[arg(long, value_name = "RANGES")]
+    deny_addresses: Option<clock::dns::AddressSet>,
+
+    /// Apply offsets measured after the first sync to the reported time: raw (step),
+    /// ema[:ALPHA], or pi[:KP,KI] (slew)
+    #[arg(long)]
+    smoothing: Option<clock::SmoothingFilter>,
+
+    /// Fastest rate, in ppm, at which the pi filter slews the reported time (default: 500)
+    #[arg(long)]
+    max_slew_ppm: Option<f64>,
+
+    /// Once no server is reachable, retry after 1, 2, 4, ... seconds up to this many instead
+    /// of waiting out the sync interval
+    #[arg(long, value_name = "SECS")]
+    offline_retry_max: Option<u64>,
+
+    /// Enter orphan mode, free-running with drift compensation, after this many seconds
+    /// without a reachable server
+    #[arg(long)]
+    orphan_after: Option<u64>,
+
+    /// Stratum reported in orphan mode (default: 10)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=15))]
+    orphan_stratum: Option<u8>,
+
+    /// Another clock instance to poll when no server is reachable (can be specified
+    /// multiple times)
+    #[arg(long, value_name = "HOST:PORT")]
+    peer: Vec<String>,
+
+    /// Answer peers' NTP queries on this address, e.g. 0.0.0.0:11123
+    #[arg(long, value_name = "ADDR")]
+    peer_listen: Option<std::net::SocketAddr>,
+
+    /// ntpd-style keys file of `ID TYPE SECRET` lines; servers with `key N` are queried with
+    /// key N, requests signed with one of its keys get signed replies from --peer-listen,
+    /// and requests failing verification none. Reloaded on SIGHUP
+    #[arg(long, value_name = "PATH")]
+    keys: Option<std::path::PathBuf>,
+
+    /// Send a client of --peer-listen kiss-o'-death RATE replies after a burst of BURST
+    /// queries, until it slows to one per INTERVAL seconds (default: 2:8)
+    #[arg(
+        long,
+        value_name = "INTERVAL[:BURST]",
+        conflicts_with = "no_rate_limit"
+    )]
+    rate_limit: Option<clock::peer::RateLimit>,
+
+    /// Answer every query to --peer-listen, however fast a client sends them
+    #[arg(long)]
+    no_rate_limit: bool,
+
+    /// Clients of --peer-listen remembered for rate limiting and the time API's /clients
+    /// (default: 1024)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    mru_size: Option<u64>,
+
+    /// Add NTP servers advertised on the local network as `_ntp._udp.local`
+    #[arg(long)]
+    mdns_discovery: bool,
+
+    /// Probe this broadcast or LAN address (port 123 unless given) for NTP servers at
+    /// startup and add those that answer (can be specified multiple times)
+    #[arg(long, value_name = "ADDR[:PORT]", value_parser = clock::manycast::parse_target)]
+    manycast: Vec<std::net::SocketAddr>,
+
+    /// Resync in a burst as soon as a network interface comes up or gains an address
+    /// (Linux, macOS, FreeBSD, Windows)
+    #[arg(long)]
+    resync_on_network_change: bool,
+
+    /// Advertise the --peer-listen responder over mDNS under this instance name
+    #[arg(long, value_name = "NAME", requires = "peer_listen")]
+    mdns_advertise: Option<String>,
+
+    /// Offset change in milliseconds that is reported as an anomaly
+    #[arg(long)]
+    anomaly_threshold_ms: Option<u64>,
+
+    /// Report anomalies to `exec:COMMAND` or an `http://` webhook (can be specified multiple
+    /// times)
+    #[arg(long)]
+    anomaly_hook: Vec<clock::AnomalyHook>,
+
+    /// Deliver alerts (lost_sync, large_step, all_servers_failed, anomaly) to
+    /// `[KIND,...=]exec:COMMAND` or an `http://` webhook (can be specified multiple times)
+    #[arg(long)]
+    alert_hook: Vec<clock::AlertHook>,
+
+    /// Run as a Windows service under the Service Control Manager
+    #[cfg(all(windows, feature = "windows-service"))]
+    #[arg(long)]
+    service: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Subcommands; without one the time is displayed continuously
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Sync once and print the clock's health
+    Status {
+        /// Exit with 0 when healthy, 1 when stale, or 2 when unsynchronized, for use as a
+        /// liveness or readiness probe
+        #[arg(long)]
+        exit_code: bool,
+    },
+    /// Wait until the clock has NTP time within the given accuracy, then print it; exits
+    /// with 1 on timeout. Run early in boot so that dependent services start with correct
+    /// time
+    Wait {
+        /// Largest acceptable uncertainty, in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        accuracy_ms: u64,
+        /// Seconds to wait before giving up
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+    },
+    /// Check the status file of a clock running with --status-file without syncing; exits
+    /// with 0 when it is healthy, or 1 when it is not or the file is outdated or missing,
+    /// for a container HEALTHCHECK
+    Healthcheck {
+        /// Status file written by the running clock
+        #[arg(long, default_value = clock::statusfile::DEFAULT_STATUS_FILE)]
+        status_file: std::path::PathBuf,
+        /// Seconds after which the file is outdated; by default three poll intervals
+        #[arg(long)]
+        max_age: Option<u64>,
+    },
+    /// Replay a capture written with --record through the clock configured by the other
+    /// options, and print the syncs it makes
+    Replay {
+        /// Capture file
+        path: std::path::PathBuf,
+    },
+    /// Check the hash chain of an audit log written with --audit-hash-chain
+    VerifyAudit {
+        /// Audit log file
+        path: std::path::PathBuf,
+    },
+    /// Serve the time over HTTP (GET /time, /status, /livez, /readyz, /metrics) instead of
+    /// printing it
+    #[cfg(feature = "api")]
+    ServeApi {
+        /// Address to listen on
+        #[arg(long, default_value = clock::api::DEFAULT_LISTEN_ADDR)]
+        listen: std::net::SocketAddr,
+        /// Answer /status with 503 until the first sync and 500 once stale, like /readyz
+        #[arg(long)]
+        gate_status: bool,
+        /// Most WebSocket clients on /ws at once; further upgrades get 503
+        #[arg(long, default_value_t = clock::api::DEFAULT_MAX_SUBSCRIBERS)]
+        max_ws_clients: usize,
+    },
+}
+
+/// Checks a `--server` value, keeping it as given
+fn parse_server(s: &str) -> Result<String, String> {
+    s.parse::<clock::ServerSpec>()?;
+    Ok(s.to_string())
+}
+
+/// Parses a `--log-throttle` value
+fn parse_log_throttle(s: &str) -> Result<(clock::LogCategory, u64), String> {
+    let (category, secs) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected CATEGORY=SECS, got '{}'", s))?;
+    let secs = secs
+        .parse()
+        .map_err(|_| format!("invalid number of seconds '{}'", secs))?;
+    Ok((category.parse()?, secs))
+}
+
+/// Renders a time in the format selected with `--format`
+fn render(time: DateTime<FixedOffset>, format: &str) -> String {
+    match format {
+        "rfc3339" => time.to_rfc3339(),
+        "rfc2822" => time.to_rfc2822(),
+        _ => time.format(format).to_string(),
+    }
+}
+
+/// Applies the clock options given on the command line on top of `config`
+fn apply_cli_overrides(mut config: ClockConfig, args: &Args, matches: &ArgMatches) -> ClockConfig {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if given("fallback") {
+        config = config.with_fallback_policy(args.fallback.clone());
+    }
+    if given("persisted_floor") {
+        config = config.with_persisted_floor(args.persisted_floor);
+    }
+    if given("interval") {
+        config = config.with_sync_interval(std::time::Duration::from_secs(args.interval));
+    }
+    if let Some(stale_after) = args.stale_after {
+        config = config.with_staleness_threshold(Some(std::time::Duration::from_secs(stale_after)));
+    }
+    if let Some(samples) = args.samples_per_poll {
+        config = config.with_samples_per_poll(samples);
+    }
+    if !args.prefer.is_empty() {
+        config = config.with_preferred_servers(args.prefer.clone());
+    }
+    if !args.noselect.is_empty() {
+        config = config.with_noselect_servers(args.noselect.clone());
+    }
+    if args.combine_sources {
+        config = config.with_combine_sources(true);
+    }
+    if args.race_initial_sync {
+        config = config.with_race_initial_sync(true);
+    }
+    if args.best_practices {
+        config = config.with_best_practices(true);
+    }
+    if let Some(max_delay) = args.max_delay_ms {
+        config = config.with_max_delay(Some(std::time::Duration::from_millis(max_delay)));
+    }
+    if let Some(ratio) = args.max_delay_ratio {
+        config = config.with_max_delay_ratio(Some(ratio));
+    }
+    if let Some(budget) = args.max_queries_per_minute {
+        config = config.with_max_queries_per_minute(Some(budget));
+    }
+    if let Some(ports) = &args.source_ports {
+        config = config.with_source_ports(ports.clone());
+    }
+    if let Some(dscp) = args.dscp {
+        config = config.with_dscp(Some(dscp));
+    }
+    if let Some(ttl) = args.ttl {
+        config = config.with_ttl(Some(ttl));
+    }
+    if let Some(dns) = &args.dns {
+        config = config.with_dns(dns.clone());
+    }
+    if let Some(allow) = &args.allow_addresses {
+        config.address_policy.allow = allow.clone();
+    }
+    if let Some(deny) = &args.deny_addresses {
+        config.address_policy.deny = deny.clone();
+    }
+    if let Some(filter) = args.smoothing {
+        config = config.with_smoothing(Some(filter));
+    }
+    if let Some(ppm) = args.max_slew_ppm {
+        config = config.with_max_slew_ppm(ppm);
+    }
+    if let Some(max) = args.offline_retry_max {
+        config = config.with_offline_retry_max(Some(std::time::Duration::from_secs(max)));
+    }
+    if let Some(after) = args.orphan_after {
+        config = config.with_orphan_after(Some(std::time::Duration::from_secs(after)));
+    }
+    if let Some(stratum) = args.orphan_stratum {
+        config = config.with_orphan_stratum(stratum);
+    }
+    if !args.peer.is_empty() {
+        config = config.with_peers(args.peer.clone());
+    }
+    if let Some(addr) = args.peer_listen {
+        config = config.with_peer_listen(Some(addr));
+    }
+    if let Some(rate_limit) = args.rate_limit {
+        config = config.with_rate_limit(Some(rate_limit));
+    }
+    if args.no_rate_limit {
+        config = config.with_rate_limit(None);
+    }
+    if let Some(size) = args.mru_size {
+        config = config.with_mru_size(size as usize);
+    }
+    if args.mdns_discovery {
+        config = config.with_mdns_discovery(true);
+    }
+    if !args.manycast.is_empty() {
+        config = config.with_manycast(args.manycast.clone());
+    }
+    if args.resync_on_network_change {
+        config = config.with_resync_on_network_change(true);
+    }
+    if let Some(instance) = &args.mdns_advertise {
+        config = config.with_mdns_advertise(Some(instance.clone()));
+    }
+    if let Some(threshold) = args.anomaly_threshold_ms {
+        config = config.with_anomaly_threshold(std::time::Duration::from_millis(threshold));
+    }
+    if !args.anomaly_hook.is_empty() {
+        config = config.with_anomaly_hooks(args.anomaly_hook.clone());
+    }
+    if !args.alert_hook.is_empty() {
+        config = config.with_alert_hooks(args.alert_hook.clone());
+    }
+    if let Some(min_time) = args.min_time {
+        config = config.with_min_time(Some(min_time));
+    }
+    if let Some(max_time) = args.max_time {
+        config = config.with_max_time(Some(max_time));
+    }
+    if args.no_max_time {
+        config = config.with_max_time(None);
+    }
+    if let Some(policy) = args.rtc_policy {
+        config = config.with_rtc_policy(Some(policy));
+    }
+    if let Some(device) = &args.rtc_device {
+        config = config.with_rtc_device(device.clone());
+    }
+    if let Some(tolerance) = args.rtc_tolerance {
+        config = config.with_rtc_tolerance(std::time::Duration::from_secs(tolerance));
+    }
+    if let Some(interval) = args.rtc_write {
+        config = config.with_rtc_write_interval(Some(std::time::Duration::from_secs(interval)));
+    }
+    if let Some(path) = &args.audit_log {
+        config = config.with_audit_log(Some(path.clone()));
+    }
+    if args.audit_hash_chain {
+        config = config.with_audit_hash_chain(true);
+    }
+    if !args.server.is_empty() {
+        config = config.with_servers(args.server.clone());
+    }
+    if let Some(recorder) = RECORDER.get() {
+        let inner = config.transport.clone().unwrap_or_else(|| {
+            let (ports, dscp, ttl) = (config.source_ports.clone(), config.dscp, config.ttl);
+            TransportFactory::new("udp", move || {
+                UdpTransport::default()
+                    .with_source_ports(ports.clone())
+                    .with_dscp(dscp)
+                    .with_ttl(ttl)
+            })
+        });
+        config = config.with_transport(Some(recorder.factory(inner)));
+    }
+    if let Some(keys) = KEYS.get() {
+        config = config.with_keys(Some(keys.clone()));
+    }
+    config
+}
+
+/// Builds the clock configuration from the `--config` file, if any, overridden by the options
+/// given on the command line
+fn load_config(
+    args: &Args,
+    matches: &ArgMatches,
+) -> Result<ClockConfig, Box<dyn std::error::Error>> {
+    let config = match &args.config {
+        Some(path) => ClockConfig::from_file(path)
+            .map_err(|e| format!("failed to load {}: {}", path.display(), e))?,
+        None => ClockConfig::new(),
+    };
+    Ok(apply_cli_overrides(config, args, matches))
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches)?;
 
     // Initialize logger
     let log_level = if args.verbose { "debug" } else { "info" };
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level)).init();
+    clock::logging::set_quiet(args.quiet);
+    for &(category, secs) in &args.log_throttle {
+        let window = (secs > 0).then(|| std::time::Duration::from_secs(secs));
+        clock::logging::set_throttle(category, window);
+    }
 
     info!("Starting NTP-synchronized clock");
     info!(
@@ -51,51 +621,329 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         args.interval, args.display_interval, args.timezone_offset
     );
 
-    let ntp_servers = if args.server.is_empty() {
-        None
-    } else {
-        Some(args.server.clone())
-    };
+    if let Some(Command::Healthcheck {
+        status_file,
+        max_age,
+    }) = &args.command
+    {
+        let max_age = max_age.map(std::time::Duration::from_secs);
+        match clock::statusfile::check(status_file, max_age) {
+            Ok(()) => {
+                println!("healthy");
+                return Ok(());
+            }
+            Err(reason) => {
+                println!("unhealthy: {}", reason);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(Command::VerifyAudit { path }) = &args.command {
+        let records = clock::audit::verify(path)?;
+        println!(
+            "{}: hash chain intact ({} records)",
+            path.display(),
+            records
+        );
+        return Ok(());
+    }
+
+    if let Some(path) = &args.record {
+        info!("Recording exchanges to {}", path.display());
+        let _ = RECORDER.set(clock::capture::Recorder::create(path)?);
+    }
+
+    if let Some(path) = &args.keys {
+        let keys = KeyStore::from_ntp_keys_file(path)
+            .map_err(|e| format!("failed to load {}: {}", path.display(), e))?;
+        info!("Loaded {} keys from {}", keys.len(), path.display());
+        let _ = KEYS.set(SharedKeyStore::new(keys));
+    }
+
+    let config = load_config(&args, &matches)?;
+    if let Some(Command::Replay { path }) = &args.command {
+        // A replay must not add records to the real audit log
+        let clock = clock::capture::Replay::open(path)?.run(config.with_audit_log(None))?;
+        for sync in clock.sync_history() {
+            println!(
+                "{} | {} ({}) | offset: {:+.6}s | delay: {:.6}s | {}",
+                sync.time,
+                sync.server,
+                sync.addr,
+                sync.offset,
+                sync.delay,
+                sync.adjustment
+                    .map_or_else(|| "no adjustment".to_string(), |kind| kind.to_string())
+            );
+        }
+        println!("final time: {}", clock.now_timestamp());
+        return Ok(());
+    }
+    if let Some(path) = &config.audit_log {
+        info!("Auditing clock adjustments to {}", path.display());
+        // Refuse to start unaudited rather than only log the failure
+        AuditLog::open(path)?;
+    }
+    let serving = (config.peer_listen, config.mdns_advertise.clone());
+    let clock = Clock::with_config(config);
+    if args.boottime {
+        clock.set_elapsed_source(Arc::new(BootTimeSource::new()?));
+    }
+    #[cfg(feature = "quanta")]
+    if args.tsc {
+        clock.set_elapsed_source(Arc::new(clock::TscSource::new()));
+    }
+    if let Some(dir) = &args.statsdir {
+        info!("Writing statistics files to {}", dir.display());
+        clock.set_stats_logger(Some(StatsLogger::new(dir)?.with_format(args.stats_format)));
+    }
+
+    if let Some(path) = &args.status_file {
+        info!("Writing status to {}", path.display());
+        clock.set_status_file(Some(path.clone()));
+    }
+
+    if let Some(Command::Status { exit_code }) = args.command {
+        let health = clock.health();
+        let own = clock.server_state();
+        println!(
+            "{} | source: {} | stratum: {} | refid: {} | root delay: {:.6}s | \
+             root dispersion: {:.6}s | time: {}",
+            health,
+            clock.time_source(),
+            own.stratum,
+            clock::sntp::display_reference_id(own.stratum, own.reference_id),
+            own.root_delay,
+            own.root_dispersion,
+            clock.format_rfc3339()
+        );
+        if let Some(reference) = clock.reference() {
+            println!(
+                "  synced to {} ({}): stratum {}, refid {}, precision {:.3e}s, \
+                 root dispersion {:.6}s",
+                reference.server,
+                reference.addr,
+                reference.stratum,
+                reference.refid(),
+                reference.precision_secs(),
+                reference.root_dispersion
+            );
+        }
+        for source in clock.source_weights() {
+            println!(
+                "  {} (stratum {}): weight {:.3}, offset {:+.6}s, root distance {:.6}s, \
+                 jitter {:.6}s",
+                source.server,
+                source.stratum,
+                source.weight,
+                source.offset,
+                source.root_distance,
+                source.jitter
+            );
+        }
+        if exit_code {
+            std::process::exit(health.exit_code());
+        }
+        return Ok(());
+    }
 
-    let clock = Arc::new(Mutex::new(Clock::new(ntp_servers)));
     let shutdown = Arc::new(AtomicBool::new(false));
 
-    // Set up Ctrl+C handler
+    if let Some(Command::Wait {
+        accuracy_ms,
+        timeout,
+    }) = args.command
+    {
+        clock.start(clock.sync_interval().as_secs(), Arc::clone(&shutdown));
+        let result = clock.block_until_plausible_time(
+            std::time::Duration::from_millis(accuracy_ms),
+            std::time::Duration::from_secs(timeout),
+        );
+        shutdown.store(true, Ordering::Relaxed);
+        match result {
+            Ok(time) => println!(
+                "{} (uncertainty {:?})",
+                time,
+                clock.uncertainty().unwrap_or_default()
+            ),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    #[cfg(all(windows, feature = "windows-service"))]
+    if args.service {
+        let service_shutdown = Arc::clone(&shutdown);
+        clock::winservice::run(SERVICE_NAME, service_shutdown, move || {
+            if let Err(e) = run(&clock, &args, &matches, serving, shutdown) {
+                error!("Service failed: {}", e);
+            }
+        })?;
+        return Ok(());
+    }
+
+    // Stop on Ctrl+C (SIGINT) or SIGTERM
     let shutdown_clone = Arc::clone(&shutdown);
     ctrlc::set_handler(move || {
         info!("Received shutdown signal");
         shutdown_clone.store(true, Ordering::Relaxed);
     })?;
 
-    Clock::start(Arc::clone(&clock), args.interval, Arc::clone(&shutdown));
+    run(&clock, &args, &matches, serving, shutdown)
+}
+
+/// Runs the clock and prints the time until `shutdown` is set
+fn run(
+    clock: &Clock,
+    args: &Args,
+    matches: &ArgMatches,
+    (peer_listen, mdns_advertise): (Option<std::net::SocketAddr>, Option<String>),
+    shutdown: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(unix)]
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(
+            libc::SIGHUP,
+            request_reload as *const () as libc::sighandler_t,
+        );
+    }
+
+    clock.start(clock.sync_interval().as_secs(), Arc::clone(&shutdown));
+    let _watcher = match (&args.config, args.watch_config) {
+        (Some(path), true) => {
+            info!("Watching {} for changes", path.display());
+            let (args, matches) = (args.clone(), matches.clone());
+            Some(clock.watch_config_with(
+                path,
+                clock::watcher::DEFAULT_POLL_INTERVAL,
+                move |config| apply_cli_overrides(config, &args, &matches),
+            ))
+        }
+        _ => None,
+    };
+    #[cfg(all(unix, feature = "systemd"))]
+    let notifier = clock::systemd::spawn_notifier(clock.handle(), Arc::clone(&shutdown));
+    #[cfg(feature = "api")]
+    let api_server = match args.command {
+        Some(Command::ServeApi {
+            listen,
+            gate_status,
+            max_ws_clients,
+        }) => {
+            let listener = std::net::TcpListener::bind(listen)?;
+            let config = clock::api::ApiConfig::new()
+                .with_gated_status(gate_status)
+                .with_max_subscribers(max_ws_clients);
+            Some(clock::api::spawn_server_with_config(
+                listener,
+                clock.handle(),
+                config,
+                Arc::clone(&shutdown),
+            )?)
+        }
+        _ => None,
+    };
+    #[cfg(not(feature = "api"))]
+    let api_server: Option<std::thread::JoinHandle<()>> = None;
+    let keys = KEYS.get().cloned().unwrap_or_default();
+    let peer_responder = match peer_listen {
+        Some(addr) => Some(clock::peer::spawn_responder_with_keys(
+            std::net::UdpSocket::bind(addr)?,
+            clock.handle(),
+            keys.clone(),
+            Arc::clone(&shutdown),
+        )?),
+        None => None,
+    };
+    let advertiser = match (mdns_advertise, peer_listen) {
+        (Some(instance), Some(addr)) => Some(clock::mdns::spawn_advertiser(
+            &instance,
+            addr,
+            Arc::clone(&shutdown),
+        )?),
+        (Some(_), None) => return Err("mdns_advertise requires peer_listen".into()),
+        _ => None,
+    };
 
-    let timezone_offset = Duration::hours(args.timezone_offset as i64);
+    let timezone_offset =
+        FixedOffset::east_opt(args.timezone_offset * 3600).ok_or("timezone offset out of range")?;
+    if !matches!(args.format.as_str(), "rfc3339" | "rfc2822") {
+        // Reject bad specifiers up front; formatting them later would panic
+        clock.format(&args.format)?;
+    }
 
     while !shutdown.load(Ordering::Relaxed) {
         std::thread::sleep(std::time::Duration::from_secs(args.display_interval));
-        let clock_guard = clock.lock().unwrap();
-        let current_time = clock_guard.get_current_time();
-        let adjusted_time = current_time + timezone_offset;
+        #[cfg(unix)]
+        if RELOAD_REQUESTED.swap(false, Ordering::Relaxed) {
+            match (&args.config, load_config(args, matches)) {
+                (None, _) if args.keys.is_none() => {
+                    info!("Received SIGHUP, but no --config or --keys file to reload")
+                }
+                (None, _) => {}
+                (Some(path), Ok(config)) => {
+                    info!("Reloading configuration from {}", path.display());
+                    clock.reconfigure(&config);
+                }
+                (Some(_), Err(e)) => error!("Keeping current configuration: {}", e),
+            }
+            if let Some(path) = &args.keys {
+                match KeyStore::from_ntp_keys_file(path) {
+                    Ok(reloaded) => {
+                        info!("Reloaded {} keys from {}", reloaded.len(), path.display());
+                        // The client and the responder share the store
+                        *keys.write() = reloaded;
+                    }
+                    Err(e) => error!("Keeping current keys: {}: {}", path.display(), e),
+                }
+            }
+        }
+        if api_server.is_some() {
+            continue;
+        }
+        let current_time = match clock.try_current_time() {
+            Ok(time) => time,
+            Err(e) => {
+                println!("Time (UTC{:+}): {}", args.timezone_offset, e);
+                continue;
+            }
+        };
+        let adjusted_time = render(current_time.with_timezone(&timezone_offset), &args.format);
 
         if args.show_stats {
-            let stats = clock_guard.get_stats();
+            let stats = clock.get_stats();
             println!(
-                "Time (UTC{:+}): {} | Syncs: {}/{} ({:.1}% success)",
+                "Time (UTC{:+}): {} [{}] | Syncs: {}/{} ({:.1}% success)",
                 args.timezone_offset,
-                adjusted_time.format("%Y-%m-%d %H:%M:%S"),
+                adjusted_time,
+                clock.time_source(),
                 stats.successful_syncs,
                 stats.total_attempts,
                 stats.success_rate()
             );
         } else {
-            println!(
-                "Time (UTC{:+}): {}",
-                args.timezone_offset,
-                adjusted_time.format("%Y-%m-%d %H:%M:%S")
-            );
+            println!("Time (UTC{:+}): {}", args.timezone_offset, adjusted_time);
         }
     }
 
     info!("Shutting down gracefully");
+    clock.stop();
+    clock.flush_state();
+    #[cfg(all(unix, feature = "systemd"))]
+    let _ = notifier.join();
+    if let Some(api_server) = api_server {
+        let _ = api_server.join();
+    }
+    if let Some(peer_responder) = peer_responder {
+        let _ = peer_responder.join();
+    }
+    if let Some(advertiser) = advertiser {
+        let _ = advertiser.join();
+    }
     Ok(())
 }
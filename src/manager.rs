@@ -0,0 +1,285 @@
+//! # Multiple Clocks
+//!
+//! Before rolling out a new server set or configuration, such as NTS servers in place of a
+//! plain pool, it helps to run both side by side and watch where they disagree. A
+//! [`ClockManager`] holds named, independent [`Clock`]s, starts and stops them together,
+//! and measures how far each one's time is from the first clock added with
+//! [`divergence`](ClockManager::divergence).
+//!
+//! ```no_run
+//! use clock::manager::ClockManager;
+//! use clock::ClockConfig;
+//! use std::sync::atomic::AtomicBool;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let mut manager = ClockManager::new()
+//!     .with_divergence_threshold(Some(Duration::from_millis(50)));
+//! let pool = vec!["pool.ntp.org:123".to_string()];
+//! manager.add("pool", ClockConfig::new().with_servers(pool));
+//! let candidate = vec!["time.cloudflare.com:123".to_string()];
+//! manager.add("candidate", ClockConfig::new().with_servers(candidate));
+//! manager.start(64, Arc::new(AtomicBool::new(false)));
+//! if let Some(divergence) = manager.divergence() {
+//!     println!("spread: {:?}", divergence.spread());
+//! }
+//! ```
+//!
+//! Clocks whose configuration leaves the resolver or transport at its default share the
+//! manager's, set with [`with_dns`](ClockManager::with_dns) and
+//! [`with_transport`](ClockManager::with_transport), so that every clock resolves pool
+//! names through the same DNS strategy and reaches its servers through the same egress.
+//! Plain UDP queries are not pooled: each one goes out from its own socket on a random
+//! port, so that replies cannot be spoofed without seeing the request.
+
+use crate::logging::clock_log;
+use crate::transport::TransportFactory;
+use crate::{Clock, ClockConfig, DnsStrategy, Health, TimeSource};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// One clock's time compared with the reference clock's
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockReading {
+    pub name: String,
+    /// Seconds this clock is ahead of the reference clock; 0 for the reference itself
+    pub offset: f64,
+    pub health: Health,
+    pub source: TimeSource,
+    pub synchronized: bool,
+    /// Bound on the error of this clock's time, `None` before its first sync
+    pub uncertainty: Option<Duration>,
+}
+
+/// The clocks of a [`ClockManager`] compared at one moment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// Name of the clock the offsets are relative to, the first one added
+    pub reference: String,
+    /// A reading per clock, in the order they were added
+    pub readings: Vec<ClockReading>,
+}
+
+impl Divergence {
+    /// Seconds between the furthest apart synchronized clocks, `None` with fewer than two
+    pub fn spread(&self) -> Option<f64> {
+        let offsets = self
+            .readings
+            .iter()
+            .filter(|reading| reading.synchronized)
+            .map(|reading| reading.offset);
+        let (count, min, max) = offsets.fold(
+            (0, f64::INFINITY, f64::NEG_INFINITY),
+            |(count, min, max), offset| (count + 1, min.min(offset), max.max(offset)),
+        );
+        (count >= 2).then_some(max - min)
+    }
+
+    /// The reading of the clock named `name`
+    pub fn reading(&self, name: &str) -> Option<&ClockReading> {
+        self.readings.iter().find(|reading| reading.name == name)
+    }
+}
+
+/// Runs several independently configured clocks side by side, see the
+/// [module documentation](self)
+#[derive(Default)]
+pub struct ClockManager {
+    clocks: Vec<(String, Clock)>,
+    dns: Option<DnsStrategy>,
+    transport: Option<TransportFactory>,
+    divergence_threshold: Option<Duration>,
+}
+
+impl ClockManager {
+    /// Creates a manager without clocks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolves server names with `dns` for the clocks added from now on whose
+    /// configuration uses the system resolver
+    pub fn with_dns(mut self, dns: DnsStrategy) -> Self {
+        self.dns = Some(dns);
+        self
+    }
+
+    /// Carries the queries of the clocks added from now on that have no transport of their
+    /// own over `transport`
+    pub fn with_transport(mut self, transport: TransportFactory) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Logs a warning from [`divergence`](Self::divergence) when the synchronized clocks
+    /// are further apart than `threshold`; `None` (the default) never warns
+    pub fn with_divergence_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.divergence_threshold = threshold;
+        self
+    }
+
+    /// Creates a clock named `name` from `config`, which syncs before this returns like
+    /// [`Clock::with_config`], replacing and returning any clock of the same name
+    pub fn add(&mut self, name: impl Into<String>, mut config: ClockConfig) -> Option<Clock> {
+        let name = name.into();
+        if let Some(dns) = &self.dns {
+            if config.dns == DnsStrategy::System {
+                config.dns = dns.clone();
+            }
+        }
+        if config.transport.is_none() {
+            config.transport = self.transport.clone();
+        }
+        let clock = Clock::with_config(config);
+        match self
+            .clocks
+            .iter_mut()
+            .find(|(existing, _)| *existing == name)
+        {
+            Some((_, existing)) => Some(std::mem::replace(existing, clock)),
+            None => {
+                self.clocks.push((name, clock));
+                None
+            }
+        }
+    }
+
+    /// Removes and returns the clock named `name`
+    pub fn remove(&mut self, name: &str) -> Option<Clock> {
+        let index = self
+            .clocks
+            .iter()
+            .position(|(existing, _)| existing == name)?;
+        Some(self.clocks.remove(index).1)
+    }
+
+    /// The clock named `name`
+    pub fn get(&self, name: &str) -> Option<&Clock> {
+        self.clocks
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, clock)| clock)
+    }
+
+    /// The names of the clocks, in the order they were added
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.clocks.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Number of clocks
+    pub fn len(&self) -> usize {
+        self.clocks.len()
+    }
+
+    /// Whether the manager has no clocks
+    pub fn is_empty(&self) -> bool {
+        self.clocks.is_empty()
+    }
+
+    /// Starts the background sync of every clock, see [`Clock::start`]
+    pub fn start(&self, interval_secs: u64, shutdown: Arc<AtomicBool>) {
+        for (_, clock) in &self.clocks {
+            clock.start(interval_secs, Arc::clone(&shutdown));
+        }
+    }
+
+    /// Stops the background sync of every clock and waits for them to exit
+    pub fn stop(&self) {
+        for (_, clock) in &self.clocks {
+            clock.stop();
+        }
+    }
+
+    /// Reads every clock and compares it with the first one added, `None` without clocks.
+    ///
+    /// The clocks are read one after another; each offset is corrected for the time that
+    /// passed since the reference was read.
+    pub fn divergence(&self) -> Option<Divergence> {
+        let (reference, _) = self.clocks.first()?;
+        let mut base = None;
+        let readings: Vec<ClockReading> = self
+            .clocks
+            .iter()
+            .map(|(name, clock)| {
+                let now = clock.now_timestamp();
+                let read_at = Instant::now();
+                let (base_time, base_at) = *base.get_or_insert((now, read_at));
+                ClockReading {
+                    name: name.clone(),
+                    offset: now.seconds_since(base_time)
+                        - read_at.duration_since(base_at).as_secs_f64(),
+                    health: clock.health(),
+                    source: clock.time_source(),
+                    synchronized: clock.is_synchronized(),
+                    uncertainty: clock.uncertainty(),
+                }
+            })
+            .collect();
+        let divergence = Divergence {
+            reference: reference.clone(),
+            readings,
+        };
+        if let (Some(threshold), Some(spread)) = (self.divergence_threshold, divergence.spread()) {
+            if spread > threshold.as_secs_f64() {
+                clock_log!(
+                    Warn,
+                    Anomaly,
+                    "Clocks {} diverge by {:.6}s, more than {:.6}s",
+                    divergence
+                        .readings
+                        .iter()
+                        .map(|reading| format!("{} ({:+.6}s)", reading.name, reading.offset))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    spread,
+                    threshold.as_secs_f64()
+                );
+            }
+        }
+        Some(divergence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::spawn_fake_server;
+    use crate::Timestamp;
+
+    #[test]
+    fn test_divergence_between_clocks() {
+        let now = Timestamp::now();
+        let ahead = spawn_fake_server(now + Duration::from_secs(10), 1);
+        let port = ahead.rsplit_once(':').unwrap().1;
+        let mut manager = ClockManager::new()
+            .with_dns("static:ahead.test=127.0.0.1".parse().unwrap())
+            .with_divergence_threshold(Some(Duration::from_secs(1)));
+        assert!(manager.divergence().is_none());
+
+        let config = ClockConfig::new().with_servers(vec![spawn_fake_server(now, 1)]);
+        assert!(manager.add("plain", config).is_none());
+        let config = ClockConfig::new().with_servers(vec![format!("ahead.test:{}", port)]);
+        assert!(manager.add("ahead", config).is_none());
+        let unreachable = ClockConfig::new().with_servers(vec!["missing.test:123".to_string()]);
+        manager.add("offline", unreachable);
+        assert_eq!(
+            manager.names().collect::<Vec<_>>(),
+            ["plain", "ahead", "offline"]
+        );
+
+        let divergence = manager.divergence().unwrap();
+        assert_eq!(divergence.reference, "plain");
+        assert_eq!(divergence.reading("plain").unwrap().offset, 0.0);
+        let ahead = divergence.reading("ahead").unwrap();
+        assert!(ahead.synchronized && ahead.health.is_healthy());
+        assert!((ahead.offset - 10.0).abs() < 0.5, "{}", ahead.offset);
+        assert!(!divergence.reading("offline").unwrap().synchronized);
+        let spread = divergence.spread().unwrap();
+        assert!((spread - 10.0).abs() < 0.5, "{}", spread);
+
+        assert!(manager.remove("ahead").is_some());
+        assert_eq!(manager.len(), 2);
+        assert_eq!(manager.divergence().unwrap().spread(), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! # Manycast Server Probing
+//!
+//! Appliance time servers on factory and lab networks often go unconfigured because
+//! nobody knows their addresses. [`probe`] sends one NTP client request to each candidate
+//! address, a subnet broadcast address such as `192.168.1.255:123` or individual LAN
+//! hosts, and returns the servers that answered with synchronized time. With
+//! [`ClockConfig::manycast`](crate::ClockConfig::manycast) the clock adds them to its
+//! servers at startup, on reconfiguration, and whenever no server answers.
+//!
+//! A reply counts only if it echoes the random transmit timestamp of the request, so a
+//! stray or spoofed packet cannot add a server.
+
+use crate::logging::clock_log;
+use crate::sntp::{self, PACKET_LEN};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// How long [`probe`] collects answers when the clock probes for servers
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Port of a target given without one
+pub const NTP_PORT: u16 = 123;
+
+/// How long a read waits on one socket before checking the other
+const RECV_POLL: Duration = Duration::from_millis(20);
+
+/// Parses a probe target: `ADDR:PORT`, or a bare IP address for the NTP port
+pub fn parse_target(s: &str) -> Result<SocketAddr, String> {
+    let s = s.trim();
+    s.parse::<SocketAddr>()
+        .or_else(|_| s.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, NTP_PORT)))
+        .map_err(|_| format!("invalid manycast address '{}'", s))
+}
+
+/// Sends a client request to each of `targets` and collects replies for `timeout`,
+/// returning the addresses of the servers that answered with synchronized time in the
+/// order they answered
+pub fn probe(targets: &[SocketAddr], timeout: Duration) -> io::Result<Vec<SocketAddr>> {
+    let request = sntp::client_request_with_version(4);
+
+    let mut sockets = Vec::new();
+    for v4 in [true, false] {
+        let family: Vec<&SocketAddr> = targets.iter().filter(|t| t.is_ipv4() == v4).collect();
+        if family.is_empty() {
+            continue;
+        }
+        let socket = if v4 {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+            socket.set_broadcast(true)?;
+            socket
+        } else {
+            UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?
+        };
+        socket.set_read_timeout(Some(RECV_POLL))?;
+        for target in family {
+            if let Err(e) = socket.send_to(&request, target) {
+                clock_log!(Warn, Discovery, "Failed to probe {}: {}", target, e);
+            }
+        }
+        sockets.push(socket);
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut servers = Vec::new();
+    let mut buf = [0u8; 1024];
+    while Instant::now() < deadline && !sockets.is_empty() {
+        for socket in &sockets {
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                // Windows reports an ICMP port unreachable from a probed host as a reset
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::ConnectionReset
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            if !is_answer(&request, &buf[..len]) {
+                clock_log!(Debug, Discovery, "Ignoring an unusable reply from {}", from);
+                continue;
+            }
+            if !servers.contains(&from) {
+                servers.push(from);
+            }
+        }
+    }
+    Ok(servers)
+}
+
+/// Whether `reply` answers `request` from a synchronized server
+fn is_answer(request: &[u8; PACKET_LEN], reply: &[u8]) -> bool {
+    let Some(reply) = reply.get(..PACKET_LEN) else {
+        return false;
+    };
+    let reply: &[u8; PACKET_LEN] = reply.try_into().expect("sliced to the packet length");
+    sntp::parse_reply(request, reply, Duration::ZERO).is_some_and(|m| m.is_synchronized())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Answers one request on `socket` with `stratum`, echoing its transmit timestamp
+    /// unless `echo` is false
+    fn answer_once(socket: UdpSocket, stratum: u8, echo: bool) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let mut request = [0u8; PACKET_LEN];
+            let (_, from) = socket.recv_from(&mut request).unwrap();
+            let mut reply = [0u8; PACKET_LEN];
+            reply[0] = 4 << 3 | 4;
+            reply[1] = stratum;
+            if echo {
+                reply[24..32].copy_from_slice(&request[40..48]);
+            }
+            reply[40..48].copy_from_slice(&sntp::encode_timestamp(crate::Timestamp::now()));
+            socket.send_to(&reply, from).unwrap();
+        })
+    }
+
+    #[test]
+    fn test_probe_keeps_synchronized_servers_that_echo_the_request() {
+        let sockets: Vec<UdpSocket> = (0..3)
+            .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let addrs: Vec<SocketAddr> = sockets.iter().map(|s| s.local_addr().unwrap()).collect();
+        let mut servers = sockets.into_iter();
+        let answering = [
+            answer_once(servers.next().unwrap(), 2, true),
+            answer_once(servers.next().unwrap(), sntp::MAX_STRATUM, true),
+            answer_once(servers.next().unwrap(), 2, false),
+        ];
+
+        let found = probe(&addrs, Duration::from_millis(500)).unwrap();
+        for server in answering {
+            server.join().unwrap();
+        }
+        assert_eq!(found, [addrs[0]]);
+    }
+
+    #[test]
+    fn test_parse_target_defaults_to_the_ntp_port() {
+        assert_eq!(
+            parse_target("192.168.1.255"),
+            Ok("192.168.1.255:123".parse().unwrap())
+        );
+        assert_eq!(
+            parse_target("10.0.0.5:1123"),
+            Ok("10.0.0.5:1123".parse().unwrap())
+        );
+        assert_eq!(
+            parse_target("fe80::1"),
+            Ok("[fe80::1]:123".parse().unwrap())
+        );
+        assert!(parse_target("ntp.example.com").is_err());
+    }
+}
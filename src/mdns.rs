@@ -0,0 +1,411 @@
+//! # mDNS/DNS-SD Server Discovery
+//!
+//! NTP servers on the local network can be advertised as `_ntp._udp.local` services
+//! (RFC 6762/6763), so that home labs and factory floors need no server configuration:
+//!
+//! * [`discover`] asks the link for `_ntp._udp.local` instances and resolves them to
+//!   addresses; with [`ClockConfig::mdns_discovery`](crate::ClockConfig::mdns_discovery)
+//!   the clock adds them to its servers at startup, on reconfiguration, and whenever no
+//!   server answers.
+//! * [`spawn_advertiser`] answers such queries for this host's [`peer`](crate::peer)
+//!   responder, so other instances find it.
+//!
+//! Only IPv4 is advertised. Queries are sent from an ephemeral port, which responders answer
+//! by unicast, so discovery works next to a system mDNS daemon.
+
+use crate::dnsmsg::{
+    encode_name, parse, Question, Record, RecordData, CLASS_IN, CLASS_TOP_BIT, TYPE_A, TYPE_ANY,
+    TYPE_PTR, TYPE_SRV, TYPE_TXT,
+};
+use crate::logging::clock_log;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// The mDNS multicast group and port
+pub const MDNS_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353);
+
+/// DNS-SD service type of NTP servers
+pub const NTP_SERVICE: &str = "_ntp._udp.local";
+
+/// How long [`discover`] collects answers when the clock discovers servers
+pub const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often the advertiser checks the shutdown flag
+const RECV_POLL: Duration = Duration::from_millis(50);
+
+/// TTLs RFC 6762 recommends for records naming a host and for all others
+const HOST_TTL: u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+
+/// TTL of answers to legacy unicast queries, which RFC 6762 caps at 10 seconds
+const LEGACY_UNICAST_TTL: u32 = 10;
+
+/// Asks the local network for NTP servers, collecting answers for `timeout`
+pub fn discover(timeout: Duration) -> io::Result<Vec<SocketAddr>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.send_to(&query(NTP_SERVICE), MDNS_ADDR)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut records = Vec::new();
+    let mut buf = [0u8; 9000];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        socket.set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => match parse(&buf[..len]) {
+                // RFC 6762 has messages with an error code ignored
+                Some(message) if message.is_response && message.rcode == 0 => {
+                    records.extend(message.records)
+                }
+                _ => clock_log!(
+                    Debug,
+                    Discovery,
+                    "Ignoring a malformed mDNS packet from {}",
+                    from
+                ),
+            },
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(resolve(&records))
+}
+
+/// Advertises an NTP server at `server` as `instance._ntp._udp.local` from a background
+/// thread until `shutdown` is set. An unspecified IP in `server` advertises the address
+/// this host uses on the local network.
+pub fn spawn_advertiser(
+    instance: &str,
+    server: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let ip = match server.ip() {
+        IpAddr::V4(ip) if !ip.is_unspecified() => ip,
+        IpAddr::V4(_) => match crate::peer::local_ip_towards(MDNS_ADDR.into())? {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => unreachable!("an IPv4 route has an IPv4 source"),
+        },
+        IpAddr::V6(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only IPv4 servers can be advertised over mDNS",
+            ))
+        }
+    };
+    let service = Service::new(instance, SocketAddrV4::new(ip, server.port()));
+    let socket = bind_shared(MDNS_ADDR.port())?;
+    socket.join_multicast_v4(MDNS_ADDR.ip(), &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_read_timeout(Some(RECV_POLL))?;
+    clock_log!(
+        Info,
+        Discovery,
+        "Advertising {} at {} over mDNS",
+        service.instance,
+        service.addr
+    );
+
+    Ok(std::thread::spawn(move || {
+        // Announce on startup so that caches pick the server up without asking
+        if let Err(e) = socket.send_to(&service.response(0, None, HOST_TTL), MDNS_ADDR) {
+            clock_log!(
+                Warn,
+                Discovery,
+                "Failed to announce {} over mDNS: {}",
+                service.instance,
+                e
+            );
+        }
+        let mut buf = [0u8; 9000];
+        while !shutdown.load(Ordering::Relaxed) {
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => {
+                    clock_log!(Warn, Discovery, "Failed to receive an mDNS query: {}", e);
+                    std::thread::sleep(RECV_POLL);
+                    continue;
+                }
+            };
+            let Some(message) = parse(&buf[..len]).filter(|m| m.rcode == 0) else {
+                continue;
+            };
+            let Some(question) = message.questions.iter().find(|q| service.answers(q)) else {
+                continue;
+            };
+            let result = if from.port() != MDNS_ADDR.port() {
+                // A legacy resolver: answer it directly, echoing its ID and question
+                let reply = service.response(message.id, Some(question), LEGACY_UNICAST_TTL);
+                socket.send_to(&reply, from)
+            } else if question.unicast {
+                socket.send_to(&service.response(0, None, HOST_TTL), from)
+            } else {
+                socket.send_to(&service.response(0, None, HOST_TTL), MDNS_ADDR)
+            };
+            if let Err(e) = result {
+                clock_log!(
+                    Warn,
+                    Discovery,
+                    "Failed to answer an mDNS query from {}: {}",
+                    from,
+                    e
+                );
+            }
+        }
+        // Goodbye: a TTL of zero removes the records from caches
+        let _ = socket.send_to(&service.response(0, None, 0), MDNS_ADDR);
+    }))
+}
+
+/// Binds a UDP socket to `port` on all IPv4 interfaces, allowing a system mDNS daemon to
+/// share it
+#[cfg(unix)]
+fn bind_shared(port: u16) -> io::Result<UdpSocket> {
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: plain socket creation; the descriptor is owned by `socket` from here on
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` is a freshly created socket that nothing else owns
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    let one: libc::c_int = 1;
+    let options = [
+        libc::SO_REUSEADDR,
+        // BSD-derived stacks only share a multicast port between sockets that all set this
+        #[cfg(any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd"
+        ))]
+        libc::SO_REUSEPORT,
+    ];
+    for option in options {
+        // SAFETY: `one` outlives the call and its size is passed along
+        let result = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                &one as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    // SAFETY: an all-zero sockaddr_in is the unspecified address; family and port are set
+    let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    addr.sin_port = port.to_be();
+    // SAFETY: `addr` outlives the call and its size is passed along
+    let result = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+#[cfg(not(unix))]
+fn bind_shared(port: u16) -> io::Result<UdpSocket> {
+    UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))
+}
+
+/// The records advertising one NTP server
+struct Service {
+    /// `instance._ntp._udp.local`
+    instance: String,
+    /// `instance.local`
+    host: String,
+    addr: SocketAddrV4,
+}
+
+impl Service {
+    fn new(instance: &str, addr: SocketAddrV4) -> Self {
+        Service {
+            instance: format!("{}.{}", instance, NTP_SERVICE),
+            host: format!("{}.local", instance),
+            addr,
+        }
+    }
+
+    /// Whether `question` asks for one of this service's records
+    fn answers(&self, question: &Question) -> bool {
+        let name = question.name.as_str();
+        match question.qtype {
+            TYPE_PTR => name.eq_ignore_ascii_case(NTP_SERVICE),
+            TYPE_SRV | TYPE_TXT => name.eq_ignore_ascii_case(&self.instance),
+            TYPE_A => name.eq_ignore_ascii_case(&self.host),
+            TYPE_ANY => [NTP_SERVICE, &self.instance, &self.host]
+                .iter()
+                .any(|n| name.eq_ignore_ascii_case(n)),
+            _ => false,
+        }
+    }
+
+    /// A response carrying all of the service's records, with `ttl` capping their TTLs,
+    /// echoing `question` if given
+    fn response(&self, id: u16, question: Option<&Question>, ttl: u32) -> Vec<u8> {
+        let mut msg = Vec::with_capacity(256);
+        msg.extend_from_slice(&id.to_be_bytes());
+        msg.extend_from_slice(&0x8400u16.to_be_bytes()); // response, authoritative
+        msg.extend_from_slice(&u16::from(question.is_some()).to_be_bytes());
+        msg.extend_from_slice(&4u16.to_be_bytes());
+        msg.extend_from_slice(&[0, 0, 0, 0]);
+        if let Some(question) = question {
+            encode_name(&mut msg, &question.name);
+            msg.extend_from_slice(&question.qtype.to_be_bytes());
+            msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+        }
+        // Records unique to this host get the cache-flush bit, except in legacy answers
+        let unique = if question.is_some() {
+            CLASS_IN
+        } else {
+            CLASS_IN | CLASS_TOP_BIT
+        };
+
+        let mut ptr = Vec::new();
+        encode_name(&mut ptr, &self.instance);
+        let mut srv = vec![0, 0, 0, 0]; // priority and weight
+        srv.extend_from_slice(&self.addr.port().to_be_bytes());
+        encode_name(&mut srv, &self.host);
+        let records = [
+            (NTP_SERVICE, TYPE_PTR, CLASS_IN, SERVICE_TTL, ptr),
+            (&self.instance, TYPE_SRV, unique, HOST_TTL, srv),
+            (&self.instance, TYPE_TXT, unique, SERVICE_TTL, vec![0]),
+            (
+                &self.host,
+                TYPE_A,
+                unique,
+                HOST_TTL,
+                self.addr.ip().octets().to_vec(),
+            ),
+        ];
+        for (name, rtype, class, record_ttl, data) in records {
+            encode_name(&mut msg, name);
+            msg.extend_from_slice(&rtype.to_be_bytes());
+            msg.extend_from_slice(&class.to_be_bytes());
+            msg.extend_from_slice(&record_ttl.min(ttl).to_be_bytes());
+            msg.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            msg.extend_from_slice(&data);
+        }
+        msg
+    }
+}
+
+/// A query for the PTR records of `service`, asking for unicast answers
+fn query(service: &str) -> Vec<u8> {
+    let mut msg = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    encode_name(&mut msg, service);
+    msg.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    msg.extend_from_slice(&(CLASS_IN | CLASS_TOP_BIT).to_be_bytes());
+    msg
+}
+
+/// Follows the PTR records of [`NTP_SERVICE`] through SRV and address records to server
+/// addresses. Hosts without an address record are looked up with the system resolver,
+/// which may itself speak mDNS.
+fn resolve(records: &[Record]) -> Vec<SocketAddr> {
+    let mut services = HashMap::new();
+    let mut hosts: HashMap<String, Vec<IpAddr>> = HashMap::new();
+    for record in records {
+        let name = record.name.to_ascii_lowercase();
+        match &record.data {
+            RecordData::Srv { port, target } => {
+                services.insert(name, (*port, target.to_ascii_lowercase()));
+            }
+            RecordData::A(ip) => hosts.entry(name).or_default().push((*ip).into()),
+            RecordData::Aaaa(ip) => hosts.entry(name).or_default().push((*ip).into()),
+            _ => {}
+        }
+    }
+
+    let mut addrs = Vec::new();
+    for record in records {
+        let RecordData::Ptr(instance) = &record.data else {
+            continue;
+        };
+        if !record.name.eq_ignore_ascii_case(NTP_SERVICE) {
+            continue;
+        }
+        let Some((port, host)) = services.get(&instance.to_ascii_lowercase()) else {
+            clock_log!(Debug, Discovery, "No SRV record for {}", instance);
+            continue;
+        };
+        let found: Vec<SocketAddr> = match hosts.get(host) {
+            Some(ips) => ips.iter().map(|ip| SocketAddr::new(*ip, *port)).collect(),
+            None => (host.as_str(), *port)
+                .to_socket_addrs()
+                .map(Iterator::collect)
+                .unwrap_or_default(),
+        };
+        for addr in found {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+    }
+    addrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advertised_records_resolve_to_the_server() {
+        let service = Service::new("lab-clock", "192.168.1.20:11123".parse().unwrap());
+        let message = parse(&service.response(0, None, HOST_TTL)).unwrap();
+        assert!(message.is_response);
+        assert_eq!(
+            resolve(&message.records),
+            ["192.168.1.20:11123".parse::<SocketAddr>().unwrap()]
+        );
+
+        // A legacy unicast answer echoes the query
+        let query = parse(&query(NTP_SERVICE)).unwrap();
+        assert!(!query.is_response);
+        let question = &query.questions[0];
+        assert!(question.unicast && service.answers(question));
+        let reply = parse(&service.response(7, Some(question), LEGACY_UNICAST_TTL)).unwrap();
+        assert_eq!(reply.id, 7);
+        assert_eq!(reply.questions.len(), 1);
+        assert_eq!(reply.records.len(), 4);
+
+        let other = Question {
+            name: "_http._tcp.local".to_string(),
+            qtype: TYPE_PTR,
+            unicast: false,
+        };
+        assert!(!service.answers(&other));
+    }
+}
@@ -0,0 +1,361 @@
+//! # Network Change Detection
+//!
+//! A laptop that joins a new Wi-Fi network or a container whose interface comes up late
+//! should not wait out a whole poll interval with a clock that could not be synchronized.
+//! With [`ClockConfig::resync_on_network_change`](crate::ClockConfig::resync_on_network_change)
+//! set, the sync loop subscribes to the operating system's interface and address change
+//! notifications and resyncs in a burst as soon as an interface comes up or gains an
+//! address:
+//!
+//! - Linux: an `rtnetlink` socket subscribed to link, address, and route changes
+//! - macOS and FreeBSD: a routing socket (`PF_ROUTE`)
+//! - Windows: `NotifyAddrChange` from the IP Helper API
+//!
+//! Elsewhere [`NetworkWatcher::new`] fails with [`io::ErrorKind::Unsupported`] and the clock
+//! keeps to its poll interval.
+
+use std::io;
+use std::time::Duration;
+
+/// Waits for the operating system to report that the network configuration changed
+#[derive(Debug)]
+pub struct NetworkWatcher {
+    inner: imp::Watcher,
+}
+
+impl NetworkWatcher {
+    /// Subscribes to network change notifications
+    pub fn new() -> io::Result<Self> {
+        Ok(NetworkWatcher {
+            inner: imp::Watcher::new()?,
+        })
+    }
+
+    /// Waits up to `timeout` for an interface to come up or an address or route to be
+    /// added, returning whether one was. Notifications that arrived meanwhile are drained,
+    /// so that one change is reported once.
+    pub fn wait(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.inner.wait(timeout)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+mod socket {
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::time::Duration;
+
+    /// Opens a raw socket of `domain` and `protocol` for notifications
+    pub(super) fn open(domain: libc::c_int, protocol: libc::c_int) -> io::Result<OwnedFd> {
+        // SAFETY: plain socket creation; the descriptor is owned by the `OwnedFd` from here on
+        let fd = unsafe { libc::socket(domain, libc::SOCK_RAW, protocol) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` is a freshly created socket that nothing else owns
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Receives one message into `buf`, or `None` if none arrives within `timeout`
+    pub(super) fn recv(
+        fd: &OwnedFd,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<Option<usize>> {
+        // A zero timeout would block forever
+        let timeout = timeout.max(Duration::from_micros(1));
+        let timeval = libc::timeval {
+            tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        // SAFETY: `timeval` outlives the call and its size is passed along
+        let result = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeval as *const libc::timeval as *const libc::c_void,
+                std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `buf` is valid for writes of its length
+        let len = unsafe {
+            libc::recv(
+                fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if len >= 0 {
+            return Ok(Some(len as usize));
+        }
+        let error = io::Error::last_os_error();
+        match error.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted => {
+                Ok(None)
+            }
+            _ => Err(error),
+        }
+    }
+
+    /// Waits up to `timeout` for a message that `is_change` accepts, then drains the queue
+    pub(super) fn wait_for_change(
+        fd: &OwnedFd,
+        timeout: Duration,
+        is_change: impl Fn(&[u8]) -> bool,
+    ) -> io::Result<bool> {
+        let mut buf = [0u8; 8192];
+        let Some(len) = recv(fd, &mut buf, timeout)? else {
+            return Ok(false);
+        };
+        let mut changed = is_change(&buf[..len]);
+        while let Some(len) = recv(fd, &mut buf, Duration::ZERO)? {
+            changed |= is_change(&buf[..len]);
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::socket;
+    use std::io;
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use std::time::Duration;
+
+    /// Size of a netlink message header; its length and type come first
+    const NLMSG_HDRLEN: usize = 16;
+
+    #[derive(Debug)]
+    pub(super) struct Watcher {
+        fd: OwnedFd,
+    }
+
+    impl Watcher {
+        pub(super) fn new() -> io::Result<Self> {
+            let fd = socket::open(libc::AF_NETLINK, libc::NETLINK_ROUTE)?;
+            // SAFETY: an all-zero sockaddr_nl lets the kernel pick the port id
+            let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = (libc::RTMGRP_LINK
+                | libc::RTMGRP_IPV4_IFADDR
+                | libc::RTMGRP_IPV6_IFADDR
+                | libc::RTMGRP_IPV4_ROUTE
+                | libc::RTMGRP_IPV6_ROUTE) as u32;
+            // SAFETY: `addr` outlives the call and its size is passed along
+            let result = unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if result != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Watcher { fd })
+        }
+
+        pub(super) fn wait(&mut self, timeout: Duration) -> io::Result<bool> {
+            socket::wait_for_change(&self.fd, timeout, is_change)
+        }
+    }
+
+    /// Whether a datagram of netlink messages reports a link, address, or route coming up;
+    /// removals leave nothing new to sync over
+    fn is_change(mut messages: &[u8]) -> bool {
+        while messages.len() >= NLMSG_HDRLEN {
+            let len = u32::from_ne_bytes([messages[0], messages[1], messages[2], messages[3]]);
+            let kind = u16::from_ne_bytes([messages[4], messages[5]]);
+            if matches!(
+                kind,
+                libc::RTM_NEWLINK | libc::RTM_NEWADDR | libc::RTM_NEWROUTE
+            ) {
+                return true;
+            }
+            // Messages are padded to four bytes
+            let len = (len as usize + 3) & !3;
+            if len < NLMSG_HDRLEN || len > messages.len() {
+                break;
+            }
+            messages = &messages[len..];
+        }
+        false
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_is_change() {
+            let message = |kind: u16| {
+                let mut message = [0u8; NLMSG_HDRLEN];
+                message[..4].copy_from_slice(&(NLMSG_HDRLEN as u32).to_ne_bytes());
+                message[4..6].copy_from_slice(&kind.to_ne_bytes());
+                message
+            };
+            let deleted = message(libc::RTM_DELADDR);
+            let added = message(libc::RTM_NEWADDR);
+            assert!(!is_change(&deleted));
+            assert!(is_change(&[deleted, added].concat()));
+            assert!(!is_change(&added[..8]));
+        }
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+mod imp {
+    use super::socket;
+    use std::io;
+    use std::os::fd::OwnedFd;
+    use std::time::Duration;
+
+    /// Routing message types, the same on macOS and FreeBSD
+    const RTM_ADD: u8 = 0x1;
+    const RTM_NEWADDR: u8 = 0xc;
+    const RTM_IFINFO: u8 = 0xe;
+
+    #[derive(Debug)]
+    pub(super) struct Watcher {
+        fd: OwnedFd,
+    }
+
+    impl Watcher {
+        pub(super) fn new() -> io::Result<Self> {
+            Ok(Watcher {
+                fd: socket::open(libc::PF_ROUTE, libc::AF_UNSPEC)?,
+            })
+        }
+
+        pub(super) fn wait(&mut self, timeout: Duration) -> io::Result<bool> {
+            // Each read returns one message; its type follows the length and version
+            socket::wait_for_change(&self.fd, timeout, |message| {
+                matches!(message.get(3), Some(&(RTM_ADD | RTM_NEWADDR | RTM_IFINFO)))
+            })
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::ffi::c_void;
+    use std::io;
+    use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+    use std::time::Duration;
+
+    #[link(name = "iphlpapi")]
+    extern "system" {
+        fn NotifyAddrChange(handle: *mut *mut c_void, overlapped: *const c_void) -> u32;
+    }
+
+    #[derive(Debug)]
+    pub(super) struct Watcher {
+        changes: Receiver<()>,
+    }
+
+    impl Watcher {
+        pub(super) fn new() -> io::Result<Self> {
+            let (sender, changes) = mpsc::channel();
+            // Called without a handle, NotifyAddrChange blocks until the next change, so it
+            // gets a thread of its own; the thread ends after the first change the watcher
+            // is no longer around for
+            std::thread::Builder::new()
+                .name("clock-netwatch".to_string())
+                .spawn(move || loop {
+                    // SAFETY: null arguments request a synchronous notification
+                    let result =
+                        unsafe { NotifyAddrChange(std::ptr::null_mut(), std::ptr::null()) };
+                    if result != 0 || sender.send(()).is_err() {
+                        return;
+                    }
+                })?;
+            Ok(Watcher { changes })
+        }
+
+        pub(super) fn wait(&mut self, timeout: Duration) -> io::Result<bool> {
+            match self.changes.recv_timeout(timeout) {
+                Ok(()) => {
+                    while self.changes.try_recv().is_ok() {}
+                    Ok(true)
+                }
+                Err(RecvTimeoutError::Timeout) => Ok(false),
+                Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "NotifyAddrChange failed",
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    windows
+)))]
+mod imp {
+    use std::io;
+    use std::time::Duration;
+
+    #[derive(Debug)]
+    pub(super) struct Watcher;
+
+    impl Watcher {
+        pub(super) fn new() -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "network change notifications are not supported on this platform",
+            ))
+        }
+
+        pub(super) fn wait(&mut self, _timeout: Duration) -> io::Result<bool> {
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::spawn_fake_server;
+    use crate::{Clock, ClockConfig, Timestamp};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_watcher_times_out_quietly() {
+        let mut watcher = NetworkWatcher::new().unwrap();
+        // Nothing is expected to change on the test machine meanwhile
+        assert!(watcher.wait(Duration::from_millis(20)).is_ok());
+    }
+
+    #[test]
+    fn test_network_change_resyncs_at_once() {
+        // The initial sync, the first cycle of the sync loop, and the resync
+        let server = spawn_fake_server(Timestamp::now(), 3);
+        let config = ClockConfig::new()
+            .with_servers(vec![server])
+            .with_resync_on_network_change(true);
+        let clock = Clock::with_config(config);
+        clock.start(3600, Arc::new(AtomicBool::new(false)));
+
+        let syncs_reach = |n: u64| {
+            (0..300).any(|_| {
+                std::thread::sleep(Duration::from_millis(10));
+                clock.get_stats().successful_syncs >= n
+            })
+        };
+        // The initial sync is not counted
+        assert!(syncs_reach(1));
+        clock.shared.network_changed();
+        assert!(syncs_reach(2), "no resync after the network changed");
+        clock.stop();
+    }
+}
@@ -0,0 +1,712 @@
+//! # NTS Sessions
+//!
+//! NTS (RFC 8915) starts with key establishment over TLS ([`ntske`](crate::ntske)), which
+//! hands the client two AEAD keys and a batch of cookies. Each authenticated NTP request
+//! spends one cookie, and each reply brings a fresh one, so a client only goes back to
+//! NTS-KE when lost replies have drained its cookies or the server has rotated its keys.
+//!
+//! An [`NtsSession`] protects requests with [`seal_request`](NtsSession::seal_request)
+//! and checks replies with [`open_reply`](NtsSession::open_reply), both authenticated with
+//! AEAD_AES_SIV_CMAC_256.
+//! A [`CookieJar`] keeps a session per server and is saved to disk encrypted with
+//! ChaCha20-Poly1305 under a [`LocalKey`] kept in a file of its own, so the cookies and
+//! keys in the jar are useless to whoever reads the jar alone. A restart that forgot them
+//! would pay a TLS handshake per server before the first sync:
+//!
+//! ```no_run
+//! use clock::nts::{CookieJar, LocalKey};
+//!
+//! let key = LocalKey::load_or_create("/var/lib/clock/nts.key")?;
+//! let mut jar = CookieJar::load("/var/lib/clock/nts.cookies", &key).unwrap_or_default();
+//! let cookie = jar.take_cookie("nts://time.cloudflare.com:4460", |server| {
+//!     // Perform NTS-KE with `server` here
+//!     # let _ = server;
+//!     # unimplemented!()
+//! })?;
+//! jar.save("/var/lib/clock/nts.cookies", &key)?;
+//! # let _ = cookie;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! [`CookieJar::take_cookie`] runs the key exchange it is given again whenever a session
+//! is down to its last cookies or older than [`SESSION_REFRESH`]. The
+//! [`Clock`](crate::Clock) engine keeps a jar of its own, saved to
+//! [`ClockConfig::nts_cookies`](crate::ClockConfig::nts_cookies) if it is set.
+
+use crate::extension::{
+    self, ExtensionField, FIELD_HEADER_LEN, NTS_AUTHENTICATOR, NTS_COOKIE, NTS_COOKIE_PLACEHOLDER,
+    UNIQUE_IDENTIFIER,
+};
+use crate::sntp::PACKET_LEN;
+use aes_siv::siv::Aes128Siv;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Cookies an NTS-KE server hands out, and how many a client keeps in stock
+pub const COOKIE_TARGET: usize = 8;
+
+/// A session with fewer cookies than this is renewed by key exchange before one is taken,
+/// leaving a spare for the request that triggers it
+pub const MIN_COOKIES: usize = 2;
+
+/// Age after which a session is renewed, since servers rotate the keys their cookies are
+/// sealed with; chrony's default
+pub const SESSION_REFRESH: Duration = Duration::from_secs(28 * 86_400);
+
+/// Start of a saved jar, also authenticated as associated data
+const MAGIC: &[u8] = b"clock-nts-cookies-1\n";
+
+/// Length of a ChaCha20-Poly1305 nonce, and of its tag and an AES-SIV one alike
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Length of the nonce of an NTS authenticator, and of the unique identifier of a request
+const SIV_NONCE_LEN: usize = 16;
+const UNIQUE_ID_LEN: usize = 32;
+
+/// The keys and cookies from one NTS key exchange with a server
+#[derive(Clone, PartialEq, Eq)]
+pub struct NtsSession {
+    /// AEAD algorithm negotiated for NTP packets, from the IANA registry (15 for
+    /// AEAD_AES_SIV_CMAC_256)
+    pub aead_algorithm: u16,
+    /// NTP server the cookies are for, when the key exchange named one other than itself
+    pub ntp_server: Option<String>,
+    /// NTP port the cookies are for, when the key exchange named one other than 123
+    pub ntp_port: Option<u16>,
+    c2s_key: Vec<u8>,
+    s2c_key: Vec<u8>,
+    cookies: VecDeque<Vec<u8>>,
+    /// When the key exchange took place, in whole seconds since the Unix epoch
+    established: u64,
+}
+
+impl NtsSession {
+    /// A session established at `established` with the client-to-server and
+    /// server-to-client keys and the cookies it handed out
+    pub fn new(
+        aead_algorithm: u16,
+        c2s_key: Vec<u8>,
+        s2c_key: Vec<u8>,
+        cookies: Vec<Vec<u8>>,
+        established: SystemTime,
+    ) -> Self {
+        NtsSession {
+            aead_algorithm,
+            ntp_server: None,
+            ntp_port: None,
+            c2s_key,
+            s2c_key,
+            cookies: cookies.into(),
+            established: unix_secs(established),
+        }
+    }
+
+    /// Key of requests to the server
+    pub fn c2s_key(&self) -> &[u8] {
+        &self.c2s_key
+    }
+
+    /// Key of replies from the server
+    pub fn s2c_key(&self) -> &[u8] {
+        &self.s2c_key
+    }
+
+    /// Number of unspent cookies
+    pub fn cookies_left(&self) -> usize {
+        self.cookies.len()
+    }
+
+    /// Takes the oldest cookie; each is sent once, so that requests cannot be linked
+    pub fn take_cookie(&mut self) -> Option<Vec<u8>> {
+        self.cookies.pop_front()
+    }
+
+    /// Stores a cookie from a reply, dropping the oldest beyond [`COOKIE_TARGET`]
+    pub fn add_cookie(&mut self, cookie: Vec<u8>) {
+        self.cookies.push_back(cookie);
+        while self.cookies.len() > COOKIE_TARGET {
+            self.cookies.pop_front();
+        }
+    }
+
+    /// Whether the session should be renewed by key exchange at `now`: it is down to its
+    /// last cookies or older than [`SESSION_REFRESH`]
+    pub fn needs_key_exchange(&self, now: SystemTime) -> bool {
+        self.cookies.len() < MIN_COOKIES
+            || unix_secs(now).saturating_sub(self.established) >= SESSION_REFRESH.as_secs()
+    }
+
+    /// Protects the request `header` for the server: spends the oldest cookie, asks for as
+    /// many new ones as refill the session to [`COOKIE_TARGET`], and authenticates it all
+    /// under the client-to-server key. Fails if no cookies are left.
+    pub fn seal_request(&mut self, header: &[u8; PACKET_LEN]) -> io::Result<NtsRequest> {
+        let cookie = self
+            .take_cookie()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no NTS cookies left"))?;
+        let unique_id = random::<UNIQUE_ID_LEN>()?;
+        let nonce = random::<SIV_NONCE_LEN>()?;
+        let placeholders = COOKIE_TARGET.saturating_sub(self.cookies.len() + 1);
+        let placeholder = ExtensionField::new(NTS_COOKIE_PLACEHOLDER, vec![0; cookie.len()]);
+        let mut fields = vec![
+            ExtensionField::new(UNIQUE_IDENTIFIER, unique_id.to_vec()),
+            ExtensionField::new(NTS_COOKIE, cookie),
+        ];
+        fields.extend(std::iter::repeat_n(placeholder, placeholders));
+        // Everything before the authenticator is authenticated by it, so it is encoded with
+        // a blank one of the final size first
+        let blank = authenticator(&nonce, &[0; TAG_LEN]);
+        let blank_len = FIELD_HEADER_LEN + blank.len();
+        fields.push(ExtensionField::new(NTS_AUTHENTICATOR, blank));
+        let mut packet = extension::encode_packet(header, &fields, None);
+        let start = packet.len() - blank_len;
+        let tag = siv_seal(&self.c2s_key, &nonce, &packet[..start], &[])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid NTS key"))?;
+        packet[start + FIELD_HEADER_LEN..].copy_from_slice(&authenticator(&nonce, &tag));
+        Ok(NtsRequest { packet, unique_id })
+    }
+
+    /// Checks `reply` to `request`: it must echo the request's unique identifier and be
+    /// authenticated under the server-to-client key. Stores the cookies it carries.
+    pub fn open_reply(&mut self, request: &NtsRequest, reply: &[u8]) -> Result<(), ReplyError> {
+        let parsed = extension::parse_packet(reply).map_err(|_| ReplyError::Malformed)?;
+        let mut offset = PACKET_LEN;
+        let mut echoed = false;
+        for field in &parsed.fields {
+            match field.field_type {
+                UNIQUE_IDENTIFIER => echoed |= field.value == request.unique_id,
+                NTS_AUTHENTICATOR if echoed => {
+                    let plaintext = open_authenticator(&self.s2c_key, &reply[..offset], field)
+                        .ok_or(ReplyError::Unauthenticated)?;
+                    // Fields after the authenticator are not authenticated, and ignored
+                    for field in decode_fields(&plaintext).ok_or(ReplyError::Malformed)? {
+                        if field.field_type == NTS_COOKIE {
+                            self.add_cookie(field.value);
+                        }
+                    }
+                    return Ok(());
+                }
+                _ => {}
+            }
+            offset += FIELD_HEADER_LEN + field.value.len();
+        }
+        if !echoed {
+            Err(ReplyError::NotOurs)
+        } else if parsed.header[1] == 0 && parsed.header[12..16] == *b"NTSN" {
+            Err(ReplyError::Nak)
+        } else {
+            Err(ReplyError::Unauthenticated)
+        }
+    }
+}
+
+/// A request protected by [`NtsSession::seal_request`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NtsRequest {
+    /// The packet to send
+    pub packet: Vec<u8>,
+    unique_id: [u8; UNIQUE_ID_LEN],
+}
+
+/// Why [`NtsSession::open_reply`] dropped a reply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyError {
+    /// The extension fields do not parse
+    Malformed,
+    /// The reply does not echo the request's unique identifier
+    NotOurs,
+    /// The reply has no authenticator, or it does not verify
+    Unauthenticated,
+    /// The server could not read the cookie and sent an NTS NAK: the session must be
+    /// renewed by key exchange
+    Nak,
+}
+
+impl fmt::Display for ReplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReplyError::Malformed => "malformed NTS extension fields",
+            ReplyError::NotOurs => "the reply is not to this request",
+            ReplyError::Unauthenticated => "the reply is not authenticated",
+            ReplyError::Nak => "the server rejected the NTS cookie",
+        })
+    }
+}
+
+impl std::error::Error for ReplyError {}
+
+/// The value of an NTS authenticator field: the lengths of the nonce and ciphertext, then
+/// both, each padded to a multiple of four bytes
+fn authenticator(nonce: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+    let mut value = Vec::new();
+    value.extend_from_slice(&(nonce.len() as u16).to_be_bytes());
+    value.extend_from_slice(&(ciphertext.len() as u16).to_be_bytes());
+    for part in [nonce, ciphertext] {
+        value.extend_from_slice(part);
+        value.resize(value.len().next_multiple_of(4), 0);
+    }
+    value
+}
+
+/// Decrypts the authenticator `field` of a packet that starts with `associated`
+fn open_authenticator(key: &[u8], associated: &[u8], field: &ExtensionField) -> Option<Vec<u8>> {
+    let value = &field.value;
+    let nonce_len = usize::from(u16::from_be_bytes([*value.first()?, *value.get(1)?]));
+    let ciphertext_len = usize::from(u16::from_be_bytes([*value.get(2)?, *value.get(3)?]));
+    let nonce = value.get(4..4 + nonce_len)?;
+    let start = 4 + nonce_len.next_multiple_of(4);
+    let ciphertext = value.get(start..start + ciphertext_len)?;
+    siv_open(key, nonce, associated, ciphertext)
+}
+
+/// Splits the plaintext of an authenticator into the extension fields it encrypts
+fn decode_fields(mut data: &[u8]) -> Option<Vec<ExtensionField>> {
+    let mut fields = Vec::new();
+    while !data.is_empty() {
+        let field_type = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+        let len = usize::from(u16::from_be_bytes([*data.get(2)?, *data.get(3)?]));
+        if len < FIELD_HEADER_LEN || len % 4 != 0 {
+            return None;
+        }
+        fields.push(ExtensionField::new(
+            field_type,
+            data.get(FIELD_HEADER_LEN..len)?.to_vec(),
+        ));
+        data = &data[len..];
+    }
+    Some(fields)
+}
+
+/// Leaves the keys and cookies out
+impl fmt::Debug for NtsSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NtsSession")
+            .field("aead_algorithm", &self.aead_algorithm)
+            .field("ntp_server", &self.ntp_server)
+            .field("ntp_port", &self.ntp_port)
+            .field("cookies_left", &self.cookies.len())
+            .field("established", &self.established)
+            .finish_non_exhaustive()
+    }
+}
+
+/// NTS sessions by server name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CookieJar {
+    sessions: BTreeMap<String, NtsSession>,
+}
+
+impl CookieJar {
+    /// An empty jar
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores the session with `server`, replacing any previous one
+    pub fn insert(&mut self, server: impl Into<String>, session: NtsSession) {
+        self.sessions.insert(server.into(), session);
+    }
+
+    /// The session with `server`
+    pub fn get(&self, server: &str) -> Option<&NtsSession> {
+        self.sessions.get(server)
+    }
+
+    /// The session with `server`, to spend its cookies
+    pub fn get_mut(&mut self, server: &str) -> Option<&mut NtsSession> {
+        self.sessions.get_mut(server)
+    }
+
+    /// Forgets the session with `server`, e.g. after it rejected a cookie with a NAK
+    pub fn remove(&mut self, server: &str) -> Option<NtsSession> {
+        self.sessions.remove(server)
+    }
+
+    /// Servers with a session, in order
+    pub fn servers(&self) -> impl Iterator<Item = &str> {
+        self.sessions.keys().map(String::as_str)
+    }
+
+    /// Stores a cookie from a reply of `server`; ignored if there is no session with it
+    pub fn add_cookie(&mut self, server: &str, cookie: Vec<u8>) {
+        if let Some(session) = self.sessions.get_mut(server) {
+            session.add_cookie(cookie);
+        }
+    }
+
+    /// The session with `server`, renewed with `key_exchange` first if there is none or it
+    /// [needs it](NtsSession::needs_key_exchange). A failed renewal falls back to the old
+    /// session while it has cookies left.
+    pub fn renew(
+        &mut self,
+        server: &str,
+        key_exchange: impl FnOnce(&str) -> io::Result<NtsSession>,
+    ) -> io::Result<&mut NtsSession> {
+        let stale = self
+            .sessions
+            .get(server)
+            .is_none_or(|session| session.needs_key_exchange(SystemTime::now()));
+        if stale {
+            match key_exchange(server) {
+                Ok(session) => self.insert(server, session),
+                Err(e) if self.get(server).map_or(0, NtsSession::cookies_left) == 0 => {
+                    return Err(e)
+                }
+                Err(_) => {}
+            }
+        }
+        self.sessions.get_mut(server).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("key exchange with {} returned no session", server),
+            )
+        })
+    }
+
+    /// Takes a cookie for a request to `server`, [renewing](Self::renew) its session first
+    /// if needed
+    pub fn take_cookie(
+        &mut self,
+        server: &str,
+        key_exchange: impl FnOnce(&str) -> io::Result<NtsSession>,
+    ) -> io::Result<Vec<u8>> {
+        self.renew(server, key_exchange)?
+            .take_cookie()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("key exchange with {} returned no cookies", server),
+                )
+            })
+    }
+
+    /// Reads a jar written by [`save`](Self::save) with the same key
+    pub fn load(path: impl AsRef<Path>, key: &LocalKey) -> io::Result<Self> {
+        let sealed = fs::read(path)?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let body = sealed
+            .strip_prefix(MAGIC)
+            .filter(|body| body.len() >= NONCE_LEN + TAG_LEN)
+            .ok_or_else(|| invalid("not an NTS cookie jar"))?;
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: MAGIC,
+        };
+        let plaintext = ChaCha20Poly1305::new(&key.0.into())
+            .decrypt(nonce.into(), payload)
+            .map_err(|_| invalid("NTS cookie jar is corrupt or sealed with another key"))?;
+        decode(&plaintext).ok_or_else(|| invalid("malformed NTS cookie jar"))
+    }
+
+    /// Atomically writes the jar to `path`, encrypted with `key` under a fresh nonce
+    pub fn save(&self, path: impl AsRef<Path>, key: &LocalKey) -> io::Result<()> {
+        let path = path.as_ref();
+        let nonce = random::<NONCE_LEN>()?;
+        let plaintext = self.encode();
+        let payload = Payload {
+            msg: &plaintext,
+            aad: MAGIC,
+        };
+        let ciphertext = ChaCha20Poly1305::new(&key.0.into())
+            .encrypt(&nonce.into(), payload)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "NTS cookie jar too large"))?;
+        let mut sealed = MAGIC.to_vec();
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        let tmp = path.with_extension("tmp");
+        write_private(&tmp, &sealed)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Lengths are big-endian u16s in front of names, keys, and cookies; an NTP server
+    /// left to the key exchange is empty, and port 0 stands for none
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let put = |out: &mut Vec<u8>, bytes: &[u8]| {
+            out.extend_from_slice(&(bytes.len().min(u16::MAX.into()) as u16).to_be_bytes());
+            out.extend_from_slice(&bytes[..bytes.len().min(u16::MAX.into())]);
+        };
+        for (server, session) in &self.sessions {
+            put(&mut out, server.as_bytes());
+            out.extend_from_slice(&session.aead_algorithm.to_be_bytes());
+            out.extend_from_slice(&session.established.to_be_bytes());
+            put(
+                &mut out,
+                session.ntp_server.as_deref().unwrap_or("").as_bytes(),
+            );
+            out.extend_from_slice(&session.ntp_port.unwrap_or(0).to_be_bytes());
+            put(&mut out, &session.c2s_key);
+            put(&mut out, &session.s2c_key);
+            out.extend_from_slice(&(session.cookies.len() as u16).to_be_bytes());
+            for cookie in &session.cookies {
+                put(&mut out, cookie);
+            }
+        }
+        out
+    }
+}
+
+fn decode(mut data: &[u8]) -> Option<CookieJar> {
+    fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+        let taken = data.get(..len)?;
+        *data = &data[len..];
+        Some(taken)
+    }
+    fn take_u16(data: &mut &[u8]) -> Option<u16> {
+        take(data, 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+    fn take_bytes(data: &mut &[u8]) -> Option<Vec<u8>> {
+        let len = take_u16(data)?;
+        take(data, len.into()).map(<[u8]>::to_vec)
+    }
+
+    let mut jar = CookieJar::new();
+    while !data.is_empty() {
+        let server = String::from_utf8(take_bytes(&mut data)?).ok()?;
+        let aead_algorithm = take_u16(&mut data)?;
+        let established = u64::from_be_bytes(take(&mut data, 8)?.try_into().ok()?);
+        let ntp_server = String::from_utf8(take_bytes(&mut data)?).ok()?;
+        let ntp_port = take_u16(&mut data)?;
+        let c2s_key = take_bytes(&mut data)?;
+        let s2c_key = take_bytes(&mut data)?;
+        let cookies = (0..take_u16(&mut data)?)
+            .map(|_| take_bytes(&mut data))
+            .collect::<Option<_>>()?;
+        let session = NtsSession {
+            aead_algorithm,
+            ntp_server: Some(ntp_server).filter(|host| !host.is_empty()),
+            ntp_port: Some(ntp_port).filter(|&port| port != 0),
+            c2s_key,
+            s2c_key,
+            cookies,
+            established,
+        };
+        jar.sessions.insert(server, session);
+    }
+    Some(jar)
+}
+
+/// The key a [`CookieJar`] is encrypted with on disk
+#[derive(Clone, PartialEq, Eq)]
+pub struct LocalKey([u8; 32]);
+
+impl LocalKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        LocalKey(bytes)
+    }
+
+    /// A new random key from the operating system
+    pub fn generate() -> io::Result<Self> {
+        random().map(LocalKey)
+    }
+
+    /// Reads the key stored at `path`, or generates one and stores it there, readable by
+    /// the owner alone
+    pub fn load_or_create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        match fs::read(path) {
+            Ok(bytes) => bytes.try_into().map(LocalKey).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "NTS key file is not 32 bytes")
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let key = Self::generate()?;
+                write_private(path, &key.0)?;
+                Ok(key)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Leaves the key out
+impl fmt::Debug for LocalKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LocalKey(..)")
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Writes `data` to a new `path` that only the owner can read, on Unix
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(data)
+}
+
+/// AEAD_AES_SIV_CMAC_256 over `plaintext` under `key`, with `associated` and then `nonce`
+/// as associated data, as RFC 8915 uses it; the SIV tag comes first
+fn siv_seal(key: &[u8], nonce: &[u8], associated: &[u8], plaintext: &[u8]) -> Option<Vec<u8>> {
+    Aes128Siv::new_from_slice(key)
+        .ok()?
+        .encrypt([associated, nonce], plaintext)
+        .ok()
+}
+
+/// Reverses [`siv_seal`], or `None` if the ciphertext does not verify
+fn siv_open(key: &[u8], nonce: &[u8], associated: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    Aes128Siv::new_from_slice(key)
+        .ok()?
+        .decrypt([associated, nonce], ciphertext)
+        .ok()
+}
+
+/// Random bytes from the operating system
+fn random<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::fill(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(cookies: usize, established: SystemTime) -> NtsSession {
+        let cookies = (0..cookies as u8).map(|i| vec![i; 100]).collect();
+        NtsSession::new(15, vec![1; 32], vec![2; 32], cookies, established)
+    }
+
+    #[test]
+    fn test_jar_survives_a_restart_encrypted() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("clock-ntp-cookies-{}", std::process::id()));
+        let key_path = dir.join(format!("clock-ntp-cookies-key-{}", std::process::id()));
+        let _ = fs::remove_file(&key_path);
+
+        let key = LocalKey::load_or_create(&key_path).unwrap();
+        assert_eq!(LocalKey::load_or_create(&key_path).unwrap(), key);
+        let mut jar = CookieJar::new();
+        jar.insert("nts://a.example:4460", session(8, SystemTime::now()));
+        let mut elsewhere = session(0, UNIX_EPOCH);
+        elsewhere.ntp_server = Some("ntp.b.example".to_string());
+        elsewhere.ntp_port = Some(1123);
+        jar.insert("nts://b.example:4460", elsewhere);
+        jar.save(&path, &key).unwrap();
+
+        let sealed = fs::read(&path).unwrap();
+        assert!(!sealed.windows(32).any(|w| w == [1; 32]));
+        assert!(!format!("{:?}", jar).contains("[1, 1"));
+        assert_eq!(CookieJar::load(&path, &key).unwrap(), jar);
+
+        let other = LocalKey::from_bytes([9; 32]);
+        let error = CookieJar::load(&path, &other).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        fs::write(&path, &sealed[..sealed.len() - 1]).unwrap();
+        assert!(CookieJar::load(&path, &key).is_err());
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(key_path).unwrap();
+    }
+
+    #[test]
+    fn test_requests_are_authenticated_and_replies_checked() {
+        let mut session = session(3, SystemTime::now());
+        let header = [0x23; PACKET_LEN];
+        let request = session.seal_request(&header).unwrap();
+        assert_eq!(session.cookies_left(), 2);
+
+        // The oldest cookie, placeholders for the cookies missing, and an authenticator
+        // over all of it
+        let parsed = extension::parse_packet(&request.packet).unwrap();
+        let types: Vec<u16> = parsed.fields.iter().map(|f| f.field_type).collect();
+        assert_eq!(
+            types,
+            [
+                UNIQUE_IDENTIFIER,
+                NTS_COOKIE,
+                NTS_COOKIE_PLACEHOLDER,
+                NTS_COOKIE_PLACEHOLDER,
+                NTS_COOKIE_PLACEHOLDER,
+                NTS_COOKIE_PLACEHOLDER,
+                NTS_COOKIE_PLACEHOLDER,
+                NTS_AUTHENTICATOR
+            ]
+        );
+        assert_eq!(parsed.fields[1].value, [0; 100]);
+        let associated = &request.packet[..request.packet.len() - 40];
+        let authenticator = &parsed.fields[7];
+        assert_eq!(
+            open_authenticator(&[1; 32], associated, authenticator),
+            Some(Vec::new())
+        );
+        assert_eq!(
+            open_authenticator(&[2; 32], associated, authenticator),
+            None
+        );
+
+        // Replies must echo the request and carry an authenticator under the other key
+        let unique_id = ExtensionField::new(UNIQUE_IDENTIFIER, request.unique_id.to_vec());
+        let echo = extension::encode_packet(&header, std::slice::from_ref(&unique_id), None);
+        assert_eq!(
+            session.open_reply(&request, &echo),
+            Err(ReplyError::Unauthenticated)
+        );
+        assert_eq!(
+            session.open_reply(&request, &request.packet),
+            Err(ReplyError::Unauthenticated)
+        );
+        let other = session.seal_request(&header).unwrap();
+        assert_eq!(
+            session.open_reply(&other, &request.packet),
+            Err(ReplyError::NotOurs)
+        );
+        let mut nak = header;
+        nak[1] = 0;
+        nak[12..16].copy_from_slice(b"NTSN");
+        let nak = extension::encode_packet(&nak, &[unique_id], None);
+        assert_eq!(session.open_reply(&request, &nak), Err(ReplyError::Nak));
+        assert_eq!(
+            session.open_reply(&request, &header),
+            Err(ReplyError::NotOurs)
+        );
+    }
+
+    #[test]
+    fn test_take_cookie_renews_sessions_running_low() {
+        let now = SystemTime::now();
+        let mut jar = CookieJar::new();
+        let server = "nts://time.example:4460";
+        let exchanges = std::cell::Cell::new(0);
+        let key_exchange = |_: &str| {
+            exchanges.set(exchanges.get() + 1);
+            Ok(session(COOKIE_TARGET, now))
+        };
+
+        // No session yet, then enough cookies for a while
+        assert_eq!(jar.take_cookie(server, key_exchange).unwrap(), [0; 100]);
+        for _ in 1..=COOKIE_TARGET - MIN_COOKIES {
+            jar.take_cookie(server, key_exchange).unwrap();
+        }
+        assert_eq!(jar.get(server).unwrap().cookies_left(), MIN_COOKIES - 1);
+        jar.add_cookie(server, vec![0xaa; 100]);
+        jar.take_cookie(server, key_exchange).unwrap();
+        assert_eq!(exchanges.get(), 1);
+        // Down to its last cookie, the session is renewed
+        jar.take_cookie(server, key_exchange).unwrap();
+        assert_eq!(exchanges.get(), 2);
+
+        // Old sessions are renewed too, and a failed renewal spends the cookies left
+        jar.insert(server, session(COOKIE_TARGET, UNIX_EPOCH));
+        assert!(jar.get(server).unwrap().needs_key_exchange(now));
+        let failing = |_: &str| Err(io::Error::new(io::ErrorKind::TimedOut, "NTS-KE failed"));
+        assert!(jar.take_cookie(server, failing).is_ok());
+        jar.insert(server, session(0, now));
+        assert_eq!(
+            jar.take_cookie(server, failing).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+}
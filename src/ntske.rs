@@ -0,0 +1,302 @@
+//! # NTS Key Establishment
+//!
+//! The first half of NTS (RFC 8915): a short TLS session with an NTS-KE server, which
+//! agrees on the protocol and AEAD algorithm, hands out cookies, and names the NTP server
+//! they are for. The keys of the NTP half are exported from the TLS session, so they never
+//! cross the network.
+//!
+//! [`key_exchange`] enforces the TLS options of the server's [`ServerSpec`]: the server
+//! must chain to the roots in `cafile` (or the system's), present a pinned public key when
+//! `pin`s are given, and speak at least `mintls`.
+//!
+//! ```no_run
+//! use clock::ntske;
+//! use clock::ServerSpec;
+//! use std::net::ToSocketAddrs;
+//!
+//! let spec: ServerSpec = "nts://time.cloudflare.com".parse().unwrap();
+//! let addr = (spec.host.as_str(), spec.port).to_socket_addrs()?.next().unwrap();
+//! let session = ntske::key_exchange(&spec, addr)?;
+//! println!("{} cookies", session.cookies_left());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::logging::clock_log;
+use crate::nts::NtsSession;
+use crate::server::ServerSpec;
+use crate::tls::{self, Trust};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+/// How long a key exchange may take, from connecting to the last record
+pub const KEY_EXCHANGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// AEAD_AES_SIV_CMAC_256 in the IANA AEAD registry, the only algorithm requested
+pub const AEAD_AES_SIV_CMAC_256: u16 = 15;
+
+/// ALPN identifier of NTS-KE
+const ALPN: &[u8] = b"ntske/1";
+
+/// Label of the TLS exporter the NTP keys come from
+const EXPORTER_LABEL: &[u8] = b"EXPORTER-network-time-security";
+
+/// Length of each AEAD_AES_SIV_CMAC_256 key
+const KEY_LEN: usize = 32;
+
+/// Longest response read, against a server that never ends it
+const MAX_RESPONSE_LEN: usize = 64 * 1024;
+
+/// Set on records the receiver must understand
+const CRITICAL: u16 = 0x8000;
+
+const END_OF_MESSAGE: u16 = 0;
+const NEXT_PROTOCOL: u16 = 1;
+const ERROR: u16 = 2;
+const WARNING: u16 = 3;
+const AEAD_ALGORITHM: u16 = 4;
+const NEW_COOKIE: u16 = 5;
+const NTP_SERVER: u16 = 6;
+const NTP_PORT: u16 = 7;
+
+/// Next protocol ID of NTPv4
+const NTPV4: u16 = 0;
+
+/// Performs NTS key establishment with `spec` at `addr`, the address its host resolved to,
+/// returning a session established now
+pub fn key_exchange(spec: &ServerSpec, addr: SocketAddr) -> io::Result<NtsSession> {
+    let trust = Trust {
+        ca_file: spec.ca_file.as_deref(),
+        spki_pins: &spec.spki_pins,
+        min_tls: spec.min_tls,
+    };
+    let config = tls::client_config(trust, ALPN)?;
+    let mut stream = tls::connect(addr, &spec.host, config, KEY_EXCHANGE_TIMEOUT)?;
+    stream.write_all(&request())?;
+    stream.flush()?;
+    let response = read_response(&mut stream, &spec.name())?;
+    if stream.conn.alpn_protocol() != Some(ALPN) {
+        return Err(invalid("the server did not agree to NTS-KE"));
+    }
+
+    let export = |direction: u8| {
+        let mut context = [0u8; 5];
+        context[..2].copy_from_slice(&NTPV4.to_be_bytes());
+        context[2..4].copy_from_slice(&AEAD_AES_SIV_CMAC_256.to_be_bytes());
+        context[4] = direction;
+        stream
+            .conn
+            .export_keying_material(vec![0; KEY_LEN], EXPORTER_LABEL, Some(&context))
+            .map_err(io::Error::other)
+    };
+    let mut session = NtsSession::new(
+        AEAD_AES_SIV_CMAC_256,
+        export(0)?,
+        export(1)?,
+        response.cookies,
+        SystemTime::now(),
+    );
+    session.ntp_server = response.ntp_server;
+    session.ntp_port = response.ntp_port;
+    Ok(session)
+}
+
+/// Asks for NTPv4 with AES-SIV-CMAC-256
+fn request() -> Vec<u8> {
+    let mut out = Vec::new();
+    put_record(&mut out, CRITICAL | NEXT_PROTOCOL, &NTPV4.to_be_bytes());
+    put_record(
+        &mut out,
+        CRITICAL | AEAD_ALGORITHM,
+        &AEAD_AES_SIV_CMAC_256.to_be_bytes(),
+    );
+    put_record(&mut out, CRITICAL | END_OF_MESSAGE, &[]);
+    out
+}
+
+fn put_record(out: &mut Vec<u8>, record_type: u16, body: &[u8]) {
+    out.extend_from_slice(&record_type.to_be_bytes());
+    out.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+/// What the server agreed to
+#[derive(Debug, Default)]
+struct Response {
+    protocols: Option<Vec<u16>>,
+    aead_algorithm: Option<u16>,
+    cookies: Vec<Vec<u8>>,
+    ntp_server: Option<String>,
+    ntp_port: Option<u16>,
+}
+
+/// Reads records up to the end of the message, failing on an error record, an unknown
+/// critical record, or a response that does not accept the request
+fn read_response(stream: &mut impl Read, server: &str) -> io::Result<Response> {
+    let mut response = Response::default();
+    let mut read = 0;
+    loop {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header)?;
+        let record_type = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        read += header.len() + len;
+        if read > MAX_RESPONSE_LEN {
+            return Err(invalid("the response is too long"));
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body)?;
+        let u16s = || {
+            body.chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .collect::<Vec<_>>()
+        };
+        match record_type & !CRITICAL {
+            END_OF_MESSAGE => break,
+            NEXT_PROTOCOL => response.protocols = Some(u16s()),
+            ERROR => {
+                let code = u16s().first().copied().unwrap_or(u16::MAX);
+                return Err(invalid(&format!("the server sent error {}", code)));
+            }
+            WARNING => {
+                let code = u16s().first().copied().unwrap_or(u16::MAX);
+                clock_log!(
+                    Warn,
+                    Server,
+                    "NTS-KE server {} sent warning {}",
+                    server,
+                    code
+                );
+            }
+            AEAD_ALGORITHM => response.aead_algorithm = u16s().first().copied(),
+            NEW_COOKIE => response.cookies.push(body),
+            NTP_SERVER => {
+                let host = String::from_utf8(body).map_err(|_| invalid("invalid NTP server"))?;
+                response.ntp_server = Some(host);
+            }
+            NTP_PORT => response.ntp_port = u16s().first().copied(),
+            other if record_type & CRITICAL != 0 => {
+                return Err(invalid(&format!("unknown critical record {}", other)));
+            }
+            _ => {}
+        }
+    }
+
+    if response.protocols.as_deref() != Some(&[NTPV4]) {
+        return Err(invalid("the server does not offer NTPv4"));
+    }
+    if response.aead_algorithm != Some(AEAD_AES_SIV_CMAC_256) {
+        return Err(invalid("the server does not offer AEAD_AES_SIV_CMAC_256"));
+    }
+    if response.cookies.is_empty() {
+        return Err(invalid("the server sent no cookies"));
+    }
+    Ok(response)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("NTS-KE: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::TlsVersion;
+    use crate::tests::{spawn_fake_nts_ke_server, TestPki};
+    use std::fs;
+
+    fn spec(pki: &TestPki, addr: SocketAddr) -> ServerSpec {
+        ServerSpec::builder("localhost")
+            .nts()
+            .port(addr.port())
+            .ca_file(pki.ca_file.to_string_lossy())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_key_exchange_trusts_the_ca_file() {
+        let pki = TestPki::new("ntske-ca-file");
+        let (addr, server_keys) =
+            spawn_fake_nts_ke_server(&pki, &[&rustls::version::TLS13], 1123, 1);
+        let session = key_exchange(&spec(&pki, addr), addr).unwrap();
+        assert_eq!(session.aead_algorithm, AEAD_AES_SIV_CMAC_256);
+        assert_eq!(session.cookies_left(), 8);
+        assert_eq!(session.ntp_server.as_deref(), Some("127.0.0.1"));
+        assert_eq!(session.ntp_port, Some(1123));
+        let (c2s, s2c) = server_keys.recv().unwrap();
+        assert_eq!(session.c2s_key(), c2s);
+        assert_eq!(session.s2c_key(), s2c);
+        assert_ne!(c2s, s2c);
+
+        // The same server is a stranger to the system store
+        let mut untrusted = spec(&pki, addr);
+        untrusted.ca_file = None;
+        let (addr, _) = spawn_fake_nts_ke_server(&pki, &[&rustls::version::TLS13], 1123, 1);
+        assert!(key_exchange(&untrusted, addr).is_err());
+        fs::remove_dir_all(&pki.dir).unwrap();
+    }
+
+    #[test]
+    fn test_key_exchange_enforces_pins() {
+        let pki = TestPki::new("ntske-pins");
+        let (addr, _) = spawn_fake_nts_ke_server(&pki, &[&rustls::version::TLS13], 1123, 3);
+
+        let mut pinned = spec(&pki, addr);
+        pinned.spki_pins = vec![[0; 32]];
+        let e = key_exchange(&pinned, addr).unwrap_err();
+        assert!(e.to_string().contains("pinned"), "{}", e);
+
+        pinned.spki_pins.push(pki.leaf_spki_sha256);
+        assert!(key_exchange(&pinned, addr).is_ok());
+        pinned.spki_pins = vec![pki.ca_spki_sha256];
+        assert!(key_exchange(&pinned, addr).is_ok());
+        fs::remove_dir_all(&pki.dir).unwrap();
+    }
+
+    #[test]
+    fn test_key_exchange_enforces_min_tls() {
+        let pki = TestPki::new("ntske-min-tls");
+        let (addr, _) = spawn_fake_nts_ke_server(&pki, &[&rustls::version::TLS12], 1123, 2);
+        let mut spec = spec(&pki, addr);
+        assert!(key_exchange(&spec, addr).is_err());
+        spec.min_tls = TlsVersion::Tls12;
+        assert!(key_exchange(&spec, addr).is_ok());
+        fs::remove_dir_all(&pki.dir).unwrap();
+    }
+
+    #[test]
+    fn test_response_must_accept_the_request() {
+        let mut records = Vec::new();
+        put_record(&mut records, CRITICAL | NEXT_PROTOCOL, &NTPV4.to_be_bytes());
+        put_record(
+            &mut records,
+            CRITICAL | AEAD_ALGORITHM,
+            &15u16.to_be_bytes(),
+        );
+        put_record(&mut records, NEW_COOKIE, &[7; 100]);
+        let end = |records: &[u8]| [records, &[0x80, 0, 0, 0]].concat();
+        let response = read_response(&mut &end(&records)[..], "test").unwrap();
+        assert_eq!(response.cookies, vec![vec![7; 100]]);
+        assert_eq!(response.ntp_server, None);
+
+        let mut unknown = records.clone();
+        put_record(&mut unknown, 0x4000, &[]);
+        assert!(read_response(&mut &end(&unknown)[..], "test").is_ok());
+        put_record(&mut unknown, CRITICAL | 0x4000, &[]);
+        assert!(read_response(&mut &end(&unknown)[..], "test").is_err());
+
+        let mut error = records.clone();
+        put_record(&mut error, CRITICAL | ERROR, &1u16.to_be_bytes());
+        assert!(read_response(&mut &end(&error)[..], "test").is_err());
+
+        let mut aes_gcm = Vec::new();
+        put_record(&mut aes_gcm, CRITICAL | NEXT_PROTOCOL, &NTPV4.to_be_bytes());
+        put_record(&mut aes_gcm, CRITICAL | AEAD_ALGORITHM, &1u16.to_be_bytes());
+        put_record(&mut aes_gcm, NEW_COOKIE, &[7; 100]);
+        assert!(read_response(&mut &end(&aes_gcm)[..], "test").is_err());
+
+        // Cut off before the end of the message
+        assert!(read_response(&mut &records[..], "test").is_err());
+    }
+}
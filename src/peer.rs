@@ -0,0 +1,486 @@
+//! # Peer Mesh
+//!
+//! Several instances on a LAN can back each other up when the internet is unreachable.
+//! Each answers NTP client requests on a UDP socket with its disciplined time, stratum,
+//! reference ID, and uncertainty (as root delay and dispersion), and polls the instances listed in
+//! [`ClockConfig::peers`](crate::ClockConfig::peers) whenever none of its servers answers.
+//!
+//! A peer is only followed if it is a better source than this clock would be in orphan
+//! mode: it has a lower stratum than [`ClockConfig::orphan_stratum`], or it is an orphan
+//! itself and has a lower address. Replies from a peer that synchronizes to this host are
+//! rejected as timing loops, so two instances never follow each other.
+//!
+//! A responder started with [`spawn_responder_with_keys`] answers requests carrying a MAC
+//! with a reply signed by the same key, and ignores requests whose MAC it cannot verify.
+//!
+//! The responder remembers the [`ClockConfig::mru_size`](crate::ClockConfig::mru_size)
+//! clients it heard from most recently, listed by
+//! [`ClockHandle::clients`](crate::ClockHandle::clients) and the time API's `/clients`.
+//! Under [`ClockConfig::rate_limit`](crate::ClockConfig::rate_limit), a client that sends
+//! more than a burst of requests faster than one per interval is answered with a
+//! kiss-o'-death `RATE` reply instead of the time until it slows down.
+
+use crate::auth::{MacError, SharedKeyStore};
+use crate::logging::clock_log;
+use crate::sntp::{self, MAX_PACKET_LEN, PACKET_LEN};
+use crate::ClockHandle;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often the responder checks the shutdown flag
+const RECV_POLL: Duration = Duration::from_millis(50);
+
+/// Clients remembered by default, see [`ClockConfig::mru_size`](crate::ClockConfig::mru_size)
+pub const DEFAULT_MRU_SIZE: usize = 1024;
+
+/// How fast a client may query the responder before it is sent kiss-o'-death `RATE`
+/// replies: `burst` requests at once, then one per `interval` on average
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub interval: Duration,
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    /// A burst of 8, then one request every 2 seconds, like chrony's `ratelimit`
+    fn default() -> Self {
+        RateLimit {
+            interval: Duration::from_secs(2),
+            burst: 8,
+        }
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    /// Parses `INTERVAL[:BURST]`, the interval in seconds
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (interval, burst) = match s.split_once(':') {
+            Some((interval, burst)) => (interval, Some(burst)),
+            None => (s, None),
+        };
+        let interval = interval
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|secs| *secs > 0.0)
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .ok_or_else(|| format!("invalid rate limit interval '{}'", interval))?;
+        let burst = match burst {
+            Some(burst) => burst
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|burst| *burst >= 1)
+                .ok_or_else(|| format!("invalid rate limit burst '{}'", burst))?,
+            None => RateLimit::default().burst,
+        };
+        Ok(RateLimit { interval, burst })
+    }
+}
+
+/// Writes the form [`FromStr`] reads
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.interval.as_secs_f64(), self.burst)
+    }
+}
+
+/// What the responder remembers about a client
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientRecord {
+    pub addr: IpAddr,
+    /// Requests received, rate limited ones included
+    pub requests: u64,
+    /// Requests answered with a kiss-o'-death `RATE` reply
+    pub rate_limited: u64,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+}
+
+/// A client's record and what is left of its burst
+#[derive(Debug)]
+struct ClientEntry {
+    record: ClientRecord,
+    tokens: f64,
+}
+
+/// The clients heard from most recently, by address, and their rate limits
+#[derive(Debug)]
+pub(crate) struct ClientTable {
+    clients: HashMap<IpAddr, ClientEntry>,
+    capacity: usize,
+    rate_limit: Option<RateLimit>,
+}
+
+impl ClientTable {
+    pub(crate) fn new(rate_limit: Option<RateLimit>, capacity: usize) -> Self {
+        ClientTable {
+            clients: HashMap::new(),
+            capacity: capacity.max(1),
+            rate_limit,
+        }
+    }
+
+    /// Applies new limits, forgetting the least recently seen clients beyond `capacity`
+    pub(crate) fn configure(&mut self, rate_limit: Option<RateLimit>, capacity: usize) {
+        self.rate_limit = rate_limit;
+        self.capacity = capacity.max(1);
+        while self.clients.len() > self.capacity {
+            self.evict_least_recent();
+        }
+    }
+
+    /// Records a request from `addr` received at `now`, returning whether it is within
+    /// the client's rate limit
+    pub(crate) fn admit(&mut self, addr: IpAddr, now: Instant) -> bool {
+        if !self.clients.contains_key(&addr) && self.clients.len() >= self.capacity {
+            self.evict_least_recent();
+        }
+        let burst = self.rate_limit.map_or(0.0, |limit| f64::from(limit.burst));
+        let entry = self.clients.entry(addr).or_insert_with(|| ClientEntry {
+            record: ClientRecord {
+                addr,
+                requests: 0,
+                rate_limited: 0,
+                first_seen: now,
+                last_seen: now,
+            },
+            tokens: burst,
+        });
+        let elapsed = now.saturating_duration_since(entry.record.last_seen);
+        entry.record.requests += 1;
+        entry.record.last_seen = now;
+        let Some(limit) = self.rate_limit else {
+            return true;
+        };
+        // Tokens trickle back at one per interval, up to the burst
+        entry.tokens =
+            (entry.tokens + elapsed.as_secs_f64() / limit.interval.as_secs_f64()).min(burst);
+        if entry.tokens >= 1.0 {
+            entry.tokens -= 1.0;
+            true
+        } else {
+            entry.record.rate_limited += 1;
+            false
+        }
+    }
+
+    /// The clients, the most recently seen first
+    pub(crate) fn records(&self) -> Vec<ClientRecord> {
+        let mut records: Vec<ClientRecord> = self
+            .clients
+            .values()
+            .map(|entry| entry.record.clone())
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.last_seen));
+        records
+    }
+
+    fn evict_least_recent(&mut self) {
+        let oldest = self
+            .clients
+            .values()
+            .min_by_key(|entry| entry.record.last_seen)
+            .map(|entry| entry.record.addr);
+        if let Some(addr) = oldest {
+            self.clients.remove(&addr);
+        }
+    }
+}
+
+/// Answers peers' NTP queries on `socket` from a background thread until `shutdown` is set.
+/// Requests carrying a MAC are ignored, as no keys are known.
+pub fn spawn_responder(
+    socket: UdpSocket,
+    handle: ClockHandle,
+    shutdown: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    spawn_responder_with_keys(socket, handle, SharedKeyStore::default(), shutdown)
+}
+
+/// Like [`spawn_responder`], verifying requests that carry a MAC against `keys` and signing
+/// the replies to them with the same key. Keys added to or removed from `keys` take
+/// effect with the next request.
+pub fn spawn_responder_with_keys(
+    socket: UdpSocket,
+    handle: ClockHandle,
+    keys: SharedKeyStore,
+    shutdown: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    socket.set_read_timeout(Some(RECV_POLL))?;
+    if let Ok(addr) = socket.local_addr() {
+        clock_log!(Info, Serving, "Answering peers' NTP queries on {}", addr);
+    }
+    Ok(std::thread::spawn(move || {
+        let mut packet = [0u8; MAX_PACKET_LEN];
+        while !shutdown.load(Ordering::Relaxed) {
+            let (len, from) = match socket.recv_from(&mut packet) {
+                Ok(received) => received,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => {
+                    clock_log!(Warn, Serving, "Failed to receive a peer query: {}", e);
+                    std::thread::sleep(RECV_POLL);
+                    continue;
+                }
+            };
+            let receive = handle.now_timestamp();
+            if len < PACKET_LEN {
+                clock_log!(
+                    Debug,
+                    Serving,
+                    "Ignoring a {}-byte packet from {}",
+                    len,
+                    from
+                );
+                continue;
+            }
+            let packet = &packet[..len];
+            let mut request = [0u8; PACKET_LEN];
+            request.copy_from_slice(&packet[..PACKET_LEN]);
+            if !handle.admit_client(from.ip()) {
+                clock_log!(Debug, Serving, "Rate limiting {}", from.ip());
+                if let Some(kiss) = sntp::kiss_reply(&request, *b"RATE", receive) {
+                    let _ = socket.send_to(&kiss, from);
+                }
+                continue;
+            }
+            let key_id = match keys.read().verify(packet) {
+                Ok(id) => Some(id),
+                Err(MacError::Missing) => None,
+                Err(e) => {
+                    clock_log!(Debug, Serving, "Ignoring a request from {}: {}", from, e);
+                    continue;
+                }
+            };
+            let state = handle.server_state();
+            let Some(reply) = sntp::server_reply(&request, &state, receive, handle.now_timestamp())
+            else {
+                clock_log!(
+                    Debug,
+                    Serving,
+                    "Ignoring a packet from {} that is not a client request",
+                    from
+                );
+                continue;
+            };
+            let reply = match key_id {
+                Some(id) => match keys.read().sign(id, &reply) {
+                    Ok(signed) => signed,
+                    // Revoked since the request was verified
+                    Err(_) => continue,
+                },
+                None => reply.to_vec(),
+            };
+            if let Err(e) = socket.send_to(&reply, from) {
+                clock_log!(Warn, Serving, "Failed to answer peer {}: {}", from, e);
+            }
+        }
+    }))
+}
+
+/// The local address this host sends from to reach `addr`, which is what a peer at `addr`
+/// sees as this clock's address
+pub(crate) fn local_ip_towards(addr: SocketAddr) -> io::Result<IpAddr> {
+    let unspecified = match addr {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind((unspecified, 0))?;
+    // Connecting a UDP socket sends nothing; it only selects the route
+    socket.connect(addr)?;
+    Ok(socket.local_addr()?.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sntp::{NtpPacket, QueryError, Transport, UdpTransport, MAX_STRATUM};
+    use crate::{Clock, ClockConfig, FallbackPolicy};
+
+    /// Sends a client request to `addr` and returns the reply as it is
+    fn exchange(transport: &mut UdpTransport, addr: &SocketAddr) -> NtpPacket {
+        let request = sntp::client_request();
+        let mut reply = [0u8; PACKET_LEN];
+        transport.exchange(addr, &request, &mut reply).unwrap();
+        assert_eq!(reply[24..32], request[40..48]);
+        NtpPacket::from(&reply)
+    }
+
+    #[test]
+    fn test_responder_answers_with_the_clock_state() {
+        let clock = Clock::with_config(
+            ClockConfig::new()
+                .with_servers(Vec::new())
+                .with_fallback_policy(FallbackPolicy::SystemClock),
+        );
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let responder = spawn_responder(socket, clock.handle(), shutdown.clone()).unwrap();
+
+        let mut transport = UdpTransport::default();
+        let reply = exchange(&mut transport, &addr);
+        // Never synchronized, so the reply says so and peers will not follow it
+        assert_eq!((reply.leap, reply.stratum), (3, MAX_STRATUM));
+        assert_eq!(reply.reference_id, *b"INIT");
+        assert!(matches!(
+            sntp::query(&mut transport, &addr),
+            Err(QueryError::InvalidReply)
+        ));
+        let transmit = reply.transmit_time().unwrap();
+        let error = transmit.seconds_since(clock.now_timestamp()).abs();
+        assert!(error < 1.0, "peer time off by {}s", error);
+
+        shutdown.store(true, Ordering::Relaxed);
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn test_client_table_limits_rate_and_forgets_least_recent() {
+        let limit = RateLimit {
+            interval: Duration::from_secs(2),
+            burst: 3,
+        };
+        let mut table = ClientTable::new(Some(limit), 2);
+        let start = Instant::now();
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+        let admitted: Vec<bool> = (0..5).map(|_| table.admit(a, start)).collect();
+        assert_eq!(admitted, [true, true, true, false, false]);
+        // One request's worth after one interval, not a whole burst
+        let later = start + Duration::from_secs(2);
+        assert!(table.admit(a, later));
+        assert!(!table.admit(a, later));
+
+        assert!(table.admit(b, later + Duration::from_secs(1)));
+        let records = table.records();
+        assert_eq!(records[0].addr, b);
+        assert_eq!((records[1].requests, records[1].rate_limited), (7, 3));
+
+        // A third client pushes out the one seen least recently
+        table.admit("192.0.2.3".parse().unwrap(), later + Duration::from_secs(2));
+        let addrs: Vec<IpAddr> = table.records().iter().map(|r| r.addr).collect();
+        assert_eq!(addrs, ["192.0.2.3".parse::<IpAddr>().unwrap(), b]);
+
+        table.configure(None, 1);
+        assert_eq!(table.records().len(), 1);
+        assert!((0..20).all(|_| table.admit(a, later)));
+
+        assert_eq!(
+            "0.5:4".parse(),
+            Ok(RateLimit {
+                interval: Duration::from_millis(500),
+                burst: 4
+            })
+        );
+        assert_eq!(
+            "2".parse::<RateLimit>().map(|r| r.to_string()),
+            Ok("2:8".to_string())
+        );
+        for bad in ["0", "-1:8", "2:0", "fast"] {
+            assert!(bad.parse::<RateLimit>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_responder_sends_rate_kiss_of_death() {
+        let config = ClockConfig::new()
+            .with_servers(Vec::new())
+            .with_fallback_policy(FallbackPolicy::SystemClock)
+            .with_rate_limit(Some(RateLimit {
+                interval: Duration::from_secs(60),
+                burst: 2,
+            }));
+        let clock = Clock::with_config(config);
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let responder = spawn_responder(socket, clock.handle(), shutdown.clone()).unwrap();
+
+        let mut transport = UdpTransport::default();
+        for _ in 0..2 {
+            assert_eq!(exchange(&mut transport, &addr).stratum, MAX_STRATUM);
+        }
+        let measurement = sntp::query(&mut transport, &addr).unwrap();
+        assert_eq!(measurement.kiss_code(), Some("RATE"));
+
+        let clients = clock.handle().clients();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].addr, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!((clients[0].requests, clients[0].rate_limited), (3, 1));
+
+        shutdown.store(true, Ordering::Relaxed);
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn test_responder_signs_replies_to_authenticated_requests() {
+        use crate::auth::{DigestType, KeyStore, SymmetricKey};
+
+        let clock = Clock::with_config(
+            ClockConfig::new()
+                .with_servers(Vec::new())
+                .with_fallback_policy(FallbackPolicy::SystemClock),
+        );
+        let mut keys = KeyStore::new();
+        keys.add_key(SymmetricKey::new(7, DigestType::Sha1, b"secret".to_vec()));
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let keys = SharedKeyStore::new(keys);
+        let responder =
+            spawn_responder_with_keys(socket, clock.handle(), keys.clone(), shutdown.clone())
+                .unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let request = sntp::client_request();
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let mut exchange = |packet: &[u8]| {
+            client.send_to(packet, addr).unwrap();
+            client
+                .recv(&mut reply)
+                .ok()
+                .map(|len| reply[..len].to_vec())
+        };
+
+        let signed = keys.read().sign(7, &request).unwrap();
+        let answer = exchange(&signed).expect("no reply to a signed request");
+        assert_eq!(answer.len(), PACKET_LEN + 4 + 20);
+        assert_eq!(keys.read().verify(&answer), Ok(7));
+
+        // Unsigned requests get unsigned replies
+        assert_eq!(exchange(&request).map(|a| a.len()), Some(PACKET_LEN));
+
+        // Unknown keys and bad digests get no reply at all
+        let unknown = SymmetricKey::new(8, DigestType::Sha1, b"secret".to_vec());
+        assert_eq!(
+            exchange(&[&request[..], &unknown.mac(&request)].concat()),
+            None
+        );
+        let mut forged = signed.clone();
+        *forged.last_mut().unwrap() ^= 1;
+        assert_eq!(exchange(&forged), None);
+
+        shutdown.store(true, Ordering::Relaxed);
+        responder.join().unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+//! # Persisted State
+//!
+//! Stores the last NTP-verified time and the measured oscillator drift on disk, so a
+//! restarted clock (for example on a board without a real-time clock) can start from a
+//! plausible time before the network is up.
+//!
+//! The file holds one `key=value` pair per line:
+//!
+//! ```text
+//! time=2026-02-03T06:50:57.250+00:00
+//! drift_ppm=12.5
+//! ```
+
+use crate::Timestamp;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// Last-known-good clock state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PersistedState {
+    /// Last NTP-verified time
+    pub time: Timestamp,
+    /// Measured frequency error of the local clock in ppm (positive when it runs slow)
+    pub drift_ppm: f64,
+}
+
+impl PersistedState {
+    /// Estimates the current time given how long the local clock says has passed since
+    /// the state was saved.
+    ///
+    /// The caller passes a lower bound on the elapsed time (such as time since boot), so the
+    /// result errs on the side of being early rather than jumping past the true time.
+    pub fn advance(&self, elapsed: Duration) -> Timestamp {
+        self.time + crate::sntp::correct_for_drift(elapsed, self.drift_ppm)
+    }
+}
+
+/// Reads state previously written by [`save_state`]
+pub fn load_state(path: &Path) -> io::Result<PersistedState> {
+    let contents = fs::read_to_string(path)?;
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let mut time = None;
+    let mut drift_ppm = 0.0;
+    for line in contents.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid(format!("malformed line '{}'", line)))?;
+        match key.trim() {
+            "time" => {
+                time = Some(Timestamp::parse_rfc3339(value.trim()).map_err(invalid)?);
+            }
+            "drift_ppm" => {
+                drift_ppm = value
+                    .trim()
+                    .parse()
+                    .map_err(|e| invalid(format!("invalid drift: {}", e)))?;
+            }
+            // Unknown keys are ignored so newer files can be read by older versions
+            _ => {}
+        }
+    }
+
+    let time = time.ok_or_else(|| invalid("missing time".to_string()))?;
+    Ok(PersistedState { time, drift_ppm })
+}
+
+/// Atomically writes `state` to `path`
+pub fn save_state(path: &Path, state: &PersistedState) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(
+        &tmp,
+        format!(
+            "time={}\ndrift_ppm={}\n",
+            state.time.to_rfc3339(),
+            state.drift_ppm
+        ),
+    )?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("clock-ntp-persist-{}", std::process::id()));
+        let state = PersistedState {
+            time: "2026-02-03T06:50:57Z".parse().unwrap(),
+            drift_ppm: -12.5,
+        };
+
+        save_state(&path, &state).unwrap();
+        assert_eq!(load_state(&path).unwrap(), state);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_rejects_garbage() {
+        let path = std::env::temp_dir().join(format!("clock-ntp-garbage-{}", std::process::id()));
+        fs::write(&path, "not a time").unwrap();
+        assert_eq!(
+            load_state(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_advance_applies_drift() {
+        let state = PersistedState {
+            time: "2026-02-03T00:00:00Z".parse().unwrap(),
+            drift_ppm: 100.0,
+        };
+        let advanced = state.advance(Duration::from_secs(10_000));
+        assert_eq!(advanced, state.time + Duration::from_secs(10_001));
+    }
+}
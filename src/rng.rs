@@ -0,0 +1,62 @@
+//! # Non-Cryptographic Random Numbers
+//!
+//! A small seeded generator for the random bits of [UUIDs](crate::ids) and the faults of
+//! the [fault injector](crate::faults). Anything an attacker must not predict, such as
+//! request nonces and source ports, comes from the operating system instead.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// The SplitMix64 generator: small, fast, and the same sequence for the same seed
+#[derive(Debug)]
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    #[cfg_attr(not(feature = "simulation"), allow(dead_code))]
+    pub(crate) fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+
+    /// Seeded from the per-process random keys of [`RandomState`] and the current time
+    #[cfg_attr(not(feature = "ids"), allow(dead_code))]
+    pub(crate) fn from_entropy() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(crate::Timestamp::now().unix_nanos() as u128);
+        SplitMix64(hasher.finish())
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    #[cfg_attr(not(feature = "simulation"), allow(dead_code))]
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// True with the given probability
+    #[cfg_attr(not(feature = "simulation"), allow(dead_code))]
+    pub(crate) fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splitmix64_known_answers() {
+        let mut rng = SplitMix64::new(0);
+        assert_eq!(rng.next_u64(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(rng.next_u64(), 0x6e78_9e6a_a1b9_65f4);
+        assert!((0..1000)
+            .map(|_| rng.next_f64())
+            .all(|x| (0.0..1.0).contains(&x)));
+    }
+}
@@ -0,0 +1,261 @@
+//! # Hardware RTC
+//!
+//! ## Cross-check
+//!
+//! A battery-backed real-time clock keeps roughly the right time through power cycles, so
+//! an NTP time far away from it points at a broken or compromised server, or a dead RTC
+//! battery. With [`ClockConfig::rtc_policy`](crate::ClockConfig::rtc_policy) set, the clock
+//! reads the RTC when it starts and on every sync and compares it with the NTP time. When
+//! they disagree by more than
+//! [`ClockConfig::rtc_tolerance`](crate::ClockConfig::rtc_tolerance), the conflict is
+//! reported as an
+//! [`AnomalyKind::RtcDisagreement`](crate::anomaly::AnomalyKind::RtcDisagreement),
+//! recorded in the [audit log](crate::audit), and resolved by the [`RtcPolicy`]. A conflict
+//! found at startup, before an event subscriber can be attached, is only logged and audited
+//! in the log named by [`ClockConfig::audit_log`](crate::ClockConfig::audit_log).
+//!
+//! ## Write-back
+//!
+//! With [`ClockConfig::rtc_write_interval`](crate::ClockConfig::rtc_write_interval) set,
+//! the clock programs the RTC with its disciplined time that often while it is healthy,
+//! like the kernel's 11-minute mode, so that an offline device starts its next boot close
+//! to the right time. The write waits for the start of a second, as `hwclock` does, since
+//! the RTC only keeps whole seconds.
+//!
+//! The RTC is read and set with the `RTC_RD_TIME` and `RTC_SET_TIME` ioctls on Linux, which
+//! needs `CAP_SYS_TIME` to set it, and is assumed to keep UTC, as `timedatectl` sets it up
+//! by default. Elsewhere, or when the device cannot be read, the check is skipped and
+//! writes fail.
+
+use crate::Timestamp;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The RTC read unless configured otherwise
+pub const DEFAULT_RTC_DEVICE: &str = "/dev/rtc0";
+
+/// Disagreement between NTP and the RTC tolerated unless configured otherwise
+pub const DEFAULT_RTC_TOLERANCE: Duration = Duration::from_secs(3600);
+
+/// How often the command-line `--rtc-write` programs the RTC unless given an interval: the
+/// period of the kernel's 11-minute mode
+pub const DEFAULT_RTC_WRITE_INTERVAL: Duration = Duration::from_secs(660);
+
+/// What to do when NTP and the RTC disagree by more than the tolerance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcPolicy {
+    /// Use the NTP time anyway; the conflict is only reported
+    TrustNtp,
+    /// Use the RTC's time instead of the NTP time
+    TrustRtc,
+    /// Use neither: the sync fails and the clock keeps its time until they agree again
+    Hold,
+}
+
+impl FromStr for RtcPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "trust-ntp" => Ok(RtcPolicy::TrustNtp),
+            "trust-rtc" => Ok(RtcPolicy::TrustRtc),
+            "hold" => Ok(RtcPolicy::Hold),
+            other => Err(format!(
+                "unknown RTC policy '{}', expected trust-ntp, trust-rtc, or hold",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RtcPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RtcPolicy::TrustNtp => "trust-ntp",
+            RtcPolicy::TrustRtc => "trust-rtc",
+            RtcPolicy::Hold => "hold",
+        })
+    }
+}
+
+/// The cross-check a clock applies to its NTP samples
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtcCheck {
+    pub device: PathBuf,
+    pub tolerance: Duration,
+    pub policy: RtcPolicy,
+}
+
+impl RtcCheck {
+    /// Reads the RTC and returns its time if it disagrees with `ntp` by more than the
+    /// tolerance
+    pub fn conflict(&self, ntp: Timestamp) -> io::Result<Option<Timestamp>> {
+        let rtc = read(&self.device)?;
+        Ok(disagrees(ntp, rtc, self.tolerance).then_some(rtc))
+    }
+}
+
+/// Where and how often a clock programs the RTC with its disciplined time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtcWrite {
+    pub device: PathBuf,
+    pub interval: Duration,
+}
+
+/// Whether `ntp` and `rtc` are further than `tolerance` apart
+fn disagrees(ntp: Timestamp, rtc: Timestamp, tolerance: Duration) -> bool {
+    rtc.seconds_since(ntp).abs() > tolerance.as_secs_f64()
+}
+
+/// The layout of the kernel's `struct rtc_time`
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct RtcTime {
+    tm_sec: libc::c_int,
+    tm_min: libc::c_int,
+    tm_hour: libc::c_int,
+    tm_mday: libc::c_int,
+    tm_mon: libc::c_int,
+    tm_year: libc::c_int,
+    tm_wday: libc::c_int,
+    tm_yday: libc::c_int,
+    tm_isdst: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+impl RtcTime {
+    fn new(time: Timestamp) -> Self {
+        let (year, month, day, secs) = time.to_civil();
+        let days = time.unix_secs().div_euclid(86_400);
+        RtcTime {
+            tm_sec: (secs % 60) as libc::c_int,
+            tm_min: (secs / 60 % 60) as libc::c_int,
+            tm_hour: (secs / 3600) as libc::c_int,
+            tm_mday: day as libc::c_int,
+            tm_mon: month as libc::c_int - 1,
+            tm_year: (year - 1900) as libc::c_int,
+            // 1970-01-01 was a Thursday
+            tm_wday: (days + 4).rem_euclid(7) as libc::c_int,
+            tm_yday: (days - crate::timestamp::days_from_civil(year, 1, 1)) as libc::c_int,
+            tm_isdst: 0,
+        }
+    }
+
+    fn timestamp(&self) -> io::Result<Timestamp> {
+        let invalid =
+            || io::Error::new(io::ErrorKind::InvalidData, "the RTC holds an invalid time");
+        let month = u32::try_from(self.tm_mon + 1).map_err(|_| invalid())?;
+        let day = u32::try_from(self.tm_mday).map_err(|_| invalid())?;
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return Err(invalid());
+        }
+        let days = crate::timestamp::days_from_civil(i64::from(self.tm_year) + 1900, month, day);
+        let secs =
+            i64::from(self.tm_hour) * 3600 + i64::from(self.tm_min) * 60 + i64::from(self.tm_sec);
+        Ok(Timestamp::from_unix_secs(days * 86_400 + secs))
+    }
+}
+
+#[cfg(target_os = "linux")]
+const RTC_RD_TIME: libc::Ioctl = libc::_IOR::<RtcTime>(b'p' as u32, 0x09);
+#[cfg(target_os = "linux")]
+const RTC_SET_TIME: libc::Ioctl = libc::_IOW::<RtcTime>(b'p' as u32, 0x0a);
+
+/// Reads the time of the RTC at `device`, to the second
+#[cfg(target_os = "linux")]
+pub fn read(device: &Path) -> io::Result<Timestamp> {
+    use std::os::fd::AsRawFd;
+
+    let file = std::fs::File::open(device)?;
+    let mut tm = RtcTime::default();
+    // SAFETY: `tm` has the layout of `struct rtc_time` and outlives the call
+    if unsafe { libc::ioctl(file.as_raw_fd(), RTC_RD_TIME, &mut tm) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    tm.timestamp()
+}
+
+/// Sets the RTC at `device` to `time`, dropping the fraction of a second
+#[cfg(target_os = "linux")]
+pub fn write(device: &Path, time: Timestamp) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let file = std::fs::File::open(device)?;
+    let tm = RtcTime::new(time);
+    // SAFETY: `tm` has the layout of `struct rtc_time` and outlives the call
+    if unsafe { libc::ioctl(file.as_raw_fd(), RTC_SET_TIME, &tm) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reads the time of the RTC at `device`; only supported on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn read(device: &Path) -> io::Result<Timestamp> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("cannot read the RTC {} on this platform", device.display()),
+    ))
+}
+
+/// Sets the RTC at `device` to `time`; only supported on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn write(device: &Path, _time: Timestamp) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("cannot set the RTC {} on this platform", device.display()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disagreement_beyond_tolerance() {
+        let ntp: Timestamp = "2026-03-01T12:00:00Z".parse().unwrap();
+        let tolerance = Duration::from_secs(60);
+        assert!(!disagrees(ntp, ntp + Duration::from_secs(59), tolerance));
+        assert!(!disagrees(ntp, ntp - Duration::from_secs(60), tolerance));
+        assert!(disagrees(ntp, ntp - Duration::from_secs(61), tolerance));
+        let year_2000: Timestamp = "2000-01-01T00:00:00Z".parse().unwrap();
+        assert!(disagrees(ntp, year_2000, tolerance));
+
+        for policy in [RtcPolicy::TrustNtp, RtcPolicy::TrustRtc, RtcPolicy::Hold] {
+            assert_eq!(policy.to_string().parse(), Ok(policy));
+        }
+        assert!("trust-gps".parse::<RtcPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_missing_device_is_an_error() {
+        let check = RtcCheck {
+            device: PathBuf::from("/nonexistent/rtc"),
+            tolerance: DEFAULT_RTC_TOLERANCE,
+            policy: RtcPolicy::Hold,
+        };
+        assert!(check.conflict(Timestamp::now()).is_err());
+        assert!(write(&check.device, Timestamp::now()).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_rtc_time_round_trip() {
+        let time: Timestamp = "2028-02-29T23:59:58Z".parse().unwrap();
+        let tm = RtcTime::new(time);
+        assert_eq!((tm.tm_year, tm.tm_mon, tm.tm_mday), (128, 1, 29));
+        assert_eq!((tm.tm_hour, tm.tm_min, tm.tm_sec), (23, 59, 58));
+        assert_eq!((tm.tm_wday, tm.tm_yday), (2, 59));
+        assert_eq!(tm.timestamp().unwrap(), time);
+        assert_eq!(
+            RtcTime::new(time.add_nanos(900_000_000))
+                .timestamp()
+                .unwrap(),
+            time
+        );
+    }
+}
@@ -0,0 +1,382 @@
+//! # Scheduling Against NTP Time
+//!
+//! [`sleep_until`](crate::Clock::sleep_until) and [`Interval`] wait for instants on the
+//! synchronized clock rather than the system clock, so jobs fire at the true wall-clock
+//! time even if the system clock is wrong.
+//!
+//! Waits are re-evaluated against the clock at least every [`MAX_SLEEP_SLICE`], and the async
+//! versions also wake whenever the clock is stepped, so corrections made while waiting move
+//! the wake-up time with them.
+//!
+//! A [`Scheduler`] runs callbacks at absolute times on its own thread.
+
+use crate::engine::ClockShared;
+use crate::lock::{self, MutexExt};
+use crate::logging::clock_log;
+use crate::{ClockHandle, Timestamp};
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Longest the blocking waits sleep before re-reading the clock
+pub const MAX_SLEEP_SLICE: Duration = Duration::from_secs(1);
+
+/// Time left until `target` on the clock, or `None` if it has passed
+fn remaining(shared: &ClockShared, target: Timestamp) -> Option<Duration> {
+    let nanos = target.nanos_since(shared.get_current_time());
+    (nanos > 0).then(|| Duration::from_nanos(nanos.min(u64::MAX as i128) as u64))
+}
+
+/// Blocks until the clock reads `target` or later
+pub(crate) fn sleep_until(shared: &ClockShared, target: Timestamp) {
+    while let Some(left) = remaining(shared, target) {
+        std::thread::sleep(left.min(MAX_SLEEP_SLICE));
+    }
+}
+
+/// Waits until the clock reads `target` or later, waking early whenever the clock is stepped
+#[cfg(feature = "tokio")]
+pub(crate) async fn sleep_until_async(shared: &ClockShared, target: Timestamp) {
+    let mut snapshots = shared.subscribe();
+    while let Some(left) = remaining(shared, target) {
+        let slice = left.min(MAX_SLEEP_SLICE);
+        if let Ok(Err(_)) = tokio::time::timeout(slice, snapshots.changed()).await {
+            // No more snapshots will be published; fall back to plain sleeping
+            tokio::time::sleep(slice).await;
+        }
+    }
+}
+
+/// Ticks at `start`, `start + period`, `start + 2 * period`, ... on a clock.
+///
+/// Created with [`Clock::interval_at`](crate::Clock::interval_at). If ticks are missed,
+/// because the caller was busy or the clock stepped forwards, the next tick fires immediately
+/// and the rest are skipped, keeping later ticks aligned to the original schedule.
+pub struct Interval {
+    clock: ClockHandle,
+    next: Timestamp,
+    period: Duration,
+}
+
+impl Interval {
+    /// Creates an interval on `clock`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn new(clock: ClockHandle, start: Timestamp, period: Duration) -> Self {
+        assert!(!period.is_zero(), "interval period must be non-zero");
+        Interval {
+            clock,
+            next: start,
+            period,
+        }
+    }
+
+    /// The scheduled instant of the next tick
+    pub fn next_tick(&self) -> Timestamp {
+        self.next
+    }
+
+    /// The time between ticks
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Blocks until the next tick and returns its scheduled instant
+    pub fn tick(&mut self) -> Timestamp {
+        let scheduled = self.next;
+        self.clock.sleep_until(scheduled);
+        self.advance();
+        scheduled
+    }
+
+    /// Async version of [`tick`](Self::tick)
+    #[cfg(feature = "tokio")]
+    pub async fn tick_async(&mut self) -> Timestamp {
+        let scheduled = self.next;
+        self.clock.sleep_until_async(scheduled).await;
+        self.advance();
+        scheduled
+    }
+
+    /// Moves to the first scheduled instant after now
+    fn advance(&mut self) {
+        self.next = next_occurrence(self.next, self.period, self.clock.now_timestamp());
+    }
+}
+
+/// The first instant of the schedule `scheduled + n * period` (n ≥ 1) after `now`
+fn next_occurrence(scheduled: Timestamp, period: Duration, now: Timestamp) -> Timestamp {
+    let period = period.as_nanos() as i128;
+    let late = now.nanos_since(scheduled);
+    let skipped = late.div_euclid(period).max(0) + 1;
+    scheduled.add_nanos(skipped * period)
+}
+
+/// Identifies a job registered with a [`Scheduler`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JobId(u64);
+
+enum Task {
+    Once(Box<dyn FnOnce() + Send>),
+    Every(Box<dyn FnMut() + Send>, Duration),
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    /// Pending jobs, keyed by due time and then registration order
+    jobs: BTreeMap<(Timestamp, JobId), Task>,
+    next_id: u64,
+    /// The job whose callback is running, and whether it was cancelled meanwhile
+    running: Option<(JobId, bool)>,
+    stop: bool,
+}
+
+struct SchedulerShared {
+    clock: ClockHandle,
+    state: Mutex<SchedulerState>,
+    wake: Condvar,
+}
+
+impl SchedulerShared {
+    fn run(&self) {
+        let mut state = self.state.lock_or_recover();
+        while !state.stop {
+            let Some((&(due, id), _)) = state.jobs.first_key_value() else {
+                state = lock::recover(self.wake.wait(state));
+                continue;
+            };
+            let now = self.clock.now_timestamp();
+            if due > now {
+                // Re-read the clock at least every slice so steps move the wake-up time
+                let left = Duration::from_nanos(due.nanos_since(now).min(u64::MAX as i128) as u64);
+                state = lock::recover(self.wake.wait_timeout(state, left.min(MAX_SLEEP_SLICE))).0;
+                continue;
+            }
+
+            let task = state.jobs.remove(&(due, id)).unwrap();
+            state.running = Some((id, false));
+            drop(state);
+            let task = match task {
+                Task::Once(callback) => {
+                    Self::call(id, callback);
+                    None
+                }
+                Task::Every(mut callback, period) => {
+                    Self::call(id, &mut callback);
+                    Some((Task::Every(callback, period), period))
+                }
+            };
+            state = self.state.lock_or_recover();
+            let cancelled = state.running.take().is_some_and(|(_, cancelled)| cancelled);
+            if let (Some((task, period)), false) = (task, cancelled) {
+                let next = next_occurrence(due, period, self.clock.now_timestamp());
+                state.jobs.insert((next, id), task);
+            }
+        }
+    }
+
+    /// Runs a callback, logging rather than propagating a panic so other jobs keep running
+    fn call(id: JobId, callback: impl FnOnce()) {
+        if panic::catch_unwind(AssertUnwindSafe(callback)).is_err() {
+            clock_log!(Error, Worker, "Scheduled job {:?} panicked", id);
+        }
+    }
+}
+
+/// Runs callbacks at absolute times on a clock.
+///
+/// Due times are read from the synchronized clock, so a job scheduled for 12:00:00 UTC runs
+/// when the clock reads 12:00:00 even if the clock is stepped in the meantime. Recurring jobs
+/// stay aligned to their original schedule: occurrences missed because of a forward step or a
+/// slow callback are collapsed into one immediate run.
+///
+/// Callbacks run one at a time on the scheduler's thread, which is stopped when the scheduler
+/// is dropped. Jobs still pending at that point are discarded.
+pub struct Scheduler {
+    shared: Arc<SchedulerShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler on `clock` and starts its thread
+    pub fn new(clock: ClockHandle) -> Self {
+        let shared = Arc::new(SchedulerShared {
+            clock,
+            state: Mutex::new(SchedulerState::default()),
+            wake: Condvar::new(),
+        });
+        let thread_shared = Arc::clone(&shared);
+        let thread = std::thread::spawn(move || thread_shared.run());
+        Scheduler {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Runs `callback` once when the clock reads `at`, or as soon as possible if that has
+    /// passed
+    pub fn schedule_at(
+        &self,
+        at: impl Into<Timestamp>,
+        callback: impl FnOnce() + Send + 'static,
+    ) -> JobId {
+        self.insert(at.into(), Task::Once(Box::new(callback)))
+    }
+
+    /// Runs `callback` at `start` and then every `period`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn schedule_every(
+        &self,
+        start: impl Into<Timestamp>,
+        period: Duration,
+        callback: impl FnMut() + Send + 'static,
+    ) -> JobId {
+        assert!(!period.is_zero(), "schedule period must be non-zero");
+        self.insert(start.into(), Task::Every(Box::new(callback), period))
+    }
+
+    /// Cancels a job. Returns whether it was still scheduled; a job cancelled from inside its
+    /// own callback does not run again.
+    pub fn cancel(&self, id: JobId) -> bool {
+        let mut state = self.shared.state.lock_or_recover();
+        if let Some((running, cancelled)) = &mut state.running {
+            if *running == id {
+                *cancelled = true;
+                return true;
+            }
+        }
+        let key = state.jobs.keys().find(|(_, job)| *job == id).copied();
+        key.is_some_and(|key| state.jobs.remove(&key).is_some())
+    }
+
+    /// Number of jobs waiting to run
+    pub fn pending(&self) -> usize {
+        self.shared.state.lock_or_recover().jobs.len()
+    }
+
+    fn insert(&self, due: Timestamp, task: Task) -> JobId {
+        let mut state = self.shared.state.lock_or_recover();
+        let id = JobId(state.next_id);
+        state.next_id += 1;
+        state.jobs.insert((due, id), task);
+        self.shared.wake.notify_all();
+        id
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.shared.state.lock_or_recover().stop = true;
+        self.shared.wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                clock_log!(Error, Worker, "Scheduler thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Clock;
+    use std::time::Instant;
+
+    fn clock() -> Clock {
+        Clock::new(Some(vec!["invalid.invalid:123".to_string()]))
+    }
+
+    #[test]
+    fn test_sleep_until_waits_for_clock_time() {
+        let clock = clock();
+        let target = clock.now_timestamp() + Duration::from_millis(50);
+        let started = Instant::now();
+        clock.sleep_until(target);
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        assert!(clock.now_timestamp() >= target);
+
+        // Past instants return immediately
+        let started = Instant::now();
+        clock.sleep_until(Timestamp::UNIX_EPOCH);
+        assert!(started.elapsed() < Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_scheduler_runs_jobs_in_order() {
+        let clock = clock();
+        let scheduler = Scheduler::new(clock.handle());
+        let (tx, rx) = std::sync::mpsc::channel();
+        let now = clock.now_timestamp();
+
+        let second = tx.clone();
+        scheduler.schedule_at(now + Duration::from_millis(60), move || {
+            second.send("second").unwrap()
+        });
+        let first = tx.clone();
+        scheduler.schedule_at(now + Duration::from_millis(20), move || {
+            first.send("first").unwrap()
+        });
+        let cancelled = scheduler.schedule_at(now + Duration::from_millis(40), move || {
+            tx.send("cancelled").unwrap()
+        });
+        assert!(scheduler.cancel(cancelled));
+        assert!(!scheduler.cancel(cancelled));
+
+        let timeout = Duration::from_secs(2);
+        assert_eq!(rx.recv_timeout(timeout), Ok("first"));
+        assert_eq!(rx.recv_timeout(timeout), Ok("second"));
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        assert!(clock.now_timestamp() >= now + Duration::from_millis(60));
+    }
+
+    #[test]
+    fn test_recurring_job_until_cancelled() {
+        let clock = clock();
+        let scheduler = Scheduler::new(clock.handle());
+        let (tx, rx) = std::sync::mpsc::channel();
+        let start = clock.now_timestamp();
+        let period = Duration::from_millis(10);
+        scheduler.schedule_every(start, period, move || tx.send(()).unwrap());
+
+        for _ in 0..3 {
+            rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        }
+        assert!(scheduler.cancel(JobId(0)));
+        // A run already in progress may still deliver once
+        while rx.recv_timeout(Duration::from_millis(50)).is_ok() {}
+        assert_eq!(scheduler.pending(), 0);
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+    }
+
+    #[test]
+    fn test_next_occurrence_stays_aligned() {
+        let start = Timestamp::from_unix_secs(100);
+        let period = Duration::from_secs(10);
+        let at = |secs| Timestamp::from_unix_secs(secs);
+        assert_eq!(next_occurrence(start, period, at(90)), at(110));
+        assert_eq!(next_occurrence(start, period, at(105)), at(110));
+        assert_eq!(next_occurrence(start, period, at(110)), at(120));
+        assert_eq!(next_occurrence(start, period, at(137)), at(140));
+    }
+
+    #[test]
+    fn test_interval_skips_missed_ticks() {
+        let clock = clock();
+        let period = Duration::from_millis(100);
+        let start = clock.now_timestamp() - Duration::from_millis(150);
+        let mut interval = clock.interval_at(start, period);
+
+        // Overdue: fires at once, then realigns to the schedule
+        assert_eq!(interval.tick(), start);
+        assert_eq!(interval.next_tick(), start + Duration::from_millis(200));
+        assert_eq!(interval.tick(), start + Duration::from_millis(200));
+        assert!(clock.now_timestamp() >= start + Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,663 @@
+//! # Server Specifications
+//!
+//! Each entry of [`ClockConfig::servers`](crate::ClockConfig::servers) is parsed into a
+//! [`ServerSpec`]: an address, optionally URL-style, followed by ntpd-style options:
+//!
+//! ```text
+//! time.google.com                      # port 123
+//! time.cloudflare.com:123 iburst prefer
+//! ntp://10.0.0.5:1123 version 4
+//! [2001:db8::1]:123 noselect
+//! nts://time.cloudflare.com            # NTS-KE port 4460
+//! nts://ntp.corp.example cafile /etc/ntp/corp-ca.pem pin sha256//y3VuAfDqt8NM8YH2cR+yc1Q0g4ttSkT6aMEVZn3Wh8M= mintls 1.3
+//! ntp://10.0.0.6 key 7
+//! 10.0.0.7 minpoll 6 maxpoll 10
+//! 10.0.0.8 xleave                      # interleaved mode
+//! ```
+//!
+//! Programmatic configuration can build the same specs with [`ServerSpec::builder`] and pass
+//! them to [`ClockConfig::with_server_specs`](crate::ClockConfig::with_server_specs).
+//!
+//! Servers are identified by their [`name`](ServerSpec::name), so `host`, `host:123`, and
+//! `ntp://host:123` are the same server in preferred lists, statistics, and logs.
+//!
+//! For NTS servers on a private PKI, `cafile` names a PEM bundle of root certificates to
+//! trust instead of the system store, each `pin` a SHA-256 hash of a subject public key
+//! (SPKI) one of which the certificates the server presents must carry, in curl's
+//! `sha256//BASE64` form, and `mintls` the lowest TLS version to accept for key
+//! establishment (1.3, as RFC 8915 requires, unless lowered to 1.2 for older servers). A
+//! path with spaces is written in double quotes, with `\"` and `\\` inside them:
+//!
+//! ```text
+//! nts://ntp.corp.example cafile "/etc/clock/Corp Root CA.pem"
+//! ```
+//!
+//! A server with a `key` is queried with requests signed with that key from
+//! [`ClockConfig::keys`](crate::ClockConfig::keys), and its replies are dropped unless they
+//! verify with it. An NTS server is queried at the NTP server its key establishment names,
+//! with requests and replies authenticated by NTS; that needs the `nts` feature, without
+//! which NTS servers are skipped rather than silently queried without authentication.
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Port of unauthenticated NTP
+pub const NTP_PORT: u16 = 123;
+
+/// Port of NTS key establishment (RFC 8915)
+pub const NTS_KE_PORT: u16 = 4460;
+
+/// NTP version sent in requests unless configured otherwise
+pub const DEFAULT_VERSION: u8 = 3;
+
+/// Range of the `minpoll` and `maxpoll` options, in log2 seconds, as in ntpd
+pub const POLL_RANGE: std::ops::RangeInclusive<u8> = 3..=17;
+
+/// The interval a `minpoll` or `maxpoll` exponent stands for
+pub fn poll_interval(exponent: u8) -> Duration {
+    Duration::from_secs(1 << exponent.min(*POLL_RANGE.end()))
+}
+
+/// A TLS protocol version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum TlsVersion {
+    Tls12,
+    /// The only version RFC 8915 allows for NTS key establishment
+    #[default]
+    Tls13,
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TlsVersion::Tls12 => "1.2",
+            TlsVersion::Tls13 => "1.3",
+        })
+    }
+}
+
+impl FromStr for TlsVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1.2" => Ok(TlsVersion::Tls12),
+            "1.3" => Ok(TlsVersion::Tls13),
+            _ => Err(format!("unsupported TLS version '{}'", s)),
+        }
+    }
+}
+
+/// How a server is spoken to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    /// Plain NTP/SNTP over UDP
+    #[default]
+    Ntp,
+    /// Network Time Security: key establishment over TLS, then authenticated NTP
+    Nts,
+}
+
+/// One configured server and its options
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerSpec {
+    pub protocol: Protocol,
+    /// Host name or IP address, without brackets
+    pub host: String,
+    pub port: u16,
+    /// NTP version sent in requests, 1 to 4
+    pub version: u8,
+    /// Symmetric key ID to authenticate with
+    pub key: Option<u32>,
+    /// Wins ties in selection, see [`ClockConfig::preferred_servers`](crate::ClockConfig::preferred_servers)
+    pub prefer: bool,
+    /// Monitored but never used, see [`ClockConfig::noselect_servers`](crate::ClockConfig::noselect_servers)
+    pub noselect: bool,
+    /// Take several samples in quick succession until the server has answered once
+    pub iburst: bool,
+    /// Ask for interleaved mode, in which the server sends the precise transmit timestamp
+    /// of its previous reply; basic mode is used if the server does not answer in kind
+    pub xleave: bool,
+    /// Shortest sync interval, in log2 seconds; the clock's single sync loop polls no
+    /// faster than the largest `min_poll` of its servers
+    pub min_poll: Option<u8>,
+    /// Longest sync interval, in log2 seconds; the clock polls at least as often as the
+    /// smallest `max_poll` of its servers
+    pub max_poll: Option<u8>,
+    /// PEM file of root certificates trusted for NTS key establishment instead of the
+    /// system's
+    pub ca_file: Option<String>,
+    /// SHA-256 hashes of subject public keys, one of which the NTS server's certificate
+    /// chain must contain; any chain the roots accept if empty
+    pub spki_pins: Vec<[u8; 32]>,
+    /// Lowest TLS version accepted for NTS key establishment
+    pub min_tls: TlsVersion,
+}
+
+impl ServerSpec {
+    /// A plain NTP server at `host` on the default port
+    pub fn new(host: impl Into<String>) -> Self {
+        ServerSpec {
+            protocol: Protocol::Ntp,
+            host: host.into(),
+            port: NTP_PORT,
+            version: DEFAULT_VERSION,
+            key: None,
+            prefer: false,
+            noselect: false,
+            iburst: false,
+            xleave: false,
+            min_poll: None,
+            max_poll: None,
+            ca_file: None,
+            spki_pins: Vec::new(),
+            min_tls: TlsVersion::default(),
+        }
+    }
+
+    /// Starts building a plain NTP server spec for `host`, a name or IP address
+    pub fn builder(host: impl Into<String>) -> ServerSpecBuilder {
+        ServerSpecBuilder {
+            spec: ServerSpec::new(host),
+            port: None,
+        }
+    }
+
+    /// The server's identity: `host:port`, with `nts://` in front for NTS servers
+    pub fn name(&self) -> String {
+        let scheme = match self.protocol {
+            Protocol::Ntp => "",
+            Protocol::Nts => "nts://",
+        };
+        if self.host.contains(':') {
+            format!("{}[{}]:{}", scheme, self.host, self.port)
+        } else {
+            format!("{}{}:{}", scheme, self.host, self.port)
+        }
+    }
+
+    /// Whether the server asks for authentication
+    pub fn is_authenticated(&self) -> bool {
+        self.protocol == Protocol::Nts || self.key.is_some()
+    }
+
+    /// Checks the options that the types alone do not constrain
+    fn validate(&self) -> Result<(), String> {
+        if self.host.is_empty() || self.host.contains(|c: char| c == '/' || c.is_whitespace()) {
+            return Err(format!("invalid host '{}'", self.host));
+        }
+        if self.port == 0 {
+            return Err("invalid port 0".to_string());
+        }
+        if !(1..=4).contains(&self.version) {
+            return Err(format!("unsupported NTP version {}", self.version));
+        }
+        if self.key == Some(0) {
+            return Err("key IDs start at 1".to_string());
+        }
+        if self.key.is_some() && self.protocol == Protocol::Nts {
+            return Err("nts:// servers authenticate with NTS, not a key".to_string());
+        }
+        for poll in [self.min_poll, self.max_poll].into_iter().flatten() {
+            if !POLL_RANGE.contains(&poll) {
+                return Err(format!(
+                    "poll exponent {} is outside {}-{}",
+                    poll,
+                    POLL_RANGE.start(),
+                    POLL_RANGE.end()
+                ));
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_poll, self.max_poll) {
+            if min > max {
+                return Err(format!("minpoll {} is above maxpoll {}", min, max));
+            }
+        }
+        let has_tls_options = self.ca_file.is_some()
+            || !self.spki_pins.is_empty()
+            || self.min_tls != TlsVersion::default();
+        if has_tls_options && self.protocol != Protocol::Nts {
+            return Err("cafile, pin, and mintls only apply to nts:// servers".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Builds a [`ServerSpec`] without going through its string form
+#[derive(Debug, Clone)]
+pub struct ServerSpecBuilder {
+    spec: ServerSpec,
+    /// Set explicitly, rather than the protocol's default
+    port: Option<u16>,
+}
+
+impl ServerSpecBuilder {
+    /// Sets the port, instead of 123 (or 4460 for NTS)
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Sets the NTP version sent in requests (1-4)
+    pub fn version(mut self, version: u8) -> Self {
+        self.spec.version = version;
+        self
+    }
+
+    /// Authenticates with the symmetric key `id`
+    pub fn key(mut self, id: u32) -> Self {
+        self.spec.key = Some(id);
+        self
+    }
+
+    /// Uses Network Time Security
+    pub fn nts(mut self) -> Self {
+        self.spec.protocol = Protocol::Nts;
+        self
+    }
+
+    /// Trusts the root certificates in the PEM file at `path` for NTS, instead of the
+    /// system's
+    pub fn ca_file(mut self, path: impl Into<String>) -> Self {
+        self.spec.ca_file = Some(path.into());
+        self
+    }
+
+    /// Accepts the NTS server only if its certificate chain contains a subject public key
+    /// with this SHA-256 hash; may be given several times, for key rollover
+    pub fn pin_spki_sha256(mut self, hash: [u8; 32]) -> Self {
+        self.spec.spki_pins.push(hash);
+        self
+    }
+
+    /// Sets the lowest TLS version accepted for NTS key establishment
+    pub fn min_tls(mut self, version: TlsVersion) -> Self {
+        self.spec.min_tls = version;
+        self
+    }
+
+    /// Sets the shortest sync interval, in log2 seconds
+    pub fn min_poll(mut self, exponent: u8) -> Self {
+        self.spec.min_poll = Some(exponent);
+        self
+    }
+
+    /// Sets the longest sync interval, in log2 seconds
+    pub fn max_poll(mut self, exponent: u8) -> Self {
+        self.spec.max_poll = Some(exponent);
+        self
+    }
+
+    /// Marks the server as preferred
+    pub fn prefer(mut self) -> Self {
+        self.spec.prefer = true;
+        self
+    }
+
+    /// Marks the server as monitored only
+    pub fn noselect(mut self) -> Self {
+        self.spec.noselect = true;
+        self
+    }
+
+    /// Samples the server several times until it first answers
+    pub fn iburst(mut self) -> Self {
+        self.spec.iburst = true;
+        self
+    }
+
+    /// Asks the server for interleaved mode
+    pub fn xleave(mut self) -> Self {
+        self.spec.xleave = true;
+        self
+    }
+
+    /// The spec, or why its options are invalid
+    pub fn build(self) -> Result<ServerSpec, String> {
+        let mut spec = self.spec;
+        spec.port = self.port.unwrap_or(match spec.protocol {
+            Protocol::Ntp => NTP_PORT,
+            Protocol::Nts => NTS_KE_PORT,
+        });
+        spec.validate()?;
+        Ok(spec)
+    }
+}
+
+/// The [`name`](ServerSpec::name) of a server string, or the string itself if it does not
+/// parse
+pub fn canonical_name(server: &str) -> String {
+    server
+        .parse::<ServerSpec>()
+        .map_or_else(|_| server.to_string(), |spec| spec.name())
+}
+
+impl FromStr for ServerSpec {
+    type Err = String;
+
+    /// Parses an address — `host`, `host:port`, `[v6]:port`, or `ntp://` / `nts://` URLs —
+    /// followed by the options `prefer`, `noselect`, `iburst`, `xleave`, `version N`,
+    /// `key ID`, `minpoll N`, and `maxpoll N`, and for NTS `cafile PATH`,
+    /// `pin sha256//BASE64`, and `mintls 1.2|1.3`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = split_words(s)?;
+        let mut words = words.iter().map(String::as_str);
+        let address = words.next().ok_or("empty server")?;
+        let (protocol, rest) = match address.split_once("://") {
+            Some(("ntp", rest)) => (Protocol::Ntp, rest),
+            Some(("nts", rest)) => (Protocol::Nts, rest),
+            Some((scheme, _)) => return Err(format!("unsupported scheme '{}://'", scheme)),
+            None => (Protocol::Ntp, address),
+        };
+        let rest = rest.strip_suffix('/').unwrap_or(rest);
+        let default_port = match protocol {
+            Protocol::Ntp => NTP_PORT,
+            Protocol::Nts => NTS_KE_PORT,
+        };
+        let (host, port) = if let Some(bracketed) = rest.strip_prefix('[') {
+            let (host, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("unclosed '[' in '{}'", address))?;
+            match after {
+                "" => (host, None),
+                _ => match after.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(format!("invalid address '{}'", address)),
+                },
+            }
+        } else if rest.matches(':').count() > 1 {
+            // A bare IPv6 address, which cannot carry a port
+            (rest, None)
+        } else {
+            match rest.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (rest, None),
+            }
+        };
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .map_err(|_| format!("invalid port '{}'", port))?,
+            None => default_port,
+        };
+
+        let mut spec = ServerSpec {
+            protocol,
+            port,
+            ..ServerSpec::new(host)
+        };
+        while let Some(option) = words.next() {
+            let mut number = |name: &str| {
+                words
+                    .next()
+                    .and_then(|n| n.parse::<u32>().ok())
+                    .ok_or_else(|| format!("'{}' needs a number", name))
+            };
+            match option {
+                "prefer" => spec.prefer = true,
+                "noselect" => spec.noselect = true,
+                "iburst" => spec.iburst = true,
+                "xleave" => spec.xleave = true,
+                "version" => spec.version = number(option)?.min(u8::MAX.into()) as u8,
+                "key" => spec.key = Some(number(option)?),
+                "minpoll" => spec.min_poll = Some(number(option)?.min(u8::MAX.into()) as u8),
+                "maxpoll" => spec.max_poll = Some(number(option)?.min(u8::MAX.into()) as u8),
+                "cafile" => {
+                    let path = words.next().ok_or("'cafile' needs a path")?;
+                    spec.ca_file = Some(path.to_string());
+                }
+                "pin" => {
+                    let pin = words.next().ok_or("'pin' needs a sha256//BASE64 hash")?;
+                    spec.spki_pins.push(parse_pin(pin)?);
+                }
+                "mintls" => {
+                    spec.min_tls = words.next().ok_or("'mintls' needs a version")?.parse()?
+                }
+                _ => return Err(format!("unknown server option '{}'", option)),
+            }
+        }
+        spec.validate()?;
+        Ok(spec)
+    }
+}
+
+/// Writes the [`name`](ServerSpec::name) followed by the options that differ from the
+/// defaults, in the form [`FromStr`] reads
+impl fmt::Display for ServerSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name())?;
+        if self.iburst {
+            f.write_str(" iburst")?;
+        }
+        if self.xleave {
+            f.write_str(" xleave")?;
+        }
+        if self.version != DEFAULT_VERSION {
+            write!(f, " version {}", self.version)?;
+        }
+        if let Some(key) = self.key {
+            write!(f, " key {}", key)?;
+        }
+        if let Some(min_poll) = self.min_poll {
+            write!(f, " minpoll {}", min_poll)?;
+        }
+        if let Some(max_poll) = self.max_poll {
+            write!(f, " maxpoll {}", max_poll)?;
+        }
+        if self.prefer {
+            f.write_str(" prefer")?;
+        }
+        if self.noselect {
+            f.write_str(" noselect")?;
+        }
+        if let Some(ca_file) = &self.ca_file {
+            write!(f, " cafile {}", quote_word(ca_file))?;
+        }
+        for pin in &self.spki_pins {
+            write!(f, " pin {}", format_pin(pin))?;
+        }
+        if self.min_tls != TlsVersion::default() {
+            write!(f, " mintls {}", self.min_tls)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a `sha256//BASE64` SPKI pin
+pub(crate) fn parse_pin(pin: &str) -> Result<[u8; 32], String> {
+    pin.strip_prefix("sha256//")
+        .and_then(|hash| BASE64_STANDARD.decode(hash).ok())
+        .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+        .ok_or_else(|| format!("invalid pin '{}'", pin))
+}
+
+/// Writes a pin in the form [`parse_pin`] reads
+pub(crate) fn format_pin(hash: &[u8; 32]) -> String {
+    format!("sha256//{}", BASE64_STANDARD.encode(hash))
+}
+
+/// Splits `s` at whitespace, reading a word that starts with `"` up to the closing `"`, with
+/// `\"` and `\\` standing for `"` and `\` inside it
+pub(crate) fn split_words(s: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = s.trim_start().chars().peekable();
+    while chars.peek().is_some() {
+        let mut word = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c @ ('"' | '\\')) => word.push(c),
+                        _ => return Err(format!("invalid escape in '{}'", s)),
+                    },
+                    Some(c) => word.push(c),
+                    None => return Err(format!("unclosed '\"' in '{}'", s)),
+                }
+            }
+            if chars.next_if(|c| !c.is_whitespace()).is_some() {
+                return Err(format!("missing space after '\"' in '{}'", s));
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+    Ok(words)
+}
+
+/// `word` as [`split_words`] reads it back: quoted if it is empty, has whitespace, or
+/// starts with `"`
+pub(crate) fn quote_word(word: &str) -> String {
+    if !word.is_empty() && !word.starts_with('"') && !word.contains(char::is_whitespace) {
+        return word.to_string();
+    }
+    let escaped = word.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_server_specs() {
+        let spec: ServerSpec = "time.google.com".parse().unwrap();
+        assert_eq!(spec, ServerSpec::new("time.google.com"));
+        assert_eq!(spec.name(), "time.google.com:123");
+
+        let spec: ServerSpec = "ntp://10.0.0.5:1123/ iburst version 4 key 7 prefer"
+            .parse()
+            .unwrap();
+        assert_eq!((spec.host.as_str(), spec.port), ("10.0.0.5", 1123));
+        assert!(spec.iburst && spec.prefer && !spec.noselect);
+        assert_eq!((spec.version, spec.key), (4, Some(7)));
+        assert!(spec.is_authenticated());
+        assert_eq!(spec.to_string().parse::<ServerSpec>(), Ok(spec.clone()));
+        assert_eq!(
+            spec.to_string(),
+            "10.0.0.5:1123 iburst version 4 key 7 prefer"
+        );
+
+        let spec: ServerSpec = "nts://time.cloudflare.com".parse().unwrap();
+        assert_eq!(spec.protocol, Protocol::Nts);
+        assert_eq!(spec.name(), "nts://time.cloudflare.com:4460");
+
+        assert_eq!(canonical_name("[2001:db8::1]:123"), "[2001:db8::1]:123");
+        assert_eq!(canonical_name("2001:db8::1"), "[2001:db8::1]:123");
+        assert_eq!(canonical_name("ntp://host:123"), canonical_name("host"));
+    }
+
+    #[test]
+    fn test_builder_matches_parsed_spec() {
+        let built = ServerSpec::builder("10.0.0.5")
+            .port(1123)
+            .version(4)
+            .key(7)
+            .min_poll(6)
+            .max_poll(10)
+            .iburst()
+            .xleave()
+            .prefer()
+            .build()
+            .unwrap();
+        let parsed =
+            "ntp://10.0.0.5:1123 iburst xleave version 4 key 7 minpoll 6 maxpoll 10 prefer";
+        assert_eq!(Ok(built.clone()), parsed.parse());
+        assert_eq!(built.to_string().parse(), Ok(built));
+
+        let nts = ServerSpec::builder("time.cloudflare.com")
+            .nts()
+            .build()
+            .unwrap();
+        assert_eq!(nts, "nts://time.cloudflare.com".parse().unwrap());
+        assert_eq!(poll_interval(6), Duration::from_secs(64));
+
+        let pinned = ServerSpec::builder("ntp.corp.example")
+            .nts()
+            .ca_file("/etc/ntp/corp-ca.pem")
+            .pin_spki_sha256([0xcb; 32])
+            .pin_spki_sha256([0; 32])
+            .min_tls(TlsVersion::Tls12)
+            .build()
+            .unwrap();
+        assert_eq!(
+            pinned.to_string(),
+            "nts://ntp.corp.example:4460 cafile /etc/ntp/corp-ca.pem \
+             pin sha256//y8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLy8s= \
+             pin sha256//AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA= mintls 1.2"
+        );
+        assert_eq!(pinned.to_string().parse(), Ok(pinned));
+        for path in [
+            "/etc/clock/Corp Root CA.pem",
+            r"C:\ntp\ca.pem",
+            r#"C:\Program Files\clock\"ca".pem"#,
+            "\"quoted\"",
+            "",
+        ] {
+            let spec = ServerSpec::builder("host")
+                .nts()
+                .ca_file(path)
+                .build()
+                .unwrap();
+            assert_eq!(spec.to_string().parse(), Ok(spec), "{}", path);
+        }
+        assert_eq!(
+            ServerSpec::builder("host")
+                .nts()
+                .ca_file("/etc/clock/Corp Root CA.pem")
+                .build()
+                .unwrap()
+                .to_string(),
+            r#"nts://host:4460 cafile "/etc/clock/Corp Root CA.pem""#
+        );
+        assert!(ServerSpec::builder("host")
+            .ca_file("ca.pem")
+            .build()
+            .is_err());
+
+        assert!(ServerSpec::builder("").build().is_err());
+        assert!(ServerSpec::builder("host").version(5).build().is_err());
+        assert!(ServerSpec::builder("host").min_poll(18).build().is_err());
+        assert!(ServerSpec::builder("host")
+            .noselect()
+            .port(0)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_specs() {
+        for bad in [
+            "",
+            "http://host",
+            "host:0",
+            "host:ntp",
+            "[::1",
+            "ntp://",
+            "host burst",
+            "host version 5",
+            "host version",
+            "host key 0",
+            "host minpoll 2",
+            "host minpoll 10 maxpoll 6",
+            "host cafile ca.pem",
+            "nts://host cafile",
+            "nts://host cafile \"ca.pem",
+            "nts://host cafile \"ca.pem\"mintls 1.2",
+            "nts://host cafile \"ca\\.pem\"",
+            "nts://host pin y8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLy8s=",
+            "nts://host pin sha256//y8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLy8vLyw==",
+            "nts://host pin sha256//y8vLy8vLy8vLy8vLy8vL=8vLy8vLy8vLy8vLy8vLy8s=",
+            "nts://host mintls 1.1",
+            "nts://host key 1",
+        ] {
+            assert!(bad.parse::<ServerSpec>().is_err(), "{}", bad);
+        }
+    }
+}
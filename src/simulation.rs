@@ -0,0 +1,324 @@
+//! # Simulated Time
+//!
+//! Integration tests of code built on a [`Clock`](crate::Clock) often need to see what
+//! happens hours into a run: many poll cycles, a source going quiet long enough to go stale
+//! or enter orphan mode, the clock free-running in holdover. A [`VirtualTimeline`] lets
+//! them fast-forward instead of waiting. Once installed with
+//! [`Clock::set_virtual_timeline`](crate::Clock::set_virtual_timeline), it measures the time
+//! elapsed since the last sync and paces the sync loop, the age of the last sync, and
+//! orphan mode.
+//!
+//! A new timeline stands still until it is [`advance`](VirtualTimeline::advance)d, or runs
+//! at a multiple of real time after [`set_rate`](VirtualTimeline::set_rate):
+//!
+//! ```no_run
+//! use clock::{Clock, ClockConfig, VirtualTimeline};
+//! use std::time::Duration;
+//!
+//! let clock = Clock::with_config(ClockConfig::new());
+//! let timeline = VirtualTimeline::new();
+//! clock.set_virtual_timeline(timeline.clone());
+//! clock.start(64, Default::default());
+//! timeline.advance(Duration::from_secs(3600)); // the sync loop catches up on the hour
+//! timeline.set_rate(60.0); // then runs a minute per second
+//! ```
+//!
+//! Queries still take real time, and kiss-o'-death hold-offs and query budgets are kept in
+//! real time, since they protect real servers.
+
+use crate::elapsed::ElapsedSource;
+use crate::lock::MutexExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Longest real wait of the sync loop on a virtual timeline, so that it notices an
+/// [`advance`](VirtualTimeline::advance) quickly
+pub(crate) const SIMULATED_TICK: Duration = Duration::from_millis(2);
+
+/// Virtual time as of a real instant, and how fast it runs from there
+#[derive(Debug)]
+struct State {
+    virtual_elapsed: Duration,
+    real_anchor: Instant,
+    rate: f64,
+}
+
+/// A timeline that moves when told to, or at a chosen multiple of real time. Clones share
+/// the timeline.
+#[derive(Debug, Clone)]
+pub struct VirtualTimeline {
+    origin: Instant,
+    state: Arc<Mutex<State>>,
+}
+
+impl VirtualTimeline {
+    /// Creates a timeline standing still at zero
+    pub fn new() -> Self {
+        let now = Instant::now();
+        VirtualTimeline {
+            origin: now,
+            state: Arc::new(Mutex::new(State {
+                virtual_elapsed: Duration::ZERO,
+                real_anchor: now,
+                rate: 0.0,
+            })),
+        }
+    }
+
+    /// Virtual time since the timeline was created
+    pub fn now(&self) -> Duration {
+        let state = self.state.lock_or_recover();
+        state.virtual_elapsed + state.real_anchor.elapsed().mul_f64(state.rate)
+    }
+
+    /// Moves the timeline forward by `by` at once
+    pub fn advance(&self, by: Duration) {
+        self.state.lock_or_recover().virtual_elapsed += by;
+    }
+
+    /// Runs the timeline at `rate` times real time from now on, or stops it with 0.
+    /// Negative rates are treated as 0, as time never runs backwards.
+    pub fn set_rate(&self, rate: f64) {
+        let mut state = self.state.lock_or_recover();
+        let now = Instant::now();
+        let ran = now.duration_since(state.real_anchor).mul_f64(state.rate);
+        state.virtual_elapsed += ran;
+        state.real_anchor = now;
+        state.rate = rate.max(0.0);
+    }
+
+    /// The current multiple of real time
+    pub fn rate(&self) -> f64 {
+        self.state.lock_or_recover().rate
+    }
+
+    /// The virtual time as an `Instant`, for code that measures ages with them
+    pub(crate) fn instant(&self) -> Instant {
+        self.origin + self.now()
+    }
+}
+
+impl Default for VirtualTimeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ElapsedSource for VirtualTimeline {
+    fn now(&self) -> Duration {
+        VirtualTimeline::now(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sntp::{self, ServerState, Transport, PACKET_LEN};
+    use crate::tests::spawn_fake_server;
+    use crate::transport::TransportFactory;
+    use crate::{Clock, ClockConfig, ClockSource, Health, ManualClock, SmoothingFilter, Timestamp};
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_timeline_advances_and_runs_at_rate() {
+        let timeline = VirtualTimeline::new();
+        assert_eq!(timeline.now(), Duration::ZERO);
+        timeline.advance(Duration::from_secs(3600));
+        assert_eq!(timeline.now(), Duration::from_secs(3600));
+
+        timeline.set_rate(1000.0);
+        std::thread::sleep(Duration::from_millis(20));
+        timeline.set_rate(0.0);
+        let ran = timeline.now() - Duration::from_secs(3600);
+        assert!(ran >= Duration::from_secs(20), "{:?}", ran);
+        assert_eq!(timeline.now(), timeline.now());
+    }
+
+    #[test]
+    fn test_sync_loop_fast_forwards() {
+        // One reply for the initial sync, then one per simulated hour
+        let server = spawn_fake_server(Timestamp::now(), 4);
+        let clock = Clock::with_config(ClockConfig::new().with_servers(vec![server]));
+        let timeline = VirtualTimeline::new();
+        clock.set_virtual_timeline(timeline.clone());
+        clock.start(3600, Arc::new(AtomicBool::new(false)));
+
+        let attempts_reach = |n: u64| {
+            (0..500).any(|_| {
+                std::thread::sleep(Duration::from_millis(10));
+                clock.get_stats().total_attempts >= n
+            })
+        };
+        assert!(attempts_reach(1));
+        for hour in 2..=3 {
+            timeline.advance(Duration::from_secs(3600));
+            assert!(attempts_reach(hour), "no poll in hour {}", hour);
+        }
+        assert_eq!(clock.get_stats().successful_syncs, 3);
+        assert!(clock.health().is_healthy());
+
+        // The server has stopped answering; four more hours make the last sync stale
+        timeline.advance(Duration::from_secs(4 * 3600));
+        assert!(matches!(clock.health(), Health::Stale { age } if age.as_secs() >= 4 * 3600));
+        clock.stop();
+    }
+
+    #[test]
+    fn test_slews_are_capped_at_max_slew_ppm() {
+        let now = Timestamp::now();
+        let clock = Clock::new(Some(vec![spawn_fake_server(now, 1)]));
+        let timeline = VirtualTimeline::new();
+        clock.set_virtual_timeline(timeline.clone());
+
+        // A minute later the server is 10 s ahead, far more than can be slewed away at once
+        let ahead = spawn_fake_server(now + Duration::from_secs(64 + 10), 1);
+        let config = ClockConfig::new()
+            .with_servers(vec![ahead])
+            .with_smoothing(Some(SmoothingFilter::Pi { kp: 0.5, ki: 0.05 }))
+            .with_max_slew_ppm(50.0);
+        clock.reconfigure(&config);
+        timeline.advance(Duration::from_secs(64));
+        clock.shared.update_latest_time();
+
+        let slew_rate = || {
+            let before = clock.now_timestamp();
+            timeline.advance(Duration::from_secs(1000));
+            (clock.now_timestamp().seconds_since(before) - 1000.0) / 1000.0
+        };
+        let rate = slew_rate();
+        assert!(rate > 49e-6 && rate <= 50e-6 + 1e-9, "{}", rate);
+
+        // Lowering the cap slows the slew in progress down at once
+        clock.reconfigure(&config.with_max_slew_ppm(10.0));
+        let rate = slew_rate();
+        assert!(rate > 9e-6 && rate <= 10e-6 + 1e-9, "{}", rate);
+    }
+
+    /// Answers queries from a [`ManualClock`] in memory, so that property tests run many
+    /// cases without sockets or real time
+    struct ManualServer {
+        time: Arc<ManualClock>,
+    }
+
+    impl Transport for ManualServer {
+        type Address = SocketAddr;
+        type Error = io::Error;
+
+        fn exchange(
+            &mut self,
+            _server: &SocketAddr,
+            request: &[u8; PACKET_LEN],
+            reply: &mut [u8; PACKET_LEN],
+        ) -> io::Result<Duration> {
+            let now = self.time.now();
+            let state = ServerState {
+                stratum: 1,
+                root_delay: 0.0,
+                root_dispersion: 0.0,
+                reference_id: *b"GPS\0",
+                reference_time: Some(now),
+            };
+            *reply = sntp::server_reply(request, &state, now, now)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a request"))?;
+            Ok(Duration::ZERO)
+        }
+    }
+
+    /// A clock synchronized to a [`ManualServer`] standing at `time`, on a timeline that
+    /// stands still until advanced
+    fn manual_clock(time: &Arc<ManualClock>, config: ClockConfig) -> (Clock, VirtualTimeline) {
+        let time = Arc::clone(time);
+        let factory = TransportFactory::new("manual", move || ManualServer {
+            time: Arc::clone(&time),
+        });
+        let config = config
+            .with_servers(vec!["192.0.2.1:123".to_string()])
+            .with_transport(Some(factory));
+        let clock = Clock::with_config(config);
+        let timeline = VirtualTimeline::new();
+        clock.set_virtual_timeline(timeline.clone());
+        (clock, timeline)
+    }
+
+    fn pi_config(max_slew_ppm: f64) -> ClockConfig {
+        ClockConfig::new()
+            .with_smoothing(Some(SmoothingFilter::Pi { kp: 0.5, ki: 0.05 }))
+            .with_max_slew_ppm(max_slew_ppm)
+    }
+
+    // The discipline has no step threshold: `raw` and `ema` step on every sample and `pi`
+    // never steps, so "steps only occur above the threshold" is checked as pi never stepping.
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_uncertainty_grows_during_holdover(
+            steps in proptest::collection::vec(0..86_400u64, 1..50),
+        ) {
+            let server = Arc::new(ManualClock::new(Timestamp::now()));
+            let (clock, timeline) = manual_clock(&server, ClockConfig::new());
+            proptest::prop_assert!(clock.is_synchronized());
+
+            // No further polls: the clock free-runs from the initial sync
+            let mut last = clock.uncertainty().unwrap();
+            for secs in steps {
+                timeline.advance(Duration::from_secs(secs));
+                let uncertainty = clock.uncertainty().unwrap();
+                proptest::prop_assert!(uncertainty >= last, "{:?} after {:?}", uncertainty, last);
+                last = uncertainty;
+            }
+        }
+
+        #[test]
+        fn prop_pi_time_never_goes_backwards_or_steps(
+            polls in proptest::collection::vec((1..3600u64, -10_000..10_000i64), 1..30),
+        ) {
+            let server = Arc::new(ManualClock::new(Timestamp::now()));
+            let (clock, timeline) = manual_clock(&server, pi_config(500.0));
+
+            let mut last = clock.now_timestamp();
+            for (secs, jump_ms) in polls {
+                timeline.advance(Duration::from_secs(secs));
+                server.advance(Duration::from_secs(secs));
+                // The server's time jumps either way, as after it is reset
+                server.set(server.now().add_nanos(i128::from(jump_ms) * 1_000_000));
+                let before = clock.now_timestamp();
+                proptest::prop_assert!(before >= last, "{} after {}", before, last);
+                clock.shared.update_latest_time();
+                last = clock.now_timestamp();
+                proptest::prop_assert!(last >= before, "{} after {}", last, before);
+            }
+            proptest::prop_assert_eq!(clock.get_stats().steps, 0);
+        }
+
+        #[test]
+        fn prop_slews_never_exceed_max_slew_ppm(
+            max_slew_ppm in 1.0..500.0f64,
+            polls in proptest::collection::vec((1..3600u64, -10_000..10_000i64), 1..30),
+        ) {
+            let server = Arc::new(ManualClock::new(Timestamp::now()));
+            let (clock, timeline) = manual_clock(&server, pi_config(max_slew_ppm));
+
+            for (secs, offset_ms) in polls {
+                server.advance(Duration::from_secs(secs));
+                timeline.advance(Duration::from_secs(secs));
+                server.set(server.now().add_nanos(i128::from(offset_ms) * 1_000_000));
+                clock.shared.update_latest_time();
+
+                let before = clock.now_timestamp();
+                timeline.advance(Duration::from_secs(secs));
+                server.advance(Duration::from_secs(secs));
+                let elapsed = secs as f64;
+                let rate = (clock.now_timestamp().seconds_since(before) - elapsed) / elapsed;
+                proptest::prop_assert!(
+                    rate.abs() <= max_slew_ppm * 1e-6 + 1e-9,
+                    "{} ppm with a cap of {}",
+                    rate * 1e6,
+                    max_slew_ppm
+                );
+            }
+        }
+    }
+}
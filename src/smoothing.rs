@@ -0,0 +1,322 @@
+//! # Output Smoothing
+//!
+//! Without a filter, a clock steps to the first NTP sample and then keeps extrapolating
+//! from it, only measuring the offset of later samples (see
+//! [`Clock::offset_history`](crate::Clock::offset_history)). A [`SmoothingFilter`] feeds
+//! those offsets back into the reported time:
+//!
+//! - [`Raw`](SmoothingFilter::Raw) jumps to every sample: most responsive, and as noisy as
+//!   the network
+//! - [`Ema`](SmoothingFilter::Ema) moves a fraction `alpha` of the way to every sample,
+//!   averaging out jitter at the cost of lagging real changes
+//! - [`Pi`](SmoothingFilter::Pi) never steps: a proportional-integral controller slews the
+//!   reported time at up to [`max_slew_ppm`](crate::ClockConfig::max_slew_ppm)
+//!   ([`DEFAULT_MAX_SLEW_PPM`] unless configured), and its integral term learns the local
+//!   oscillator's frequency error. Latency-sensitive services lower the cap to bound how
+//!   far the reported time may run from real elapsed time while a correction is slewed in.
+//!
+//! Offsets keep being measured against the unfiltered clock, so drift estimation and
+//! anomaly detection see the same history whichever filter is used.
+
+use crate::sntp::MAX_DRIFT_PPM;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Fraction of each offset applied by `ema` when none is given
+pub const DEFAULT_EMA_ALPHA: f64 = 0.25;
+
+/// Proportional gain of `pi` when none is given: half the error is slewed away per interval
+pub const DEFAULT_PI_KP: f64 = 0.5;
+
+/// Integral gain of `pi` when none is given
+pub const DEFAULT_PI_KI: f64 = 0.05;
+
+/// Fastest rate `pi` slews at when no cap is configured, matching ntpd's
+pub const DEFAULT_MAX_SLEW_PPM: f64 = MAX_DRIFT_PPM;
+
+/// How measured offsets are applied to the reported time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmoothingFilter {
+    /// Step to each sample
+    Raw,
+    /// Step a fraction `alpha` (0 < alpha <= 1) of the way to each sample
+    Ema { alpha: f64 },
+    /// Slew towards each sample at a rate of `kp` times the error per sync interval, plus
+    /// an integral term accumulating `ki` times the error per interval
+    Pi { kp: f64, ki: f64 },
+}
+
+impl fmt::Display for SmoothingFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmoothingFilter::Raw => write!(f, "raw"),
+            SmoothingFilter::Ema { alpha } => write!(f, "ema:{}", alpha),
+            SmoothingFilter::Pi { kp, ki } => write!(f, "pi:{},{}", kp, ki),
+        }
+    }
+}
+
+impl FromStr for SmoothingFilter {
+    type Err = String;
+
+    /// Parses `raw`, `ema`, `ema:ALPHA`, `pi`, or `pi:KP,KI`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, params) = match s.split_once(':') {
+            Some((name, params)) => (name, Some(params)),
+            None => (s, None),
+        };
+        let number = |p: &str| {
+            p.trim()
+                .parse::<f64>()
+                .map_err(|e| format!("invalid filter constant '{}': {}", p.trim(), e))
+        };
+        match (name.trim(), params) {
+            ("raw", None) => Ok(SmoothingFilter::Raw),
+            ("ema", params) => {
+                let alpha = params.map(number).transpose()?.unwrap_or(DEFAULT_EMA_ALPHA);
+                if !(alpha > 0.0 && alpha <= 1.0) {
+                    return Err(format!(
+                        "invalid ema alpha {}: expected 0 < alpha <= 1",
+                        alpha
+                    ));
+                }
+                Ok(SmoothingFilter::Ema { alpha })
+            }
+            ("pi", params) => {
+                let (kp, ki) = match params {
+                    Some(params) => {
+                        let (kp, ki) = params
+                            .split_once(',')
+                            .ok_or_else(|| format!("expected 'pi:KP,KI', found '{}'", s))?;
+                        (number(kp)?, number(ki)?)
+                    }
+                    None => (DEFAULT_PI_KP, DEFAULT_PI_KI),
+                };
+                if !(kp >= 0.0 && ki >= 0.0) {
+                    return Err(format!("invalid pi gains {},{}: expected >= 0", kp, ki));
+                }
+                Ok(SmoothingFilter::Pi { kp, ki })
+            }
+            _ => Err(format!(
+                "unknown smoothing filter '{}': expected raw, ema[:ALPHA], or pi[:KP,KI]",
+                s
+            )),
+        }
+    }
+}
+
+/// Correction added to the extrapolated time: `phase` seconds at elapsed time `since`,
+/// changing at `rate` seconds per second
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Correction {
+    phase: f64,
+    rate: f64,
+    since: Duration,
+    /// Integral term of the PI controller, a rate in seconds per second
+    integral: f64,
+}
+
+impl Correction {
+    /// The correction in seconds at elapsed time `elapsed`
+    pub fn at(&self, elapsed: Duration) -> f64 {
+        self.phase + self.rate * elapsed.saturating_sub(self.since).as_secs_f64()
+    }
+
+    /// Feeds in `offset`, the measured difference between NTP time and the uncorrected
+    /// clock, at elapsed time `elapsed`, slewing at no more than `max_slew_ppm`
+    pub fn update(
+        &mut self,
+        filter: &SmoothingFilter,
+        offset: f64,
+        elapsed: Duration,
+        max_slew_ppm: f64,
+    ) {
+        let current = self.at(elapsed);
+        let error = offset - current;
+        let interval = elapsed.saturating_sub(self.since).as_secs_f64();
+        match *filter {
+            SmoothingFilter::Raw => *self = Correction::default().with_phase(offset),
+            SmoothingFilter::Ema { alpha } => {
+                *self = Correction::default().with_phase(current + alpha * error)
+            }
+            SmoothingFilter::Pi { kp, ki } => {
+                self.phase = current;
+                if interval > 0.0 {
+                    let max_rate = max_slew_ppm * 1e-6;
+                    self.integral =
+                        (self.integral + ki * error / interval).clamp(-max_rate, max_rate);
+                    self.rate = (kp * error / interval + self.integral).clamp(-max_rate, max_rate);
+                }
+            }
+        }
+        self.since = elapsed;
+    }
+
+    /// The same correction, measured from a new reference point `elapsed` from the old one
+    pub fn rebased(&self, elapsed: Duration) -> Self {
+        Correction {
+            phase: self.at(elapsed),
+            since: Duration::ZERO,
+            ..*self
+        }
+    }
+
+    /// The same correction, slewing at no more than `max_slew_ppm` from elapsed time
+    /// `elapsed` on
+    pub fn capped(&self, elapsed: Duration, max_slew_ppm: f64) -> Self {
+        let max_rate = max_slew_ppm * 1e-6;
+        Correction {
+            phase: self.at(elapsed),
+            rate: self.rate.clamp(-max_rate, max_rate),
+            since: elapsed,
+            integral: self.integral.clamp(-max_rate, max_rate),
+        }
+    }
+
+    fn with_phase(mut self, phase: f64) -> Self {
+        self.phase = phase;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_smoothing_filter_from_str() {
+        assert_eq!("raw".parse(), Ok(SmoothingFilter::Raw));
+        assert_eq!(
+            "ema".parse(),
+            Ok(SmoothingFilter::Ema {
+                alpha: DEFAULT_EMA_ALPHA
+            })
+        );
+        assert_eq!("ema:0.5".parse(), Ok(SmoothingFilter::Ema { alpha: 0.5 }));
+        assert_eq!(
+            "pi:0.3, 0.01".parse(),
+            Ok(SmoothingFilter::Pi { kp: 0.3, ki: 0.01 })
+        );
+        let pi: SmoothingFilter = "pi".parse().unwrap();
+        assert_eq!(pi.to_string().parse(), Ok(pi));
+        assert!("ema:0".parse::<SmoothingFilter>().is_err());
+        assert!("ema:1.5".parse::<SmoothingFilter>().is_err());
+        assert!("pi:0.3".parse::<SmoothingFilter>().is_err());
+        assert!("raw:1".parse::<SmoothingFilter>().is_err());
+        assert!("kalman".parse::<SmoothingFilter>().is_err());
+    }
+
+    #[test]
+    fn test_raw_and_ema_step_towards_offset() {
+        let mut raw = Correction::default();
+        raw.update(&SmoothingFilter::Raw, 0.2, MINUTE, DEFAULT_MAX_SLEW_PPM);
+        assert_eq!(raw.at(MINUTE * 2), 0.2);
+
+        let mut ema = Correction::default();
+        let filter = SmoothingFilter::Ema { alpha: 0.5 };
+        ema.update(&filter, 0.2, MINUTE, DEFAULT_MAX_SLEW_PPM);
+        assert!((ema.at(MINUTE) - 0.1).abs() < 1e-12);
+        ema.update(&filter, 0.2, MINUTE * 2, DEFAULT_MAX_SLEW_PPM);
+        assert!((ema.at(MINUTE * 2) - 0.15).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_pi_slews_without_stepping() {
+        let filter = SmoothingFilter::Pi { kp: 0.5, ki: 0.05 };
+        let mut pi = Correction::default();
+        // A local clock losing 10 ppm against NTP
+        let offset = |elapsed: Duration| elapsed.as_secs_f64() * 10e-6;
+        for n in 1..=200 {
+            let elapsed = MINUTE * n;
+            let before = pi.at(elapsed);
+            pi.update(&filter, offset(elapsed), elapsed, DEFAULT_MAX_SLEW_PPM);
+            assert_eq!(pi.at(elapsed), before);
+        }
+        let elapsed = MINUTE * 200;
+        assert!((pi.at(elapsed) - offset(elapsed)).abs() < 1e-4);
+        assert!((pi.rate - 10e-6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_pi_rate_is_capped() {
+        let mut pi = Correction::default();
+        pi.update(&"pi".parse().unwrap(), 5.0, MINUTE, DEFAULT_MAX_SLEW_PPM);
+        assert_eq!(pi.rate, MAX_DRIFT_PPM * 1e-6);
+
+        // Lowering the cap mid-slew keeps the time and slows the slew down at once
+        let capped = pi.capped(MINUTE * 2, 50.0);
+        assert_eq!(capped.at(MINUTE * 2), pi.at(MINUTE * 2));
+        assert!((capped.at(MINUTE * 3) - capped.at(MINUTE * 2) - 60.0 * 50e-6).abs() < 1e-12);
+    }
+
+    /// Measured offsets (seconds) and the intervals (seconds) between the syncs that took them
+    fn syncs() -> impl Strategy<Value = Vec<(f64, u64)>> {
+        proptest::collection::vec((-1000.0..1000.0f64, 1..100_000u64), 1..40)
+    }
+
+    fn pi() -> impl Strategy<Value = SmoothingFilter> {
+        (0.01..1.0f64, 0.0..0.5f64).prop_map(|(kp, ki)| SmoothingFilter::Pi { kp, ki })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_pi_never_steps_or_exceeds_the_slew_cap(
+            filter in pi(),
+            syncs in syncs(),
+            max_slew_ppm in 1.0..=DEFAULT_MAX_SLEW_PPM,
+        ) {
+            let max_rate = max_slew_ppm * 1e-6;
+            let mut pi = Correction::default();
+            let mut elapsed = Duration::ZERO;
+            for (offset, interval) in syncs {
+                let next = elapsed + Duration::from_secs(interval);
+                let slewed = pi.at(next) - pi.at(elapsed);
+                prop_assert!(slewed.abs() <= max_rate * interval as f64 * (1.0 + 1e-9));
+                elapsed = next;
+
+                let before = pi.at(elapsed);
+                pi.update(&filter, offset, elapsed, max_slew_ppm);
+                prop_assert_eq!(pi.at(elapsed), before);
+            }
+        }
+
+        #[test]
+        fn prop_pi_time_never_goes_backwards(filter in pi(), syncs in syncs()) {
+            let mut pi = Correction::default();
+            let mut elapsed = Duration::ZERO;
+            let mut last = 0.0;
+            for (offset, interval) in syncs {
+                for _ in 0..4 {
+                    elapsed += Duration::from_secs(interval) / 4;
+                    let time = elapsed.as_secs_f64() + pi.at(elapsed);
+                    prop_assert!(time >= last, "{} after {}", time, last);
+                    last = time;
+                }
+                pi.update(&filter, offset, elapsed, DEFAULT_MAX_SLEW_PPM);
+            }
+        }
+
+        #[test]
+        fn prop_steps_land_between_the_time_and_the_sample(
+            alpha in 0.01..=1.0f64,
+            syncs in syncs(),
+        ) {
+            let filter = SmoothingFilter::Ema { alpha };
+            let mut ema = Correction::default();
+            let mut elapsed = Duration::ZERO;
+            for (offset, interval) in syncs {
+                elapsed += Duration::from_secs(interval);
+                let before = ema.at(elapsed);
+                ema.update(&filter, offset, elapsed, DEFAULT_MAX_SLEW_PPM);
+                let after = ema.at(elapsed);
+                let (low, high) = (before.min(offset), before.max(offset));
+                prop_assert!(after >= low - 1e-9 && after <= high + 1e-9);
+                prop_assert!(((after - before) - alpha * (offset - before)).abs() < 1e-6);
+            }
+        }
+    }
+}
@@ -0,0 +1,1860 @@
+//! # Sans-I/O SNTP Core
+//!
+//! Packet construction and parsing and the clock discipline math, with no I/O of their own.
+//! This module only needs `core` and `alloc`, so it is available when the crate is built
+//! with `default-features = false` for targets without `std`.
+//!
+//! The network is reached through the [`Transport`] trait. The std build implements it with
+//! [`UdpTransport`], which the [`Clock`](crate::Clock) engine uses unless given another (see
+//! [`transport`](crate::transport)); firmware can implement it over smoltcp or embassy-net
+//! and send requests directly, with a nonce from its hardware RNG:
+//!
+//! ```ignore
+//! let request = clock::sntp::client_request_with_nonce(4, rng.next_u64().to_be_bytes());
+//! let sample = clock::sntp::query_with_request(&mut my_transport, &server_endpoint, &request)?;
+//! rtc.set(sample.time);
+//! ```
+//!
+//! Async network stacks implement [`AsyncTransport`] and call [`query_async`] instead; the
+//! `embassy` feature provides one for embassy executors.
+
+use crate::Timestamp;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+/// Size of an NTP packet without extension fields
+pub const PACKET_LEN: usize = 48;
+
+/// Longest packet read by [`Transport::exchange_packet`], enough for a MAC after several
+/// extension fields
+pub const MAX_PACKET_LEN: usize = 1024;
+
+/// Seconds from the NTP era 0 epoch (1900-01-01) to the Unix epoch
+pub const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+/// Largest frequency error accepted from [`drift_ppm`], matching ntpd's tolerance
+pub const MAX_DRIFT_PPM: f64 = 500.0;
+
+/// Number of recent round-trip delays a [`DelayFilter`] takes its minimum over
+pub const DELAY_WINDOW: usize = 32;
+
+/// Delay above the recent minimum that is always allowed, so that jitter on fast local
+/// links is not mistaken for an attack
+pub const DELAY_SLACK: Duration = Duration::from_millis(1);
+
+/// Stratum of a server that is not synchronized, and the highest stratum there is
+pub const MAX_STRATUM: u8 = 16;
+
+/// Builds an SNTP client request with a random nonce as its transmit timestamp
+#[cfg(feature = "std")]
+pub fn client_request() -> [u8; PACKET_LEN] {
+    client_request_with_version(3)
+}
+
+/// Builds an SNTP client request announcing NTP `version` (1-4), with a random nonce as its
+/// transmit timestamp
+#[cfg(feature = "std")]
+pub fn client_request_with_version(version: u8) -> [u8; PACKET_LEN] {
+    client_request_with_nonce(version, random_u64().to_be_bytes())
+}
+
+/// Builds an SNTP client request announcing NTP `version` (1-4) that carries `nonce` as its
+/// transmit timestamp. A reply only counts if it echoes the nonce (see [`parse_reply`]), so
+/// it should be random and never reused; firmware draws it from its hardware RNG.
+pub fn client_request_with_nonce(version: u8, nonce: [u8; 8]) -> [u8; PACKET_LEN] {
+    let mut packet = [0u8; PACKET_LEN];
+    packet[0] = (version & 0x07) << 3 | 3; // client mode
+    packet[40..48].copy_from_slice(&nonce);
+    packet
+}
+
+/// Extracts the transmit timestamp from an NTP response packet, or `None` if the server
+/// left it unset
+pub fn parse_transmit_time(buf: &[u8; PACKET_LEN]) -> Option<Timestamp> {
+    parse_timestamp(&buf[40..48])
+}
+
+/// Decodes an NTP timestamp, or `None` if it is unset
+fn parse_timestamp(bytes: &[u8]) -> Option<Timestamp> {
+    let seconds = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let fraction = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    if seconds == 0 && fraction == 0 {
+        return None;
+    }
+    let nanos = (fraction as u64 * 1_000_000_000) >> 32;
+    Some(Timestamp::from_unix_secs(ntp_seconds_to_unix(seconds)).add_nanos(nanos as i128))
+}
+
+/// Converts the seconds of an NTP timestamp to Unix seconds. As in RFC 4330, values with the
+/// top bit set are in era 0 (1968-2036) and the rest in era 1 (2036-2104), since no server
+/// sends times before 1968.
+pub fn ntp_seconds_to_unix(seconds: u32) -> i64 {
+    let era = if seconds & 0x8000_0000 == 0 { 1 } else { 0 };
+    (era << 32) + seconds as i64 - NTP_UNIX_OFFSET
+}
+
+/// Converts an NTP short format value (16.16 fixed point) to seconds
+pub fn parse_short_format(bytes: &[u8]) -> f64 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64 / 65536.0
+}
+
+/// The header of an NTP packet (RFC 5905), field by field.
+///
+/// Timestamps are kept as sent, so that [`encode`](Self::encode) reproduces the packet
+/// exactly; extension fields and a MAC after the header are read with
+/// [`extension::parse_packet`](crate::extension::parse_packet).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtpPacket {
+    /// Leap indicator: 1 or 2 for a leap second at the end of the day, 3 while unsynchronized
+    pub leap: u8,
+    /// NTP version, 1 to 4
+    pub version: u8,
+    /// Association mode: 3 for a client, 4 for a server
+    pub mode: u8,
+    /// See [`Measurement::stratum`]
+    pub stratum: u8,
+    /// Poll interval, as a power of two in seconds
+    pub poll: i8,
+    /// See [`Measurement::precision`]
+    pub precision: i8,
+    /// See [`Measurement::root_delay`]
+    pub root_delay: f64,
+    /// See [`Measurement::root_dispersion`]
+    pub root_dispersion: f64,
+    /// See [`Measurement::reference_id`]
+    pub reference_id: [u8; 4],
+    /// When the sender's clock was last set
+    pub reference_timestamp: [u8; 8],
+    /// The transmit timestamp of the request a reply answers
+    pub origin_timestamp: [u8; 8],
+    /// When the request arrived at the server
+    pub receive_timestamp: [u8; 8],
+    /// When the packet left its sender
+    pub transmit_timestamp: [u8; 8],
+}
+
+impl NtpPacket {
+    /// Decodes the header at the start of `bytes`, or `None` if it is shorter than
+    /// [`PACKET_LEN`]. Every bit pattern is a header, so nothing else is refused.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let header: &[u8; PACKET_LEN] = bytes.get(..PACKET_LEN)?.try_into().ok()?;
+        Some(Self::from(header))
+    }
+
+    /// Encodes the header as it is sent
+    pub fn encode(&self) -> [u8; PACKET_LEN] {
+        let mut packet = [0u8; PACKET_LEN];
+        packet[0] = (self.leap & 0x03) << 6 | (self.version & 0x07) << 3 | self.mode & 0x07;
+        packet[1] = self.stratum;
+        packet[2] = self.poll as u8;
+        packet[3] = self.precision as u8;
+        packet[4..8].copy_from_slice(&encode_short_format(self.root_delay));
+        packet[8..12].copy_from_slice(&encode_short_format(self.root_dispersion));
+        packet[12..16].copy_from_slice(&self.reference_id);
+        packet[16..24].copy_from_slice(&self.reference_timestamp);
+        packet[24..32].copy_from_slice(&self.origin_timestamp);
+        packet[32..40].copy_from_slice(&self.receive_timestamp);
+        packet[40..48].copy_from_slice(&self.transmit_timestamp);
+        packet
+    }
+
+    /// Whether this is a client request of a version this crate answers
+    pub fn is_client_request(&self) -> bool {
+        self.mode == 3 && (1..=4).contains(&self.version)
+    }
+
+    /// The reference timestamp, or `None` if it is unset
+    pub fn reference_time(&self) -> Option<Timestamp> {
+        parse_timestamp(&self.reference_timestamp)
+    }
+
+    /// The receive timestamp, or `None` if it is unset
+    pub fn receive_time(&self) -> Option<Timestamp> {
+        parse_timestamp(&self.receive_timestamp)
+    }
+
+    /// The transmit timestamp, or `None` if it is unset
+    pub fn transmit_time(&self) -> Option<Timestamp> {
+        parse_timestamp(&self.transmit_timestamp)
+    }
+}
+
+impl From<&[u8; PACKET_LEN]> for NtpPacket {
+    fn from(packet: &[u8; PACKET_LEN]) -> Self {
+        let timestamp = |at: usize| -> [u8; 8] {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&packet[at..at + 8]);
+            bytes
+        };
+        NtpPacket {
+            leap: packet[0] >> 6,
+            version: (packet[0] >> 3) & 0x07,
+            mode: packet[0] & 0x07,
+            stratum: packet[1],
+            poll: packet[2] as i8,
+            precision: packet[3] as i8,
+            root_delay: parse_short_format(&packet[4..8]),
+            root_dispersion: parse_short_format(&packet[8..12]),
+            reference_id: [packet[12], packet[13], packet[14], packet[15]],
+            reference_timestamp: timestamp(16),
+            origin_timestamp: timestamp(24),
+            receive_timestamp: timestamp(32),
+            transmit_timestamp: timestamp(40),
+        }
+    }
+}
+
+/// A time sample taken from one request/reply exchange
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    /// Server time, corrected for half the round-trip delay
+    pub time: Timestamp,
+    /// Round-trip delay of the request
+    pub delay: Duration,
+    /// Round-trip delay from the server to its reference clock, as reported by the
+    /// server, in seconds
+    pub root_delay: f64,
+    /// Root dispersion reported by the server, in seconds
+    pub root_dispersion: f64,
+    /// The server's distance from a reference clock: 1 for a primary server, 0 for a
+    /// kiss-o'-death reply, [`MAX_STRATUM`] if it is unsynchronized
+    pub stratum: u8,
+    /// What the server is synchronized to: the IPv4 address (or hash of the IPv6 address)
+    /// of its upstream server, or an ASCII reference clock code at stratum 1
+    pub reference_id: [u8; 4],
+    /// Precision of the server's clock, as a power of two in seconds (-20 is about a
+    /// microsecond)
+    pub precision: i8,
+}
+
+impl Measurement {
+    /// Whether the server claims to be synchronized to a source, so its time may be used
+    pub fn is_synchronized(&self) -> bool {
+        (1..MAX_STRATUM).contains(&self.stratum)
+    }
+
+    /// The kiss code of a kiss-o'-death reply, such as `RATE` or `DENY`
+    pub fn kiss_code(&self) -> Option<&str> {
+        if self.stratum != 0 {
+            return None;
+        }
+        core::str::from_utf8(&self.reference_id).ok()
+    }
+
+    /// Whether the server reports `addr` as its upstream, i.e. it synchronizes to the host
+    /// with that address and would form a timing loop if that host synchronized to it
+    pub fn is_synchronized_to(&self, addr: [u8; 4]) -> bool {
+        self.stratum > 1 && self.reference_id == addr
+    }
+}
+
+/// Turns a server reply to `request` and the measured round trip into a [`Measurement`].
+///
+/// Returns `None` unless the reply is in server mode and echoes the request's transmit
+/// timestamp as its origin, or its receive timestamp for an [`interleaved_request`], so
+/// that an off-path attacker has to guess the request's nonce to forge a reply. Replies of
+/// unsynchronized servers (leap indicator 3) and at stratum 0 are refused as well, except
+/// for kiss-o'-death replies, as are replies without a transmit timestamp.
+pub fn parse_reply(
+    request: &[u8; PACKET_LEN],
+    reply: &[u8; PACKET_LEN],
+    delay: Duration,
+) -> Option<Measurement> {
+    let request = NtpPacket::from(request);
+    let packet = NtpPacket::from(reply);
+    let origin = packet.origin_timestamp;
+    let answers = origin != [0; 8]
+        && (origin == request.transmit_timestamp || origin == request.receive_timestamp);
+    if !answers {
+        return None;
+    }
+    measure(&packet, delay)
+}
+
+/// Whether `packet` is a kiss-o'-death reply: stratum 0 with an ASCII kiss code
+fn is_kiss(packet: &NtpPacket) -> bool {
+    packet.stratum == 0 && packet.reference_id.iter().all(u8::is_ascii_graphic)
+}
+
+/// The measurement a server reply carries, or `None` if it is not a server reply with
+/// usable time or a kiss-o'-death reply
+fn measure(reply: &NtpPacket, delay: Duration) -> Option<Measurement> {
+    let usable = reply.mode == 4
+        && if reply.stratum == 0 {
+            is_kiss(reply)
+        } else {
+            reply.leap != 3
+        };
+    if !usable {
+        return None;
+    }
+    let transmit = reply.transmit_time()?;
+    Some(Measurement {
+        // The reply spent roughly half the round trip in flight
+        time: transmit + delay / 2,
+        delay,
+        root_delay: reply.root_delay,
+        root_dispersion: reply.root_dispersion,
+        stratum: reply.stratum,
+        reference_id: reply.reference_id,
+        precision: reply.precision,
+    })
+}
+
+/// Renders a reference ID the way `ntpq` does: as the ASCII code of a reference clock
+/// (`GPS`, `PPS`) or kiss code at stratum 0 and 1 and while unsynchronized (`INIT`), and
+/// as the upstream server's IPv4 address otherwise
+pub fn display_reference_id(stratum: u8, reference_id: [u8; 4]) -> impl fmt::Display {
+    DisplayReferenceId {
+        stratum,
+        reference_id,
+    }
+}
+
+struct DisplayReferenceId {
+    stratum: u8,
+    reference_id: [u8; 4],
+}
+
+impl fmt::Display for DisplayReferenceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self
+            .reference_id
+            .split(|&byte| byte == 0)
+            .next()
+            .unwrap_or_default();
+        let is_code = !code.is_empty() && code.iter().all(u8::is_ascii_graphic);
+        if is_code && (self.stratum <= 1 || self.stratum >= MAX_STRATUM) {
+            // ASCII graphic characters are valid UTF-8
+            return f.write_str(core::str::from_utf8(code).unwrap_or_default());
+        }
+        let [a, b, c, d] = self.reference_id;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+/// Encodes a time in the NTP timestamp format: seconds since 1900 (wrapping in era 1) and a
+/// 32-bit binary fraction
+pub fn encode_timestamp(time: Timestamp) -> [u8; 8] {
+    let seconds = (time.unix_secs() + NTP_UNIX_OFFSET) as u32;
+    let fraction = ((time.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    let mut bytes = [0u8; 8];
+    bytes[..4].copy_from_slice(&seconds.to_be_bytes());
+    bytes[4..].copy_from_slice(&(fraction as u32).to_be_bytes());
+    bytes
+}
+
+/// Converts seconds to the NTP short format (16.16 fixed point), saturating
+pub fn encode_short_format(seconds: f64) -> [u8; 4] {
+    ((seconds.max(0.0) * 65536.0).min(u32::MAX as f64) as u32).to_be_bytes()
+}
+
+/// What a server reports about itself in its replies
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServerState {
+    /// The server's stratum, [`MAX_STRATUM`] while it is not synchronized
+    pub stratum: u8,
+    /// Round-trip delay from the server to its reference clock, in seconds
+    pub root_delay: f64,
+    /// Bound on the error of the server's time beyond half its root delay, in seconds
+    pub root_dispersion: f64,
+    /// What the server is synchronized to, see [`Measurement::reference_id`]
+    pub reference_id: [u8; 4],
+    /// When the server last synchronized, if it has
+    pub reference_time: Option<Timestamp>,
+}
+
+/// Builds a server's reply to `request`, received at `receive` and answered at `transmit`,
+/// or `None` if `request` is not a client request
+pub fn server_reply(
+    request: &[u8; PACKET_LEN],
+    state: &ServerState,
+    receive: Timestamp,
+    transmit: Timestamp,
+) -> Option<[u8; PACKET_LEN]> {
+    let request = NtpPacket::from(request);
+    if !request.is_client_request() {
+        return None;
+    }
+    // Leap indicator 3 ("alarm") tells clients the server is not synchronized
+    let leap = if state.stratum >= MAX_STRATUM { 3 } else { 0 };
+    let mut reply = [0u8; PACKET_LEN];
+    reply[0] = leap << 6 | request.version << 3 | 4; // server mode
+    reply[1] = state.stratum;
+    reply[2] = request.poll as u8; // poll interval, echoed
+    reply[3] = -20i8 as u8; // precision: about a microsecond
+    reply[4..8].copy_from_slice(&encode_short_format(state.root_delay));
+    reply[8..12].copy_from_slice(&encode_short_format(state.root_dispersion));
+    reply[12..16].copy_from_slice(&state.reference_id);
+    if let Some(reference) = state.reference_time {
+        reply[16..24].copy_from_slice(&encode_timestamp(reference));
+    }
+    // The client's transmit timestamp becomes the origin timestamp
+    reply[24..32].copy_from_slice(&request.transmit_timestamp);
+    reply[32..40].copy_from_slice(&encode_timestamp(receive));
+    reply[40..48].copy_from_slice(&encode_timestamp(transmit));
+    Some(reply)
+}
+
+/// Builds a kiss-o'-death reply to `request` with the kiss `code`, such as `RATE`, or `None`
+/// if `request` is not a client request. Its receive and transmit timestamps are
+/// `received`, but at stratum 0 it is not to be taken as time.
+pub fn kiss_reply(
+    request: &[u8; PACKET_LEN],
+    code: [u8; 4],
+    received: Timestamp,
+) -> Option<[u8; PACKET_LEN]> {
+    let request = NtpPacket::from(request);
+    if !request.is_client_request() {
+        return None;
+    }
+    let mut reply = [0u8; PACKET_LEN];
+    reply[0] = 3 << 6 | request.version << 3 | 4; // unsynchronized, server mode
+    reply[2] = request.poll as u8;
+    reply[12..16].copy_from_slice(&code);
+    reply[24..32].copy_from_slice(&request.transmit_timestamp);
+    reply[32..40].copy_from_slice(&encode_timestamp(received));
+    reply[40..48].copy_from_slice(&encode_timestamp(received));
+    Some(reply)
+}
+
+/// What a client keeps of an exchange to ask for interleaved mode (RFC 5905) in the next.
+///
+/// A server cannot know when its reply actually leaves until after it has sent it, so the
+/// transmit timestamp of a basic reply is taken early. In interleaved mode the server
+/// instead sends the precise transmit timestamp of its previous reply, with the matching
+/// receive timestamp, and the client measures the previous exchange with them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InterleavedState {
+    /// The server's receive timestamp of the request, by which the server finds the
+    /// exchange again
+    pub server_receive: [u8; 8],
+    /// When the client received the reply; an interleaved reply echoes it as its origin
+    pub client_receive: [u8; 8],
+    /// Round trip of the exchange, as the transport measured it
+    pub delay: Duration,
+}
+
+impl InterleavedState {
+    /// Remembers the exchange that brought `reply` after `delay`, received at `received`
+    pub fn new(reply: &[u8; PACKET_LEN], received: Timestamp, delay: Duration) -> Self {
+        let mut server_receive = [0u8; 8];
+        server_receive.copy_from_slice(&reply[32..40]);
+        InterleavedState {
+            server_receive,
+            client_receive: encode_timestamp(received),
+            delay,
+        }
+    }
+}
+
+/// Builds a client request announcing NTP `version` that asks for the transmit timestamp of
+/// the reply that ended the `previous` exchange, sent at `transmit`
+pub fn interleaved_request(
+    version: u8,
+    previous: &InterleavedState,
+    transmit: Timestamp,
+) -> [u8; PACKET_LEN] {
+    let mut packet = client_request_with_nonce(version, encode_timestamp(transmit));
+    packet[24..32].copy_from_slice(&previous.server_receive);
+    packet[32..40].copy_from_slice(&previous.client_receive);
+    packet
+}
+
+/// Whether `reply` answers an [`interleaved_request`] in kind, by echoing its receive
+/// timestamp rather than its transmit timestamp as the origin
+pub fn is_interleaved_reply(request: &[u8; PACKET_LEN], reply: &[u8; PACKET_LEN]) -> bool {
+    request[32..40] != [0; 8] && reply[24..32] == request[32..40]
+}
+
+/// Measures the `previous` exchange from an interleaved `reply`, which carries the server's
+/// receive and precise transmit timestamps of it. The server's turnaround is taken out of
+/// the round trip, and the time is carried forward by the `since` that passed locally from
+/// the previous reply's arrival to this one's, so that the measurement is dated like one
+/// from [`parse_reply`]. Returns `None` if either timestamp is unset, or if `parse_reply`
+/// would refuse the reply.
+pub fn parse_interleaved_reply(
+    reply: &[u8; PACKET_LEN],
+    previous: &InterleavedState,
+    since: Duration,
+) -> Option<Measurement> {
+    let receive = parse_timestamp(&reply[32..40])?;
+    let transmit = parse_transmit_time(reply)?;
+    let turnaround = transmit
+        .nanos_since(receive)
+        .clamp(0, previous.delay.as_nanos() as i128);
+    let delay = previous.delay - Duration::from_nanos(turnaround as u64);
+    Some(Measurement {
+        time: transmit + delay / 2 + since,
+        delay,
+        ..measure(&NtpPacket::from(reply), Duration::ZERO)?
+    })
+}
+
+/// Moves NTP packets to and from a server
+pub trait Transport {
+    /// How a server is addressed, e.g. a `SocketAddr` or an embassy-net `IpEndpoint`
+    type Address;
+    /// Error returned when the exchange fails, including timeouts
+    type Error;
+
+    /// Sends `request` to `server`, reads the reply into `reply`, and returns the round-trip
+    /// time as measured by the transport's own monotonic clock
+    fn exchange(
+        &mut self,
+        server: &Self::Address,
+        request: &[u8; PACKET_LEN],
+        reply: &mut [u8; PACKET_LEN],
+    ) -> Result<Duration, Self::Error>;
+
+    /// Like [`exchange`](Self::exchange), for packets that may carry extension fields or a
+    /// MAC after the header; `reply` is replaced with the whole reply.
+    ///
+    /// The default sends only the header of `request` and returns only the header of the
+    /// reply, so over a transport that does not override it, MACs fail to verify rather
+    /// than go unchecked.
+    fn exchange_packet(
+        &mut self,
+        server: &Self::Address,
+        request: &[u8],
+        reply: &mut Vec<u8>,
+    ) -> Result<Duration, Self::Error> {
+        let mut header = [0u8; PACKET_LEN];
+        let len = request.len().min(PACKET_LEN);
+        header[..len].copy_from_slice(&request[..len]);
+        let mut reply_header = [0u8; PACKET_LEN];
+        let delay = self.exchange(server, &header, &mut reply_header)?;
+        reply.clear();
+        reply.extend_from_slice(&reply_header);
+        Ok(delay)
+    }
+
+    /// How long before the last [`exchange`](Self::exchange) returned its reply actually
+    /// arrived, for transports that know when the packet was received
+    fn receive_latency(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Local address the last exchange was sent from, if the transport has one the server
+    /// would see; used to detect servers that synchronize to this host
+    fn local_addr(&self) -> Option<Self::Address> {
+        None
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    type Address = T::Address;
+    type Error = T::Error;
+
+    fn exchange(
+        &mut self,
+        server: &Self::Address,
+        request: &[u8; PACKET_LEN],
+        reply: &mut [u8; PACKET_LEN],
+    ) -> Result<Duration, Self::Error> {
+        (**self).exchange(server, request, reply)
+    }
+
+    fn exchange_packet(
+        &mut self,
+        server: &Self::Address,
+        request: &[u8],
+        reply: &mut Vec<u8>,
+    ) -> Result<Duration, Self::Error> {
+        (**self).exchange_packet(server, request, reply)
+    }
+
+    fn receive_latency(&self) -> Duration {
+        (**self).receive_latency()
+    }
+
+    fn local_addr(&self) -> Option<Self::Address> {
+        (**self).local_addr()
+    }
+}
+
+/// Moves NTP packets to and from a server without blocking
+///
+/// The futures are not required to be `Send`, which suits the single-threaded executors
+/// used in firmware.
+#[allow(async_fn_in_trait)]
+pub trait AsyncTransport {
+    /// How a server is addressed
+    type Address;
+    /// Error returned when the exchange fails, including timeouts
+    type Error;
+
+    /// Sends `request` to `server`, reads the reply into `reply`, and returns the round-trip
+    /// time as measured by the transport's own monotonic clock
+    async fn exchange(
+        &mut self,
+        server: &Self::Address,
+        request: &[u8; PACKET_LEN],
+        reply: &mut [u8; PACKET_LEN],
+    ) -> Result<Duration, Self::Error>;
+}
+
+/// Why a [`query`] or [`query_async`] failed
+#[derive(Debug)]
+pub enum QueryError<E> {
+    /// The transport could not complete the exchange
+    Transport(E),
+    /// The reply does not answer the request, comes from an unsynchronized server, or has
+    /// no transmit timestamp
+    InvalidReply,
+}
+
+impl<E: fmt::Display> fmt::Display for QueryError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Transport(e) => write!(f, "transport error: {}", e),
+            QueryError::InvalidReply => write!(f, "reply does not answer the request"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: std::error::Error + 'static> std::error::Error for QueryError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QueryError::Transport(e) => Some(e),
+            QueryError::InvalidReply => None,
+        }
+    }
+}
+
+/// Performs one SNTP exchange with `server` over `transport`, with a random nonce
+#[cfg(feature = "std")]
+pub fn query<T: Transport>(
+    transport: &mut T,
+    server: &T::Address,
+) -> Result<Measurement, QueryError<T::Error>> {
+    query_with_request(transport, server, &client_request())
+}
+
+/// Like [`query`], sending `request` instead of the default client request
+pub fn query_with_request<T: Transport>(
+    transport: &mut T,
+    server: &T::Address,
+    request: &[u8; PACKET_LEN],
+) -> Result<Measurement, QueryError<T::Error>> {
+    query_with_reply(transport, server, request).map(|(measurement, _)| measurement)
+}
+
+/// Like [`query_with_request`], also returning the reply, e.g. to check for an
+/// interleaved one
+pub fn query_with_reply<T: Transport>(
+    transport: &mut T,
+    server: &T::Address,
+    request: &[u8; PACKET_LEN],
+) -> Result<(Measurement, [u8; PACKET_LEN]), QueryError<T::Error>> {
+    let mut reply = [0u8; PACKET_LEN];
+    let delay = transport
+        .exchange(server, request, &mut reply)
+        .map_err(QueryError::Transport)?;
+    let mut measurement = parse_reply(request, &reply, delay).ok_or(QueryError::InvalidReply)?;
+    // The reply has aged since it arrived
+    measurement.time = measurement.time + transport.receive_latency();
+    Ok((measurement, reply))
+}
+
+/// Like [`query_with_reply`], sending `request` whole, e.g. with a MAC after the header,
+/// and returning the whole reply
+pub fn query_with_packet<T: Transport>(
+    transport: &mut T,
+    server: &T::Address,
+    request: &[u8],
+) -> Result<(Measurement, Vec<u8>), QueryError<T::Error>> {
+    let mut reply = Vec::new();
+    let delay = transport
+        .exchange_packet(server, request, &mut reply)
+        .map_err(QueryError::Transport)?;
+    let header = |packet: &[u8]| {
+        packet
+            .get(..PACKET_LEN)
+            .and_then(|header| <[u8; PACKET_LEN]>::try_from(header).ok())
+            .ok_or(QueryError::InvalidReply)
+    };
+    let mut measurement =
+        parse_reply(&header(request)?, &header(&reply)?, delay).ok_or(QueryError::InvalidReply)?;
+    measurement.time = measurement.time + transport.receive_latency();
+    Ok((measurement, reply))
+}
+
+/// Performs one SNTP exchange with `server` over an [`AsyncTransport`], sending `nonce` as
+/// the transmit timestamp (see [`client_request_with_nonce`])
+pub async fn query_async<T: AsyncTransport>(
+    transport: &mut T,
+    server: &T::Address,
+    nonce: [u8; 8],
+) -> Result<Measurement, QueryError<T::Error>> {
+    let request = client_request_with_nonce(3, nonce);
+    let mut reply = [0u8; PACKET_LEN];
+    let delay = transport
+        .exchange(server, &request, &mut reply)
+        .await
+        .map_err(QueryError::Transport)?;
+    parse_reply(&request, &reply, delay).ok_or(QueryError::InvalidReply)
+}
+
+/// Offsets further than this many median absolute deviations from the median are outliers
+const OUTLIER_MADS: i128 = 3;
+
+/// Combines measurements taken from one server in quick succession into one, dated at the
+/// last of them.
+///
+/// Each entry pairs a measurement with the local time elapsed between the first measurement
+/// and it. Offsets more than three median absolute deviations from the median are discarded
+/// as outliers; the result carries the median offset of the rest, and the smallest delay,
+/// root delay, and root dispersion among them. Returns `None` if `samples` is empty.
+pub fn combine_measurements(samples: &[(Measurement, Duration)]) -> Option<Measurement> {
+    let (first, _) = samples.first()?;
+    let (_, last_elapsed) = samples.last()?;
+    // Offset of each sample from the first, once local elapsed time is taken out
+    let offsets: Vec<i128> = samples
+        .iter()
+        .map(|(m, elapsed)| m.time.nanos_since(first.time) - elapsed.as_nanos() as i128)
+        .collect();
+
+    let median = median_nanos(offsets.clone());
+    let mad = median_nanos(offsets.iter().map(|o| (o - median).abs()).collect());
+    let inliers: Vec<usize> = (0..samples.len())
+        .filter(|&i| mad == 0 || (offsets[i] - median).abs() <= OUTLIER_MADS * mad)
+        .collect();
+
+    let offset = median_nanos(inliers.iter().map(|&i| offsets[i]).collect());
+    let delay = inliers.iter().map(|&i| samples[i].0.delay).min()?;
+    let root_delay = inliers
+        .iter()
+        .map(|&i| samples[i].0.root_delay)
+        .fold(f64::INFINITY, f64::min);
+    let root_dispersion = inliers
+        .iter()
+        .map(|&i| samples[i].0.root_dispersion)
+        .fold(f64::INFINITY, f64::min);
+    Some(Measurement {
+        time: first
+            .time
+            .add_nanos(last_elapsed.as_nanos() as i128 + offset),
+        delay,
+        root_delay,
+        root_dispersion,
+        stratum: samples[*inliers.last()?].0.stratum,
+        reference_id: samples[*inliers.last()?].0.reference_id,
+        precision: samples[*inliers.last()?].0.precision,
+    })
+}
+
+fn median_nanos(mut values: Vec<i128>) -> i128 {
+    values.sort_unstable();
+    let mid = values.len() / 2;
+    match values.len() {
+        0 => 0,
+        n if n % 2 == 0 => (values[mid - 1] + values[mid]) / 2,
+        _ => values[mid],
+    }
+}
+
+/// RMS deviation, in seconds, of the offsets in a set of measurements passed to
+/// [`combine_measurements`] from their median; zero for a single measurement
+pub fn sample_jitter(samples: &[(Measurement, Duration)]) -> f64 {
+    let Some((first, _)) = samples.first() else {
+        return 0.0;
+    };
+    let offsets: Vec<i128> = samples
+        .iter()
+        .map(|(m, elapsed)| m.time.nanos_since(first.time) - elapsed.as_nanos() as i128)
+        .collect();
+    let median = median_nanos(offsets.clone());
+    let sum_squares: f64 = offsets
+        .iter()
+        .map(|o| {
+            let deviation = (o - median) as f64 / 1e9;
+            deviation * deviation
+        })
+        .sum();
+    sqrt(sum_squares / offsets.len() as f64)
+}
+
+/// Square root by Newton's method, as `f64::sqrt` needs `std`
+fn sqrt(x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let mut root = if x > 1.0 { x } else { 1.0 };
+    for _ in 0..64 {
+        let next = (root + x / root) / 2.0;
+        if next >= root {
+            break;
+        }
+        root = next;
+    }
+    root
+}
+
+/// Smallest root distance a source is credited with, as ntpd's MINDISP: it covers clock
+/// reading and timestamp resolution errors, and keeps a perfect source from taking all the
+/// weight through a division by zero
+const MIN_DISTANCE: f64 = 0.01;
+
+/// What one server said about the local clock, for selecting and combining sources
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceEstimate {
+    /// Offset of the server from the local clock, in seconds
+    pub offset: f64,
+    /// Half the round-trip delay plus the server's root dispersion, in seconds: the true
+    /// offset lies within this distance of `offset` if the server is honest
+    pub root_distance: f64,
+    /// RMS spread of the server's recent offsets, in seconds
+    pub jitter: f64,
+}
+
+impl SourceEstimate {
+    fn distance(&self) -> f64 {
+        self.root_distance.max(MIN_DISTANCE)
+    }
+}
+
+/// Picks the truechimers among `sources` with Marzullo's algorithm.
+///
+/// Each source's correctness interval is its offset ± root distance, at least 10 ms. Returns the indices of
+/// the sources whose intervals all contain the region where the most intervals overlap, or
+/// nothing if those are not a majority of the sources.
+pub fn select_truechimers(sources: &[SourceEstimate]) -> Vec<usize> {
+    let mut edges: Vec<(f64, i32)> = sources
+        .iter()
+        .flat_map(|s| [(s.offset - s.distance(), 1), (s.offset + s.distance(), -1)])
+        .collect();
+    // Starts sort before ends at the same point, so touching intervals count as overlapping
+    edges.sort_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)));
+
+    let (mut depth, mut best, mut low, mut high) = (0, 0, 0.0, 0.0);
+    for (i, &(point, step)) in edges.iter().enumerate() {
+        depth += step;
+        if depth > best {
+            best = depth;
+            low = point;
+            high = edges[i + 1].0;
+        }
+    }
+    if (best as usize) * 2 <= sources.len() {
+        return Vec::new();
+    }
+    (0..sources.len())
+        .filter(|&i| {
+            let s = &sources[i];
+            s.offset - s.distance() <= low && s.offset + s.distance() >= high
+        })
+        .collect()
+}
+
+/// Combines the `selected` sources into one offset, weighting each by the inverse of its
+/// root distance plus jitter as ntpd's combine algorithm does. Returns the offset and each
+/// selected source's weight (in the order of `selected`, summing to one), or `None` if
+/// nothing is selected.
+pub fn combine_offsets(sources: &[SourceEstimate], selected: &[usize]) -> Option<(f64, Vec<f64>)> {
+    if selected.is_empty() {
+        return None;
+    }
+    let raw: Vec<f64> = selected
+        .iter()
+        .map(|&i| 1.0 / (sources[i].distance() + sources[i].jitter))
+        .collect();
+    let total: f64 = raw.iter().sum();
+    let weights: Vec<f64> = raw.iter().map(|w| w / total).collect();
+    let offset = selected
+        .iter()
+        .zip(&weights)
+        .map(|(&i, w)| sources[i].offset * w)
+        .sum();
+    Some((offset, weights))
+}
+
+/// A single measured offset between NTP time and the local clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OffsetSample {
+    /// Time at which the offset was measured
+    pub timestamp: Timestamp,
+    /// Offset in seconds (positive when the local clock is behind NTP)
+    pub offset: f64,
+}
+
+/// Estimates the local oscillator's frequency error in parts per million from the slope of
+/// the offset history (positive when the local clock runs slow).
+///
+/// Uses a least-squares fit, clamped to ±[`MAX_DRIFT_PPM`]. Returns `None` with fewer than
+/// two samples or when they all share one timestamp.
+pub fn drift_ppm(history: &[OffsetSample]) -> Option<f64> {
+    let first = history.first()?.timestamp;
+    let points: Vec<(f64, f64)> = history
+        .iter()
+        .map(|s| (s.timestamp.seconds_since(first), s.offset))
+        .collect();
+    if points.len() < 2 {
+        return None;
+    }
+
+    let n = points.len() as f64;
+    let mean_t = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_o = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let (num, den) = points.iter().fold((0.0, 0.0), |(num, den), (t, o)| {
+        let dt = t - mean_t;
+        (num + dt * (o - mean_o), den + dt * dt)
+    });
+    if den == 0.0 {
+        return None;
+    }
+    Some((num / den * 1e6).clamp(-MAX_DRIFT_PPM, MAX_DRIFT_PPM))
+}
+
+/// Scales a locally measured duration by a frequency error in parts per million, using the
+/// sign convention of [`drift_ppm`]
+pub fn correct_for_drift(raw: Duration, drift_ppm: f64) -> Duration {
+    Duration::from_secs_f64((raw.as_secs_f64() * (1.0 + drift_ppm * 1e-6)).max(0.0))
+}
+
+/// Round-trip delays above which samples are discarded
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DelayLimits {
+    /// Absolute cap on the round-trip delay
+    pub max_delay: Option<Duration>,
+    /// Cap as a multiple of the smallest delay recently seen on the same path
+    pub max_ratio: Option<f64>,
+}
+
+/// Why a [`DelayFilter`] discarded a sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DelayRejection {
+    /// The delay exceeded [`DelayLimits::max_delay`]
+    AboveCap { delay: Duration, cap: Duration },
+    /// The delay exceeded [`DelayLimits::max_ratio`] times the recent minimum
+    AboveMinimum {
+        delay: Duration,
+        minimum: Duration,
+        ratio: f64,
+    },
+}
+
+impl fmt::Display for DelayRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DelayRejection::AboveCap { delay, cap } => {
+                write!(f, "round-trip delay {:?} exceeds the {:?} cap", delay, cap)
+            }
+            DelayRejection::AboveMinimum {
+                delay,
+                minimum,
+                ratio,
+            } => write!(
+                f,
+                "round-trip delay {:?} exceeds {}x the recent minimum of {:?}",
+                delay, ratio, minimum
+            ),
+        }
+    }
+}
+
+/// Tracks the round-trip delays seen on one path and discards samples whose delay is
+/// unusually long.
+///
+/// However the delay splits between the two directions, a sample's error is at most half
+/// its round trip. A man-in-the-middle who holds packets back on one leg can therefore shift
+/// the time by up to half the delay they add; capping the delay, absolutely or relative to
+/// the path's recent minimum, caps that shift.
+#[derive(Debug, Clone, Default)]
+pub struct DelayFilter {
+    recent: VecDeque<Duration>,
+}
+
+impl DelayFilter {
+    /// Smallest of the last [`DELAY_WINDOW`] delays, if any were recorded
+    pub fn minimum(&self) -> Option<Duration> {
+        self.recent.iter().min().copied()
+    }
+
+    /// Records `delay` and checks it against `limits`.
+    ///
+    /// Rejected delays are recorded too, so after a lasting route change the minimum catches
+    /// up within [`DELAY_WINDOW`] samples instead of rejecting the new path forever.
+    pub fn check(&mut self, delay: Duration, limits: &DelayLimits) -> Result<(), DelayRejection> {
+        if self.recent.len() == DELAY_WINDOW {
+            self.recent.pop_front();
+        }
+        let minimum = self.minimum();
+        self.recent.push_back(delay);
+
+        if let Some(cap) = limits.max_delay.filter(|cap| delay > *cap) {
+            return Err(DelayRejection::AboveCap { delay, cap });
+        }
+        if let (Some(ratio), Some(minimum)) = (limits.max_ratio, minimum) {
+            let allowed = minimum.mul_f64(ratio).max(minimum + DELAY_SLACK);
+            if delay > allowed {
+                return Err(DelayRejection::AboveMinimum {
+                    delay,
+                    minimum,
+                    ratio,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) use udp::random_u64;
+#[cfg(feature = "std")]
+pub use udp::{UdpTransport, DEFAULT_SOURCE_PORTS};
+
+#[cfg(feature = "std")]
+mod udp {
+    use super::{Transport, MAX_PACKET_LEN, PACKET_LEN};
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+    use std::ops::RangeInclusive;
+    use std::time::{Duration, Instant};
+
+    /// Source ports queries are sent from by default: the IANA dynamic port range
+    pub const DEFAULT_SOURCE_PORTS: RangeInclusive<u16> = 49152..=65535;
+
+    /// Random ports tried before leaving the choice to the operating system
+    const BIND_ATTEMPTS: usize = 16;
+
+    /// A random number from the operating system's CSPRNG
+    pub(crate) fn random_u64() -> u64 {
+        let mut bytes = [0u8; 8];
+        // As with std's hash map keys, there is no going on without randomness
+        getrandom::fill(&mut bytes).expect("the operating system has no random numbers");
+        u64::from_be_bytes(bytes)
+    }
+
+    /// The default transport: a fresh `std::net::UdpSocket` per exchange, bound to a random
+    /// source port so that an off-path attacker has to guess the port to spoof a reply.
+    ///
+    /// On 64-bit Linux the round trip is measured between the kernel's (or the NIC's)
+    /// transmit and receive timestamps of the packets rather than in userspace, which
+    /// removes scheduling noise from the measurement.
+    #[derive(Debug, Clone, Copy)]
+    pub struct UdpTransport {
+        timeout: Duration,
+        ports: (u16, u16),
+        kernel_timestamps: bool,
+        dscp: Option<u8>,
+        ttl: Option<u32>,
+        local_addr: Option<SocketAddr>,
+        receive_latency: Duration,
+    }
+
+    impl UdpTransport {
+        /// Creates a transport that gives up on a server after `timeout`
+        pub fn new(timeout: Duration) -> Self {
+            UdpTransport {
+                timeout,
+                ports: (*DEFAULT_SOURCE_PORTS.start(), *DEFAULT_SOURCE_PORTS.end()),
+                kernel_timestamps: true,
+                dscp: None,
+                ttl: None,
+                local_addr: None,
+                receive_latency: Duration::ZERO,
+            }
+        }
+
+        /// Whether to use kernel packet timestamps where the platform supports them (the
+        /// default), or always measure in userspace
+        pub fn with_kernel_timestamps(mut self, enabled: bool) -> Self {
+            self.kernel_timestamps = enabled;
+            self
+        }
+
+        /// Picks source ports at random from `ports` instead of [`DEFAULT_SOURCE_PORTS`]
+        pub fn with_source_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+            self.ports = (*ports.start(), *ports.end());
+            self
+        }
+
+        /// Marks queries with a DSCP code point (0-63), e.g. 46 for Expedited Forwarding, so
+        /// that the network can classify them. Only supported on Unix; ignored elsewhere.
+        pub fn with_dscp(mut self, dscp: Option<u8>) -> Self {
+            self.dscp = dscp;
+            self
+        }
+
+        /// Sets the TTL (IPv4) or hop limit (IPv6) of queries instead of the system default.
+        /// The IPv6 hop limit is only supported on Unix; it is ignored elsewhere.
+        pub fn with_ttl(mut self, ttl: Option<u32>) -> Self {
+            self.ttl = ttl;
+            self
+        }
+
+        /// Applies the configured DSCP and TTL to a socket of `server`'s address family
+        pub(super) fn set_ip_options(
+            &self,
+            socket: &UdpSocket,
+            server: &SocketAddr,
+        ) -> io::Result<()> {
+            if let Some(ttl) = self.ttl {
+                if server.is_ipv4() {
+                    socket.set_ttl(ttl)?;
+                } else {
+                    #[cfg(unix)]
+                    set_socket_option(socket, libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, ttl)?;
+                }
+            }
+            #[cfg(unix)]
+            if let Some(dscp) = self.dscp {
+                // The code point is the upper six bits of the TOS / traffic class byte
+                let class = u32::from(dscp) << 2;
+                if server.is_ipv4() {
+                    set_socket_option(socket, libc::IPPROTO_IP, libc::IP_TOS, class)?;
+                } else {
+                    set_socket_option(socket, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, class)?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Binds a socket of `server`'s address family to a random port of the configured
+        /// range, falling back to a port chosen by the operating system if the ones tried
+        /// are taken
+        fn bind(&self, server: &SocketAddr) -> io::Result<UdpSocket> {
+            // An IPv4 socket cannot reach an IPv6 server
+            let ip = if server.is_ipv6() {
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+            } else {
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+            };
+            let (first, last) = self.ports;
+            let count = u64::from(last.saturating_sub(first)) + 1;
+            for _ in 0..BIND_ATTEMPTS {
+                let port = first + (random_u64() % count) as u16;
+                match UdpSocket::bind((ip, port)) {
+                    Ok(socket) => return Ok(socket),
+                    // Windows reports ports reserved by other services as access denied
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::AddrInUse | io::ErrorKind::PermissionDenied
+                        ) =>
+                    {
+                        continue
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            UdpSocket::bind((ip, 0))
+        }
+
+        /// Local address the last exchange was sent from, as chosen by the routing table
+        pub fn local_addr(&self) -> Option<SocketAddr> {
+            self.local_addr
+        }
+    }
+
+    impl Default for UdpTransport {
+        fn default() -> Self {
+            Self::new(Duration::from_secs(3))
+        }
+    }
+
+    impl Transport for UdpTransport {
+        type Address = SocketAddr;
+        type Error = io::Error;
+
+        fn exchange(
+            &mut self,
+            server: &SocketAddr,
+            request: &[u8; PACKET_LEN],
+            reply: &mut [u8; PACKET_LEN],
+        ) -> io::Result<Duration> {
+            self.exchange_bytes(server, request, reply)
+                .map(|(_, delay)| delay)
+        }
+
+        fn exchange_packet(
+            &mut self,
+            server: &SocketAddr,
+            request: &[u8],
+            reply: &mut Vec<u8>,
+        ) -> io::Result<Duration> {
+            reply.clear();
+            reply.resize(MAX_PACKET_LEN, 0);
+            let result = self.exchange_bytes(server, request, reply);
+            reply.truncate(result.as_ref().map_or(0, |&(len, _)| len));
+            result.map(|(_, delay)| delay)
+        }
+
+        fn receive_latency(&self) -> Duration {
+            self.receive_latency
+        }
+
+        fn local_addr(&self) -> Option<SocketAddr> {
+            self.local_addr
+        }
+    }
+
+    impl UdpTransport {
+        /// Sends `request` and reads the reply into `reply`, returning the reply's length
+        /// and the round-trip time
+        fn exchange_bytes(
+            &mut self,
+            server: &SocketAddr,
+            request: &[u8],
+            reply: &mut [u8],
+        ) -> io::Result<(usize, Duration)> {
+            let socket = self.bind(server)?;
+            self.set_ip_options(&socket, server)?;
+            // On Windows a timed-out recv fails with TimedOut rather than WouldBlock; both
+            // are reported as errors
+            socket.set_read_timeout(Some(self.timeout))?;
+            socket.set_write_timeout(Some(self.timeout))?;
+            socket.connect(server)?;
+            self.local_addr = socket.local_addr().ok();
+            self.receive_latency = Duration::ZERO;
+
+            #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+            if self.kernel_timestamps && crate::timestamping::enable(&socket).is_ok() {
+                return self.exchange_timestamped(&socket, request, reply);
+            }
+            let sent_at = Instant::now();
+            socket.send(request)?;
+            let len = socket.recv(reply)?;
+            let delay = sent_at.elapsed();
+            check_reply_len(len)?;
+            Ok((len, delay))
+        }
+    }
+
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    impl UdpTransport {
+        /// Like the userspace exchange, taking the transmit and receive times from the
+        /// packets' kernel timestamps where they are available
+        fn exchange_timestamped(
+            &mut self,
+            socket: &UdpSocket,
+            request: &[u8],
+            reply: &mut [u8],
+        ) -> io::Result<(usize, Duration)> {
+            use crate::timestamping;
+            use std::time::{SystemTime, UNIX_EPOCH};
+
+            let sent_at = Instant::now();
+            socket.send(request)?;
+            let (len, received) = timestamping::recv(socket, reply)?;
+            let mut delay = sent_at.elapsed();
+            check_reply_len(len)?;
+
+            if let Some(arrived) = received.software {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                self.receive_latency = now.saturating_sub(arrived);
+                // Without a transmit timestamp, the round trip still ends on arrival
+                delay = delay.saturating_sub(self.receive_latency);
+            }
+            if let Some(kernel_delay) =
+                timestamping::sent(socket).and_then(|sent| received.since(&sent))
+            {
+                delay = kernel_delay;
+            }
+            Ok((len, delay))
+        }
+    }
+
+    /// Sets an integer socket option that std does not expose
+    #[cfg(unix)]
+    fn set_socket_option(
+        socket: &UdpSocket,
+        level: libc::c_int,
+        name: libc::c_int,
+        value: u32,
+    ) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let value = value as libc::c_int;
+        // SAFETY: `value` outlives the call and its size is passed along
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn check_reply_len(len: usize) -> io::Result<()> {
+        if len < PACKET_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("short NTP reply of {} bytes", len),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_TIMESTAMP;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    /// Replies with a fixed packet echoing the request's nonce after a fixed delay,
+    /// recording what was sent
+    struct LoopbackTransport {
+        reply: [u8; PACKET_LEN],
+        sent: Option<[u8; PACKET_LEN]>,
+    }
+
+    impl Transport for LoopbackTransport {
+        type Address = ();
+        type Error = &'static str;
+
+        fn exchange(
+            &mut self,
+            _server: &(),
+            request: &[u8; PACKET_LEN],
+            reply: &mut [u8; PACKET_LEN],
+        ) -> Result<Duration, &'static str> {
+            self.sent = Some(*request);
+            *reply = self.reply;
+            reply[24..32].copy_from_slice(&request[40..48]);
+            Ok(Duration::from_millis(40))
+        }
+    }
+
+    #[test]
+    fn test_parse_transmit_time() {
+        let mut buf = [0u8; PACKET_LEN];
+        assert_eq!(parse_transmit_time(&buf), None);
+
+        // 2000-01-01T00:00:00.5Z in NTP era 0
+        buf[40..44].copy_from_slice(&3_155_673_600u32.to_be_bytes());
+        buf[44..48].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+        assert_eq!(
+            parse_transmit_time(&buf),
+            Some(DEFAULT_TIMESTAMP.add_nanos(500_000_000))
+        );
+    }
+
+    #[test]
+    fn test_timestamps_without_the_top_bit_are_in_era_1() {
+        // Era 1 starts on 2036-02-07T06:28:16Z, when the seconds wrap to zero
+        assert_eq!(ntp_seconds_to_unix(0), 2_085_978_496);
+        assert_eq!(
+            ntp_seconds_to_unix(0x7fff_ffff),
+            2_085_978_496 + 0x7fff_ffff
+        );
+        // The top bit set is era 0, from 1968-01-20T03:14:08Z
+        assert_eq!(ntp_seconds_to_unix(0x8000_0000), -61_505_152);
+        assert_eq!(ntp_seconds_to_unix(u32::MAX), 2_085_978_495);
+
+        let mut buf = [0u8; PACKET_LEN];
+        for time in [
+            "2036-02-07T06:28:16.25Z",
+            "2040-01-01T00:00:00Z",
+            "2104-02-26T09:42:23Z",
+        ] {
+            let time: Timestamp = time.parse().unwrap();
+            buf[40..48].copy_from_slice(&encode_timestamp(time));
+            assert_eq!(parse_transmit_time(&buf), Some(time));
+        }
+        // The zero timestamp at the start of era 1 still means unset
+        buf[40..48].copy_from_slice(&[0; 8]);
+        assert_eq!(parse_transmit_time(&buf), None);
+    }
+
+    #[test]
+    fn test_ntp_packet_fields() {
+        let mut request = client_request_with_version(4);
+        request[2] = 6;
+        request[40..48].copy_from_slice(&encode_timestamp(DEFAULT_TIMESTAMP));
+        let state = ServerState {
+            stratum: 2,
+            root_delay: 0.5,
+            root_dispersion: 0.25,
+            reference_id: [192, 0, 2, 1],
+            reference_time: Some(DEFAULT_TIMESTAMP),
+        };
+        let reply = server_reply(&request, &state, DEFAULT_TIMESTAMP, DEFAULT_TIMESTAMP).unwrap();
+
+        let packet = NtpPacket::parse(&reply).unwrap();
+        assert_eq!((packet.leap, packet.version, packet.mode), (0, 4, 4));
+        assert_eq!((packet.stratum, packet.poll, packet.precision), (2, 6, -20));
+        assert_eq!((packet.root_delay, packet.root_dispersion), (0.5, 0.25));
+        assert_eq!(packet.reference_id, [192, 0, 2, 1]);
+        assert_eq!(packet.origin_timestamp, request[40..48]);
+        assert_eq!(packet.reference_time(), Some(DEFAULT_TIMESTAMP));
+        assert_eq!(packet.receive_time(), Some(DEFAULT_TIMESTAMP));
+        assert_eq!(packet.transmit_time(), Some(DEFAULT_TIMESTAMP));
+        assert_eq!(packet.encode(), reply);
+        assert!(!packet.is_client_request());
+        assert!(NtpPacket::from(&request).is_client_request());
+
+        // Extension fields after the header are left alone, a short packet is refused
+        let mut long = reply.to_vec();
+        long.extend_from_slice(&[0xff; 20]);
+        assert_eq!(NtpPacket::parse(&long), Some(packet));
+        assert_eq!(NtpPacket::parse(&reply[..PACKET_LEN - 1]), None);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_ntp_packet_round_trips_any_bytes(
+            bytes in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..128),
+        ) {
+            match NtpPacket::parse(&bytes) {
+                Some(packet) => {
+                    proptest::prop_assert_eq!(&packet.encode()[..], &bytes[..PACKET_LEN]);
+                    let _ = (packet.reference_time(), packet.receive_time());
+                    let _ = packet.transmit_time();
+                    let _ = crate::extension::parse_packet(&bytes);
+                }
+                None => proptest::prop_assert!(bytes.len() < PACKET_LEN),
+            }
+        }
+
+        #[test]
+        fn prop_parse_reply_accepts_any_bytes(bytes: [u8; PACKET_LEN], delay_nanos: u64) {
+            let delay = Duration::from_nanos(delay_nanos);
+            if let Some(sample) = parse_reply(&client_request(), &bytes, delay) {
+                proptest::prop_assert_eq!(sample.stratum, bytes[1]);
+                proptest::prop_assert!(sample.root_dispersion >= 0.0);
+                let _ = sample.kiss_code();
+            }
+            let _ = server_reply(&bytes, &ServerState {
+                stratum: 2,
+                root_delay: 0.0,
+                root_dispersion: 0.0,
+                reference_id: [0; 4],
+                reference_time: None,
+            }, DEFAULT_TIMESTAMP, DEFAULT_TIMESTAMP);
+        }
+
+        #[test]
+        fn prop_timestamps_round_trip_within_both_eras(
+            // 1968-01-20 to 2104-02-26, the range the eras of an NTP timestamp cover
+            secs in -61_505_152i64..4_233_462_144,
+            nanos in 0i128..1_000_000_000,
+        ) {
+            let time = Timestamp::from_unix_secs(secs).add_nanos(nanos);
+            let mut buf = [0u8; PACKET_LEN];
+            buf[40..48].copy_from_slice(&encode_timestamp(time));
+            if let Some(parsed) = parse_transmit_time(&buf) {
+                // The 32-bit fraction resolves about a quarter of a nanosecond
+                proptest::prop_assert!((time.nanos_since(parsed)).abs() <= 1, "{} {}", time, parsed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_server_reply_round_trips_through_parse_reply() {
+        let state = ServerState {
+            stratum: 3,
+            root_delay: 0.125,
+            root_dispersion: 0.25,
+            reference_id: [10, 0, 0, 1],
+            reference_time: Some(DEFAULT_TIMESTAMP),
+        };
+        let transmit = DEFAULT_TIMESTAMP.add_nanos(250_000_000);
+        let request = client_request();
+        let reply = server_reply(&request, &state, transmit, transmit).unwrap();
+        assert_eq!(reply[0] & 0x07, 4);
+        assert_eq!(&reply[24..32], &request[40..48]);
+        assert_eq!(parse_transmit_time(&reply), Some(transmit));
+
+        let sample = parse_reply(&request, &reply, Duration::ZERO).unwrap();
+        assert_eq!(sample.stratum, 3);
+        assert_eq!(sample.root_delay, 0.125);
+        assert_eq!(sample.root_dispersion, 0.25);
+        assert_eq!(sample.reference_id, [10, 0, 0, 1]);
+        assert!(sample.is_synchronized());
+
+        let unsynchronized = ServerState {
+            stratum: MAX_STRATUM,
+            ..state
+        };
+        let reply = server_reply(&request, &unsynchronized, transmit, transmit).unwrap();
+        assert_eq!(parse_reply(&request, &reply, Duration::ZERO), None);
+
+        // Only client requests are answered
+        assert_eq!(server_reply(&reply, &state, transmit, transmit), None);
+    }
+
+    #[test]
+    fn test_parse_reply_refuses_replies_that_do_not_answer_the_request() {
+        let state = ServerState {
+            stratum: 2,
+            root_delay: 0.0,
+            root_dispersion: 0.0,
+            reference_id: [10, 0, 0, 1],
+            reference_time: None,
+        };
+        let request = client_request();
+        assert_ne!(&request[40..48], &client_request()[40..48]);
+        let reply = server_reply(&request, &state, DEFAULT_TIMESTAMP, DEFAULT_TIMESTAMP).unwrap();
+        assert!(parse_reply(&request, &reply, Duration::ZERO).is_some());
+
+        // A reply to another request, or one that guessed wrong, is forged
+        assert_eq!(parse_reply(&client_request(), &reply, Duration::ZERO), None);
+        let mut forged = reply;
+        forged[24..32].fill(0);
+        assert_eq!(parse_reply(&request, &forged, Duration::ZERO), None);
+        // Only server mode answers
+        let mut forged = reply;
+        forged[0] = forged[0] & !0x07 | 5;
+        assert_eq!(parse_reply(&request, &forged, Duration::ZERO), None);
+        // Leap 3 means the server is unsynchronized
+        let mut unsynchronized = reply;
+        unsynchronized[0] |= 3 << 6;
+        assert_eq!(parse_reply(&request, &unsynchronized, Duration::ZERO), None);
+
+        // Stratum 0 is only a kiss-o'-death reply, which is kept for its code
+        let kiss = kiss_reply(&request, *b"RATE", DEFAULT_TIMESTAMP).unwrap();
+        let sample = parse_reply(&request, &kiss, Duration::ZERO).unwrap();
+        assert_eq!(sample.kiss_code(), Some("RATE"));
+        let mut no_code = kiss;
+        no_code[12..16].fill(0);
+        assert_eq!(parse_reply(&request, &no_code, Duration::ZERO), None);
+    }
+
+    #[test]
+    fn test_display_reference_id() {
+        let display = |stratum, id| display_reference_id(stratum, id).to_string();
+        assert_eq!(display(1, *b"GPS\0"), "GPS");
+        assert_eq!(display(1, *b"PPS\0"), "PPS");
+        assert_eq!(display(MAX_STRATUM, *b"INIT"), "INIT");
+        assert_eq!(display(2, [192, 0, 2, 1]), "192.0.2.1");
+        // Codes are only expected from reference clocks
+        assert_eq!(display(3, *b"GPS\0"), "71.80.83.0");
+        assert_eq!(display(1, [0, 0, 0, 0]), "0.0.0.0");
+    }
+
+    #[test]
+    fn test_query_corrects_for_half_the_round_trip() {
+        let mut reply = [0u8; PACKET_LEN];
+        reply[0] = 4;
+        reply[1] = 3;
+        reply[3] = -23i8 as u8;
+        reply[8..12].copy_from_slice(&0x0000_8000u32.to_be_bytes());
+        reply[12..16].copy_from_slice(&[192, 168, 1, 10]);
+        reply[40..44].copy_from_slice(&3_155_673_600u32.to_be_bytes());
+        let mut transport = LoopbackTransport { reply, sent: None };
+
+        let sample = query(&mut transport, &()).unwrap();
+        let sent = transport.sent.unwrap();
+        assert_eq!(sent[0], 3 << 3 | 3);
+        assert_ne!(&sent[40..48], &[0; 8]);
+        assert_eq!(sample.time, DEFAULT_TIMESTAMP + Duration::from_millis(20));
+        assert_eq!(sample.delay, Duration::from_millis(40));
+        assert_eq!(sample.root_dispersion, 0.5);
+        assert_eq!(sample.precision, -23);
+        assert!(sample.is_synchronized());
+        assert!(sample.is_synchronized_to([192, 168, 1, 10]));
+        assert!(!sample.is_synchronized_to([192, 168, 1, 11]));
+        assert_eq!(sample.kiss_code(), None);
+        let kiss_of_death = Measurement {
+            stratum: 0,
+            reference_id: *b"RATE",
+            ..sample
+        };
+        assert!(!kiss_of_death.is_synchronized());
+        assert_eq!(kiss_of_death.kiss_code(), Some("RATE"));
+
+        transport.reply = [0u8; PACKET_LEN];
+        assert!(matches!(
+            query(&mut transport, &()),
+            Err(QueryError::InvalidReply)
+        ));
+    }
+
+    impl AsyncTransport for LoopbackTransport {
+        type Address = ();
+        type Error = &'static str;
+
+        async fn exchange(
+            &mut self,
+            server: &(),
+            request: &[u8; PACKET_LEN],
+            reply: &mut [u8; PACKET_LEN],
+        ) -> Result<Duration, &'static str> {
+            Transport::exchange(self, server, request, reply)
+        }
+    }
+
+    #[test]
+    fn test_interleaved_reply_measures_previous_exchange() {
+        let server_receive = DEFAULT_TIMESTAMP;
+        let mut reply = [0u8; PACKET_LEN];
+        reply[1] = 2;
+        reply[32..40].copy_from_slice(&encode_timestamp(server_receive));
+        // The transmit timestamp of a basic reply is taken early
+        reply[40..48].copy_from_slice(&encode_timestamp(server_receive));
+        let received = DEFAULT_TIMESTAMP + Duration::from_secs(5);
+        let previous = InterleavedState::new(&reply, received, Duration::from_millis(40));
+
+        let request = interleaved_request(4, &previous, received + Duration::from_secs(1));
+        assert_eq!(request[0], 4 << 3 | 3);
+        assert_eq!(&request[24..32], &encode_timestamp(server_receive));
+
+        // The reply to it echoes the client's receive timestamp and carries the precise
+        // transmit timestamp of the previous reply, which left 10 ms after its request came
+        let mut interleaved = [0u8; PACKET_LEN];
+        interleaved[0] = 4;
+        interleaved[1] = 2;
+        interleaved[24..32].copy_from_slice(&request[32..40]);
+        interleaved[32..40].copy_from_slice(&encode_timestamp(server_receive));
+        let precise = server_receive + Duration::from_millis(10);
+        interleaved[40..48].copy_from_slice(&encode_timestamp(precise));
+        assert!(is_interleaved_reply(&request, &interleaved));
+        let sample =
+            parse_interleaved_reply(&interleaved, &previous, Duration::from_millis(100)).unwrap();
+        // The 32-bit fractions resolve about a quarter of a nanosecond
+        assert!(sample.delay.abs_diff(Duration::from_millis(30)) <= Duration::from_nanos(2));
+        let expected = precise + Duration::from_millis(15 + 100);
+        assert!(
+            sample.time.nanos_since(expected).abs() <= 1,
+            "{}",
+            sample.time
+        );
+        assert_eq!(sample.stratum, 2);
+
+        // A basic reply echoes the transmit timestamp instead
+        let basic = server_reply(
+            &request,
+            &ServerState {
+                stratum: 2,
+                root_delay: 0.0,
+                root_dispersion: 0.0,
+                reference_id: [0; 4],
+                reference_time: None,
+            },
+            received,
+            received,
+        )
+        .unwrap();
+        assert!(!is_interleaved_reply(&request, &basic));
+        assert!(!is_interleaved_reply(&client_request(), &[0; PACKET_LEN]));
+    }
+
+    #[test]
+    fn test_query_async_matches_blocking_query() {
+        let mut reply = [0u8; PACKET_LEN];
+        reply[0] = 4;
+        reply[1] = 2;
+        reply[40..44].copy_from_slice(&3_155_673_600u32.to_be_bytes());
+        let mut transport = LoopbackTransport { reply, sent: None };
+
+        let blocking = query(&mut transport, &()).unwrap();
+        let future = pin!(query_async(&mut transport, &(), [1; 8]));
+        let mut cx = Context::from_waker(Waker::noop());
+        let Poll::Ready(Ok(sample)) = Future::poll(future, &mut cx) else {
+            panic!("loopback exchange should complete immediately");
+        };
+        assert_eq!(sample, blocking);
+    }
+
+    #[test]
+    fn test_drift_ppm_fits_offset_slope() {
+        let history: Vec<OffsetSample> = (0..10)
+            .map(|i| OffsetSample {
+                timestamp: DEFAULT_TIMESTAMP + Duration::from_secs(100 * i),
+                offset: 0.5 + i as f64 * 100.0 * 20e-6,
+            })
+            .collect();
+        let drift = drift_ppm(&history).unwrap();
+        assert!((drift - 20.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_drift_ppm_needs_distinct_timestamps() {
+        let sample = OffsetSample {
+            timestamp: DEFAULT_TIMESTAMP,
+            offset: 0.1,
+        };
+        assert_eq!(drift_ppm(&[sample]), None);
+        assert_eq!(drift_ppm(&[sample, sample]), None);
+    }
+
+    #[test]
+    fn test_delay_filter_bounds_delay() {
+        let ms = Duration::from_millis;
+        let limits = DelayLimits {
+            max_delay: Some(ms(500)),
+            max_ratio: Some(3.0),
+        };
+        let mut filter = DelayFilter::default();
+        assert_eq!(filter.check(ms(20), &limits), Ok(()));
+        assert_eq!(filter.check(ms(55), &limits), Ok(()));
+        assert_eq!(
+            filter.check(ms(61), &limits),
+            Err(DelayRejection::AboveMinimum {
+                delay: ms(61),
+                minimum: ms(20),
+                ratio: 3.0
+            })
+        );
+        assert_eq!(
+            filter.check(ms(600), &limits),
+            Err(DelayRejection::AboveCap {
+                delay: ms(600),
+                cap: ms(500)
+            })
+        );
+        assert_eq!(filter.check(ms(600), &DelayLimits::default()), Ok(()));
+    }
+
+    #[test]
+    fn test_delay_filter_follows_route_changes() {
+        let ms = Duration::from_millis;
+        let limits = DelayLimits {
+            max_delay: None,
+            max_ratio: Some(2.0),
+        };
+        let mut filter = DelayFilter::default();
+        filter.check(ms(10), &limits).unwrap();
+        for _ in 1..DELAY_WINDOW {
+            assert!(filter.check(ms(40), &limits).is_err());
+        }
+        assert_eq!(filter.check(ms(40), &limits), Ok(()));
+        assert_eq!(filter.minimum(), Some(ms(40)));
+
+        // Sub-millisecond jitter on a fast link is within the slack
+        let mut filter = DelayFilter::default();
+        filter.check(Duration::from_micros(100), &limits).unwrap();
+        assert_eq!(filter.check(Duration::from_micros(900), &limits), Ok(()));
+    }
+
+    #[test]
+    fn test_combine_measurements_takes_median_without_outliers() {
+        let start: Timestamp = "2026-02-03T00:00:00Z".parse().unwrap();
+        let ms = Duration::from_millis;
+        // Offsets of 0, +2, -1, +1, and an outlier of +900 ms, spaced 200 ms apart
+        let samples: Vec<(Measurement, Duration)> = [0i128, 2, -1, 1, 900]
+            .iter()
+            .enumerate()
+            .map(|(i, offset)| {
+                let elapsed = ms(200 * i as u64);
+                let measurement = Measurement {
+                    time: (start + elapsed).add_nanos(offset * 1_000_000),
+                    delay: ms(20 + i as u64),
+                    root_delay: 0.0,
+                    root_dispersion: 0.01 - i as f64 * 0.001,
+                    stratum: 2,
+                    reference_id: [10, 0, 0, 1],
+                    precision: -20,
+                };
+                (measurement, elapsed)
+            })
+            .collect();
+
+        let combined = combine_measurements(&samples).unwrap();
+        // Median of 0, 2, -1, 1 is 0.5 ms, dated at the last sample
+        assert_eq!(combined.time, (start + ms(800)).add_nanos(500_000));
+        assert_eq!(combined.delay, ms(20));
+        assert!((combined.root_dispersion - 0.007).abs() < 1e-12);
+
+        assert_eq!(combine_measurements(&samples[..1]), Some(samples[0].0));
+        assert_eq!(combine_measurements(&[]), None);
+    }
+
+    #[test]
+    fn test_sample_jitter() {
+        let start: Timestamp = "2026-02-03T00:00:00Z".parse().unwrap();
+        let samples: Vec<(Measurement, Duration)> = [-3i128, 0, 3]
+            .iter()
+            .map(|offset| {
+                let measurement = Measurement {
+                    time: start.add_nanos(offset * 1_000_000),
+                    delay: Duration::ZERO,
+                    root_delay: 0.0,
+                    root_dispersion: 0.0,
+                    stratum: 2,
+                    reference_id: [10, 0, 0, 1],
+                    precision: -20,
+                };
+                (measurement, Duration::ZERO)
+            })
+            .collect();
+        // RMS of -3, 0, and +3 ms is sqrt(6) ms
+        assert!((sample_jitter(&samples) - 6e-6_f64.sqrt()).abs() < 1e-12);
+        assert_eq!(sample_jitter(&samples[..1]), 0.0);
+    }
+
+    #[test]
+    fn test_select_and_combine_sources() {
+        let source = |offset, root_distance| SourceEstimate {
+            offset,
+            root_distance,
+            jitter: 0.0,
+        };
+        // Three servers agree around +10 ms; one falseticker is 2 s off
+        let sources = [
+            source(0.010, 0.020),
+            source(0.012, 0.040),
+            source(2.0, 0.020),
+            source(0.008, 0.020),
+        ];
+        let selected = select_truechimers(&sources);
+        assert_eq!(selected, [0, 1, 3]);
+
+        let (offset, weights) = combine_offsets(&sources, &selected).unwrap();
+        assert_eq!(weights.len(), 3);
+        assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!((weights[0] - 0.4).abs() < 1e-12 && (weights[1] - 0.2).abs() < 1e-12);
+        assert!((offset - (0.010 * 0.4 + 0.012 * 0.2 + 0.008 * 0.4)).abs() < 1e-12);
+
+        // No majority when two servers disagree
+        assert!(select_truechimers(&sources[1..3]).is_empty());
+        assert_eq!(combine_offsets(&sources, &[]), None);
+    }
+
+    #[cfg(all(feature = "std", unix))]
+    #[test]
+    fn test_udp_transport_sets_dscp_and_ttl() {
+        use std::net::{SocketAddr, UdpSocket};
+        use std::os::fd::AsRawFd;
+
+        let transport = UdpTransport::default()
+            .with_dscp(Some(46))
+            .with_ttl(Some(7));
+        let server: SocketAddr = "127.0.0.1:123".parse().unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        transport.set_ip_options(&socket, &server).unwrap();
+        assert_eq!(socket.ttl().unwrap(), 7);
+
+        let mut tos: libc::c_int = 0;
+        let mut len = core::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: `tos` and `len` outlive the call and `len` holds the size of `tos`
+        let result = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_TOS,
+                &mut tos as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(result, 0);
+        // EF in the upper six bits
+        assert_eq!(tos, 0xb8);
+    }
+
+    #[test]
+    fn test_correct_for_drift() {
+        let day = Duration::from_secs(86_400);
+        // A clock running 20 ppm slow loses 1.728 s per day
+        let corrected = correct_for_drift(day, 20.0);
+        assert!((corrected.as_secs_f64() - 86_401.728).abs() < 1e-6);
+        assert_eq!(correct_for_drift(day, 0.0), day);
+    }
+}
@@ -0,0 +1,122 @@
+//! # Stability Analysis
+//!
+//! Allan deviation over the clock's measured offset history. The offsets are treated as
+//! phase (time error) samples taken once per sync cycle, so the result describes how stable
+//! the local oscillator is between NTP polls.
+
+pub use crate::sntp::{drift_ppm, OffsetSample, MAX_DRIFT_PPM};
+use std::time::Duration;
+
+/// Allan deviation at one averaging time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StabilityPoint {
+    /// Averaging time (tau)
+    pub tau: Duration,
+    /// Overlapping Allan deviation (dimensionless fractional frequency)
+    pub adev: f64,
+    /// Number of second differences that contributed to the estimate
+    pub samples: usize,
+}
+
+/// Computes the overlapping Allan deviation of phase data for an averaging factor `m`.
+///
+/// `phases` are time errors in seconds sampled every `tau0` seconds. Returns `None` when
+/// there are not enough samples (at least `2 * m + 1` are required).
+pub fn allan_deviation(phases: &[f64], tau0: f64, m: usize) -> Option<f64> {
+    if m == 0 || tau0 <= 0.0 || phases.len() < 2 * m + 1 {
+        return None;
+    }
+
+    let terms = phases.len() - 2 * m;
+    let sum: f64 = (0..terms)
+        .map(|i| {
+            let d = phases[i + 2 * m] - 2.0 * phases[i + m] + phases[i];
+            d * d
+        })
+        .sum();
+
+    let tau = m as f64 * tau0;
+    Some((sum / (2.0 * tau * tau * terms as f64)).sqrt())
+}
+
+/// Computes Allan deviation at octave-spaced taus (tau0, 2·tau0, 4·tau0, ...) for a history
+/// of offset samples.
+///
+/// The base interval `tau0` is the median spacing between consecutive samples, which keeps
+/// the estimate sensible when an occasional sync cycle was delayed or skipped.
+pub fn analyze(history: &[OffsetSample]) -> Vec<StabilityPoint> {
+    if history.len() < 3 {
+        return Vec::new();
+    }
+
+    let mut spacings: Vec<f64> = history
+        .windows(2)
+        .map(|w| w[1].timestamp.seconds_since(w[0].timestamp))
+        .filter(|s| *s > 0.0)
+        .collect();
+    if spacings.is_empty() {
+        return Vec::new();
+    }
+    spacings.sort_by(|a, b| a.total_cmp(b));
+    let tau0 = spacings[spacings.len() / 2];
+
+    let phases: Vec<f64> = history.iter().map(|s| s.offset).collect();
+    let mut points = Vec::new();
+    let mut m = 1;
+    while let Some(adev) = allan_deviation(&phases, tau0, m) {
+        points.push(StabilityPoint {
+            tau: Duration::from_secs_f64(m as f64 * tau0),
+            adev,
+            samples: phases.len() - 2 * m,
+        });
+        m *= 2;
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allan_deviation_constant_offset_is_zero() {
+        let phases = vec![0.25; 16];
+        assert_eq!(allan_deviation(&phases, 10.0, 1), Some(0.0));
+    }
+
+    #[test]
+    fn test_allan_deviation_linear_drift_is_zero() {
+        // A constant frequency offset produces a linear phase ramp, which ADEV ignores
+        let phases: Vec<f64> = (0..32).map(|i| i as f64 * 1e-6).collect();
+        let adev = allan_deviation(&phases, 1.0, 2).unwrap();
+        assert!(adev.abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_allan_deviation_alternating_phase() {
+        let phases: Vec<f64> = (0..9).map(|i| if i % 2 == 0 { 0.0 } else { 1.0 }).collect();
+        // Every second difference is ±2, so AVAR = 4 / 2 = 2
+        let adev = allan_deviation(&phases, 1.0, 1).unwrap();
+        assert!((adev - 2f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_analyze_uses_octave_taus() {
+        let start = crate::DEFAULT_TIMESTAMP;
+        let history: Vec<OffsetSample> = (0..20)
+            .map(|i| OffsetSample {
+                timestamp: start + Duration::from_secs(10 * i),
+                offset: (i % 3) as f64 * 1e-3,
+            })
+            .collect();
+
+        let points = analyze(&history);
+        let taus: Vec<u64> = points.iter().map(|p| p.tau.as_secs()).collect();
+        assert_eq!(taus, vec![10, 20, 40, 80]);
+    }
+
+    #[test]
+    fn test_analyze_requires_three_samples() {
+        assert!(analyze(&[]).is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+//! # Rolling Statistics
+//!
+//! [`SyncStats`](crate::SyncStats) counts sync attempts since the clock started, or since
+//! [`Clock::reset_stats`](crate::Clock::reset_stats). Alongside those lifetime counters,
+//! attempts are counted in one-minute buckets over the last day, so
+//! [`Clock::window_stats`](crate::Clock::window_stats) can tell how the last hour
+//! ([`LAST_HOUR`]) or day ([`LAST_DAY`]) went, to the minute.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The last hour, see [`Clock::window_stats`](crate::Clock::window_stats)
+pub const LAST_HOUR: Duration = Duration::from_secs(3600);
+
+/// The last 24 hours, the longest window kept
+pub const LAST_DAY: Duration = Duration::from_secs(86_400);
+
+/// Resolution of the rolling windows
+const BUCKET: Duration = Duration::from_secs(60);
+
+/// Sync attempts within a rolling window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WindowStats {
+    /// The window counted, at most [`LAST_DAY`]
+    pub window: Duration,
+    pub total_attempts: u64,
+    pub successful_syncs: u64,
+    pub failed_syncs: u64,
+}
+
+impl WindowStats {
+    /// Success rate as a percentage, 0 without attempts
+    pub fn success_rate(&self) -> f64 {
+        if self.total_attempts == 0 {
+            0.0
+        } else {
+            (self.successful_syncs as f64 / self.total_attempts as f64) * 100.0
+        }
+    }
+}
+
+/// Attempts and successes in one bucket
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// Buckets since [`RollingCounts::start`]
+    index: u64,
+    attempts: u64,
+    successes: u64,
+}
+
+/// Per-minute attempt counts over the last [`LAST_DAY`]
+#[derive(Debug)]
+pub(crate) struct RollingCounts {
+    start: Instant,
+    /// Oldest first; minutes without attempts have no bucket
+    buckets: VecDeque<Bucket>,
+}
+
+impl RollingCounts {
+    pub(crate) fn new() -> Self {
+        RollingCounts {
+            start: Instant::now(),
+            buckets: VecDeque::new(),
+        }
+    }
+
+    fn index(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_secs() / BUCKET.as_secs()
+    }
+
+    pub(crate) fn record(&mut self, success: bool) {
+        self.record_at(Instant::now(), success);
+    }
+
+    fn record_at(&mut self, now: Instant, success: bool) {
+        let index = self.index(now);
+        if self
+            .buckets
+            .back()
+            .is_none_or(|bucket| bucket.index != index)
+        {
+            self.buckets.push_back(Bucket {
+                index,
+                attempts: 0,
+                successes: 0,
+            });
+        }
+        if let Some(bucket) = self.buckets.back_mut() {
+            bucket.attempts += 1;
+            bucket.successes += success as u64;
+        }
+        let kept = LAST_DAY.as_secs() / BUCKET.as_secs();
+        while self
+            .buckets
+            .front()
+            .is_some_and(|bucket| bucket.index + kept <= index)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    pub(crate) fn window(&self, window: Duration) -> WindowStats {
+        self.window_at(Instant::now(), window)
+    }
+
+    /// Counts the buckets overlapping the last `window`, including the current one
+    fn window_at(&self, now: Instant, window: Duration) -> WindowStats {
+        let window = window.min(LAST_DAY);
+        let span = window.as_secs().div_ceil(BUCKET.as_secs());
+        let index = self.index(now);
+        let mut stats = WindowStats {
+            window,
+            ..WindowStats::default()
+        };
+        for bucket in self.buckets.iter().filter(|b| b.index + span > index) {
+            stats.total_attempts += bucket.attempts;
+            stats.successful_syncs += bucket.successes;
+        }
+        stats.failed_syncs = stats.total_attempts - stats.successful_syncs;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolling_windows() {
+        let mut counts = RollingCounts::new();
+        let start = counts.start;
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let (t0, t1, t2) = (at(0), at(7200), at(7230));
+        counts.record_at(t0, true);
+        counts.record_at(t1, false);
+        counts.record_at(t2, true);
+
+        let hour = counts.window_at(t2, LAST_HOUR);
+        assert_eq!((hour.total_attempts, hour.failed_syncs), (2, 1));
+        assert_eq!(hour.success_rate(), 50.0);
+        let day = counts.window_at(t2, Duration::from_secs(10 * 86_400));
+        assert_eq!((day.window, day.total_attempts), (LAST_DAY, 3));
+
+        // More than a day after the first attempt, its bucket is dropped
+        let later = at(7230 + 86_340);
+        counts.record_at(later, true);
+        assert_eq!(counts.window_at(later, LAST_DAY).total_attempts, 3);
+        assert_eq!(counts.buckets.len(), 2);
+    }
+}
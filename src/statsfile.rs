@@ -0,0 +1,355 @@
+//! # Statistics Files
+//!
+//! Writes sync measurements to disk in ntpd's `loopstats`/`peerstats` layout (or CSV), so
+//! existing NTP analysis tooling such as `ntpviz` can consume them. Files can be rotated
+//! daily using ntpd's `name.YYYYMMDD` naming scheme.
+
+use crate::stability::OffsetSample;
+use crate::Timestamp;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Days between the Modified Julian Date epoch (1858-11-17) and the Unix epoch
+const MJD_UNIX_EPOCH: i64 = 40_587;
+
+/// Peer status word written for accepted samples: configured, reachable, system peer
+const PEER_STATUS_SYS_PEER: u16 = 0x9600;
+
+/// Number of recent offsets used for jitter estimates
+const JITTER_WINDOW: usize = 8;
+
+/// On-disk record layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatsFormat {
+    /// Whitespace-separated records identical to ntpd's statistics files
+    #[default]
+    Ntpd,
+    /// Comma-separated records with a header line
+    Csv,
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ntpd" => Ok(StatsFormat::Ntpd),
+            "csv" => Ok(StatsFormat::Csv),
+            other => Err(format!(
+                "unknown stats format '{}', expected ntpd or csv",
+                other
+            )),
+        }
+    }
+}
+
+/// How statistics files are rotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    /// Append to a single file forever
+    Never,
+    /// Start a new file each UTC day, suffixed with `.YYYYMMDD`
+    #[default]
+    Daily,
+}
+
+/// One peerstats record (a single accepted server sample)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerRecord {
+    pub time: Timestamp,
+    pub addr: SocketAddr,
+    /// Offset in seconds
+    pub offset: f64,
+    /// Round-trip delay in seconds
+    pub delay: f64,
+    /// Dispersion in seconds
+    pub dispersion: f64,
+    /// RMS jitter in seconds
+    pub jitter: f64,
+}
+
+/// One loopstats record (the state of the local clock after a sync)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopRecord {
+    pub time: Timestamp,
+    /// Offset in seconds
+    pub offset: f64,
+    /// Frequency error in PPM
+    pub frequency: f64,
+    /// RMS jitter in seconds
+    pub jitter: f64,
+    /// RMS frequency wander in PPM
+    pub wander: f64,
+    /// Loop time constant (log2 of the mean poll interval)
+    pub time_constant: u32,
+}
+
+impl LoopRecord {
+    /// Derives a loop record from the offset history. Needs at least two samples.
+    pub fn from_history(history: &[OffsetSample]) -> Option<Self> {
+        let frequencies = frequencies(history);
+        let last = history.last()?;
+        let frequency = *frequencies.last()?;
+
+        let wander = if frequencies.len() < 2 {
+            0.0
+        } else {
+            let recent = &frequencies[frequencies.len().saturating_sub(JITTER_WINDOW)..];
+            rms(&recent.windows(2).map(|w| w[1] - w[0]).collect::<Vec<_>>())
+        };
+
+        let first = &history[history.len().saturating_sub(JITTER_WINDOW)];
+        let span = (last.timestamp.nanos_since(first.timestamp) / 1_000_000_000) as i64;
+        let intervals = (history.len() - 1).clamp(1, JITTER_WINDOW - 1) as i64;
+        let mean_poll = (span / intervals).max(1) as u64;
+
+        Some(LoopRecord {
+            time: last.timestamp,
+            offset: last.offset,
+            frequency,
+            jitter: offset_jitter(history),
+            wander,
+            time_constant: mean_poll.ilog2(),
+        })
+    }
+}
+
+/// RMS of the differences between recent consecutive offsets, in seconds
+pub fn offset_jitter(history: &[OffsetSample]) -> f64 {
+    let recent = &history[history.len().saturating_sub(JITTER_WINDOW)..];
+    let diffs: Vec<f64> = recent
+        .windows(2)
+        .map(|w| w[1].offset - w[0].offset)
+        .collect();
+    rms(&diffs)
+}
+
+/// Frequency error in PPM between each pair of consecutive samples
+fn frequencies(history: &[OffsetSample]) -> Vec<f64> {
+    history
+        .windows(2)
+        .filter_map(|w| {
+            let dt = w[1].timestamp.seconds_since(w[0].timestamp);
+            (dt > 0.0).then(|| (w[1].offset - w[0].offset) / dt * 1_000_000.0)
+        })
+        .collect()
+}
+
+fn rms(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    (values.iter().map(|v| v * v).sum::<f64>() / values.len() as f64).sqrt()
+}
+
+/// Returns the Modified Julian Date and seconds past UTC midnight
+fn mjd_and_seconds(time: &Timestamp) -> (i64, f64) {
+    let mjd = time.unix_secs().div_euclid(86_400) + MJD_UNIX_EPOCH;
+    let (_, _, _, secs) = time.to_civil();
+    let seconds = secs as f64 + time.subsec_nanos() as f64 / 1e9;
+    (mjd, seconds)
+}
+
+/// Appends statistics records to files in a directory
+#[derive(Debug, Clone)]
+pub struct StatsLogger {
+    dir: PathBuf,
+    format: StatsFormat,
+    rotation: Rotation,
+}
+
+impl StatsLogger {
+    /// Creates a logger writing into `dir`, creating the directory if needed
+    pub fn new(dir: impl AsRef<Path>) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(StatsLogger {
+            dir: dir.as_ref().to_path_buf(),
+            format: StatsFormat::default(),
+            rotation: Rotation::default(),
+        })
+    }
+
+    /// Sets the record format
+    pub fn with_format(mut self, format: StatsFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the rotation scheme
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Returns the file a record for `time` would be written to
+    pub fn file_path(&self, name: &str, time: &Timestamp) -> PathBuf {
+        let mut file = name.to_string();
+        if self.rotation == Rotation::Daily {
+            let (year, month, day, _) = time.to_civil();
+            file.push_str(&format!(".{:04}{:02}{:02}", year, month, day));
+        }
+        if self.format == StatsFormat::Csv {
+            file.push_str(".csv");
+        }
+        self.dir.join(file)
+    }
+
+    /// Appends a peerstats record
+    pub fn log_peer(&self, record: &PeerRecord) -> io::Result<()> {
+        let (mjd, secs) = mjd_and_seconds(&record.time);
+        let line = match self.format {
+            StatsFormat::Ntpd => format!(
+                "{} {:.3} {} {:04x} {:.9} {:.9} {:.9} {:.9}",
+                mjd,
+                secs,
+                record.addr.ip(),
+                PEER_STATUS_SYS_PEER,
+                record.offset,
+                record.delay,
+                record.dispersion,
+                record.jitter
+            ),
+            StatsFormat::Csv => format!(
+                "{},{},{:.9},{:.9},{:.9},{:.9}",
+                record.time.to_rfc3339(),
+                record.addr,
+                record.offset,
+                record.delay,
+                record.dispersion,
+                record.jitter
+            ),
+        };
+        self.append(
+            "peerstats",
+            &record.time,
+            "time,address,offset,delay,dispersion,jitter",
+            &line,
+        )
+    }
+
+    /// Appends a loopstats record
+    pub fn log_loop(&self, record: &LoopRecord) -> io::Result<()> {
+        let (mjd, secs) = mjd_and_seconds(&record.time);
+        let line = match self.format {
+            StatsFormat::Ntpd => format!(
+                "{} {:.3} {:.9} {:.3} {:.9} {:.6} {}",
+                mjd,
+                secs,
+                record.offset,
+                record.frequency,
+                record.jitter,
+                record.wander,
+                record.time_constant
+            ),
+            StatsFormat::Csv => format!(
+                "{},{:.9},{:.3},{:.9},{:.6},{}",
+                record.time.to_rfc3339(),
+                record.offset,
+                record.frequency,
+                record.jitter,
+                record.wander,
+                record.time_constant
+            ),
+        };
+        self.append(
+            "loopstats",
+            &record.time,
+            "time,offset,frequency,jitter,wander,time_constant",
+            &line,
+        )
+    }
+
+    fn append(&self, name: &str, time: &Timestamp, header: &str, line: &str) -> io::Result<()> {
+        let path = self.file_path(name, time);
+        let is_new = !path.exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if is_new && self.format == StatsFormat::Csv {
+            writeln!(file, "{}", header)?;
+        }
+        writeln!(file, "{}", line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("clock-ntp-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn peer_record() -> PeerRecord {
+        PeerRecord {
+            time: "2026-02-03T06:50:57Z".parse().unwrap(),
+            addr: "192.0.2.1:123".parse().unwrap(),
+            offset: -0.0016,
+            delay: 0.021,
+            dispersion: 0.0014,
+            jitter: 0.0009,
+        }
+    }
+
+    #[test]
+    fn test_peerstats_ntpd_format() {
+        let dir = temp_dir("peer");
+        let logger = StatsLogger::new(&dir).unwrap();
+        let record = peer_record();
+        logger.log_peer(&record).unwrap();
+
+        let path = logger.file_path("peerstats", &record.time);
+        assert!(path.ends_with("peerstats.20260203"));
+        let contents = fs::read_to_string(path).unwrap();
+        assert_eq!(
+            contents.trim(),
+            "61074 24657.000 192.0.2.1 9600 -0.001600000 0.021000000 0.001400000 0.000900000"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_csv_writes_header_once() {
+        let dir = temp_dir("csv");
+        let logger = StatsLogger::new(&dir)
+            .unwrap()
+            .with_format(StatsFormat::Csv)
+            .with_rotation(Rotation::Never);
+        let record = peer_record();
+        logger.log_peer(&record).unwrap();
+        logger.log_peer(&record).unwrap();
+
+        let contents = fs::read_to_string(dir.join("peerstats.csv")).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("time,"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_loop_record_from_history() {
+        let start: Timestamp = "2026-02-03T00:00:00Z".parse().unwrap();
+        let history: Vec<OffsetSample> = (0..4)
+            .map(|i| OffsetSample {
+                timestamp: start + Duration::from_secs(64 * i),
+                offset: i as f64 * 64e-6,
+            })
+            .collect();
+
+        let record = LoopRecord::from_history(&history).unwrap();
+        assert!((record.frequency - 1.0).abs() < 1e-9);
+        assert!(record.wander.abs() < 1e-9);
+        assert_eq!(record.time_constant, 6);
+        assert!(LoopRecord::from_history(&history[..1]).is_none());
+    }
+
+    #[test]
+    fn test_stats_format_from_str() {
+        assert_eq!("CSV".parse::<StatsFormat>(), Ok(StatsFormat::Csv));
+        assert!("xml".parse::<StatsFormat>().is_err());
+    }
+}
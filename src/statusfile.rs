@@ -0,0 +1,202 @@
+//! # Status File
+//!
+//! Containers check a process's health by running a command inside it, and sidecars share
+//! files rather than ports. With [`Clock::set_status_file`](crate::Clock::set_status_file)
+//! the clock rewrites a small JSON [`Status`] after every sync cycle, replacing the file
+//! atomically so that readers never see a partial write. [`check`] is its counterpart for
+//! `clock-ntp healthcheck` in a Docker `HEALTHCHECK`: the daemon keeps syncing, and the
+//! probe only reads the file.
+//!
+//! The file is a single flat object, such as
+//!
+//! ```json
+//! {"healthy":true,"health":"healthy","synchronized":true,"source":"NTP-verified",
+//!  "stratum":2,"time":"2026-10-16T09:30:00.123456789Z","last_offset":-0.0012,
+//!  "last_success":"2026-10-16T09:30:00.123Z","consecutive_failures":0,"poll_interval":64}
+//! ```
+//!
+//! A file that is not rewritten for [`DEFAULT_STALENESS_FACTOR`] poll intervals belongs to
+//! a daemon that hung or exited, and fails the check even if it last said healthy.
+
+use crate::health::{Health, DEFAULT_STALENESS_FACTOR};
+use crate::json::{json_number, json_string};
+use crate::{TimeSource, Timestamp};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Where `clock-ntp healthcheck` looks for the status file unless told otherwise
+pub const DEFAULT_STATUS_FILE: &str = "/run/clock.json";
+
+/// What the clock writes to its status file after each cycle
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub health: Health,
+    pub synchronized: bool,
+    pub source: TimeSource,
+    pub stratum: u8,
+    /// The clock's time when the status was taken
+    pub time: Timestamp,
+    /// Offset of the last synced time from the clock's reading just before, in seconds
+    pub last_offset: Option<f64>,
+    /// System time of the last successful sync
+    pub last_success: Option<Timestamp>,
+    pub consecutive_failures: u64,
+    /// Time until the next cycle rewrites the file
+    pub poll_interval: Duration,
+}
+
+impl Status {
+    /// Renders the status as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"healthy\":{},\"health\":{},\"synchronized\":{},\"source\":{},\"stratum\":{},\
+             \"time\":{},\"last_offset\":{},\"last_success\":{},\"consecutive_failures\":{},\
+             \"poll_interval\":{}}}\n",
+            self.health.is_healthy(),
+            json_string(&self.health.to_string()),
+            self.synchronized,
+            json_string(&self.source.to_string()),
+            self.stratum,
+            json_string(&self.time.to_rfc3339()),
+            json_number(self.last_offset),
+            self.last_success
+                .map_or_else(|| "null".to_string(), |t| json_string(&t.to_rfc3339())),
+            self.consecutive_failures,
+            self.poll_interval.as_secs()
+        )
+    }
+}
+
+/// Replaces the file at `path` with `status`, by writing a temporary file next to it and
+/// renaming it over the old one
+pub fn write(path: &Path, status: &Status) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, status.to_json())?;
+    fs::rename(&tmp, path)
+}
+
+/// Checks the status file at `path` for a healthcheck: passes if the clock last reported
+/// itself healthy and the file was rewritten within `max_age`, by default
+/// [`DEFAULT_STALENESS_FACTOR`] times the poll interval it records. The error says why
+/// the check failed.
+pub fn check(path: &Path, max_age: Option<Duration>) -> Result<(), String> {
+    let unreadable = |e: io::Error| format!("cannot read {}: {}", path.display(), e);
+    let contents = fs::read_to_string(path).map_err(unreadable)?;
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(unreadable)?;
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or(Duration::ZERO);
+
+    let max_age = match max_age {
+        Some(max_age) => max_age,
+        None => {
+            let poll_interval = field(&contents, "poll_interval")
+                .and_then(|value| value.parse::<u64>().ok())
+                .ok_or_else(|| format!("{} has no poll interval", path.display()))?;
+            Duration::from_secs(poll_interval) * DEFAULT_STALENESS_FACTOR
+        }
+    };
+    if age > max_age {
+        return Err(format!(
+            "{} was last updated {}s ago, more than {}s",
+            path.display(),
+            age.as_secs(),
+            max_age.as_secs()
+        ));
+    }
+
+    match field(&contents, "healthy") {
+        Some("true") => Ok(()),
+        Some("false") => Err(field(&contents, "health").map_or_else(
+            || "unhealthy".to_string(),
+            |health| health.trim_matches('"').into(),
+        )),
+        _ => Err(format!("{} is not a status file", path.display())),
+    }
+}
+
+/// Returns the raw value of the top-level `key` of a flat JSON object as written by
+/// [`Status::to_json`]. A quote cannot precede `key":` inside one of its strings, since
+/// those quotes are escaped, and only string values can contain a comma or brace.
+fn field<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+    let start = json.find(&format!("\"{}\":", key))? + key.len() + 3;
+    let rest = &json[start..];
+    let end = if let Some(string) = rest.strip_prefix('"') {
+        let mut escaped = false;
+        let close = string.char_indices().find_map(|(i, c)| {
+            let close = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            close.then_some(i)
+        })?;
+        close + 2
+    } else {
+        rest.find([',', '}'])?
+    };
+    Some(rest[..end].trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(health: Health) -> Status {
+        Status {
+            health,
+            synchronized: true,
+            source: TimeSource::Ntp,
+            stratum: 2,
+            time: "2026-10-16T09:30:00Z".parse().unwrap(),
+            last_offset: Some(-0.0012),
+            last_success: Some("2026-10-16T09:29:58Z".parse().unwrap()),
+            consecutive_failures: 0,
+            poll_interval: Duration::from_secs(64),
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "clock-ntp-status-{}-{}.json",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn test_write_and_check() {
+        let path = temp_path("healthy");
+        write(&path, &status(Health::Healthy)).unwrap();
+        assert!(!path.with_extension("tmp").exists());
+        assert_eq!(check(&path, None), Ok(()));
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(check(&path, Some(Duration::ZERO)).is_err());
+
+        let stale = Health::Stale {
+            age: Duration::from_secs(300),
+        };
+        write(&path, &status(stale)).unwrap();
+        assert_eq!(
+            check(&path, None),
+            Err("stale (last sync 300s ago)".to_string())
+        );
+        fs::remove_file(&path).unwrap();
+        assert!(check(&path, None).is_err());
+    }
+
+    #[test]
+    fn test_field_reads_flat_objects() {
+        let json = status(Health::Unsynchronized).to_json();
+        assert_eq!(field(&json, "healthy"), Some("false"));
+        assert_eq!(field(&json, "health"), Some("\"unsynchronized\""));
+        assert_eq!(field(&json, "poll_interval"), Some("64"));
+        assert_eq!(field(&json, "last_offset"), Some("-0.0012"));
+        assert_eq!(field(&json, "missing"), None);
+
+        let json = r#"{"health":"a \"healthy\": b,}","healthy":true}"#;
+        assert_eq!(field(json, "health"), Some(r#""a \"healthy\": b,}""#));
+        assert_eq!(field(json, "healthy"), Some("true"));
+    }
+}
@@ -0,0 +1,85 @@
+//! # Stopwatch
+//!
+//! A [`Stopwatch`] measures durations with the local monotonic clock and corrects them for
+//! the oscillator's frequency error as estimated from the sync history. Over long runs this
+//! removes the seconds per day a cheap crystal can gain or lose, while still being immune to
+//! the clock being stepped.
+
+use crate::sntp::correct_for_drift;
+use crate::ClockHandle;
+use std::time::{Duration, Instant};
+
+/// Measures elapsed time in drift-compensated units.
+///
+/// Created with [`Clock::start_stopwatch`](crate::Clock::start_stopwatch). Each reading
+/// applies the clock's current drift estimate to the whole measured span, so readings
+/// improve as the estimate does. Until the clock has enough samples to estimate drift,
+/// readings equal the raw monotonic time.
+pub struct Stopwatch {
+    clock: ClockHandle,
+    started: Instant,
+    lap_started: Instant,
+}
+
+impl Stopwatch {
+    pub(crate) fn new(clock: ClockHandle) -> Self {
+        let now = Instant::now();
+        Stopwatch {
+            clock,
+            started: now,
+            lap_started: now,
+        }
+    }
+
+    /// Corrected time since the stopwatch was started or last reset
+    pub fn elapsed(&self) -> Duration {
+        self.correct(self.started.elapsed())
+    }
+
+    /// Uncorrected monotonic time since the stopwatch was started or last reset
+    pub fn raw_elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Corrected time since the previous lap (or the start), beginning a new lap
+    pub fn lap(&mut self) -> Duration {
+        let now = Instant::now();
+        let lap = now - self.lap_started;
+        self.lap_started = now;
+        self.correct(lap)
+    }
+
+    /// Restarts the stopwatch from zero
+    pub fn reset(&mut self) {
+        let now = Instant::now();
+        self.started = now;
+        self.lap_started = now;
+    }
+
+    fn correct(&self, raw: Duration) -> Duration {
+        correct_for_drift(raw, self.clock.drift_ppm().unwrap_or(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Clock;
+
+    #[test]
+    fn test_laps_partition_elapsed_time() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let mut stopwatch = clock.start_stopwatch();
+        std::thread::sleep(Duration::from_millis(20));
+        let first = stopwatch.lap();
+        std::thread::sleep(Duration::from_millis(20));
+        let second = stopwatch.lap();
+        let total = stopwatch.elapsed();
+
+        assert!(first >= Duration::from_millis(20));
+        assert!(second >= Duration::from_millis(20));
+        assert!(total >= first + second);
+        stopwatch.reset();
+        assert!(stopwatch.elapsed() < first);
+    }
+}
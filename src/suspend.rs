@@ -0,0 +1,87 @@
+//! # Suspend Detection
+//!
+//! `Instant` does not advance while the system is suspended on some platforms, so a clock
+//! extrapolating from it falls behind after a laptop resumes. The detector compares how far
+//! the wall clock and the monotonic clock progressed between checks; a wall clock that ran
+//! well ahead means the machine was asleep.
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// Default discrepancy between wall-clock and monotonic progress treated as a suspend
+pub const DEFAULT_SUSPEND_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Detects system suspend by comparing wall-clock and monotonic progression
+#[derive(Debug, Clone)]
+pub struct SuspendDetector {
+    wall: SystemTime,
+    mono: Instant,
+    threshold: Duration,
+}
+
+impl SuspendDetector {
+    /// Creates a detector that reports gaps larger than `threshold`
+    pub fn new(threshold: Duration) -> Self {
+        SuspendDetector {
+            wall: SystemTime::now(),
+            mono: Instant::now(),
+            threshold,
+        }
+    }
+
+    /// Checks for a suspend since the previous call, returning the unaccounted time if one
+    /// was detected
+    pub fn check(&mut self) -> Option<Duration> {
+        self.check_at(SystemTime::now(), Instant::now())
+    }
+
+    fn check_at(&mut self, wall: SystemTime, mono: Instant) -> Option<Duration> {
+        let wall_progress = wall.duration_since(self.wall).unwrap_or(Duration::ZERO);
+        let mono_progress = mono.saturating_duration_since(self.mono);
+        self.wall = wall;
+        self.mono = mono;
+
+        let gap = wall_progress.saturating_sub(mono_progress);
+        (gap > self.threshold).then_some(gap)
+    }
+}
+
+impl Default for SuspendDetector {
+    fn default() -> Self {
+        Self::new(DEFAULT_SUSPEND_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_suspend_when_clocks_agree() {
+        let mut detector = SuspendDetector::default();
+        let (wall, mono) = (detector.wall, detector.mono);
+        let step = Duration::from_secs(10);
+        assert_eq!(detector.check_at(wall + step, mono + step), None);
+    }
+
+    #[test]
+    fn test_detects_wall_clock_running_ahead() {
+        let mut detector = SuspendDetector::default();
+        let (wall, mono) = (detector.wall, detector.mono);
+        let gap = detector.check_at(
+            wall + Duration::from_secs(3610),
+            mono + Duration::from_secs(10),
+        );
+        assert_eq!(gap, Some(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn test_ignores_wall_clock_stepping_backwards() {
+        let mut detector = SuspendDetector::default();
+        let (wall, mono) = (detector.wall, detector.mono);
+        let gap = detector.check_at(
+            wall - Duration::from_secs(60),
+            mono + Duration::from_secs(1),
+        );
+        assert_eq!(gap, None);
+    }
+}
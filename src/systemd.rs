@@ -0,0 +1,221 @@
+//! # systemd Integration
+//!
+//! Implements the parts of the systemd service protocol a time daemon needs, without linking
+//! libsystemd:
+//!
+//! - [`notify`] sends `sd_notify` messages such as `READY=1` and `WATCHDOG=1` to the socket
+//!   in `$NOTIFY_SOCKET`;
+//! - [`watchdog_interval`] reads the watchdog timeout from `$WATCHDOG_USEC`;
+//! - [`listen_fds`] takes the sockets passed by socket activation;
+//! - [`mark_synchronized`] creates the flag file `systemd-time-wait-sync` waits for.
+//!
+//! [`spawn_notifier`] ties these together for a running clock: it reports readiness once
+//! the first NTP sync succeeds, so units ordered after `time-sync.target` start with correct
+//! time, and pings the watchdog while the process is alive.
+
+use crate::logging::clock_log;
+use crate::ClockHandle;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Flag file whose existence tells `systemd-time-wait-sync` the clock is synchronized
+pub const TIMESYNC_SYNCHRONIZED_PATH: &str = "/run/systemd/timesync/synchronized";
+
+/// First file descriptor passed by socket activation
+const LISTEN_FDS_START: i32 = 3;
+
+/// Longest the notifier sleeps between checks
+const NOTIFIER_TICK: Duration = Duration::from_secs(1);
+
+/// Sends a notification such as `READY=1` to the service manager.
+///
+/// Returns `Ok(false)` without doing anything when not running under systemd (no
+/// `$NOTIFY_SOCKET`).
+pub fn notify(state: &str) -> io::Result<bool> {
+    match env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => notify_to(Path::new(&socket), state).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+fn notify_to(socket: &Path, state: &str) -> io::Result<()> {
+    let sender = UnixDatagram::unbound()?;
+    let bytes = socket.as_os_str().as_encoded_bytes();
+    match bytes.strip_prefix(b"@") {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Some(name) => {
+            #[cfg(target_os = "android")]
+            use std::os::android::net::SocketAddrExt;
+            #[cfg(target_os = "linux")]
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            sender.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "abstract notify sockets are only supported on Linux",
+            ))
+        }
+        None => {
+            sender.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+/// How often the service manager expects `WATCHDOG=1`, or `None` if the watchdog is not
+/// enabled for this process
+pub fn watchdog_interval() -> Option<Duration> {
+    parse_watchdog(
+        env::var("WATCHDOG_USEC").ok().as_deref(),
+        env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn parse_watchdog(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.trim().parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+    let usec: u64 = usec?.trim().parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+static LISTEN_FDS_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Takes ownership of the sockets passed by systemd socket activation, in the order of the
+/// socket unit's `Listen*=` lines.
+///
+/// Returns an empty list when the process was not socket activated, and on every call after
+/// the first.
+pub fn listen_fds() -> Vec<OwnedFd> {
+    let Some(count) = parse_listen_fds(
+        env::var("LISTEN_FDS").ok().as_deref(),
+        env::var("LISTEN_PID").ok().as_deref(),
+        std::process::id(),
+    ) else {
+        return Vec::new();
+    };
+    if LISTEN_FDS_TAKEN.swap(true, Ordering::SeqCst) {
+        return Vec::new();
+    }
+    (LISTEN_FDS_START..LISTEN_FDS_START + count as i32)
+        // SAFETY: systemd passes these descriptors to this process, and the flag above
+        // ensures they are wrapped only once
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+        .collect()
+}
+
+fn parse_listen_fds(fds: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<u32> {
+    if pid?.trim().parse::<u32>().ok()? != own_pid {
+        return None;
+    }
+    fds?.trim().parse().ok().filter(|&n| n > 0)
+}
+
+/// Creates the flag file `systemd-time-wait-sync` waits for
+pub fn mark_synchronized() -> io::Result<()> {
+    let path = Path::new(TIMESYNC_SYNCHRONIZED_PATH);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, b"")
+}
+
+/// Reports the clock to systemd until `shutdown` is set: `READY=1` (and the
+/// `systemd-time-wait-sync` flag file) once it first synchronizes, and `WATCHDOG=1` at half
+/// the watchdog interval throughout. Sends `STOPPING=1` when it exits.
+pub fn spawn_notifier(clock: ClockHandle, shutdown: Arc<AtomicBool>) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let watchdog = watchdog_interval().map(|interval| interval / 2);
+        let tick = watchdog.map_or(NOTIFIER_TICK, |w| w.min(NOTIFIER_TICK));
+        let mut ready = false;
+        let mut since_ping = Duration::ZERO;
+        let send = |state: &str| {
+            if let Err(e) = notify(state) {
+                clock_log!(Warn, Worker, "Failed to notify systemd: {}", e);
+            }
+        };
+
+        send("STATUS=Waiting for first NTP sync");
+        while !shutdown.load(Ordering::Relaxed) {
+            if !ready && clock.is_synchronized() {
+                ready = true;
+                clock_log!(Info, Worker, "Clock synchronized; notifying systemd");
+                send("READY=1\nSTATUS=Synchronized");
+                if let Err(e) = mark_synchronized() {
+                    clock_log!(
+                        Warn,
+                        Worker,
+                        "Failed to create {}: {}",
+                        TIMESYNC_SYNCHRONIZED_PATH,
+                        e
+                    );
+                }
+            }
+            if let Some(watchdog) = watchdog {
+                if since_ping >= watchdog || since_ping.is_zero() {
+                    send("WATCHDOG=1");
+                    since_ping = Duration::ZERO;
+                }
+            }
+            std::thread::sleep(tick);
+            since_ping += tick;
+        }
+        send("STOPPING=1");
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify_to_socket() {
+        let path = env::temp_dir().join(format!("clock-ntp-notify-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        notify_to(&path, "READY=1").unwrap();
+
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_watchdog() {
+        let pid = 42;
+        assert_eq!(
+            parse_watchdog(Some("3000000"), None, pid),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            parse_watchdog(Some("3000000"), Some("42"), pid),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(parse_watchdog(Some("3000000"), Some("7"), pid), None);
+        assert_eq!(parse_watchdog(Some("0"), None, pid), None);
+        assert_eq!(parse_watchdog(None, None, pid), None);
+    }
+
+    #[test]
+    fn test_parse_listen_fds() {
+        assert_eq!(parse_listen_fds(Some("2"), Some("42"), 42), Some(2));
+        assert_eq!(parse_listen_fds(Some("2"), Some("7"), 42), None);
+        assert_eq!(parse_listen_fds(Some("2"), None, 42), None);
+        assert_eq!(parse_listen_fds(Some("0"), Some("42"), 42), None);
+    }
+}
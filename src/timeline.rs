@@ -0,0 +1,113 @@
+//! # Sync Attempt Timeline
+//!
+//! The clock remembers its last [`MAX_RECENT_ATTEMPTS`] sync attempts, failed ones
+//! included, so a running process can be asked what happened recently without parsing its
+//! logs: [`Clock::recent_events`](crate::Clock::recent_events) returns them, and the HTTP
+//! API serves them at `/events`.
+//!
+//! ```text
+//! 2026-02-03T06:50:57.250+00:00 synced to pool.ntp.org:123, offset +0.002130000s
+//! 2026-02-03T06:51:01.004+00:00 failed: All NTP servers failed
+//! ```
+
+use crate::{NtpSample, Timestamp};
+use std::fmt;
+
+/// Number of sync attempts remembered: an hour of polls at a 4 s interval
+pub const MAX_RECENT_ATTEMPTS: usize = 1024;
+
+/// Whether a sync attempt got a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttemptResult {
+    Synced,
+    Failed,
+}
+
+impl fmt::Display for AttemptResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AttemptResult::Synced => "synced",
+            AttemptResult::Failed => "failed",
+        })
+    }
+}
+
+/// One sync attempt
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncAttempt {
+    /// System time when the attempt finished
+    pub at: Timestamp,
+    /// Server (or peer) the time came from, `None` if the attempt failed
+    pub server: Option<String>,
+    pub result: AttemptResult,
+    /// Offset of the new time from the clock's reading before it, in seconds
+    pub offset: Option<f64>,
+    /// Why the attempt failed
+    pub error: Option<String>,
+}
+
+impl SyncAttempt {
+    pub(crate) fn synced(sample: &NtpSample, offset: f64) -> Self {
+        SyncAttempt {
+            at: Timestamp::now(),
+            server: Some(sample.server.clone()),
+            result: AttemptResult::Synced,
+            offset: Some(offset),
+            error: None,
+        }
+    }
+
+    pub(crate) fn failed(error: impl fmt::Display) -> Self {
+        SyncAttempt {
+            at: Timestamp::now(),
+            server: None,
+            result: AttemptResult::Failed,
+            offset: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for SyncAttempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.at.to_rfc3339(), self.result)?;
+        if let Some(server) = &self.server {
+            write!(f, " to {}", server)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, ", offset {:+.9}s", offset)?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, ": {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::spawn_fake_server;
+    use crate::{Clock, ClockConfig};
+
+    #[test]
+    fn test_recent_events_record_successes_and_failures() {
+        let refused = spawn_fake_server(Timestamp::now(), 0);
+        let clock = Clock::with_config(ClockConfig::new().with_servers(vec![refused]));
+        assert!(!clock.resync_now());
+        let server = spawn_fake_server(Timestamp::now(), 1);
+        clock.reconfigure(&ClockConfig::new().with_servers(vec![server.clone()]));
+        assert!(clock.resync_now());
+
+        let events = clock.recent_events(10);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].result, AttemptResult::Failed);
+        assert!(events[0].server.is_none() && events[0].error.is_some());
+        assert_eq!(events[1].result, AttemptResult::Synced);
+        assert_eq!(events[1].server, Some(server));
+        assert!(events[1].offset.unwrap().abs() < 2.0);
+        assert!(events[1].to_string().contains(" synced to 127.0.0.1:"));
+
+        assert_eq!(clock.recent_events(1), events[1..]);
+    }
+}
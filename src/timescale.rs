@@ -0,0 +1,92 @@
+//! # TAI and GPS Time Scales
+//!
+//! Conversions between UTC and the leap-free TAI and GPS time scales. TAI−UTC is looked up
+//! in a [`LeapSecondTable`]; the bundled table can be replaced at runtime with
+//! [`update_leap_second_table`] when a new leap-seconds.list file is published.
+//!
+//! Timestamps on the TAI and GPS scales use the same representation as UTC ones (a count
+//! of nanoseconds since 1970-01-01T00:00:00 on that scale), so they can be compared and
+//! subtracted directly but should not be formatted as UTC.
+
+use crate::error::LeapSecondError;
+use crate::leapseconds::LeapSecondTable;
+use crate::lock::RwLockExt;
+use crate::Timestamp;
+use lazy_static::lazy_static;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// TAI−GPS, fixed since the GPS epoch (1980-01-06)
+pub const TAI_MINUS_GPS: Duration = Duration::from_secs(19);
+
+lazy_static! {
+    static ref LEAP_SECOND_TABLE: RwLock<Arc<LeapSecondTable>> =
+        RwLock::new(Arc::new(LeapSecondTable::builtin()));
+}
+
+/// The leap-second table used by the process-wide conversions
+pub fn leap_second_table() -> Arc<LeapSecondTable> {
+    Arc::clone(&LEAP_SECOND_TABLE.read_or_recover())
+}
+
+/// Replaces the leap-second table used by the process-wide conversions without any checks
+pub fn set_leap_second_table(table: LeapSecondTable) {
+    *LEAP_SECOND_TABLE.write_or_recover() = Arc::new(table);
+}
+
+/// Replaces the leap-second table used by the process-wide conversions if `table` has not
+/// expired and is not older than the table in use
+pub fn update_leap_second_table(table: LeapSecondTable) -> Result<(), LeapSecondError> {
+    table.validate(Timestamp::now())?;
+    let mut current = LEAP_SECOND_TABLE.write_or_recover();
+    if let (Some(current), Some(candidate)) = (current.expires(), table.expires()) {
+        if candidate < current {
+            return Err(LeapSecondError::Outdated { current, candidate });
+        }
+    }
+    *current = Arc::new(table);
+    Ok(())
+}
+
+/// Converts a UTC timestamp to TAI using the current leap-second table
+pub fn utc_to_tai(utc: Timestamp) -> Timestamp {
+    leap_second_table().utc_to_tai(utc)
+}
+
+/// Converts a TAI timestamp to UTC using the current leap-second table
+pub fn tai_to_utc(tai: Timestamp) -> Timestamp {
+    leap_second_table().tai_to_utc(tai)
+}
+
+/// Converts a UTC timestamp to GPS time using the current leap-second table
+pub fn utc_to_gps(utc: Timestamp) -> Timestamp {
+    utc_to_tai(utc) - TAI_MINUS_GPS
+}
+
+/// Converts a GPS timestamp to UTC using the current leap-second table
+pub fn gps_to_utc(gps: Timestamp) -> Timestamp {
+    tai_to_utc(gps + TAI_MINUS_GPS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gps_offset() {
+        let utc: Timestamp = "2026-02-03T06:50:57Z".parse().unwrap();
+        // GPS has been 18 s ahead of UTC since 2017
+        assert_eq!(utc_to_gps(utc), utc + Duration::from_secs(18));
+        assert_eq!(gps_to_utc(utc_to_gps(utc)), utc);
+    }
+
+    #[test]
+    fn test_update_rejects_expired_table() {
+        let list = "#@\t3155673600\n2272060800\t10\n"; // expired 2000-01-01
+        let table = LeapSecondTable::from_bytes(list.as_bytes()).unwrap();
+        assert!(matches!(
+            update_leap_second_table(table),
+            Err(LeapSecondError::Expired(_))
+        ));
+    }
+}
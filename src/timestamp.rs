@@ -0,0 +1,483 @@
+//! # Timestamps
+//!
+//! The clock's internal representation of time: nanoseconds since the Unix epoch as a plain
+//! integer. The chrono (`chrono` feature) and `time` (`time` feature) APIs are thin
+//! conversions from this type, so neither library is needed by the sync engine itself.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Add, Sub};
+use core::str::FromStr;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+const SECS_PER_DAY: i64 = 86_400;
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A point in time, in nanoseconds since 1970-01-01T00:00:00Z (leap seconds not counted)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp {
+    nanos: i128,
+}
+
+impl Timestamp {
+    /// 1970-01-01T00:00:00Z
+    pub const UNIX_EPOCH: Timestamp = Timestamp { nanos: 0 };
+
+    /// Creates a timestamp from nanoseconds since the Unix epoch
+    pub const fn from_unix_nanos(nanos: i128) -> Self {
+        Timestamp { nanos }
+    }
+
+    /// Creates a timestamp from whole seconds since the Unix epoch
+    pub const fn from_unix_secs(secs: i64) -> Self {
+        Timestamp {
+            nanos: secs as i128 * NANOS_PER_SEC,
+        }
+    }
+
+    /// Reads the operating system's clock
+    #[cfg(all(feature = "std", not(all(target_arch = "wasm32", feature = "wasm"))))]
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    /// Reads the browser's clock with `Date.now()`, since `SystemTime` is unsupported there
+    #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+    pub fn now() -> Self {
+        Timestamp::from_unix_nanos((js_sys::Date::now() * 1e6) as i128)
+    }
+
+    /// Nanoseconds since the Unix epoch
+    pub const fn unix_nanos(&self) -> i128 {
+        self.nanos
+    }
+
+    /// Whole seconds since the Unix epoch, rounded towards negative infinity
+    pub fn unix_secs(&self) -> i64 {
+        self.nanos.div_euclid(NANOS_PER_SEC) as i64
+    }
+
+    /// Milliseconds since the Unix epoch, rounded towards negative infinity
+    pub fn unix_millis(&self) -> i64 {
+        self.nanos.div_euclid(1_000_000) as i64
+    }
+
+    /// Nanoseconds past the last whole second
+    pub fn subsec_nanos(&self) -> u32 {
+        self.nanos.rem_euclid(NANOS_PER_SEC) as u32
+    }
+
+    /// Signed nanoseconds from `earlier` to `self`
+    pub fn nanos_since(&self, earlier: Timestamp) -> i128 {
+        self.nanos - earlier.nanos
+    }
+
+    /// Signed seconds from `earlier` to `self`
+    pub fn seconds_since(&self, earlier: Timestamp) -> f64 {
+        self.nanos_since(earlier) as f64 / 1e9
+    }
+
+    /// Moves the timestamp by a signed number of nanoseconds
+    pub fn add_nanos(self, nanos: i128) -> Self {
+        Timestamp {
+            nanos: self.nanos + nanos,
+        }
+    }
+
+    /// Calendar date (year, month, day) and seconds past midnight, in UTC
+    pub fn to_civil(&self) -> (i64, u32, u32, u32) {
+        let secs = self.unix_secs();
+        let (year, month, day) = civil_from_days(secs.div_euclid(SECS_PER_DAY));
+        (year, month, day, secs.rem_euclid(SECS_PER_DAY) as u32)
+    }
+
+    /// Formats as RFC 3339 in UTC, e.g. `2026-02-03T06:50:57.250+00:00`.
+    ///
+    /// The fraction is omitted when zero and otherwise written with 3, 6, or 9 digits,
+    /// matching chrono's `to_rfc3339`.
+    pub fn to_rfc3339(&self) -> String {
+        let (year, month, day, secs) = self.to_civil();
+        let mut out = format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        );
+        match self.subsec_nanos() {
+            0 => {}
+            n if n.is_multiple_of(1_000_000) => out.push_str(&format!(".{:03}", n / 1_000_000)),
+            n if n.is_multiple_of(1_000) => out.push_str(&format!(".{:06}", n / 1_000)),
+            n => out.push_str(&format!(".{:09}", n)),
+        }
+        out.push_str("+00:00");
+        out
+    }
+
+    /// Formats as RFC 2822 in UTC, e.g. `Tue, 3 Feb 2026 06:50:57 +0000`
+    pub fn to_rfc2822(&self) -> String {
+        const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+        let (year, month, day, secs) = self.to_civil();
+        // The Unix epoch fell on a Thursday
+        let weekday = (self.unix_secs().div_euclid(SECS_PER_DAY) + 4).rem_euclid(7);
+        format!(
+            "{}, {} {} {:04} {:02}:{:02}:{:02} +0000",
+            WEEKDAYS[weekday as usize],
+            day,
+            MONTH_NAMES[month as usize - 1],
+            year,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+
+    /// Parses an HTTP `Date` header in the IMF-fixdate form, e.g.
+    /// `Sun, 06 Nov 1994 08:49:37 GMT`. The weekday is not checked.
+    pub fn parse_http_date(s: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid HTTP date '{}'", s);
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [_weekday, day, month, year, time, "GMT"] = fields[..] else {
+            return Err(invalid());
+        };
+        let month = MONTH_NAMES
+            .iter()
+            .position(|name| *name == month)
+            .ok_or_else(invalid)? as u32
+            + 1;
+        let day: u32 = day.parse().map_err(|_| invalid())?;
+        let year: i64 = year.parse().map_err(|_| invalid())?;
+        let hms: Vec<u32> = time
+            .split(':')
+            .map(|n| n.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        let [hour, minute, second] = hms[..] else {
+            return Err(invalid());
+        };
+        if day < 1 || day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 60 {
+            return Err(invalid());
+        }
+        let secs = days_from_civil(year, month, day) * SECS_PER_DAY
+            + (hour * 3600 + minute * 60 + second) as i64;
+        Ok(Timestamp::from_unix_secs(secs))
+    }
+
+    /// Parses an RFC 3339 timestamp such as `2026-02-03T06:50:57.25Z` or
+    /// `2026-02-03T01:50:57-05:00`
+    pub fn parse_rfc3339(s: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid RFC 3339 timestamp '{}'", s);
+        let b = s.as_bytes();
+        if !s.is_ascii()
+            || b.len() < 20
+            || b[4] != b'-'
+            || b[7] != b'-'
+            || !matches!(b[10], b'T' | b't' | b' ')
+            || b[13] != b':'
+            || b[16] != b':'
+        {
+            return Err(invalid());
+        }
+        let num = |range: core::ops::Range<usize>| -> Result<i64, String> {
+            let field = &s[range];
+            if !field.bytes().all(|c| c.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            field.parse().map_err(|_| invalid())
+        };
+
+        let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+        let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+        if !(1..=12).contains(&month)
+            || day < 1
+            || day > days_in_month(year, month as u32) as i64
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return Err(invalid());
+        }
+
+        let mut pos = 19;
+        let mut nanos: i128 = 0;
+        if b[pos] == b'.' {
+            pos += 1;
+            let start = pos;
+            while pos < b.len() && b[pos].is_ascii_digit() {
+                pos += 1;
+            }
+            let digits = &s[start..pos];
+            if digits.is_empty() {
+                return Err(invalid());
+            }
+            // Digits beyond nanosecond precision are truncated
+            for (i, c) in digits.bytes().take(9).enumerate() {
+                nanos += (c - b'0') as i128 * 10i128.pow(8 - i as u32);
+            }
+        }
+
+        let offset_secs = match &s[pos..] {
+            "Z" | "z" => 0,
+            tz if tz.len() == 6
+                && matches!(tz.as_bytes()[0], b'+' | b'-')
+                && tz.as_bytes()[3] == b':' =>
+            {
+                let hours = num(pos + 1..pos + 3)?;
+                let minutes = num(pos + 4..pos + 6)?;
+                let sign = if tz.starts_with('-') { -1 } else { 1 };
+                sign * (hours * 3600 + minutes * 60)
+            }
+            _ => return Err(invalid()),
+        };
+
+        // A leap second is folded into the following second, as the Unix timescale does
+        let secs = days_from_civil(year, month as u32, day as u32) * SECS_PER_DAY
+            + hour * 3600
+            + minute * 60
+            + second
+            - offset_secs;
+        Ok(Timestamp::from_unix_secs(secs).add_nanos(nanos))
+    }
+}
+
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, rhs: Duration) -> Timestamp {
+        self.add_nanos(rhs.as_nanos() as i128)
+    }
+}
+
+impl Sub<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn sub(self, rhs: Duration) -> Timestamp {
+        self.add_nanos(-(rhs.as_nanos() as i128))
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_rfc3339())
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Timestamp::parse_rfc3339(s)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => Timestamp::UNIX_EPOCH + after,
+            Err(e) => Timestamp::UNIX_EPOCH - e.duration(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Timestamp> for SystemTime {
+    fn from(time: Timestamp) -> Self {
+        let magnitude = Duration::new(
+            (time.nanos.unsigned_abs() / NANOS_PER_SEC as u128) as u64,
+            (time.nanos.unsigned_abs() % NANOS_PER_SEC as u128) as u32,
+        );
+        if time.nanos >= 0 {
+            UNIX_EPOCH + magnitude
+        } else {
+            UNIX_EPOCH - magnitude
+        }
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> From<chrono::DateTime<Tz>> for Timestamp {
+    fn from(time: chrono::DateTime<Tz>) -> Self {
+        Timestamp::from_unix_secs(time.timestamp()).add_nanos(time.timestamp_subsec_nanos() as i128)
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<Timestamp> for chrono::DateTime<chrono::Utc> {
+    /// Saturates at chrono's supported range (about ±262,000 years)
+    fn from(time: Timestamp) -> Self {
+        chrono::DateTime::from_timestamp(time.unix_secs(), time.subsec_nanos()).unwrap_or(
+            if time.nanos < 0 {
+                chrono::DateTime::<chrono::Utc>::MIN_UTC
+            } else {
+                chrono::DateTime::<chrono::Utc>::MAX_UTC
+            },
+        )
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<time::OffsetDateTime> for Timestamp {
+    fn from(time: time::OffsetDateTime) -> Self {
+        Timestamp::from_unix_nanos(time.unix_timestamp_nanos())
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<Timestamp> for time::OffsetDateTime {
+    /// Saturates at the `time` crate's supported range (years -9999 to 9999)
+    fn from(time: Timestamp) -> Self {
+        time::OffsetDateTime::from_unix_timestamp_nanos(time.nanos).unwrap_or_else(|_| {
+            let year = if time.nanos < 0 { -9999 } else { 9999 };
+            let date = time::Date::from_calendar_date(year, time::Month::January, 1).unwrap();
+            date.midnight().assume_utc()
+        })
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since the Unix epoch for a proleptic Gregorian date (Howard Hinnant's algorithm)
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Proleptic Gregorian date for a number of days since the Unix epoch
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_round_trip() {
+        for days in [-719_468, -1, 0, 10_957, 20_487, 2_932_896] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+        assert_eq!(civil_from_days(10_957), (2000, 1, 1));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_parse_rfc3339_never_panics(s in "\\PC*") {
+            let _ = Timestamp::parse_rfc3339(&s);
+        }
+
+        #[test]
+        fn prop_parse_rfc3339_accepts_what_it_formats(secs in -62_135_596_800i64..253_402_300_799, millis in 0i128..1000) {
+            let time = Timestamp::from_unix_secs(secs).add_nanos(millis * 1_000_000);
+            proptest::prop_assert_eq!(Timestamp::parse_rfc3339(&time.to_rfc3339()), Ok(time));
+        }
+
+        #[test]
+        fn prop_parse_rfc3339_handles_timestamp_shaped_input(
+            s in "[0-9]{4}-[0-9]{2}-[0-9]{2}[Tt ][0-9]{2}:[0-9]{2}:[0-9]{2}(\\.[0-9]{0,12})?([Zz]|[+-][0-9]{2}:[0-9]{2})"
+        ) {
+            let _ = Timestamp::parse_rfc3339(&s);
+        }
+    }
+
+    #[test]
+    fn test_rfc3339_round_trip() {
+        let time = Timestamp::from_unix_secs(1_770_101_457).add_nanos(250_000_000);
+        assert_eq!(time.to_rfc3339(), "2026-02-03T06:50:57.250+00:00");
+        assert_eq!(time.to_rfc3339().parse::<Timestamp>(), Ok(time));
+    }
+
+    #[test]
+    fn test_rfc2822() {
+        let time = Timestamp::from_unix_secs(1_770_101_457);
+        assert_eq!(time.to_rfc2822(), "Tue, 3 Feb 2026 06:50:57 +0000");
+        assert_eq!(
+            Timestamp::UNIX_EPOCH.to_rfc2822(),
+            "Thu, 1 Jan 1970 00:00:00 +0000"
+        );
+    }
+
+    #[test]
+    fn test_parse_rfc3339_offsets() {
+        let utc: Timestamp = "2026-02-03T06:50:57Z".parse().unwrap();
+        let est: Timestamp = "2026-02-03T01:50:57-05:00".parse().unwrap();
+        assert_eq!(utc, est);
+        assert!("2026-02-30T00:00:00Z".parse::<Timestamp>().is_err());
+        assert!("2026-02-03 06:50".parse::<Timestamp>().is_err());
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        let time = Timestamp::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(time, "1994-11-06T08:49:37Z".parse().unwrap());
+        assert_eq!(
+            Timestamp::parse_http_date(&time.to_rfc2822().replace("+0000", "GMT")),
+            Ok(time)
+        );
+        assert!(Timestamp::parse_http_date("Sun, 06 Nov 1994 08:49:37 +0000").is_err());
+        assert!(Timestamp::parse_http_date("Sun, 31 Feb 1994 08:49:37 GMT").is_err());
+    }
+
+    #[test]
+    fn test_negative_timestamps() {
+        let time = Timestamp::from_unix_nanos(-500_000_000);
+        assert_eq!(time.unix_secs(), -1);
+        assert_eq!(time.subsec_nanos(), 500_000_000);
+        assert_eq!(time.to_rfc3339(), "1969-12-31T23:59:59.500+00:00");
+        #[cfg(feature = "std")]
+        assert_eq!(Timestamp::from(SystemTime::from(time)), time);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_chrono_conversion() {
+        use chrono::TimeZone;
+        let dt = chrono::Utc.with_ymd_and_hms(2026, 2, 3, 6, 50, 57).unwrap();
+        let time = Timestamp::from(dt);
+        assert_eq!(time, Timestamp::from_unix_secs(1_770_101_457));
+        assert_eq!(chrono::DateTime::<chrono::Utc>::from(time), dt);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_time_conversion() {
+        let time = Timestamp::from_unix_secs(1_770_101_457).add_nanos(1);
+        let odt = time::OffsetDateTime::from(time);
+        assert_eq!(odt.year(), 2026);
+        assert_eq!(Timestamp::from(odt), time);
+    }
+}
@@ -0,0 +1,116 @@
+//! # Event Timestamping
+//!
+//! A [`Timestamper`] stamps high-rate event streams with synchronized time. Each
+//! [`batch`](Timestamper::batch) reads the clock once and reserves a run of consecutive
+//! nanoseconds, so stamping a million events costs one clock read instead of a million.
+//!
+//! Stamps are strictly increasing across all timestampers of a clock and across threads,
+//! even when a sync steps the clock backwards: stamps then continue from the last one
+//! issued until the clock catches up.
+
+use crate::{ClockHandle, Timestamp};
+use std::iter::FusedIterator;
+
+/// Issues strictly increasing timestamps from a clock.
+///
+/// Created with [`Clock::timestamper`](crate::Clock::timestamper) or
+/// [`ClockHandle::timestamper`]. Cheap to clone; clones share the clock's ordering.
+#[derive(Clone)]
+pub struct Timestamper {
+    clock: ClockHandle,
+}
+
+impl Timestamper {
+    pub(crate) fn new(clock: ClockHandle) -> Self {
+        Timestamper { clock }
+    }
+
+    /// A timestamp later than every one issued before
+    pub fn next(&self) -> Timestamp {
+        self.clock.reserve_stamps(1)
+    }
+
+    /// `count` consecutive nanosecond timestamps starting at the current time, all later
+    /// than every one issued before
+    pub fn batch(&self, count: usize) -> TimestampBatch {
+        TimestampBatch {
+            next: self.clock.reserve_stamps(count as u64),
+            remaining: count,
+        }
+    }
+}
+
+/// Timestamps reserved by [`Timestamper::batch`]
+#[derive(Debug, Clone)]
+pub struct TimestampBatch {
+    next: Timestamp,
+    remaining: usize,
+}
+
+impl Iterator for TimestampBatch {
+    type Item = Timestamp;
+
+    fn next(&mut self) -> Option<Timestamp> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let stamp = self.next;
+        self.next = stamp.add_nanos(1);
+        self.remaining -= 1;
+        Some(stamp)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for TimestampBatch {}
+
+impl FusedIterator for TimestampBatch {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::spawn_fake_server;
+    use crate::Clock;
+
+    #[test]
+    fn test_batches_are_strictly_increasing() {
+        let clock = Clock::new(Some(vec![spawn_fake_server(Timestamp::now(), 0)]));
+        let stamper = clock.timestamper();
+        let before = clock.now_timestamp();
+
+        let first: Vec<Timestamp> = stamper.batch(1000).collect();
+        assert_eq!(first.len(), 1000);
+        assert!(first[0] >= before);
+        assert!(first.windows(2).all(|w| w[1].nanos_since(w[0]) == 1));
+
+        let other = clock.handle().timestamper();
+        let single = other.next();
+        assert!(single > first[999]);
+        assert!(stamper.batch(3).next().unwrap() > single);
+        assert_eq!(stamper.batch(0).count(), 0);
+    }
+
+    #[test]
+    fn test_stamps_stay_ordered_across_threads() {
+        let clock = Clock::new(Some(vec![spawn_fake_server(Timestamp::now(), 0)]));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let stamper = clock.timestamper();
+                std::thread::spawn(move || {
+                    (0..100).flat_map(|_| stamper.batch(50)).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut stamps: Vec<Timestamp> = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect();
+        let issued = stamps.len();
+        stamps.sort();
+        stamps.dedup();
+        assert_eq!(stamps.len(), issued);
+    }
+}
@@ -0,0 +1,174 @@
+//! # Kernel Packet Timestamps
+//!
+//! Linux can stamp each UDP packet as it leaves or arrives at the network stack, and NICs
+//! with PTP support can stamp it on the wire. [`UdpTransport`](crate::sntp::UdpTransport)
+//! uses these as the transmit and receive times of an exchange, so the measured round trip
+//! and the reply's age no longer include the time the process took to be scheduled.
+//!
+//! Hardware timestamps are only delivered once the NIC has been configured for them (e.g.
+//! with `hwstamp_ctl`); software timestamps work on any interface, including loopback.
+
+use std::io;
+use std::mem;
+use std::net::UdpSocket;
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+/// When the kernel, and possibly the NIC, saw a packet
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct PacketTimestamps {
+    /// Software timestamp on the `CLOCK_REALTIME` timescale
+    pub software: Option<Duration>,
+    /// Raw hardware timestamp on the NIC's clock, only comparable to other hardware
+    /// timestamps from the same NIC
+    pub hardware: Option<Duration>,
+}
+
+impl PacketTimestamps {
+    /// Time from `earlier` to `self`, preferring hardware timestamps when both packets
+    /// have one
+    pub fn since(&self, earlier: &PacketTimestamps) -> Option<Duration> {
+        match (self.hardware, earlier.hardware) {
+            (Some(later), Some(earlier)) => later.checked_sub(earlier),
+            _ => self.software?.checked_sub(earlier.software?),
+        }
+    }
+}
+
+/// Asks the kernel to timestamp the packets `socket` sends and receives
+pub(crate) fn enable(socket: &UdpSocket) -> io::Result<()> {
+    let flags: libc::c_uint = libc::SOF_TIMESTAMPING_TX_SOFTWARE
+        | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+        | libc::SOF_TIMESTAMPING_SOFTWARE
+        | libc::SOF_TIMESTAMPING_TX_HARDWARE
+        | libc::SOF_TIMESTAMPING_RX_HARDWARE
+        | libc::SOF_TIMESTAMPING_RAW_HARDWARE
+        | libc::SOF_TIMESTAMPING_OPT_TSONLY;
+    // SAFETY: `flags` outlives the call and its size is passed along
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            &flags as *const libc::c_uint as *const libc::c_void,
+            mem::size_of::<libc::c_uint>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receives a packet like [`UdpSocket::recv`], along with its receive timestamps
+pub(crate) fn recv(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, PacketTimestamps)> {
+    recv_with_flags(socket, buf, 0)
+}
+
+/// The transmit timestamps of the last packet sent on `socket`, if the kernel has queued
+/// them yet
+pub(crate) fn sent(socket: &UdpSocket) -> Option<PacketTimestamps> {
+    let mut buf = [0u8; 64];
+    recv_with_flags(socket, &mut buf, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT)
+        .ok()
+        .map(|(_, timestamps)| timestamps)
+}
+
+fn recv_with_flags(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    flags: libc::c_int,
+) -> io::Result<(usize, PacketTimestamps)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // u64 elements keep the control buffer aligned for cmsghdr
+    let mut control = [0u64; 32];
+    // SAFETY: msghdr is plain data for which all zeroes is a valid value
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    // SAFETY: `msg` points at `iov` and `control`, which outlive the call
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, flags) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut timestamps = PacketTimestamps::default();
+    // SAFETY: the kernel filled `msg.msg_control` with `msg.msg_controllen` bytes of
+    // well-formed control messages, which the CMSG macros walk within those bounds
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPING
+            {
+                // struct scm_timestamping: software, legacy, and raw hardware timespecs,
+                // each two 64-bit fields on 64-bit targets
+                let data = libc::CMSG_DATA(cmsg) as *const [i64; 6];
+                let fields = data.read_unaligned();
+                timestamps.software = timespec(fields[0], fields[1]);
+                timestamps.hardware = timespec(fields[4], fields[5]);
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((len as usize, timestamps))
+}
+
+/// A timestamp from its seconds and nanoseconds; all zeroes means it is absent
+fn timespec(secs: i64, nanos: i64) -> Option<Duration> {
+    if secs <= 0 && nanos <= 0 {
+        return None;
+    }
+    Some(Duration::new(secs as u64, nanos as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loopback_packets_are_timestamped() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        enable(&client).unwrap();
+        enable(&server).unwrap();
+
+        // The kernel turns receive timestamping on asynchronously, so the first packets
+        // may arrive unstamped
+        for _ in 0..50 {
+            client.send(b"ping").unwrap();
+            let mut buf = [0u8; 16];
+            let (len, received) = recv(&server, &mut buf).unwrap();
+            assert_eq!(&buf[..len], b"ping");
+            let sent = sent(&client);
+            if let (Some(sent), Some(received)) = (sent.and_then(|s| s.software), received.software)
+            {
+                // On loopback both stamps are taken in the same call chain, in either order
+                assert!(sent.abs_diff(received) < Duration::from_secs(1));
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("no packet was timestamped");
+    }
+
+    #[test]
+    fn test_since_prefers_hardware_timestamps() {
+        let stamp = |software, hardware| PacketTimestamps {
+            software: Some(Duration::from_micros(software)),
+            hardware,
+        };
+        let sent = stamp(100, Some(Duration::from_micros(5)));
+        let received = stamp(400, Some(Duration::from_micros(205)));
+        assert_eq!(received.since(&sent), Some(Duration::from_micros(200)));
+        let received = stamp(400, None);
+        assert_eq!(received.since(&sent), Some(Duration::from_micros(300)));
+        assert_eq!(sent.since(&received), None);
+    }
+}
@@ -0,0 +1,172 @@
+//! # TLS Client
+//!
+//! TLS connections for NTS key establishment and DNS-over-HTTPS, made with rustls and its
+//! *ring* provider. A server is trusted when its certificate chains to the system roots, or
+//! to the roots in a `cafile` instead, and when SPKI pins are given, one of the certificates
+//! it presents must also carry a pinned subject public key.
+
+use crate::server::TlsVersion;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{self, WebPkiSupportedAlgorithms};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, StreamOwned};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A TLS session over TCP
+pub(crate) type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// Who a client trusts
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Trust<'a> {
+    /// PEM file of the root certificates to trust instead of the system store
+    pub ca_file: Option<&'a str>,
+    /// SHA-256 hashes of subject public keys, one of which the server must present
+    pub spki_pins: &'a [[u8; 32]],
+    pub min_tls: TlsVersion,
+}
+
+/// A client configuration trusting servers as `trust` says and offering the ALPN `protocol`
+pub(crate) fn client_config(trust: Trust<'_>, protocol: &[u8]) -> io::Result<Arc<ClientConfig>> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let roots = Arc::new(root_store(trust.ca_file)?);
+    let verifier = PinnedVerifier {
+        roots: WebPkiServerVerifier::builder_with_provider(roots, Arc::clone(&provider))
+            .build()
+            .map_err(io::Error::other)?,
+        spki_pins: trust.spki_pins.to_vec(),
+        algorithms: provider.signature_verification_algorithms,
+    };
+    let versions: &[&rustls::SupportedProtocolVersion] = match trust.min_tls {
+        TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        TlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+    let mut config = ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(versions)
+        .map_err(io::Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    config.alpn_protocols = vec![protocol.to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Connects to `addr` and starts a TLS session with it as `server_name`; the handshake
+/// completes with the first read or write. Both time out after `timeout`.
+pub(crate) fn connect(
+    addr: SocketAddr,
+    server_name: &str,
+    config: Arc<ClientConfig>,
+    timeout: Duration,
+) -> io::Result<TlsStream> {
+    let name = ServerName::try_from(server_name.to_string()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid TLS server name '{}'", server_name),
+        )
+    })?;
+    let socket = TcpStream::connect_timeout(&addr, timeout)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.set_write_timeout(Some(timeout))?;
+    let session = ClientConnection::new(config, name).map_err(io::Error::other)?;
+    Ok(StreamOwned::new(session, socket))
+}
+
+/// SHA-256 of the DER subject public key info of `cert`, as `pin sha256//` hashes are
+pub(crate) fn spki_sha256(cert: &CertificateDer<'_>) -> Option<[u8; 32]> {
+    let cert = webpki::EndEntityCert::try_from(cert).ok()?;
+    Some(Sha256::digest(cert.subject_public_key_info()).into())
+}
+
+fn root_store(ca_file: Option<&str>) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match ca_file {
+        Some(path) => {
+            let invalid =
+                |e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e));
+            for cert in CertificateDer::pem_file_iter(path).map_err(invalid)? {
+                roots
+                    .add(cert.map_err(invalid)?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+        }
+        None => {
+            roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+        }
+    }
+    if roots.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no root certificates in {}",
+                ca_file.unwrap_or("the system store")
+            ),
+        ));
+    }
+    Ok(roots)
+}
+
+/// Checks the chain against the roots, then the pins against the presented certificates
+#[derive(Debug)]
+struct PinnedVerifier {
+    roots: Arc<WebPkiServerVerifier>,
+    spki_pins: Vec<[u8; 32]>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.roots.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let pinned = self.spki_pins.is_empty()
+            || std::iter::once(end_entity)
+                .chain(intermediates)
+                .filter_map(spki_sha256)
+                .any(|hash| self.spki_pins.contains(&hash));
+        if !pinned {
+            return Err(rustls::Error::General(
+                "no certificate of the server has a pinned public key".to_string(),
+            ));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,119 @@
+//! # Custom Transports
+//!
+//! The [`Clock`](crate::Clock) engine reaches its servers over plain UDP by default. Where
+//! time has to come through an authorized egress — a WireGuard socket, a QUIC tunnel, or a
+//! vendor relay — implement [`Transport`] for it and set a [`TransportFactory`] as
+//! [`ClockConfig::transport`](crate::ClockConfig::transport). Samples then go through the
+//! usual selection, filtering, and discipline as if they had arrived over UDP.
+//!
+//! Server names are still resolved to socket addresses locally, and the transport is handed
+//! those; configure IP addresses where the local resolver cannot see the servers. The
+//! `source_ports`, `dscp`, and `ttl` settings only apply to the built-in
+//! [`UdpTransport`](crate::sntp::UdpTransport).
+
+use crate::sntp::Transport;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// A transport the engine can use, see [`TransportFactory`]
+pub type DynTransport = dyn Transport<Address = SocketAddr, Error = io::Error>;
+
+/// Creates the transport the engine queries servers through, once per poll
+#[derive(Clone)]
+pub struct TransportFactory {
+    name: String,
+    create: Arc<dyn Fn() -> Box<DynTransport> + Send + Sync>,
+}
+
+impl TransportFactory {
+    /// Wraps `create`, which is called at the start of every poll; `name` identifies the
+    /// transport in logs and configuration diffs
+    pub fn new<T, F>(name: impl Into<String>, create: F) -> Self
+    where
+        T: Transport<Address = SocketAddr, Error = io::Error> + 'static,
+        F: Fn() -> T + Send + Sync + 'static,
+    {
+        TransportFactory {
+            name: name.into(),
+            create: Arc::new(move || Box::new(create())),
+        }
+    }
+
+    /// The name given to [`new`](Self::new)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn create(&self) -> Box<DynTransport> {
+        (self.create)()
+    }
+}
+
+impl fmt::Debug for TransportFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TransportFactory").field(&self.name).finish()
+    }
+}
+
+impl fmt::Display for TransportFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// Factories are equal if they are clones of each other
+impl PartialEq for TransportFactory {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.create, &other.create)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sntp::{UdpTransport, PACKET_LEN};
+    use crate::tests::spawn_fake_server;
+    use crate::{Clock, ClockConfig, Timestamp};
+    use std::time::Duration;
+
+    /// Forwards every query to one fixed address, like a relay would
+    struct Relay {
+        to: SocketAddr,
+        inner: UdpTransport,
+    }
+
+    impl Transport for Relay {
+        type Address = SocketAddr;
+        type Error = io::Error;
+
+        fn exchange(
+            &mut self,
+            _server: &SocketAddr,
+            request: &[u8; PACKET_LEN],
+            reply: &mut [u8; PACKET_LEN],
+        ) -> io::Result<Duration> {
+            self.inner.exchange(&self.to, request, reply)
+        }
+    }
+
+    #[test]
+    fn test_clock_syncs_through_custom_transport() {
+        let relay: SocketAddr = spawn_fake_server(Timestamp::now(), 1).parse().unwrap();
+        let factory = TransportFactory::new("relay", move || Relay {
+            to: relay,
+            inner: UdpTransport::default(),
+        });
+        assert_eq!(factory, factory.clone());
+        assert_eq!(format!("{:?}", factory), "TransportFactory(\"relay\")");
+
+        // Not reachable directly: only the relay gets the time through
+        let config = ClockConfig::new()
+            .with_servers(vec!["192.0.2.1:123".to_string()])
+            .with_transport(Some(factory));
+        let clock = Clock::with_config(config);
+        assert!(clock.is_synchronized());
+        assert_eq!(clock.stratum(), 3);
+    }
+}
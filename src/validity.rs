@@ -0,0 +1,108 @@
+//! # Certificate Validity
+//!
+//! Devices without a real-time clock boot into a wrong system time, so TLS stacks that check
+//! certificates against it reject good ones or accept expired ones.
+//! [`Clock::check_validity`](crate::Clock::check_validity) checks an X.509-style validity
+//! period against verified time instead, and only gives a definite answer when the whole
+//! uncertainty window around the current time falls on one side of the period's bounds.
+
+use crate::Timestamp;
+use std::fmt;
+use std::time::Duration;
+
+/// Whether the current time lies within a validity period
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidityStatus {
+    /// The period has started and not yet ended
+    Valid,
+    /// The period starts in the future
+    NotYetValid,
+    /// The period has ended
+    Expired,
+    /// The time is too uncertain to tell, because a bound falls within the uncertainty
+    /// window around it
+    Uncertain,
+    /// The clock has never synced, so its time cannot be trusted
+    Unverified,
+}
+
+impl ValidityStatus {
+    /// Whether the period is known to be valid
+    pub fn is_valid(&self) -> bool {
+        matches!(self, ValidityStatus::Valid)
+    }
+}
+
+impl fmt::Display for ValidityStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ValidityStatus::Valid => "valid",
+            ValidityStatus::NotYetValid => "not yet valid",
+            ValidityStatus::Expired => "expired",
+            ValidityStatus::Uncertain => "uncertain",
+            ValidityStatus::Unverified => "unverified",
+        })
+    }
+}
+
+/// Checks `now`, give or take `uncertainty` (`None` if the time is unverified), against
+/// the period from `not_before` to `not_after`, both included
+pub fn check(
+    now: Timestamp,
+    uncertainty: Option<Duration>,
+    not_before: Timestamp,
+    not_after: Timestamp,
+) -> ValidityStatus {
+    let Some(uncertainty) = uncertainty else {
+        return ValidityStatus::Unverified;
+    };
+    let (earliest, latest) = (now - uncertainty, now + uncertainty);
+    if latest < not_before {
+        ValidityStatus::NotYetValid
+    } else if earliest > not_after {
+        ValidityStatus::Expired
+    } else if earliest >= not_before && latest <= not_after {
+        ValidityStatus::Valid
+    } else {
+        ValidityStatus::Uncertain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validity_accounts_for_uncertainty() {
+        let not_before: Timestamp = "2026-01-01T00:00:00Z".parse().unwrap();
+        let not_after: Timestamp = "2026-04-01T00:00:00Z".parse().unwrap();
+        let at = |s: &str| s.parse::<Timestamp>().unwrap();
+        let second = Some(Duration::from_secs(1));
+
+        let check = |now, uncertainty| check(now, uncertainty, not_before, not_after);
+        assert_eq!(
+            check(at("2026-02-03T06:50:57Z"), second),
+            ValidityStatus::Valid
+        );
+        assert_eq!(
+            check(at("2025-12-31T23:59:58Z"), second),
+            ValidityStatus::NotYetValid
+        );
+        assert_eq!(
+            check(at("2025-12-31T23:59:59.5Z"), second),
+            ValidityStatus::Uncertain
+        );
+        assert_eq!(
+            check(at("2026-04-01T00:00:00.5Z"), second),
+            ValidityStatus::Uncertain
+        );
+        assert_eq!(
+            check(at("2026-04-01T00:00:02Z"), second),
+            ValidityStatus::Expired
+        );
+        assert_eq!(
+            check(at("2026-02-03T06:50:57Z"), None),
+            ValidityStatus::Unverified
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! # Windows Time Service Cross-Check
+//!
+//! Reads the status of the Windows Time service (W32Time) with `w32tm /query /status` so a
+//! clock can be sanity-checked against the system clock W32Time disciplines. If both are
+//! synchronized they should agree to within W32Time's accuracy (typically well under a
+//! second); a larger difference points at a bad NTP source on one side.
+//!
+//! The parser understands the English output of `w32tm`; on other display languages the
+//! fields it cannot find are left as `None`.
+
+#[cfg(windows)]
+use std::io;
+use std::time::Duration;
+
+/// Fields of `w32tm /query /status /verbose`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct W32TimeStatus {
+    /// Leap indicator; 3 means the service is not synchronized
+    pub leap_indicator: Option<u8>,
+    /// Stratum of the service; 0 means unspecified
+    pub stratum: Option<u8>,
+    /// Where the service gets its time from, e.g. `time.windows.com,0x9`
+    pub source: Option<String>,
+    /// Offset of the system clock from the source in seconds, when reported
+    pub phase_offset: Option<f64>,
+}
+
+/// Outcome of [`W32TimeStatus::cross_check`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CrossCheck {
+    /// The clock and the W32Time-disciplined system clock agree within the tolerance
+    Agrees { difference: f64 },
+    /// The two differ by more than the tolerance, in seconds (clock minus system)
+    Disagrees { difference: f64 },
+    /// W32Time is not synchronized, so the system clock proves nothing
+    Unsynchronized,
+}
+
+impl W32TimeStatus {
+    /// Parses the output of `w32tm /query /status [/verbose]`
+    pub fn parse(output: &str) -> Self {
+        let mut status = W32TimeStatus::default();
+        for line in output.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            // Values like "2 (secondary reference - syncd by (S)NTP)" start with the number
+            let leading_number = || {
+                value
+                    .split(|c: char| !c.is_ascii_digit())
+                    .next()?
+                    .parse()
+                    .ok()
+            };
+            match key.trim() {
+                "Leap Indicator" => status.leap_indicator = leading_number(),
+                "Stratum" => status.stratum = leading_number(),
+                "Source" => status.source = Some(value.to_string()),
+                "Phase Offset" => {
+                    status.phase_offset = value.trim_end_matches('s').parse().ok();
+                }
+                _ => {}
+            }
+        }
+        status
+    }
+
+    /// Whether W32Time reports synchronized time from a real source
+    pub fn is_synchronized(&self) -> bool {
+        let free_running = self.source.as_deref().is_some_and(|source| {
+            source.eq_ignore_ascii_case("Local CMOS Clock")
+                || source.eq_ignore_ascii_case("Free-running System Clock")
+        });
+        self.leap_indicator != Some(3) && matches!(self.stratum, Some(1..=15)) && !free_running
+    }
+
+    /// Compares a clock reading with the system clock taken at the same moment.
+    ///
+    /// `difference` is the clock minus the system clock in seconds.
+    pub fn cross_check(&self, difference: f64, tolerance: Duration) -> CrossCheck {
+        if !self.is_synchronized() {
+            CrossCheck::Unsynchronized
+        } else if difference.abs() <= tolerance.as_secs_f64() {
+            CrossCheck::Agrees { difference }
+        } else {
+            CrossCheck::Disagrees { difference }
+        }
+    }
+}
+
+/// Runs `w32tm /query /status /verbose` and parses its output
+#[cfg(windows)]
+pub fn query_status() -> io::Result<W32TimeStatus> {
+    let output = std::process::Command::new("w32tm")
+        .args(["/query", "/status", "/verbose"])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "w32tm failed: {}",
+            String::from_utf8_lossy(&output.stdout).trim()
+        )));
+    }
+    Ok(W32TimeStatus::parse(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Queries W32Time and cross-checks `clock` against the system clock
+#[cfg(windows)]
+pub fn cross_check(clock: &crate::ClockHandle, tolerance: Duration) -> io::Result<CrossCheck> {
+    let status = query_status()?;
+    let difference = clock.now_timestamp().seconds_since(crate::Timestamp::now());
+    Ok(status.cross_check(difference, tolerance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYNCED: &str = "Leap Indicator: 0(no warning)
+Stratum: 4 (secondary reference - syncd by (S)NTP)
+Precision: -23 (119.209ns per tick)
+Root Delay: 0.0462151s
+ReferenceId: 0x142E0B2A (source IP:  20.46.11.42)
+Last Successful Sync Time: 10/16/2026 5:12:03 PM
+Source: time.windows.com,0x9
+Poll Interval: 10 (1024s)
+Phase Offset: -0.0012345s
+";
+
+    #[test]
+    fn test_parse_synchronized_status() {
+        let status = W32TimeStatus::parse(SYNCED);
+        assert_eq!(status.leap_indicator, Some(0));
+        assert_eq!(status.stratum, Some(4));
+        assert_eq!(status.source.as_deref(), Some("time.windows.com,0x9"));
+        assert_eq!(status.phase_offset, Some(-0.0012345));
+        assert!(status.is_synchronized());
+    }
+
+    #[test]
+    fn test_cross_check() {
+        let tolerance = Duration::from_millis(500);
+        let status = W32TimeStatus::parse(SYNCED);
+        assert_eq!(
+            status.cross_check(0.2, tolerance),
+            CrossCheck::Agrees { difference: 0.2 }
+        );
+        assert_eq!(
+            status.cross_check(-3.0, tolerance),
+            CrossCheck::Disagrees { difference: -3.0 }
+        );
+
+        let free_running = W32TimeStatus::parse(
+            "Leap Indicator: 3(not synchronized)\nStratum: 0 (unspecified)\nSource: Local CMOS Clock\n",
+        );
+        assert!(!free_running.is_synchronized());
+        assert_eq!(
+            free_running.cross_check(0.0, tolerance),
+            CrossCheck::Unsynchronized
+        );
+    }
+}
@@ -0,0 +1,339 @@
+//! # Browser Builds
+//!
+//! Browsers cannot open UDP sockets, so the NTP engine is not available on `wasm32`. With the
+//! `wasm` feature, [`WebClock`] takes its place: it fetches time over HTTP(S) with the Fetch
+//! API and extrapolates from the last sync with `performance.now()`, which keeps counting
+//! monotonically even when the user changes the system clock.
+//!
+//! Two kinds of [`HttpTimeSource`] are supported:
+//!
+//! * [`HttpTimeSource::DateHeader`] — the `Date` response header of any HTTP server. It only
+//!   has one-second resolution, so half a second is added to the parsed value. Cross-origin
+//!   servers must send `Access-Control-Expose-Headers: Date` for the header to be readable.
+//! * [`HttpTimeSource::Json`] — a JSON endpoint with a field holding either an RFC 3339
+//!   string or a number of seconds since the Unix epoch.
+//!
+//! Roughtime needs UDP as well and is not available from a browser.
+
+use crate::{ClockError, Timestamp};
+use std::time::Duration;
+
+/// An HTTP(S) endpoint to take the time from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpTimeSource {
+    /// Use the `Date` header of a `HEAD` request to `url`
+    DateHeader(String),
+    /// Read `field` from the JSON object returned by a `GET` request to `url`
+    Json { url: String, field: String },
+}
+
+impl HttpTimeSource {
+    /// The URL requested for this source
+    pub fn url(&self) -> &str {
+        match self {
+            HttpTimeSource::DateHeader(url) => url,
+            HttpTimeSource::Json { url, .. } => url,
+        }
+    }
+}
+
+/// The `Date` header has whole-second resolution; assume the middle of that second
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+const DATE_HEADER_ROUNDING: Duration = Duration::from_millis(500);
+
+/// Extracts the server's time from a response to `source`
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+fn parse_response(
+    source: &HttpTimeSource,
+    date_header: Option<&str>,
+    body: &str,
+) -> Result<Timestamp, ClockError> {
+    let invalid =
+        |reason: String| ClockError::SourceUnavailable(format!("{}: {}", source.url(), reason));
+    match source {
+        HttpTimeSource::DateHeader(_) => {
+            let header = date_header.ok_or_else(|| invalid("no readable Date header".into()))?;
+            Ok(Timestamp::parse_http_date(header).map_err(invalid)? + DATE_HEADER_ROUNDING)
+        }
+        HttpTimeSource::Json { field, .. } => {
+            let value = json_field(body, field)
+                .ok_or_else(|| invalid(format!("no '{}' field in response", field)))?;
+            parse_json_time(value).ok_or_else(|| invalid(format!("unrecognised time '{}'", value)))
+        }
+    }
+}
+
+/// Returns the raw value of a top-level `"field": value` pair in a JSON object.
+///
+/// This is deliberately minimal: time endpoints return small flat objects, and pulling in a
+/// JSON parser for one field is not worth the extra weight in a wasm binary.
+fn json_field<'a>(body: &'a str, field: &str) -> Option<&'a str> {
+    let key = format!("\"{}\"", field);
+    let mut rest = body;
+    while let Some(position) = rest.find(&key) {
+        let after = rest[position + key.len()..].trim_start();
+        if let Some(value) = after.strip_prefix(':') {
+            let value = value.trim_start();
+            if let Some(string) = value.strip_prefix('"') {
+                return string.find('"').map(|end| &string[..end]);
+            }
+            let end = value
+                .find(|c: char| c == ',' || c == '}' || c.is_whitespace())
+                .unwrap_or(value.len());
+            return Some(&value[..end]);
+        }
+        rest = after;
+    }
+    None
+}
+
+/// Interprets a JSON time value as RFC 3339 or as (fractional) Unix seconds
+fn parse_json_time(value: &str) -> Option<Timestamp> {
+    if let Ok(time) = Timestamp::parse_rfc3339(value) {
+        return Some(time);
+    }
+    let secs: f64 = value.parse().ok()?;
+    secs.is_finite()
+        .then(|| Timestamp::from_unix_nanos((secs * 1e9) as i128))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use browser::{PerformanceSource, WebClock};
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use super::{parse_response, HttpTimeSource};
+    use crate::lock::MutexExt;
+    use crate::{ClockError, ElapsedSource, TimeSource, Timestamp};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{
+        Performance, Request, RequestCache, RequestInit, RequestMode, Response, Window,
+        WorkerGlobalScope,
+    };
+
+    fn performance() -> Option<Performance> {
+        let global = js_sys::global();
+        if let Some(window) = global.dyn_ref::<Window>() {
+            window.performance()
+        } else if let Some(worker) = global.dyn_ref::<WorkerGlobalScope>() {
+            worker.performance()
+        } else {
+            None
+        }
+    }
+
+    /// Elapsed time from `performance.now()`, in a window or a worker
+    ///
+    /// The `Performance` object is looked up on each reading rather than stored, since
+    /// JavaScript values cannot be shared between threads.
+    #[derive(Debug, Clone, Copy)]
+    pub struct PerformanceSource {
+        _private: (),
+    }
+
+    impl PerformanceSource {
+        /// Creates the source, or `None` if the global scope has no `performance` object
+        pub fn new() -> Option<Self> {
+            performance().map(|_| PerformanceSource { _private: () })
+        }
+    }
+
+    impl ElapsedSource for PerformanceSource {
+        fn now(&self) -> Duration {
+            let millis = performance().map_or(0.0, |performance| performance.now());
+            Duration::from_secs_f64(millis.max(0.0) / 1000.0)
+        }
+    }
+
+    async fn fetch(request: &Request) -> Result<Response, ClockError> {
+        let global = js_sys::global();
+        let promise = if let Some(window) = global.dyn_ref::<Window>() {
+            window.fetch_with_request(request)
+        } else if let Some(worker) = global.dyn_ref::<WorkerGlobalScope>() {
+            worker.fetch_with_request(request)
+        } else {
+            return Err(ClockError::SourceUnavailable(
+                "fetch is not available in this context".into(),
+            ));
+        };
+        JsFuture::from(promise)
+            .await
+            .and_then(|response| response.dyn_into::<Response>())
+            .map_err(|err| ClockError::SourceUnavailable(format!("{:?}", err)))
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct SyncPoint {
+        time: Timestamp,
+        elapsed: Duration,
+    }
+
+    /// A clock synchronized from HTTP(S) time sources, for use in the browser
+    #[derive(Debug)]
+    pub struct WebClock {
+        sources: Vec<HttpTimeSource>,
+        elapsed: PerformanceSource,
+        last_sync: Mutex<Option<SyncPoint>>,
+    }
+
+    impl WebClock {
+        /// Creates an unsynchronized clock that will query `sources` in order.
+        ///
+        /// Returns `None` outside a window or worker, where `performance.now()` is missing.
+        pub fn new(sources: Vec<HttpTimeSource>) -> Option<Self> {
+            Some(WebClock {
+                sources,
+                elapsed: PerformanceSource::new()?,
+                last_sync: Mutex::new(None),
+            })
+        }
+
+        /// Queries the sources in order until one answers and returns the time it gave
+        pub async fn sync(&self) -> Result<Timestamp, ClockError> {
+            let mut last_error = ClockError::SourceUnavailable("no sources configured".into());
+            for source in &self.sources {
+                match self.query(source).await {
+                    Ok(sync) => {
+                        *self.last_sync.lock_or_recover() = Some(sync);
+                        return Ok(sync.time);
+                    }
+                    Err(err) => {
+                        crate::logging::clock_log!(
+                            Warn,
+                            Server,
+                            "Time source {} failed: {}",
+                            source.url(),
+                            err
+                        );
+                        last_error = err;
+                    }
+                }
+            }
+            Err(last_error)
+        }
+
+        async fn query(&self, source: &HttpTimeSource) -> Result<SyncPoint, ClockError> {
+            let init = RequestInit::new();
+            init.set_mode(RequestMode::Cors);
+            init.set_cache(RequestCache::NoStore);
+            init.set_method(match source {
+                HttpTimeSource::DateHeader(_) => "HEAD",
+                HttpTimeSource::Json { .. } => "GET",
+            });
+            let request = Request::new_with_str_and_init(source.url(), &init)
+                .map_err(|err| ClockError::SourceUnavailable(format!("{:?}", err)))?;
+
+            let sent = self.elapsed.now();
+            let response = fetch(&request).await?;
+            let received = self.elapsed.now();
+            if !response.ok() {
+                return Err(ClockError::SourceUnavailable(format!(
+                    "{}: HTTP {}",
+                    source.url(),
+                    response.status()
+                )));
+            }
+
+            let date = response.headers().get("Date").ok().flatten();
+            let body = match source {
+                HttpTimeSource::DateHeader(_) => String::new(),
+                HttpTimeSource::Json { .. } => {
+                    let text = response
+                        .text()
+                        .map_err(|err| ClockError::SourceUnavailable(format!("{:?}", err)))?;
+                    JsFuture::from(text)
+                        .await
+                        .ok()
+                        .and_then(|text| text.as_string())
+                        .unwrap_or_default()
+                }
+            };
+            // The server's time corresponds to the middle of the round trip
+            let time = parse_response(source, date.as_deref(), &body)? + (received - sent) / 2;
+            Ok(SyncPoint {
+                time,
+                elapsed: received,
+            })
+        }
+
+        /// Whether a sync has succeeded
+        pub fn is_synchronized(&self) -> bool {
+            self.last_sync.lock_or_recover().is_some()
+        }
+
+        /// Where [`now_timestamp`](Self::now_timestamp) currently gets its time from
+        pub fn time_source(&self) -> TimeSource {
+            if self.is_synchronized() {
+                TimeSource::Http
+            } else {
+                TimeSource::SystemClock
+            }
+        }
+
+        /// Synchronized time, or `Date.now()` before the first successful sync
+        pub fn now_timestamp(&self) -> Timestamp {
+            self.try_now_timestamp()
+                .unwrap_or_else(|_| Timestamp::now())
+        }
+
+        /// Synchronized time, failing with [`ClockError::NotSynchronized`] before the first
+        /// successful sync
+        pub fn try_now_timestamp(&self) -> Result<Timestamp, ClockError> {
+            let sync = self
+                .last_sync
+                .lock_or_recover()
+                .ok_or(ClockError::NotSynchronized)?;
+            Ok(sync.time + self.elapsed.now().saturating_sub(sync.elapsed))
+        }
+
+        /// Milliseconds since the Unix epoch, the unit JavaScript's `Date` uses
+        pub fn now_unix_millis(&self) -> i64 {
+            self.now_timestamp().unix_millis()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_header_source() {
+        let source = HttpTimeSource::DateHeader("https://example.com/".into());
+        let time = parse_response(&source, Some("Sun, 06 Nov 1994 08:49:37 GMT"), "").unwrap();
+        assert_eq!(
+            time,
+            Timestamp::from_unix_secs(784_111_777) + Duration::from_millis(500)
+        );
+        assert!(matches!(
+            parse_response(&source, None, ""),
+            Err(ClockError::SourceUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn test_json_source() {
+        let source = HttpTimeSource::Json {
+            url: "https://example.com/time".into(),
+            field: "utc_datetime".into(),
+        };
+        let body = r#"{"abbreviation":"UTC","utc_datetime":"2026-02-03T06:50:57.5Z","unixtime":1770101457}"#;
+        assert_eq!(
+            parse_response(&source, None, body).unwrap(),
+            "2026-02-03T06:50:57.5Z".parse().unwrap()
+        );
+
+        let source = HttpTimeSource::Json {
+            url: "https://example.com/time".into(),
+            field: "unixtime".into(),
+        };
+        assert_eq!(
+            parse_response(&source, None, body).unwrap(),
+            Timestamp::from_unix_secs(1_770_101_457)
+        );
+        assert!(parse_response(&source, None, r#"{"time": 1}"#).is_err());
+    }
+}
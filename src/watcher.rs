@@ -0,0 +1,127 @@
+//! # Configuration File Watcher
+//!
+//! A [`ConfigWatcher`] polls a configuration file and applies it to a running clock whenever
+//! it changes, emitting [`ClockEvent::ConfigReloaded`](crate::ClockEvent::ConfigReloaded).
+//! Polling the modification time and size keeps it dependency-free and works on every
+//! platform and filesystem, including network mounts and Kubernetes ConfigMap volumes.
+//! Changes are applied once the file has stopped changing for one poll interval.
+
+use crate::engine::ClockShared;
+use crate::lock::{self, MutexExt};
+use crate::logging::clock_log;
+use crate::ClockConfig;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+/// Default interval between checks of the watched file
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Adjusts each configuration read from the file before it is applied
+pub type ConfigAdjuster = Box<dyn Fn(ClockConfig) -> ClockConfig + Send>;
+
+/// Modification time and size, compared to detect changes
+fn fingerprint(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Watches a configuration file and applies changes to a clock until dropped
+pub struct ConfigWatcher {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ConfigWatcher {
+    pub(crate) fn spawn(
+        shared: Arc<ClockShared>,
+        path: PathBuf,
+        poll_interval: Duration,
+        adjust: ConfigAdjuster,
+    ) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stop = Arc::clone(&stop);
+        let thread = std::thread::spawn(move || {
+            let mut last = fingerprint(&path);
+            // A change is applied once it has been seen on two consecutive polls, so a file
+            // caught halfway through being written is not loaded
+            let mut pending = None;
+            let (stopped, wake) = &*thread_stop;
+            let mut stopped = stopped.lock_or_recover();
+            loop {
+                stopped = lock::recover(wake.wait_timeout(stopped, poll_interval)).0;
+                if *stopped {
+                    return;
+                }
+                let current = fingerprint(&path);
+                if current == last || current.is_none() {
+                    pending = None;
+                    continue;
+                }
+                if pending != current {
+                    pending = current;
+                    continue;
+                }
+                last = current;
+                pending = None;
+                match ClockConfig::from_file(&path) {
+                    Ok(config) => {
+                        clock_log!(
+                            Info,
+                            Config,
+                            "Configuration file {} changed",
+                            path.display()
+                        );
+                        shared.reconfigure(&adjust(config));
+                    }
+                    Err(e) => {
+                        clock_log!(Warn, Config, "Ignoring invalid {}: {}", path.display(), e)
+                    }
+                }
+            }
+        });
+        ConfigWatcher {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        let (stopped, wake) = &*self.stop;
+        *stopped.lock_or_recover() = true;
+        wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, ClockEvent};
+
+    #[test]
+    fn test_watcher_applies_changes() {
+        let path = std::env::temp_dir().join(format!("clock-ntp-watch-{}", std::process::id()));
+        fs::write(&path, "server = invalid.invalid:123\n").unwrap();
+        let clock = Clock::with_config(ClockConfig::from_file(&path).unwrap());
+        let events = clock.events();
+        let _watcher = clock.watch_config(&path, Duration::from_millis(20));
+
+        std::thread::sleep(Duration::from_millis(50));
+        fs::write(&path, "server = invalid.invalid:123\nstale_after = 90\n").unwrap();
+        let event = events.recv_timeout(Duration::from_secs(2)).unwrap();
+        let ClockEvent::ConfigReloaded { changes } = event else {
+            panic!("unexpected event {:?}", event);
+        };
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].setting, "stale_after");
+        assert_eq!(changes[0].new, "90s");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,388 @@
+//! # WebSocket Time Broadcast
+//!
+//! `GET /ws` on the [`api`](crate::api) server upgrades to a WebSocket that pushes the
+//! server's time once a second, for dashboards and browser clients that want live,
+//! server-authoritative time:
+//!
+//! ```text
+//! {"utc":"2026-02-03T06:50:57.250+00:00","uncertainty_ms":1.25,"state":"synchronized"}
+//! ```
+//!
+//! `uncertainty_ms` is `null` before the first sync. Clients can pick another push interval
+//! with `/ws?interval_ms=N` (at least 100). Pings are answered and a close frame ends the
+//! stream; other frames from the client are ignored.
+//!
+//! Each client is served by its own thread, so the server takes at most
+//! [`ApiConfig::max_subscribers`](crate::api::ApiConfig::max_subscribers) of them at once
+//! and answers further upgrades with `503 Service Unavailable`.
+
+use crate::logging::clock_log;
+use crate::{ClockHandle, ClockState};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use sha1::{Digest, Sha1};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Interval between pushed frames unless the client asks for another
+pub const DEFAULT_PUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shortest push interval a client may request
+const MIN_PUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Largest client frame payload accepted
+const MAX_CLIENT_PAYLOAD: u64 = 4096;
+
+/// Appended to the client's key before hashing, from RFC 6455
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// Completes the upgrade handshake on `stream` and pushes frames from a background thread
+/// until the client goes away or `shutdown` is set. `subscribers` counts the clients being
+/// served; once it reaches `max_subscribers` the upgrade is refused.
+pub(crate) fn upgrade(
+    mut stream: TcpStream,
+    target: &str,
+    headers: &[(String, String)],
+    handle: ClockHandle,
+    subscribers: Arc<AtomicUsize>,
+    max_subscribers: usize,
+    shutdown: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let key = match (header("upgrade"), header("sec-websocket-key")) {
+        (Some(upgrade), Some(key)) if upgrade.eq_ignore_ascii_case("websocket") => key,
+        _ => {
+            let body = "400 Bad Request: expected a WebSocket upgrade\n";
+            return write!(
+                stream,
+                "HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+    };
+    let Some(subscription) = Subscription::take(subscribers, max_subscribers) else {
+        clock_log!(
+            Warn,
+            Serving,
+            "Refusing a WebSocket client: {} already subscribed",
+            max_subscribers
+        );
+        let body = "503 Service Unavailable: too many WebSocket clients\n";
+        return write!(
+            stream,
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+    };
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    )?;
+
+    let interval = push_interval(target);
+    std::thread::spawn(move || {
+        let _subscription = subscription;
+        if let Err(e) = push_frames(stream, interval, &handle, &shutdown) {
+            clock_log!(Debug, Serving, "WebSocket client disconnected: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// A place among the subscribers, given back when dropped
+struct Subscription(Arc<AtomicUsize>);
+
+impl Subscription {
+    fn take(subscribers: Arc<AtomicUsize>, max_subscribers: usize) -> Option<Self> {
+        subscribers
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max_subscribers).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Subscription(subscribers))
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The push interval requested with `?interval_ms=N`, or the default
+fn push_interval(target: &str) -> Duration {
+    let requested = target
+        .split_once('?')
+        .into_iter()
+        .flat_map(|(_, query)| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("interval_ms="))
+        .and_then(|ms| ms.parse().ok())
+        .map(Duration::from_millis);
+    requested.map_or(DEFAULT_PUSH_INTERVAL, |interval| {
+        interval.max(MIN_PUSH_INTERVAL)
+    })
+}
+
+fn push_frames(
+    mut stream: TcpStream,
+    interval: Duration,
+    handle: &ClockHandle,
+    shutdown: &AtomicBool,
+) -> io::Result<()> {
+    while !shutdown.load(Ordering::Relaxed) {
+        write_frame(&mut stream, OPCODE_TEXT, time_frame(handle).as_bytes())?;
+
+        // Wait for the next push by reading, so pings and closes are answered promptly
+        let next_push = Instant::now() + interval;
+        while let Some(wait) = next_push.checked_duration_since(Instant::now()) {
+            if wait.is_zero() {
+                break;
+            }
+            stream.set_read_timeout(Some(wait))?;
+            match read_frame(&mut stream) {
+                Ok((OPCODE_CLOSE, _)) => {
+                    let _ = write_frame(&mut stream, OPCODE_CLOSE, &[]);
+                    return Ok(());
+                }
+                Ok((OPCODE_PING, payload)) => write_frame(&mut stream, OPCODE_PONG, &payload)?,
+                Ok(_) => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    // Going away
+    write_frame(&mut stream, OPCODE_CLOSE, &1001u16.to_be_bytes())
+}
+
+/// The JSON pushed to clients
+fn time_frame(handle: &ClockHandle) -> String {
+    let uncertainty = match handle.uncertainty() {
+        Some(bound) => format!("{}", bound.as_secs_f64() * 1000.0),
+        None => "null".to_string(),
+    };
+    let state = match handle.state() {
+        ClockState::Synchronized => "synchronized",
+        ClockState::Unsynchronized => "unsynchronized",
+    };
+    format!(
+        "{{\"utc\":\"{}\",\"uncertainty_ms\":{},\"state\":\"{}\"}}",
+        handle.now_timestamp().to_rfc3339(),
+        uncertainty,
+        state
+    )
+}
+
+/// Writes an unmasked, unfragmented server frame
+fn write_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+/// Reads one client frame, returning its opcode and unmasked payload
+fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head)?;
+    // The rest of the frame follows immediately; don't let the push timeout split it
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            stream.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if len > MAX_CLIENT_PAYLOAD {
+        clock_log!(
+            Warn,
+            Serving,
+            "Dropping WebSocket client that sent a {}-byte frame",
+            len
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame too large",
+        ));
+    }
+    let mut mask = [0u8; 4];
+    if head[1] & 0x80 != 0 {
+        stream.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((head[0] & 0x0f, payload))
+}
+
+/// The `Sec-WebSocket-Accept` value for a client's `Sec-WebSocket-Key`
+fn accept_key(key: &str) -> String {
+    BASE64_STANDARD.encode(sha1(format!("{}{}", key.trim(), ACCEPT_GUID).as_bytes()))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    Sha1::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ApiConfig;
+    use crate::Clock;
+    use std::net::{SocketAddr, TcpListener};
+
+    #[test]
+    fn test_accept_key_matches_rfc_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_push_interval_from_query() {
+        assert_eq!(push_interval("/ws"), DEFAULT_PUSH_INTERVAL);
+        assert_eq!(
+            push_interval("/ws?x=1&interval_ms=250"),
+            Duration::from_millis(250)
+        );
+        assert_eq!(push_interval("/ws?interval_ms=1"), MIN_PUSH_INTERVAL);
+    }
+
+    /// Asks `addr` for an upgrade, returning the connection and the response head
+    fn connect(addr: SocketAddr) -> (TcpStream, String) {
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(
+                b"GET /ws?interval_ms=100 HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\n\
+                  Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                  Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8];
+            client.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        (client, String::from_utf8(response).unwrap())
+    }
+
+    #[test]
+    fn test_pushes_frames_and_answers_ping() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server =
+            crate::api::spawn_server(listener, clock.handle(), Arc::clone(&shutdown)).unwrap();
+
+        let (mut client, response) = connect(addr);
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        for _ in 0..2 {
+            let (opcode, payload) = read_frame(&mut client).unwrap();
+            assert_eq!(opcode, OPCODE_TEXT);
+            let text = String::from_utf8(payload).unwrap();
+            assert!(text.contains("\"uncertainty_ms\":null,\"state\":\"unsynchronized\""));
+        }
+
+        // Client frames are masked
+        client
+            .write_all(&[0x80 | OPCODE_PING, 0x82, 1, 2, 3, 4, b'h' ^ 1, b'i' ^ 2])
+            .unwrap();
+        loop {
+            let (opcode, payload) = read_frame(&mut client).unwrap();
+            if opcode == OPCODE_PONG {
+                assert_eq!(payload, b"hi");
+                break;
+            }
+        }
+
+        shutdown.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_refuses_subscribers_beyond_the_limit() {
+        let clock = Clock::new(Some(vec!["invalid.invalid:123".to_string()]));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let config = ApiConfig::new().with_max_subscribers(1);
+        let server = crate::api::spawn_server_with_config(
+            listener,
+            clock.handle(),
+            config,
+            Arc::clone(&shutdown),
+        )
+        .unwrap();
+
+        let (mut first, response) = connect(addr);
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        let (_, response) = connect(addr);
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        // Closing the first subscription makes room for another
+        first
+            .write_all(&[0x80 | OPCODE_CLOSE, 0x80, 0, 0, 0, 0])
+            .unwrap();
+        while read_frame(&mut first).unwrap().0 != OPCODE_CLOSE {}
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            let (_, response) = connect(addr);
+            if response.starts_with("HTTP/1.1 101 Switching Protocols\r\n") {
+                break;
+            }
+            assert!(Instant::now() < deadline, "{}", response);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        shutdown.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+    }
+}
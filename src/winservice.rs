@@ -0,0 +1,124 @@
+//! # Windows Service Support
+//!
+//! Lets a program run under the Windows Service Control Manager. [`run`] connects the
+//! calling thread to the SCM, runs the service body on the thread the SCM provides, and
+//! sets the shutdown flag when the service is stopped or the system shuts down. The body is
+//! expected to return promptly once the flag is set.
+//!
+//! The SCM is driven through the `windows-service` crate. The service itself is registered
+//! with the usual tools, e.g.
+//! `sc.exe create clock-ntp binPath= "C:\clock\clock.exe --service"`.
+
+use crate::lock::MutexExt;
+use crate::logging::clock_log;
+use std::ffi::OsString;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+use windows_service::{define_windows_service, service_dispatcher};
+
+/// How long the SCM is told to wait for the service to start
+const PENDING_WAIT_HINT: Duration = Duration::from_secs(30);
+
+/// What the service entry point needs, which the SCM cannot pass to it
+struct Service {
+    name: String,
+    shutdown: Arc<AtomicBool>,
+    body: Option<Box<dyn FnOnce() + Send>>,
+}
+
+static SERVICE: Mutex<Option<Service>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Runs `body` as the Windows service `name`, blocking until it stops.
+///
+/// `shutdown` is set when the SCM asks the service to stop. Fails if the process was not
+/// started by the SCM.
+pub fn run(
+    name: &str,
+    shutdown: Arc<AtomicBool>,
+    body: impl FnOnce() + Send + 'static,
+) -> io::Result<()> {
+    *SERVICE.lock_or_recover() = Some(Service {
+        name: name.to_string(),
+        shutdown,
+        body: Some(Box::new(body)),
+    });
+    service_dispatcher::start(name, ffi_service_main).map_err(io::Error::other)
+}
+
+fn set_status(handle: &ServiceStatusHandle, state: ServiceState) {
+    let running = state == ServiceState::Running;
+    let status = ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: if running {
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+        } else {
+            ServiceControlAccept::empty()
+        },
+        exit_code: ServiceExitCode::Win32(0),
+        checkpoint: 0,
+        wait_hint: if running {
+            Duration::ZERO
+        } else {
+            PENDING_WAIT_HINT
+        },
+        process_id: None,
+    };
+    if let Err(e) = handle.set_service_status(status) {
+        clock_log!(Error, Worker, "Failed to report service status: {}", e);
+    }
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let (name, shutdown, body) = {
+        let mut service = SERVICE.lock_or_recover();
+        let Some(service) = service.as_mut() else {
+            return;
+        };
+        (
+            service.name.clone(),
+            Arc::clone(&service.shutdown),
+            service.body.take(),
+        )
+    };
+
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            clock_log!(Info, Worker, "Service stop requested");
+            shutdown.store(true, Ordering::Relaxed);
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let handle = match service_control_handler::register(&name, handler) {
+        Ok(handle) => handle,
+        Err(e) => {
+            clock_log!(
+                Error,
+                Worker,
+                "Failed to register service control handler: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    set_status(&handle, ServiceState::StartPending);
+    clock_log!(Info, Worker, "Service started");
+    set_status(&handle, ServiceState::Running);
+    if let Some(body) = body {
+        body();
+    }
+    set_status(&handle, ServiceState::Stopped);
+}
@@ -1,16 +1,19 @@
 // Integration tests for the Clock-NTP library
+#![cfg(feature = "chrono")]
 
-use clock::{Clock, SyncStats, DEFAULT};
+use clock::{Clock, ClockConfig, SyncStats, DEFAULT};
+use std::net::UdpSocket;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[test]
 fn test_sync_stats_functionality() {
     let mut stats = SyncStats::default();
     assert_eq!(stats.success_rate(), 0.0);
-    
+
     stats.total_attempts = 10;
     stats.successful_syncs = 8;
     stats.failed_syncs = 2;
-    
+
     assert_eq!(stats.success_rate(), 80.0);
 }
 
@@ -18,7 +21,7 @@ fn test_sync_stats_functionality() {
 fn test_clock_with_default_servers() {
     let clock = Clock::new(None);
     // Clock should be initialized even if NTP servers are unavailable
-    assert!(clock.latest_instant.elapsed().as_secs() < 1);
+    assert!(clock.latest_instant().elapsed().as_secs() < 1);
 }
 
 #[test]
@@ -38,16 +41,16 @@ fn test_clock_with_multiple_custom_servers() {
     ];
     let clock = Clock::new(Some(servers.clone()));
     // The ntp_servers field should match what we provided
-    assert_eq!(clock.ntp_servers, servers);
+    assert_eq!(clock.ntp_servers(), servers);
 }
 
 #[test]
 fn test_clock_current_time_advances() {
     let clock = Clock::new(None);
     let time1 = clock.get_current_time();
-    
+
     std::thread::sleep(std::time::Duration::from_millis(100));
-    
+
     let time2 = clock.get_current_time();
     // Time should advance
     assert!(time2 > time1);
@@ -59,9 +62,46 @@ fn test_stats_accumulation() {
         total_attempts: 100,
         successful_syncs: 95,
         failed_syncs: 5,
+        ..SyncStats::default()
     };
-    
+
     assert_eq!(stats.total_attempts, 100);
     assert_eq!(stats.successful_syncs + stats.failed_syncs, 100);
     assert!(stats.success_rate() > 90.0);
 }
+
+/// Starts a local server answering NTP requests with the system time, returning its address
+fn spawn_fake_server() -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 48];
+        while let Ok((_, peer)) = socket.recv_from(&mut buf) {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            let seconds = (now.as_secs() + 2_208_988_800) as u32;
+            buf[0] = 0x1c; // NTP version 3, server mode
+            buf[1] = 2; // stratum
+            buf[12..16].copy_from_slice(&[192, 0, 2, 1]);
+            buf.copy_within(40..48, 24); // the request's nonce as the origin
+            buf[40..44].copy_from_slice(&seconds.to_be_bytes());
+            buf[44..48].copy_from_slice(&[0; 4]);
+            let _ = socket.send_to(&buf, peer);
+        }
+    });
+    addr
+}
+
+#[test]
+fn test_global_clock_uses_configured_servers() {
+    let servers = vec![spawn_fake_server()];
+    clock::set_global_config(ClockConfig::new().with_servers(servers.clone())).unwrap();
+
+    // Starting does not wait for the first sync
+    assert!(clock::now_utc() >= DEFAULT);
+    assert_eq!(clock::global_clock().ntp_servers(), servers);
+    clock::wait_until_synchronized(Duration::from_secs(10)).unwrap();
+    assert!(clock::is_synchronized());
+
+    // Once started, the configuration can no longer be changed
+    assert!(clock::set_global_config(ClockConfig::default()).is_err());
+}